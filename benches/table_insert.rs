@@ -0,0 +1,53 @@
+//! Measures building tables, which exercises growing and rehashing the array and map parts.
+//!
+//! Run with `cargo bench --bench table_insert`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use piccolo::{Lua, Table, Value};
+
+const SIZE: i64 = 10_000;
+
+fn table_insert(c: &mut Criterion) {
+    let mut lua = Lua::empty();
+    lua.enter(|ctx| {
+        // Appending to a sequence, like `table.insert(t, v)`.
+        c.bench_function("append", |b| {
+            b.iter(|| {
+                let table = Table::new(&ctx);
+                for i in 1..=SIZE {
+                    table.set(ctx, i, i).unwrap();
+                }
+                table
+            })
+        });
+
+        // Filling a sequence from the end, so that every key starts out in the map part.
+        c.bench_function("reverse fill", |b| {
+            b.iter(|| {
+                let table = Table::new(&ctx);
+                for i in (1..=SIZE).rev() {
+                    table.set(ctx, i, i).unwrap();
+                }
+                table
+            })
+        });
+
+        // Integer keys mixed with string keys.
+        c.bench_function("mixed keys", |b| {
+            let names = (0..SIZE)
+                .map(|i| ctx.intern(format!("key{i}").as_bytes()))
+                .collect::<Vec<_>>();
+            b.iter(|| {
+                let table = Table::new(&ctx);
+                for (i, &name) in (1..=SIZE).zip(&names) {
+                    table.set(ctx, name, i).unwrap();
+                    table.set(ctx, i, Value::Boolean(true)).unwrap();
+                }
+                table
+            })
+        });
+    });
+}
+
+criterion_group!(benches, table_insert);
+criterion_main!(benches);
@@ -0,0 +1,60 @@
+//! Measures the cost of `Table::length` on sequences and on tables with holes.
+//!
+//! Run with `cargo bench --bench table_length`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use piccolo::{Lua, Table, Value};
+
+const SIZE: i64 = 10_000;
+
+fn table_length(c: &mut Criterion) {
+    let mut lua = Lua::empty();
+    lua.enter(|ctx| {
+        // A table used as a stack, growing and shrinking one element at a time.
+        c.bench_function("push / pop", |b| {
+            let table = Table::new(&ctx);
+            b.iter(|| {
+                for i in 1..=SIZE {
+                    let len = table.length();
+                    table.set(ctx, len + 1, i).unwrap();
+                }
+                for _ in 1..=SIZE {
+                    let len = table.length();
+                    table.set(ctx, len, Value::Nil).unwrap();
+                }
+            })
+        });
+
+        // The length of an unchanging sequence, like `#t` in the condition of a loop.
+        c.bench_function("repeated length", |b| {
+            let table = Table::new(&ctx);
+            for i in 1..=SIZE {
+                table.set(ctx, i, i).unwrap();
+            }
+            table.set(ctx, SIZE, Value::Nil).unwrap();
+            b.iter(|| table.length())
+        });
+
+        // A table with holes punched into it between length queries.
+        c.bench_function("holes", |b| {
+            let table = Table::new(&ctx);
+            for i in 1..=SIZE {
+                table.set(ctx, i, i).unwrap();
+            }
+            let mut seed: i64 = 1;
+            b.iter(|| {
+                seed = (seed * 1103515245 + 12345) % 2147483648;
+                let key = seed % SIZE + 1;
+                if seed % 3 == 0 {
+                    table.set(ctx, key, Value::Nil).unwrap();
+                } else {
+                    table.set(ctx, key, seed).unwrap();
+                }
+                table.length()
+            })
+        });
+    });
+}
+
+criterion_group!(benches, table_length);
+criterion_main!(benches);
@@ -0,0 +1,94 @@
+//! Measures the VM on small numeric kernels, which are dominated by `for` loops, arithmetic and
+//! comparisons.
+//!
+//! Run with `cargo bench --bench vm`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use piccolo::{Closure, Executor, Lua};
+
+// Compiles the script once and then measures running it to completion.
+fn bench_script(c: &mut Criterion, name: &str, source: &str) {
+    let mut lua = Lua::core();
+    let (closure, executor) = lua.enter(|ctx| {
+        let closure = Closure::load(ctx, Some(name), source.as_bytes()).unwrap();
+        (
+            ctx.stash(closure),
+            ctx.stash(Executor::start(ctx, closure.into(), ())),
+        )
+    });
+    c.bench_function(name, |b| {
+        b.iter(|| {
+            lua.enter(|ctx| {
+                let closure = ctx.fetch(&closure);
+                ctx.fetch(&executor)
+                    .try_restart(ctx, closure.into(), ())
+                    .unwrap();
+            });
+            lua.execute::<()>(&executor).unwrap();
+        })
+    });
+}
+
+fn vm(c: &mut Criterion) {
+    // An integer `for` loop doing integer arithmetic.
+    bench_script(
+        c,
+        "integer for",
+        r#"
+            local sum = 0
+            for i = 1, 1000000 do
+                sum = sum + i * 2 - 1
+            end
+            assert(sum == 1000000000000)
+        "#,
+    );
+
+    // An integer `for` loop with a float limit.
+    bench_script(
+        c,
+        "float limit for",
+        r#"
+            local sum = 0
+            for i = 1, 1000000.5 do
+                sum = sum + i
+            end
+            assert(sum == 500000500000)
+        "#,
+    );
+
+    // Float arithmetic in a `while` loop, with a comparison guarding every iteration.
+    bench_script(
+        c,
+        "float while",
+        r#"
+            local x, n = 0.0, 0
+            while n < 1000000 do
+                x = x + 0.5
+                n = n + 1
+            end
+            assert(x == 500000.0)
+        "#,
+    );
+
+    // Branches on comparisons inside of a loop.
+    bench_script(
+        c,
+        "compare branches",
+        r#"
+            local count = 0
+            for i = 1, 1000000 do
+                if i % 3 == 0 then
+                    count = count + 1
+                elseif i < 500000 then
+                    count = count + 2
+                end
+                if i ~= 7 and i <= 900000 then
+                    count = count - 1
+                end
+            end
+        "#,
+    );
+}
+
+criterion_group!(benches, vm);
+criterion_main!(benches);
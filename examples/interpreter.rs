@@ -5,9 +5,8 @@ use clap::{crate_description, crate_name, crate_version, Arg, Command};
 use rustyline::DefaultEditor;
 
 use piccolo::{
-    compiler::{ParseError, ParseErrorKind},
-    io, meta_ops, Callback, CallbackReturn, Closure, Executor, ExternError, Function, Lua,
-    StashedExecutor,
+    compiler::ParseError, io, meta_ops, stdlib::HostFilesystem, Callback, CallbackReturn, Closure,
+    Executor, ExternError, Function, Lua, StashedExecutor,
 };
 
 fn run_code(lua: &mut Lua, executor: &StashedExecutor, code: &str) -> Result<(), ExternError> {
@@ -62,13 +61,10 @@ fn run_repl(lua: &mut Lua) -> Result<(), Box<dyn StdError>> {
             match run_code(lua, &executor, &line) {
                 Err(err)
                     if !read_empty
-                        && matches!(
-                            err.root_cause().downcast_ref::<ParseError>(),
-                            Some(ParseError {
-                                kind: ParseErrorKind::EndOfStream { .. },
-                                ..
-                            })
-                        ) =>
+                        && err
+                            .root_cause()
+                            .downcast_ref::<ParseError>()
+                            .is_some_and(ParseError::is_incomplete) =>
                 {
                     prompt = ">> ";
                 }
@@ -99,7 +95,11 @@ fn main() -> Result<(), Box<dyn StdError>> {
         .arg(Arg::new("file").help("File to interpret").index(1))
         .get_matches();
 
-    let mut lua = Lua::full();
+    let mut lua = Lua::core();
+    lua.load_class();
+    lua.load_io_with_vfs(HostFilesystem);
+    lua.load_os_with_vfs(HostFilesystem);
+    lua.load_package_with_vfs(HostFilesystem);
 
     if !matches.contains_id("file") {
         run_repl(&mut lua)?;
@@ -0,0 +1,54 @@
+/* C declarations for the API in `src/capi.rs`, enabled with the `capi` feature. */
+
+#ifndef PICCOLO_H
+#define PICCOLO_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define PICCOLO_OK 0
+#define PICCOLO_ERROR 1
+#define PICCOLO_FUEL_EXHAUSTED 2
+#define PICCOLO_MEMORY_EXHAUSTED 3
+#define PICCOLO_INVALID 4
+
+typedef struct PiccoloVm PiccoloVm;
+
+PiccoloVm *piccolo_new(void);
+void piccolo_free(PiccoloVm *vm);
+
+/* A limit of 0 removes the limit. */
+void piccolo_set_fuel_limit(PiccoloVm *vm, uint64_t fuel);
+void piccolo_set_memory_limit(PiccoloVm *vm, size_t bytes);
+
+/* Function handles are never 0, 0 is returned on failure. */
+uint64_t piccolo_load(PiccoloVm *vm, const char *name, const uint8_t *source, size_t len);
+uint64_t piccolo_get_global(PiccoloVm *vm, const char *name);
+void piccolo_release(PiccoloVm *vm, uint64_t function);
+
+void piccolo_push_nil(PiccoloVm *vm);
+void piccolo_push_boolean(PiccoloVm *vm, int value);
+void piccolo_push_integer(PiccoloVm *vm, int64_t value);
+void piccolo_push_number(PiccoloVm *vm, double value);
+void piccolo_push_string(PiccoloVm *vm, const uint8_t *data, size_t len);
+
+/* Returns one of the PICCOLO_ status codes. */
+int piccolo_call(PiccoloVm *vm, uint64_t function);
+
+/* Returned pointers are valid until the next call taking the same `vm`, and are not
+ * nul-terminated. */
+size_t piccolo_result_count(const PiccoloVm *vm);
+const uint8_t *piccolo_result_json(PiccoloVm *vm, size_t *len);
+const uint8_t *piccolo_result_string(PiccoloVm *vm, size_t index, size_t *len);
+
+const char *piccolo_error(const PiccoloVm *vm);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -3,7 +3,7 @@ use std::{
     future::{poll_fn, Future},
     marker::PhantomData,
     mem,
-    pin::Pin,
+    pin::{pin, Pin},
     ptr,
     rc::Rc,
     task::{self, Poll, RawWaker, RawWakerVTable, Waker},
@@ -27,10 +27,9 @@ use crate::{
 /// available within the created future is **meaningless** and has a NOOP waker; we are only using
 /// `async` as a stable way to express what would be better expressed as a simple coroutine.
 ///
-/// It is possible to integrate proper async APIs with `piccolo`, and to even have a method to
-/// "wake" Lua coroutines with a *real* [`std::task::Waker`], but simply calling an external async
-/// function from the created future here is *not* the way to do it. It will not do what you want,
-/// and probably will result in panics.
+/// Simply `.await`ing an external async function from the created future here will not do what you
+/// want, and will result in panics. Instead, pass external futures to
+/// [`AsyncSequence::await_external`], which suspends the running thread until the future is woken.
 ///
 /// The provided `create` function is given two parameters: a [`Locals`] object to stash values
 /// that will be owned by the future, and an [`AsyncSequence`] object which the future shuld use to
@@ -52,8 +51,8 @@ use crate::{
 /// # Panics
 ///
 /// All Rust yields (`.await`) within the returned future must occur from calling an async method
-/// on the `AsyncSequence` handle. If some other future is `.await`ed, this will cause the outer
-/// `Sequence` poll methods to panic.
+/// on the `AsyncSequence` handle. If some other future is `.await`ed directly rather than through
+/// [`AsyncSequence::await_external`], this will cause the outer `Sequence` poll methods to panic.
 ///
 /// Methods on `AsyncSequence` must *only* be called from the returned future. Calling methods on
 /// `AsyncSequence` directly from the provided create function or from storing the `AsyncSequence`
@@ -193,6 +192,47 @@ impl AsyncSequence {
         });
     }
 
+    /// Await a future which is not driven by `piccolo`, such as a request made by the host.
+    ///
+    /// The future is polled with the waker set by [`Executor::set_waker`], or with a no-op waker if
+    /// there is none. Whenever it is pending, this returns [`SequencePoll::Suspend`], which stops
+    /// the `Executor` in [`ExecutorMode::Suspended`] with [`Executor::is_awaiting_external`]
+    /// returning true. Once the future has been woken, the embedder should call
+    /// [`Executor::resume`] and continue stepping the `Executor`, which polls the future again.
+    ///
+    /// If the `Executor` is instead resumed with [`Executor::resume_err`], the future is dropped
+    /// and the error is returned.
+    ///
+    /// [`Executor::set_waker`]: crate::Executor::set_waker
+    /// [`ExecutorMode::Suspended`]: crate::ExecutorMode::Suspended
+    /// [`Executor::is_awaiting_external`]: crate::Executor::is_awaiting_external
+    /// [`Executor::resume`]: crate::Executor::resume
+    /// [`Executor::resume_err`]: crate::Executor::resume_err
+    pub async fn await_external<F: Future>(&mut self, fut: F) -> Result<F::Output, StashedError> {
+        let mut fut = pin!(fut);
+        loop {
+            let waker = self
+                .shared
+                .visit(|shared| shared.exec.waker().cloned())
+                .unwrap_or_else(noop_waker);
+            if let Poll::Ready(output) = fut.as_mut().poll(&mut task::Context::from_waker(&waker)) {
+                return Ok(output);
+            }
+
+            self.shared.visit(move |shared| {
+                shared.set_next_op(SequenceOp::Suspend);
+            });
+            wait_once().await;
+            self.shared.visit(move |shared| {
+                if let Some(err) = shared.error.take() {
+                    Err(err.stash(&shared.ctx, shared.roots))
+                } else {
+                    Ok(())
+                }
+            })?;
+        }
+    }
+
     /// Call the given Lua function with arguments / returns starting at `bottom` in the Stack.
     pub async fn call(
         &mut self,
@@ -391,6 +431,7 @@ where
             Poll::Pending => Ok(
                 match next_op.expect("`await` of a future other than `AsyncSequence` methods") {
                     SequenceOp::Pending => SequencePoll::Pending,
+                    SequenceOp::Suspend => SequencePoll::Suspend,
                     SequenceOp::Call { function, bottom } => {
                         SequencePoll::Call { function, bottom }
                     }
@@ -432,6 +473,7 @@ where
 
 enum SequenceOp<'gc> {
     Pending,
+    Suspend,
     Call {
         function: Function<'gc>,
         bottom: usize,
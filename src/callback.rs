@@ -7,7 +7,9 @@ use std::{
 use allocator_api2::boxed;
 use gc_arena::{allocator_api::MetricsAlloc, Collect, Gc, Mutation};
 
-use crate::{Context, Error, Execution, Function, Stack, Thread};
+use crate::{
+    Context, Error, Execution, FromMultiValue, Function, IntoMultiValue, IntoValue, Stack, Thread,
+};
 
 /// Describes the next action for an [`Executor`](crate::Executor) to take after a callback has
 /// returned.
@@ -79,6 +81,34 @@ pub trait CallbackFn<'gc>: Collect {
     ) -> Result<CallbackReturn<'gc>, Error<'gc>>;
 }
 
+/// How calling a [`Callback`] is charged against the [`Fuel`](crate::Fuel) of the running
+/// `Executor`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum CallbackFuel {
+    /// The `Executor` charges a small fixed amount for every call, and the callback may consume
+    /// more fuel itself through [`Execution::fuel`].
+    #[default]
+    Metered,
+    /// Every call costs exactly this amount of fuel, regardless of how much fuel the callback
+    /// consumes or refunds itself.
+    Fixed(i32),
+    /// Calls cost no fuel at all, so that calling the callback does not affect the budget of the
+    /// calling script. This is useful for host functions like logging which should not count
+    /// against scripts.
+    Exempt,
+}
+
+impl CallbackFuel {
+    /// The amount of fuel charged for every call, or `None` if the callback is metered.
+    pub fn fixed_cost(self) -> Option<i32> {
+        match self {
+            CallbackFuel::Metered => None,
+            CallbackFuel::Fixed(cost) => Some(cost),
+            CallbackFuel::Exempt => Some(0),
+        }
+    }
+}
+
 /// A garbage collected instance of an object that impelments [`CallbackFn`].
 #[derive(Copy, Clone, Collect)]
 #[collect(no_drop)]
@@ -92,10 +122,20 @@ pub struct CallbackInner<'gc> {
         Execution<'gc, '_>,
         Stack<'gc, '_>,
     ) -> Result<CallbackReturn<'gc>, Error<'gc>>,
+    fuel: CallbackFuel,
 }
 
 impl<'gc> Callback<'gc> {
     pub fn new<C: CallbackFn<'gc> + 'gc>(mc: &Mutation<'gc>, callback: C) -> Self {
+        Self::new_with_fuel(mc, callback, CallbackFuel::Metered)
+    }
+
+    /// Create a callback which is charged for according to the given [`CallbackFuel`].
+    pub fn new_with_fuel<C: CallbackFn<'gc> + 'gc>(
+        mc: &Mutation<'gc>,
+        callback: C,
+        fuel: CallbackFuel,
+    ) -> Self {
         #[repr(C)]
         struct HeaderCallback<'gc, C> {
             header: CallbackInner<'gc>,
@@ -125,6 +165,7 @@ impl<'gc> Callback<'gc> {
                         let hc = ptr as *const HeaderCallback<C>;
                         ((*hc).callback).call(ctx, exec, stack)
                     },
+                    fuel,
                 },
                 callback,
             },
@@ -149,8 +190,90 @@ impl<'gc> Callback<'gc> {
         Self::from_fn_with(mc, (), move |_, ctx, exec, stack| call(ctx, exec, stack))
     }
 
+    /// Create a callback from a Rust function which takes and returns typed values.
+    ///
+    /// The arguments are converted with [`FromMultiValue`] before calling the function, and the
+    /// returned values are converted with [`IntoMultiValue`]. If an argument cannot be converted,
+    /// a Lua error like `"bad argument #2 to 'name' (table expected, got number)"` is raised, where
+    /// `name` is the given function name.
+    ///
+    /// ```
+    /// # use piccolo::{Callback, Lua, Table};
+    /// # let mut lua = Lua::core();
+    /// # lua.enter(|ctx| {
+    /// let callback = Callback::from_fn_typed(&ctx, "set", |ctx, (t, k, v): (Table, i64, i64)| {
+    ///     t.set(ctx, k, v)?;
+    ///     Ok(t.length())
+    /// });
+    /// # let _ = callback;
+    /// # });
+    /// ```
+    pub fn from_fn_typed<A, R, F>(mc: &Mutation<'gc>, name: &'static str, call: F) -> Callback<'gc>
+    where
+        A: FromMultiValue<'gc>,
+        R: IntoMultiValue<'gc>,
+        F: 'static + Fn(Context<'gc>, A) -> Result<R, Error<'gc>>,
+    {
+        Self::from_fn(mc, move |ctx, _, mut stack| {
+            let arg_count = stack.len();
+            let args = A::from_multi_value(ctx, stack.drain(..));
+
+            let args = args.map_err(|err| {
+                let position = err.position.unwrap_or(1);
+                let found = if position > arg_count {
+                    "no value"
+                } else {
+                    err.found_name.as_deref().unwrap_or(err.found)
+                };
+                format!(
+                    "bad argument #{position} to '{name}' ({} expected, got {found})",
+                    err.expected
+                )
+                .into_value(ctx)
+            })?;
+
+            stack.replace(ctx, call(ctx, args)?);
+            Ok(CallbackReturn::Return)
+        })
+    }
+
+    /// Create a callback from a Rust function which is charged for according to the given
+    /// [`CallbackFuel`].
+    pub fn from_fn_with_fuel<F>(mc: &Mutation<'gc>, fuel: CallbackFuel, call: F) -> Callback<'gc>
+    where
+        F: 'static
+            + Fn(
+                Context<'gc>,
+                Execution<'gc, '_>,
+                Stack<'gc, '_>,
+            ) -> Result<CallbackReturn<'gc>, Error<'gc>>,
+    {
+        Self::from_fn_with_root_and_fuel(mc, (), fuel, move |_, ctx, exec, stack| {
+            call(ctx, exec, stack)
+        })
+    }
+
     /// Create a callback from a Rust function together with a GC object.
     pub fn from_fn_with<R, F>(mc: &Mutation<'gc>, root: R, call: F) -> Callback<'gc>
+    where
+        R: 'gc + Collect,
+        F: 'static
+            + Fn(
+                &R,
+                Context<'gc>,
+                Execution<'gc, '_>,
+                Stack<'gc, '_>,
+            ) -> Result<CallbackReturn<'gc>, Error<'gc>>,
+    {
+        Self::from_fn_with_root_and_fuel(mc, root, CallbackFuel::Metered, call)
+    }
+
+    fn from_fn_with_root_and_fuel<R, F>(
+        mc: &Mutation<'gc>,
+        root: R,
+        fuel: CallbackFuel,
+        call: F,
+    ) -> Callback<'gc>
     where
         R: 'gc + Collect,
         F: 'static
@@ -190,7 +313,7 @@ impl<'gc> Callback<'gc> {
             }
         }
 
-        Callback::new(mc, RootCallback { root, call })
+        Callback::new_with_fuel(mc, RootCallback { root, call }, fuel)
     }
 
     pub fn from_inner(inner: Gc<'gc, CallbackInner<'gc>>) -> Self {
@@ -201,6 +324,11 @@ impl<'gc> Callback<'gc> {
         self.0
     }
 
+    /// How calls to this callback are charged for, set when the callback is created.
+    pub fn fuel(self) -> CallbackFuel {
+        self.0.fuel
+    }
+
     pub fn call(
         self,
         ctx: Context<'gc>,
@@ -242,6 +370,13 @@ pub enum SequencePoll<'gc> {
     /// `Sequence` is pending, `Sequence::poll` will be called on the next step with the stack
     /// unchanged.
     Pending,
+    /// `Sequence` is waiting on something outside of the `Executor`, such as I/O performed by the
+    /// host. The current thread is suspended as if by [`Thread::suspend_external`], and
+    /// `Sequence::poll` will be called with the stack unchanged once it is resumed.
+    ///
+    /// The `Sequence` should arrange for the waker from [`Execution::waker`] (if there is one) to
+    /// be woken once it can make progress, see [`Executor::set_waker`](crate::Executor::set_waker).
+    Suspend,
     /// Call the given functions with the arguments in the stack starting at `bottom`. When the
     /// function returns, `Sequence::poll` will be called with the return values placed into the
     /// stack starting at `bottom`. If the given function errors, then `Sequence::error` will be
@@ -311,6 +446,31 @@ pub trait Sequence<'gc>: Collect {
     ) -> Result<SequencePoll<'gc>, Error<'gc>> {
         Err(error)
     }
+
+    /// Whether this `Sequence` catches errors raised by the functions it calls.
+    ///
+    /// When an error is raised, the running `Executor` searches for the nearest `Sequence` below
+    /// the error that is not [`ErrorBoundary::Transparent`]. If that sequence returns
+    /// [`ErrorBoundary::Handler`], the handler is called with the error before any frames are
+    /// unwound, and its first return value replaces the error.
+    ///
+    /// By default, sequences are transparent.
+    fn error_boundary(&self) -> ErrorBoundary<'gc> {
+        ErrorBoundary::Transparent
+    }
+}
+
+/// How a [`Sequence`] treats errors raised by the functions it calls, see
+/// [`Sequence::error_boundary`].
+#[derive(Debug, Copy, Clone)]
+pub enum ErrorBoundary<'gc> {
+    /// Errors may or may not be caught by the sequence, a message handler below it will still be
+    /// called.
+    Transparent,
+    /// Errors are caught by the sequence without calling any message handler, like `pcall`.
+    Catch,
+    /// Errors are caught by the sequence after being passed to a message handler, like `xpcall`.
+    Handler(Function<'gc>),
 }
 
 /// A `Box` containing a value that implements [`Sequence`].
@@ -363,4 +523,8 @@ impl<'gc> BoxSequence<'gc> {
     ) -> Result<SequencePoll<'gc>, Error<'gc>> {
         self.0.as_mut().error(ctx, exec, error, stack)
     }
+
+    pub fn error_boundary(&self) -> ErrorBoundary<'gc> {
+        self.0.as_ref().get_ref().error_boundary()
+    }
 }
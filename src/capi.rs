@@ -0,0 +1,512 @@
+//! A minimal C API for embedding piccolo in applications that are not written in Rust.
+//!
+//! Enabled with the `capi` feature. Every function is `#[no_mangle] extern "C"` and the matching
+//! declarations are in `include/piccolo.h`, so a `cdylib` or `staticlib` crate which depends on
+//! piccolo with this feature enabled can be linked from C directly.
+//!
+//! No GC pointers are ever exposed. Loaded functions are stashed in the registry and referred to
+//! by integer handles, and call results are read out as JSON or as raw string bytes.
+//!
+//! A `PiccoloVm` must only be used from one thread at a time, and pointers returned from it are
+//! only valid until the next call which takes the same `PiccoloVm`.
+
+use std::{
+    ffi::{c_char, c_int, CStr, CString},
+    io::Write,
+    mem, ptr, slice,
+    string::String as StdString,
+};
+
+use crate::{
+    Closure, Executor, Fuel, Function, Lua, StashedExecutor, StashedFunction, StashedValue, Value,
+    Variadic,
+};
+
+/// The operation succeeded.
+pub const PICCOLO_OK: c_int = 0;
+/// Lua code raised an error, or there was an error loading a chunk.
+pub const PICCOLO_ERROR: c_int = 1;
+/// A call used more than the fuel limit set with `piccolo_set_fuel_limit`.
+pub const PICCOLO_FUEL_EXHAUSTED: c_int = 2;
+/// A call used more than the memory limit set with `piccolo_set_memory_limit`.
+pub const PICCOLO_MEMORY_EXHAUSTED: c_int = 3;
+/// An invalid handle or argument was passed.
+pub const PICCOLO_INVALID: c_int = 4;
+
+/// An embedded Lua instance with all of the stdlib loaded.
+pub struct PiccoloVm {
+    lua: Lua,
+    // Function handles are indexes into this list plus one, so that 0 is never a valid handle.
+    functions: Vec<Option<StashedFunction>>,
+    args: Vec<StashedValue>,
+    results: Vec<StashedValue>,
+    // Holds the data for the most recently returned pointer.
+    buffer: Vec<u8>,
+    error: CString,
+    fuel_limit: Option<u64>,
+    memory_limit: Option<usize>,
+}
+
+impl PiccoloVm {
+    // The fuel for each step of an executor, the arena is exited and limits are checked in between.
+    const FUEL_PER_STEP: i32 = 4096;
+    // Tables nested deeper than this are written to JSON as `null`, which also stops cycles.
+    const MAX_JSON_DEPTH: usize = 32;
+
+    fn new() -> Self {
+        Self {
+            lua: Lua::full(),
+            functions: Vec::new(),
+            args: Vec::new(),
+            results: Vec::new(),
+            buffer: Vec::new(),
+            error: CString::default(),
+            fuel_limit: None,
+            memory_limit: None,
+        }
+    }
+
+    fn fail(&mut self, code: c_int, message: impl ToString) -> c_int {
+        let mut message = message.to_string().into_bytes();
+        message.retain(|&b| b != 0);
+        self.error = CString::new(message).unwrap();
+        code
+    }
+
+    fn add_function(&mut self, function: StashedFunction) -> u64 {
+        let index = match self.functions.iter().position(Option::is_none) {
+            Some(index) => {
+                self.functions[index] = Some(function);
+                index
+            }
+            None => {
+                self.functions.push(Some(function));
+                self.functions.len() - 1
+            }
+        };
+        index as u64 + 1
+    }
+
+    fn function(&self, handle: u64) -> Option<&StashedFunction> {
+        let index = usize::try_from(handle.checked_sub(1)?).ok()?;
+        self.functions.get(index)?.as_ref()
+    }
+
+    fn over_memory_limit(&mut self) -> bool {
+        let Some(limit) = self.memory_limit else {
+            return false;
+        };
+        if self.lua.gc_metrics().total_allocation() <= limit {
+            return false;
+        }
+        self.lua.gc_collect();
+        self.lua.gc_metrics().total_allocation() > limit
+    }
+
+    fn call(&mut self, handle: u64) -> c_int {
+        let args = mem::take(&mut self.args);
+        self.results.clear();
+
+        let Some(function) = self.function(handle).cloned() else {
+            return self.fail(PICCOLO_INVALID, "invalid function handle");
+        };
+
+        let executor = self.lua.enter(|ctx| {
+            let args = args.iter().map(|arg| ctx.fetch(arg)).collect::<Vec<_>>();
+            ctx.stash(Executor::start(ctx, ctx.fetch(&function), Variadic(args)))
+        });
+
+        let mut fuel_used = 0u64;
+        loop {
+            if self.over_memory_limit() {
+                self.stop(&executor);
+                return self.fail(PICCOLO_MEMORY_EXHAUSTED, "memory limit exceeded");
+            }
+
+            let step_fuel = match self.fuel_limit {
+                Some(limit) if fuel_used >= limit => {
+                    self.stop(&executor);
+                    return self.fail(PICCOLO_FUEL_EXHAUSTED, "fuel limit exceeded");
+                }
+                Some(limit) => (limit - fuel_used).min(Self::FUEL_PER_STEP as u64) as i32,
+                None => Self::FUEL_PER_STEP,
+            };
+
+            let mut fuel = Fuel::with(step_fuel);
+            match self
+                .lua
+                .enter(|ctx| ctx.fetch(&executor).step(ctx, &mut fuel))
+            {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(err) => return self.fail(PICCOLO_ERROR, err),
+            }
+            fuel_used =
+                fuel_used.saturating_add(step_fuel.saturating_sub(fuel.remaining()).max(0) as u64);
+        }
+
+        match self.lua.try_enter(|ctx| {
+            let results = ctx
+                .fetch(&executor)
+                .take_result::<Variadic<Vec<Value>>>(ctx)??;
+            Ok(results.into_iter().map(|v| ctx.stash(v)).collect())
+        }) {
+            Ok(results) => {
+                self.results = results;
+                PICCOLO_OK
+            }
+            Err(err) => self.fail(PICCOLO_ERROR, err),
+        }
+    }
+
+    fn stop(&mut self, executor: &StashedExecutor) {
+        self.lua.enter(|ctx| ctx.fetch(executor).stop(&ctx));
+    }
+
+    fn results_json(&mut self) {
+        let mut buffer = mem::take(&mut self.buffer);
+        buffer.clear();
+        self.lua.enter(|ctx| {
+            buffer.push(b'[');
+            for (i, result) in self.results.iter().enumerate() {
+                if i != 0 {
+                    buffer.push(b',');
+                }
+                write_json(&mut buffer, ctx.fetch(result), 0);
+            }
+            buffer.push(b']');
+        });
+        self.buffer = buffer;
+    }
+}
+
+fn write_json(out: &mut Vec<u8>, value: Value<'_>, depth: usize) {
+    match value {
+        Value::Boolean(b) => write!(out, "{b}").unwrap(),
+        Value::Integer(i) => write!(out, "{i}").unwrap(),
+        Value::Number(n) if n.is_finite() => write!(out, "{n}").unwrap(),
+        Value::String(s) => write_json_string(out, &StdString::from_utf8_lossy(s.as_bytes())),
+        Value::Table(table) if depth < PiccoloVm::MAX_JSON_DEPTH => {
+            let len = table.length();
+            if len > 0 && table.iter().count() as i64 == len {
+                out.push(b'[');
+                for i in 1..=len {
+                    if i != 1 {
+                        out.push(b',');
+                    }
+                    write_json(out, table.get_raw(i.into()), depth + 1);
+                }
+                out.push(b']');
+            } else {
+                out.push(b'{');
+                let mut first = true;
+                for (key, value) in table.iter() {
+                    let key = match key {
+                        Value::String(s) => StdString::from_utf8_lossy(s.as_bytes()).into_owned(),
+                        Value::Integer(_) | Value::Number(_) => key.display().to_string(),
+                        _ => continue,
+                    };
+                    if !first {
+                        out.push(b',');
+                    }
+                    first = false;
+                    write_json_string(out, &key);
+                    out.push(b':');
+                    write_json(out, value, depth + 1);
+                }
+                out.push(b'}');
+            }
+        }
+        _ => out.extend_from_slice(b"null"),
+    }
+}
+
+fn write_json_string(out: &mut Vec<u8>, s: &str) {
+    out.push(b'"');
+    for c in s.chars() {
+        match c {
+            '"' => out.extend_from_slice(b"\\\""),
+            '\\' => out.extend_from_slice(b"\\\\"),
+            '\n' => out.extend_from_slice(b"\\n"),
+            '\r' => out.extend_from_slice(b"\\r"),
+            '\t' => out.extend_from_slice(b"\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => {
+                let mut buf = [0; 4];
+                out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+    out.push(b'"');
+}
+
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(data, len)
+    }
+}
+
+/// Create a new `PiccoloVm`, which must be freed with `piccolo_free`.
+#[no_mangle]
+pub extern "C" fn piccolo_new() -> *mut PiccoloVm {
+    Box::into_raw(Box::new(PiccoloVm::new()))
+}
+
+/// Free a `PiccoloVm` created with `piccolo_new`.
+///
+/// # Safety
+///
+/// `vm` must be null or a pointer returned from `piccolo_new` which has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn piccolo_free(vm: *mut PiccoloVm) {
+    if !vm.is_null() {
+        drop(Box::from_raw(vm));
+    }
+}
+
+/// Limit the amount of fuel, roughly the number of VM instructions, that a single call may use.
+/// A limit of 0 removes the limit.
+///
+/// # Safety
+///
+/// `vm` must be a valid pointer returned from `piccolo_new`.
+#[no_mangle]
+pub unsafe extern "C" fn piccolo_set_fuel_limit(vm: *mut PiccoloVm, fuel: u64) {
+    (*vm).fuel_limit = (fuel != 0).then_some(fuel);
+}
+
+/// Limit the total memory in bytes used by the VM, checked while running calls. A limit of 0
+/// removes the limit.
+///
+/// # Safety
+///
+/// `vm` must be a valid pointer returned from `piccolo_new`.
+#[no_mangle]
+pub unsafe extern "C" fn piccolo_set_memory_limit(vm: *mut PiccoloVm, bytes: usize) {
+    (*vm).memory_limit = (bytes != 0).then_some(bytes);
+}
+
+/// Compile a chunk of Lua source and return a handle to it as a function, or 0 if compilation
+/// fails.
+///
+/// # Safety
+///
+/// `vm` must be a valid pointer returned from `piccolo_new`, `name` must be null or a
+/// nul-terminated string, and `source` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn piccolo_load(
+    vm: *mut PiccoloVm,
+    name: *const c_char,
+    source: *const u8,
+    len: usize,
+) -> u64 {
+    let vm = &mut *vm;
+    let name = (!name.is_null()).then(|| CStr::from_ptr(name).to_string_lossy());
+    let source = bytes(source, len);
+    match vm.lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, name.as_deref(), source)?;
+        Ok(ctx.stash(Function::from(closure)))
+    }) {
+        Ok(function) => vm.add_function(function),
+        Err(err) => {
+            vm.fail(PICCOLO_ERROR, err);
+            0
+        }
+    }
+}
+
+/// Return a handle to the global function with the given name, or 0 if it is not a function.
+///
+/// # Safety
+///
+/// `vm` must be a valid pointer returned from `piccolo_new` and `name` must be a nul-terminated
+/// string.
+#[no_mangle]
+pub unsafe extern "C" fn piccolo_get_global(vm: *mut PiccoloVm, name: *const c_char) -> u64 {
+    let vm = &mut *vm;
+    let name = CStr::from_ptr(name).to_bytes();
+    match vm
+        .lua
+        .enter(|ctx| match ctx.globals().get_value(ctx, ctx.intern(name)) {
+            Value::Function(f) => Some(ctx.stash(f)),
+            _ => None,
+        }) {
+        Some(function) => vm.add_function(function),
+        None => {
+            vm.fail(PICCOLO_INVALID, "global is not a function");
+            0
+        }
+    }
+}
+
+/// Release a function handle. Releasing an invalid handle does nothing.
+///
+/// # Safety
+///
+/// `vm` must be a valid pointer returned from `piccolo_new`.
+#[no_mangle]
+pub unsafe extern "C" fn piccolo_release(vm: *mut PiccoloVm, function: u64) {
+    let vm = &mut *vm;
+    if vm.function(function).is_some() {
+        vm.functions[function as usize - 1] = None;
+    }
+}
+
+/// Push a nil argument for the next call.
+///
+/// # Safety
+///
+/// `vm` must be a valid pointer returned from `piccolo_new`.
+#[no_mangle]
+pub unsafe extern "C" fn piccolo_push_nil(vm: *mut PiccoloVm) {
+    let vm = &mut *vm;
+    let arg = vm.lua.enter(|ctx| ctx.stash(Value::Nil));
+    vm.args.push(arg);
+}
+
+/// Push a boolean argument for the next call, any non-zero value is true.
+///
+/// # Safety
+///
+/// `vm` must be a valid pointer returned from `piccolo_new`.
+#[no_mangle]
+pub unsafe extern "C" fn piccolo_push_boolean(vm: *mut PiccoloVm, value: c_int) {
+    let vm = &mut *vm;
+    let arg = vm.lua.enter(|ctx| ctx.stash(Value::Boolean(value != 0)));
+    vm.args.push(arg);
+}
+
+/// Push an integer argument for the next call.
+///
+/// # Safety
+///
+/// `vm` must be a valid pointer returned from `piccolo_new`.
+#[no_mangle]
+pub unsafe extern "C" fn piccolo_push_integer(vm: *mut PiccoloVm, value: i64) {
+    let vm = &mut *vm;
+    let arg = vm.lua.enter(|ctx| ctx.stash(Value::Integer(value)));
+    vm.args.push(arg);
+}
+
+/// Push a floating point argument for the next call.
+///
+/// # Safety
+///
+/// `vm` must be a valid pointer returned from `piccolo_new`.
+#[no_mangle]
+pub unsafe extern "C" fn piccolo_push_number(vm: *mut PiccoloVm, value: f64) {
+    let vm = &mut *vm;
+    let arg = vm.lua.enter(|ctx| ctx.stash(Value::Number(value)));
+    vm.args.push(arg);
+}
+
+/// Push a string argument for the next call, which may contain any bytes.
+///
+/// # Safety
+///
+/// `vm` must be a valid pointer returned from `piccolo_new` and `data` must point to `len`
+/// readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn piccolo_push_string(vm: *mut PiccoloVm, data: *const u8, len: usize) {
+    let vm = &mut *vm;
+    let data = bytes(data, len);
+    let arg = vm
+        .lua
+        .enter(|ctx| ctx.stash(Value::String(ctx.intern(data))));
+    vm.args.push(arg);
+}
+
+/// Call a function with every argument pushed since the last call.
+///
+/// Returns `PICCOLO_OK` on success, and the results can then be read with
+/// `piccolo_result_count`, `piccolo_result_json`, and `piccolo_result_string`. Otherwise, returns
+/// an error code and the message can be read with `piccolo_error`.
+///
+/// # Safety
+///
+/// `vm` must be a valid pointer returned from `piccolo_new`.
+#[no_mangle]
+pub unsafe extern "C" fn piccolo_call(vm: *mut PiccoloVm, function: u64) -> c_int {
+    (*vm).call(function)
+}
+
+/// The number of values returned by the last successful call.
+///
+/// # Safety
+///
+/// `vm` must be a valid pointer returned from `piccolo_new`.
+#[no_mangle]
+pub unsafe extern "C" fn piccolo_result_count(vm: *const PiccoloVm) -> usize {
+    (*vm).results.len()
+}
+
+/// Every value returned by the last successful call as a UTF-8 JSON array, which is *not*
+/// nul-terminated. The length is written to `len`.
+///
+/// Tables which are sequences become arrays, other tables become objects with only their string
+/// and number keys, and functions, threads, and userdata become `null`.
+///
+/// # Safety
+///
+/// `vm` must be a valid pointer returned from `piccolo_new` and `len` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn piccolo_result_json(vm: *mut PiccoloVm, len: *mut usize) -> *const u8 {
+    let vm = &mut *vm;
+    vm.results_json();
+    *len = vm.buffer.len();
+    vm.buffer.as_ptr()
+}
+
+/// The bytes of a string or number returned by the last successful call, which are *not*
+/// nul-terminated. The length is written to `len`.
+///
+/// Returns null if there is no result at `index` or if it is not a string or number.
+///
+/// # Safety
+///
+/// `vm` must be a valid pointer returned from `piccolo_new` and `len` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn piccolo_result_string(
+    vm: *mut PiccoloVm,
+    index: usize,
+    len: *mut usize,
+) -> *const u8 {
+    let vm = &mut *vm;
+    let Some(result) = vm.results.get(index) else {
+        return ptr::null();
+    };
+
+    let mut buffer = mem::take(&mut vm.buffer);
+    buffer.clear();
+    let found = vm.lua.enter(|ctx| match ctx.fetch(result) {
+        Value::String(s) => {
+            buffer.extend_from_slice(s.as_bytes());
+            true
+        }
+        v @ (Value::Integer(_) | Value::Number(_)) => {
+            write!(buffer, "{}", v.display()).unwrap();
+            true
+        }
+        _ => false,
+    });
+    vm.buffer = buffer;
+
+    if found {
+        *len = vm.buffer.len();
+        vm.buffer.as_ptr()
+    } else {
+        ptr::null()
+    }
+}
+
+/// The message for the last error, as a nul-terminated string.
+///
+/// # Safety
+///
+/// `vm` must be a valid pointer returned from `piccolo_new`.
+#[no_mangle]
+pub unsafe extern "C" fn piccolo_error(vm: *const PiccoloVm) -> *const c_char {
+    (*vm).error.as_ptr()
+}
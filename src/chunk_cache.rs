@@ -0,0 +1,265 @@
+use std::{
+    hash::{BuildHasherDefault, Hash, Hasher},
+    mem,
+};
+
+use ahash::AHasher;
+use allocator_api2::vec;
+use gc_arena::{allocator_api::MetricsAlloc, lock::RefLock, Collect, Gc, Mutation};
+use hashbrown::HashMap;
+
+use crate::{CompileOptions, CompilerError, FunctionPrototype};
+
+/// Limits on the size of a [`ChunkCache`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ChunkCacheLimits {
+    /// The maximum number of cached chunks. Limits below 2 disable caching.
+    pub max_entries: usize,
+    /// The maximum total length of the source of every cached chunk. Chunks with a source longer
+    /// than half of this are never cached.
+    pub max_source_bytes: usize,
+}
+
+impl Default for ChunkCacheLimits {
+    fn default() -> Self {
+        Self {
+            max_entries: 256,
+            max_source_bytes: 1 << 20,
+        }
+    }
+}
+
+/// Counters describing the use of a [`ChunkCache`].
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ChunkCacheMetrics {
+    /// Loads which reused a cached prototype.
+    pub hits: u64,
+    /// Loads which had to compile their source.
+    pub misses: u64,
+    /// Cached chunks which were dropped to stay within the limits.
+    pub evictions: u64,
+    /// The number of chunks currently cached.
+    pub entries: usize,
+    /// The total length of the source of every chunk currently cached.
+    pub source_bytes: usize,
+}
+
+/// A cache of compiled prototypes keyed by chunk name, [`CompileOptions`], and source.
+///
+/// This lets workloads which load the same snippets over and over, like a rules engine evaluating
+/// the same expressions, skip compiling them again. The cache is disabled by default and is
+/// enabled with [`ChunkCache::set_limits`] or [`Lua::set_chunk_cache`](crate::Lua::set_chunk_cache).
+/// While enabled, every chunk loaded with [`Closure::load`](crate::Closure::load) and its variants
+/// goes through the cache, including chunks loaded by the `load` function of the base library.
+/// Every load still creates a new closure with its own `_ENV`, only the immutable prototype is
+/// shared.
+///
+/// Chunks are kept in two generations which each hold at most half of the limits. New chunks are
+/// added to the young generation, and once it is full it replaces the old generation, dropping
+/// every chunk which was in the old generation. A hit in the old generation moves the chunk back
+/// to the young generation, so chunks which are still being used survive.
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub struct ChunkCache<'gc>(Gc<'gc, RefLock<ChunkCacheState<'gc>>>);
+
+impl<'gc> ChunkCache<'gc> {
+    pub(crate) fn new(mc: &Mutation<'gc>) -> Self {
+        ChunkCache(Gc::new(
+            mc,
+            RefLock::new(ChunkCacheState {
+                limits: None,
+                young: Generation::new(mc),
+                old: Generation::new(mc),
+                metrics: ChunkCacheMetrics::default(),
+            }),
+        ))
+    }
+
+    pub fn limits(self) -> Option<ChunkCacheLimits> {
+        self.0.borrow().limits
+    }
+
+    /// Enable the cache with the given limits, or disable it with `None`.
+    ///
+    /// Changing the limits drops every cached chunk, but keeps the hit, miss, and eviction
+    /// counters.
+    pub fn set_limits(self, mc: &Mutation<'gc>, limits: Option<ChunkCacheLimits>) {
+        let mut state = self.0.borrow_mut(mc);
+        state.limits = limits;
+        state.young.clear();
+        state.old.clear();
+    }
+
+    pub fn is_enabled(self) -> bool {
+        self.limits().is_some()
+    }
+
+    pub fn metrics(self) -> ChunkCacheMetrics {
+        let state = self.0.borrow();
+        ChunkCacheMetrics {
+            entries: state.young.chunks.len() + state.old.chunks.len(),
+            source_bytes: state.young.source_bytes + state.old.source_bytes,
+            ..state.metrics
+        }
+    }
+
+    /// Drop every cached chunk.
+    pub fn clear(self, mc: &Mutation<'gc>) {
+        let mut state = self.0.borrow_mut(mc);
+        state.young.clear();
+        state.old.clear();
+    }
+
+    /// Returns the cached prototype for the given chunk, or compiles and caches a new one.
+    ///
+    /// If the cache is disabled, this always compiles a new prototype.
+    pub(crate) fn get_or_compile(
+        self,
+        mc: &Mutation<'gc>,
+        name: &str,
+        options: CompileOptions,
+        source: &[u8],
+        compile: impl FnOnce() -> Result<FunctionPrototype<'gc>, CompilerError>,
+    ) -> Result<Gc<'gc, FunctionPrototype<'gc>>, CompilerError> {
+        let Some(limits) = self.limits() else {
+            return Ok(Gc::new(mc, compile()?));
+        };
+
+        let key = chunk_key(name, options, source);
+        {
+            let mut state = self.0.borrow_mut(mc);
+            let state = &mut *state;
+            if let Some(chunk) = state.young.chunks.get(&key) {
+                if chunk.matches(name, options, source) {
+                    state.metrics.hits += 1;
+                    return Ok(chunk.prototype);
+                }
+            }
+            if let Some(chunk) = state.old.take(key, name, options, source) {
+                state.metrics.hits += 1;
+                let prototype = chunk.prototype;
+                state.insert(limits, key, chunk);
+                return Ok(prototype);
+            }
+            state.metrics.misses += 1;
+        }
+
+        // The cache is not borrowed while compiling, compilation may allocate and must not be able
+        // to observe a half-updated cache.
+        let prototype = Gc::new(mc, compile()?);
+
+        if limits.max_entries >= 2 && source.len() <= limits.max_source_bytes / 2 {
+            let mut cached_source = vec::Vec::new_in(MetricsAlloc::new(mc));
+            cached_source.extend_from_slice(source);
+            self.0.borrow_mut(mc).insert(
+                limits,
+                key,
+                CachedChunk {
+                    name: name.into(),
+                    options,
+                    source: cached_source,
+                    prototype,
+                },
+            );
+        }
+
+        Ok(prototype)
+    }
+}
+
+#[derive(Collect)]
+#[collect(no_drop)]
+struct ChunkCacheState<'gc> {
+    #[collect(require_static)]
+    limits: Option<ChunkCacheLimits>,
+    young: Generation<'gc>,
+    old: Generation<'gc>,
+    // Only the counters are kept here, the sizes are computed from the generations.
+    #[collect(require_static)]
+    metrics: ChunkCacheMetrics,
+}
+
+impl<'gc> ChunkCacheState<'gc> {
+    fn insert(&mut self, limits: ChunkCacheLimits, key: u64, chunk: CachedChunk<'gc>) {
+        if self.young.chunks.len() >= limits.max_entries / 2
+            || self.young.source_bytes + chunk.source.len() > limits.max_source_bytes / 2
+        {
+            // The young generation is full, so it becomes the old generation and everything in the
+            // current old generation is dropped.
+            mem::swap(&mut self.young, &mut self.old);
+            self.metrics.evictions += self.young.chunks.len() as u64;
+            self.young.clear();
+        }
+        self.young.insert(key, chunk);
+    }
+}
+
+#[derive(Collect)]
+#[collect(no_drop)]
+struct Generation<'gc> {
+    chunks: HashMap<u64, CachedChunk<'gc>, BuildHasherDefault<AHasher>, MetricsAlloc<'gc>>,
+    source_bytes: usize,
+}
+
+impl<'gc> Generation<'gc> {
+    fn new(mc: &Mutation<'gc>) -> Self {
+        Self {
+            chunks: HashMap::with_hasher_in(BuildHasherDefault::default(), MetricsAlloc::new(mc)),
+            source_bytes: 0,
+        }
+    }
+
+    fn insert(&mut self, key: u64, chunk: CachedChunk<'gc>) {
+        self.source_bytes += chunk.source.len();
+        if let Some(replaced) = self.chunks.insert(key, chunk) {
+            self.source_bytes -= replaced.source.len();
+        }
+    }
+
+    fn take(
+        &mut self,
+        key: u64,
+        name: &str,
+        options: CompileOptions,
+        source: &[u8],
+    ) -> Option<CachedChunk<'gc>> {
+        if !self.chunks.get(&key)?.matches(name, options, source) {
+            return None;
+        }
+        let chunk = self.chunks.remove(&key)?;
+        self.source_bytes -= chunk.source.len();
+        Some(chunk)
+    }
+
+    fn clear(&mut self) {
+        self.chunks.clear();
+        self.source_bytes = 0;
+    }
+}
+
+#[derive(Collect)]
+#[collect(no_drop)]
+struct CachedChunk<'gc> {
+    #[collect(require_static)]
+    name: Box<str>,
+    #[collect(require_static)]
+    options: CompileOptions,
+    source: vec::Vec<u8, MetricsAlloc<'gc>>,
+    prototype: Gc<'gc, FunctionPrototype<'gc>>,
+}
+
+impl<'gc> CachedChunk<'gc> {
+    // Chunks are looked up by a hash of their key, so the whole key must be compared to rule out a
+    // collision.
+    fn matches(&self, name: &str, options: CompileOptions, source: &[u8]) -> bool {
+        *self.name == *name && self.options == options && self.source[..] == *source
+    }
+}
+
+fn chunk_key(name: &str, options: CompileOptions, source: &[u8]) -> u64 {
+    let mut hasher = AHasher::default();
+    name.hash(&mut hasher);
+    options.hash(&mut hasher);
+    source.hash(&mut hasher);
+    hasher.finish()
+}
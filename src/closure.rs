@@ -1,4 +1,5 @@
 use std::{
+    fmt,
     hash::{Hash, Hasher},
     io::Read,
 };
@@ -8,19 +9,170 @@ use gc_arena::{allocator_api::MetricsAlloc, lock::Lock, Collect, Gc, Mutation};
 use thiserror::Error;
 
 use crate::{
-    compiler::{self, CompiledPrototype, FunctionRef, LineNumber},
-    opcode::OpCode,
+    compiler::{
+        self, ast, lexer::LexError, Annotation, CompiledPrototype, FunctionRef, LineNumber,
+        LocalVariable, Restrictions, SourceLocation,
+    },
+    opcode::{OpCode, Operation, RCIndex},
     thread::OpenUpValue,
-    types::UpValueDescriptor,
+    types::{RegisterIndex, UpValueDescriptor, UpValueIndex, VarCount},
     Constant, Context, String, Table, Value,
 };
 
+/// A description of where a value came from, returned by [`FunctionPrototype::describe_register`].
+///
+/// Displays the same way as the variable descriptions in PUC-Rio Lua error messages, like
+/// `global 'print'` or `method 'insert'`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VariableName<'gc> {
+    Local(String<'gc>),
+    UpValue(String<'gc>),
+    Global(String<'gc>),
+    Field(String<'gc>),
+    Method(String<'gc>),
+    Constant(String<'gc>),
+}
+
+impl<'gc> VariableName<'gc> {
+    /// The kind of variable, like `"global"` or `"local"`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            VariableName::Local(_) => "local",
+            VariableName::UpValue(_) => "upvalue",
+            VariableName::Global(_) => "global",
+            VariableName::Field(_) => "field",
+            VariableName::Method(_) => "method",
+            VariableName::Constant(_) => "constant",
+        }
+    }
+
+    /// The name of the variable, field, method, or the contents of the string constant.
+    pub fn name(&self) -> String<'gc> {
+        match *self {
+            VariableName::Local(n)
+            | VariableName::UpValue(n)
+            | VariableName::Global(n)
+            | VariableName::Field(n)
+            | VariableName::Method(n)
+            | VariableName::Constant(n) => n,
+        }
+    }
+}
+
+impl<'gc> fmt::Display for VariableName<'gc> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} '{}'", self.kind(), self.name().display_lossy())
+    }
+}
+
+/// An inconsistency found by [`FunctionPrototype::validate`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Error)]
+pub enum PrototypeError {
+    #[error("opcode {opcode} references constant {index}, but there are only {count} constants")]
+    ConstantOutOfRange {
+        opcode: usize,
+        index: usize,
+        count: usize,
+    },
+    #[error("opcode {opcode} references prototype {index}, but there are only {count} prototypes")]
+    PrototypeOutOfRange {
+        opcode: usize,
+        index: usize,
+        count: usize,
+    },
+    #[error("opcode {opcode} references upvalue {index}, but there are only {count} upvalues")]
+    UpValueOutOfRange {
+        opcode: usize,
+        index: usize,
+        count: usize,
+    },
+    #[error("opcode {opcode} references register {register}, but the stack size is {stack_size}")]
+    RegisterOutOfRange {
+        opcode: usize,
+        register: usize,
+        stack_size: usize,
+    },
+    #[error("opcode {opcode} jumps to {target}, but there are only {count} opcodes")]
+    JumpOutOfRange {
+        opcode: usize,
+        target: isize,
+        count: usize,
+    },
+    #[error("execution can continue past the last opcode")]
+    MissingReturn,
+    #[error("{fixed_params} fixed parameters do not fit in a stack size of {stack_size}")]
+    TooManyParameters {
+        fixed_params: usize,
+        stack_size: usize,
+    },
+    #[error("upvalue {upvalue} of prototype {prototype} refers to a missing register or upvalue")]
+    BadUpValueDescriptor { prototype: usize, upvalue: usize },
+    #[error(
+        "prototype {prototype} has an _ENV upvalue, which only a top-level prototype may have"
+    )]
+    NestedEnvUpValue { prototype: usize },
+}
+
 #[derive(Debug, Error)]
 pub enum CompilerError {
     #[error("parse error")]
     Parsing(#[from] compiler::ParseError),
     #[error("compile error")]
     Compilation(#[from] compiler::CompileError),
+    /// An error within one of the fragments passed to [`FunctionPrototype::compile_fragments`].
+    ///
+    /// The line number and source locations of the inner error are relative to the start of the
+    /// fragment.
+    #[error("error in fragment '{name}'")]
+    Fragment {
+        name: std::string::String,
+        #[source]
+        error: Box<CompilerError>,
+    },
+}
+
+impl CompilerError {
+    /// Returns true if compilation failed only because the source ended unexpectedly, such as in
+    /// the middle of a block or a long string.
+    ///
+    /// A REPL can use this to prompt for a continuation line instead of reporting a syntax error.
+    pub fn is_incomplete(&self) -> bool {
+        match self {
+            CompilerError::Parsing(err) => err.is_incomplete(),
+            CompilerError::Compilation(_) => false,
+            CompilerError::Fragment { error, .. } => error.is_incomplete(),
+        }
+    }
+
+    /// Returns the exact source location of a parse error.
+    ///
+    /// Errors found after parsing only record a line number, and return `None`.
+    pub fn location(&self) -> Option<SourceLocation> {
+        match self {
+            CompilerError::Parsing(err) => Some(err.location),
+            CompilerError::Compilation(_) => None,
+            CompilerError::Fragment { error, .. } => error.location(),
+        }
+    }
+
+    /// Returns the location of a related site of a parse error, such as the opening token of an
+    /// unclosed block or bracket.
+    pub fn related_location(&self) -> Option<SourceLocation> {
+        match self {
+            CompilerError::Parsing(err) => err.related,
+            CompilerError::Compilation(_) => None,
+            CompilerError::Fragment { error, .. } => error.related_location(),
+        }
+    }
+}
+
+/// A named piece of the source of a chunk compiled with [`FunctionPrototype::compile_fragments`].
+#[derive(Debug, Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub struct SourceFragment<'gc> {
+    pub name: String<'gc>,
+    /// The line of the combined source on which this fragment starts.
+    pub first_line: LineNumber,
 }
 
 /// A compiled Lua function.
@@ -43,6 +195,45 @@ pub struct FunctionPrototype<'gc> {
     pub opcode_line_numbers: boxed::Box<[(usize, LineNumber)], MetricsAlloc<'gc>>,
     pub upvalues: boxed::Box<[UpValueDescriptor], MetricsAlloc<'gc>>,
     pub prototypes: boxed::Box<[Gc<'gc, FunctionPrototype<'gc>>], MetricsAlloc<'gc>>,
+    /// Debug info: the `---@tag text` annotation comments attached to this function's definition.
+    ///
+    /// Only present if the prototype was compiled with
+    /// [`FunctionPrototype::compile_with_annotations`].
+    pub annotations: boxed::Box<[Annotation<String<'gc>>], MetricsAlloc<'gc>>,
+    /// If true, reading a nil value from the globals table within this function is an error
+    /// rather than evaluating to nil.
+    ///
+    /// Set for every function in a chunk compiled with [`CompileOptions::strict_globals`].
+    pub strict_globals: bool,
+    /// Debug info: the fragments that the chunk was assembled from, if it was compiled with
+    /// [`FunctionPrototype::compile_fragments`].
+    ///
+    /// The lines in `opcode_line_numbers` are lines of the combined source, use
+    /// [`FunctionPrototype::opcode_location`] to find the fragment and line within it.
+    pub fragments: boxed::Box<[SourceFragment<'gc>], MetricsAlloc<'gc>>,
+    /// Debug info: every local variable declared in this function, including parameters, in
+    /// order of declaration.
+    ///
+    /// Use [`FunctionPrototype::local_name`] or [`FunctionPrototype::active_locals`] to find the
+    /// locals at a particular opcode.
+    pub local_variables: boxed::Box<[LocalVariable<String<'gc>>], MetricsAlloc<'gc>>,
+    /// Debug info: the name of each upvalue, in the same order as `upvalues`.
+    pub upvalue_names: boxed::Box<[String<'gc>], MetricsAlloc<'gc>>,
+}
+
+/// Options for compiling a chunk of Lua source.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub struct CompileOptions {
+    /// Collect `---@tag text` annotation comments into [`FunctionPrototype::annotations`].
+    pub annotations: bool,
+    /// Make reading an undeclared (nil) global an error which names the global and its location,
+    /// like the classic `strict.lua`, but only for code within this chunk.
+    ///
+    /// A global is any name read through `_ENV`, so this applies to whichever environment the
+    /// chunk is run with.
+    pub strict_globals: bool,
+    /// Syntax to reject while parsing the chunk, see [`Restrictions`].
+    pub restrictions: Restrictions,
 }
 
 impl<'gc> FunctionPrototype<'gc> {
@@ -59,15 +250,45 @@ impl<'gc> FunctionPrototype<'gc> {
         chunk_name: String<'gc>,
         compiled_function: &CompiledPrototype<S>,
         map_string: impl Fn(&S) -> String<'gc>,
+    ) -> Self {
+        Self::from_compiled_impl(mc, chunk_name, compiled_function, map_string, false, &[])
+    }
+
+    fn from_compiled_impl<S>(
+        mc: &Mutation<'gc>,
+        chunk_name: String<'gc>,
+        compiled_function: &CompiledPrototype<S>,
+        map_string: impl Fn(&S) -> String<'gc>,
+        strict_globals: bool,
+        fragments: &[SourceFragment<'gc>],
     ) -> Self {
         fn new<'gc, S>(
             mc: &Mutation<'gc>,
             chunk_name: String<'gc>,
             compiled_function: &CompiledPrototype<S>,
             map_string: impl Fn(&S) -> String<'gc> + Copy,
+            strict_globals: bool,
+            fragments: &[SourceFragment<'gc>],
         ) -> FunctionPrototype<'gc> {
             let alloc = MetricsAlloc::new(mc);
 
+            // Functions defined within a fragment are named after the fragment rather than the
+            // whole chunk.
+            let mut chunk_name = chunk_name;
+            let mut reference = compiled_function
+                .reference
+                .as_string_ref()
+                .map_strings(map_string);
+            match &mut reference {
+                FunctionRef::Named(_, line) | FunctionRef::Expression(line) => {
+                    if let Some((name, local_line)) = locate_fragment(fragments, *line) {
+                        chunk_name = name;
+                        *line = local_line;
+                    }
+                }
+                FunctionRef::Chunk => {}
+            }
+
             let mut constants = vec::Vec::new_in(alloc.clone());
             constants.extend(
                 compiled_function
@@ -84,20 +305,36 @@ impl<'gc> FunctionPrototype<'gc> {
             let upvalues =
                 SliceExt::to_vec_in(compiled_function.upvalues.as_slice(), alloc.clone());
 
-            let mut prototypes = vec::Vec::new_in(alloc);
-            prototypes.extend(
+            let mut annotations = vec::Vec::new_in(alloc.clone());
+            annotations.extend(
                 compiled_function
-                    .prototypes
+                    .annotations
                     .iter()
-                    .map(|cf| Gc::new(mc, new(mc, chunk_name, cf, map_string))),
+                    .map(|a| a.as_string_ref().map_strings(map_string)),
             );
 
+            let mut local_variables = vec::Vec::new_in(alloc.clone());
+            local_variables.extend(
+                compiled_function
+                    .local_variables
+                    .iter()
+                    .map(|l| l.as_string_ref().map_strings(map_string)),
+            );
+
+            let mut upvalue_names = vec::Vec::new_in(alloc.clone());
+            upvalue_names.extend(compiled_function.upvalue_names.iter().map(map_string));
+
+            let mut prototypes = vec::Vec::new_in(alloc.clone());
+            prototypes.extend(compiled_function.prototypes.iter().map(|cf| {
+                Gc::new(
+                    mc,
+                    new(mc, chunk_name, cf, map_string, strict_globals, fragments),
+                )
+            }));
+
             FunctionPrototype {
                 chunk_name,
-                reference: compiled_function
-                    .reference
-                    .as_string_ref()
-                    .map_strings(map_string),
+                reference,
                 fixed_params: compiled_function.fixed_params,
                 has_varargs: compiled_function.has_varargs,
                 stack_size: compiled_function.stack_size,
@@ -106,10 +343,22 @@ impl<'gc> FunctionPrototype<'gc> {
                 opcode_line_numbers: opcode_line_numbers.into_boxed_slice(),
                 upvalues: upvalues.into_boxed_slice(),
                 prototypes: prototypes.into_boxed_slice(),
+                annotations: annotations.into_boxed_slice(),
+                strict_globals,
+                fragments: SliceExt::to_vec_in(fragments, alloc).into_boxed_slice(),
+                local_variables: local_variables.into_boxed_slice(),
+                upvalue_names: upvalue_names.into_boxed_slice(),
             }
         }
 
-        new(mc, chunk_name, compiled_function, &map_string)
+        new(
+            mc,
+            chunk_name,
+            compiled_function,
+            &map_string,
+            strict_globals,
+            fragments,
+        )
     }
 
     pub fn compile(
@@ -117,28 +366,718 @@ impl<'gc> FunctionPrototype<'gc> {
         source_name: &str,
         source: impl Read,
     ) -> Result<FunctionPrototype<'gc>, CompilerError> {
-        #[derive(Copy, Clone)]
-        struct Interner<'gc>(Context<'gc>);
+        Self::compile_with_options(ctx, source_name, source, CompileOptions::default())
+    }
+
+    /// Compile a prototype, additionally collecting `---@param` / `---@return` style annotation
+    /// comments into the [`FunctionPrototype::annotations`] of every function they precede.
+    pub fn compile_with_annotations(
+        ctx: Context<'gc>,
+        source_name: &str,
+        source: impl Read,
+    ) -> Result<FunctionPrototype<'gc>, CompilerError> {
+        Self::compile_with_options(
+            ctx,
+            source_name,
+            source,
+            CompileOptions {
+                annotations: true,
+                ..Default::default()
+            },
+        )
+    }
 
-        impl<'gc> compiler::StringInterner for Interner<'gc> {
-            type String = String<'gc>;
+    pub fn compile_with_options(
+        ctx: Context<'gc>,
+        source_name: &str,
+        source: impl Read,
+        options: CompileOptions,
+    ) -> Result<FunctionPrototype<'gc>, CompilerError> {
+        Self::compile_impl(ctx, source_name, source, options, &[])
+    }
 
-            fn intern(&mut self, s: &[u8]) -> Self::String {
-                self.0.intern(s)
+    /// Compile a single chunk assembled from several named fragments of source.
+    ///
+    /// This is useful for hosts which wrap user code in a prologue and epilogue: the fragments are
+    /// compiled as though they were concatenated, but the debug info of the resulting prototype
+    /// still refers to each fragment by its own name and lines. Each fragment starts on a new
+    /// line of the combined source.
+    ///
+    /// Compile errors are reported as [`CompilerError::Fragment`] with a line number relative to
+    /// the fragment they occur in.
+    pub fn compile_fragments<'a>(
+        ctx: Context<'gc>,
+        fragments: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+        options: CompileOptions,
+    ) -> Result<FunctionPrototype<'gc>, CompilerError> {
+        let mut chunk_name = None;
+        let mut source = Vec::new();
+        let mut source_fragments = Vec::new();
+        let mut fragment_offsets = Vec::new();
+        let mut line = 0;
+        for (name, fragment) in fragments {
+            chunk_name.get_or_insert(name);
+            source_fragments.push(SourceFragment {
+                name: ctx.intern(name.as_bytes()),
+                first_line: LineNumber(line),
+            });
+
+            let start = source.len();
+            fragment_offsets.push(start as u64);
+            source.extend_from_slice(fragment);
+            if !fragment.is_empty() && fragment.last() != Some(&b'\n') {
+                source.push(b'\n');
             }
+            line += count_lines(&source[start..]);
         }
 
-        let interner = Interner(ctx);
+        Self::compile_impl(
+            ctx,
+            chunk_name.unwrap_or("<anonymous>"),
+            &source[..],
+            options,
+            &source_fragments,
+        )
+        .map_err(|mut err| {
+            let line_number = match &err {
+                CompilerError::Parsing(err) => err.line_number,
+                CompilerError::Compilation(err) => err.line_number,
+                CompilerError::Fragment { .. } => return err,
+            };
+            let Some(index) = fragment_index(&source_fragments, line_number) else {
+                return err;
+            };
+            let fragment = source_fragments[index];
+            let to_local = |location: SourceLocation| SourceLocation {
+                offset: location.offset - fragment_offsets[index],
+                line_number: LineNumber(location.line_number.0 - fragment.first_line.0),
+                column: location.column,
+            };
+
+            match &mut err {
+                CompilerError::Parsing(err) => {
+                    err.location = to_local(err.location);
+                    err.line_number = err.location.line_number;
+                    // A related location in another fragment cannot be expressed relative to this
+                    // one.
+                    err.related = err
+                        .related
+                        .filter(|related| {
+                            fragment_index(&source_fragments, related.line_number) == Some(index)
+                        })
+                        .map(to_local);
+                }
+                CompilerError::Compilation(err) => {
+                    err.line_number = LineNumber(err.line_number.0 - fragment.first_line.0);
+                }
+                CompilerError::Fragment { .. } => unreachable!(),
+            }
+
+            CompilerError::Fragment {
+                name: fragment.name.display_lossy().to_string(),
+                error: Box::new(err),
+            }
+        })
+    }
+
+    /// Parse source into an AST without compiling it.
+    ///
+    /// The resulting chunk can be checked or transformed, for example to inject instrumentation
+    /// calls, and then compiled with [`FunctionPrototype::compile_ast`]. Only the parsing options
+    /// in `options` are used here.
+    pub fn parse(
+        ctx: Context<'gc>,
+        source: impl Read,
+        options: CompileOptions,
+    ) -> Result<ast::Chunk<String<'gc>>, CompilerError> {
+        Ok(compiler::parse_chunk_with_options(
+            source,
+            Interner(ctx),
+            compiler::ParseOptions {
+                annotations: options.annotations,
+                restrictions: options.restrictions,
+            },
+        )?)
+    }
+
+    /// Compile a chunk previously produced by [`FunctionPrototype::parse`].
+    ///
+    /// Only the code generation options in `options` are used here, parsing options such as
+    /// restrictions have already been applied when the chunk was parsed.
+    pub fn compile_ast(
+        ctx: Context<'gc>,
+        source_name: &str,
+        chunk: &ast::Chunk<String<'gc>>,
+        options: CompileOptions,
+    ) -> Result<FunctionPrototype<'gc>, CompilerError> {
+        Self::compile_ast_impl(ctx, source_name, chunk, options, &[])
+    }
+
+    fn compile_impl(
+        ctx: Context<'gc>,
+        source_name: &str,
+        source: impl Read,
+        options: CompileOptions,
+        fragments: &[SourceFragment<'gc>],
+    ) -> Result<FunctionPrototype<'gc>, CompilerError> {
+        let chunk = Self::parse(ctx, source, options)?;
+        Self::compile_ast_impl(ctx, source_name, &chunk, options, fragments)
+    }
 
-        let chunk = compiler::parse_chunk(source, interner)?;
-        let compiled_function = compiler::compile_chunk(&chunk, interner)?;
+    fn compile_ast_impl(
+        ctx: Context<'gc>,
+        source_name: &str,
+        chunk: &ast::Chunk<String<'gc>>,
+        options: CompileOptions,
+        fragments: &[SourceFragment<'gc>],
+    ) -> Result<FunctionPrototype<'gc>, CompilerError> {
+        let compiled_function = compiler::compile_chunk(chunk, Interner(ctx))?;
 
-        Ok(FunctionPrototype::from_compiled(
+        Ok(FunctionPrototype::from_compiled_impl(
             &ctx,
             ctx.intern(source_name.as_bytes()),
             &compiled_function,
+            |s| *s,
+            options.strict_globals,
+            fragments,
         ))
     }
+
+    /// Returns a copy of this prototype with every constant, including the constants of all nested
+    /// prototypes, replaced by the result of `f`.
+    ///
+    /// This can be used to patch an already compiled chunk, for example to redact string literals
+    /// or to relocate resource paths. Opcodes refer to constants by index, so the number and order
+    /// of constants is always preserved. The rewritten prototype is checked with
+    /// [`FunctionPrototype::validate`] before it is returned.
+    pub fn map_constants(
+        &self,
+        mc: &Mutation<'gc>,
+        mut f: impl FnMut(Constant<String<'gc>>) -> Constant<String<'gc>>,
+    ) -> Result<FunctionPrototype<'gc>, PrototypeError> {
+        fn map<'gc>(
+            mc: &Mutation<'gc>,
+            proto: &FunctionPrototype<'gc>,
+            f: &mut dyn FnMut(Constant<String<'gc>>) -> Constant<String<'gc>>,
+        ) -> FunctionPrototype<'gc> {
+            let alloc = MetricsAlloc::new(mc);
+
+            let mut constants = vec::Vec::new_in(alloc.clone());
+            constants.extend(proto.constants.iter().map(|&c| f(c)));
+
+            let mut prototypes = vec::Vec::new_in(alloc.clone());
+            for p in proto.prototypes.iter() {
+                prototypes.push(Gc::new(mc, map(mc, p, f)));
+            }
+
+            FunctionPrototype {
+                chunk_name: proto.chunk_name,
+                reference: proto.reference,
+                fixed_params: proto.fixed_params,
+                has_varargs: proto.has_varargs,
+                stack_size: proto.stack_size,
+                constants: constants.into_boxed_slice(),
+                opcodes: SliceExt::to_vec_in(&proto.opcodes[..], alloc.clone()).into_boxed_slice(),
+                opcode_line_numbers: SliceExt::to_vec_in(
+                    &proto.opcode_line_numbers[..],
+                    alloc.clone(),
+                )
+                .into_boxed_slice(),
+                upvalues: SliceExt::to_vec_in(&proto.upvalues[..], alloc.clone())
+                    .into_boxed_slice(),
+                prototypes: prototypes.into_boxed_slice(),
+                annotations: SliceExt::to_vec_in(&proto.annotations[..], alloc.clone())
+                    .into_boxed_slice(),
+                strict_globals: proto.strict_globals,
+                fragments: SliceExt::to_vec_in(&proto.fragments[..], alloc.clone())
+                    .into_boxed_slice(),
+                local_variables: SliceExt::to_vec_in(&proto.local_variables[..], alloc.clone())
+                    .into_boxed_slice(),
+                upvalue_names: SliceExt::to_vec_in(&proto.upvalue_names[..], alloc)
+                    .into_boxed_slice(),
+            }
+        }
+
+        let proto = map(mc, self, &mut f);
+        proto.validate()?;
+        Ok(proto)
+    }
+
+    /// Check that this prototype and all of its nested prototypes are well-formed bytecode.
+    ///
+    /// The VM assumes that the prototypes it runs were produced by the compiler. Prototypes from
+    /// any other source, such as bytecode loaded from outside of the program, must pass this check
+    /// before they are run, since otherwise malformed opcodes may cause panics.
+    ///
+    /// This checks that every register an opcode uses is within the stack size, that every
+    /// constant, prototype, and upvalue it references exists, that every jump lands on an opcode,
+    /// that execution cannot run past the last opcode, and that the upvalue descriptors of nested
+    /// prototypes refer to registers and upvalues which exist in this prototype.
+    pub fn validate(&self) -> Result<(), PrototypeError> {
+        let stack_size = self.stack_size as usize;
+        let opcode_count = self.opcodes.len();
+
+        if self.fixed_params as usize > stack_size {
+            return Err(PrototypeError::TooManyParameters {
+                fixed_params: self.fixed_params as usize,
+                stack_size,
+            });
+        }
+
+        // Check that the `count` registers starting at `start` are all within the stack.
+        let check_registers = |opcode: usize, start: RegisterIndex, count: usize| {
+            let end = start.0 as usize + count;
+            if end <= stack_size {
+                Ok(())
+            } else {
+                Err(PrototypeError::RegisterOutOfRange {
+                    opcode,
+                    register: end - 1,
+                    stack_size,
+                })
+            }
+        };
+        let check_register =
+            |opcode: usize, register: RegisterIndex| check_registers(opcode, register, 1);
+        let check_var_registers = |opcode: usize, start: RegisterIndex, count: VarCount| {
+            check_registers(
+                opcode,
+                start,
+                count.to_constant().map(|c| c as usize).unwrap_or(0),
+            )
+        };
+        let check_constant = |opcode: usize, index: usize| {
+            if index < self.constants.len() {
+                Ok(())
+            } else {
+                Err(PrototypeError::ConstantOutOfRange {
+                    opcode,
+                    index,
+                    count: self.constants.len(),
+                })
+            }
+        };
+        let check_rc = |opcode: usize, rc: RCIndex| match rc {
+            RCIndex::Register(r) => check_register(opcode, r),
+            RCIndex::Constant(c) => check_constant(opcode, c.0 as usize),
+        };
+        let check_upvalue = |opcode: usize, index: UpValueIndex| {
+            if (index.0 as usize) < self.upvalues.len() {
+                Ok(())
+            } else {
+                Err(PrototypeError::UpValueOutOfRange {
+                    opcode,
+                    index: index.0 as usize,
+                    count: self.upvalues.len(),
+                })
+            }
+        };
+        // Check that the opcode `offset` places after the next one exists.
+        let check_jump = |opcode: usize, offset: isize| {
+            let target = opcode as isize + 1 + offset;
+            if target >= 0 && (target as usize) < opcode_count {
+                Ok(())
+            } else {
+                Err(PrototypeError::JumpOutOfRange {
+                    opcode,
+                    target,
+                    count: opcode_count,
+                })
+            }
+        };
+        let check_skip = |opcode: usize, skip: bool| {
+            if skip {
+                check_jump(opcode, 1)
+            } else {
+                Ok(())
+            }
+        };
+
+        // Every opcode other than the last may continue on to the next one, so only the last needs
+        // to be checked.
+        match self.opcodes.last().map(|op| op.decode()) {
+            Some(
+                Operation::Return { .. } | Operation::TailCall { .. } | Operation::Jump { .. },
+            ) => {}
+            _ => return Err(PrototypeError::MissingReturn),
+        }
+
+        for (i, opcode) in self.opcodes.iter().enumerate() {
+            match opcode.decode() {
+                Operation::Move { dest, source }
+                | Operation::Length { dest, source }
+                | Operation::Not { dest, source }
+                | Operation::Minus { dest, source }
+                | Operation::BitNot { dest, source } => {
+                    check_register(i, dest)?;
+                    check_register(i, source)?;
+                }
+                Operation::LoadConstant { dest, constant } => {
+                    check_register(i, dest)?;
+                    check_constant(i, constant.0 as usize)?;
+                }
+                Operation::LoadBool {
+                    dest, skip_next, ..
+                } => {
+                    check_register(i, dest)?;
+                    check_skip(i, skip_next)?;
+                }
+                Operation::LoadNil { dest, count } => check_registers(i, dest, count as usize)?,
+                Operation::NewTable { dest, .. } => check_register(i, dest)?,
+                Operation::GetTable { dest, table, key } => {
+                    check_register(i, dest)?;
+                    check_register(i, table)?;
+                    check_rc(i, key)?;
+                }
+                Operation::SetTable { table, key, value } => {
+                    check_register(i, table)?;
+                    check_rc(i, key)?;
+                    check_rc(i, value)?;
+                }
+                Operation::GetUpTable { dest, table, key } => {
+                    check_register(i, dest)?;
+                    check_upvalue(i, table)?;
+                    check_rc(i, key)?;
+                }
+                Operation::SetUpTable { table, key, value } => {
+                    check_upvalue(i, table)?;
+                    check_rc(i, key)?;
+                    check_rc(i, value)?;
+                }
+                Operation::SetList { base, count } => {
+                    check_registers(
+                        i,
+                        base,
+                        2 + count.to_constant().map(|c| c as usize).unwrap_or(0),
+                    )?;
+                }
+                Operation::Call { func, args, .. } | Operation::TailCall { func, args } => {
+                    check_registers(
+                        i,
+                        func,
+                        1 + args.to_constant().map(|c| c as usize).unwrap_or(0),
+                    )?;
+                }
+                Operation::Return { start, count } => check_var_registers(i, start, count)?,
+                Operation::VarArgs { dest, count } => check_var_registers(i, dest, count)?,
+                Operation::Jump { offset, .. } => check_jump(i, offset as isize)?,
+                Operation::Test { value, .. } => {
+                    check_register(i, value)?;
+                    check_skip(i, true)?;
+                }
+                Operation::TestSet { dest, value, .. } => {
+                    check_register(i, dest)?;
+                    check_register(i, value)?;
+                    check_skip(i, true)?;
+                }
+                Operation::Closure { dest, proto } => {
+                    check_register(i, dest)?;
+                    if proto.0 as usize >= self.prototypes.len() {
+                        return Err(PrototypeError::PrototypeOutOfRange {
+                            opcode: i,
+                            index: proto.0 as usize,
+                            count: self.prototypes.len(),
+                        });
+                    }
+                }
+                Operation::NumericForPrep { base, jump } => {
+                    check_registers(i, base, 3)?;
+                    check_jump(i, jump as isize)?;
+                }
+                Operation::NumericForLoop { base, jump } => {
+                    check_registers(i, base, 4)?;
+                    check_jump(i, jump as isize)?;
+                }
+                Operation::GenericForCall { base, .. } => check_registers(i, base, 3)?,
+                Operation::GenericForLoop { base, jump } => {
+                    check_registers(i, base, 2)?;
+                    check_jump(i, jump as isize)?;
+                }
+                Operation::Method { base, table, key } => {
+                    check_registers(i, base, 2)?;
+                    check_register(i, table)?;
+                    check_rc(i, key)?;
+                }
+                Operation::Concat {
+                    dest,
+                    source,
+                    count,
+                } => {
+                    check_register(i, dest)?;
+                    check_registers(i, source, count as usize)?;
+                }
+                Operation::GetUpValue { dest, source } => {
+                    check_register(i, dest)?;
+                    check_upvalue(i, source)?;
+                }
+                Operation::SetUpValue { dest, source } => {
+                    check_upvalue(i, dest)?;
+                    check_register(i, source)?;
+                }
+                Operation::Eq { left, right, .. }
+                | Operation::Less { left, right, .. }
+                | Operation::LessEq { left, right, .. } => {
+                    check_rc(i, left)?;
+                    check_rc(i, right)?;
+                    check_skip(i, true)?;
+                }
+                Operation::Add { dest, left, right }
+                | Operation::Sub { dest, left, right }
+                | Operation::Mul { dest, left, right }
+                | Operation::Div { dest, left, right }
+                | Operation::IDiv { dest, left, right }
+                | Operation::Mod { dest, left, right }
+                | Operation::Pow { dest, left, right }
+                | Operation::BitAnd { dest, left, right }
+                | Operation::BitOr { dest, left, right }
+                | Operation::BitXor { dest, left, right }
+                | Operation::ShiftLeft { dest, left, right }
+                | Operation::ShiftRight { dest, left, right } => {
+                    check_register(i, dest)?;
+                    check_rc(i, left)?;
+                    check_rc(i, right)?;
+                }
+            }
+        }
+
+        for (i, proto) in self.prototypes.iter().enumerate() {
+            for (j, &desc) in proto.upvalues.iter().enumerate() {
+                let valid = match desc {
+                    UpValueDescriptor::Environment => {
+                        return Err(PrototypeError::NestedEnvUpValue { prototype: i });
+                    }
+                    UpValueDescriptor::ParentLocal(reg) => (reg.0 as usize) < stack_size,
+                    UpValueDescriptor::Outer(index) => (index.0 as usize) < self.upvalues.len(),
+                };
+                if !valid {
+                    return Err(PrototypeError::BadUpValueDescriptor {
+                        prototype: i,
+                        upvalue: j,
+                    });
+                }
+            }
+            proto.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the source line number of the opcode at the given index.
+    ///
+    /// For a prototype compiled from fragments, this is a line of the combined source, see
+    /// [`FunctionPrototype::opcode_location`].
+    pub fn opcode_line_number(&self, opcode_index: usize) -> LineNumber {
+        match self
+            .opcode_line_numbers
+            .binary_search_by_key(&opcode_index, |(opi, _)| *opi)
+        {
+            Ok(i) => self.opcode_line_numbers[i].1,
+            Err(i) => self.opcode_line_numbers[i - 1].1,
+        }
+    }
+
+    /// Returns the name of the chunk or fragment and the line within it of the opcode at the given
+    /// index.
+    pub fn opcode_location(&self, opcode_index: usize) -> (String<'gc>, LineNumber) {
+        let line = self.opcode_line_number(opcode_index);
+        locate_fragment(&self.fragments, line).unwrap_or((self.chunk_name, line))
+    }
+
+    /// Returns every local variable in scope at the opcode at the given index, in order of
+    /// declaration.
+    pub fn active_locals(
+        &self,
+        opcode_index: usize,
+    ) -> impl Iterator<Item = &LocalVariable<String<'gc>>> + '_ {
+        self.local_variables
+            .iter()
+            .filter(move |l| l.is_active(opcode_index))
+    }
+
+    /// Returns the name of the local variable stored in `register` at the opcode at the given
+    /// index, or `None` if the register is a temporary.
+    pub fn local_name(&self, register: RegisterIndex, opcode_index: usize) -> Option<String<'gc>> {
+        self.local_variables
+            .iter()
+            .rev()
+            .find(|l| l.register == register && l.is_active(opcode_index))
+            .map(|l| l.name)
+    }
+
+    /// Returns the name of the upvalue at the given index.
+    pub fn upvalue_name(&self, index: UpValueIndex) -> Option<String<'gc>> {
+        self.upvalue_names.get(usize::from(index.0)).copied()
+    }
+
+    /// Describes the variable which the value in `register` was loaded from at the opcode at the
+    /// given index, in the same way as PUC-Rio Lua does for error messages.
+    ///
+    /// Returns `None` if the register is a temporary whose origin is unknown or not nameable, or
+    /// if the register may have been set on more than one path of execution.
+    pub fn describe_register(
+        &self,
+        register: RegisterIndex,
+        opcode_index: usize,
+    ) -> Option<VariableName<'gc>> {
+        if let Some(name) = self.local_name(register, opcode_index) {
+            return Some(VariableName::Local(name));
+        }
+
+        let set_index = self.find_set_register(register, opcode_index)?;
+        let constant_string = |key: RCIndex| match key {
+            RCIndex::Constant(c) => match self.constants.get(c.0 as usize)? {
+                &Constant::String(s) => Some(s),
+                _ => None,
+            },
+            RCIndex::Register(_) => None,
+        };
+
+        match self.opcodes[set_index].decode() {
+            Operation::Move { source, .. } if source.0 < register.0 => {
+                self.describe_register(source, set_index)
+            }
+            Operation::LoadConstant { constant, .. } => {
+                match self.constants.get(constant.0 as usize)? {
+                    &Constant::String(s) => Some(VariableName::Constant(s)),
+                    _ => None,
+                }
+            }
+            Operation::GetUpValue { source, .. } => {
+                self.upvalue_name(source).map(VariableName::UpValue)
+            }
+            Operation::GetUpTable { table, key, .. } => {
+                let key = constant_string(key)?;
+                if self.upvalue_name(table).is_some_and(|n| n == "_ENV") {
+                    Some(VariableName::Global(key))
+                } else {
+                    Some(VariableName::Field(key))
+                }
+            }
+            Operation::GetTable { table, key, .. } => {
+                let key = constant_string(key)?;
+                if self
+                    .local_name(table, set_index)
+                    .is_some_and(|n| n == "_ENV")
+                {
+                    Some(VariableName::Global(key))
+                } else {
+                    Some(VariableName::Field(key))
+                }
+            }
+            Operation::Method { base, key, .. } if base == register => {
+                constant_string(key).map(VariableName::Method)
+            }
+            _ => None,
+        }
+    }
+
+    // Find the index of the last opcode before `opcode_index` which sets `register`.
+    //
+    // Returns `None` if no opcode sets the register, or if the last one is before the target of a
+    // forward jump, since then the register may have been set by a different opcode.
+    fn find_set_register(&self, register: RegisterIndex, opcode_index: usize) -> Option<usize> {
+        let r = register.0;
+        let mut set_index = None;
+        let mut jump_target = 0;
+
+        for (index, opcode) in self.opcodes[..opcode_index].iter().enumerate() {
+            let (sets_register, jump) = match opcode.decode() {
+                Operation::LoadNil { dest, count } => (
+                    r >= dest.0 && u16::from(r) < u16::from(dest.0) + u16::from(count),
+                    None,
+                ),
+                Operation::Call { func, .. } | Operation::TailCall { func, .. } => {
+                    (r >= func.0, None)
+                }
+                Operation::VarArgs { dest, .. } => (r >= dest.0, None),
+                Operation::GenericForCall { base, .. } => (r >= base.0, None),
+                Operation::Method { base, .. } => {
+                    (r == base.0 || r == base.0.wrapping_add(1), None)
+                }
+                Operation::SetList { base, .. } => (r == base.0.wrapping_add(1), None),
+                Operation::Jump { offset, .. } => (false, Some(offset)),
+                Operation::NumericForPrep { base, jump }
+                | Operation::NumericForLoop { base, jump } => (
+                    r >= base.0 && u16::from(r) <= u16::from(base.0) + 3,
+                    Some(jump),
+                ),
+                Operation::GenericForLoop { base, jump } => (r == base.0, Some(jump)),
+                Operation::Move { dest, .. }
+                | Operation::LoadConstant { dest, .. }
+                | Operation::LoadBool { dest, .. }
+                | Operation::NewTable { dest, .. }
+                | Operation::GetTable { dest, .. }
+                | Operation::GetUpTable { dest, .. }
+                | Operation::TestSet { dest, .. }
+                | Operation::Closure { dest, .. }
+                | Operation::Concat { dest, .. }
+                | Operation::GetUpValue { dest, .. }
+                | Operation::Length { dest, .. }
+                | Operation::Not { dest, .. }
+                | Operation::Minus { dest, .. }
+                | Operation::BitNot { dest, .. }
+                | Operation::Add { dest, .. }
+                | Operation::Sub { dest, .. }
+                | Operation::Mul { dest, .. }
+                | Operation::Div { dest, .. }
+                | Operation::IDiv { dest, .. }
+                | Operation::Mod { dest, .. }
+                | Operation::Pow { dest, .. }
+                | Operation::BitAnd { dest, .. }
+                | Operation::BitOr { dest, .. }
+                | Operation::BitXor { dest, .. }
+                | Operation::ShiftLeft { dest, .. }
+                | Operation::ShiftRight { dest, .. } => (r == dest.0, None),
+                _ => (false, None),
+            };
+
+            if let Some(jump) = jump {
+                let target = (index as isize + 1 + jump as isize) as usize;
+                if target > index && target <= opcode_index {
+                    jump_target = jump_target.max(target);
+                }
+            }
+
+            if sets_register {
+                set_index = (index >= jump_target).then_some(index);
+            }
+        }
+
+        set_index
+    }
+}
+
+// Find the fragment containing a line of the combined source, returning the name of the fragment
+// and the line within it.
+fn locate_fragment<'gc>(
+    fragments: &[SourceFragment<'gc>],
+    line: LineNumber,
+) -> Option<(String<'gc>, LineNumber)> {
+    let fragment = &fragments[fragment_index(fragments, line)?];
+    Some((fragment.name, LineNumber(line.0 - fragment.first_line.0)))
+}
+
+// Find the index of the fragment containing a line of the combined source.
+fn fragment_index(fragments: &[SourceFragment<'_>], line: LineNumber) -> Option<usize> {
+    fragments
+        .partition_point(|f| f.first_line <= line)
+        .checked_sub(1)
+}
+
+// Count lines the same way as the lexer, where `\r\n` and `\n\r` are a single line ending.
+fn count_lines(source: &[u8]) -> u64 {
+    let mut lines = 0;
+    let mut iter = source.iter().peekable();
+    while let Some(&c) = iter.next() {
+        if c == b'\n' || c == b'\r' {
+            lines += 1;
+            if let Some(&&next) = iter.peek() {
+                if (next == b'\n' || next == b'\r') && next != c {
+                    iter.next();
+                }
+            }
+        }
+    }
+    lines
 }
 
 #[derive(Debug, Copy, Clone, Collect)]
@@ -154,6 +1093,20 @@ pub type UpValueInner<'gc> = Lock<UpValueState<'gc>>;
 #[collect(no_drop)]
 pub struct UpValue<'gc>(Gc<'gc, UpValueInner<'gc>>);
 
+impl<'gc> PartialEq for UpValue<'gc> {
+    fn eq(&self, other: &UpValue<'gc>) -> bool {
+        Gc::ptr_eq(self.0, other.0)
+    }
+}
+
+impl<'gc> Eq for UpValue<'gc> {}
+
+impl<'gc> Hash for UpValue<'gc> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Gc::as_ptr(self.0).hash(state)
+    }
+}
+
 impl<'gc> UpValue<'gc> {
     pub fn new(mc: &Mutation<'gc>, state: UpValueState<'gc>) -> Self {
         Self(Gc::new(mc, Lock::new(state)))
@@ -176,6 +1129,17 @@ impl<'gc> UpValue<'gc> {
     }
 }
 
+#[derive(Copy, Clone)]
+struct Interner<'gc>(Context<'gc>);
+
+impl<'gc> compiler::StringInterner for Interner<'gc> {
+    type String = String<'gc>;
+
+    fn intern(&mut self, s: &[u8]) -> Self::String {
+        self.0.intern(s)
+    }
+}
+
 #[derive(Debug, Copy, Clone, Error)]
 pub enum ClosureError {
     #[error("cannot use prototype with upvalues other than _ENV to create top-level closure")]
@@ -222,7 +1186,16 @@ impl<'gc> Closure<'gc> {
         proto: FunctionPrototype<'gc>,
         environment: Option<Table<'gc>>,
     ) -> Result<Closure<'gc>, ClosureError> {
-        let proto = Gc::new(mc, proto);
+        Self::from_prototype(mc, Gc::new(mc, proto), environment)
+    }
+
+    /// Create a top-level closure from an already allocated prototype, which may be shared with
+    /// other closures. The prototype must not have any upvalues besides _ENV.
+    pub fn from_prototype(
+        mc: &Mutation<'gc>,
+        proto: Gc<'gc, FunctionPrototype<'gc>>,
+        environment: Option<Table<'gc>>,
+    ) -> Result<Closure<'gc>, ClosureError> {
         let mut upvalues = vec::Vec::new_in(MetricsAlloc::new(mc));
 
         if !proto.upvalues.is_empty() {
@@ -241,6 +1214,21 @@ impl<'gc> Closure<'gc> {
         Ok(Closure(Gc::new(mc, ClosureInner { proto, upvalues })))
     }
 
+    /// Create a new closure with the same prototype as this one, but with `env` as its `_ENV`
+    /// table.
+    ///
+    /// This allows a chunk to be compiled once and then run against any number of environments,
+    /// such as a separate sandbox per tenant, without recompiling it. Like
+    /// [`Closure::from_prototype`], this only works for top-level closures that have no upvalues
+    /// besides `_ENV`.
+    pub fn with_env(
+        self,
+        mc: &Mutation<'gc>,
+        env: Table<'gc>,
+    ) -> Result<Closure<'gc>, ClosureError> {
+        Self::from_prototype(mc, self.prototype(), Some(env))
+    }
+
     pub fn from_parts(
         mc: &Mutation<'gc>,
         proto: Gc<'gc, FunctionPrototype<'gc>>,
@@ -273,7 +1261,62 @@ impl<'gc> Closure<'gc> {
         source: impl Read,
         env: Table<'gc>,
     ) -> Result<Closure<'gc>, CompilerError> {
-        let proto = FunctionPrototype::compile(ctx, name.unwrap_or("<anonymous>"), source)?;
+        Self::load_with_options(ctx, name, source, env, CompileOptions::default())
+    }
+
+    /// Compile a top-level closure from source with the given [`CompileOptions`], using the given
+    /// table as the `_ENV` table.
+    ///
+    /// If the [`ChunkCache`](crate::ChunkCache) is enabled, the prototype is reused from a previous
+    /// load of the same chunk if possible.
+    pub fn load_with_options(
+        ctx: Context<'gc>,
+        name: Option<&str>,
+        mut source: impl Read,
+        env: Table<'gc>,
+        options: CompileOptions,
+    ) -> Result<Closure<'gc>, CompilerError> {
+        let name = name.unwrap_or("<anonymous>");
+        let cache = ctx.chunk_cache();
+        let proto = if cache.is_enabled() {
+            let mut buf = Vec::new();
+            if let Err(err) = source.read_to_end(&mut buf) {
+                return Err(compiler::ParseError::new(
+                    LexError::IOError(err).into(),
+                    SourceLocation::default(),
+                )
+                .into());
+            }
+            cache.get_or_compile(&ctx, name, options, &buf, || {
+                FunctionPrototype::compile_with_options(ctx, name, &buf[..], options)
+            })?
+        } else {
+            Gc::new(
+                &ctx,
+                FunctionPrototype::compile_with_options(ctx, name, source, options)?,
+            )
+        };
+        Ok(Closure::from_prototype(&ctx, proto, Some(env)).unwrap())
+    }
+
+    /// Compile a top-level closure from several named fragments of source, see
+    /// [`FunctionPrototype::compile_fragments`].
+    pub fn load_fragments<'a>(
+        ctx: Context<'gc>,
+        fragments: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+    ) -> Result<Closure<'gc>, CompilerError> {
+        Self::load_fragments_with_env(ctx, fragments, ctx.globals())
+    }
+
+    /// Compile a top-level closure from several named fragments of source, using the given table
+    /// as the `_ENV` table.
+    pub fn load_fragments_with_env<'a>(
+        ctx: Context<'gc>,
+        fragments: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+        env: Table<'gc>,
+    ) -> Result<Closure<'gc>, CompilerError> {
+        let proto =
+            FunctionPrototype::compile_fragments(ctx, fragments, CompileOptions::default())?;
         Ok(Closure::new(&ctx, proto, Some(env)).unwrap())
     }
 
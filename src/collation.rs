@@ -0,0 +1,68 @@
+use std::{cell::RefCell, cmp::Ordering, fmt};
+
+use gc_arena::Collect;
+
+/// An ordering of strings, used by the `<`, `<=`, `>`, and `>=` operators (and so by
+/// `table.sort`) when both operands are strings.
+pub trait Collation: 'static {
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering;
+}
+
+impl<F> Collation for F
+where
+    F: Fn(&[u8], &[u8]) -> Ordering + 'static,
+{
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        self(a, b)
+    }
+}
+
+/// The string ordering used by comparison operators.
+///
+/// By default, strings are compared byte by byte, like `memcmp`. Unlike PUC-Rio Lua, which uses
+/// `strcoll`, this never depends on the locale of the host, so scripts behave the same everywhere.
+/// Hosts which want locale-aware ordering (for example, to sort names for display) can set a
+/// custom [`Collation`] with [`Lua::set_collation`](crate::Lua::set_collation).
+///
+/// The collation only affects ordering, string equality is always byte-wise.
+#[derive(Collect, Default)]
+#[collect(require_static)]
+pub struct Collator {
+    collation: RefCell<Option<Box<dyn Collation>>>,
+}
+
+impl fmt::Debug for Collator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Collator")
+            .field("byte_order", &self.is_byte_order())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Collator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the current collation.
+    pub fn set(&self, collation: impl Collation) {
+        *self.collation.borrow_mut() = Some(Box::new(collation));
+    }
+
+    /// Go back to the default byte-wise ordering.
+    pub fn reset(&self) {
+        *self.collation.borrow_mut() = None;
+    }
+
+    /// Returns true if strings are compared with the default byte-wise ordering.
+    pub fn is_byte_order(&self) -> bool {
+        self.collation.borrow().is_none()
+    }
+
+    pub fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        match &*self.collation.borrow() {
+            Some(collation) => collation.compare(a, b),
+            None => a.cmp(b),
+        }
+    }
+}
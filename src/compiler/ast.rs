@@ -0,0 +1,284 @@
+//! The abstract syntax tree produced by the parser and consumed by the compiler.
+//!
+//! Chunks can be parsed with [`parse_chunk`](super::parse_chunk), inspected or transformed, and
+//! then compiled with [`compile_chunk`](super::compile_chunk). Every node is generic over the
+//! string type `S` produced by the [`StringInterner`](super::StringInterner) used while parsing.
+
+use std::ops;
+
+use super::lexer::{Annotation, LineNumber};
+
+#[derive(Debug, Clone)]
+pub struct LineAnnotated<T> {
+    pub inner: T,
+    pub line_number: LineNumber,
+}
+
+impl<T> ops::Deref for LineAnnotated<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T> AsRef<T> for LineAnnotated<T> {
+    fn as_ref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> LineAnnotated<T> {
+    pub fn new(line_number: LineNumber, inner: T) -> Self {
+        Self { inner, line_number }
+    }
+
+    pub fn map<R>(self, f: impl FnOnce(T) -> R) -> LineAnnotated<R> {
+        LineAnnotated {
+            inner: f(self.inner),
+            line_number: self.line_number,
+        }
+    }
+
+    pub fn try_map<R, E>(self, f: impl FnOnce(T) -> Result<R, E>) -> Result<LineAnnotated<R>, E> {
+        Ok(LineAnnotated {
+            inner: f(self.inner)?,
+            line_number: self.line_number,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Chunk<S> {
+    pub block: Block<S>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Block<S> {
+    pub statements: Vec<LineAnnotated<Statement<S>>>,
+    pub return_statement: Option<LineAnnotated<ReturnStatement<S>>>,
+    pub closed_on: LineNumber,
+}
+
+#[derive(Debug, Clone)]
+pub enum Statement<S> {
+    If(IfStatement<S>),
+    While(WhileStatement<S>),
+    Do(Block<S>),
+    For(ForStatement<S>),
+    Repeat(RepeatStatement<S>),
+    Function(FunctionStatement<S>),
+    LocalFunction(LocalFunctionStatement<S>),
+    LocalStatement(LocalStatement<S>),
+    Label(LabelStatement<S>),
+    Break,
+    Goto(GotoStatement<S>),
+    FunctionCall(FunctionCallStatement<S>),
+    Assignment(AssignmentStatement<S>),
+}
+
+#[derive(Debug, Clone)]
+pub struct ReturnStatement<S> {
+    pub returns: Vec<Expression<S>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct IfStatement<S> {
+    pub if_part: (Expression<S>, Block<S>),
+    pub else_if_parts: Vec<(Expression<S>, Block<S>)>,
+    pub else_part: Option<Block<S>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WhileStatement<S> {
+    pub condition: Expression<S>,
+    pub block: Block<S>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ForStatement<S> {
+    Numeric {
+        name: S,
+        initial: Expression<S>,
+        limit: Expression<S>,
+        step: Option<Expression<S>>,
+        body: Block<S>,
+    },
+    Generic {
+        names: Vec<S>,
+        arguments: Vec<Expression<S>>,
+        body: Block<S>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct RepeatStatement<S> {
+    pub body: Block<S>,
+    pub until: Expression<S>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LabelStatement<S> {
+    pub name: S,
+}
+
+#[derive(Debug, Clone)]
+pub struct GotoStatement<S> {
+    pub name: S,
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionStatement<S> {
+    pub name: S,
+    pub fields: Vec<S>,
+    pub method: Option<S>,
+    pub definition: FunctionDefinition<S>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LocalFunctionStatement<S> {
+    pub name: S,
+    pub definition: FunctionDefinition<S>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LocalStatement<S> {
+    pub names: Vec<S>,
+    pub values: Vec<Expression<S>>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum BinaryOperator {
+    Add,
+    Sub,
+    Mul,
+    Mod,
+    Pow,
+    Div,
+    IDiv,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    Concat,
+    NotEqual,
+    Equal,
+    LessThan,
+    LessEqual,
+    GreaterThan,
+    GreaterEqual,
+    And,
+    Or,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum UnaryOperator {
+    Not,
+    Minus,
+    BitNot,
+    Len,
+}
+
+#[derive(Debug, Clone)]
+pub struct Expression<S> {
+    pub head: Box<HeadExpression<S>>,
+    pub tail: Vec<(BinaryOperator, Expression<S>)>,
+}
+
+#[derive(Debug, Clone)]
+pub enum HeadExpression<S> {
+    Simple(SimpleExpression<S>),
+    UnaryOperator(UnaryOperator, Expression<S>),
+}
+
+#[derive(Debug, Clone)]
+pub enum SimpleExpression<S> {
+    Float(f64),
+    Integer(i64),
+    String(S),
+    Nil,
+    True,
+    False,
+    VarArgs,
+    TableConstructor(TableConstructor<S>),
+    Function(FunctionDefinition<S>),
+    Suffixed(SuffixedExpression<S>),
+}
+
+#[derive(Debug, Clone)]
+pub enum PrimaryExpression<S> {
+    Name(S),
+    GroupedExpression(Expression<S>),
+}
+
+#[derive(Debug, Clone)]
+pub enum FieldSuffix<S> {
+    Named(S),
+    Indexed(Expression<S>),
+}
+
+#[derive(Debug, Clone)]
+pub enum CallSuffix<S> {
+    Method(S, Vec<Expression<S>>),
+    Function(Vec<Expression<S>>),
+}
+
+#[derive(Debug, Clone)]
+pub enum SuffixPart<S> {
+    Field(FieldSuffix<S>),
+    Call(CallSuffix<S>),
+}
+
+#[derive(Debug, Clone)]
+pub struct SuffixedExpression<S> {
+    pub primary: PrimaryExpression<S>,
+    pub suffixes: Vec<SuffixPart<S>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionDefinition<S> {
+    pub parameters: Vec<S>,
+    pub has_varargs: bool,
+    pub body: Block<S>,
+    /// Any `---@tag text` annotation comments directly preceding a `function` or `local function`
+    /// statement.
+    ///
+    /// Only collected when parsing with [`parse_chunk_with_annotations`](super::parse_chunk_with_annotations), otherwise always empty.
+    pub annotations: Vec<Annotation<S>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionCallStatement<S> {
+    pub head: SuffixedExpression<S>,
+    pub call: CallSuffix<S>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AssignmentStatement<S> {
+    pub targets: Vec<AssignmentTarget<S>>,
+    pub values: Vec<Expression<S>>,
+}
+
+#[derive(Debug, Clone)]
+pub enum AssignmentTarget<S> {
+    Name(S),
+    Field(SuffixedExpression<S>, FieldSuffix<S>),
+}
+
+#[derive(Debug, Clone)]
+pub struct TableConstructor<S> {
+    pub fields: Vec<ConstructorField<S>>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ConstructorField<S> {
+    Array(Expression<S>),
+    Record(RecordKey<S>, Expression<S>),
+}
+
+#[derive(Debug, Clone)]
+pub enum RecordKey<S> {
+    Named(S),
+    Indexed(Expression<S>),
+}
@@ -0,0 +1,975 @@
+use std::{fmt, rc::Rc};
+
+use thiserror::Error;
+
+use super::{
+    ast::{
+        AssignmentTarget, BinaryOperator, Block, CallSuffix, Chunk, ConstructorField, Expression,
+        FieldSuffix, ForStatement, FunctionDefinition, HeadExpression, LineAnnotated,
+        PrimaryExpression, RecordKey, SimpleExpression, Statement, SuffixPart, SuffixedExpression,
+        UnaryOperator,
+    },
+    lexer::{Annotation, LineNumber},
+};
+
+#[derive(Debug, Clone, Error)]
+pub enum TypeWarningKind {
+    #[error("argument '{parameter}' of '{function}' expects {expected}, found {found}")]
+    Argument {
+        function: String,
+        parameter: String,
+        expected: String,
+        found: String,
+    },
+    #[error("return value {index} of '{function}' expects {expected}, found {found}")]
+    Return {
+        function: String,
+        index: usize,
+        expected: String,
+        found: String,
+    },
+    #[error("attempt to perform arithmetic on a {found} value")]
+    Arithmetic { found: String },
+    #[error("attempt to concatenate a {found} value")]
+    Concat { found: String },
+    #[error("attempt to call a {found} value")]
+    Call { found: String },
+    #[error("attempt to index a {found} value")]
+    Index { found: String },
+}
+
+/// A likely type error found by [`check_chunk`].
+#[derive(Debug, Clone, Error)]
+#[error("type warning at line {line_number}: {kind}")]
+pub struct TypeWarning {
+    pub kind: TypeWarningKind,
+    pub line_number: LineNumber,
+}
+
+/// Run a best-effort static type check over a parsed chunk.
+///
+/// Types are inferred for literals and for local variables which are never re-assigned, and are
+/// taken from `---@param` and `---@return` annotation comments on `function` and `local function`
+/// statements (see [`parse_chunk_with_annotations`](super::parse_chunk_with_annotations)).
+///
+/// The checker is deliberately conservative: it only warns when an operation is certain to fail or
+/// to violate an annotation, anything it cannot reason about is assumed to be correct. Warnings
+/// never prevent a chunk from being compiled.
+pub fn check_chunk<S: AsRef<[u8]>>(chunk: &Chunk<S>) -> Vec<TypeWarning> {
+    let mut checker = Checker {
+        scopes: Vec::new(),
+        globals: Vec::new(),
+        functions: Vec::new(),
+        line_number: LineNumber(0),
+        warnings: Vec::new(),
+    };
+    checker.block(&chunk.block);
+    checker.warnings
+}
+
+// A set of possible Lua types as a bitset.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Types(u8);
+
+impl Types {
+    const NIL: Types = Types(1 << 0);
+    const BOOLEAN: Types = Types(1 << 1);
+    const NUMBER: Types = Types(1 << 2);
+    const STRING: Types = Types(1 << 3);
+    const TABLE: Types = Types(1 << 4);
+    const FUNCTION: Types = Types(1 << 5);
+    const THREAD: Types = Types(1 << 6);
+    const USERDATA: Types = Types(1 << 7);
+    const ANY: Types = Types(u8::MAX);
+
+    const NAMES: [(Types, &'static str); 8] = [
+        (Types::NIL, "nil"),
+        (Types::BOOLEAN, "boolean"),
+        (Types::NUMBER, "number"),
+        (Types::STRING, "string"),
+        (Types::TABLE, "table"),
+        (Types::FUNCTION, "function"),
+        (Types::THREAD, "thread"),
+        (Types::USERDATA, "userdata"),
+    ];
+
+    fn union(self, other: Types) -> Types {
+        Types(self.0 | other.0)
+    }
+
+    fn intersects(self, other: Types) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    fn is_subset(self, other: Types) -> bool {
+        self.0 & !other.0 == 0
+    }
+}
+
+impl fmt::Display for Types {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if *self == Types::ANY {
+            return write!(f, "any");
+        }
+
+        let mut first = true;
+        for (t, name) in Types::NAMES {
+            if self.intersects(t) {
+                if !first {
+                    write!(f, "|")?;
+                }
+                write!(f, "{name}")?;
+                first = false;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Types which may support arithmetic, either natively, through string coercion, or through
+// metamethods.
+const ARITHMETIC: Types =
+    Types(Types::NUMBER.0 | Types::STRING.0 | Types::TABLE.0 | Types::USERDATA.0);
+const CONCAT: Types = ARITHMETIC;
+const CALL: Types = Types(Types::FUNCTION.0 | Types::TABLE.0 | Types::USERDATA.0);
+const INDEX: Types = Types(Types::STRING.0 | Types::TABLE.0 | Types::USERDATA.0);
+
+#[derive(Debug)]
+struct AnnotatedType {
+    types: Types,
+    text: String,
+}
+
+#[derive(Debug)]
+struct Signature {
+    name: String,
+    // Parameters in order, with their annotated type if they have one.
+    parameters: Vec<(String, Option<AnnotatedType>)>,
+    returns: Vec<AnnotatedType>,
+}
+
+#[derive(Debug)]
+struct Local<'a, S> {
+    name: &'a S,
+    types: Types,
+    signature: Option<Rc<Signature>>,
+}
+
+struct Checker<'a, S> {
+    scopes: Vec<Vec<Local<'a, S>>>,
+    // Annotated global functions, by name.
+    globals: Vec<(&'a S, Rc<Signature>)>,
+    // The signature of every function we are currently inside of, if it has one.
+    functions: Vec<Option<Rc<Signature>>>,
+    line_number: LineNumber,
+    warnings: Vec<TypeWarning>,
+}
+
+impl<'a, S: AsRef<[u8]>> Checker<'a, S> {
+    fn block(&mut self, block: &'a Block<S>) {
+        self.scopes.push(Vec::new());
+        self.block_statements(block);
+        self.scopes.pop();
+    }
+
+    // Check the statements of a block without introducing a new scope.
+    fn block_statements(&mut self, block: &'a Block<S>) {
+        for (i, statement) in block.statements.iter().enumerate() {
+            self.line_number = statement.line_number;
+            self.statement(&statement.inner, &block.statements[i + 1..]);
+        }
+
+        if let Some(return_statement) = &block.return_statement {
+            self.line_number = return_statement.line_number;
+            let returns = &return_statement.returns;
+            for expression in returns {
+                self.expression(expression);
+            }
+
+            if let Some(Some(signature)) = self.functions.last().cloned() {
+                for (index, (expression, expected)) in
+                    returns.iter().zip(&signature.returns).enumerate()
+                {
+                    if index == returns.len() - 1 && is_multi_value(expression) {
+                        break;
+                    }
+
+                    let found = self.infer(expression);
+                    if !found.intersects(expected.types) {
+                        self.warn(TypeWarningKind::Return {
+                            function: signature.name.clone(),
+                            index: index + 1,
+                            expected: expected.text.clone(),
+                            found: found.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &'a Statement<S>, rest: &'a [LineAnnotated<Statement<S>>]) {
+        match statement {
+            Statement::If(if_statement) => {
+                self.expression(&if_statement.if_part.0);
+                self.block(&if_statement.if_part.1);
+                for (condition, block) in &if_statement.else_if_parts {
+                    self.expression(condition);
+                    self.block(block);
+                }
+                if let Some(block) = &if_statement.else_part {
+                    self.block(block);
+                }
+            }
+            Statement::While(while_statement) => {
+                self.expression(&while_statement.condition);
+                self.block(&while_statement.block);
+            }
+            Statement::Do(block) => self.block(block),
+            Statement::For(ForStatement::Numeric {
+                name,
+                initial,
+                limit,
+                step,
+                body,
+            }) => {
+                self.expression(initial);
+                self.expression(limit);
+                if let Some(step) = step {
+                    self.expression(step);
+                }
+
+                self.scopes.push(Vec::new());
+                let types = if assigns_block(name, body) {
+                    Types::ANY
+                } else {
+                    Types::NUMBER
+                };
+                self.declare(name, types, None);
+                self.block(body);
+                self.scopes.pop();
+            }
+            Statement::For(ForStatement::Generic {
+                names,
+                arguments,
+                body,
+            }) => {
+                for argument in arguments {
+                    self.expression(argument);
+                }
+
+                self.scopes.push(Vec::new());
+                for name in names {
+                    self.declare(name, Types::ANY, None);
+                }
+                self.block(body);
+                self.scopes.pop();
+            }
+            Statement::Repeat(repeat_statement) => {
+                // The `until` condition is within the scope of the loop body.
+                self.scopes.push(Vec::new());
+                self.block_statements(&repeat_statement.body);
+                self.expression(&repeat_statement.until);
+                self.scopes.pop();
+            }
+            Statement::Function(function_statement) => {
+                let mut name =
+                    String::from_utf8_lossy(function_statement.name.as_ref()).into_owned();
+                for field in &function_statement.fields {
+                    name.push('.');
+                    name.push_str(&String::from_utf8_lossy(field.as_ref()));
+                }
+                if let Some(method) = &function_statement.method {
+                    name.push(':');
+                    name.push_str(&String::from_utf8_lossy(method.as_ref()));
+                }
+
+                let signature = signature(name, &function_statement.definition);
+                if function_statement.fields.is_empty() && function_statement.method.is_none() {
+                    if let Some(signature) = &signature {
+                        if self.find_local(&function_statement.name).is_none() {
+                            self.globals
+                                .retain(|(n, _)| n.as_ref() != function_statement.name.as_ref());
+                            self.globals
+                                .push((&function_statement.name, signature.clone()));
+                        }
+                    }
+                }
+                self.function(&function_statement.definition, signature);
+            }
+            Statement::LocalFunction(local_function) => {
+                let name = String::from_utf8_lossy(local_function.name.as_ref()).into_owned();
+                let signature = signature(name, &local_function.definition);
+                if assigns_statements(&local_function.name, rest) {
+                    self.declare(&local_function.name, Types::ANY, None);
+                } else {
+                    self.declare(&local_function.name, Types::FUNCTION, signature.clone());
+                }
+                self.function(&local_function.definition, signature);
+            }
+            Statement::LocalStatement(local_statement) => {
+                let mut types = Vec::new();
+                for (i, value) in local_statement.values.iter().enumerate() {
+                    self.expression(value);
+                    if i == local_statement.values.len() - 1 && is_multi_value(value) {
+                        types.push(Types::ANY);
+                    } else {
+                        types.push(self.infer(value));
+                    }
+                }
+
+                let rest_types = match types.last() {
+                    Some(&Types::ANY) => Types::ANY,
+                    _ => Types::NIL,
+                };
+
+                for (i, name) in local_statement.names.iter().enumerate() {
+                    let t = if assigns_statements(name, rest) {
+                        Types::ANY
+                    } else {
+                        types.get(i).copied().unwrap_or(rest_types)
+                    };
+                    self.declare(name, t, None);
+                }
+            }
+            Statement::Label(_) | Statement::Break | Statement::Goto(_) => {}
+            Statement::FunctionCall(function_call) => {
+                self.call(&function_call.head, &function_call.call);
+            }
+            Statement::Assignment(assignment) => {
+                for target in &assignment.targets {
+                    if let AssignmentTarget::Field(table, field) = target {
+                        let t = self.suffixed_expression(table);
+                        self.field(t, field);
+                    }
+                }
+                for value in &assignment.values {
+                    self.expression(value);
+                }
+            }
+        }
+    }
+
+    fn function(
+        &mut self,
+        definition: &'a FunctionDefinition<S>,
+        signature: Option<Rc<Signature>>,
+    ) {
+        self.scopes.push(Vec::new());
+        for parameter in &definition.parameters {
+            let mut types = Types::ANY;
+            if let Some(signature) = &signature {
+                if let Some((_, Some(annotated))) = signature
+                    .parameters
+                    .iter()
+                    .find(|(name, _)| name.as_bytes() == parameter.as_ref())
+                {
+                    types = annotated.types;
+                }
+            }
+            if assigns_block(parameter, &definition.body) {
+                types = Types::ANY;
+            }
+            self.declare(parameter, types, None);
+        }
+
+        self.functions.push(signature);
+        let line_number = self.line_number;
+        self.block(&definition.body);
+        self.line_number = line_number;
+        self.functions.pop();
+        self.scopes.pop();
+    }
+
+    fn expression(&mut self, expression: &'a Expression<S>) {
+        match &*expression.head {
+            HeadExpression::Simple(simple) => self.simple_expression(simple),
+            HeadExpression::UnaryOperator(op, operand) => {
+                self.expression(operand);
+                let t = self.infer(operand);
+                match op {
+                    UnaryOperator::Minus | UnaryOperator::BitNot => {
+                        self.check(t, ARITHMETIC, |found| TypeWarningKind::Arithmetic { found });
+                    }
+                    UnaryOperator::Not | UnaryOperator::Len => {}
+                }
+            }
+        }
+
+        let mut left = self.infer_head(&expression.head);
+        for (op, right) in &expression.tail {
+            self.expression(right);
+            let right_types = self.infer(right);
+            match op {
+                BinaryOperator::Add
+                | BinaryOperator::Sub
+                | BinaryOperator::Mul
+                | BinaryOperator::Mod
+                | BinaryOperator::Pow
+                | BinaryOperator::Div
+                | BinaryOperator::IDiv
+                | BinaryOperator::BitAnd
+                | BinaryOperator::BitOr
+                | BinaryOperator::BitXor
+                | BinaryOperator::ShiftLeft
+                | BinaryOperator::ShiftRight => {
+                    self.check(left, ARITHMETIC, |found| TypeWarningKind::Arithmetic {
+                        found,
+                    });
+                    self.check(right_types, ARITHMETIC, |found| {
+                        TypeWarningKind::Arithmetic { found }
+                    });
+                }
+                BinaryOperator::Concat => {
+                    self.check(left, CONCAT, |found| TypeWarningKind::Concat { found });
+                    self.check(right_types, CONCAT, |found| TypeWarningKind::Concat {
+                        found,
+                    });
+                }
+                _ => {}
+            }
+            left = binary_result(*op, left, right_types);
+        }
+    }
+
+    fn simple_expression(&mut self, simple: &'a SimpleExpression<S>) {
+        match simple {
+            SimpleExpression::TableConstructor(constructor) => {
+                for field in &constructor.fields {
+                    match field {
+                        ConstructorField::Array(value) => self.expression(value),
+                        ConstructorField::Record(key, value) => {
+                            if let RecordKey::Indexed(key) = key {
+                                self.expression(key);
+                            }
+                            self.expression(value);
+                        }
+                    }
+                }
+            }
+            SimpleExpression::Function(definition) => self.function(definition, None),
+            SimpleExpression::Suffixed(suffixed) => {
+                self.suffixed_expression(suffixed);
+            }
+            _ => {}
+        }
+    }
+
+    // Check a suffixed expression, returning the type of the result.
+    fn suffixed_expression(&mut self, suffixed: &'a SuffixedExpression<S>) -> Types {
+        let (mut t, mut signature) = self.primary_expression(&suffixed.primary);
+        for suffix in &suffixed.suffixes {
+            match suffix {
+                SuffixPart::Field(field) => {
+                    self.field(t, field);
+                    t = Types::ANY;
+                }
+                SuffixPart::Call(call) => {
+                    t = self.call_suffix(t, signature.as_deref(), call);
+                }
+            }
+            signature = None;
+        }
+        t
+    }
+
+    fn primary_expression(
+        &mut self,
+        primary: &'a PrimaryExpression<S>,
+    ) -> (Types, Option<Rc<Signature>>) {
+        match primary {
+            PrimaryExpression::Name(name) => self.lookup(name),
+            PrimaryExpression::GroupedExpression(expression) => {
+                self.expression(expression);
+                (self.infer(expression), None)
+            }
+        }
+    }
+
+    fn field(&mut self, t: Types, field: &'a FieldSuffix<S>) {
+        self.check(t, INDEX, |found| TypeWarningKind::Index { found });
+        if let FieldSuffix::Indexed(key) = field {
+            self.expression(key);
+        }
+    }
+
+    fn call(&mut self, head: &'a SuffixedExpression<S>, call: &'a CallSuffix<S>) {
+        let (t, signature) = if head.suffixes.is_empty() {
+            self.primary_expression(&head.primary)
+        } else {
+            (self.suffixed_expression(head), None)
+        };
+        self.call_suffix(t, signature.as_deref(), call);
+    }
+
+    // Check a call suffix on a value of the given type, returning the type of the first result.
+    fn call_suffix(
+        &mut self,
+        t: Types,
+        signature: Option<&Signature>,
+        call: &'a CallSuffix<S>,
+    ) -> Types {
+        match call {
+            CallSuffix::Method(_, arguments) => {
+                self.check(t, INDEX, |found| TypeWarningKind::Index { found });
+                for argument in arguments {
+                    self.expression(argument);
+                }
+                Types::ANY
+            }
+            CallSuffix::Function(arguments) => {
+                self.check(t, CALL, |found| TypeWarningKind::Call { found });
+                for argument in arguments {
+                    self.expression(argument);
+                }
+
+                if let Some(signature) = signature {
+                    for (i, (argument, (parameter, expected))) in
+                        arguments.iter().zip(&signature.parameters).enumerate()
+                    {
+                        if i == arguments.len() - 1 && is_multi_value(argument) {
+                            break;
+                        }
+
+                        if let Some(expected) = expected {
+                            let found = self.infer(argument);
+                            if !found.intersects(expected.types) {
+                                self.warn(TypeWarningKind::Argument {
+                                    function: signature.name.clone(),
+                                    parameter: parameter.clone(),
+                                    expected: expected.text.clone(),
+                                    found: found.to_string(),
+                                });
+                            }
+                        }
+                    }
+
+                    signature
+                        .returns
+                        .first()
+                        .map(|r| r.types)
+                        .unwrap_or(Types::NIL)
+                } else {
+                    Types::ANY
+                }
+            }
+        }
+    }
+
+    // Infer the type of an expression without checking it.
+    fn infer(&self, expression: &Expression<S>) -> Types {
+        let mut t = self.infer_head(&expression.head);
+        for (op, right) in &expression.tail {
+            t = binary_result(*op, t, self.infer(right));
+        }
+        t
+    }
+
+    fn infer_head(&self, head: &HeadExpression<S>) -> Types {
+        match head {
+            HeadExpression::Simple(simple) => match simple {
+                SimpleExpression::Float(_) | SimpleExpression::Integer(_) => Types::NUMBER,
+                SimpleExpression::String(_) => Types::STRING,
+                SimpleExpression::Nil => Types::NIL,
+                SimpleExpression::True | SimpleExpression::False => Types::BOOLEAN,
+                SimpleExpression::VarArgs => Types::ANY,
+                SimpleExpression::TableConstructor(_) => Types::TABLE,
+                SimpleExpression::Function(_) => Types::FUNCTION,
+                SimpleExpression::Suffixed(suffixed) => {
+                    let (mut t, mut signature) = match &suffixed.primary {
+                        PrimaryExpression::Name(name) => self.lookup(name),
+                        PrimaryExpression::GroupedExpression(expression) => {
+                            (self.infer(expression), None)
+                        }
+                    };
+                    for suffix in &suffixed.suffixes {
+                        t = match (suffix, &signature) {
+                            (SuffixPart::Call(CallSuffix::Function(_)), Some(signature)) => {
+                                signature
+                                    .returns
+                                    .first()
+                                    .map(|r| r.types)
+                                    .unwrap_or(Types::NIL)
+                            }
+                            _ => Types::ANY,
+                        };
+                        signature = None;
+                    }
+                    t
+                }
+            },
+            HeadExpression::UnaryOperator(op, operand) => match op {
+                UnaryOperator::Not => Types::BOOLEAN,
+                UnaryOperator::Minus | UnaryOperator::BitNot => {
+                    if self.infer(operand).is_subset(Types::NUMBER) {
+                        Types::NUMBER
+                    } else {
+                        Types::ANY
+                    }
+                }
+                UnaryOperator::Len => {
+                    if self.infer(operand).is_subset(Types::STRING) {
+                        Types::NUMBER
+                    } else {
+                        Types::ANY
+                    }
+                }
+            },
+        }
+    }
+
+    fn lookup(&self, name: &S) -> (Types, Option<Rc<Signature>>) {
+        if let Some(local) = self.find_local(name) {
+            (local.types, local.signature.clone())
+        } else if let Some((_, signature)) = self
+            .globals
+            .iter()
+            .find(|(n, _)| n.as_ref() == name.as_ref())
+        {
+            // Globals may be reassigned at any time, so we only trust the signature, not the type.
+            (Types::ANY, Some(signature.clone()))
+        } else {
+            (Types::ANY, None)
+        }
+    }
+
+    fn find_local(&self, name: &S) -> Option<&Local<'a, S>> {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|local| local.name.as_ref() == name.as_ref())
+    }
+
+    fn declare(&mut self, name: &'a S, types: Types, signature: Option<Rc<Signature>>) {
+        self.scopes.last_mut().unwrap().push(Local {
+            name,
+            types,
+            signature,
+        });
+    }
+
+    fn check(
+        &mut self,
+        found: Types,
+        allowed: Types,
+        kind: impl FnOnce(String) -> TypeWarningKind,
+    ) {
+        if !found.intersects(allowed) {
+            self.warn(kind(found.to_string()));
+        }
+    }
+
+    fn warn(&mut self, kind: TypeWarningKind) {
+        self.warnings.push(TypeWarning {
+            kind,
+            line_number: self.line_number,
+        });
+    }
+}
+
+fn binary_result(op: BinaryOperator, left: Types, right: Types) -> Types {
+    match op {
+        BinaryOperator::Add
+        | BinaryOperator::Sub
+        | BinaryOperator::Mul
+        | BinaryOperator::Mod
+        | BinaryOperator::Pow
+        | BinaryOperator::Div
+        | BinaryOperator::IDiv
+        | BinaryOperator::BitAnd
+        | BinaryOperator::BitOr
+        | BinaryOperator::BitXor
+        | BinaryOperator::ShiftLeft
+        | BinaryOperator::ShiftRight => {
+            if left.is_subset(Types::NUMBER) && right.is_subset(Types::NUMBER) {
+                Types::NUMBER
+            } else {
+                Types::ANY
+            }
+        }
+        BinaryOperator::Concat => {
+            let strings = Types::STRING.union(Types::NUMBER);
+            if left.is_subset(strings) && right.is_subset(strings) {
+                Types::STRING
+            } else {
+                Types::ANY
+            }
+        }
+        BinaryOperator::NotEqual
+        | BinaryOperator::Equal
+        | BinaryOperator::LessThan
+        | BinaryOperator::LessEqual
+        | BinaryOperator::GreaterThan
+        | BinaryOperator::GreaterEqual => Types::BOOLEAN,
+        BinaryOperator::And | BinaryOperator::Or => left.union(right),
+    }
+}
+
+// Returns true if an expression may evaluate to more than one value when it is last in a list.
+fn is_multi_value<S>(expression: &Expression<S>) -> bool {
+    if !expression.tail.is_empty() {
+        return false;
+    }
+
+    match &*expression.head {
+        HeadExpression::Simple(SimpleExpression::VarArgs) => true,
+        HeadExpression::Simple(SimpleExpression::Suffixed(suffixed)) => {
+            matches!(suffixed.suffixes.last(), Some(SuffixPart::Call(_)))
+        }
+        _ => false,
+    }
+}
+
+fn signature<S: AsRef<[u8]>>(
+    name: String,
+    definition: &FunctionDefinition<S>,
+) -> Option<Rc<Signature>> {
+    if definition.annotations.is_empty() {
+        return None;
+    }
+
+    let mut parameters: Vec<(String, Option<AnnotatedType>)> = definition
+        .parameters
+        .iter()
+        .map(|p| (String::from_utf8_lossy(p.as_ref()).into_owned(), None))
+        .collect();
+    let mut returns = Vec::new();
+
+    for Annotation { tag, text, .. } in &definition.annotations {
+        let text = String::from_utf8_lossy(text.as_ref());
+        match tag.as_ref() {
+            b"param" => {
+                let text = text.trim_start();
+                let name_end = text.find(char::is_whitespace).unwrap_or(text.len());
+                let (name, rest) = text.split_at(name_end);
+                let (name, optional) = match name.strip_suffix('?') {
+                    Some(name) => (name, true),
+                    None => (name, false),
+                };
+                if let Some(mut annotated) = parse_type(rest) {
+                    if optional {
+                        annotated.types = annotated.types.union(Types::NIL);
+                    }
+                    if let Some((_, t)) = parameters.iter_mut().find(|(n, _)| n == name) {
+                        *t = Some(annotated);
+                    }
+                }
+            }
+            b"return" => {
+                for part in split_top_level(&text, ',') {
+                    if let Some(annotated) = parse_type(part) {
+                        returns.push(annotated);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Some(Rc::new(Signature {
+        name,
+        parameters,
+        returns,
+    }))
+}
+
+// Parse the leading type expression of some annotation text.
+fn parse_type(text: &str) -> Option<AnnotatedType> {
+    let text = text.trim_start();
+
+    // The type ends at the first whitespace outside of any brackets.
+    let mut depth = 0i32;
+    let mut end = text.len();
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' | '<' | '[' | '{' => depth += 1,
+            ')' | '>' | ']' | '}' => depth -= 1,
+            c if c.is_whitespace() && depth <= 0 => {
+                end = i;
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    let text = &text[..end];
+    if text.is_empty() {
+        return None;
+    }
+
+    let mut types = Types(0);
+    for alternative in split_top_level(text, '|') {
+        types = types.union(parse_single_type(alternative.trim()));
+    }
+
+    Some(AnnotatedType {
+        types,
+        text: text.to_owned(),
+    })
+}
+
+fn parse_single_type(text: &str) -> Types {
+    if let Some(text) = text.strip_suffix('?') {
+        return parse_single_type(text).union(Types::NIL);
+    }
+
+    match text {
+        "nil" => Types::NIL,
+        "boolean" | "true" | "false" => Types::BOOLEAN,
+        "number" | "integer" => Types::NUMBER,
+        "string" => Types::STRING,
+        "table" => Types::TABLE,
+        "function" | "fun" => Types::FUNCTION,
+        "thread" => Types::THREAD,
+        "userdata" | "lightuserdata" => Types::USERDATA,
+        _ if text.starts_with("fun(") => Types::FUNCTION,
+        _ if text.starts_with("table<") || text.starts_with('{') || text.ends_with("[]") => {
+            Types::TABLE
+        }
+        _ if text.starts_with('"') || text.starts_with('\'') => Types::STRING,
+        _ if text.starts_with(|c: char| c.is_ascii_digit()) => Types::NUMBER,
+        // Class names, aliases and anything else we don't understand.
+        _ => Types::ANY,
+    }
+}
+
+// Split a string at the given separator, ignoring separators inside of brackets.
+fn split_top_level(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' | '<' | '[' | '{' => depth += 1,
+            ')' | '>' | ']' | '}' => depth -= 1,
+            c if c == separator && depth <= 0 => {
+                parts.push(&text[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+// Returns true if the given name is assigned to anywhere in the given statements, including within
+// nested blocks and functions.
+//
+// This ignores shadowing, which can only make the check more conservative.
+fn assigns_statements<S: AsRef<[u8]>>(
+    name: &S,
+    statements: &[LineAnnotated<Statement<S>>],
+) -> bool {
+    statements.iter().any(|s| assigns_statement(name, &s.inner))
+}
+
+fn assigns_block<S: AsRef<[u8]>>(name: &S, block: &Block<S>) -> bool {
+    assigns_statements(name, &block.statements)
+        || block
+            .return_statement
+            .iter()
+            .any(|r| r.returns.iter().any(|e| assigns_expression(name, e)))
+}
+
+fn assigns_statement<S: AsRef<[u8]>>(name: &S, statement: &Statement<S>) -> bool {
+    let is_name = |n: &S| n.as_ref() == name.as_ref();
+    let any_expression = |es: &[Expression<S>]| es.iter().any(|e| assigns_expression(name, e));
+
+    match statement {
+        Statement::If(s) => {
+            assigns_expression(name, &s.if_part.0)
+                || assigns_block(name, &s.if_part.1)
+                || s.else_if_parts
+                    .iter()
+                    .any(|(c, b)| assigns_expression(name, c) || assigns_block(name, b))
+                || s.else_part.iter().any(|b| assigns_block(name, b))
+        }
+        Statement::While(s) => {
+            assigns_expression(name, &s.condition) || assigns_block(name, &s.block)
+        }
+        Statement::Do(b) => assigns_block(name, b),
+        Statement::For(ForStatement::Numeric {
+            initial,
+            limit,
+            step,
+            body,
+            ..
+        }) => {
+            assigns_expression(name, initial)
+                || assigns_expression(name, limit)
+                || step.iter().any(|e| assigns_expression(name, e))
+                || assigns_block(name, body)
+        }
+        Statement::For(ForStatement::Generic {
+            arguments, body, ..
+        }) => any_expression(arguments) || assigns_block(name, body),
+        Statement::Repeat(s) => assigns_block(name, &s.body) || assigns_expression(name, &s.until),
+        Statement::Function(s) => {
+            (is_name(&s.name) && s.fields.is_empty() && s.method.is_none())
+                || assigns_block(name, &s.definition.body)
+        }
+        Statement::LocalFunction(s) => assigns_block(name, &s.definition.body),
+        Statement::LocalStatement(s) => any_expression(&s.values),
+        Statement::Label(_) | Statement::Break | Statement::Goto(_) => false,
+        Statement::FunctionCall(s) => {
+            assigns_suffixed(name, &s.head)
+                || match &s.call {
+                    CallSuffix::Method(_, args) | CallSuffix::Function(args) => {
+                        any_expression(args)
+                    }
+                }
+        }
+        Statement::Assignment(s) => {
+            s.targets.iter().any(|t| match t {
+                AssignmentTarget::Name(n) => is_name(n),
+                AssignmentTarget::Field(table, field) => {
+                    assigns_suffixed(name, table)
+                        || matches!(field, FieldSuffix::Indexed(e) if assigns_expression(name, e))
+                }
+            }) || any_expression(&s.values)
+        }
+    }
+}
+
+fn assigns_expression<S: AsRef<[u8]>>(name: &S, expression: &Expression<S>) -> bool {
+    let head = match &*expression.head {
+        HeadExpression::Simple(simple) => match simple {
+            SimpleExpression::TableConstructor(constructor) => {
+                constructor.fields.iter().any(|field| match field {
+                    ConstructorField::Array(value) => assigns_expression(name, value),
+                    ConstructorField::Record(key, value) => {
+                        matches!(key, RecordKey::Indexed(k) if assigns_expression(name, k))
+                            || assigns_expression(name, value)
+                    }
+                })
+            }
+            SimpleExpression::Function(definition) => assigns_block(name, &definition.body),
+            SimpleExpression::Suffixed(suffixed) => assigns_suffixed(name, suffixed),
+            _ => false,
+        },
+        HeadExpression::UnaryOperator(_, operand) => assigns_expression(name, operand),
+    };
+
+    head || expression
+        .tail
+        .iter()
+        .any(|(_, right)| assigns_expression(name, right))
+}
+
+fn assigns_suffixed<S: AsRef<[u8]>>(name: &S, suffixed: &SuffixedExpression<S>) -> bool {
+    let primary = match &suffixed.primary {
+        PrimaryExpression::Name(_) => false,
+        PrimaryExpression::GroupedExpression(e) => assigns_expression(name, e),
+    };
+
+    primary
+        || suffixed.suffixes.iter().any(|suffix| match suffix {
+            SuffixPart::Field(FieldSuffix::Named(_)) => false,
+            SuffixPart::Field(FieldSuffix::Indexed(e)) => assigns_expression(name, e),
+            SuffixPart::Call(CallSuffix::Method(_, args) | CallSuffix::Function(args)) => {
+                args.iter().any(|e| assigns_expression(name, e))
+            }
+        })
+}
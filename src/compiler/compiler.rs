@@ -18,13 +18,7 @@ use crate::{
 };
 
 use super::{
-    lexer::LineNumber,
-    operators::{
-        categorize_binop, comparison_binop_const_fold, comparison_binop_operation,
-        simple_binop_const_fold, simple_binop_operation, unop_const_fold, unop_operation,
-        BinOpCategory, ComparisonBinOp, ShortCircuitBinOp, SimpleBinOp,
-    },
-    parser::{
+    ast::{
         AssignmentStatement, AssignmentTarget, BinaryOperator, Block, CallSuffix, Chunk,
         ConstructorField, Expression, FieldSuffix, ForStatement, FunctionCallStatement,
         FunctionDefinition, FunctionStatement, HeadExpression, IfStatement, LocalFunctionStatement,
@@ -32,6 +26,12 @@ use super::{
         SimpleExpression, Statement, SuffixPart, SuffixedExpression, TableConstructor,
         UnaryOperator, WhileStatement,
     },
+    lexer::{Annotation, LineNumber},
+    operators::{
+        categorize_binop, comparison_binop_const_fold, comparison_binop_operation,
+        simple_binop_const_fold, simple_binop_operation, unop_const_fold, unop_operation,
+        BinOpCategory, ComparisonBinOp, ShortCircuitBinOp, SimpleBinOp,
+    },
     register_allocator::RegisterAllocator,
     StringInterner,
 };
@@ -116,6 +116,50 @@ pub struct CompiledPrototype<S> {
     pub opcode_line_numbers: Vec<(usize, LineNumber)>,
     pub upvalues: Vec<UpValueDescriptor>,
     pub prototypes: Vec<Box<CompiledPrototype<S>>>,
+    /// Annotation comments attached to this function's definition, if any were collected.
+    pub annotations: Vec<Annotation<S>>,
+    /// Every local variable declared in this function, including parameters, in order of
+    /// declaration.
+    pub local_variables: Vec<LocalVariable<S>>,
+    /// The name of each upvalue, in the same order as `upvalues`.
+    pub upvalue_names: Vec<S>,
+}
+
+/// Debug info for a local variable.
+#[derive(Debug, Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub struct LocalVariable<S> {
+    pub name: S,
+    pub register: RegisterIndex,
+    /// The index of the first opcode at which the variable is in scope.
+    pub start_pc: usize,
+    /// The index of the first opcode after the variable goes out of scope.
+    pub end_pc: usize,
+}
+
+impl<S> LocalVariable<S> {
+    /// Returns true if the variable is in scope at the given opcode index.
+    pub fn is_active(&self, pc: usize) -> bool {
+        self.start_pc <= pc && pc < self.end_pc
+    }
+
+    pub fn as_string_ref(&self) -> LocalVariable<&S> {
+        LocalVariable {
+            name: &self.name,
+            register: self.register,
+            start_pc: self.start_pc,
+            end_pc: self.end_pc,
+        }
+    }
+
+    pub fn map_strings<S2>(self, f: impl Fn(S) -> S2) -> LocalVariable<S2> {
+        LocalVariable {
+            name: f(self.name),
+            register: self.register,
+            start_pc: self.start_pc,
+            end_pc: self.end_pc,
+        }
+    }
 }
 
 impl<S> CompiledPrototype<S> {
@@ -142,6 +186,17 @@ impl<S> CompiledPrototype<S> {
                     .into_iter()
                     .map(|p| Box::new(do_map(*p, f)))
                     .collect(),
+                annotations: this
+                    .annotations
+                    .into_iter()
+                    .map(|a| a.map_strings(f))
+                    .collect(),
+                local_variables: this
+                    .local_variables
+                    .into_iter()
+                    .map(|l| l.map_strings(f))
+                    .collect(),
+                upvalue_names: this.upvalue_names.into_iter().map(f).collect(),
             }
         }
         do_map(self, &f)
@@ -189,6 +244,10 @@ struct CompilerFunction<S> {
     has_varargs: bool,
     fixed_params: u8,
     locals: Vec<(S, RegisterIndex)>,
+    // Debug info for every local declared so far, and the indexes in it of the locals currently in
+    // scope, which always correspond to the entries of `locals`.
+    local_variables: Vec<LocalVariable<S>>,
+    open_local_variables: Vec<usize>,
 
     blocks: Vec<BlockDescriptor>,
     unique_jump_id: u64,
@@ -360,7 +419,7 @@ impl<S: StringInterner> Compiler<S> {
         while let Some((_, last)) = self.current_function.locals.last() {
             if last.0 as u16 >= last_block.stack_bottom {
                 self.current_function.register_allocator.free(*last);
-                self.current_function.locals.pop();
+                self.current_function.pop_local();
             } else {
                 break;
             }
@@ -587,7 +646,7 @@ impl<S: StringInterner> Compiler<S> {
                     .register_allocator
                     .push(1)
                     .ok_or(CompileErrorKind::Registers)?;
-                self.current_function.locals.push((name.clone(), loop_var));
+                self.current_function.declare_local(name.clone(), loop_var);
 
                 self.block_statements(body)?;
                 self.exit_block()?;
@@ -669,8 +728,7 @@ impl<S: StringInterner> Compiler<S> {
                     .ok_or(CompileErrorKind::Registers)?;
                 for i in 0..name_count {
                     self.current_function
-                        .locals
-                        .push((names[i as usize].clone(), RegisterIndex(names_reg.0 + i)));
+                        .declare_local(names[i as usize].clone(), RegisterIndex(names_reg.0 + i));
                 }
 
                 self.jump(loop_label.clone())?;
@@ -796,6 +854,7 @@ impl<S: StringInterner> Compiler<S> {
                 &parameters,
                 function_statement.definition.has_varargs,
                 &function_statement.definition.body,
+                &function_statement.definition.annotations,
             )?
         } else {
             self.new_prototype(
@@ -803,6 +862,7 @@ impl<S: StringInterner> Compiler<S> {
                 &function_statement.definition.parameters,
                 function_statement.definition.has_varargs,
                 &function_statement.definition.body,
+                &function_statement.definition.annotations,
             )?
         };
 
@@ -864,10 +924,10 @@ impl<S: StringInterner> Compiler<S> {
                 .operations
                 .push(Operation::LoadNil { dest, count });
             for i in 0..name_len {
-                self.current_function.locals.push((
+                self.current_function.declare_local(
                     local_statement.names[i].clone(),
                     RegisterIndex(dest.0 + i as u8),
-                ));
+                );
             }
         } else {
             for i in 0..val_len {
@@ -883,16 +943,15 @@ impl<S: StringInterner> Compiler<S> {
                     let dest = self.expr_push_count(expr, names_left)?;
 
                     for j in 0..names_left {
-                        self.current_function.locals.push((
+                        self.current_function.declare_local(
                             local_statement.names[val_len - 1 + j as usize].clone(),
                             RegisterIndex(dest.0 + j),
-                        ));
+                        );
                     }
                 } else {
                     let reg = self.expr_discharge(expr, ExprDestination::PushNew)?;
                     self.current_function
-                        .locals
-                        .push((local_statement.names[i].clone(), reg));
+                        .declare_local(local_statement.names[i].clone(), reg);
                 }
             }
         }
@@ -1027,8 +1086,7 @@ impl<S: StringInterner> Compiler<S> {
             .push(1)
             .ok_or(CompileErrorKind::Registers)?;
         self.current_function
-            .locals
-            .push((local_function.name.clone(), dest));
+            .declare_local(local_function.name.clone(), dest);
 
         let proto = self.new_prototype(
             FunctionRef::Named(
@@ -1038,6 +1096,7 @@ impl<S: StringInterner> Compiler<S> {
             &local_function.definition.parameters,
             local_function.definition.has_varargs,
             &local_function.definition.body,
+            &local_function.definition.annotations,
         )?;
 
         self.current_function
@@ -1131,6 +1190,7 @@ impl<S: StringInterner> Compiler<S> {
             &function.parameters,
             function.has_varargs,
             &function.body,
+            &function.annotations,
         )?;
         Ok(ExprDescriptor::Closure(proto))
     }
@@ -1285,6 +1345,7 @@ impl<S: StringInterner> Compiler<S> {
         parameters: &[S::String],
         has_varargs: bool,
         body: &Block<S::String>,
+        annotations: &[Annotation<S::String>],
     ) -> Result<PrototypeIndex, CompileErrorKind> {
         let old_current = mem::replace(
             &mut self.current_function,
@@ -1292,11 +1353,12 @@ impl<S: StringInterner> Compiler<S> {
         );
         self.upper_functions.push(old_current);
         self.block(body)?;
-        let proto = mem::replace(
+        let mut proto = mem::replace(
             &mut self.current_function,
             self.upper_functions.pop().unwrap(),
         )
         .finish()?;
+        proto.annotations = annotations.to_vec();
         self.current_function.functions.push(proto);
         Ok(PrototypeIndex(
             (self.current_function.functions.len() - 1)
@@ -2432,6 +2494,8 @@ impl<S: Clone> CompilerFunction<S> {
             has_varargs: false,
             fixed_params: 0,
             locals: Vec::new(),
+            local_variables: Vec::new(),
+            open_local_variables: Vec::new(),
             blocks: Vec::new(),
             unique_jump_id: 0,
             jump_targets: Vec::new(),
@@ -2451,9 +2515,7 @@ impl<S: Clone> CompilerFunction<S> {
         function.has_varargs = has_varargs;
         function.fixed_params = fixed_params;
         for i in 0..fixed_params {
-            function
-                .locals
-                .push((parameters[i as usize].clone(), RegisterIndex(i)));
+            function.declare_local(parameters[i as usize].clone(), RegisterIndex(i));
         }
         Ok(function)
     }
@@ -2464,8 +2526,9 @@ impl<S: Clone> CompilerFunction<S> {
             count: VarCount::constant(0),
         });
         assert!(self.locals.len() == self.fixed_params as usize);
-        for (_, r) in self.locals.drain(..) {
-            self.register_allocator.free(r);
+        while let Some((_, r)) = self.locals.last() {
+            self.register_allocator.free(*r);
+            self.pop_local();
         }
         assert_eq!(
             self.register_allocator.stack_top(),
@@ -2504,9 +2567,33 @@ impl<S: Clone> CompilerFunction<S> {
             opcode_line_numbers: operation_lines,
             upvalues: self.upvalues.iter().map(|(_, d)| *d).collect(),
             prototypes: self.functions.into_iter().map(|f| Box::new(f)).collect(),
+            annotations: Vec::new(),
+            local_variables: self.local_variables,
+            upvalue_names: self.upvalues.into_iter().map(|(n, _)| n).collect(),
         })
     }
 
+    // Declare a local variable stored in `register`, which is in scope starting from the next
+    // operation.
+    fn declare_local(&mut self, name: S, register: RegisterIndex) {
+        self.open_local_variables.push(self.local_variables.len());
+        self.local_variables.push(LocalVariable {
+            name: name.clone(),
+            register,
+            start_pc: self.operations.len(),
+            end_pc: self.operations.len(),
+        });
+        self.locals.push((name, register));
+    }
+
+    // Remove the most recently declared local variable from scope, its register must be freed
+    // separately.
+    fn pop_local(&mut self) {
+        self.locals.pop();
+        let index = self.open_local_variables.pop().unwrap();
+        self.local_variables[index].end_pc = self.operations.len();
+    }
+
     pub fn set_line_number(&mut self, line_number: LineNumber) {
         self.current_line_number = line_number;
         self.operation_lines
@@ -246,8 +246,16 @@ pub enum LexError {
     IOError(#[from] io::Error),
 }
 
+impl LexError {
+    /// Returns true if this error was caused by the input ending in the middle of a token, so it
+    /// may be fixed by appending more input.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(self, LexError::UnfinishedLongString)
+    }
+}
+
 /// A 0-indexed line number of the current source input.
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Collect)]
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Collect)]
 #[collect(require_static)]
 pub struct LineNumber(pub u64);
 
@@ -257,12 +265,65 @@ impl fmt::Display for LineNumber {
     }
 }
 
-pub struct Lexer<R, S> {
+/// A position in the current source input.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Collect)]
+#[collect(require_static)]
+pub struct SourceLocation {
+    /// The 0-indexed byte offset from the start of the source.
+    pub offset: u64,
+    pub line_number: LineNumber,
+    /// The 0-indexed byte offset from the start of the line.
+    pub column: u64,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line_number, u128::from(self.column) + 1)
+    }
+}
+
+/// A `---@tag text` annotation comment, as used by documentation and type checking tools.
+///
+/// For example, the comment `---@param x number The x coordinate` has the tag `param` and the text
+/// `x number The x coordinate`.
+#[derive(Debug, Clone, Collect)]
+#[collect(no_drop)]
+pub struct Annotation<S> {
+    pub tag: S,
+    pub text: S,
+    pub line_number: LineNumber,
+}
+
+impl<S> Annotation<S> {
+    pub fn as_string_ref(&self) -> Annotation<&S> {
+        Annotation {
+            tag: &self.tag,
+            text: &self.text,
+            line_number: self.line_number,
+        }
+    }
+
+    pub fn map_strings<S2>(self, f: impl Fn(S) -> S2) -> Annotation<S2> {
+        Annotation {
+            tag: f(self.tag),
+            text: f(self.text),
+            line_number: self.line_number,
+        }
+    }
+}
+
+pub struct Lexer<R, S: StringInterner> {
     source: Option<R>,
     interner: S,
     peek_buffer: Vec<u8>,
     string_buffer: Vec<u8>,
     line_number: u64,
+    // The number of bytes consumed from the source.
+    offset: u64,
+    // The offset of the start of the current line.
+    line_start: u64,
+    collect_annotations: bool,
+    annotations: Vec<Annotation<S::String>>,
 }
 
 impl<R, S> Lexer<R, S>
@@ -277,14 +338,38 @@ where
             peek_buffer: Vec::new(),
             string_buffer: Vec::new(),
             line_number: 0,
+            offset: 0,
+            line_start: 0,
+            collect_annotations: false,
+            annotations: Vec::new(),
         }
     }
 
+    /// If enabled, `---@tag text` comments are collected as they are skipped rather than simply
+    /// discarded. Collected annotations can be retrieved with [`Lexer::take_annotations`].
+    pub fn set_collect_annotations(&mut self, collect_annotations: bool) {
+        self.collect_annotations = collect_annotations;
+    }
+
+    /// Take all annotations collected since the last call to this method.
+    pub fn take_annotations(&mut self) -> Vec<Annotation<S::String>> {
+        std::mem::take(&mut self.annotations)
+    }
+
     /// Current line number of the source file.
     pub fn line_number(&self) -> LineNumber {
         LineNumber(self.line_number)
     }
 
+    /// Current location in the source file.
+    pub fn location(&self) -> SourceLocation {
+        SourceLocation {
+            offset: self.offset,
+            line_number: self.line_number(),
+            column: self.offset - self.line_start,
+        }
+    }
+
     pub fn skip_whitespace(&mut self) -> Result<(), LexError> {
         let mut do_skip_whitespace = || {
             while let Some(c) = self.peek(0)? {
@@ -308,6 +393,9 @@ where
                                     // long comment
                                     self.read_long_string(false)?;
                                 }
+                                (Some(b'-'), Some(b'@')) if self.collect_annotations => {
+                                    self.read_annotation()?;
+                                }
                                 _ => {
                                     // Short comment, read until end of line
                                     while let Some(c) = self.peek(0)? {
@@ -530,6 +618,7 @@ where
         }
 
         self.line_number += 1;
+        self.line_start = self.offset;
         Ok(())
     }
 
@@ -854,6 +943,51 @@ where
         ))
     }
 
+    // Read an annotation comment of the form `---@tag text` up to the end of the line, assumes the
+    // initial `--` has already been consumed.
+    fn read_annotation(&mut self) -> Result<(), LexError> {
+        let line_number = self.line_number();
+        self.advance(2);
+
+        self.string_buffer.clear();
+        while let Some(c) = self.peek(0)? {
+            if is_alpha(c) || is_digit(c) {
+                self.string_buffer.push(c);
+                self.advance(1);
+            } else {
+                break;
+            }
+        }
+        let tag = self.take_string();
+
+        while let Some(c) = self.peek(0)? {
+            if is_newline(c) {
+                break;
+            }
+            self.string_buffer.push(c);
+            self.advance(1);
+        }
+        let start = self
+            .string_buffer
+            .iter()
+            .position(|&c| !is_space(c))
+            .unwrap_or(self.string_buffer.len());
+        let end = self
+            .string_buffer
+            .iter()
+            .rposition(|&c| !is_space(c))
+            .map_or(start, |i| i + 1);
+        let text = self.interner.intern(&self.string_buffer[start..end]);
+        self.string_buffer.clear();
+
+        self.annotations.push(Annotation {
+            tag,
+            text,
+            line_number,
+        });
+        Ok(())
+    }
+
     fn peek(&mut self, n: usize) -> Result<Option<u8>, LexError> {
         if let Some(source) = self.source.as_mut() {
             while self.peek_buffer.len() <= n {
@@ -885,6 +1019,7 @@ where
             "cannot advance over un-peeked characters"
         );
         self.peek_buffer.drain(0..n);
+        self.offset += n as u64;
     }
 
     fn take_string(&mut self) -> S::String {
@@ -1,3 +1,5 @@
+pub mod ast;
+mod checker;
 mod compiler;
 pub mod interning;
 pub mod lexer;
@@ -7,8 +9,16 @@ mod register_allocator;
 pub mod string_utils;
 
 pub use self::{
-    compiler::{compile_chunk, CompileError, CompileErrorKind, CompiledPrototype, FunctionRef},
+    checker::{check_chunk, TypeWarning, TypeWarningKind},
+    compiler::{
+        compile_chunk, CompileError, CompileErrorKind, CompiledPrototype, FunctionRef,
+        LocalVariable,
+    },
     interning::StringInterner,
-    lexer::LineNumber,
-    parser::{parse_chunk, ParseError, ParseErrorKind},
+    lexer::{Annotation, LineNumber, SourceLocation},
+    parser::{
+        parse_chunk, parse_chunk_recovering, parse_chunk_with_annotations,
+        parse_chunk_with_options, ParseError, ParseErrorKind, ParseOptions, RecoveredChunk,
+        Restrictions,
+    },
 };
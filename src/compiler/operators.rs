@@ -4,7 +4,7 @@ use crate::{
     Constant,
 };
 
-use super::parser::{BinaryOperator, UnaryOperator};
+use super::ast::{BinaryOperator, UnaryOperator};
 
 // Binary operators which map directly to a single opcode
 #[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
@@ -164,6 +164,8 @@ pub fn comparison_binop_const_fold<S: AsRef<[u8]>>(
     match comparison_binop {
         ComparisonBinOp::Equal => Some(Constant::Boolean(left.is_equal(right))),
         ComparisonBinOp::NotEqual => Some(Constant::Boolean(!left.is_equal(right))),
+        // The ordering of strings depends on the collation in use when the comparison runs.
+        _ if matches!((left, right), (Constant::String(_), Constant::String(_))) => None,
         ComparisonBinOp::LessThan => Some(Constant::Boolean(left.less_than(right)?)),
         ComparisonBinOp::LessEqual => Some(Constant::Boolean(left.less_equal(right)?)),
         ComparisonBinOp::GreaterThan => Some(Constant::Boolean(right.less_than(left)?)),
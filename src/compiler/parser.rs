@@ -1,282 +1,14 @@
-use std::{io::Read, ops, rc::Rc};
+use std::{collections::VecDeque, io::Read, rc::Rc};
 
 use thiserror::Error;
 
+// The AST types were originally defined in this module.
+pub use super::ast::*;
 use super::{
-    lexer::{LexError, Lexer, LineNumber, Token},
+    lexer::{Annotation, LexError, Lexer, LineNumber, SourceLocation, Token},
     StringInterner,
 };
 
-#[derive(Debug, Clone)]
-pub struct LineAnnotated<T> {
-    pub inner: T,
-    pub line_number: LineNumber,
-}
-
-impl<T> ops::Deref for LineAnnotated<T> {
-    type Target = T;
-
-    fn deref(&self) -> &Self::Target {
-        &self.inner
-    }
-}
-
-impl<T> AsRef<T> for LineAnnotated<T> {
-    fn as_ref(&self) -> &T {
-        &self.inner
-    }
-}
-
-impl<T> LineAnnotated<T> {
-    pub fn new(line_number: LineNumber, inner: T) -> Self {
-        Self { inner, line_number }
-    }
-
-    pub fn map<R>(self, f: impl FnOnce(T) -> R) -> LineAnnotated<R> {
-        LineAnnotated {
-            inner: f(self.inner),
-            line_number: self.line_number,
-        }
-    }
-
-    pub fn try_map<R, E>(self, f: impl FnOnce(T) -> Result<R, E>) -> Result<LineAnnotated<R>, E> {
-        Ok(LineAnnotated {
-            inner: f(self.inner)?,
-            line_number: self.line_number,
-        })
-    }
-}
-
-#[derive(Debug, Clone)]
-pub struct Chunk<S> {
-    pub block: Block<S>,
-}
-
-#[derive(Debug, Clone)]
-pub struct Block<S> {
-    pub statements: Vec<LineAnnotated<Statement<S>>>,
-    pub return_statement: Option<LineAnnotated<ReturnStatement<S>>>,
-    pub closed_on: LineNumber,
-}
-
-#[derive(Debug, Clone)]
-pub enum Statement<S> {
-    If(IfStatement<S>),
-    While(WhileStatement<S>),
-    Do(Block<S>),
-    For(ForStatement<S>),
-    Repeat(RepeatStatement<S>),
-    Function(FunctionStatement<S>),
-    LocalFunction(LocalFunctionStatement<S>),
-    LocalStatement(LocalStatement<S>),
-    Label(LabelStatement<S>),
-    Break,
-    Goto(GotoStatement<S>),
-    FunctionCall(FunctionCallStatement<S>),
-    Assignment(AssignmentStatement<S>),
-}
-
-#[derive(Debug, Clone)]
-pub struct ReturnStatement<S> {
-    pub returns: Vec<Expression<S>>,
-}
-
-#[derive(Debug, Clone)]
-pub struct IfStatement<S> {
-    pub if_part: (Expression<S>, Block<S>),
-    pub else_if_parts: Vec<(Expression<S>, Block<S>)>,
-    pub else_part: Option<Block<S>>,
-}
-
-#[derive(Debug, Clone)]
-pub struct WhileStatement<S> {
-    pub condition: Expression<S>,
-    pub block: Block<S>,
-}
-
-#[derive(Debug, Clone)]
-pub enum ForStatement<S> {
-    Numeric {
-        name: S,
-        initial: Expression<S>,
-        limit: Expression<S>,
-        step: Option<Expression<S>>,
-        body: Block<S>,
-    },
-    Generic {
-        names: Vec<S>,
-        arguments: Vec<Expression<S>>,
-        body: Block<S>,
-    },
-}
-
-#[derive(Debug, Clone)]
-pub struct RepeatStatement<S> {
-    pub body: Block<S>,
-    pub until: Expression<S>,
-}
-
-#[derive(Debug, Clone)]
-pub struct LabelStatement<S> {
-    pub name: S,
-}
-
-#[derive(Debug, Clone)]
-pub struct GotoStatement<S> {
-    pub name: S,
-}
-
-#[derive(Debug, Clone)]
-pub struct FunctionStatement<S> {
-    pub name: S,
-    pub fields: Vec<S>,
-    pub method: Option<S>,
-    pub definition: FunctionDefinition<S>,
-}
-
-#[derive(Debug, Clone)]
-pub struct LocalFunctionStatement<S> {
-    pub name: S,
-    pub definition: FunctionDefinition<S>,
-}
-
-#[derive(Debug, Clone)]
-pub struct LocalStatement<S> {
-    pub names: Vec<S>,
-    pub values: Vec<Expression<S>>,
-}
-
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
-pub enum BinaryOperator {
-    Add,
-    Sub,
-    Mul,
-    Mod,
-    Pow,
-    Div,
-    IDiv,
-    BitAnd,
-    BitOr,
-    BitXor,
-    ShiftLeft,
-    ShiftRight,
-    Concat,
-    NotEqual,
-    Equal,
-    LessThan,
-    LessEqual,
-    GreaterThan,
-    GreaterEqual,
-    And,
-    Or,
-}
-
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
-pub enum UnaryOperator {
-    Not,
-    Minus,
-    BitNot,
-    Len,
-}
-
-#[derive(Debug, Clone)]
-pub struct Expression<S> {
-    pub head: Box<HeadExpression<S>>,
-    pub tail: Vec<(BinaryOperator, Expression<S>)>,
-}
-
-#[derive(Debug, Clone)]
-pub enum HeadExpression<S> {
-    Simple(SimpleExpression<S>),
-    UnaryOperator(UnaryOperator, Expression<S>),
-}
-
-#[derive(Debug, Clone)]
-pub enum SimpleExpression<S> {
-    Float(f64),
-    Integer(i64),
-    String(S),
-    Nil,
-    True,
-    False,
-    VarArgs,
-    TableConstructor(TableConstructor<S>),
-    Function(FunctionDefinition<S>),
-    Suffixed(SuffixedExpression<S>),
-}
-
-#[derive(Debug, Clone)]
-pub enum PrimaryExpression<S> {
-    Name(S),
-    GroupedExpression(Expression<S>),
-}
-
-#[derive(Debug, Clone)]
-pub enum FieldSuffix<S> {
-    Named(S),
-    Indexed(Expression<S>),
-}
-
-#[derive(Debug, Clone)]
-pub enum CallSuffix<S> {
-    Method(S, Vec<Expression<S>>),
-    Function(Vec<Expression<S>>),
-}
-
-#[derive(Debug, Clone)]
-pub enum SuffixPart<S> {
-    Field(FieldSuffix<S>),
-    Call(CallSuffix<S>),
-}
-
-#[derive(Debug, Clone)]
-pub struct SuffixedExpression<S> {
-    pub primary: PrimaryExpression<S>,
-    pub suffixes: Vec<SuffixPart<S>>,
-}
-
-#[derive(Debug, Clone)]
-pub struct FunctionDefinition<S> {
-    pub parameters: Vec<S>,
-    pub has_varargs: bool,
-    pub body: Block<S>,
-}
-
-#[derive(Debug, Clone)]
-pub struct FunctionCallStatement<S> {
-    pub head: SuffixedExpression<S>,
-    pub call: CallSuffix<S>,
-}
-
-#[derive(Debug, Clone)]
-pub struct AssignmentStatement<S> {
-    pub targets: Vec<AssignmentTarget<S>>,
-    pub values: Vec<Expression<S>>,
-}
-
-#[derive(Debug, Clone)]
-pub enum AssignmentTarget<S> {
-    Name(S),
-    Field(SuffixedExpression<S>, FieldSuffix<S>),
-}
-
-#[derive(Debug, Clone)]
-pub struct TableConstructor<S> {
-    pub fields: Vec<ConstructorField<S>>,
-}
-
-#[derive(Debug, Clone)]
-pub enum ConstructorField<S> {
-    Array(Expression<S>),
-    Record(RecordKey<S>, Expression<S>),
-}
-
-#[derive(Debug, Clone)]
-pub enum RecordKey<S> {
-    Named(S),
-    Indexed(Expression<S>),
-}
-
 #[derive(Debug, Error)]
 pub enum ParseErrorKind {
     #[error("found {unexpected:?}, expected {expected:?}")]
@@ -295,15 +27,88 @@ pub enum ParseErrorKind {
     ExpressionNotStatement,
     #[error("recursion limit reached")]
     RecursionLimit,
+    #[error("{0} is not allowed in this chunk")]
+    Restricted(&'static str),
     #[error("lexer error")]
     LexError(#[from] LexError),
 }
 
+impl ParseErrorKind {
+    /// Returns true if this error was caused by unexpectedly reaching the end of the input, so it
+    /// may be fixed by appending more input.
+    ///
+    /// This is useful for interactive front-ends, which can prompt for a continuation line rather
+    /// than reporting a syntax error.
+    pub fn is_incomplete(&self) -> bool {
+        match self {
+            ParseErrorKind::EndOfStream { .. } => true,
+            ParseErrorKind::LexError(err) => err.is_incomplete(),
+            _ => false,
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 #[error("parse error at line {line_number}: {kind}")]
 pub struct ParseError {
     pub kind: ParseErrorKind,
     pub line_number: LineNumber,
+    /// The exact location of the error, the start of the offending token if there is one.
+    pub location: SourceLocation,
+    /// The location of the token which opened an unclosed block or bracket, if the error is a
+    /// missing closing token.
+    pub related: Option<SourceLocation>,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, location: SourceLocation) -> Self {
+        Self {
+            kind,
+            line_number: location.line_number,
+            location,
+            related: None,
+        }
+    }
+
+    /// Returns true if more input may fix this error, see [`ParseErrorKind::is_incomplete`].
+    pub fn is_incomplete(&self) -> bool {
+        self.kind.is_incomplete()
+    }
+}
+
+/// Syntax which may be rejected while parsing a chunk.
+///
+/// Restrictions apply to the entire chunk, including the bodies of any functions defined within it.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Restrictions {
+    /// Reject `while` loops.
+    pub no_while: bool,
+    /// Reject `repeat ... until` loops.
+    pub no_repeat: bool,
+    /// Reject `goto` statements and labels.
+    pub no_goto: bool,
+    /// Require the chunk to be a single expression, which is returned as the chunk's only result.
+    pub expression_only: bool,
+}
+
+impl Restrictions {
+    /// Rejects every looping construct which is not bounded when it begins: `while`, `repeat`, and
+    /// `goto`.
+    ///
+    /// Numeric and generic `for` loops are still allowed.
+    pub const NO_UNBOUNDED_LOOPS: Restrictions = Restrictions {
+        no_while: true,
+        no_repeat: true,
+        no_goto: true,
+        expression_only: false,
+    };
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct ParseOptions {
+    /// Collect annotation comments, see [`parse_chunk_with_annotations`].
+    pub annotations: bool,
+    pub restrictions: Restrictions,
 }
 
 pub fn parse_chunk<R, S>(source: R, interner: S) -> Result<Chunk<S::String>, ParseError>
@@ -311,36 +116,217 @@ where
     R: Read,
     S: StringInterner,
 {
-    Parser {
-        lexer: Lexer::new(source, interner),
-        read_buffer: Vec::new(),
-        recursion_guard: Rc::new(()),
-    }
-    .parse_chunk()
+    parse_chunk_with_options(source, interner, ParseOptions::default())
+}
+
+/// Parse a chunk, additionally collecting `---@param` / `---@return` style annotation comments.
+///
+/// Annotations which directly precede a `function` or `local function` statement are attached to
+/// its [`FunctionDefinition`], all other annotation comments are ignored.
+pub fn parse_chunk_with_annotations<R, S>(
+    source: R,
+    interner: S,
+) -> Result<Chunk<S::String>, ParseError>
+where
+    R: Read,
+    S: StringInterner,
+{
+    parse_chunk_with_options(
+        source,
+        interner,
+        ParseOptions {
+            annotations: true,
+            ..Default::default()
+        },
+    )
+}
+
+pub fn parse_chunk_with_options<R, S>(
+    source: R,
+    interner: S,
+    options: ParseOptions,
+) -> Result<Chunk<S::String>, ParseError>
+where
+    R: Read,
+    S: StringInterner,
+{
+    let mut lexer = Lexer::new(source, interner);
+    lexer.set_collect_annotations(options.annotations);
+    Parser::new(lexer, options.restrictions).parse_chunk()
+}
+
+/// The result of [`parse_chunk_recovering`].
+#[derive(Debug)]
+pub struct RecoveredChunk<S> {
+    /// Every statement which could be parsed, or `None` if parsing could not continue past an
+    /// error (such as a lexer error).
+    pub chunk: Option<Chunk<S>>,
+    /// Every error found, in the order they were found.
+    pub errors: Vec<ParseError>,
+}
+
+/// Parse a chunk, continuing past syntax errors rather than stopping at the first one.
+///
+/// When a statement fails to parse, the error is recorded and the tokens up to the start of the
+/// next statement are skipped. The resulting chunk leaves out the statements with errors, and is
+/// only meant for tooling such as editors, which can show every error in a file at once. It should
+/// not be compiled unless `errors` is empty.
+pub fn parse_chunk_recovering<R, S>(
+    source: R,
+    interner: S,
+    options: ParseOptions,
+) -> RecoveredChunk<S::String>
+where
+    R: Read,
+    S: StringInterner,
+{
+    let mut lexer = Lexer::new(source, interner);
+    lexer.set_collect_annotations(options.annotations);
+    let mut parser = Parser::new(lexer, options.restrictions);
+    parser.recover = true;
+    let chunk = parser.parse_chunk();
+    let mut errors = parser.errors;
+    let chunk = match chunk {
+        Ok(chunk) => Some(chunk),
+        Err(err) => {
+            errors.push(err);
+            None
+        }
+    };
+    RecoveredChunk { chunk, errors }
 }
 
 struct Parser<R, S: StringInterner> {
     lexer: Lexer<R, S>,
     read_buffer: Vec<LineAnnotated<Token<S::String>>>,
+    // The location of the start of each token in the read buffer.
+    read_locations: VecDeque<SourceLocation>,
+    // The location of the start of the most recently taken token.
+    last_location: SourceLocation,
+    // The total number of tokens ever taken from the read buffer.
+    tokens_taken: usize,
+    // Annotations collected by the lexer, paired with the index of the token that they precede.
+    annotations: VecDeque<(usize, Vec<Annotation<S::String>>)>,
+    restrictions: Restrictions,
     recursion_guard: Rc<()>,
+    // If set, errors in statements are recorded in `errors` and parsing continues with the next
+    // statement.
+    recover: bool,
+    errors: Vec<ParseError>,
 }
 
 impl<R, S: StringInterner> Parser<R, S>
 where
     R: Read,
 {
+    fn new(lexer: Lexer<R, S>, restrictions: Restrictions) -> Self {
+        Parser {
+            lexer,
+            read_buffer: Vec::new(),
+            read_locations: VecDeque::new(),
+            last_location: SourceLocation::default(),
+            tokens_taken: 0,
+            annotations: VecDeque::new(),
+            restrictions,
+            recursion_guard: Rc::new(()),
+            recover: false,
+            errors: Vec::new(),
+        }
+    }
+
     fn parse_chunk(&mut self) -> Result<Chunk<S::String>, ParseError> {
-        let block = self.parse_block()?;
+        let block = if self.restrictions.expression_only {
+            let line_number = self.get_next()?.line_number;
+            let expression = self.parse_expression()?;
+            Block {
+                statements: Vec::new(),
+                return_statement: Some(LineAnnotated::new(
+                    line_number,
+                    ReturnStatement {
+                        returns: vec![expression],
+                    },
+                )),
+                closed_on: self.lexer.line_number(),
+            }
+        } else {
+            let mut block = self.parse_block()?;
+            // A stray closing token ends the top-level block early. When recovering, the rest of
+            // the chunk is parsed as a continuation of the same block.
+            while self.recover && self.look_ahead(0)?.is_some() {
+                let taken = self.tokens_taken;
+                let err = ParseError::new(
+                    ParseErrorKind::EndOfStream { expected: None },
+                    self.next_location(),
+                );
+                self.recover_from(err, taken)?;
+                let rest = self.parse_block()?;
+                block.statements.extend(rest.statements);
+                block.return_statement = rest.return_statement.or(block.return_statement);
+                block.closed_on = rest.closed_on;
+            }
+            block
+        };
         if !self.look_ahead(0)?.is_none() {
-            Err(ParseError {
-                kind: ParseErrorKind::EndOfStream { expected: None },
-                line_number: self.lexer.line_number(),
-            })
+            Err(ParseError::new(
+                ParseErrorKind::EndOfStream { expected: None },
+                self.next_location(),
+            ))
         } else {
             Ok(Chunk { block })
         }
     }
 
+    // Record an error from a statement which started after `tokens_taken` tokens, and skip ahead
+    // to the next token which may start a statement or end the current block.
+    //
+    // Errors which leave the parser in an unknown state are returned instead.
+    fn recover_from(&mut self, err: ParseError, tokens_taken: usize) -> Result<(), ParseError> {
+        if !self.recover
+            || matches!(
+                err.kind,
+                ParseErrorKind::LexError(_) | ParseErrorKind::RecursionLimit
+            )
+        {
+            return Err(err);
+        }
+
+        let error_line = err.location.line_number;
+        self.errors.push(err);
+
+        // Always make progress, so the same token cannot cause an error forever.
+        if self.tokens_taken == tokens_taken && self.look_ahead(0)?.is_some() {
+            self.take_next()?;
+        }
+
+        while let Some(next) = self.look_ahead(0)? {
+            match &next.inner {
+                Token::If
+                | Token::While
+                | Token::Do
+                | Token::For
+                | Token::Repeat
+                | Token::Function
+                | Token::Local
+                | Token::DoubleColon
+                | Token::Break
+                | Token::Goto
+                | Token::Return
+                | Token::SemiColon
+                | Token::Else
+                | Token::ElseIf
+                | Token::End
+                | Token::Until => break,
+                // Most statements which start with a name are on a line of their own.
+                Token::Name(_) if next.line_number > error_line => break,
+                _ => {
+                    self.take_next()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     fn parse_block(&mut self) -> Result<Block<S::String>, ParseError> {
         let mut statements = Vec::new();
         let mut return_statement = None;
@@ -356,17 +342,25 @@ where
                     self.take_next()?;
                 }
                 Token::Return => {
-                    return_statement = Some(LineAnnotated::new(
-                        next.line_number,
-                        self.parse_return_statement()?,
-                    ));
-                    break;
+                    let line_number = next.line_number;
+                    let taken = self.tokens_taken;
+                    match self.parse_return_statement() {
+                        Ok(statement) => {
+                            return_statement = Some(LineAnnotated::new(line_number, statement));
+                            break;
+                        }
+                        Err(err) => self.recover_from(err, taken)?,
+                    }
                 }
                 _ => {
-                    statements.push(LineAnnotated::new(
-                        next.line_number,
-                        self.parse_statement()?,
-                    ));
+                    let line_number = next.line_number;
+                    let taken = self.tokens_taken;
+                    match self.parse_statement() {
+                        Ok(statement) => {
+                            statements.push(LineAnnotated::new(line_number, statement));
+                        }
+                        Err(err) => self.recover_from(err, taken)?,
+                    }
                 }
             }
         }
@@ -387,22 +381,47 @@ where
     fn parse_statement(&mut self) -> Result<Statement<S::String>, ParseError> {
         let _recursion_guard = self.recursion_guard()?;
 
+        let restrictions = self.restrictions;
+        let next = self.get_next()?;
+        let restricted = match &next.inner {
+            Token::While if restrictions.no_while => Some("while loop"),
+            Token::Repeat if restrictions.no_repeat => Some("repeat loop"),
+            Token::Goto if restrictions.no_goto => Some("goto"),
+            Token::DoubleColon if restrictions.no_goto => Some("label"),
+            _ => None,
+        };
+        if let Some(restricted) = restricted {
+            return Err(ParseError::new(
+                ParseErrorKind::Restricted(restricted),
+                self.next_location(),
+            ));
+        }
+
         let statement = match &self.get_next()?.inner {
             Token::If => Statement::If(self.parse_if_statement()?),
             Token::While => Statement::While(self.parse_while_statement()?),
             Token::Do => {
                 self.take_next()?;
+                let opened_at = self.last_location;
                 let statement = Statement::Do(self.parse_block()?);
-                self.expect_next(Token::End)?;
+                self.expect_closing(Token::End, opened_at)?;
                 statement
             }
             Token::For => Statement::For(self.parse_for_statement()?),
             Token::Repeat => Statement::Repeat(self.parse_repeat_statement()?),
-            Token::Function => Statement::Function(self.parse_function_statement()?),
+            Token::Function => {
+                let annotations = self.take_annotations();
+                let mut statement = self.parse_function_statement()?;
+                statement.definition.annotations = annotations;
+                Statement::Function(statement)
+            }
             Token::Local => {
                 if self.check_ahead(1, Token::Function)? {
+                    let annotations = self.take_annotations();
                     self.take_next()?;
-                    Statement::LocalFunction(self.parse_local_function_statement()?)
+                    let mut statement = self.parse_local_function_statement()?;
+                    statement.definition.annotations = annotations;
+                    Statement::LocalFunction(statement)
                 } else {
                     Statement::LocalStatement(self.parse_local_statement()?)
                 }
@@ -438,6 +457,7 @@ where
 
     fn parse_if_statement(&mut self) -> Result<IfStatement<S::String>, ParseError> {
         self.expect_next(Token::If)?;
+        let opened_at = self.last_location;
         let if_cond = self.parse_expression()?;
         self.expect_next(Token::Then)?;
         let if_block = self.parse_block()?;
@@ -458,7 +478,7 @@ where
             None
         };
 
-        self.expect_next(Token::End)?;
+        self.expect_closing(Token::End, opened_at)?;
 
         Ok(IfStatement {
             if_part: (if_cond, if_block),
@@ -469,16 +489,18 @@ where
 
     fn parse_while_statement(&mut self) -> Result<WhileStatement<S::String>, ParseError> {
         self.expect_next(Token::While)?;
+        let opened_at = self.last_location;
         let condition = self.parse_expression()?;
         self.expect_next(Token::Do)?;
         let block = self.parse_block()?;
-        self.expect_next(Token::End)?;
+        self.expect_closing(Token::End, opened_at)?;
 
         Ok(WhileStatement { condition, block })
     }
 
     fn parse_for_statement(&mut self) -> Result<ForStatement<S::String>, ParseError> {
         self.expect_next(Token::For)?;
+        let opened_at = self.last_location;
         let name = self.expect_name()?.inner;
 
         let next = self.get_next()?;
@@ -497,7 +519,7 @@ where
 
                 self.expect_next(Token::Do)?;
                 let body = self.parse_block()?;
-                self.expect_next(Token::End)?;
+                self.expect_closing(Token::End, opened_at)?;
 
                 Ok(ForStatement::Numeric {
                     name,
@@ -520,7 +542,7 @@ where
 
                 self.expect_next(Token::Do)?;
                 let body = self.parse_block()?;
-                self.expect_next(Token::End)?;
+                self.expect_closing(Token::End, opened_at)?;
 
                 Ok(ForStatement::Generic {
                     names,
@@ -529,26 +551,28 @@ where
                 })
             }
 
-            token => Err(ParseError {
-                kind: ParseErrorKind::Unexpected {
+            token => Err(ParseError::new(
+                ParseErrorKind::Unexpected {
                     unexpected: format!("{:?}", token),
                     expected: "'=' or 'in'".to_owned(),
                 },
-                line_number: next.line_number,
-            }),
+                self.next_location(),
+            )),
         }
     }
 
     fn parse_repeat_statement(&mut self) -> Result<RepeatStatement<S::String>, ParseError> {
         self.expect_next(Token::Repeat)?;
+        let opened_at = self.last_location;
         let body = self.parse_block()?;
-        self.expect_next(Token::Until)?;
+        self.expect_closing(Token::Until, opened_at)?;
         let until = self.parse_expression()?;
         Ok(RepeatStatement { body, until })
     }
 
     fn parse_function_statement(&mut self) -> Result<FunctionStatement<S::String>, ParseError> {
         self.expect_next(Token::Function)?;
+        let opened_at = self.last_location;
 
         let name = self.expect_name()?.inner;
         let mut fields = Vec::new();
@@ -568,7 +592,7 @@ where
             }
         }
 
-        let definition = self.parse_function_definition()?;
+        let definition = self.parse_function_definition(opened_at)?;
 
         Ok(FunctionStatement {
             name,
@@ -582,9 +606,10 @@ where
         &mut self,
     ) -> Result<LocalFunctionStatement<S::String>, ParseError> {
         self.expect_next(Token::Function)?;
+        let opened_at = self.last_location;
 
         let name = self.expect_name()?.inner;
-        let definition = self.parse_function_definition()?;
+        let definition = self.parse_function_definition(opened_at)?;
 
         Ok(LocalFunctionStatement { name, definition })
     }
@@ -623,10 +648,8 @@ where
 
     fn parse_expression_statement(&mut self) -> Result<Statement<S::String>, ParseError> {
         let mut suffixed_expression = self.parse_suffixed_expression()?;
-        let line_number = self
-            .look_ahead(0)?
-            .map(|t| t.line_number)
-            .unwrap_or_else(|| self.lexer.line_number());
+        self.read_ahead(1)?;
+        let location = self.next_location();
         if self.check_ahead(0, Token::Assign)? || self.check_ahead(0, Token::Comma)? {
             let mut targets = Vec::new();
             loop {
@@ -636,20 +659,20 @@ where
                             AssignmentTarget::Field(suffixed_expression, field_suffix)
                         }
                         SuffixPart::Call(_) => {
-                            return Err(ParseError {
-                                kind: ParseErrorKind::AssignToExpression,
-                                line_number,
-                            });
+                            return Err(ParseError::new(
+                                ParseErrorKind::AssignToExpression,
+                                location,
+                            ));
                         }
                     }
                 } else {
                     match suffixed_expression.primary {
                         PrimaryExpression::Name(name) => AssignmentTarget::Name(name),
                         _ => {
-                            return Err(ParseError {
-                                kind: ParseErrorKind::AssignToExpression,
-                                line_number,
-                            })
+                            return Err(ParseError::new(
+                                ParseErrorKind::AssignToExpression,
+                                location,
+                            ))
                         }
                     }
                 };
@@ -678,16 +701,16 @@ where
                         call: call_suffix,
                     }))
                 }
-                SuffixPart::Field(_) => Err(ParseError {
-                    kind: ParseErrorKind::ExpressionNotStatement,
-                    line_number,
-                }),
+                SuffixPart::Field(_) => Err(ParseError::new(
+                    ParseErrorKind::ExpressionNotStatement,
+                    location,
+                )),
             }
         } else {
-            Err(ParseError {
-                kind: ParseErrorKind::ExpressionNotStatement,
-                line_number,
-            })
+            Err(ParseError::new(
+                ParseErrorKind::ExpressionNotStatement,
+                location,
+            ))
         }
     }
 
@@ -766,7 +789,8 @@ where
             Token::LeftBrace => SimpleExpression::TableConstructor(self.parse_table_constructor()?),
             Token::Function => {
                 self.take_next()?;
-                SimpleExpression::Function(self.parse_function_definition()?)
+                let opened_at = self.last_location;
+                SimpleExpression::Function(self.parse_function_definition(opened_at)?)
             }
             _ => SimpleExpression::Suffixed(self.parse_suffixed_expression()?),
         })
@@ -776,18 +800,19 @@ where
         let next = self.take_next()?;
         match next.inner {
             Token::LeftParen => {
+                let opened_at = self.last_location;
                 let expr = self.parse_expression()?;
-                self.expect_next(Token::RightParen)?;
+                self.expect_closing(Token::RightParen, opened_at)?;
                 Ok(PrimaryExpression::GroupedExpression(expr))
             }
             Token::Name(n) => Ok(PrimaryExpression::Name(n)),
-            token => Err(ParseError {
-                kind: ParseErrorKind::Unexpected {
+            token => Err(ParseError::new(
+                ParseErrorKind::Unexpected {
                     unexpected: format!("{:?}", token),
                     expected: "grouped expression or name".to_owned(),
                 },
-                line_number: next.line_number,
-            }),
+                self.last_location,
+            )),
         }
     }
 
@@ -800,17 +825,18 @@ where
             }
             Token::LeftBracket => {
                 self.take_next()?;
+                let opened_at = self.last_location;
                 let expr = self.parse_expression()?;
-                self.expect_next(Token::RightBracket)?;
+                self.expect_closing(Token::RightBracket, opened_at)?;
                 Ok(FieldSuffix::Indexed(expr))
             }
-            token => Err(ParseError {
-                kind: ParseErrorKind::Unexpected {
+            token => Err(ParseError::new(
+                ParseErrorKind::Unexpected {
                     unexpected: format!("{:?}", token),
                     expected: "field or suffix".to_owned(),
                 },
-                line_number: next.line_number,
-            }),
+                self.next_location(),
+            )),
         }
     }
 
@@ -827,12 +853,13 @@ where
         let args = match &next.inner {
             Token::LeftParen => {
                 self.take_next()?;
+                let opened_at = self.last_location;
                 let args = if !matches!(**self.get_next()?, Token::RightParen) {
                     self.parse_expression_list()?
                 } else {
                     Vec::new()
                 };
-                self.expect_next(Token::RightParen)?;
+                self.expect_closing(Token::RightParen, opened_at)?;
                 args
             }
             Token::LeftBrace => vec![Expression {
@@ -848,13 +875,13 @@ where
                 tail: vec![],
             }],
             token => {
-                return Err(ParseError {
-                    kind: ParseErrorKind::Unexpected {
+                return Err(ParseError::new(
+                    ParseErrorKind::Unexpected {
                         unexpected: format!("{:?}", token),
                         expected: "function arguments".to_owned(),
                     },
-                    line_number: next.line_number,
-                });
+                    self.next_location(),
+                ));
             }
         };
 
@@ -872,13 +899,13 @@ where
             Token::Colon | Token::LeftParen | Token::LeftBrace | Token::String(_) => {
                 Ok(SuffixPart::Call(self.parse_call_suffix()?))
             }
-            token => Err(ParseError {
-                kind: ParseErrorKind::Unexpected {
+            token => Err(ParseError::new(
+                ParseErrorKind::Unexpected {
                     unexpected: format!("{:?}", token),
                     expected: "expression suffix".to_owned(),
                 },
-                line_number: next.line_number,
-            }),
+                self.next_location(),
+            )),
         }
     }
 
@@ -902,8 +929,14 @@ where
         Ok(SuffixedExpression { primary, suffixes })
     }
 
-    fn parse_function_definition(&mut self) -> Result<FunctionDefinition<S::String>, ParseError> {
+    // Parse the parameters and body of a function, where `opened_at` is the location of the
+    // `function` keyword.
+    fn parse_function_definition(
+        &mut self,
+        opened_at: SourceLocation,
+    ) -> Result<FunctionDefinition<S::String>, ParseError> {
         self.expect_next(Token::LeftParen)?;
+        let params_opened_at = self.last_location;
 
         let mut parameters = Vec::new();
         let mut has_varargs = false;
@@ -917,13 +950,13 @@ where
                         break;
                     }
                     token => {
-                        return Err(ParseError {
-                            kind: ParseErrorKind::Unexpected {
+                        return Err(ParseError::new(
+                            ParseErrorKind::Unexpected {
                                 unexpected: format!("{:?}", token),
                                 expected: "parameter name or '...'".to_owned(),
                             },
-                            line_number: next.line_number,
-                        });
+                            self.last_location,
+                        ));
                     }
                 }
                 if self.check_ahead(0, Token::Comma)? {
@@ -933,20 +966,22 @@ where
                 }
             }
         }
-        self.expect_next(Token::RightParen)?;
+        self.expect_closing(Token::RightParen, params_opened_at)?;
 
         let body = self.parse_block()?;
-        self.expect_next(Token::End)?;
+        self.expect_closing(Token::End, opened_at)?;
 
         Ok(FunctionDefinition {
             parameters,
             has_varargs,
             body,
+            annotations: Vec::new(),
         })
     }
 
     fn parse_table_constructor(&mut self) -> Result<TableConstructor<S::String>, ParseError> {
         self.expect_next(Token::LeftBrace)?;
+        let opened_at = self.last_location;
         let mut fields = Vec::new();
         loop {
             if self.check_ahead(0, Token::RightBrace)? {
@@ -960,7 +995,7 @@ where
                 _ => break,
             }
         }
-        self.expect_next(Token::RightBrace)?;
+        self.expect_closing(Token::RightBrace, opened_at)?;
         Ok(TableConstructor { fields })
     }
 
@@ -978,8 +1013,9 @@ where
             }
             Token::LeftBracket => {
                 self.take_next()?;
+                let opened_at = self.last_location;
                 let key = self.parse_expression()?;
-                self.expect_next(Token::RightBracket)?;
+                self.expect_closing(Token::RightBracket, opened_at)?;
                 self.expect_next(Token::Assign)?;
                 let value = self.parse_expression()?;
                 return Ok(ConstructorField::Record(RecordKey::Indexed(key), value));
@@ -988,16 +1024,31 @@ where
         })
     }
 
+    // Take the annotations which directly precede the next token in the stream, discarding any
+    // annotations preceding already consumed tokens.
+    fn take_annotations(&mut self) -> Vec<Annotation<S::String>> {
+        while let Some((index, _)) = self.annotations.front() {
+            if *index < self.tokens_taken {
+                self.annotations.pop_front();
+            } else if *index == self.tokens_taken {
+                return self.annotations.pop_front().unwrap().1;
+            } else {
+                break;
+            }
+        }
+        Vec::new()
+    }
+
     // Error if we have more than MAX_RECURSION guards live, otherwise return a new recursion guard
     // (a recursion guard is just an Rc used solely for its live count).
     fn recursion_guard(&self) -> Result<Rc<()>, ParseError> {
         if Rc::strong_count(&self.recursion_guard) < MAX_RECURSION {
             Ok(self.recursion_guard.clone())
         } else {
-            Err(ParseError {
-                kind: ParseErrorKind::RecursionLimit,
-                line_number: self.lexer.line_number(),
-            })
+            Err(ParseError::new(
+                ParseErrorKind::RecursionLimit,
+                self.lexer.location(),
+            ))
         }
     }
 
@@ -1007,10 +1058,10 @@ where
         if let Some(token) = self.read_buffer.get(0) {
             Ok(token)
         } else {
-            Err(ParseError {
-                kind: ParseErrorKind::EndOfStream { expected: None },
-                line_number: self.lexer.line_number(),
-            })
+            Err(ParseError::new(
+                ParseErrorKind::EndOfStream { expected: None },
+                self.lexer.location(),
+            ))
         }
     }
 
@@ -1018,24 +1069,24 @@ where
     fn expect_next(&mut self, token: Token<S::String>) -> Result<LineNumber, ParseError> {
         self.read_ahead(1)?;
         if self.read_buffer.is_empty() {
-            Err(ParseError {
-                kind: ParseErrorKind::EndOfStream {
+            Err(ParseError::new(
+                ParseErrorKind::EndOfStream {
                     expected: Some(format!("{:?}", token)),
                 },
-                line_number: self.lexer.line_number(),
-            })
+                self.lexer.location(),
+            ))
         } else {
-            let next_token = self.read_buffer.remove(0);
+            let next_token = self.pop_next();
             if *next_token == token {
                 Ok(next_token.line_number)
             } else {
-                Err(ParseError {
-                    kind: ParseErrorKind::Unexpected {
+                Err(ParseError::new(
+                    ParseErrorKind::Unexpected {
                         unexpected: format!("{:?}", next_token.inner),
                         expected: format!("{:?}", token),
                     },
-                    line_number: next_token.line_number,
-                })
+                    self.last_location,
+                ))
             }
         }
     }
@@ -1044,22 +1095,22 @@ where
     fn expect_name(&mut self) -> Result<LineAnnotated<S::String>, ParseError> {
         self.read_ahead(1)?;
         if self.read_buffer.is_empty() {
-            Err(ParseError {
-                kind: ParseErrorKind::EndOfStream {
+            Err(ParseError::new(
+                ParseErrorKind::EndOfStream {
                     expected: Some("name".to_owned()),
                 },
-                line_number: self.lexer.line_number(),
-            })
+                self.lexer.location(),
+            ))
         } else {
-            self.read_buffer.remove(0).try_map(|t| match t {
+            self.pop_next().try_map(|t| match t {
                 Token::Name(name) => Ok(name),
-                token => Err(ParseError {
-                    kind: ParseErrorKind::Unexpected {
+                token => Err(ParseError::new(
+                    ParseErrorKind::Unexpected {
                         unexpected: format!("{:?}", token),
                         expected: "name".to_owned(),
                     },
-                    line_number: self.lexer.line_number(),
-                }),
+                    self.last_location,
+                )),
             })
         }
     }
@@ -1068,22 +1119,22 @@ where
     fn expect_string(&mut self) -> Result<LineAnnotated<S::String>, ParseError> {
         self.read_ahead(1)?;
         if self.read_buffer.is_empty() {
-            Err(ParseError {
-                kind: ParseErrorKind::EndOfStream {
+            Err(ParseError::new(
+                ParseErrorKind::EndOfStream {
                     expected: Some("string".to_owned()),
                 },
-                line_number: self.lexer.line_number(),
-            })
+                self.lexer.location(),
+            ))
         } else {
-            self.read_buffer.remove(0).try_map(|t| match t {
+            self.pop_next().try_map(|t| match t {
                 Token::String(string) => Ok(string),
-                token => Err(ParseError {
-                    kind: ParseErrorKind::Unexpected {
+                token => Err(ParseError::new(
+                    ParseErrorKind::Unexpected {
                         unexpected: format!("{:?}", token),
                         expected: "string".to_owned(),
                     },
-                    line_number: self.lexer.line_number(),
-                }),
+                    self.last_location,
+                )),
             })
         }
     }
@@ -1092,15 +1143,44 @@ where
     fn take_next(&mut self) -> Result<LineAnnotated<Token<S::String>>, ParseError> {
         self.read_ahead(1)?;
         if self.read_buffer.is_empty() {
-            Err(ParseError {
-                kind: ParseErrorKind::EndOfStream { expected: None },
-                line_number: self.lexer.line_number(),
-            })
+            Err(ParseError::new(
+                ParseErrorKind::EndOfStream { expected: None },
+                self.lexer.location(),
+            ))
         } else {
-            Ok(self.read_buffer.remove(0))
+            Ok(self.pop_next())
         }
     }
 
+    // Remove the first token from the read buffer, which must not be empty.
+    fn pop_next(&mut self) -> LineAnnotated<Token<S::String>> {
+        self.tokens_taken += 1;
+        self.last_location = self.read_locations.pop_front().unwrap();
+        self.read_buffer.remove(0)
+    }
+
+    // The location of the next token in the stream, or the end of the stream if there are no more
+    // tokens. The next token must already have been read if there is one.
+    fn next_location(&self) -> SourceLocation {
+        self.read_locations
+            .front()
+            .copied()
+            .unwrap_or_else(|| self.lexer.location())
+    }
+
+    // Consumes the next token which should close a block or bracket opened at `opened_at`,
+    // otherwise errors with `opened_at` as the related location.
+    fn expect_closing(
+        &mut self,
+        token: Token<S::String>,
+        opened_at: SourceLocation,
+    ) -> Result<LineNumber, ParseError> {
+        self.expect_next(token).map_err(|mut err| {
+            err.related = Some(opened_at);
+            err
+        })
+    }
+
     // Return the nth token ahead in the stream, if it is not past the end.
     fn look_ahead(
         &mut self,
@@ -1125,17 +1205,24 @@ where
     // possible).
     fn read_ahead(&mut self, n: usize) -> Result<(), ParseError> {
         while self.read_buffer.len() <= n {
-            self.lexer.skip_whitespace().map_err(|e| ParseError {
-                kind: ParseErrorKind::LexError(e),
-                line_number: self.lexer.line_number(),
-            })?;
+            self.lexer
+                .skip_whitespace()
+                .map_err(|e| ParseError::new(ParseErrorKind::LexError(e), self.lexer.location()))?;
             let line_number = self.lexer.line_number();
-            if let Some(token) = self.lexer.read_token().map_err(|e| ParseError {
-                kind: ParseErrorKind::LexError(e),
-                line_number: self.lexer.line_number(),
-            })? {
+            let location = self.lexer.location();
+            if let Some(token) = self
+                .lexer
+                .read_token()
+                .map_err(|e| ParseError::new(ParseErrorKind::LexError(e), location))?
+            {
+                let annotations = self.lexer.take_annotations();
+                if !annotations.is_empty() {
+                    self.annotations
+                        .push_back((self.tokens_taken + self.read_buffer.len(), annotations));
+                }
                 self.read_buffer
                     .push(LineAnnotated::new(line_number, token));
+                self.read_locations.push_back(location);
             } else {
                 break;
             }
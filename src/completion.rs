@@ -0,0 +1,221 @@
+use crate::{Context, MetaMethod, String, Table, Value};
+
+/// Options controlling how [`complete`] resolves a chain of identifiers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CompletionOptions {
+    /// If true, then when a key is missing from a table (or the value being indexed is a userdata),
+    /// follow `__index` metafields that are themselves *tables*.
+    ///
+    /// `__index` functions are never called, completion must never run Lua code.
+    pub follow_index_tables: bool,
+    /// The maximum number of `__index` tables to follow for a single lookup, guards against
+    /// `__index` cycles.
+    pub max_index_chain: usize,
+}
+
+impl Default for CompletionOptions {
+    fn default() -> Self {
+        Self {
+            follow_index_tables: true,
+            max_index_chain: 32,
+        }
+    }
+}
+
+/// The result of [`complete`].
+#[derive(Debug, Clone)]
+pub struct Completions<'gc> {
+    /// The byte offset in the given prefix where the identifier being completed starts.
+    ///
+    /// Replacing `prefix[start..]` with any of the candidates produces a completed expression.
+    pub start: usize,
+    /// All matching keys, sorted and de-duplicated.
+    pub candidates: Vec<String<'gc>>,
+}
+
+impl<'gc> Completions<'gc> {
+    /// Returns true if there are no completion candidates.
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+}
+
+/// Find completion candidates for the trailing identifier chain in a source prefix.
+///
+/// Given a prefix like `player.inv`, this resolves `player` in `env` and returns every string key
+/// of the resulting value which starts with `inv`. A trailing `:` (as in `player:ad`) only returns
+/// keys which hold functions.
+///
+/// This is entirely side-effect free: tables are accessed "raw" and no metamethods are ever
+/// called, so it is safe to use on untrusted state (for example, for REPL or editor completion). If
+/// any part of the chain cannot be resolved this way, no candidates are returned.
+pub fn complete<'gc>(
+    ctx: Context<'gc>,
+    env: Value<'gc>,
+    prefix: &[u8],
+    options: CompletionOptions,
+) -> Completions<'gc> {
+    let (start, path, partial, is_method) = split_chain(prefix);
+    let mut completions = Completions {
+        start,
+        candidates: Vec::new(),
+    };
+
+    let Some(path) = path else {
+        return completions;
+    };
+
+    let mut value = env;
+    for name in path {
+        value = lookup(ctx, value, ctx.intern(name).into(), options);
+        if value.is_nil() {
+            return completions;
+        }
+    }
+
+    let mut candidates = Vec::new();
+    let mut current = Some(value);
+    let mut chain_len = 0;
+    while let Some(v) = current.take() {
+        if let Value::Table(t) = v {
+            for (key, val) in t {
+                if let Value::String(key) = key {
+                    if key.as_bytes().starts_with(partial)
+                        && is_identifier(key.as_bytes())
+                        && (!is_method || matches!(val, Value::Function(_)))
+                    {
+                        candidates.push(key);
+                    }
+                }
+            }
+        }
+
+        if options.follow_index_tables && chain_len < options.max_index_chain {
+            chain_len += 1;
+            current = index_table(ctx, v).map(Value::Table);
+        }
+    }
+
+    candidates.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+    candidates.dedup();
+    completions.candidates = candidates;
+    completions
+}
+
+/// Splits a prefix into (start of the partial word, resolved path, partial word, is method).
+///
+/// The path is `None` if the trailing chain is not a valid sequence of identifiers.
+fn split_chain(prefix: &[u8]) -> (usize, Option<Vec<&[u8]>>, &[u8], bool) {
+    fn ident_start(s: &[u8], end: usize) -> usize {
+        let mut i = end;
+        while i > 0 && (s[i - 1].is_ascii_alphanumeric() || s[i - 1] == b'_') {
+            i -= 1;
+        }
+        i
+    }
+
+    let start = ident_start(prefix, prefix.len());
+    let partial = &prefix[start..];
+    if partial.first().is_some_and(|c| c.is_ascii_digit()) {
+        return (start, None, partial, false);
+    }
+
+    let mut path = Vec::new();
+    let mut is_method = false;
+    let mut end = start;
+    while end > 0 && (prefix[end - 1] == b'.' || prefix[end - 1] == b':') {
+        // Only the last separator may be a method call.
+        if prefix[end - 1] == b':' {
+            if !path.is_empty() || is_method {
+                return (start, None, partial, false);
+            }
+            is_method = true;
+        }
+
+        let name_end = end - 1;
+        let name_start = ident_start(prefix, name_end);
+        let name = &prefix[name_start..name_end];
+        if !is_identifier(name) {
+            return (start, None, partial, false);
+        }
+        path.push(name);
+        end = name_start;
+    }
+    path.reverse();
+
+    (start, Some(path), partial, is_method)
+}
+
+fn lookup<'gc>(
+    ctx: Context<'gc>,
+    value: Value<'gc>,
+    key: Value<'gc>,
+    options: CompletionOptions,
+) -> Value<'gc> {
+    let mut value = value;
+    for _ in 0..=options.max_index_chain {
+        if let Value::Table(t) = value {
+            let v = t.get_raw(key);
+            if !v.is_nil() {
+                return v;
+            }
+        }
+
+        if !options.follow_index_tables {
+            break;
+        }
+
+        match index_table(ctx, value) {
+            Some(t) => value = t.into(),
+            None => break,
+        }
+    }
+    Value::Nil
+}
+
+fn index_table<'gc>(ctx: Context<'gc>, value: Value<'gc>) -> Option<Table<'gc>> {
+    let mt = match value {
+        Value::Table(t) => t.metatable(),
+        Value::UserData(u) => u.metatable(),
+        _ => None,
+    }?;
+
+    match mt.get_value(ctx, MetaMethod::Index) {
+        Value::Table(t) => Some(t),
+        _ => None,
+    }
+}
+
+fn is_identifier(s: &[u8]) -> bool {
+    const KEYWORDS: &[&[u8]] = &[
+        b"and",
+        b"break",
+        b"do",
+        b"else",
+        b"elseif",
+        b"end",
+        b"false",
+        b"for",
+        b"function",
+        b"goto",
+        b"if",
+        b"in",
+        b"local",
+        b"nil",
+        b"not",
+        b"or",
+        b"repeat",
+        b"return",
+        b"then",
+        b"true",
+        b"until",
+        b"while",
+    ];
+
+    match s.first() {
+        Some(c) if c.is_ascii_alphabetic() || *c == b'_' => {}
+        _ => return false,
+    }
+
+    s.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'_') && !KEYWORDS.iter().any(|k| *k == s)
+}
@@ -2,7 +2,10 @@ use std::hash::{Hash, Hasher};
 
 use gc_arena::Collect;
 
-use crate::compiler::string_utils::{read_float, read_integer, trim_whitespace};
+use crate::{
+    compiler::string_utils::{read_float, read_integer, trim_whitespace},
+    value::f64_to_i64_exact,
+};
 
 #[derive(Debug, Copy, Clone, Collect)]
 #[collect(no_drop)]
@@ -81,13 +84,7 @@ impl<S: AsRef<[u8]>> Constant<S> {
     pub fn to_integer(&self) -> Option<i64> {
         match self.to_numeric() {
             Some(Self::Integer(a)) => Some(a),
-            Some(Self::Number(a)) => {
-                if ((a as i64) as f64) == a {
-                    Some(a as i64)
-                } else {
-                    None
-                }
-            }
+            Some(Self::Number(a)) => f64_to_i64_exact(a),
             _ => None,
         }
     }
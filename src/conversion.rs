@@ -1,4 +1,11 @@
-use std::{array, iter, ops, string::String as StdString};
+use std::{
+    array,
+    cell::Cell,
+    collections::{BTreeMap, HashMap},
+    hash::{BuildHasher, Hash},
+    iter, ops,
+    string::String as StdString,
+};
 
 use crate::{
     Callback, Closure, Context, Function, String, Table, Thread, TypeError, UserData, Value,
@@ -153,6 +160,42 @@ where
     }
 }
 
+// Entries with a key that cannot be a table key (nil or NaN) are skipped.
+fn table_from_entries<'gc, K, V>(
+    ctx: Context<'gc>,
+    entries: impl IntoIterator<Item = (K, V)>,
+) -> Value<'gc>
+where
+    K: IntoValue<'gc>,
+    V: IntoValue<'gc>,
+{
+    let table = Table::new(&ctx);
+    for (k, v) in entries {
+        let _ = table.set(ctx, k, v);
+    }
+    table.into()
+}
+
+impl<'gc, K, V, S> IntoValue<'gc> for HashMap<K, V, S>
+where
+    K: IntoValue<'gc>,
+    V: IntoValue<'gc>,
+{
+    fn into_value(self, ctx: Context<'gc>) -> Value<'gc> {
+        table_from_entries(ctx, self)
+    }
+}
+
+impl<'gc, K, V> IntoValue<'gc> for BTreeMap<K, V>
+where
+    K: IntoValue<'gc>,
+    V: IntoValue<'gc>,
+{
+    fn into_value(self, ctx: Context<'gc>) -> Value<'gc> {
+        table_from_entries(ctx, self)
+    }
+}
+
 pub trait FromValue<'gc>: Sized {
     fn from_value(ctx: Context<'gc>, value: Value<'gc>) -> Result<Self, TypeError>;
 }
@@ -181,10 +224,42 @@ impl<'gc, T: FromValue<'gc>> FromValue<'gc> for Vec<T> {
                 .map(|i| table.get(ctx, i))
                 .collect()
         } else {
-            Err(TypeError {
-                expected: "sequence",
-                found: value.type_name(),
-            })
+            Err(TypeError::new("sequence", value))
+        }
+    }
+}
+
+impl<'gc, K, V, S> FromValue<'gc> for HashMap<K, V, S>
+where
+    K: FromValue<'gc> + Eq + Hash,
+    V: FromValue<'gc>,
+    S: BuildHasher + Default,
+{
+    fn from_value(ctx: Context<'gc>, value: Value<'gc>) -> Result<Self, TypeError> {
+        if let Value::Table(table) = value {
+            table
+                .iter()
+                .map(|(k, v)| Ok((K::from_value(ctx, k)?, V::from_value(ctx, v)?)))
+                .collect()
+        } else {
+            Err(TypeError::new("table", value))
+        }
+    }
+}
+
+impl<'gc, K, V> FromValue<'gc> for BTreeMap<K, V>
+where
+    K: FromValue<'gc> + Ord,
+    V: FromValue<'gc>,
+{
+    fn from_value(ctx: Context<'gc>, value: Value<'gc>) -> Result<Self, TypeError> {
+        if let Value::Table(table) = value {
+            table
+                .iter()
+                .map(|(k, v)| Ok((K::from_value(ctx, k)?, V::from_value(ctx, v)?)))
+                .collect()
+        } else {
+            Err(TypeError::new("table", value))
         }
     }
 }
@@ -198,10 +273,7 @@ impl<'gc, T: FromValue<'gc>, const N: usize> FromValue<'gc> for [T; N] {
             }
             Ok(res.map(|r| r.unwrap()))
         } else {
-            Err(TypeError {
-                expected: "sequence",
-                found: value.type_name(),
-            })
+            Err(TypeError::new("sequence", value))
         }
     }
 }
@@ -221,13 +293,12 @@ macro_rules! impl_int_from {
                             Err(TypeError {
                                 expected: stringify!($i),
                                 found: "integer out of range",
+                                found_name: None,
+                                position: None,
                             })
                         }
                     } else {
-                        Err(TypeError {
-                            expected: stringify!($i),
-                            found: value.type_name(),
-                        })
+                        Err(TypeError::new(stringify!($i), value))
                     }
                 }
             }
@@ -247,10 +318,7 @@ macro_rules! impl_float_from {
                     if let Some(n) = value.to_number() {
                         Ok(n as $f)
                     } else {
-                        Err(TypeError {
-                            expected: stringify!($f),
-                            found: value.type_name(),
-                        })
+                        Err(TypeError::new(stringify!($f), value))
                     }
                 }
             }
@@ -270,10 +338,7 @@ macro_rules! impl_from {
                     match value {
                         Value::$e(a) => Ok(a),
                         _ => {
-                            Err(TypeError {
-                                expected: stringify!($e),
-                                found: value.type_name(),
-                            })
+                            Err(TypeError::new(stringify!($e), value))
                         }
                     }
                 }
@@ -296,11 +361,10 @@ impl<'gc> FromValue<'gc> for Closure<'gc> {
             Value::Function(Function::Callback(_)) => Err(TypeError {
                 expected: "Closure",
                 found: "Callback",
+                found_name: None,
+                position: None,
             }),
-            _ => Err(TypeError {
-                expected: "Closure",
-                found: value.type_name(),
-            }),
+            _ => Err(TypeError::new("Closure", value)),
         }
     }
 }
@@ -312,21 +376,19 @@ impl<'gc> FromValue<'gc> for Callback<'gc> {
             Value::Function(Function::Closure(_)) => Err(TypeError {
                 expected: "Callback",
                 found: "Closure",
+                found_name: None,
+                position: None,
             }),
-            _ => Err(TypeError {
-                expected: "Callback",
-                found: value.type_name(),
-            }),
+            _ => Err(TypeError::new("Callback", value)),
         }
     }
 }
 
 impl<'gc> FromValue<'gc> for String<'gc> {
     fn from_value(ctx: Context<'gc>, value: Value<'gc>) -> Result<Self, TypeError> {
-        value.into_string(ctx).ok_or_else(|| TypeError {
-            expected: "string",
-            found: value.type_name(),
-        })
+        value
+            .into_string(ctx)
+            .ok_or_else(|| TypeError::new("string", value))
     }
 }
 
@@ -336,6 +398,8 @@ impl<'gc> FromValue<'gc> for StdString {
         let str = str.to_str().map_err(|_| TypeError {
             expected: "UTF-8 String",
             found: "non-UTF-8 String",
+            found_name: None,
+            position: None,
         })?;
         Ok(str.to_owned())
     }
@@ -451,29 +515,46 @@ impl<I, T: FromIterator<I>> FromIterator<I> for Variadic<T> {
 
 impl<'gc, T: IntoIterator> IntoMultiValue<'gc> for Variadic<T>
 where
-    T::Item: IntoValue<'gc>,
+    T::Item: IntoMultiValue<'gc>,
 {
     fn into_multi_value(self, ctx: Context<'gc>) -> impl Iterator<Item = Value<'gc>> {
-        self.0.into_iter().map(move |v| v.into_value(ctx))
+        self.0
+            .into_iter()
+            .flat_map(move |v| v.into_multi_value(ctx))
     }
 }
 
 impl<'a, 'gc, T> IntoMultiValue<'gc> for &'a Variadic<T>
 where
     &'a T: IntoIterator,
-    <&'a T as IntoIterator>::Item: IntoValue<'gc>,
+    <&'a T as IntoIterator>::Item: IntoMultiValue<'gc>,
 {
     fn into_multi_value(self, ctx: Context<'gc>) -> impl Iterator<Item = Value<'gc>> {
-        self.0.into_iter().map(move |v| v.into_value(ctx))
+        self.0
+            .into_iter()
+            .flat_map(move |v| v.into_multi_value(ctx))
     }
 }
 
-impl<'gc, I: FromValue<'gc>> FromMultiValue<'gc> for Variadic<Vec<I>> {
+/// Every item takes as many values as it needs, so a `Variadic<Vec<(K, V)>>` takes values in
+/// pairs. If the values run out part way through an item, the rest of that item is converted
+/// from `nil`.
+impl<'gc, I: FromMultiValue<'gc>> FromMultiValue<'gc> for Variadic<Vec<I>> {
     fn from_multi_value(
         ctx: Context<'gc>,
         values: impl Iterator<Item = Value<'gc>>,
     ) -> Result<Self, TypeError> {
-        values.map(|v| I::from_value(ctx, v)).collect()
+        let taken = Cell::new(0);
+        let mut values = values.inspect(|_| taken.set(taken.get() + 1));
+        let mut items = Vec::new();
+        // Every item takes at least one value, even if it converts from no values at all.
+        while let Some(first) = values.next() {
+            let start = taken.get() - 1;
+            let item = I::from_multi_value(ctx, iter::once(first).chain(&mut values))
+                .map_err(|err| offset_error(err, start, taken.get()))?;
+            items.push(item);
+        }
+        Ok(Self(items))
     }
 }
 
@@ -484,13 +565,25 @@ impl<'gc, I: FromValue<'gc>, const N: usize> FromMultiValue<'gc> for Variadic<[I
     ) -> Result<Self, TypeError> {
         let mut res: [Option<I>; N] = array::from_fn(|_| None);
         for i in 0..N {
-            res[i] = Some(I::from_value(ctx, values.next().unwrap_or(Value::Nil))?);
+            res[i] = Some(
+                I::from_value(ctx, values.next().unwrap_or(Value::Nil))
+                    .map_err(|err| err.at_position(i + 1))?,
+            );
         }
 
         Ok(Self(res.map(|v| v.unwrap())))
     }
 }
 
+// Set the position of an error from converting the part of a sequence of values which begins after
+// `start` values, where `taken` values have been taken in total. An error without a position is
+// for the first value of that part, or for the missing value after it if there were none.
+fn offset_error(mut err: TypeError, start: usize, taken: usize) -> TypeError {
+    let position = err.position.unwrap_or((taken - start).max(1));
+    err.position = Some(start + position);
+    err
+}
+
 macro_rules! impl_tuple {
     ($($name:ident),* $(,)?) => (
         impl<'gc, $($name,)*> IntoMultiValue<'gc> for ($($name,)*)
@@ -518,9 +611,15 @@ macro_rules! impl_tuple {
             #[allow(non_snake_case)]
             fn from_multi_value(
                 ctx: Context<'gc>,
-                mut values: impl Iterator<Item = Value<'gc>>,
+                values: impl Iterator<Item = Value<'gc>>,
             ) -> Result<Self, TypeError> {
-                $(let $name = FromMultiValue::from_multi_value(ctx, &mut values)?;)*
+                let taken = Cell::new(0);
+                let mut values = values.inspect(|_| taken.set(taken.get() + 1));
+                $(
+                    let start = taken.get();
+                    let $name = FromMultiValue::from_multi_value(ctx, &mut values)
+                        .map_err(|err| offset_error(err, start, taken.get()))?;
+                )*
                 Ok(($($name,)*))
             }
         }
@@ -1,20 +1,67 @@
-use std::{error::Error as StdError, fmt, string::String as StdString, sync::Arc};
+use std::{cell::Cell, error::Error as StdError, fmt, string::String as StdString, sync::Arc};
 
 use gc_arena::{Collect, Gc, Rootable};
 use thiserror::Error;
 
 use crate::{
-    Callback, CallbackReturn, Context, FromValue, Function, IntoValue, MetaMethod, Singleton,
-    Table, UserData, Value,
+    meta_ops::{self, MetaResult},
+    Callback, CallbackReturn, Context, Executor, FromValue, Fuel, Function, IntoValue, MetaMethod,
+    Singleton, Table, UserData, Value,
 };
 
-#[derive(Debug, Clone, Copy, Error)]
-#[error("type error, expected {expected}, found {found}")]
+/// An error converting a value into a Rust type.
+///
+/// Construct this with [`TypeError::new`], more fields may be added in the future.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct TypeError {
     pub expected: &'static str,
     pub found: &'static str,
+    /// The `__name` metafield of the found value, if it is a userdata whose metatable has one.
+    pub found_name: Option<Box<str>>,
+    /// The 1-based position of the bad value, when it was one of several values converted with
+    /// [`FromMultiValue`](crate::FromMultiValue), such as the arguments of a callback.
+    pub position: Option<usize>,
+}
+
+impl TypeError {
+    /// Construct a `TypeError` for an unexpected value, recording its type name and (for userdata)
+    /// its `__name`.
+    pub fn new(expected: &'static str, found: Value<'_>) -> Self {
+        TypeError {
+            expected,
+            found: found.type_name(),
+            found_name: match found {
+                Value::UserData(ud) => ud.name().map(|n| n.display_lossy().to_string().into()),
+                _ => None,
+            },
+            position: None,
+        }
+    }
+
+    /// Set the position of the bad value, see [`TypeError::position`].
+    pub fn at_position(mut self, position: usize) -> Self {
+        self.position = Some(position);
+        self
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "type error")?;
+        if let Some(position) = self.position {
+            write!(f, " in value #{position}")?;
+        }
+        write!(f, ", expected {}, found ", self.expected)?;
+        match &self.found_name {
+            Some(name) => write!(f, "{name}"),
+            None => write!(f, "{}", self.found),
+        }
+    }
 }
 
+impl StdError for TypeError {}
+
 /// An error raised directly from Lua which contains a Lua value.
 ///
 /// Any [`Value`] can be raised as an error and it will be contained here.
@@ -38,6 +85,73 @@ impl<'gc> LuaError<'gc> {
     pub fn to_extern(self) -> ExternLuaError {
         self.into()
     }
+
+    /// Like [`LuaError::to_extern`], but a table or userdata error with a `__tostring` metamethod
+    /// is converted to [`ExternLuaError::Described`] with the result of calling it.
+    ///
+    /// The metamethod is run to completion on a new [`Executor`] with a limited amount of fuel. If
+    /// it errors, yields, runs out of fuel, or does not return a string, or if this is called
+    /// again from within the metamethod, then the error is converted as by `to_extern`.
+    ///
+    /// A table or userdata without a `__tostring` metamethod but with a `__name` metafield is
+    /// described as `"name: 0x..."`, like the `tostring` builtin.
+    pub fn to_extern_with(self, ctx: Context<'gc>) -> ExternLuaError {
+        describe(ctx, self.0).unwrap_or_else(|| self.into())
+    }
+}
+
+// Call the `__tostring` metamethod of a table or userdata error to describe it.
+fn describe<'gc>(ctx: Context<'gc>, value: Value<'gc>) -> Option<ExternLuaError> {
+    const DESCRIBE_FUEL: i32 = 1 << 16;
+
+    #[derive(Default, Collect)]
+    #[collect(require_static)]
+    struct Describing(Cell<bool>);
+
+    let (type_name, ptr) = match value {
+        Value::Table(t) => ("table", Gc::as_ptr(t.into_inner()) as *const ()),
+        Value::UserData(u) => ("userdata", Gc::as_ptr(u.into_inner()) as *const ()),
+        _ => return None,
+    };
+
+    let call = match meta_ops::tostring(ctx, value).ok()? {
+        MetaResult::Call(call) => call,
+        MetaResult::Value(_) => {
+            let name = match value {
+                Value::Table(t) => t.name(),
+                Value::UserData(u) => u.name(),
+                _ => None,
+            };
+            name?;
+            return Some(ExternLuaError::Described {
+                type_name,
+                ptr,
+                message: value.display().to_string(),
+            });
+        }
+    };
+
+    // Errors raised while describing an error are not described themselves, which prevents
+    // unbounded recursion.
+    let describing = &ctx.singleton::<Rootable![Describing]>().0;
+    if describing.replace(true) {
+        return None;
+    }
+
+    let executor = Executor::start(ctx, call.function, call.args);
+    let finished = executor.step(ctx, &mut Fuel::with(DESCRIBE_FUEL));
+    describing.set(false);
+    if !finished.ok()? {
+        return None;
+    }
+
+    let message = executor.take_result::<Value>(ctx).ok()?.ok()?;
+    let message = message.into_string(ctx)?;
+    Some(ExternLuaError::Described {
+        type_name,
+        ptr,
+        message: message.display_lossy().to_string(),
+    })
 }
 
 /// A [`LuaError`] that is not bound to the GC context.
@@ -65,6 +179,14 @@ pub enum ExternLuaError {
     Thread(*const ()),
     #[error("<userdata {0:p}>")]
     UserData(*const ()),
+    /// A table or userdata described by its `__tostring` metamethod, see
+    /// [`LuaError::to_extern_with`].
+    #[error("{message}")]
+    Described {
+        type_name: &'static str,
+        ptr: *const (),
+        message: StdString,
+    },
 }
 
 impl<'gc> From<LuaError<'gc>> for ExternLuaError {
@@ -249,6 +371,18 @@ impl<'gc> Error<'gc> {
         }
     }
 
+    /// If this is a Lua error holding a static userdata of type `T`, returns a reference to the
+    /// held value.
+    ///
+    /// This allows Rust to get back a structured error object raised by a script, for example one
+    /// fetched from a [`StashedError`](crate::StashedError).
+    pub fn downcast_static<T: 'static>(&self) -> Option<&'gc T> {
+        match self {
+            Error::Lua(LuaError(Value::UserData(ud))) => ud.downcast_static::<T>().ok(),
+            _ => None,
+        }
+    }
+
     pub fn to_extern(&self) -> ExternError {
         self.clone().into_extern()
     }
@@ -256,6 +390,15 @@ impl<'gc> Error<'gc> {
     pub fn into_extern(self) -> ExternError {
         self.into()
     }
+
+    /// Like [`Error::into_extern`], but Lua errors are described by their `__tostring` metamethod
+    /// if they have one, see [`LuaError::to_extern_with`].
+    pub fn into_extern_with(self, ctx: Context<'gc>) -> ExternError {
+        match self {
+            Error::Lua(err) => err.to_extern_with(ctx).into(),
+            Error::Runtime(err) => err.into(),
+        }
+    }
 }
 
 impl<'gc> IntoValue<'gc> for Error<'gc> {
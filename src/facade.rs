@@ -0,0 +1,158 @@
+use thiserror::Error;
+
+use crate::{
+    Context, Executor, ExternError, FromMultiValue, IntoMultiValue, Lua, StashedExecutor,
+    StashedTable, Table, Value,
+};
+
+#[derive(Debug, Error)]
+pub enum FacadeError {
+    #[error("module has no function '{0}'")]
+    MissingFunction(&'static str),
+    #[error("call to module function '{name}' failed: {error}")]
+    Runtime {
+        name: &'static str,
+        #[source]
+        error: ExternError,
+    },
+}
+
+/// Calls functions in a Lua module table from Rust.
+///
+/// This is the mirror image of a [`Callback`](crate::Callback): rather than scripts calling into
+/// Rust, Rust calls hooks which are defined by a script, converting arguments and results and
+/// running each call to completion on an executor owned by the `Facade`.
+///
+/// Functions are looked up by name on every call, so a module which replaces its functions (for
+/// example when it is reloaded) is always called through the current ones. Functions are called
+/// with only the given arguments, there is no implicit `self`.
+///
+/// Usually this is used through the [`facade!`](crate::facade!) macro, which generates a typed
+/// wrapper with one method per module function.
+#[derive(Clone)]
+pub struct Facade {
+    module: StashedTable,
+    executor: StashedExecutor,
+}
+
+impl Facade {
+    pub fn new<'gc>(ctx: Context<'gc>, module: Table<'gc>) -> Self {
+        Self {
+            module: ctx.stash(module),
+            executor: ctx.stash(Executor::new(ctx)),
+        }
+    }
+
+    pub fn module(&self) -> &StashedTable {
+        &self.module
+    }
+
+    /// Returns true if the module has a function with the given name.
+    pub fn has_function(&self, lua: &mut Lua, name: &'static str) -> bool {
+        lua.enter(|ctx| {
+            matches!(
+                ctx.fetch(&self.module).get_value(ctx, name),
+                Value::Function(_)
+            )
+        })
+    }
+
+    /// Call the module function `name` with the given arguments and convert its results to `R`.
+    pub fn call<A, R>(&self, lua: &mut Lua, name: &'static str, args: A) -> Result<R, FacadeError>
+    where
+        A: for<'gc> IntoMultiValue<'gc>,
+        R: for<'gc> FromMultiValue<'gc>,
+    {
+        lua.enter(|ctx| match ctx.fetch(&self.module).get_value(ctx, name) {
+            Value::Function(function) => {
+                ctx.fetch(&self.executor).restart(ctx, function, args);
+                Ok(())
+            }
+            _ => Err(FacadeError::MissingFunction(name)),
+        })?;
+
+        lua.execute::<R>(&self.executor)
+            .map_err(|error| FacadeError::Runtime { name, error })
+    }
+}
+
+/// Generate a typed wrapper for calling the functions of a Lua module.
+///
+/// The wrapper is declared like a trait, and becomes a struct wrapping a [`Facade`] with one method
+/// per declared function. Each method takes the `Lua` instance as an extra first argument, calls
+/// the module function of the same name, and converts the results to the declared return type
+/// (or `()` if there is none).
+///
+/// ```
+/// # use piccolo::{facade, Closure, Executor, Lua};
+/// facade! {
+///     /// Hooks implemented by a quest script.
+///     pub trait Quest {
+///         fn on_start(&self, id: i64) -> bool;
+///         fn describe(&self, id: i64, verbose: bool) -> String;
+///         fn on_finish(&self);
+///     }
+/// }
+///
+/// let mut lua = Lua::core();
+/// let executor = lua.enter(|ctx| {
+///     let closure = Closure::load(
+///         ctx,
+///         None,
+///         &br#"
+///             local M = {}
+///             function M.on_start(id) return id > 0 end
+///             function M.describe(id, verbose) return "quest " .. id end
+///             function M.on_finish() end
+///             quest = M
+///         "#[..],
+///     )
+///     .unwrap();
+///     ctx.stash(Executor::start(ctx, closure.into(), ()))
+/// });
+/// lua.execute::<()>(&executor).unwrap();
+///
+/// let quest = lua.enter(|ctx| Quest::new(ctx, ctx.get_global("quest").unwrap()));
+/// assert!(quest.on_start(&mut lua, 3).unwrap());
+/// assert_eq!(quest.describe(&mut lua, 3, false).unwrap(), "quest 3");
+/// quest.on_finish(&mut lua).unwrap();
+/// ```
+#[macro_export]
+macro_rules! facade {
+    (@return) => { () };
+    (@return $ret:ty) => { $ret };
+    (
+        $(#[$meta:meta])*
+        $vis:vis trait $name:ident {
+            $(
+                $(#[$fn_meta:meta])*
+                fn $method:ident(&self $(, $arg:ident : $arg_ty:ty)* $(,)?) $(-> $ret:ty)?;
+            )*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone)]
+        $vis struct $name($crate::facade::Facade);
+
+        impl $name {
+            $vis fn new<'gc>(ctx: $crate::Context<'gc>, module: $crate::Table<'gc>) -> Self {
+                Self($crate::facade::Facade::new(ctx, module))
+            }
+
+            $vis fn facade(&self) -> &$crate::facade::Facade {
+                &self.0
+            }
+
+            $(
+                $(#[$fn_meta])*
+                $vis fn $method(
+                    &self,
+                    lua: &mut $crate::Lua,
+                    $($arg: $arg_ty,)*
+                ) -> ::std::result::Result<$crate::facade!(@return $($ret)?), $crate::facade::FacadeError> {
+                    self.0.call(lua, ::std::stringify!($method), ($($arg,)*))
+                }
+            )*
+        }
+    };
+}
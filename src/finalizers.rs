@@ -1,6 +1,6 @@
 use gc_arena::{lock::RefLock, Collect, Finalization, Gc, GcWeak, Mutation};
 
-use crate::{thread::ThreadInner, Thread};
+use crate::{table::TableInner, thread::ThreadInner, Table, Thread};
 
 #[derive(Copy, Clone, Collect)]
 #[collect(no_drop)]
@@ -17,6 +17,10 @@ impl<'gc> Finalizers<'gc> {
         self.0.borrow_mut(mc).threads.push(Gc::downgrade(ptr));
     }
 
+    pub(crate) fn register_weak_table(&self, mc: &Mutation<'gc>, ptr: Gc<'gc, TableInner<'gc>>) {
+        self.0.borrow_mut(mc).weak_tables.push(Gc::downgrade(ptr));
+    }
+
     /// First stage of two-stage finalization.
     ///
     /// This stage can cause resurrection, so the arena must be *fully re-marked* before stage two
@@ -47,6 +51,15 @@ impl<'gc> Finalizers<'gc> {
                 true
             }
         });
+
+        // Every object which will be collected is now known to be dead, so remove any references to
+        // them from weak tables before they are freed.
+        state.weak_tables.retain(|&ptr| match ptr.upgrade(fc) {
+            Some(ptr) if !Gc::is_dead(fc, ptr) => {
+                Table::from_inner(ptr).clear_dead_weak_entries(fc)
+            }
+            _ => false,
+        });
     }
 }
 
@@ -54,4 +67,5 @@ impl<'gc> Finalizers<'gc> {
 #[collect(no_drop)]
 struct FinalizersState<'gc> {
     threads: Vec<GcWeak<'gc, ThreadInner<'gc>>>,
+    weak_tables: Vec<GcWeak<'gc, TableInner<'gc>>>,
 }
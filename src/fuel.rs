@@ -1,3 +1,7 @@
+use std::{fmt, sync::Arc, time::Instant};
+
+use thiserror::Error;
+
 /// A counter for tracking the amount of time spent in `Executor::step` and in callbacks.
 ///
 /// The fuel unit is *approximately* one VM instruction, but this is just a rough estimate
@@ -5,10 +9,31 @@
 ///
 /// All operations that take a variable amount of time should consume some amount of fuel, so that
 /// it is always possible to bound the amount of time spent in `Executor::step`.
-#[derive(Debug, Clone)]
+///
+/// Since the cost of a unit of fuel varies from machine to machine, `Fuel` can additionally stop
+/// `Executor::step` once a wall-clock deadline has passed (see [`Fuel::set_deadline`]), or once a
+/// host provided check says to (see [`Fuel::set_yield_check`]). These are consulted whenever the
+/// remaining fuel is, which is at least once for every slice of VM instructions run.
+///
+/// The exact amount of fuel charged for running a given piece of code is versioned, see
+/// [`FuelSchedule`].
+#[derive(Clone)]
 pub struct Fuel {
     fuel: i32,
     interrupted: bool,
+    deadline: Option<Instant>,
+    yield_check: Option<Arc<dyn Fn() -> bool + Send + Sync>>,
+}
+
+impl fmt::Debug for Fuel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Fuel")
+            .field("fuel", &self.fuel)
+            .field("interrupted", &self.interrupted)
+            .field("deadline", &self.deadline)
+            .field("yield_check", &self.yield_check.is_some())
+            .finish()
+    }
 }
 
 impl Fuel {
@@ -20,9 +45,18 @@ impl Fuel {
         Self {
             fuel,
             interrupted: false,
+            deadline: None,
+            yield_check: None,
         }
     }
 
+    /// Unlimited fuel which runs out once the given deadline has passed.
+    pub fn until(deadline: Instant) -> Self {
+        let mut fuel = Self::with(i32::MAX);
+        fuel.set_deadline(Some(deadline));
+        fuel
+    }
+
     /// Refills fuel up to a given maximum and also clears the fuel interrupt flag.
     ///
     /// This is a convenience method that is intended to be called outside of a call to
@@ -75,10 +109,91 @@ impl Fuel {
         self.interrupted = false;
     }
 
-    /// Returns true if we have positive fuel remaining *and* we have not been interrupted.
+    /// Stop once the given instant has passed, in addition to when fuel runs out.
+    ///
+    /// The deadline is not cleared by [`Fuel::refill`], set a new deadline for every tick instead.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Stop whenever the given function returns true, in addition to when fuel runs out.
+    ///
+    /// The function is called frequently while running Lua code, so it should be cheap, like
+    /// checking an atomic flag set by another thread.
+    pub fn set_yield_check(&mut self, check: impl Fn() -> bool + Send + Sync + 'static) {
+        self.yield_check = Some(Arc::new(check));
+    }
+
+    pub fn clear_yield_check(&mut self) {
+        self.yield_check = None;
+    }
+
+    /// Returns true if the deadline has passed or the yield check asks to stop.
+    pub fn is_expired(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+            || self.yield_check.as_ref().is_some_and(|check| check())
+    }
+
+    /// Returns true if we have positive fuel remaining, we have not been interrupted, and neither
+    /// the deadline nor the yield check say to stop.
     pub fn should_continue(&self) -> bool {
-        self.fuel > 0 && !self.interrupted
+        self.fuel > 0 && !self.interrupted && !self.is_expired()
+    }
+}
+
+/// A version of the amount of fuel charged for running Lua code and the builtin callbacks.
+///
+/// Running the same code with the same inputs always consumes the same amount of fuel, but the
+/// costs of individual operations may be retuned between releases. Any change which alters the
+/// fuel consumed by existing code comes with a new [`FuelSchedule::CURRENT`], this is enforced by
+/// a conformance test which records the fuel consumed by a fixed corpus of scripts.
+///
+/// Embedders which rely on fuel numbers staying the same across upgrades, such as for billing, can
+/// pin the schedule they were built against with [`FuelSchedule::require`], so that an upgrade
+/// which changes fuel consumption fails loudly instead of silently.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FuelSchedule(u32);
+
+impl FuelSchedule {
+    pub const V1: FuelSchedule = FuelSchedule(1);
+
+    /// The schedule implemented by this version of piccolo.
+    pub const CURRENT: FuelSchedule = FuelSchedule::V1;
+
+    pub fn version(self) -> u32 {
+        self.0
     }
+
+    /// Returns the schedule with the given version if it is the one implemented by this version
+    /// of piccolo, and an error otherwise.
+    pub fn require(version: u32) -> Result<FuelSchedule, UnsupportedFuelSchedule> {
+        if version == Self::CURRENT.0 {
+            Ok(Self::CURRENT)
+        } else {
+            Err(UnsupportedFuelSchedule {
+                required: version,
+                current: Self::CURRENT.0,
+            })
+        }
+    }
+}
+
+impl fmt::Display for FuelSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Error)]
+#[error("fuel schedule version {required} is required, but the current version is {current}")]
+pub struct UnsupportedFuelSchedule {
+    pub required: u32,
+    pub current: u32,
 }
 
 pub(crate) fn count_fuel(per_item: i32, len: usize) -> i32 {
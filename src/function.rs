@@ -4,7 +4,7 @@ use gc_arena::{Collect, Gc, Mutation};
 
 use crate::{
     BoxSequence, Callback, CallbackReturn, Closure, Context, Error, Execution, IntoMultiValue,
-    Sequence, SequencePoll, Stack,
+    Sequence, SequencePoll, Stack, Value, Variadic,
 };
 
 /// Any callable Lua value (either a [`Closure`] or a [`Callback`]).
@@ -106,4 +106,32 @@ impl<'gc> Function<'gc> {
             },
         ))
     }
+
+    /// Bind the given values as the leading arguments of this function, like [`Function::bind`].
+    ///
+    /// Unlike [`Function::bind`], the arguments are converted to values immediately and stored
+    /// directly in the returned callback, so they need not be `Clone` or `Collect`. Calling the
+    /// returned function does not create any Lua closure, the bound values are simply inserted in
+    /// front of the given arguments.
+    ///
+    /// This is most useful for extracting a method from a script object to call it later: binding
+    /// the object to the method gives a function equivalent to `function(...) return
+    /// obj:method(...) end`, which can be stashed and called without any access to the object.
+    pub fn bind_front(self, ctx: Context<'gc>, args: impl IntoMultiValue<'gc>) -> Self {
+        let args: Box<[Value<'gc>]> = args.into_multi_value(ctx).collect();
+        Self::Callback(Callback::from_fn_with(
+            &ctx,
+            (self, args),
+            |(f, args), ctx, exec, mut stack| {
+                stack.into_front(ctx, Variadic(args.iter().copied()));
+                match *f {
+                    Function::Closure(c) => Ok(CallbackReturn::Call {
+                        function: c.into(),
+                        then: None,
+                    }),
+                    Function::Callback(c) => c.call(ctx, exec, stack),
+                }
+            },
+        ))
+    }
 }
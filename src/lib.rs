@@ -1,24 +1,43 @@
 pub mod any;
 pub mod async_callback;
 pub mod callback;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod chunk_cache;
 pub mod closure;
+pub mod collation;
 pub mod compiler;
+pub mod completion;
 pub mod constant;
 pub mod conversion;
 pub mod error;
+pub mod facade;
 pub mod finalizers;
 pub mod fuel;
 pub mod function;
 pub mod io;
 pub mod lua;
+pub mod memory;
 pub mod meta_ops;
 pub mod opcode;
+pub mod output;
+pub mod profile;
+pub mod random;
 pub mod registry;
+pub mod sandbox;
+pub mod scheduler;
+pub mod scratch;
+pub mod send_lua;
+pub mod shared_data;
 pub mod stack;
 pub mod stash;
 pub mod stdlib;
 pub mod string;
+pub mod symbol;
 pub mod table;
+pub mod tags;
+#[cfg(feature = "test-support")]
+mod test_support;
 pub mod thread;
 pub mod types;
 pub mod userdata;
@@ -26,24 +45,43 @@ pub mod value;
 
 pub use self::{
     async_callback::{async_sequence, SequenceReturn},
-    callback::{BoxSequence, Callback, CallbackFn, CallbackReturn, Sequence, SequencePoll},
-    closure::{Closure, CompilerError, FunctionPrototype},
+    callback::{
+        BoxSequence, Callback, CallbackFn, CallbackFuel, CallbackReturn, ErrorBoundary, Sequence,
+        SequencePoll,
+    },
+    chunk_cache::{ChunkCache, ChunkCacheLimits, ChunkCacheMetrics},
+    closure::{
+        Closure, CompileOptions, CompilerError, FunctionPrototype, PrototypeError, SourceFragment,
+        VariableName,
+    },
+    collation::{Collation, Collator},
     constant::Constant,
     conversion::{FromMultiValue, FromValue, IntoMultiValue, IntoValue, Variadic},
     error::{Error, ExternError, RuntimeError, TypeError},
-    fuel::Fuel,
+    facade::{Facade, FacadeError},
+    fuel::{Fuel, FuelSchedule},
     function::Function,
-    lua::{Context, Lua},
+    lua::{Context, EvalConfigError, ExecuteFuture, Lua, ModuleError, RecursionLimits},
+    memory::{GcControl, MemoryLimit},
     meta_ops::MetaMethod,
+    profile::{FunctionStats, Profile, ProfileCost, ProfileMetric},
     registry::{Registry, Singleton},
+    sandbox::{SandboxBuilder, SandboxError},
+    scheduler::{Scheduler, TaskId},
+    send_lua::SendLua,
+    shared_data::{SharedDataError, SharedKey, SharedTable, SharedValue},
     stack::Stack,
     stash::{
         StashedCallback, StashedClosure, StashedError, StashedExecutor, StashedFunction,
         StashedString, StashedTable, StashedThread, StashedUserData, StashedValue,
     },
     string::String,
+    symbol::Symbol,
     table::Table,
-    thread::{Execution, Executor, ExecutorMode, Thread, ThreadMode},
-    userdata::UserData,
-    value::Value,
+    thread::{
+        Execution, ExecutionMetrics, Executor, ExecutorMode, OutOfFuel, StackLimits, Thread,
+        ThreadMode, ThreadResult, TypedThread,
+    },
+    userdata::{UserData, UserDataType},
+    value::{NumberKind, Value},
 };
@@ -1,21 +1,88 @@
-use std::ops;
+use std::{
+    cell::Cell,
+    collections::{BTreeMap, HashSet, VecDeque},
+    future::Future,
+    io::Write,
+    marker::PhantomData,
+    ops,
+    pin::Pin,
+    rc::Rc,
+    task::{self, Poll},
+};
 
+use allocator_api2::vec;
 use gc_arena::{
+    allocator_api::MetricsAlloc,
     arena::{CollectionPhase, Root},
-    metrics::Metrics,
-    Arena, Collect, Mutation, Rootable,
+    lock::{Lock, RefLock},
+    metrics::{Metrics, Pacing},
+    Arena, Collect, Gc, Mutation, Rootable,
 };
+use thiserror::Error;
 
 use crate::{
+    chunk_cache::{ChunkCache, ChunkCacheLimits},
+    collation::{Collation, Collator},
+    compiler::Restrictions,
     finalizers::Finalizers,
+    memory::{GcControl, MemoryLimit},
+    meta_ops::{self, MetaResult},
+    output::Output,
+    random::{Random, RandomSource},
+    scratch::{Scratch, ScratchBuffer},
     stash::{Fetchable, Stashable},
-    stdlib::{load_base, load_coroutine, load_io, load_math, load_string, load_table},
+    stdlib::{
+        load_base, load_class, load_coroutine, load_io, load_io_with_vfs, load_math, load_os,
+        load_os_with_vfs, load_package, load_package_with_vfs, load_string, load_table,
+        load_unsupported, set_unsupported_hook, VfsProvider,
+    },
     string::InternedStringSet,
-    thread::BadThreadMode,
-    Error, ExternError, FromMultiValue, FromValue, Fuel, IntoValue, Registry, RuntimeError,
-    Singleton, StashedExecutor, String, Table, TypeError, Value,
+    tags::{self, TagMemory},
+    thread::{
+        restore_fork, restore_globals, save_fork, save_globals, BadThreadMode, SnapshotError,
+        SnapshotHooks,
+    },
+    Closure, CompileOptions, CompilerError, Error, Executor, ExternError, FromMultiValue,
+    FromValue, Fuel, Function, IntoMultiValue, IntoValue, Registry, RuntimeError, SandboxBuilder,
+    SandboxError, Singleton, StackLimits, StashedError, StashedExecutor, StashedTable,
+    StashedThread, String, Table, Thread, TypeError, Value,
 };
 
+#[cfg(feature = "test-support")]
+use crate::test_support::TestSupport;
+
+/// Limits on recursion which happens without growing the call stack of a thread, and which is
+/// therefore not bounded by [`StackLimits`](crate::StackLimits).
+///
+/// These are shared by every thread of a [`Lua`] instance and can be changed with
+/// [`Lua::set_recursion_limits`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RecursionLimits {
+    /// The maximum length of a chain of `__call` metamethods, such as a table whose `__call`
+    /// metamethod is another callable table. Longer chains raise an error, which also stops
+    /// infinite chains like `setmetatable(t, { __call = t })`.
+    pub max_call_chain: usize,
+    /// The maximum number of times a message handler (such as the handler given to `xpcall`) is
+    /// called again to handle an error raised by the handler itself. Past this depth, the error
+    /// becomes "error in error handling".
+    ///
+    /// The default of 1 means that errors raised by a message handler are never handled again.
+    pub max_handler_depth: usize,
+}
+
+impl RecursionLimits {
+    pub const DEFAULT: RecursionLimits = RecursionLimits {
+        max_call_chain: 2000,
+        max_handler_depth: 1,
+    };
+}
+
+impl Default for RecursionLimits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
 /// A value representing the main "execution context" of a Lua state.
 ///
 /// It provides access to the table of global variables, the registry, the string interner, and
@@ -72,6 +139,101 @@ impl<'gc> Context<'gc> {
         self.state.finalizers
     }
 
+    /// The cache of compiled chunks used by [`Closure::load`] and its variants.
+    pub fn chunk_cache(self) -> ChunkCache<'gc> {
+        self.state.chunk_cache
+    }
+
+    /// The metatable shared by every string, set by [`load_string`](crate::stdlib::load_string) so
+    /// that string methods can be called like `s:upper()`.
+    pub fn string_metatable(self) -> Option<Table<'gc>> {
+        self.state.string_metatable.get()
+    }
+
+    /// Set the metatable shared by every string, returning the previous metatable.
+    ///
+    /// Scripts cannot replace the string metatable themselves, this is how an embedder changes it.
+    pub fn set_string_metatable(self, metatable: Option<Table<'gc>>) -> Option<Table<'gc>> {
+        Gc::write(&self, self.state.string_metatable)
+            .unlock()
+            .replace(metatable)
+    }
+
+    /// The destination for the output of `print`.
+    pub fn output(self) -> &'gc Output {
+        &self.state.output
+    }
+
+    /// The memory limit of this `Lua` instance.
+    pub fn memory_limit(self) -> &'gc MemoryLimit {
+        &self.state.memory_limit
+    }
+
+    /// The current [`RecursionLimits`].
+    pub fn recursion_limits(self) -> RecursionLimits {
+        self.state.recursion_limits.get()
+    }
+
+    pub fn set_recursion_limits(self, limits: RecursionLimits) {
+        self.state.recursion_limits.set(limits);
+    }
+
+    /// Garbage collection requests from inside the arena, used by `collectgarbage`.
+    pub fn gc_control(self) -> &'gc GcControl {
+        &self.state.gc_control
+    }
+
+    /// The total memory used by this `Lua` instance, as counted by [`MemoryLimit`].
+    ///
+    /// This is the same as [`Lua::total_memory`].
+    pub fn memory_usage(self) -> usize {
+        self.mutation.metrics().total_allocation()
+    }
+
+    /// Returns a "not enough memory" error if allocating `additional` more bytes would exceed the
+    /// [`MemoryLimit`].
+    ///
+    /// Callbacks which allocate an amount of memory controlled by a script should check this
+    /// first, since the limit is otherwise only checked in between VM instructions. Unlike those
+    /// checks, this cannot wait for garbage to be collected, so garbage counts against the limit.
+    pub fn check_memory(self, additional: usize) -> Result<(), Error<'gc>> {
+        if self
+            .memory_limit()
+            .would_exceed(self.memory_usage(), additional)
+        {
+            Err("not enough memory".into_value(self).into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The random number generator used by `math.random`.
+    pub fn random(self) -> &'gc Random {
+        &self.state.random
+    }
+
+    /// The ordering of strings used by comparison operators.
+    pub fn collator(self) -> &'gc Collator {
+        &self.state.collator
+    }
+
+    #[cfg(feature = "test-support")]
+    pub(crate) fn test_support(self) -> &'gc TestSupport {
+        &self.state.test_support
+    }
+
+    pub fn scratch(self) -> &'gc Scratch {
+        &self.state.scratch
+    }
+
+    /// Calls `ctx.scratch().buffer()`.
+    ///
+    /// Borrows an empty temporary byte buffer which is returned for reuse when dropped, rather
+    /// than allocating a new `Vec` or a GC object.
+    pub fn scratch_buffer(self) -> ScratchBuffer<'gc> {
+        self.scratch().buffer()
+    }
+
     // Calls `ctx.globals().get(key)`
     pub fn get_global<V: FromValue<'gc>>(self, key: &'static str) -> Result<V, TypeError> {
         self.state.globals.get(self, key)
@@ -115,6 +277,64 @@ impl<'gc> Context<'gc> {
     pub fn intern_static(self, s: &'static [u8]) -> String<'gc> {
         self.state.strings.intern_static(&self, s)
     }
+
+    /// Create a new thread which will run `function` with the given arguments, and hand it to the
+    /// host once the current call to [`Lua::enter`] returns.
+    ///
+    /// This is meant for callbacks which need to start independent, fire-and-forget script tasks.
+    /// The new thread is not run by anything in the arena. Instead, after the enclosing
+    /// `Lua::enter` exits the arena, every thread spawned during it is passed to the handler set
+    /// with [`Lua::set_spawn_handler`], which can then start an [`Executor`] for it or register it
+    /// with whatever scheduler the host uses. Without a spawn handler, spawned threads are kept
+    /// until they are taken with [`Lua::take_spawned_threads`].
+    ///
+    /// The returned thread is already started, so it may also be inspected or resumed directly.
+    pub fn spawn_callback_thread(
+        self,
+        function: Function<'gc>,
+        args: impl IntoMultiValue<'gc>,
+    ) -> Thread<'gc> {
+        let thread = Thread::new(self);
+        thread
+            .start(self, function, args)
+            .expect("new thread must be stopped");
+        self.state.spawned.borrow_mut(&self).push(thread);
+        thread
+    }
+
+    /// Convert a value to a string exactly like the `tostring` builtin, including calling any
+    /// `__tostring` metamethod.
+    ///
+    /// The metamethod is run on a new [`Executor`] before this returns, so it must not yield. It
+    /// may use at most `fuel`, if it does not finish within that an error is returned instead.
+    /// Without a `__tostring` metamethod, this is the same as formatting the value with
+    /// [`Value::display`].
+    pub fn tostring(self, value: Value<'gc>, fuel: i32) -> Result<String<'gc>, Error<'gc>> {
+        let result = match meta_ops::tostring(self, value)? {
+            MetaResult::Value(v) => v,
+            MetaResult::Call(call) => {
+                let executor = Executor::start(self, call.function, call.args);
+                let mut fuel = Fuel::with(fuel);
+                loop {
+                    let start = fuel.remaining();
+                    if executor.step(self, &mut fuel)? {
+                        break executor.take_result::<Value>(self)??;
+                    }
+                    // The executor may also stop without using any fuel to wait for a garbage
+                    // collection, which cannot happen before this returns.
+                    if !fuel.should_continue() || fuel.remaining() == start {
+                        return Err("'__tostring' did not finish within its fuel limit"
+                            .into_value(self)
+                            .into());
+                    }
+                }
+            }
+        };
+
+        result
+            .into_string(self)
+            .ok_or_else(|| "'__tostring' must return a string".into_value(self).into())
+    }
 }
 
 impl<'gc> ops::Deref for Context<'gc> {
@@ -129,8 +349,137 @@ impl<'gc> ops::Deref for Context<'gc> {
 ///
 /// This is the top-level `piccolo` type. In order to load and call any Lua code, the first step is
 /// to create a `Lua` instance.
+///
+/// A `Lua` instance is not `Send`, since it may own host values which are not `Send`, such as data
+/// captured by callbacks. Use [`SendLua`](crate::SendLua) for an instance which can be moved
+/// between threads.
 pub struct Lua {
     arena: Arena<Rootable![State<'_>]>,
+    // The parts of the stdlib loaded with the `Lua::load_*` methods, in order, see `Lua::fork`.
+    stdlib: Vec<StdLib>,
+    spawn_handler: Option<Box<SpawnHandler>>,
+    // The allocation debt that automatic collection is deferred until, see `Lua::set_gc_deferred`.
+    gc_deferred: Option<f64>,
+    #[cfg(feature = "test-support")]
+    collect_all_every_step: bool,
+}
+
+type StdLib = Rc<dyn Fn(Context<'_>)>;
+
+// The tables, callbacks, and userdata which the stdlib has added to the globals, each mapped to the
+// path of keys from the globals table it was first found at, or to `false` for objects which
+// existed before the stdlib was loaded.
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+struct StdLibObjects<'gc>(Table<'gc>);
+
+impl<'gc> Singleton<'gc> for StdLibObjects<'gc> {
+    fn create(ctx: Context<'gc>) -> Self {
+        StdLibObjects(Table::new(&ctx))
+    }
+}
+
+impl<'gc> StdLibObjects<'gc> {
+    // Record every object reachable from the globals table through string and integer keys which
+    // has not been recorded yet, as part of the stdlib if `stdlib` is set.
+    fn record(ctx: Context<'gc>, stdlib: bool) {
+        let objects = ctx.singleton::<Rootable![StdLibObjects<'_>]>().0;
+        let mut seen = HashSet::from([ctx.globals()]);
+        let mut queue = VecDeque::from([(ctx.globals(), Vec::new())]);
+        while let Some((table, path)) = queue.pop_front() {
+            for (key, value) in table {
+                if !matches!(key, Value::String(_) | Value::Integer(_))
+                    || !matches!(
+                        value,
+                        Value::Table(_)
+                            | Value::Function(Function::Callback(_))
+                            | Value::UserData(_)
+                    )
+                {
+                    continue;
+                }
+
+                let mut path = path.clone();
+                write_path_key(&mut path, key);
+                if objects.get_raw(value).is_nil() {
+                    let recorded = if stdlib {
+                        Value::String(ctx.intern(&path))
+                    } else {
+                        Value::Boolean(false)
+                    };
+                    objects.set_raw(&ctx, value, recorded).unwrap();
+                }
+
+                if let Value::Table(table) = value {
+                    if seen.insert(table) {
+                        queue.push_back((table, path));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Snapshot hooks which save the stdlib objects of one instance as their paths, and restore the
+// paths to the objects at the same place in an instance which has just loaded the same stdlib.
+struct StdLibHooks;
+
+impl<'gc> SnapshotHooks<'gc> for StdLibHooks {
+    fn save_value(&mut self, ctx: Context<'gc>, value: Value<'gc>) -> Option<Vec<u8>> {
+        match ctx
+            .singleton::<Rootable![StdLibObjects<'_>]>()
+            .0
+            .get_raw(value)
+        {
+            Value::String(path) => Some(path.as_bytes().to_vec()),
+            _ => None,
+        }
+    }
+
+    fn restore_value(&mut self, ctx: Context<'gc>, mut path: &[u8]) -> Option<Value<'gc>> {
+        let mut value = Value::Table(ctx.globals());
+        while !path.is_empty() {
+            let key = read_path_key(ctx, &mut path)?;
+            value = match value {
+                Value::Table(table) => table.get_raw(key),
+                _ => return None,
+            };
+        }
+        (!value.is_nil()).then_some(value)
+    }
+}
+
+fn write_path_key(path: &mut Vec<u8>, key: Value<'_>) {
+    match key {
+        Value::String(s) => {
+            path.push(b's');
+            path.extend_from_slice(&u32::try_from(s.len()).unwrap().to_le_bytes());
+            path.extend_from_slice(s.as_bytes());
+        }
+        Value::Integer(i) => {
+            path.push(b'i');
+            path.extend_from_slice(&i.to_le_bytes());
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn read_path_key<'gc>(ctx: Context<'gc>, path: &mut &[u8]) -> Option<Value<'gc>> {
+    let (&tag, rest) = path.split_first()?;
+    let (key, rest) = match tag {
+        b's' => {
+            let len = u32::from_le_bytes(rest.get(..4)?.try_into().unwrap()) as usize;
+            let bytes = rest.get(4..4 + len)?;
+            (Value::String(ctx.intern(bytes)), &rest[4 + len..])
+        }
+        b'i' => {
+            let i = i64::from_le_bytes(rest.get(..8)?.try_into().unwrap());
+            (Value::Integer(i), &rest[8..])
+        }
+        _ => return None,
+    };
+    *path = rest;
+    Some(key)
 }
 
 impl Default for Lua {
@@ -144,6 +493,11 @@ impl Lua {
     pub fn empty() -> Self {
         Lua {
             arena: Arena::<Rootable![State<'_>]>::new(|mc| State::new(mc)),
+            stdlib: Vec::new(),
+            spawn_handler: None,
+            gc_deferred: None,
+            #[cfg(feature = "test-support")]
+            collect_all_every_step: false,
         }
     }
 
@@ -155,9 +509,16 @@ impl Lua {
     }
 
     /// Create a new `Lua` instance with all of the stdlib loaded.
+    ///
+    /// None of the loaded libraries can access the host filesystem, use
+    /// [`Lua::load_io_with_vfs`], [`Lua::load_os_with_vfs`], and [`Lua::load_package_with_vfs`]
+    /// with [`HostFilesystem`](crate::stdlib::HostFilesystem) to opt in.
     pub fn full() -> Self {
         let mut lua = Lua::core();
+        lua.load_class();
         lua.load_io();
+        lua.load_os();
+        lua.load_package();
         lua
     }
 
@@ -170,7 +531,7 @@ impl Lua {
     ///   - `load_string`
     ///   - `load_table`
     pub fn load_core(&mut self) {
-        self.enter(|ctx| {
+        self.load_stdlib(|ctx| {
             load_base(ctx);
             load_coroutine(ctx);
             load_math(ctx);
@@ -179,11 +540,227 @@ impl Lua {
         })
     }
 
-    /// Load the parts of the stdlib that allow I/O.
+    /// Load the `class` library, see [`load_class`].
+    pub fn load_class(&mut self) {
+        self.load_stdlib(load_class)
+    }
+
+    /// Load the parts of the stdlib that allow I/O, without access to any filesystem, see
+    /// [`load_io`].
     pub fn load_io(&mut self) {
+        self.load_stdlib(load_io)
+    }
+
+    /// Load the parts of the stdlib that allow I/O, with the `io` library opening files from the
+    /// given [`VfsProvider`] rather than from the host filesystem.
+    pub fn load_io_with_vfs(&mut self, vfs: impl VfsProvider) {
+        let vfs = Rc::new(vfs);
+        self.load_stdlib(move |ctx| load_io_with_vfs(ctx, vfs.clone()))
+    }
+
+    /// Send the output of `print` to the given writer instead of stdout.
+    pub fn set_output(&mut self, writer: impl Write + 'static) {
+        self.enter(|ctx| ctx.output().set(writer))
+    }
+
+    /// Send the output of `print` to the given function instead of stdout.
+    ///
+    /// See [`Output::set_fn`].
+    pub fn set_output_fn(&mut self, f: impl FnMut(&[u8]) + 'static) {
+        self.enter(|ctx| ctx.output().set_fn(f))
+    }
+
+    /// Send warnings to the given function instead of writing them to stderr.
+    ///
+    /// See [`Output::set_warn_fn`].
+    pub fn set_warn_fn(&mut self, f: impl FnMut(&[u8]) + 'static) {
+        self.enter(|ctx| ctx.output().set_warn_fn(f))
+    }
+
+    /// Enable or disable warnings, which are disabled by default.
+    ///
+    /// Scripts can also enable or disable warnings with `warn("@on")` and `warn("@off")`.
+    pub fn set_warnings_enabled(&mut self, enabled: bool) {
+        self.enter(|ctx| ctx.output().set_warnings_enabled(enabled))
+    }
+
+    /// Seed the random number generator used by `math.random`, so that scripts produce the same
+    /// sequence of random numbers every time they are run.
+    ///
+    /// This is the same as calling `math.randomseed(n1, n2)` from Lua.
+    pub fn set_random_seed(&mut self, n1: i64, n2: i64) {
+        self.enter(|ctx| ctx.random().seed(n1, n2))
+    }
+
+    /// Replace the random number generator used by `math.random`.
+    pub fn set_random_source(&mut self, source: impl RandomSource) {
+        self.enter(|ctx| ctx.random().set_source(source))
+    }
+
+    /// Order strings with the given collation rather than byte-wise, see [`Collator`].
+    ///
+    /// This affects every ordering comparison between two strings, including those made by
+    /// `table.sort`.
+    pub fn set_collation(&mut self, collation: impl Collation) {
+        self.enter(|ctx| ctx.collator().set(collation))
+    }
+
+    /// Go back to ordering strings byte-wise.
+    pub fn reset_collation(&mut self) {
+        self.enter(|ctx| ctx.collator().reset())
+    }
+
+    /// Enable caching of compiled chunks with the given limits, or disable it with `None`.
+    ///
+    /// See [`ChunkCache`] for details, the cache metrics are available from
+    /// [`Context::chunk_cache`].
+    pub fn set_chunk_cache(&mut self, limits: Option<ChunkCacheLimits>) {
+        self.enter(|ctx| ctx.chunk_cache().set_limits(&ctx, limits))
+    }
+
+    /// Set the handler which receives every thread created with [`Context::spawn_callback_thread`].
+    ///
+    /// The handler is called outside of the arena after every call to [`Lua::enter`] (and so after
+    /// every step of [`Lua::finish`] and [`Lua::execute`]) during which a thread was spawned, once
+    /// for each spawned thread in the order they were spawned. The handler may freely use the
+    /// given `Lua` instance, including entering it again or running another executor to
+    /// completion. Threads spawned while the handler is running are passed to it as well before
+    /// the original call to `Lua::enter` returns.
+    pub fn set_spawn_handler(&mut self, handler: impl FnMut(&mut Lua, StashedThread) + 'static) {
+        self.spawn_handler = Some(Box::new(handler));
+    }
+
+    /// Remove the spawn handler, spawned threads will be kept until they are taken with
+    /// [`Lua::take_spawned_threads`].
+    pub fn clear_spawn_handler(&mut self) {
+        self.spawn_handler = None;
+    }
+
+    /// Take every thread created with [`Context::spawn_callback_thread`] which has not been passed
+    /// to a spawn handler yet.
+    pub fn take_spawned_threads(&mut self) -> Vec<StashedThread> {
+        self.arena
+            .mutate(|mc, state| state.take_spawned(state.ctx(mc)))
+    }
+
+    /// Calls `ctx.set_recursion_limits(limits)`.
+    pub fn set_recursion_limits(&mut self, limits: RecursionLimits) {
+        self.enter(|ctx| ctx.set_recursion_limits(limits))
+    }
+
+    /// Limit the total memory used by this `Lua` instance to `bytes`, or remove the limit with
+    /// `None`.
+    ///
+    /// Lua code which exceeds the limit raises a catchable "not enough memory" error, see
+    /// [`MemoryLimit`] for details.
+    pub fn set_memory_limit(&mut self, bytes: Option<usize>) {
+        self.enter(|ctx| ctx.memory_limit().set_limit(bytes))
+    }
+
+    pub fn memory_limit(&mut self) -> Option<usize> {
+        self.enter(|ctx| ctx.memory_limit().limit())
+    }
+
+    /// Load the `os` library, which exposes the host clock and environment variables.
+    pub fn load_os(&mut self) {
+        self.load_stdlib(load_os)
+    }
+
+    /// Load the `os` library, with `os.remove`, `os.rename`, and `os.tmpname` managing files
+    /// through the given [`VfsProvider`].
+    pub fn load_os_with_vfs(&mut self, vfs: impl VfsProvider) {
+        let vfs = Rc::new(vfs);
+        self.load_stdlib(move |ctx| load_os_with_vfs(ctx, vfs.clone()))
+    }
+
+    /// Load `require` and the `package` library, without searching for modules in any filesystem,
+    /// see [`load_package`].
+    ///
+    /// This should be called after any other libraries are loaded, so that they are also available
+    /// in `package.loaded`.
+    pub fn load_package(&mut self) {
+        self.load_stdlib(load_package)
+    }
+
+    /// Load `require` and the `package` library, searching for Lua modules in the given
+    /// [`VfsProvider`] using `package.path`.
+    ///
+    /// Pass [`HostFilesystem`](crate::stdlib::HostFilesystem) to search the host filesystem.
+    pub fn load_package_with_vfs(&mut self, vfs: impl VfsProvider) {
+        let vfs = Rc::new(vfs);
+        self.load_stdlib(move |ctx| load_package_with_vfs(ctx, vfs.clone()))
+    }
+
+    /// Fill in every missing standard library function with a stub which raises an
+    /// [`UnsupportedFunction`](crate::stdlib::UnsupportedFunction) error naming it.
+    ///
+    /// This should be called after any other libraries are loaded, see
+    /// [`load_unsupported`](crate::stdlib::load_unsupported).
+    pub fn load_unsupported(&mut self) {
+        self.load_stdlib(load_unsupported)
+    }
+
+    fn load_stdlib(&mut self, load: impl Fn(Context<'_>) + 'static) {
+        self.add_stdlib(Rc::new(load))
+    }
+
+    // Load a part of the stdlib, remembering how it was loaded and the objects it creates so that
+    // `Lua::fork` can load it again and find their counterparts in the new instance.
+    fn add_stdlib(&mut self, load: StdLib) {
         self.enter(|ctx| {
-            load_io(ctx);
-        })
+            StdLibObjects::record(ctx, false);
+            load(ctx);
+            StdLibObjects::record(ctx, true);
+        });
+        self.stdlib.push(load);
+    }
+
+    /// Call the given function with the name of every unsupported stdlib function that a script
+    /// calls, see [`Lua::load_unsupported`].
+    pub fn set_unsupported_fn(&mut self, f: impl FnMut(&str) + 'static) {
+        self.enter(|ctx| set_unsupported_hook(ctx, f))
+    }
+
+    /// Create a new `Lua` instance with the same parts of the stdlib loaded as this one and a deep
+    /// copy of its globals.
+    ///
+    /// This lets a template instance be set up once, such as by running some initialization
+    /// scripts, and then copied for every isolated session without running the setup again. The
+    /// new instance repeats every `Lua::load_*` call made on this one, and then every table,
+    /// closure, and suspended thread reachable from the globals is copied, including cycles and
+    /// shared references. Tables, callbacks, and userdata created by the stdlib are replaced by
+    /// their counterparts in the new instance, and the stdlib tables are given the contents of the
+    /// template's, so changes to the stdlib (such as new `string` methods, or modules in
+    /// `package.loaded`) carry over. The recursion and memory limits are copied as well, nothing
+    /// else (such as the registry or the output) is.
+    ///
+    /// The copy is made by snapshotting the globals like [`Thread::snapshot`] does, so it costs
+    /// about as much as saving and restoring them. Any other callbacks or userdata make this fail,
+    /// use [`Lua::fork_into`] with hooks which handle them instead.
+    pub fn fork(&mut self) -> Result<Lua, SnapshotError> {
+        let mut lua = Lua::empty();
+        for load in &self.stdlib {
+            lua.add_stdlib(load.clone());
+        }
+        let data = self.enter(|ctx| save_fork(ctx, &mut StdLibHooks))?;
+        lua.enter(|ctx| restore_fork(ctx, &data, &mut StdLibHooks))?;
+        lua.set_recursion_limits(self.enter(|ctx| ctx.recursion_limits()));
+        lua.set_memory_limit(self.memory_limit());
+        Ok(lua)
+    }
+
+    /// Copy the globals of this instance into the globals of `target`, which should have the same
+    /// parts of the stdlib loaded.
+    ///
+    /// Like with [`Thread::snapshot`], `hooks` can save and restore values which would otherwise
+    /// not be copied, such as userdata. Existing globals of `target` are overwritten by globals of
+    /// the same name.
+    pub fn fork_into<H>(&mut self, target: &mut Lua, hooks: &mut H) -> Result<(), SnapshotError>
+    where
+        H: for<'gc> SnapshotHooks<'gc>,
+    {
+        let data = self.enter(|ctx| save_globals(ctx, &mut *hooks))?;
+        target.enter(|ctx| restore_globals(ctx, &data, hooks))
     }
 
     /// Size of all memory used by this Lua context.
@@ -195,6 +772,13 @@ impl Lua {
         self.gc_metrics().total_allocation()
     }
 
+    /// Returns the memory used by all live tagged tables and userdata, grouped by tag.
+    ///
+    /// See [`tags::memory_by_tag`].
+    pub fn memory_by_tag(&mut self) -> BTreeMap<u32, TagMemory> {
+        self.enter(tags::memory_by_tag)
+    }
+
     /// Finish the current collection cycle completely, calls `gc_arena::Arena::collect_all()`.
     pub fn gc_collect(&mut self) {
         if self.arena.collection_phase() != CollectionPhase::Sweeping {
@@ -214,6 +798,107 @@ impl Lua {
         self.arena.metrics()
     }
 
+    /// Set the pacing of the garbage collector, which controls how long it waits after a
+    /// collection before starting the next one and how much work it does per allocated byte.
+    ///
+    /// This is equivalent to `self.gc_metrics().set_pacing(pacing)`.
+    pub fn set_gc_pacing(&mut self, pacing: Pacing) {
+        self.arena.metrics().set_pacing(pacing);
+    }
+
+    /// Returns false if automatic collection has been stopped with [`Lua::set_gc_running`] or by
+    /// `collectgarbage("stop")`.
+    pub fn is_gc_running(&mut self) -> bool {
+        self.enter(|ctx| ctx.gc_control().is_running())
+    }
+
+    /// Stop or restart automatic collection.
+    ///
+    /// While stopped, [`Lua::enter`] never performs collection work on its own, so an embedder can
+    /// instead call [`Lua::gc_step`] or [`Lua::gc_collect`] at a convenient time, such as in
+    /// between frames. Scripts can do the same with `collectgarbage("stop")` and
+    /// `collectgarbage("restart")`.
+    pub fn set_gc_running(&mut self, running: bool) {
+        self.enter(|ctx| ctx.gc_control().set_running(running))
+    }
+
+    /// Perform an amount of collection work proportional to the memory allocated since the last
+    /// step, the same as `Lua::enter` does automatically whenever enough memory has been
+    /// allocated.
+    pub fn gc_step(&mut self) {
+        if self.arena.collection_phase() == CollectionPhase::Sweeping {
+            self.arena.collect_debt();
+        } else if let Some(marked) = self.arena.mark_debt() {
+            marked.finalize(|fc, root| {
+                root.finalizers.prepare(fc);
+            });
+            self.arena.mark_all().unwrap().finalize(|fc, root| {
+                root.finalizers.finalize(fc);
+            });
+            // Immediately transition to `CollectionPhase::Sweeping`.
+            self.arena.mark_all().unwrap().start_sweeping();
+        }
+    }
+
+    /// Defer automatic collection work to when the host is idle.
+    ///
+    /// While deferred, [`Lua::enter`] performs no collection work on its own until the allocation
+    /// debt exceeds `max_debt` bytes, so running a latency-critical script never pauses for
+    /// collection. The debt keeps accumulating instead, and the host should pay it off with
+    /// [`Lua::gc_idle`] whenever it has time to spare, for example once every executor has yielded
+    /// or finished for the current frame. The limit bounds how much garbage can build up if the
+    /// host does not call `Lua::gc_idle` often enough.
+    ///
+    /// Full collections requested with `collectgarbage()` or by the memory limit are still
+    /// performed immediately. Passing `None` restores normal automatic collection.
+    pub fn set_gc_deferred(&mut self, max_debt: Option<usize>) {
+        self.gc_deferred = max_debt.map(|debt| debt as f64);
+    }
+
+    /// Returns true if automatic collection is deferred with [`Lua::set_gc_deferred`].
+    pub fn is_gc_deferred(&self) -> bool {
+        self.gc_deferred.is_some()
+    }
+
+    /// Signal that the host is idle, paying off all of the collection debt accumulated so far.
+    ///
+    /// This is meant to be called in between running scripts, when collection pauses are harmless,
+    /// and is most useful together with [`Lua::set_gc_deferred`]. Like [`Lua::gc_step`], but does
+    /// nothing if automatic collection has been stopped with [`Lua::set_gc_running`].
+    ///
+    /// Returns the allocation debt in bytes that was paid off.
+    pub fn gc_idle(&mut self) -> f64 {
+        if !self.is_gc_running() {
+            return 0.0;
+        }
+        let debt = self.arena.metrics().allocation_debt();
+        self.gc_step();
+        (debt - self.arena.metrics().allocation_debt()).max(0.0)
+    }
+
+    /// Perform a full collection, including finalization, every time the arena is exited.
+    ///
+    /// Since [`Lua::finish`] and [`Lua::execute`] exit the arena after every step of an executor,
+    /// this collects as often as possible while running Lua code, so that a missing root or a
+    /// `Gc` pointer held across a yield in a callback fails deterministically rather than rarely.
+    /// This is extremely slow and only meant for tests.
+    #[cfg(feature = "test-support")]
+    pub fn collect_all_every_step(&mut self, enabled: bool) {
+        self.collect_all_every_step = enabled;
+    }
+
+    /// Inject an allocation failure once [`Lua::total_memory`] grows more than `bytes` past its
+    /// current value.
+    ///
+    /// Allocation cannot actually fail, so instead the next time the executor resumes a Lua
+    /// function after the limit is exceeded, the function raises a "not enough memory" error
+    /// which can be caught like any other error. Only a single failure is injected, passing `None` cancels a pending failure.
+    #[cfg(feature = "test-support")]
+    pub fn fail_allocations_after(&mut self, bytes: Option<usize>) {
+        let limit = bytes.map(|bytes| self.total_memory().saturating_add(bytes));
+        self.enter(|ctx| ctx.test_support().set_allocation_limit(limit));
+    }
+
     /// Enter the garbage collection arena and perform some operation.
     ///
     /// In order to interact with Lua or do any useful work with Lua values, you must do so from
@@ -224,40 +909,85 @@ impl Lua {
     /// collected cocurrently with accessing the arena.
     ///
     /// Automatically triggers garbage collection before returning if the allocation debt is larger
-    /// than a small constant.
+    /// than a small constant, unless automatic collection has been stopped with
+    /// [`Lua::set_gc_running`] or deferred with [`Lua::set_gc_deferred`]. A full collection requested with [`GcControl::request_collection`]
+    /// is always performed.
+    ///
+    /// Excess memory held by [`Context::scratch`] buffers is released before returning.
     pub fn enter<F, T>(&mut self, f: F) -> T
     where
         F: for<'gc> FnOnce(Context<'gc>) -> T,
     {
         const COLLECTOR_GRANULARITY: f64 = 1024.0;
 
-        let r = self.arena.mutate(move |mc, state| f(state.ctx(mc)));
-        if self.arena.metrics().allocation_debt() > COLLECTOR_GRANULARITY {
-            if self.arena.collection_phase() == CollectionPhase::Sweeping {
-                self.arena.collect_debt();
-            } else {
-                if let Some(marked) = self.arena.mark_debt() {
-                    marked.finalize(|fc, root| {
-                        root.finalizers.prepare(fc);
-                    });
-                    self.arena.mark_all().unwrap().finalize(|fc, root| {
-                        root.finalizers.finalize(fc);
-                    });
-                    // Immediately transition to `CollectionPhase::Sweeping`.
-                    self.arena.mark_all().unwrap().start_sweeping();
-                }
-            }
+        let (r, collect, running) = self.arena.mutate(move |mc, state| {
+            let r = f(state.ctx(mc));
+            state.scratch.reset();
+            // Both requests must be taken, so this must not short-circuit.
+            let collect = state.memory_limit.take_collection_request()
+                | state.gc_control.take_collection_request();
+            (r, collect, state.gc_control.is_running())
+        });
+        self.dispatch_spawned_threads();
+        if collect {
+            // Either an executor stopped early because the memory limit was exceeded, in which
+            // case all garbage must be collected so that only live memory counts against the limit
+            // when it is checked again, or a full collection was explicitly requested.
+            self.gc_collect();
+            return r;
+        }
+        #[cfg(feature = "test-support")]
+        if self.collect_all_every_step {
+            self.gc_collect();
+            return r;
+        }
+        let debt = self.arena.metrics().allocation_debt();
+        if running
+            && debt > COLLECTOR_GRANULARITY
+            && !matches!(self.gc_deferred, Some(max_debt) if debt <= max_debt)
+        {
+            self.gc_step();
         }
         r
     }
 
     /// A version of `Lua::enter` that expects failure and automatically converts [`Error`] into
     /// [`ExternError`], allowing the error type to escape the arena.
+    ///
+    /// Lua errors are converted with [`Error::into_extern_with`], so table and userdata errors are
+    /// described by their `__tostring` metamethod.
     pub fn try_enter<F, R>(&mut self, f: F) -> Result<R, ExternError>
     where
         F: for<'gc> FnOnce(Context<'gc>) -> Result<R, Error<'gc>>,
     {
-        self.enter(move |ctx| f(ctx).map_err(Error::into_extern))
+        self.enter(move |ctx| f(ctx).map_err(|err| err.into_extern_with(ctx)))
+    }
+
+    fn dispatch_spawned_threads(&mut self) {
+        if self.spawn_handler.is_none()
+            || self
+                .arena
+                .mutate(|_, state| state.spawned.borrow().is_empty())
+        {
+            return;
+        }
+
+        // The handler is taken out while it runs so that it can use `self`, any thread it spawns
+        // is picked up by the loop below rather than by a nested dispatch.
+        let mut handler = self.spawn_handler.take().unwrap();
+        loop {
+            let threads = self.take_spawned_threads();
+            if threads.is_empty() {
+                break;
+            }
+            for thread in threads {
+                handler(self, thread);
+            }
+        }
+        // The handler may have replaced or cleared itself.
+        if self.spawn_handler.is_none() {
+            self.spawn_handler = Some(handler);
+        }
     }
 
     /// Run the given executor to completion.
@@ -289,6 +1019,188 @@ impl Lua {
         self.finish(executor).map_err(RuntimeError::new)?;
         self.try_enter(|ctx| ctx.fetch(executor).take_result::<R>(ctx)?)
     }
+
+    /// Like [`Lua::execute`], but an error is stashed rather than converted to an [`ExternError`].
+    ///
+    /// A table or userdata raised as an error by the script can then be fetched in a later call to
+    /// [`Lua::enter`] and inspected, see [`Error::downcast_static`].
+    pub fn execute_stashed<R: for<'gc> FromMultiValue<'gc>>(
+        &mut self,
+        executor: &StashedExecutor,
+    ) -> Result<R, StashedError> {
+        self.finish(executor)?;
+        self.enter(|ctx| {
+            ctx.fetch(executor)
+                .take_result::<R>(ctx)?
+                .map_err(|err| ctx.stash(err))
+        })
+    }
+
+    /// Returns a [`Future`] which runs the given executor to completion and then takes return
+    /// values from the returning thread, like `Lua::execute`.
+    ///
+    /// The future steps the executor a limited amount every time it is polled and yields to the
+    /// async runtime in between, so a long running script does not block other tasks.
+    pub fn execute_async<R: for<'gc> FromMultiValue<'gc>>(
+        &mut self,
+        executor: &StashedExecutor,
+    ) -> ExecuteFuture<'_, R> {
+        ExecuteFuture {
+            lua: self,
+            executor: executor.clone(),
+            fuel_per_poll: ExecuteFuture::<R>::DEFAULT_FUEL_PER_POLL,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Evaluate a single Lua expression from an untrusted source and convert its value to `R`.
+    ///
+    /// The source must be a single expression (as if preceded by `return`), and `while`, `repeat`,
+    /// and `goto` are rejected anywhere within it. This does not guarantee termination on its own,
+    /// since the expression may still recurse or call functions which loop, so evaluation also
+    /// fails once it has used `fuel` fuel or nested more than `max_depth` function calls.
+    ///
+    /// The expression is evaluated in a new environment built with `sandbox`, so it can only reach
+    /// the globals which `sandbox` allows. An empty [`SandboxBuilder`] evaluates it in an empty
+    /// environment.
+    pub fn eval_config<R: for<'gc> FromMultiValue<'gc>>(
+        &mut self,
+        source: &str,
+        sandbox: &SandboxBuilder,
+        fuel: i32,
+        max_depth: usize,
+    ) -> Result<R, EvalConfigError> {
+        let executor = self.enter(|ctx| {
+            let env = sandbox.build(ctx).map_err(EvalConfigError::Sandbox)?;
+            let closure = Closure::load_with_options(
+                ctx,
+                Some("config"),
+                source.as_bytes(),
+                env,
+                CompileOptions {
+                    restrictions: Restrictions {
+                        expression_only: true,
+                        ..Restrictions::NO_UNBOUNDED_LOOPS
+                    },
+                    ..Default::default()
+                },
+            )
+            .map_err(EvalConfigError::Compile)?;
+
+            let thread = Thread::new(ctx);
+            thread.set_stack_limits(StackLimits {
+                max_frames: max_depth,
+                ..StackLimits::DEFAULT
+            });
+            thread.start(ctx, closure.into(), ()).unwrap();
+            Ok::<_, EvalConfigError>(ctx.stash(Executor::run(&ctx, thread).unwrap()))
+        })?;
+
+        if !self
+            .finish_with_fuel(&executor, fuel)
+            .map_err(|e| EvalConfigError::Runtime(RuntimeError::new(e).into()))?
+        {
+            return Err(EvalConfigError::OutOfFuel);
+        }
+
+        self.try_enter(|ctx| ctx.fetch(&executor).take_result::<R>(ctx)?)
+            .map_err(EvalConfigError::Runtime)
+    }
+
+    /// Load a module from source in a fresh environment and return its module table.
+    ///
+    /// A new environment is built with `sandbox`, the chunk is loaded with that environment as its
+    /// `_ENV`, and then run to completion using at most `fuel` fuel. The table returned by the
+    /// chunk becomes the module table. Modules which set globals rather than returning anything
+    /// produce their environment table instead.
+    ///
+    /// This is the same flow as `require`, without needing the package library to be loaded.
+    pub fn load_module(
+        &mut self,
+        name: &str,
+        source: &[u8],
+        sandbox: &SandboxBuilder,
+        fuel: i32,
+    ) -> Result<StashedTable, ModuleError> {
+        let (executor, env) = self.enter(|ctx| {
+            let env = sandbox.build(ctx).map_err(ModuleError::Sandbox)?;
+            let closure = Closure::load_with_env(ctx, Some(name), source, env)
+                .map_err(ModuleError::Compile)?;
+            Ok::<_, ModuleError>((
+                ctx.stash(Executor::start(ctx, closure.into(), ())),
+                ctx.stash(env),
+            ))
+        })?;
+
+        if !self
+            .finish_with_fuel(&executor, fuel)
+            .map_err(|e| ModuleError::Runtime(RuntimeError::new(e).into()))?
+        {
+            return Err(ModuleError::OutOfFuel);
+        }
+
+        self.enter(|ctx| {
+            let module = ctx
+                .fetch(&executor)
+                .take_result::<Value>(ctx)
+                .map_err(|e| ModuleError::Runtime(RuntimeError::new(e).into()))?
+                .map_err(|e| ModuleError::Runtime(e.into_extern()))?;
+            match module {
+                Value::Nil => Ok(env),
+                Value::Table(module) => Ok(ctx.stash(module)),
+                module => Err(ModuleError::NotATable(module.type_name())),
+            }
+        })
+    }
+
+    // Step the executor until it finishes or has consumed `limit` fuel, returning whether it
+    // finished.
+    fn finish_with_fuel(
+        &mut self,
+        executor: &StashedExecutor,
+        limit: i32,
+    ) -> Result<bool, BadThreadMode> {
+        const FUEL_PER_GC: i32 = 4096;
+
+        let mut remaining = limit;
+        while remaining > 0 {
+            let mut fuel = Fuel::with(remaining.min(FUEL_PER_GC));
+            let start = fuel.remaining();
+            if self.enter(|ctx| ctx.fetch(executor).step(ctx, &mut fuel))? {
+                return Ok(true);
+            }
+            remaining -= start - fuel.remaining();
+        }
+        Ok(false)
+    }
+}
+
+/// An error returned by [`Lua::eval_config`].
+#[derive(Debug, Error)]
+pub enum EvalConfigError {
+    #[error("could not build config environment")]
+    Sandbox(#[source] SandboxError),
+    #[error("invalid config expression")]
+    Compile(#[source] CompilerError),
+    #[error("config evaluation exceeded its fuel limit")]
+    OutOfFuel,
+    #[error("config evaluation failed: {0}")]
+    Runtime(#[source] ExternError),
+}
+
+/// An error returned by [`Lua::load_module`].
+#[derive(Debug, Error)]
+pub enum ModuleError {
+    #[error("could not build module environment")]
+    Sandbox(#[source] SandboxError),
+    #[error("invalid module source")]
+    Compile(#[source] CompilerError),
+    #[error("module loading exceeded its fuel limit")]
+    OutOfFuel,
+    #[error("module loading failed: {0}")]
+    Runtime(#[source] ExternError),
+    #[error("module returned a {0} value rather than a table")]
+    NotATable(&'static str),
 }
 
 #[derive(Copy, Clone, Collect)]
@@ -298,6 +1210,19 @@ struct State<'gc> {
     registry: Registry<'gc>,
     strings: InternedStringSet<'gc>,
     finalizers: Finalizers<'gc>,
+    chunk_cache: ChunkCache<'gc>,
+    string_metatable: Gc<'gc, Lock<Option<Table<'gc>>>>,
+    spawned: Gc<'gc, RefLock<vec::Vec<Thread<'gc>, MetricsAlloc<'gc>>>>,
+    memory_limit: Gc<'gc, MemoryLimit>,
+    gc_control: Gc<'gc, GcControl>,
+    #[collect(require_static)]
+    recursion_limits: Cell<RecursionLimits>,
+    scratch: Gc<'gc, Scratch>,
+    output: Gc<'gc, Output>,
+    random: Gc<'gc, Random>,
+    collator: Gc<'gc, Collator>,
+    #[cfg(feature = "test-support")]
+    test_support: Gc<'gc, TestSupport>,
 }
 
 impl<'gc> State<'gc> {
@@ -307,6 +1232,18 @@ impl<'gc> State<'gc> {
             registry: Registry::new(mc),
             strings: InternedStringSet::new(mc),
             finalizers: Finalizers::new(mc),
+            chunk_cache: ChunkCache::new(mc),
+            string_metatable: Gc::new(mc, Lock::new(None)),
+            spawned: Gc::new(mc, RefLock::new(vec::Vec::new_in(MetricsAlloc::new(mc)))),
+            memory_limit: Gc::new(mc, MemoryLimit::new()),
+            gc_control: Gc::new(mc, GcControl::new()),
+            recursion_limits: Cell::new(RecursionLimits::DEFAULT),
+            scratch: Gc::new(mc, Scratch::new()),
+            output: Gc::new(mc, Output::new()),
+            random: Gc::new(mc, Random::new()),
+            collator: Gc::new(mc, Collator::new()),
+            #[cfg(feature = "test-support")]
+            test_support: Gc::new(mc, TestSupport::default()),
         }
     }
 
@@ -316,4 +1253,75 @@ impl<'gc> State<'gc> {
             state: self,
         }
     }
+
+    fn take_spawned(&self, ctx: Context<'gc>) -> Vec<StashedThread> {
+        self.spawned
+            .borrow_mut(&ctx)
+            .drain(..)
+            .map(|thread| ctx.stash(thread))
+            .collect()
+    }
+}
+
+type SpawnHandler = dyn FnMut(&mut Lua, StashedThread);
+
+/// A [`Future`] which runs an [`Executor`] to completion, returned by [`Lua::execute_async`].
+///
+/// Every poll steps the executor once with [`ExecuteFuture::fuel_per_poll`] fuel. If the executor
+/// has not finished, the future immediately wakes itself and returns `Poll::Pending`, giving the
+/// async runtime a chance to run other tasks.
+///
+/// The task's waker is given to the executor with [`Executor::set_waker`], so while a sequence is
+/// waiting on an external future (see [`Executor::is_awaiting_external`]), the future is only
+/// polled again once that external future has been woken.
+#[must_use = "futures do nothing unless polled"]
+pub struct ExecuteFuture<'a, R> {
+    lua: &'a mut Lua,
+    executor: StashedExecutor,
+    fuel_per_poll: i32,
+    _marker: PhantomData<fn() -> R>,
+}
+
+impl<'a, R> ExecuteFuture<'a, R> {
+    pub const DEFAULT_FUEL_PER_POLL: i32 = 4096;
+
+    /// Set the amount of fuel that the executor is stepped with on every poll, defaults to
+    /// [`ExecuteFuture::DEFAULT_FUEL_PER_POLL`].
+    pub fn fuel_per_poll(mut self, fuel: i32) -> Self {
+        self.fuel_per_poll = fuel;
+        self
+    }
+}
+
+impl<'a, R: for<'gc> FromMultiValue<'gc>> Future for ExecuteFuture<'a, R> {
+    type Output = Result<R, ExternError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let executor = &this.executor;
+
+        let mut fuel = Fuel::with(this.fuel_per_poll);
+        match this.lua.enter(|ctx| {
+            let executor = ctx.fetch(executor);
+            if executor.is_awaiting_external() {
+                // We have been woken, so let the waiting sequence check on its future again.
+                executor.resume(ctx, ()).unwrap();
+            }
+            executor.set_waker(&ctx, Some(cx.waker().clone()))?;
+            let finished = executor.step(ctx, &mut fuel)?;
+            Ok::<_, BadThreadMode>((finished, executor.is_awaiting_external()))
+        }) {
+            Ok((true, true)) => Poll::Pending,
+            Ok((true, false)) => Poll::Ready(this.lua.try_enter(|ctx| {
+                let executor = ctx.fetch(executor);
+                executor.set_waker(&ctx, None)?;
+                executor.take_result::<R>(ctx)?
+            })),
+            Ok((false, _)) => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            Err(err) => Poll::Ready(Err(RuntimeError::new(err).into())),
+        }
+    }
 }
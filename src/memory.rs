@@ -0,0 +1,206 @@
+use std::cell::Cell;
+
+use gc_arena::Collect;
+
+/// A ceiling on the memory used by a [`Lua`](crate::Lua) instance.
+///
+/// Every `Lua` instance has its own `MemoryLimit`, which is unlimited by default and can be set
+/// with [`Lua::set_memory_limit`](crate::Lua::set_memory_limit) or [`MemoryLimit::set_limit`]. The
+/// memory used is the same as [`Lua::total_memory`](crate::Lua::total_memory).
+///
+/// Allocation itself cannot fail, so the limit is instead checked by the [`Executor`] every time
+/// it resumes a Lua function, which happens at least every few dozen VM instructions. Instructions
+/// which may allocate an amount of memory controlled by the script, such as concatenation or
+/// creating and growing tables, also check the size they are about to allocate beforehand, as does
+/// `table.concat`. When the limit is exceeded, the executor first stops early so that [`Lua::enter`](crate::Lua::enter)
+/// can perform a full garbage collection, and if the limit is *still* exceeded the next time it is
+/// checked, the running Lua function raises a "not enough memory" error. This error can be caught
+/// with `pcall` like any other error, and it is raised again for as long as the script stays over
+/// the limit.
+///
+/// Since other allocations are only checked periodically, a script may briefly overshoot the limit,
+/// so it should leave some headroom below the memory the host can actually spare.
+///
+/// [`Executor`]: crate::Executor
+#[derive(Debug, Default, Collect)]
+#[collect(require_static)]
+pub struct MemoryLimit {
+    limit: Cell<Option<usize>>,
+    state: Cell<LimitState>,
+    // The size of an allocation which was refused until garbage has been collected.
+    pending: Cell<usize>,
+}
+
+impl MemoryLimit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The maximum number of bytes which may be used, if any.
+    pub fn limit(&self) -> Option<usize> {
+        self.limit.get()
+    }
+
+    pub fn set_limit(&self, limit: Option<usize>) {
+        self.limit.set(limit);
+        self.state.set(LimitState::Ok);
+        self.pending.set(0);
+    }
+
+    /// Returns true if `usage` is over the limit.
+    pub fn is_exceeded(&self, usage: usize) -> bool {
+        self.limit.get().is_some_and(|limit| usage > limit)
+    }
+
+    /// Returns true if allocating `additional` more bytes on top of `usage` would exceed the limit.
+    pub fn would_exceed(&self, usage: usize, additional: usize) -> bool {
+        self.is_exceeded(usage.saturating_add(additional))
+    }
+
+    // Called by the executor at every point where it can raise a memory error.
+    pub(crate) fn check(&self, usage: usize) -> LimitCheck {
+        if !self.would_exceed(usage, self.pending.get()) {
+            self.state.set(LimitState::Ok);
+            self.pending.set(0);
+            return LimitCheck::Ok;
+        }
+
+        match self.state.get() {
+            LimitState::Ok => {
+                self.state.set(LimitState::CollectRequested);
+                LimitCheck::Collect
+            }
+            // If no collection happened since it was requested, the executor is being stepped
+            // without ever leaving the arena, so there is no point in waiting for one.
+            LimitState::CollectRequested | LimitState::Collected => {
+                self.state.set(LimitState::Ok);
+                self.pending.set(0);
+                LimitCheck::Exceeded
+            }
+        }
+    }
+
+    // Called by the VM before an allocation of `additional` bytes. If this returns `Collect`, the
+    // VM must stop before allocating, the executor then stops as well so that garbage can be
+    // collected, and takes the refused allocation into account when it next checks the limit.
+    pub(crate) fn check_allocation(&self, usage: usize, additional: usize) -> LimitCheck {
+        if !self.would_exceed(usage, additional) {
+            return LimitCheck::Ok;
+        }
+
+        match self.state.get() {
+            LimitState::Ok => {
+                self.pending.set(additional);
+                LimitCheck::Collect
+            }
+            LimitState::CollectRequested | LimitState::Collected => {
+                self.state.set(LimitState::Ok);
+                self.pending.set(0);
+                LimitCheck::Exceeded
+            }
+        }
+    }
+
+    // Returns true if the executor stopped early to request a full collection. The caller must
+    // then perform one.
+    pub(crate) fn take_collection_request(&self) -> bool {
+        if self.state.get() == LimitState::CollectRequested {
+            self.state.set(LimitState::Collected);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum LimitCheck {
+    Ok,
+    // Stop and exit the arena so that garbage can be collected before checking again.
+    Collect,
+    // Raise a memory error.
+    Exceeded,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+enum LimitState {
+    #[default]
+    Ok,
+    CollectRequested,
+    Collected,
+}
+
+/// Garbage collection requests made from inside the arena, such as by the `collectgarbage`
+/// function.
+///
+/// Garbage can only be collected in between calls to [`Lua::enter`](crate::Lua::enter), so
+/// requests are carried out once the arena is exited. A requested full collection also makes the
+/// running [`Executor`](crate::Executor) stop early at its next safe point, so that the collection
+/// happens before the requesting Lua code continues.
+#[derive(Debug, Collect)]
+#[collect(require_static)]
+pub struct GcControl {
+    running: Cell<bool>,
+    collection: Cell<CollectionRequest>,
+}
+
+impl Default for GcControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GcControl {
+    pub fn new() -> Self {
+        Self {
+            running: Cell::new(true),
+            collection: Cell::new(CollectionRequest::None),
+        }
+    }
+
+    /// Returns false if automatic collection has been stopped.
+    pub fn is_running(&self) -> bool {
+        self.running.get()
+    }
+
+    /// Stop or restart automatic collection.
+    ///
+    /// While stopped, [`Lua::enter`](crate::Lua::enter) never performs collection work on its
+    /// own, and garbage is only collected when explicitly requested.
+    pub fn set_running(&self, running: bool) {
+        self.running.set(running);
+    }
+
+    /// Request a full collection once the arena is exited.
+    pub fn request_collection(&self) {
+        self.collection.set(CollectionRequest::Requested);
+    }
+
+    pub fn is_collection_requested(&self) -> bool {
+        self.collection.get() != CollectionRequest::None
+    }
+
+    // Returns true if the executor should stop so that a requested collection can happen. This
+    // only returns true once per request, so that an executor stepped without ever leaving the
+    // arena still makes progress.
+    pub(crate) fn should_stop(&self) -> bool {
+        if self.collection.get() == CollectionRequest::Requested {
+            self.collection.set(CollectionRequest::Stopped);
+            true
+        } else {
+            false
+        }
+    }
+
+    // Returns true if a full collection was requested, the caller must then perform one.
+    pub(crate) fn take_collection_request(&self) -> bool {
+        self.collection.replace(CollectionRequest::None) != CollectionRequest::None
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum CollectionRequest {
+    None,
+    Requested,
+    Stopped,
+}
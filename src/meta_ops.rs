@@ -1,4 +1,4 @@
-use std::io::Write;
+use std::{io::Write, pin::Pin};
 
 use gc_arena::Collect;
 use thiserror::Error;
@@ -6,7 +6,8 @@ use thiserror::Error;
 use crate::async_callback::{AsyncSequence, Locals};
 use crate::{async_sequence, SequenceReturn, Stack};
 use crate::{
-    table::InvalidTableKey, Callback, CallbackReturn, Context, Function, IntoValue, Table, Value,
+    table::InvalidTableKey, value::FloatDisplay, BoxSequence, Callback, CallbackReturn, Context,
+    Error, Execution, Function, IntoValue, Sequence, SequencePoll, Table, Value,
 };
 
 /// An enum of every possible Lua metamethod.
@@ -131,6 +132,100 @@ pub enum MetaResult<'gc, const N: usize> {
     Call(MetaCall<'gc, N>),
 }
 
+impl<'gc, const N: usize> MetaCall<'gc, N> {
+    /// Finish a callback by calling this metamethod, returning its first result to the caller of
+    /// the callback.
+    ///
+    /// This replaces the contents of the stack with the metamethod arguments.
+    pub fn into_callback_return(
+        self,
+        ctx: Context<'gc>,
+        stack: Stack<'gc, '_>,
+    ) -> CallbackReturn<'gc> {
+        self.call_then(ctx, stack, false)
+    }
+
+    fn call_then(
+        self,
+        ctx: Context<'gc>,
+        mut stack: Stack<'gc, '_>,
+        to_bool: bool,
+    ) -> CallbackReturn<'gc> {
+        #[derive(Collect)]
+        #[collect(require_static)]
+        struct FirstResult {
+            to_bool: bool,
+        }
+
+        impl<'gc> Sequence<'gc> for FirstResult {
+            fn poll(
+                self: Pin<&mut Self>,
+                _: Context<'gc>,
+                _: Execution<'gc, '_>,
+                mut stack: Stack<'gc, '_>,
+            ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+                let value = stack.get(0);
+                stack.clear();
+                stack.push_back(if self.to_bool {
+                    Value::Boolean(value.to_bool())
+                } else {
+                    value
+                });
+                Ok(SequencePoll::Return)
+            }
+        }
+
+        stack.clear();
+        stack.extend(self.args);
+        CallbackReturn::Call {
+            function: self.function,
+            then: Some(BoxSequence::new(&ctx, FirstResult { to_bool })),
+        }
+    }
+}
+
+impl<'gc, const N: usize> MetaResult<'gc, N> {
+    /// Finish a callback with the result of this metaoperation.
+    ///
+    /// A finished value is returned directly, while a metamethod is called with the stack replaced
+    /// by its arguments, and its first result is returned. This allows a callback to end with an
+    /// operation done exactly the way Lua would do it, such as with [`index`] or [`add`], without
+    /// needing a sequence to wait for the metamethod.
+    pub fn into_callback_return(
+        self,
+        ctx: Context<'gc>,
+        stack: Stack<'gc, '_>,
+    ) -> CallbackReturn<'gc> {
+        self.into_callback_return_with(ctx, stack, false)
+    }
+
+    /// Like [`MetaResult::into_callback_return`], but the result of a metamethod is converted to a
+    /// boolean, as Lua does for the comparison metamethods used by [`equal`], [`less_than`], and
+    /// [`less_equal`].
+    pub fn into_callback_return_bool(
+        self,
+        ctx: Context<'gc>,
+        stack: Stack<'gc, '_>,
+    ) -> CallbackReturn<'gc> {
+        self.into_callback_return_with(ctx, stack, true)
+    }
+
+    fn into_callback_return_with(
+        self,
+        ctx: Context<'gc>,
+        mut stack: Stack<'gc, '_>,
+        to_bool: bool,
+    ) -> CallbackReturn<'gc> {
+        match self {
+            MetaResult::Value(value) => {
+                stack.replace(ctx, value);
+                CallbackReturn::Return
+            }
+            MetaResult::Call(call) => call.call_then(ctx, stack, to_bool),
+        }
+    }
+}
+
 impl<'gc, const N: usize> From<Value<'gc>> for MetaResult<'gc, N> {
     fn from(value: Value<'gc>) -> Self {
         Self::Value(value)
@@ -155,12 +250,21 @@ pub enum MetaOperatorError {
     IndexKeyError(#[from] InvalidTableKey),
     #[error("concatenation result is too long")]
     ConcatOverflow,
+    #[error("'{}' chain too long; possible loop", .0.name())]
+    ChainTooLong(MetaMethod),
 }
 
 #[derive(Debug, Copy, Clone, Error)]
 #[error("could not call a {} value", .0)]
 pub struct MetaCallError(&'static str);
 
+impl MetaCallError {
+    /// The type name of the value which could not be called.
+    pub fn type_name(&self) -> &'static str {
+        self.0
+    }
+}
+
 fn get_metatable<'gc>(val: Value<'gc>) -> Option<Table<'gc>> {
     match val {
         Value::Table(t) => t.metatable(),
@@ -179,16 +283,27 @@ fn get_metamethod<'gc>(
         .filter(|v| !v.is_nil())
 }
 
-pub fn index<'gc>(
+/// The maximum number of `__index` or `__newindex` tables followed by [`index_chain`] and
+/// [`new_index_chain`] before giving up.
+pub const MAX_META_CHAIN: usize = 2000;
+
+// The result of a single step of indexing, either the final value or the `__index` metamethod to
+// continue with.
+enum IndexLookup<'gc> {
+    Value(Value<'gc>),
+    Meta(Value<'gc>),
+}
+
+fn index_lookup<'gc>(
     ctx: Context<'gc>,
     table: Value<'gc>,
     key: Value<'gc>,
-) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
-    let idx = match table {
+) -> Result<IndexLookup<'gc>, MetaOperatorError> {
+    match table {
         Value::Table(table) => {
             let v = table.get_value(ctx, key);
             if !v.is_nil() {
-                return Ok(MetaResult::Value(v));
+                return Ok(IndexLookup::Value(v));
             }
 
             let idx = if let Some(mt) = table.metatable() {
@@ -198,10 +313,10 @@ pub fn index<'gc>(
             };
 
             if idx.is_nil() {
-                return Ok(MetaResult::Value(Value::Nil));
+                return Ok(IndexLookup::Value(Value::Nil));
             }
 
-            idx
+            Ok(IndexLookup::Meta(idx))
         }
         Value::UserData(u) if u.metatable().is_some() => {
             let idx = if let Some(mt) = u.metatable() {
@@ -217,14 +332,41 @@ pub fn index<'gc>(
                 ));
             }
 
-            idx
+            Ok(IndexLookup::Meta(idx))
         }
-        _ => {
-            return Err(MetaOperatorError::Unary(
-                MetaMethod::Index,
-                table.type_name(),
-            ))
+        Value::String(_) => {
+            let idx = match ctx.string_metatable() {
+                Some(mt) => mt.get_value(ctx, MetaMethod::Index),
+                None => Value::Nil,
+            };
+
+            match idx {
+                Value::Nil => Err(MetaOperatorError::Unary(
+                    MetaMethod::Index,
+                    table.type_name(),
+                )),
+                // Look string methods up directly rather than through a callback.
+                Value::Table(t) if t.metatable().is_none() => {
+                    Ok(IndexLookup::Value(t.get_value(ctx, key)))
+                }
+                idx => Ok(IndexLookup::Meta(idx)),
+            }
         }
+        _ => Err(MetaOperatorError::Unary(
+            MetaMethod::Index,
+            table.type_name(),
+        )),
+    }
+}
+
+pub fn index<'gc>(
+    ctx: Context<'gc>,
+    table: Value<'gc>,
+    key: Value<'gc>,
+) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
+    let idx = match index_lookup(ctx, table, key)? {
+        IndexLookup::Value(v) => return Ok(MetaResult::Value(v)),
+        IndexLookup::Meta(idx) => idx,
     };
 
     // NOTE: The __index metamethod (and others) can easily infinite loop or enter arbitrarily long
@@ -279,13 +421,15 @@ pub fn index<'gc>(
     }))
 }
 
-pub fn new_index<'gc>(
+// Perform a single step of a new index operation, returning the `__newindex` metamethod to
+// continue with if the value was not set.
+fn new_index_lookup<'gc>(
     ctx: Context<'gc>,
     table: Value<'gc>,
     key: Value<'gc>,
     value: Value<'gc>,
-) -> Result<Option<MetaCall<'gc, 3>>, MetaOperatorError> {
-    let idx = match table {
+) -> Result<Option<Value<'gc>>, MetaOperatorError> {
+    match table {
         Value::Table(table) => {
             let v = table.get_value(ctx, key);
             if !v.is_nil() {
@@ -307,7 +451,7 @@ pub fn new_index<'gc>(
                 return Ok(None);
             }
 
-            idx
+            Ok(Some(idx))
         }
         Value::UserData(u) if u.metatable().is_some() => {
             let idx = if let Some(mt) = u.metatable() {
@@ -317,16 +461,29 @@ pub fn new_index<'gc>(
             };
 
             if idx.is_nil() {
-                return Err(
-                    MetaOperatorError::Unary(MetaMethod::NewIndex, table.type_name()).into(),
-                );
+                return Err(MetaOperatorError::Unary(
+                    MetaMethod::NewIndex,
+                    table.type_name(),
+                ));
             }
 
-            idx
-        }
-        _ => {
-            return Err(MetaOperatorError::Unary(MetaMethod::NewIndex, table.type_name()).into());
+            Ok(Some(idx))
         }
+        _ => Err(MetaOperatorError::Unary(
+            MetaMethod::NewIndex,
+            table.type_name(),
+        )),
+    }
+}
+
+pub fn new_index<'gc>(
+    ctx: Context<'gc>,
+    table: Value<'gc>,
+    key: Value<'gc>,
+    value: Value<'gc>,
+) -> Result<Option<MetaCall<'gc, 3>>, MetaOperatorError> {
+    let Some(idx) = new_index_lookup(ctx, table, key, value)? else {
+        return Ok(None);
     };
 
     Ok(Some(match idx {
@@ -355,7 +512,70 @@ pub fn new_index<'gc>(
     }))
 }
 
+/// Like [`index`], but follows chains of `__index` tables and userdata directly rather than
+/// returning a [`MetaCall`] for every step of the chain.
+///
+/// A call is only returned once an `__index` function must actually be run to produce the value,
+/// and it is called with the table or userdata it was found on, exactly like Lua would. Returns
+/// an error if the chain is longer than [`MAX_META_CHAIN`].
+pub fn index_chain<'gc>(
+    ctx: Context<'gc>,
+    mut table: Value<'gc>,
+    key: Value<'gc>,
+) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
+    for _ in 0..MAX_META_CHAIN {
+        match index_lookup(ctx, table, key)? {
+            IndexLookup::Value(v) => return Ok(MetaResult::Value(v)),
+            IndexLookup::Meta(idx @ (Value::Table(_) | Value::UserData(_))) => table = idx,
+            IndexLookup::Meta(idx) => {
+                return Ok(MetaResult::Call(MetaCall {
+                    function: call(ctx, idx)
+                        .map_err(|e| MetaOperatorError::Call(MetaMethod::Index, e))?,
+                    args: [table, key],
+                }))
+            }
+        }
+    }
+    Err(MetaOperatorError::ChainTooLong(MetaMethod::Index))
+}
+
+/// Like [`new_index`], but follows chains of `__newindex` tables and userdata directly rather
+/// than returning a [`MetaCall`] for every step of the chain.
+///
+/// A call is only returned once a `__newindex` function must actually be run to set the value.
+/// Returns an error if the chain is longer than [`MAX_META_CHAIN`].
+pub fn new_index_chain<'gc>(
+    ctx: Context<'gc>,
+    mut table: Value<'gc>,
+    key: Value<'gc>,
+    value: Value<'gc>,
+) -> Result<Option<MetaCall<'gc, 3>>, MetaOperatorError> {
+    for _ in 0..MAX_META_CHAIN {
+        match new_index_lookup(ctx, table, key, value)? {
+            None => return Ok(None),
+            Some(idx @ (Value::Table(_) | Value::UserData(_))) => table = idx,
+            Some(idx) => {
+                return Ok(Some(MetaCall {
+                    function: call(ctx, idx)
+                        .map_err(|e| MetaOperatorError::Call(MetaMethod::NewIndex, e))?,
+                    args: [table, key, value],
+                }))
+            }
+        }
+    }
+    Err(MetaOperatorError::ChainTooLong(MetaMethod::NewIndex))
+}
+
 pub fn call<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Result<Function<'gc>, MetaCallError> {
+    call_chain(ctx, v, 1)
+}
+
+// Resolves `v` as the `depth`th value in a chain of `__call` metamethods.
+fn call_chain<'gc>(
+    ctx: Context<'gc>,
+    v: Value<'gc>,
+    depth: usize,
+) -> Result<Function<'gc>, MetaCallError> {
     let metatable = match v {
         Value::Function(f) => return Ok(f),
         Value::Table(t) => t.metatable(),
@@ -366,13 +586,20 @@ pub fn call<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Result<Function<'gc>, Meta
 
     match metatable.get_value(ctx, MetaMethod::Call) {
         f @ (Value::Function(_) | Value::Table(_) | Value::UserData(_)) => Ok(
-            // NOTE: Potential for infinite or arbitrarily long chains here, see note in __index.
+            // Chains may be infinite, so their length is limited by `RecursionLimits`.
             //
             // Example: `t = {}; setmetatable(t, { __call = t }); t()`
-            Callback::from_fn_with(&ctx, (v, f), |&(v, f), ctx, _, mut stack| {
+            Callback::from_fn_with(&ctx, (v, f), move |&(v, f), ctx, _, mut stack| {
+                if depth >= ctx.recursion_limits().max_call_chain
+                    && !matches!(f, Value::Function(_))
+                {
+                    return Err("'__call' chain too long; possible loop"
+                        .into_value(ctx)
+                        .into());
+                }
                 stack.push_front(v);
                 Ok(CallbackReturn::Call {
-                    function: call(ctx, f)?,
+                    function: call_chain(ctx, f, depth + 1)?,
                     then: None,
                 })
             })
@@ -382,6 +609,37 @@ pub fn call<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Result<Function<'gc>, Meta
     }
 }
 
+/// Return value for [`pairs`].
+#[derive(Debug, Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub enum MetaPairs<'gc> {
+    /// The value has no `__pairs` metamethod and is a table, iterate it with `next` (or with
+    /// [`Table::iter`] from Rust).
+    Table(Table<'gc>),
+    /// The value has a `__pairs` metamethod. Calling it produces an iterator function, a state
+    /// value and an initial control value, exactly like `pairs` in Lua.
+    Call(MetaCall<'gc, 1>),
+}
+
+/// Determine how to iterate over a value the way `pairs` does, respecting the `__pairs`
+/// metamethod.
+///
+/// This allows callbacks to iterate over proxy tables and userdata which implement `__pairs`.
+pub fn pairs<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Result<MetaPairs<'gc>, MetaOperatorError> {
+    if let Some(pairs) = get_metamethod(ctx, v, MetaMethod::Pairs) {
+        return Ok(MetaPairs::Call(MetaCall {
+            function: call(ctx, pairs)
+                .map_err(|e| MetaOperatorError::Call(MetaMethod::Pairs, e))?,
+            args: [v],
+        }));
+    }
+
+    match v {
+        Value::Table(t) => Ok(MetaPairs::Table(t)),
+        v => Err(MetaOperatorError::Unary(MetaMethod::Pairs, v.type_name())),
+    }
+}
+
 pub fn len<'gc>(ctx: Context<'gc>, v: Value<'gc>) -> Result<MetaResult<'gc, 1>, MetaOperatorError> {
     if let Some(metatable) = match v {
         Value::Table(t) => t.metatable(),
@@ -728,7 +986,15 @@ pub fn less_than<'gc>(
     lhs: Value<'gc>,
     rhs: Value<'gc>,
 ) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
-    meta_metaop(ctx, lhs, rhs, MetaMethod::Lt, |_, a, b| {
+    meta_metaop(ctx, lhs, rhs, MetaMethod::Lt, |ctx, a, b| {
+        if let (Value::String(a), Value::String(b)) = (a, b) {
+            return Some(
+                ctx.collator()
+                    .compare(a.as_bytes(), b.as_bytes())
+                    .is_lt()
+                    .into(),
+            );
+        }
         Some(a.to_constant()?.less_than(&b.to_constant()?)?.into())
     })
 }
@@ -738,7 +1004,15 @@ pub fn less_equal<'gc>(
     lhs: Value<'gc>,
     rhs: Value<'gc>,
 ) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
-    meta_metaop(ctx, lhs, rhs, MetaMethod::Le, |_, a, b| {
+    meta_metaop(ctx, lhs, rhs, MetaMethod::Le, |ctx, a, b| {
+        if let (Value::String(a), Value::String(b)) = (a, b) {
+            return Some(
+                ctx.collator()
+                    .compare(a.as_bytes(), b.as_bytes())
+                    .is_le()
+                    .into(),
+            );
+        }
         Some(a.to_constant()?.less_equal(&b.to_constant()?)?.into())
     })
 }
@@ -764,7 +1038,7 @@ pub fn concat<'gc>(
             for value in [a, b] {
                 match value {
                     Value::Integer(i) => write!(&mut bytes, "{}", i).unwrap(),
-                    Value::Number(n) => write!(&mut bytes, "{}", n).unwrap(),
+                    Value::Number(n) => write!(&mut bytes, "{}", FloatDisplay(n)).unwrap(),
                     Value::String(s) => bytes.extend(s.as_bytes()),
                     _ => return None,
                 }
@@ -778,7 +1052,7 @@ pub fn concat<'gc>(
 
 /// Returns an estimate of the length of the concatenation of a list of values,
 /// or returns [`None`] if any value is not implicitly coercible to a string.
-fn estimate_concatenated_len<'gc>(
+pub(crate) fn estimate_concatenated_len<'gc>(
     values: &[Value<'gc>],
 ) -> Result<Option<usize>, MetaOperatorError> {
     let mut len = 0usize;
@@ -809,11 +1083,12 @@ pub fn concat_many<'gc>(
             break;
         };
 
-        let mut bytes = Vec::with_capacity(len);
+        let mut bytes = ctx.scratch_buffer();
+        bytes.reserve(len);
         for value in values {
             match value {
-                Value::Integer(i) => write!(&mut bytes, "{}", i).unwrap(),
-                Value::Number(n) => write!(&mut bytes, "{}", n).unwrap(),
+                Value::Integer(i) => write!(&mut *bytes, "{}", i).unwrap(),
+                Value::Number(n) => write!(&mut *bytes, "{}", FloatDisplay(*n)).unwrap(),
                 Value::String(s) => bytes.extend(s.as_bytes()),
                 _ => unreachable!(),
             }
@@ -870,14 +1145,14 @@ pub fn concat_separated<'gc>(
             .and_then(|l| l.checked_add(len))
             .ok_or(MetaOperatorError::ConcatOverflow)?;
 
-        // Should this be allocated in-place in the GC heap?
-        let mut bytes = Vec::with_capacity(total_len);
+        let mut bytes = ctx.scratch_buffer();
+        bytes.reserve(total_len);
 
         let mut iter = values.iter();
         if let Some(val) = iter.next() {
             match val {
-                Value::Integer(i) => write!(&mut bytes, "{}", i).unwrap(),
-                Value::Number(n) => write!(&mut bytes, "{}", n).unwrap(),
+                Value::Integer(i) => write!(&mut *bytes, "{}", i).unwrap(),
+                Value::Number(n) => write!(&mut *bytes, "{}", FloatDisplay(*n)).unwrap(),
                 Value::String(s) => bytes.extend(s.as_bytes()),
                 _ => unreachable!(),
             }
@@ -885,8 +1160,8 @@ pub fn concat_separated<'gc>(
             while let Some(val) = iter.next() {
                 bytes.extend(&*sep_str);
                 match val {
-                    Value::Integer(i) => write!(&mut bytes, "{}", i).unwrap(),
-                    Value::Number(n) => write!(&mut bytes, "{}", n).unwrap(),
+                    Value::Integer(i) => write!(&mut *bytes, "{}", i).unwrap(),
+                    Value::Number(n) => write!(&mut *bytes, "{}", FloatDisplay(*n)).unwrap(),
                     Value::String(s) => bytes.extend(s.as_bytes()),
                     _ => unreachable!(),
                 }
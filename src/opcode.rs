@@ -1,10 +1,13 @@
+use std::fmt;
+
 use gc_arena::Collect;
+use thiserror::Error;
 
 use crate::types::{
     ConstantIndex16, ConstantIndex8, Opt254, PrototypeIndex, RegisterIndex, UpValueIndex, VarCount,
 };
 
-#[derive(Debug, Copy, Clone, Collect)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Collect)]
 #[collect(require_static)]
 pub enum RCIndex {
     Register(RegisterIndex),
@@ -23,48 +26,74 @@ impl From<ConstantIndex8> for RCIndex {
     }
 }
 
-#[derive(Debug, Copy, Clone, Collect)]
+impl fmt::Display for RCIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RCIndex::Register(r) => write!(f, "{r}"),
+            RCIndex::Constant(c) => write!(f, "{c}"),
+        }
+    }
+}
+
+/// A single VM instruction.
+///
+/// In the descriptions of each operation, `R(n)` is the register `n` in the current frame, `K(n)`
+/// is the constant `n` of the current prototype, `U(n)` is the upvalue `n` of the current closure,
+/// and `RC(x)` is either a register or a constant depending on the [`RCIndex`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Collect)]
 #[collect(require_static)]
 pub enum Operation {
+    /// R(dest) = R(source)
     Move {
         dest: RegisterIndex,
         source: RegisterIndex,
     },
+    /// R(dest) = K(constant)
     LoadConstant {
         dest: RegisterIndex,
         constant: ConstantIndex16,
     },
+    /// R(dest) = value
     LoadBool {
         dest: RegisterIndex,
         value: bool,
-        // If true, unconditionally skip the next instruction
+        /// If true, unconditionally skip the next instruction.
         skip_next: bool,
     },
-    // Load `count` Nil values starting at `dest`
-    LoadNil {
-        dest: RegisterIndex,
-        count: u8,
-    },
+    /// Set `count` registers starting at `dest` to nil.
+    LoadNil { dest: RegisterIndex, count: u8 },
+    /// R(dest) = {}
+    ///
+    /// The new table has space preallocated for `array_size` array entries and `map_size` map
+    /// entries.
     NewTable {
         dest: RegisterIndex,
         array_size: u8,
         map_size: u8,
     },
+    /// R(dest) = R(table)[RC(key)]
     GetTable {
         dest: RegisterIndex,
         table: RegisterIndex,
         key: RCIndex,
     },
+    /// R(table)[RC(key)] = RC(value)
     SetTable {
         table: RegisterIndex,
         key: RCIndex,
         value: RCIndex,
     },
+    /// R(dest) = U(table)[RC(key)]
+    ///
+    /// Used to read globals from the `_ENV` upvalue.
     GetUpTable {
         dest: RegisterIndex,
         table: UpValueIndex,
         key: RCIndex,
     },
+    /// U(table)[RC(key)] = RC(value)
+    ///
+    /// Used to write globals to the `_ENV` upvalue.
     SetUpTable {
         table: UpValueIndex,
         key: RCIndex,
@@ -93,10 +122,9 @@ pub enum Operation {
     /// Effectively, call the given function with the arguments placed after it, and return the
     /// results of this function to the upper frame. Invalidates the entire current frame and
     /// replaces it with the called function.
-    TailCall {
-        func: RegisterIndex,
-        args: VarCount,
-    },
+    TailCall { func: RegisterIndex, args: VarCount },
+    /// Return `count` values starting at the `start` register from the current function. If the
+    /// count is "variable", then every value from `start` to the top of the stack is returned.
     Return {
         start: RegisterIndex,
         count: VarCount,
@@ -108,17 +136,14 @@ pub enum Operation {
         dest: RegisterIndex,
         count: VarCount,
     },
-    Jump {
-        offset: i16,
-        // If set, close upvalues >= `close_upvalues`
-        close_upvalues: Opt254,
-    },
+    /// pc += offset
+    ///
+    /// If `close_upvalues` is set, also closes every upvalue for a register greater than or equal
+    /// to it.
+    Jump { offset: i16, close_upvalues: Opt254 },
     /// Test the register as a boolean, if its boolean value matches `is_true`, skip the next
     /// instruction.
-    Test {
-        value: RegisterIndex,
-        is_true: bool,
-    },
+    Test { value: RegisterIndex, is_true: bool },
     /// Test the value at the `value` register as a boolean, if its boolean value matches `is_true`,
     /// skip the next instruction, otherwise assign the given value (not converted to a boolean) to
     /// the destination register.
@@ -127,6 +152,7 @@ pub enum Operation {
         value: RegisterIndex,
         is_true: bool,
     },
+    /// R(dest) = a new closure of the prototype at index `proto` within the current prototype
     Closure {
         dest: RegisterIndex,
         proto: PrototypeIndex,
@@ -135,10 +161,7 @@ pub enum Operation {
     ///
     /// R(base) -= R(base + 2)
     /// pc += jump
-    NumericForPrep {
-        base: RegisterIndex,
-        jump: i16,
-    },
+    NumericForPrep { base: RegisterIndex, jump: i16 },
     /// Used to iterate a numeric for loop:
     ///
     /// R(base) += R(base + 2)
@@ -149,27 +172,18 @@ pub enum Operation {
     ///
     /// The `<?=` operator here means "less than" if the step (aka R(base + 2)) is positive, and
     /// "greater than" if the step is negative
-    NumericForLoop {
-        base: RegisterIndex,
-        jump: i16,
-    },
+    NumericForLoop { base: RegisterIndex, jump: i16 },
     /// Used to set up for a generic for loop:
     ///
     /// R(base + 3), ..., R(base + 2 + var_count) = R(base)(R(base + 1), R(base + 2))
-    GenericForCall {
-        base: RegisterIndex,
-        var_count: u8,
-    },
+    GenericForCall { base: RegisterIndex, var_count: u8 },
     /// Used to iterate a generic for loop:
     ///
     /// if R(base + 1) ~= nil then
     ///     R(base) = R(base + 1)
     ///     pc += jump
     /// end
-    GenericForLoop {
-        base: RegisterIndex,
-        jump: i16,
-    },
+    GenericForLoop { base: RegisterIndex, jump: i16 },
     /// Used for calling methods on tables:
     /// R(base + 1) = R(table)
     /// R(base) = R(table)[RC(key)]
@@ -178,118 +192,315 @@ pub enum Operation {
         table: RegisterIndex,
         key: RCIndex,
     },
-    /// Concatenate the given arguments into a string
+    /// R(dest) = R(source) .. R(source + 1) .. ... .. R(source + count - 1)
     Concat {
         dest: RegisterIndex,
         source: RegisterIndex,
         count: u8,
     },
+    /// R(dest) = U(source)
     GetUpValue {
         dest: RegisterIndex,
         source: UpValueIndex,
     },
+    /// U(dest) = R(source)
     SetUpValue {
         dest: UpValueIndex,
         source: RegisterIndex,
     },
+    /// R(dest) = #R(source)
     Length {
         dest: RegisterIndex,
         source: RegisterIndex,
     },
+    /// If (RC(left) == RC(right)) == skip_if, skip the next instruction.
     Eq {
         skip_if: bool,
         left: RCIndex,
         right: RCIndex,
     },
+    /// If (RC(left) < RC(right)) == skip_if, skip the next instruction.
     Less {
         skip_if: bool,
         left: RCIndex,
         right: RCIndex,
     },
+    /// If (RC(left) <= RC(right)) == skip_if, skip the next instruction.
     LessEq {
         skip_if: bool,
         left: RCIndex,
         right: RCIndex,
     },
+    /// R(dest) = not R(source)
     Not {
         dest: RegisterIndex,
         source: RegisterIndex,
     },
+    /// R(dest) = -R(source)
     Minus {
         dest: RegisterIndex,
         source: RegisterIndex,
     },
+    /// R(dest) = RC(left) + RC(right)
     Add {
         dest: RegisterIndex,
         left: RCIndex,
         right: RCIndex,
     },
+    /// R(dest) = RC(left) - RC(right)
     Sub {
         dest: RegisterIndex,
         left: RCIndex,
         right: RCIndex,
     },
+    /// R(dest) = RC(left) * RC(right)
     Mul {
         dest: RegisterIndex,
         left: RCIndex,
         right: RCIndex,
     },
+    /// R(dest) = RC(left) / RC(right)
     Div {
         dest: RegisterIndex,
         left: RCIndex,
         right: RCIndex,
     },
+    /// R(dest) = RC(left) // RC(right)
     IDiv {
         dest: RegisterIndex,
         left: RCIndex,
         right: RCIndex,
     },
+    /// R(dest) = RC(left) % RC(right)
     Mod {
         dest: RegisterIndex,
         left: RCIndex,
         right: RCIndex,
     },
+    /// R(dest) = RC(left) ^ RC(right)
     Pow {
         dest: RegisterIndex,
         left: RCIndex,
         right: RCIndex,
     },
+    /// R(dest) = RC(left) & RC(right)
     BitAnd {
         dest: RegisterIndex,
         left: RCIndex,
         right: RCIndex,
     },
+    /// R(dest) = RC(left) | RC(right)
     BitOr {
         dest: RegisterIndex,
         left: RCIndex,
         right: RCIndex,
     },
+    /// R(dest) = RC(left) ~ RC(right)
     BitXor {
         dest: RegisterIndex,
         left: RCIndex,
         right: RCIndex,
     },
+    /// R(dest) = RC(left) << RC(right)
     ShiftLeft {
         dest: RegisterIndex,
         left: RCIndex,
         right: RCIndex,
     },
+    /// R(dest) = RC(left) >> RC(right)
     ShiftRight {
         dest: RegisterIndex,
         left: RCIndex,
         right: RCIndex,
     },
+    /// R(dest) = ~R(source)
     BitNot {
         dest: RegisterIndex,
         source: RegisterIndex,
     },
 }
 
-#[derive(Debug, Copy, Clone, Collect)]
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Operation::Move { dest, source } => write!(f, "Move dest={dest} source={source}"),
+            Operation::LoadConstant { dest, constant } => {
+                write!(f, "LoadConstant dest={dest} constant={constant}")
+            }
+            Operation::LoadBool {
+                dest,
+                value,
+                skip_next,
+            } => write!(
+                f,
+                "LoadBool dest={dest} value={value} skip_next={skip_next}"
+            ),
+            Operation::LoadNil { dest, count } => write!(f, "LoadNil dest={dest} count={count}"),
+            Operation::NewTable {
+                dest,
+                array_size,
+                map_size,
+            } => write!(
+                f,
+                "NewTable dest={dest} array_size={array_size} map_size={map_size}"
+            ),
+            Operation::GetTable { dest, table, key } => {
+                write!(f, "GetTable dest={dest} table={table} key={key}")
+            }
+            Operation::SetTable { table, key, value } => {
+                write!(f, "SetTable table={table} key={key} value={value}")
+            }
+            Operation::GetUpTable { dest, table, key } => {
+                write!(f, "GetUpTable dest={dest} table={table} key={key}")
+            }
+            Operation::SetUpTable { table, key, value } => {
+                write!(f, "SetUpTable table={table} key={key} value={value}")
+            }
+            Operation::SetList { base, count } => write!(f, "SetList base={base} count={count}"),
+            Operation::Call {
+                func,
+                args,
+                returns,
+            } => write!(f, "Call func={func} args={args} returns={returns}"),
+            Operation::TailCall { func, args } => write!(f, "TailCall func={func} args={args}"),
+            Operation::Return { start, count } => write!(f, "Return start={start} count={count}"),
+            Operation::VarArgs { dest, count } => write!(f, "VarArgs dest={dest} count={count}"),
+            Operation::Jump {
+                offset,
+                close_upvalues,
+            } => write!(f, "Jump offset={offset} close_upvalues={close_upvalues}"),
+            Operation::Test { value, is_true } => write!(f, "Test value={value} is_true={is_true}"),
+            Operation::TestSet {
+                dest,
+                value,
+                is_true,
+            } => write!(f, "TestSet dest={dest} value={value} is_true={is_true}"),
+            Operation::Closure { dest, proto } => write!(f, "Closure dest={dest} proto={proto}"),
+            Operation::NumericForPrep { base, jump } => {
+                write!(f, "NumericForPrep base={base} jump={jump}")
+            }
+            Operation::NumericForLoop { base, jump } => {
+                write!(f, "NumericForLoop base={base} jump={jump}")
+            }
+            Operation::GenericForCall { base, var_count } => {
+                write!(f, "GenericForCall base={base} var_count={var_count}")
+            }
+            Operation::GenericForLoop { base, jump } => {
+                write!(f, "GenericForLoop base={base} jump={jump}")
+            }
+            Operation::Method { base, table, key } => {
+                write!(f, "Method base={base} table={table} key={key}")
+            }
+            Operation::Concat {
+                dest,
+                source,
+                count,
+            } => write!(f, "Concat dest={dest} source={source} count={count}"),
+            Operation::GetUpValue { dest, source } => {
+                write!(f, "GetUpValue dest={dest} source={source}")
+            }
+            Operation::SetUpValue { dest, source } => {
+                write!(f, "SetUpValue dest={dest} source={source}")
+            }
+            Operation::Length { dest, source } => write!(f, "Length dest={dest} source={source}"),
+            Operation::Eq {
+                skip_if,
+                left,
+                right,
+            } => write!(f, "Eq skip_if={skip_if} left={left} right={right}"),
+            Operation::Less {
+                skip_if,
+                left,
+                right,
+            } => write!(f, "Less skip_if={skip_if} left={left} right={right}"),
+            Operation::LessEq {
+                skip_if,
+                left,
+                right,
+            } => write!(f, "LessEq skip_if={skip_if} left={left} right={right}"),
+            Operation::Not { dest, source } => write!(f, "Not dest={dest} source={source}"),
+            Operation::Minus { dest, source } => write!(f, "Minus dest={dest} source={source}"),
+            Operation::Add { dest, left, right } => {
+                write!(f, "Add dest={dest} left={left} right={right}")
+            }
+            Operation::Sub { dest, left, right } => {
+                write!(f, "Sub dest={dest} left={left} right={right}")
+            }
+            Operation::Mul { dest, left, right } => {
+                write!(f, "Mul dest={dest} left={left} right={right}")
+            }
+            Operation::Div { dest, left, right } => {
+                write!(f, "Div dest={dest} left={left} right={right}")
+            }
+            Operation::IDiv { dest, left, right } => {
+                write!(f, "IDiv dest={dest} left={left} right={right}")
+            }
+            Operation::Mod { dest, left, right } => {
+                write!(f, "Mod dest={dest} left={left} right={right}")
+            }
+            Operation::Pow { dest, left, right } => {
+                write!(f, "Pow dest={dest} left={left} right={right}")
+            }
+            Operation::BitAnd { dest, left, right } => {
+                write!(f, "BitAnd dest={dest} left={left} right={right}")
+            }
+            Operation::BitOr { dest, left, right } => {
+                write!(f, "BitOr dest={dest} left={left} right={right}")
+            }
+            Operation::BitXor { dest, left, right } => {
+                write!(f, "BitXor dest={dest} left={left} right={right}")
+            }
+            Operation::ShiftLeft { dest, left, right } => {
+                write!(f, "ShiftLeft dest={dest} left={left} right={right}")
+            }
+            Operation::ShiftRight { dest, left, right } => {
+                write!(f, "ShiftRight dest={dest} left={left} right={right}")
+            }
+            Operation::BitNot { dest, source } => write!(f, "BitNot dest={dest} source={source}"),
+        }
+    }
+}
+
+/// The version of the numeric encoding used by [`OpCode::to_u32`] and [`OpCode::from_u32`].
+///
+/// The encoding of an existing operation never changes without incrementing this version, so any
+/// bytecode serialized with the same version can be decoded. Adding new operations does not change
+/// the version.
+pub const OPCODE_FORMAT_VERSION: u32 = 1;
+
+/// Returned by [`OpCode::from_u32`] for a value that is not the encoding of any operation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Error)]
+#[error("invalid opcode encoding {0:#010x}")]
+pub struct InvalidOpCode(pub u32);
+
+/// The compact form of an [`Operation`] stored in function prototypes.
+///
+/// Every opcode has a stable 32-bit encoding: the low byte is a number identifying the operation
+/// and which of its operands are constants, and the remaining three bytes are the operands in
+/// order, little-endian and zero padded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Collect)]
 #[collect(require_static)]
 pub struct OpCode(OpCodeRepr);
 
+impl fmt::Display for OpCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.decode().fmt(f)
+    }
+}
+
 impl OpCode {
+    /// Returns the stable numeric encoding of this opcode, see [`OPCODE_FORMAT_VERSION`].
+    pub fn to_u32(self) -> u32 {
+        self.0.to_u32()
+    }
+
+    /// Decode an opcode from its stable numeric encoding, see [`OPCODE_FORMAT_VERSION`].
+    pub fn from_u32(value: u32) -> Result<Self, InvalidOpCode> {
+        OpCodeRepr::from_u32(value)
+            .map(Self)
+            .ok_or(InvalidOpCode(value))
+    }
+
     pub fn encode(operation: Operation) -> Self {
         Self(match operation {
             Operation::Move { dest, source } => OpCodeRepr::Move { dest, source },
@@ -1155,7 +1366,7 @@ impl OpCode {
     }
 }
 
-#[derive(Debug, Copy, Clone, Collect)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Collect)]
 #[collect(require_static)]
 enum OpCodeRepr {
     Move {
@@ -1634,3 +1845,265 @@ enum OpCodeRepr {
         source: RegisterIndex,
     },
 }
+
+// Defines the stable numeric encoding of every `OpCodeRepr` variant.
+//
+// The numbers assigned here must never be changed or reused, new variants should only ever be
+// given new numbers.
+macro_rules! stable_encoding {
+    ($($code:literal => $name:ident { $($field:ident),* },)*) => {
+        impl OpCodeRepr {
+            fn to_u32(self) -> u32 {
+                let mut bytes = [0; 4];
+                let mut pos = 1;
+                match self {
+                    $(OpCodeRepr::$name { $($field),* } => {
+                        bytes[0] = $code;
+                        $($field.write(&mut bytes, &mut pos);)*
+                    })*
+                }
+                u32::from_le_bytes(bytes)
+            }
+
+            fn from_u32(value: u32) -> Option<Self> {
+                let bytes = value.to_le_bytes();
+                let mut pos = 1;
+                let repr = match bytes[0] {
+                    $($code => OpCodeRepr::$name {
+                        $($field: Operand::read(&bytes, &mut pos)?,)*
+                    },)*
+                    _ => return None,
+                };
+                // Unused bytes must be zero, so that every opcode has exactly one encoding.
+                if bytes[pos..].iter().any(|&b| b != 0) {
+                    return None;
+                }
+                Some(repr)
+            }
+        }
+    };
+}
+
+stable_encoding! {
+    0 => Move { dest, source },
+    1 => LoadConstant { dest, constant },
+    2 => LoadBool { dest, value, skip_next },
+    3 => LoadNil { dest, count },
+    4 => NewTable { dest, array_size, map_size },
+    5 => GetTableR { dest, table, key },
+    6 => GetTableC { dest, table, key },
+    7 => SetTableRR { table, key, value },
+    8 => SetTableRC { table, key, value },
+    9 => SetTableCR { table, key, value },
+    10 => SetTableCC { table, key, value },
+    11 => GetUpTableR { dest, table, key },
+    12 => GetUpTableC { dest, table, key },
+    13 => SetUpTableRR { table, key, value },
+    14 => SetUpTableRC { table, key, value },
+    15 => SetUpTableCR { table, key, value },
+    16 => SetUpTableCC { table, key, value },
+    17 => SetList { base, count },
+    18 => Call { func, args, returns },
+    19 => TailCall { func, args },
+    20 => Return { start, count },
+    21 => VarArgs { dest, count },
+    22 => Jump { offset, close_upvalues },
+    23 => Test { value, is_true },
+    24 => TestSet { dest, value, is_true },
+    25 => Closure { dest, proto },
+    26 => NumericForPrep { base, jump },
+    27 => NumericForLoop { base, jump },
+    28 => GenericForCall { base, var_count },
+    29 => GenericForLoop { base, jump },
+    30 => MethodR { base, table, key },
+    31 => MethodC { base, table, key },
+    32 => Concat { dest, source, count },
+    33 => GetUpValue { dest, source },
+    34 => SetUpValue { dest, source },
+    35 => Length { dest, source },
+    36 => EqRR { skip_if, left, right },
+    37 => EqRC { skip_if, left, right },
+    38 => EqCR { skip_if, left, right },
+    39 => EqCC { skip_if, left, right },
+    40 => LessRR { skip_if, left, right },
+    41 => LessRC { skip_if, left, right },
+    42 => LessCR { skip_if, left, right },
+    43 => LessCC { skip_if, left, right },
+    44 => LessEqRR { skip_if, left, right },
+    45 => LessEqRC { skip_if, left, right },
+    46 => LessEqCR { skip_if, left, right },
+    47 => LessEqCC { skip_if, left, right },
+    48 => Not { dest, source },
+    49 => Minus { dest, source },
+    50 => AddRR { dest, left, right },
+    51 => AddRC { dest, left, right },
+    52 => AddCR { dest, left, right },
+    53 => AddCC { dest, left, right },
+    54 => SubRR { dest, left, right },
+    55 => SubRC { dest, left, right },
+    56 => SubCR { dest, left, right },
+    57 => SubCC { dest, left, right },
+    58 => MulRR { dest, left, right },
+    59 => MulRC { dest, left, right },
+    60 => MulCR { dest, left, right },
+    61 => MulCC { dest, left, right },
+    62 => DivRR { dest, left, right },
+    63 => DivRC { dest, left, right },
+    64 => DivCR { dest, left, right },
+    65 => DivCC { dest, left, right },
+    66 => IDivRR { dest, left, right },
+    67 => IDivRC { dest, left, right },
+    68 => IDivCR { dest, left, right },
+    69 => IDivCC { dest, left, right },
+    70 => ModRR { dest, left, right },
+    71 => ModRC { dest, left, right },
+    72 => ModCR { dest, left, right },
+    73 => ModCC { dest, left, right },
+    74 => PowRR { dest, left, right },
+    75 => PowRC { dest, left, right },
+    76 => PowCR { dest, left, right },
+    77 => PowCC { dest, left, right },
+    78 => BitAndRR { dest, left, right },
+    79 => BitAndRC { dest, left, right },
+    80 => BitAndCR { dest, left, right },
+    81 => BitAndCC { dest, left, right },
+    82 => BitOrRR { dest, left, right },
+    83 => BitOrRC { dest, left, right },
+    84 => BitOrCR { dest, left, right },
+    85 => BitOrCC { dest, left, right },
+    86 => BitXorRR { dest, left, right },
+    87 => BitXorRC { dest, left, right },
+    88 => BitXorCR { dest, left, right },
+    89 => BitXorCC { dest, left, right },
+    90 => ShiftLeftRR { dest, left, right },
+    91 => ShiftLeftRC { dest, left, right },
+    92 => ShiftLeftCR { dest, left, right },
+    93 => ShiftLeftCC { dest, left, right },
+    94 => ShiftRightRR { dest, left, right },
+    95 => ShiftRightRC { dest, left, right },
+    96 => ShiftRightCR { dest, left, right },
+    97 => ShiftRightCC { dest, left, right },
+    98 => BitNot { dest, source },
+}
+
+trait Operand: Sized {
+    fn write(self, bytes: &mut [u8; 4], pos: &mut usize);
+    fn read(bytes: &[u8; 4], pos: &mut usize) -> Option<Self>;
+}
+
+impl Operand for u8 {
+    fn write(self, bytes: &mut [u8; 4], pos: &mut usize) {
+        bytes[*pos] = self;
+        *pos += 1;
+    }
+
+    fn read(bytes: &[u8; 4], pos: &mut usize) -> Option<Self> {
+        let b = bytes[*pos];
+        *pos += 1;
+        Some(b)
+    }
+}
+
+impl Operand for i16 {
+    fn write(self, bytes: &mut [u8; 4], pos: &mut usize) {
+        bytes[*pos..*pos + 2].copy_from_slice(&self.to_le_bytes());
+        *pos += 2;
+    }
+
+    fn read(bytes: &[u8; 4], pos: &mut usize) -> Option<Self> {
+        let v = i16::from_le_bytes([bytes[*pos], bytes[*pos + 1]]);
+        *pos += 2;
+        Some(v)
+    }
+}
+
+impl Operand for bool {
+    fn write(self, bytes: &mut [u8; 4], pos: &mut usize) {
+        u8::from(self).write(bytes, pos);
+    }
+
+    fn read(bytes: &[u8; 4], pos: &mut usize) -> Option<Self> {
+        match u8::read(bytes, pos)? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+}
+
+impl Operand for RegisterIndex {
+    fn write(self, bytes: &mut [u8; 4], pos: &mut usize) {
+        self.0.write(bytes, pos);
+    }
+
+    fn read(bytes: &[u8; 4], pos: &mut usize) -> Option<Self> {
+        u8::read(bytes, pos).map(RegisterIndex)
+    }
+}
+
+impl Operand for UpValueIndex {
+    fn write(self, bytes: &mut [u8; 4], pos: &mut usize) {
+        self.0.write(bytes, pos);
+    }
+
+    fn read(bytes: &[u8; 4], pos: &mut usize) -> Option<Self> {
+        u8::read(bytes, pos).map(UpValueIndex)
+    }
+}
+
+impl Operand for PrototypeIndex {
+    fn write(self, bytes: &mut [u8; 4], pos: &mut usize) {
+        self.0.write(bytes, pos);
+    }
+
+    fn read(bytes: &[u8; 4], pos: &mut usize) -> Option<Self> {
+        u8::read(bytes, pos).map(PrototypeIndex)
+    }
+}
+
+impl Operand for ConstantIndex8 {
+    fn write(self, bytes: &mut [u8; 4], pos: &mut usize) {
+        self.0.write(bytes, pos);
+    }
+
+    fn read(bytes: &[u8; 4], pos: &mut usize) -> Option<Self> {
+        u8::read(bytes, pos).map(ConstantIndex8)
+    }
+}
+
+impl Operand for ConstantIndex16 {
+    fn write(self, bytes: &mut [u8; 4], pos: &mut usize) {
+        bytes[*pos..*pos + 2].copy_from_slice(&self.0.to_le_bytes());
+        *pos += 2;
+    }
+
+    fn read(bytes: &[u8; 4], pos: &mut usize) -> Option<Self> {
+        let v = u16::from_le_bytes([bytes[*pos], bytes[*pos + 1]]);
+        *pos += 2;
+        Some(ConstantIndex16(v))
+    }
+}
+
+impl Operand for Opt254 {
+    fn write(self, bytes: &mut [u8; 4], pos: &mut usize) {
+        self.to_u8().unwrap_or(255).write(bytes, pos);
+    }
+
+    fn read(bytes: &[u8; 4], pos: &mut usize) -> Option<Self> {
+        let b = u8::read(bytes, pos)?;
+        Opt254::try_new((b != 255).then_some(b))
+    }
+}
+
+impl Operand for VarCount {
+    fn write(self, bytes: &mut [u8; 4], pos: &mut usize) {
+        self.to_constant().unwrap_or(255).write(bytes, pos);
+    }
+
+    fn read(bytes: &[u8; 4], pos: &mut usize) -> Option<Self> {
+        match u8::read(bytes, pos)? {
+            255 => Some(VarCount::variable()),
+            b => VarCount::try_constant(b),
+        }
+    }
+}
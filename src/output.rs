@@ -0,0 +1,120 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    io::{self, Write},
+};
+
+use gc_arena::Collect;
+
+/// The destination for everything written by `print`, and for warnings.
+///
+/// Every [`Lua`](crate::Lua) instance has its own `Output`, which writes to the process stdout
+/// unless it has been replaced with [`Lua::set_output`](crate::Lua::set_output) or
+/// [`Lua::set_output_fn`](crate::Lua::set_output_fn). This allows an embedder to route script
+/// output somewhere else, such as an in-app console, and to keep the output of multiple `Lua`
+/// instances apart.
+///
+/// Warnings from the `warn` function, or emitted by the crate itself with [`Output::warn`], are
+/// disabled by default like in the standalone PUC-Rio interpreter. Once enabled, they are written
+/// to the process stderr as `Lua warning: <message>` lines unless a warning function has been set
+/// with [`Lua::set_warn_fn`](crate::Lua::set_warn_fn).
+#[derive(Collect)]
+#[collect(require_static)]
+pub struct Output {
+    writer: RefCell<Box<dyn Write>>,
+    warn_fn: RefCell<Option<Box<dyn FnMut(&[u8])>>>,
+    warnings_enabled: Cell<bool>,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Output").finish_non_exhaustive()
+    }
+}
+
+impl Output {
+    /// Create an `Output` which writes to stdout.
+    pub fn new() -> Self {
+        Self {
+            writer: RefCell::new(Box::new(io::stdout())),
+            warn_fn: RefCell::new(None),
+            warnings_enabled: Cell::new(false),
+        }
+    }
+
+    /// Replace the writer that output is sent to.
+    pub fn set(&self, writer: impl Write + 'static) {
+        *self.writer.borrow_mut() = Box::new(writer);
+    }
+
+    /// Replace the writer with a function which is called with every piece of output.
+    ///
+    /// Output is always written in whole lines, so each call receives one or more complete lines
+    /// including their final newline.
+    pub fn set_fn(&self, f: impl FnMut(&[u8]) + 'static) {
+        struct FnWriter<F>(F);
+
+        impl<F: FnMut(&[u8])> Write for FnWriter<F> {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                (self.0)(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        self.set(FnWriter(f));
+    }
+
+    /// Write all of `buf` to the current writer and flush it.
+    pub fn write(&self, buf: &[u8]) -> io::Result<()> {
+        let mut writer = self.writer.borrow_mut();
+        writer.write_all(buf)?;
+        writer.flush()
+    }
+
+    /// Send warnings to the given function instead of writing them to stderr.
+    ///
+    /// The function receives each complete warning message, without any prefix or final newline.
+    /// It is only called while warnings are enabled.
+    pub fn set_warn_fn(&self, f: impl FnMut(&[u8]) + 'static) {
+        *self.warn_fn.borrow_mut() = Some(Box::new(f));
+    }
+
+    pub fn warnings_enabled(&self) -> bool {
+        self.warnings_enabled.get()
+    }
+
+    /// Enable or disable warnings, the same as the `"@on"` and `"@off"` control messages.
+    pub fn set_warnings_enabled(&self, enabled: bool) {
+        self.warnings_enabled.set(enabled);
+    }
+
+    /// Emit a warning, if warnings are enabled.
+    pub fn warn(&self, message: &[u8]) -> io::Result<()> {
+        if !self.warnings_enabled.get() {
+            return Ok(());
+        }
+
+        if let Some(f) = &mut *self.warn_fn.borrow_mut() {
+            f(message);
+            Ok(())
+        } else {
+            let mut line = Vec::with_capacity(message.len() + 14);
+            line.extend_from_slice(b"Lua warning: ");
+            line.extend_from_slice(message);
+            line.push(b'\n');
+            let mut stderr = io::stderr().lock();
+            stderr.write_all(&line)?;
+            stderr.flush()
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    ops::{Add, AddAssign},
+    string::String as StdString,
+    time::Duration,
+};
+
+use crate::compiler::LineNumber;
+
+/// The cost attributed to a call stack or a function in a [`Profile`].
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ProfileCost {
+    /// The amount of fuel consumed.
+    pub fuel: u64,
+    /// The wall-clock time spent inside `Executor::step`.
+    pub time: Duration,
+}
+
+impl Add for ProfileCost {
+    type Output = ProfileCost;
+
+    fn add(self, rhs: ProfileCost) -> ProfileCost {
+        ProfileCost {
+            fuel: self.fuel.saturating_add(rhs.fuel),
+            time: self.time.saturating_add(rhs.time),
+        }
+    }
+}
+
+impl AddAssign for ProfileCost {
+    fn add_assign(&mut self, rhs: ProfileCost) {
+        *self = *self + rhs;
+    }
+}
+
+/// The statistics for a single function in a [`Profile`].
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct FunctionStats {
+    /// The number of times the function was called.
+    pub calls: u64,
+    /// The number of VM instructions run inside of the function, always zero for callbacks.
+    pub instructions: u64,
+    /// The exclusive cost of the function, not including the cost of the functions it calls.
+    pub cost: ProfileCost,
+}
+
+impl Add for FunctionStats {
+    type Output = FunctionStats;
+
+    fn add(self, rhs: FunctionStats) -> FunctionStats {
+        FunctionStats {
+            calls: self.calls.saturating_add(rhs.calls),
+            instructions: self.instructions.saturating_add(rhs.instructions),
+            cost: self.cost + rhs.cost,
+        }
+    }
+}
+
+impl AddAssign for FunctionStats {
+    fn add_assign(&mut self, rhs: FunctionStats) {
+        *self = *self + rhs;
+    }
+}
+
+/// Which cost to use as the sample count when exporting a [`Profile`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ProfileMetric {
+    /// Fuel consumed, roughly the number of VM instructions run.
+    Fuel,
+    /// Wall-clock time in nanoseconds.
+    Time,
+}
+
+impl ProfileMetric {
+    fn get(self, cost: ProfileCost) -> u64 {
+        match self {
+            ProfileMetric::Fuel => cost.fuel,
+            ProfileMetric::Time => cost.time.as_nanos().try_into().unwrap_or(u64::MAX),
+        }
+    }
+}
+
+/// Profiling data accumulated by an [`Executor`] with profiling enabled.
+///
+/// The data is a set of call stacks, outermost function first, each with the exclusive cost spent
+/// with that stack on top. Lua functions are named like `name (chunk:line)`, and Rust callbacks and
+/// sequences are shown as `[callback]` and `[sequence]`. Callbacks which are called from Lua are
+/// also given the name that they were called by, like `[callback] print`.
+///
+/// Alongside the call stacks, every function has [`FunctionStats`] with its number of calls and
+/// instructions run, and the instructions run by Lua functions are attributed to individual source
+/// lines. [`Profile::to_report`] summarizes all of this as text.
+///
+/// Profiles are incremental, [`Executor::take_profile`] returns the data gathered since the last
+/// call and profiles from several calls can be combined with [`Profile::merge`].
+///
+/// [`Executor`]: crate::Executor
+/// [`Executor::take_profile`]: crate::Executor::take_profile
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    stacks: BTreeMap<Vec<StdString>, ProfileCost>,
+    functions: BTreeMap<StdString, FunctionStats>,
+    lines: BTreeMap<(StdString, LineNumber), u64>,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stacks.is_empty() && self.functions.is_empty() && self.lines.is_empty()
+    }
+
+    /// Add a cost to the given call stack.
+    pub fn record(&mut self, stack: Vec<StdString>, cost: ProfileCost) {
+        *self.stacks.entry(stack).or_default() += cost;
+    }
+
+    /// Add to the statistics of the given function.
+    pub fn record_function(&mut self, function: &str, stats: FunctionStats) {
+        match self.functions.get_mut(function) {
+            Some(existing) => *existing += stats,
+            None => {
+                self.functions.insert(function.to_owned(), stats);
+            }
+        }
+    }
+
+    /// Add to the number of instructions run on a source line of the given function.
+    pub fn record_line(&mut self, function: &str, line: LineNumber, instructions: u64) {
+        let count = self.lines.entry((function.to_owned(), line)).or_default();
+        *count = count.saturating_add(instructions);
+    }
+
+    /// Add all of the data from another profile to this one.
+    pub fn merge(&mut self, other: &Profile) {
+        for (stack, &cost) in &other.stacks {
+            *self.stacks.entry(stack.clone()).or_default() += cost;
+        }
+        for (function, &stats) in &other.functions {
+            self.record_function(function, stats);
+        }
+        for ((function, line), &instructions) in &other.lines {
+            self.record_line(function, *line, instructions);
+        }
+    }
+
+    /// Every recorded call stack with its exclusive cost, in a stable order.
+    pub fn stacks(&self) -> impl Iterator<Item = (&[StdString], ProfileCost)> + '_ {
+        self.stacks
+            .iter()
+            .map(|(stack, &cost)| (stack.as_slice(), cost))
+    }
+
+    /// The statistics of every function, in a stable order.
+    pub fn functions(&self) -> impl Iterator<Item = (&str, FunctionStats)> + '_ {
+        self.functions
+            .iter()
+            .map(|(function, &stats)| (function.as_str(), stats))
+    }
+
+    /// The number of instructions run on every source line of every Lua function, ordered by
+    /// function and then by line.
+    pub fn lines(&self) -> impl Iterator<Item = (&str, LineNumber, u64)> + '_ {
+        self.lines
+            .iter()
+            .map(|((function, line), &instructions)| (function.as_str(), *line, instructions))
+    }
+
+    /// The total cost of all recorded call stacks.
+    pub fn total(&self) -> ProfileCost {
+        self.stacks
+            .values()
+            .fold(ProfileCost::default(), |acc, &cost| acc + cost)
+    }
+
+    /// The inclusive cost of every function, which is the cost of every stack that the function
+    /// appears in.
+    ///
+    /// A recursive function is only counted once per stack.
+    pub fn inclusive(&self) -> BTreeMap<StdString, ProfileCost> {
+        let mut functions = BTreeMap::<StdString, ProfileCost>::new();
+        for (stack, &cost) in &self.stacks {
+            for (i, function) in stack.iter().enumerate() {
+                if !stack[..i].contains(function) {
+                    *functions.entry(function.clone()).or_default() += cost;
+                }
+            }
+        }
+        functions
+    }
+
+    /// Write the profile in the "collapsed stack" format used by `flamegraph.pl`, `inferno`, and
+    /// most other flame graph tools.
+    ///
+    /// Each line is a call stack with frames separated by `;`, followed by a space and the sample
+    /// count for the given metric. Stacks with a zero count are skipped.
+    pub fn write_collapsed(&self, mut w: impl io::Write, metric: ProfileMetric) -> io::Result<()> {
+        for (stack, &cost) in &self.stacks {
+            let count = metric.get(cost);
+            if count == 0 || stack.is_empty() {
+                continue;
+            }
+
+            for (i, frame) in stack.iter().enumerate() {
+                if i != 0 {
+                    w.write_all(b";")?;
+                }
+                // Semicolons separate frames and newlines separate stacks, so neither may appear
+                // inside of a frame name.
+                w.write_all(frame.replace([';', '\n'], "_").as_bytes())?;
+            }
+            writeln!(w, " {count}")?;
+        }
+        Ok(())
+    }
+
+    /// Returns the profile in the collapsed stack format, see [`Profile::write_collapsed`].
+    pub fn to_collapsed(&self, metric: ProfileMetric) -> StdString {
+        let mut out = Vec::new();
+        self.write_collapsed(&mut out, metric).unwrap();
+        StdString::from_utf8(out).unwrap()
+    }
+
+    /// Write a human readable summary of the profile.
+    ///
+    /// The summary lists every function with its calls, instructions, fuel, and time, most
+    /// expensive first by the given metric, followed by the `max_lines` source lines which ran the
+    /// most instructions.
+    pub fn write_report(
+        &self,
+        mut w: impl io::Write,
+        metric: ProfileMetric,
+        max_lines: usize,
+    ) -> io::Result<()> {
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_by_key(|(_, stats)| std::cmp::Reverse(metric.get(stats.cost)));
+
+        writeln!(
+            w,
+            "{:>10} {:>12} {:>12} {:>12}  function",
+            "calls", "instructions", "fuel", "time (us)"
+        )?;
+        for (function, stats) in functions {
+            writeln!(
+                w,
+                "{:>10} {:>12} {:>12} {:>12}  {function}",
+                stats.calls,
+                stats.instructions,
+                stats.cost.fuel,
+                stats.cost.time.as_micros(),
+            )?;
+        }
+
+        if max_lines > 0 && !self.lines.is_empty() {
+            let mut lines: Vec<_> = self.lines.iter().collect();
+            lines.sort_by_key(|(_, &instructions)| std::cmp::Reverse(instructions));
+
+            writeln!(w)?;
+            writeln!(w, "{:>12}  line", "instructions")?;
+            for ((function, line), instructions) in lines.into_iter().take(max_lines) {
+                writeln!(w, "{instructions:>12}  {function} line {line}")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the summary written by [`Profile::write_report`].
+    pub fn to_report(&self, metric: ProfileMetric, max_lines: usize) -> StdString {
+        let mut out = Vec::new();
+        self.write_report(&mut out, metric, max_lines).unwrap();
+        StdString::from_utf8(out).unwrap()
+    }
+}
@@ -0,0 +1,148 @@
+use std::{cell::RefCell, fmt};
+
+use gc_arena::Collect;
+
+/// A source of random numbers for `math.random`.
+pub trait RandomSource: 'static {
+    /// Returns the next 64 random bits.
+    fn next_u64(&mut self) -> u64;
+
+    /// Reset the generator to a state determined only by the given seed, called by
+    /// `math.randomseed(n1, n2)`.
+    fn seed(&mut self, n1: i64, n2: i64);
+
+    /// Reseed the generator unpredictably, called by `math.randomseed()` with no arguments.
+    ///
+    /// Returns the chosen seed, which is also returned to Lua. The default implementation seeds
+    /// from host entropy, sources used for deterministic replay should override this.
+    fn randomize(&mut self) -> (i64, i64) {
+        let (n1, n2) = (rand::random(), rand::random());
+        self.seed(n1, n2);
+        (n1, n2)
+    }
+}
+
+/// The `xoshiro256**` generator, seeded in the same way as PUC-Rio Lua 5.4.
+///
+/// Given the same seed, this produces the same sequence of values from `math.random` as Lua 5.4.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Xoshiro256 {
+    state: [u64; 4],
+}
+
+impl Xoshiro256 {
+    pub fn new(n1: i64, n2: i64) -> Self {
+        let mut rng = Self { state: [0; 4] };
+        rng.seed(n1, n2);
+        rng
+    }
+}
+
+impl RandomSource for Xoshiro256 {
+    fn next_u64(&mut self) -> u64 {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+
+    fn seed(&mut self, n1: i64, n2: i64) {
+        // The constant avoids an all zero state.
+        self.state = [n1 as u64, 0xff, n2 as u64, 0];
+        // Discard the initial values to spread the seed.
+        for _ in 0..16 {
+            self.next_u64();
+        }
+    }
+}
+
+/// The random number generator used by `math.random`.
+///
+/// Every [`Lua`](crate::Lua) instance has its own `Random`, which starts out as a [`Xoshiro256`]
+/// with a random seed. Scripts can be made deterministic by seeding it with
+/// [`Lua::set_random_seed`](crate::Lua::set_random_seed), or by replacing the generator entirely
+/// with [`Lua::set_random_source`](crate::Lua::set_random_source).
+#[derive(Collect)]
+#[collect(require_static)]
+pub struct Random {
+    source: RefCell<Box<dyn RandomSource>>,
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Random {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Random").finish_non_exhaustive()
+    }
+}
+
+impl Random {
+    /// Create a `Random` using [`Xoshiro256`] with a random seed.
+    pub fn new() -> Self {
+        let mut rng = Xoshiro256::new(0, 0);
+        rng.randomize();
+        Self::with_source(rng)
+    }
+
+    pub fn with_source(source: impl RandomSource) -> Self {
+        Self {
+            source: RefCell::new(Box::new(source)),
+        }
+    }
+
+    /// Replace the current source of random numbers.
+    pub fn set_source(&self, source: impl RandomSource) {
+        *self.source.borrow_mut() = Box::new(source);
+    }
+
+    /// Returns the next 64 random bits.
+    pub fn next_u64(&self) -> u64 {
+        self.source.borrow_mut().next_u64()
+    }
+
+    /// Returns a random float in the range `[0, 1)`.
+    pub fn next_f64(&self) -> f64 {
+        // Use the top 53 bits, which is all the precision a float has.
+        (self.next_u64() >> 11) as f64 * (0.5 / (1u64 << 52) as f64)
+    }
+
+    /// Returns a random integer uniformly distributed in `[0, n]`.
+    pub fn next_below_or_eq(&self, n: u64) -> u64 {
+        let mut source = self.source.borrow_mut();
+        let mut r = source.next_u64();
+        if n & n.wrapping_add(1) == 0 {
+            // `n + 1` is a power of two, so masking has no bias.
+            return r & n;
+        }
+
+        // The smallest `2^b - 1` not smaller than `n`.
+        let lim = u64::MAX >> n.leading_zeros();
+        loop {
+            r &= lim;
+            if r <= n {
+                return r;
+            }
+            r = source.next_u64();
+        }
+    }
+
+    /// Reseed the current source, see [`RandomSource::seed`].
+    pub fn seed(&self, n1: i64, n2: i64) {
+        self.source.borrow_mut().seed(n1, n2);
+    }
+
+    /// Reseed the current source unpredictably, see [`RandomSource::randomize`].
+    pub fn randomize(&self) -> (i64, i64) {
+        self.source.borrow_mut().randomize()
+    }
+}
@@ -1,4 +1,4 @@
-use std::{any::TypeId, hash::BuildHasherDefault};
+use std::{any::TypeId, hash::BuildHasherDefault, string::String as StdString};
 
 use ahash::AHasher;
 use gc_arena::{
@@ -10,7 +10,7 @@ use hashbrown::{hash_map, HashMap};
 use crate::{
     any::Any,
     stash::{Fetchable, Stashable},
-    Context,
+    Context, Value,
 };
 
 /// A type which can have a single registered value per [`Lua`](crate::Lua) instance.
@@ -27,15 +27,28 @@ impl<'gc, T: Default> Singleton<'gc> for T {
     }
 }
 
-/// A collection of stashed values and [`Singleton`]s.
+type NamedSlots<'gc> =
+    HashMap<StdString, Value<'gc>, BuildHasherDefault<AHasher>, MetricsAlloc<'gc>>;
+
+/// A collection of stashed values, [`Singleton`]s, and named slots.
 ///
 /// Generally, there is one globally accessible `Registry` per [`Lua`](crate::Lua) instance.
+///
+/// Named slots hold [`Value`]s under a namespace and a name, similar to the registry table of the
+/// PUC-Rio C API. Independent Rust modules can use them to share values such as metatables without
+/// defining a `Singleton` type, using their own namespace to avoid conflicts.
 #[derive(Copy, Clone, Collect)]
 #[collect(no_drop)]
 pub struct Registry<'gc> {
     roots: DynamicRootSet<'gc>,
     singletons:
         Gc<'gc, RefLock<HashMap<TypeId, Any<'gc>, BuildHasherDefault<AHasher>, MetricsAlloc<'gc>>>>,
+    named: Gc<
+        'gc,
+        RefLock<
+            HashMap<StdString, NamedSlots<'gc>, BuildHasherDefault<AHasher>, MetricsAlloc<'gc>>,
+        >,
+    >,
 }
 
 impl<'gc> Registry<'gc> {
@@ -43,9 +56,12 @@ impl<'gc> Registry<'gc> {
         let singletons =
             HashMap::with_hasher_in(BuildHasherDefault::default(), MetricsAlloc::new(mc));
 
+        let named = HashMap::with_hasher_in(BuildHasherDefault::default(), MetricsAlloc::new(mc));
+
         Self {
             roots: DynamicRootSet::new(mc),
             singletons: Gc::new(mc, RefLock::new(singletons)),
+            named: Gc::new(mc, RefLock::new(named)),
         }
     }
 
@@ -71,6 +87,67 @@ impl<'gc> Registry<'gc> {
         }
     }
 
+    /// Returns the value in the named slot, or nil if the slot is empty.
+    pub fn get_named(&self, namespace: &str, name: &str) -> Value<'gc> {
+        self.named
+            .borrow()
+            .get(namespace)
+            .and_then(|slots| slots.get(name))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Set the value in the named slot, returning the previous value.
+    ///
+    /// Setting a slot to nil removes it.
+    pub fn set_named(
+        &self,
+        mc: &Mutation<'gc>,
+        namespace: &str,
+        name: &str,
+        value: impl Into<Value<'gc>>,
+    ) -> Value<'gc> {
+        let value = value.into();
+        let mut named = self.named.borrow_mut(mc);
+        if value.is_nil() {
+            let Some(slots) = named.get_mut(namespace) else {
+                return Value::Nil;
+            };
+            let prev = slots.remove(name).unwrap_or_default();
+            if slots.is_empty() {
+                named.remove(namespace);
+            }
+            prev
+        } else {
+            named
+                .entry(namespace.to_owned())
+                .or_insert_with(|| {
+                    HashMap::with_hasher_in(BuildHasherDefault::default(), MetricsAlloc::new(mc))
+                })
+                .insert(name.to_owned(), value)
+                .unwrap_or_default()
+        }
+    }
+
+    /// Returns the name and value of every non-empty slot in the namespace, in no particular order.
+    pub fn named_slots(&self, namespace: &str) -> Vec<(StdString, Value<'gc>)> {
+        self.named
+            .borrow()
+            .get(namespace)
+            .map(|slots| {
+                slots
+                    .iter()
+                    .map(|(name, &value)| (name.clone(), value))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Empty every named slot in the namespace.
+    pub fn clear_namespace(&self, mc: &Mutation<'gc>, namespace: &str) {
+        self.named.borrow_mut(mc).remove(namespace);
+    }
+
     /// Returns the inner [`DynamicRootSet`] held inside the global registry.
     ///
     /// This can be used to create `'static` roots directly without having to deal with the
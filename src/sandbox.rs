@@ -0,0 +1,312 @@
+use std::{collections::BTreeMap, string::String as StdString};
+
+use thiserror::Error;
+
+use crate::{
+    table::NextValue, Callback, CallbackReturn, Context, Function, IntoValue, Table, Value,
+};
+
+/// The globals allowed by [`SandboxBuilder::allow_safe_core`].
+///
+/// These are the parts of the core stdlib which cannot observe or affect anything outside of the
+/// sandbox, other than by using memory and time.
+pub const SAFE_CORE: &[&str] = &[
+    "_VERSION",
+    "assert",
+    "error",
+    "getmetatable",
+    "ipairs",
+    "next",
+    "pairs",
+    "pcall",
+    "xpcall",
+    "select",
+    "setmetatable",
+    "tonumber",
+    "tostring",
+    "type",
+    "coroutine",
+    "math",
+    "string",
+    "table",
+];
+
+#[derive(Debug, Clone, Error)]
+pub enum SandboxError {
+    #[error("'{0}' does not exist")]
+    NotFound(StdString),
+    #[error("'{0}' is not a table")]
+    NotATable(StdString),
+}
+
+/// Builds a restricted environment table for running untrusted scripts.
+///
+/// Only the globals which are explicitly allowed are copied from the globals table into the
+/// environment. Allowing a dotted path like `"string.format"` copies only that one field into a
+/// new table owned by the environment, while allowing `"string"` shares the entire table.
+///
+/// Shared tables are by default wrapped in read-only proxies, so that a script cannot modify them
+/// and affect the host or any other script using the same tables. Tables reached through a proxy
+/// are wrapped as well, and each proxy has a protected metatable so that the original table cannot
+/// be reached through `getmetatable`. Proxies support indexing, `#`, and `pairs`, but they are
+/// empty as far as the `raw*` functions are concerned. Since every string shares one metatable
+/// with the host, an allowed `getmetatable` returns a read-only proxy for it as well.
+///
+/// The environment can then be used to load a chunk with [`Closure::load_with_env`].
+///
+/// [`Closure::load_with_env`]: crate::Closure::load_with_env
+#[derive(Debug, Clone)]
+pub struct SandboxBuilder {
+    allowed: BTreeMap<StdString, Allowed>,
+    read_only: bool,
+}
+
+impl Default for SandboxBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SandboxBuilder {
+    /// Create a builder for an empty environment with read-only shared tables.
+    pub fn new() -> Self {
+        Self {
+            allowed: BTreeMap::new(),
+            read_only: true,
+        }
+    }
+
+    /// Allow a global, or a field of a global table with a dotted path like `"string.format"`.
+    pub fn allow(mut self, path: &str) -> Self {
+        let mut allowed = &mut self.allowed;
+        let mut parts = path.split('.').peekable();
+        while let Some(part) = parts.next() {
+            let entry = allowed.entry(part.to_owned());
+            if parts.peek().is_none() {
+                *entry.or_insert(Allowed::All) = Allowed::All;
+                break;
+            }
+
+            match entry.or_insert_with(|| Allowed::Fields(BTreeMap::new())) {
+                // The whole table is already allowed.
+                Allowed::All => break,
+                Allowed::Fields(fields) => allowed = fields,
+            }
+        }
+        self
+    }
+
+    /// Allow every global or field in `paths`, see [`SandboxBuilder::allow`].
+    pub fn allow_all<'a>(mut self, paths: impl IntoIterator<Item = &'a str>) -> Self {
+        for path in paths {
+            self = self.allow(path);
+        }
+        self
+    }
+
+    /// Allow every global in [`SAFE_CORE`].
+    pub fn allow_safe_core(self) -> Self {
+        self.allow_all(SAFE_CORE.iter().copied())
+    }
+
+    /// Set whether shared tables are wrapped in read-only proxies, defaults to true.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Build a new environment table from the current globals.
+    ///
+    /// Returns an error if an allowed path does not exist in the globals table.
+    pub fn build<'gc>(&self, ctx: Context<'gc>) -> Result<Table<'gc>, SandboxError> {
+        let env = Table::new(&ctx);
+        let proxies = self.read_only.then(|| Table::new(&ctx));
+        copy_allowed(
+            ctx,
+            proxies,
+            &self.allowed,
+            ctx.globals(),
+            env,
+            &mut StdString::new(),
+        )?;
+
+        if let Some(proxies) = proxies {
+            if let Value::Function(getmetatable) = env.get_raw(ctx.intern(b"getmetatable").into()) {
+                env.set_field(
+                    ctx,
+                    "getmetatable",
+                    string_metatable_proxy(ctx, proxies, getmetatable),
+                );
+            }
+        }
+
+        Ok(env)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Allowed {
+    All,
+    Fields(BTreeMap<StdString, Allowed>),
+}
+
+fn copy_allowed<'gc>(
+    ctx: Context<'gc>,
+    proxies: Option<Table<'gc>>,
+    allowed: &BTreeMap<StdString, Allowed>,
+    from: Table<'gc>,
+    to: Table<'gc>,
+    path: &mut StdString,
+) -> Result<(), SandboxError> {
+    for (name, allowed) in allowed {
+        let prefix_len = path.len();
+        if !path.is_empty() {
+            path.push('.');
+        }
+        path.push_str(name);
+
+        let key = ctx.intern(name.as_bytes());
+        let value = from.get_raw(key.into());
+        if value.is_nil() {
+            return Err(SandboxError::NotFound(path.clone()));
+        }
+
+        match allowed {
+            Allowed::All => {
+                let value = match proxies {
+                    Some(proxies) => read_only(ctx, proxies, value),
+                    None => value,
+                };
+                to.set(ctx, key, value).unwrap();
+            }
+            Allowed::Fields(fields) => {
+                let Value::Table(from) = value else {
+                    return Err(SandboxError::NotATable(path.clone()));
+                };
+                let table = Table::new(&ctx);
+                copy_allowed(ctx, proxies, fields, from, table, path)?;
+                to.set(ctx, key, table).unwrap();
+            }
+        }
+
+        path.truncate(prefix_len);
+    }
+    Ok(())
+}
+
+// Wraps `getmetatable` so that the string metatable, which is shared by the whole `Lua` instance,
+// is only returned as a read-only proxy.
+fn string_metatable_proxy<'gc>(
+    ctx: Context<'gc>,
+    proxies: Table<'gc>,
+    getmetatable: Function<'gc>,
+) -> Callback<'gc> {
+    Callback::from_fn_with(
+        &ctx,
+        (proxies, getmetatable),
+        |&(proxies, getmetatable), ctx, _, mut stack| {
+            if !matches!(stack.get(0), Value::String(_)) {
+                return Ok(CallbackReturn::Call {
+                    function: getmetatable,
+                    then: None,
+                });
+            }
+
+            let metatable = match ctx.string_metatable() {
+                Some(mt) => match mt.get_value(ctx, "__metatable") {
+                    Value::Nil => mt.into(),
+                    protected => protected,
+                },
+                None => Value::Nil,
+            };
+            stack.replace(ctx, read_only(ctx, proxies, metatable));
+            Ok(CallbackReturn::Return)
+        },
+    )
+}
+
+// Returns a read-only proxy for the given table.
+//
+// Proxies are cached in `proxies`, so that the same table is always wrapped by the same proxy and
+// table identity is preserved within a sandbox.
+fn read_only<'gc>(ctx: Context<'gc>, proxies: Table<'gc>, value: Value<'gc>) -> Value<'gc> {
+    let Value::Table(table) = value else {
+        return value;
+    };
+
+    let proxy = proxies.get_value(ctx, table);
+    if !proxy.is_nil() {
+        return proxy;
+    }
+
+    let metatable = Table::new(&ctx);
+
+    metatable.set_field(
+        ctx,
+        "__index",
+        Callback::from_fn_with(
+            &ctx,
+            (proxies, table),
+            |&(proxies, table), ctx, _, mut stack| {
+                let (_, key): (Value, Value) = stack.consume(ctx)?;
+                stack.replace(ctx, read_only(ctx, proxies, table.get_raw(key)));
+                Ok(CallbackReturn::Return)
+            },
+        ),
+    );
+
+    metatable.set_field(
+        ctx,
+        "__newindex",
+        Callback::from_fn(&ctx, |ctx, _, _| {
+            // A failure to write the warning must not replace the error that the script sees.
+            let _ = ctx
+                .output()
+                .warn(b"sandbox: denied an attempt to modify a read-only table");
+            Err("attempt to modify a read-only table".into_value(ctx).into())
+        }),
+    );
+
+    metatable.set_field(
+        ctx,
+        "__len",
+        Callback::from_fn_with(&ctx, table, |table, ctx, _, mut stack| {
+            stack.replace(ctx, table.length());
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    let next = Callback::from_fn_with(
+        &ctx,
+        (proxies, table),
+        |&(proxies, table), ctx, _, mut stack| {
+            let (_, key): (Value, Value) = stack.consume(ctx)?;
+            match table.next(key) {
+                NextValue::Found { key, value } => {
+                    stack.replace(ctx, (key, read_only(ctx, proxies, value)))
+                }
+                NextValue::Last => stack.replace(ctx, Value::Nil),
+                NextValue::NotFound => {
+                    return Err("invalid table key".into_value(ctx).into());
+                }
+            }
+            Ok(CallbackReturn::Return)
+        },
+    );
+    metatable.set_field(
+        ctx,
+        "__pairs",
+        Callback::from_fn_with(&ctx, next, |&next, ctx, _, mut stack| {
+            let proxy = stack.get(0);
+            stack.replace(ctx, (next, proxy, Value::Nil));
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    metatable.set_field(ctx, "__metatable", false);
+
+    let proxy = Table::new(&ctx);
+    proxy.set_metatable(ctx, Some(metatable));
+    proxies.set(ctx, table, proxy).unwrap();
+    proxy.into()
+}
@@ -0,0 +1,294 @@
+use std::fmt;
+
+use crate::{thread::BadThreadMode, ExecutorMode, Fuel, Lua, StashedExecutor};
+
+/// Identifies a task added to a [`Scheduler`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaskId(u64);
+
+/// Why the completion callback of a task was called.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Completion {
+    /// The executor has a result to take, because it returned, errored, or its main thread
+    /// yielded. The task stays scheduled only if the callback resumes or restarts the executor.
+    Result,
+    /// The task used all of its fuel quota and was removed from the scheduler.
+    QuotaExhausted,
+}
+
+/// A callback called when a task completes, see [`Scheduler::set_on_complete`].
+pub type CompletionCallback = dyn FnMut(&mut Lua, &StashedExecutor, Completion);
+
+/// Runs many executors at once, sharing a total fuel budget between them.
+///
+/// Tasks are stepped round-robin, every turn a task is given [`Scheduler::slice`] fuel multiplied
+/// by its priority, so a task with priority 2 gets twice as much fuel as a task with priority 1
+/// over time, but every runnable task gets a turn in every round. The position in the round is
+/// kept between calls to [`Scheduler::run`], so a budget smaller than a whole round does not
+/// starve the tasks at the end of it.
+///
+/// Tasks which are suspended, such as while waiting on an async sequence or on being resumed by
+/// the host, are skipped until they are runnable again. A task is removed from the scheduler once
+/// its executor has a result (unless its completion callback resumes it), or once it has used up
+/// its fuel quota.
+pub struct Scheduler {
+    tasks: Vec<Task>,
+    next_id: u64,
+    cursor: usize,
+    slice: i32,
+}
+
+struct Task {
+    id: TaskId,
+    executor: StashedExecutor,
+    priority: u32,
+    quota: Option<u64>,
+    consumed: u64,
+    on_complete: Option<Box<CompletionCallback>>,
+}
+
+impl fmt::Debug for Scheduler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scheduler")
+            .field("tasks", &self.tasks.len())
+            .field("cursor", &self.cursor)
+            .field("slice", &self.slice)
+            .finish()
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scheduler {
+    pub const DEFAULT_SLICE: i32 = 4096;
+
+    pub fn new() -> Self {
+        Self::with_slice(Self::DEFAULT_SLICE)
+    }
+
+    pub fn with_slice(slice: i32) -> Self {
+        Self {
+            tasks: Vec::new(),
+            next_id: 0,
+            cursor: 0,
+            slice: slice.max(1),
+        }
+    }
+
+    /// The amount of fuel given to a task with priority 1 every turn.
+    pub fn slice(&self) -> i32 {
+        self.slice
+    }
+
+    pub fn set_slice(&mut self, slice: i32) {
+        self.slice = slice.max(1);
+    }
+
+    /// Add an executor to the end of the round, with priority 1 and no fuel quota.
+    pub fn spawn(&mut self, executor: StashedExecutor) -> TaskId {
+        let id = TaskId(self.next_id);
+        self.next_id += 1;
+        self.tasks.push(Task {
+            id,
+            executor,
+            priority: 1,
+            quota: None,
+            consumed: 0,
+            on_complete: None,
+        });
+        id
+    }
+
+    /// Remove a task without calling its completion callback, returning its executor.
+    pub fn cancel(&mut self, id: TaskId) -> Option<StashedExecutor> {
+        let index = self.index_of(id)?;
+        Some(self.remove(index).executor)
+    }
+
+    pub fn contains(&self, id: TaskId) -> bool {
+        self.index_of(id).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    pub fn executor(&self, id: TaskId) -> Option<&StashedExecutor> {
+        Some(&self.tasks[self.index_of(id)?].executor)
+    }
+
+    /// Set the multiple of the slice a task is given every turn, a priority of 0 is treated as 1.
+    ///
+    /// Returns false if there is no such task.
+    pub fn set_priority(&mut self, id: TaskId, priority: u32) -> bool {
+        self.with_task(id, |task| task.priority = priority.max(1))
+    }
+
+    /// Limit the total amount of fuel a task may consume.
+    ///
+    /// Once it has consumed at least this much, the task is removed and its completion callback is
+    /// called with [`Completion::QuotaExhausted`]. Returns false if there is no such task.
+    pub fn set_quota(&mut self, id: TaskId, quota: Option<u64>) -> bool {
+        self.with_task(id, |task| task.quota = quota)
+    }
+
+    /// Set a callback to call when a task completes.
+    ///
+    /// The callback is given the task's executor, so that it can take its result, and may resume
+    /// or restart the executor to keep the task scheduled. Returns false if there is no such task.
+    pub fn set_on_complete(
+        &mut self,
+        id: TaskId,
+        callback: impl FnMut(&mut Lua, &StashedExecutor, Completion) + 'static,
+    ) -> bool {
+        self.with_task(id, |task| task.on_complete = Some(Box::new(callback)))
+    }
+
+    /// The total amount of fuel consumed by a task so far.
+    pub fn fuel_consumed(&self, id: TaskId) -> Option<u64> {
+        Some(self.tasks[self.index_of(id)?].consumed)
+    }
+
+    /// Step tasks until `budget` fuel has been consumed, there are no tasks left, or every
+    /// remaining task is suspended.
+    ///
+    /// Returns the amount of fuel consumed, which may slightly exceed the budget since executors
+    /// can overrun the fuel they are given.
+    pub fn run(&mut self, lua: &mut Lua, budget: u64) -> Result<u64, BadThreadMode> {
+        let mut consumed = 0;
+        // The number of tasks in a row which were skipped because they could not run.
+        let mut skipped = 0;
+
+        while consumed < budget && skipped < self.tasks.len() {
+            if self.cursor >= self.tasks.len() {
+                self.cursor = 0;
+            }
+
+            match self.mode(lua, self.cursor) {
+                ExecutorMode::Normal => {}
+                ExecutorMode::Result | ExecutorMode::Stopped => {
+                    // Either the task is removed or its callback made it runnable again.
+                    self.complete(lua, self.cursor);
+                    continue;
+                }
+                ExecutorMode::Suspended | ExecutorMode::Running => {
+                    skipped += 1;
+                    self.cursor += 1;
+                    continue;
+                }
+            }
+            skipped = 0;
+
+            let task = &mut self.tasks[self.cursor];
+            let mut allowance = i64::from(self.slice) * i64::from(task.priority);
+            allowance = allowance.min(clamp(budget - consumed));
+            if let Some(quota) = task.quota {
+                allowance = allowance.min(clamp(quota.saturating_sub(task.consumed)));
+            }
+            let allowance = i32::try_from(allowance).unwrap_or(i32::MAX);
+
+            let mut fuel = Fuel::with(allowance);
+            lua.enter(|ctx| ctx.fetch(&task.executor).step(ctx, &mut fuel))?;
+            let used = (i64::from(allowance) - i64::from(fuel.remaining())).max(0) as u64;
+            task.consumed += used;
+            consumed += used;
+
+            match self.mode(lua, self.cursor) {
+                ExecutorMode::Result | ExecutorMode::Stopped => {
+                    if !self.complete(lua, self.cursor) {
+                        self.cursor += 1;
+                    }
+                }
+                _ => {
+                    let task = &self.tasks[self.cursor];
+                    if task.quota.is_some_and(|quota| task.consumed >= quota) {
+                        let mut task = self.remove(self.cursor);
+                        if let Some(on_complete) = &mut task.on_complete {
+                            on_complete(lua, &task.executor, Completion::QuotaExhausted);
+                        }
+                    } else {
+                        self.cursor += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(consumed)
+    }
+
+    /// Run every task to completion, in slices of `budget`.
+    ///
+    /// Returns once no tasks are left or every remaining task is suspended.
+    pub fn finish(&mut self, lua: &mut Lua, budget: u64) -> Result<(), BadThreadMode> {
+        while !self.is_empty() {
+            if self.run(lua, budget)? == 0 && self.all_suspended(lua) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn all_suspended(&self, lua: &mut Lua) -> bool {
+        (0..self.tasks.len()).all(|index| {
+            matches!(
+                self.mode(lua, index),
+                ExecutorMode::Suspended | ExecutorMode::Running
+            )
+        })
+    }
+
+    // Call the completion callback of a task which has a result, and remove it unless the callback
+    // made it runnable again. Returns true if the task was removed.
+    fn complete(&mut self, lua: &mut Lua, index: usize) -> bool {
+        let task = &mut self.tasks[index];
+        if let Some(on_complete) = &mut task.on_complete {
+            on_complete(lua, &task.executor, Completion::Result);
+        }
+        if matches!(
+            self.mode(lua, index),
+            ExecutorMode::Result | ExecutorMode::Stopped
+        ) {
+            self.remove(index);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn mode(&self, lua: &mut Lua, index: usize) -> ExecutorMode {
+        lua.enter(|ctx| ctx.fetch(&self.tasks[index].executor).mode())
+    }
+
+    fn remove(&mut self, index: usize) -> Task {
+        if index < self.cursor {
+            self.cursor -= 1;
+        }
+        self.tasks.remove(index)
+    }
+
+    fn index_of(&self, id: TaskId) -> Option<usize> {
+        self.tasks.iter().position(|task| task.id == id)
+    }
+
+    fn with_task(&mut self, id: TaskId, f: impl FnOnce(&mut Task)) -> bool {
+        match self.index_of(id) {
+            Some(index) => {
+                f(&mut self.tasks[index]);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn clamp(fuel: u64) -> i64 {
+    i64::try_from(fuel).unwrap_or(i64::MAX)
+}
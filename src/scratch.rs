@@ -0,0 +1,84 @@
+use std::{
+    cell::RefCell,
+    fmt,
+    ops::{Deref, DerefMut},
+};
+
+use gc_arena::Collect;
+
+/// Reusable temporary byte buffers, which live outside of the garbage collected heap.
+///
+/// Buffers are borrowed with [`Context::scratch_buffer`](crate::Context::scratch_buffer) and are
+/// returned to the pool when dropped. Since a borrowed buffer is branded with the `'gc` lifetime,
+/// it cannot outlive the [`Lua::enter`](crate::Lua::enter) call it was borrowed in, and the pool
+/// releases any excess memory whenever `Lua::enter` returns.
+///
+/// Scratch buffers are not traced and do not count towards GC debt, so they should only hold plain
+/// data like partially built strings.
+#[derive(Default, Collect)]
+#[collect(require_static)]
+pub struct Scratch {
+    buffers: RefCell<Vec<Vec<u8>>>,
+}
+
+impl Scratch {
+    /// The number of free buffers kept between calls to `Lua::enter`.
+    const RETAINED_BUFFERS: usize = 4;
+    /// Free buffers with a larger capacity than this are released when `Lua::enter` returns.
+    const RETAINED_CAPACITY: usize = 16 * 1024;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Borrow an empty buffer, reusing a previously allocated buffer if one is free.
+    pub fn buffer(&self) -> ScratchBuffer<'_> {
+        ScratchBuffer {
+            buffer: self.buffers.borrow_mut().pop().unwrap_or_default(),
+            scratch: self,
+        }
+    }
+
+    /// Release memory held by free buffers beyond what is retained for reuse.
+    pub fn reset(&self) {
+        let mut buffers = self.buffers.borrow_mut();
+        buffers.retain(|b| b.capacity() <= Self::RETAINED_CAPACITY);
+        buffers.truncate(Self::RETAINED_BUFFERS);
+    }
+}
+
+/// A temporary byte buffer borrowed from [`Scratch`].
+///
+/// Derefs to a `Vec<u8>` which is always empty when first borrowed.
+pub struct ScratchBuffer<'a> {
+    buffer: Vec<u8>,
+    scratch: &'a Scratch,
+}
+
+impl<'a> fmt::Debug for ScratchBuffer<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ScratchBuffer").field(&self.buffer).finish()
+    }
+}
+
+impl<'a> Deref for ScratchBuffer<'a> {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buffer
+    }
+}
+
+impl<'a> DerefMut for ScratchBuffer<'a> {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buffer
+    }
+}
+
+impl<'a> Drop for ScratchBuffer<'a> {
+    fn drop(&mut self) {
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.clear();
+        self.scratch.buffers.borrow_mut().push(buffer);
+    }
+}
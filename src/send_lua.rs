@@ -0,0 +1,164 @@
+use std::io::Write;
+
+use crate::{
+    Callback, CallbackReturn, Closure, Executor, ExternError, FromValue, Function, IntoValue, Lua,
+    RuntimeError, SharedDataError, SharedValue, StashedExecutor, Value, Variadic,
+};
+
+/// A [`Lua`] instance which can be moved to another thread, such as between the workers of a
+/// thread pool.
+///
+/// A `Lua` is not `Send`, because the host values it owns (callbacks, userdata, output writers and
+/// so on) may share data which is not `Send` with the thread that created them. The stdlib only
+/// shares such data within the instance itself, so a whole instance can be moved as long as
+/// nothing else is added to it.
+///
+/// `SendLua` makes sure of this by never handing out a [`Context`](crate::Context) or stashed
+/// values, and by only adding host values through its own methods, which require them to be
+/// `Send`. Values are passed in and out as [`SharedValue`]s, with `None` standing in for nil.
+pub struct SendLua(Lua);
+
+// SAFETY: Everything owned by a `SendLua` is either `Send`, or is only shared within the instance
+// and so moves along with it, see above.
+unsafe impl Send for SendLua {}
+
+impl SendLua {
+    /// Create a new `SendLua` with the core stdlib loaded, see [`Lua::core`].
+    pub fn core() -> Self {
+        SendLua(Lua::core())
+    }
+
+    /// Create a new `SendLua` with all of the stdlib loaded, see [`Lua::full`].
+    pub fn full() -> Self {
+        SendLua(Lua::full())
+    }
+
+    /// Wrap a `Lua` instance which has been set up through [`Lua::enter`].
+    ///
+    /// # Safety
+    ///
+    /// Nothing owned by `lua` may share data which is not `Send` with anything outside of it, such
+    /// as an `Rc` captured by a callback which the host also holds a clone of. This includes
+    /// stashed values, which must all be dropped before the `SendLua` is moved to another thread.
+    pub unsafe fn from_lua(lua: Lua) -> Self {
+        SendLua(lua)
+    }
+
+    /// Unwrap the `Lua` instance, which must then stay on the current thread.
+    pub fn into_lua(self) -> Lua {
+        self.0
+    }
+
+    /// Set the global variable `name`, `None` removes it.
+    pub fn set_global(&mut self, name: &str, value: Option<SharedValue>) {
+        self.0.enter(|ctx| {
+            ctx.globals()
+                .set(ctx, ctx.intern(name.as_bytes()), value.as_ref())
+                .unwrap();
+        })
+    }
+
+    /// Copy the global variable `name` out of the instance.
+    pub fn get_global(&mut self, name: &str) -> Result<Option<SharedValue>, SharedDataError> {
+        self.0.enter(|ctx| {
+            SharedValue::from_value(ctx.globals().get_value(ctx, ctx.intern(name.as_bytes())))
+        })
+    }
+
+    /// Set the global function `name`, which calls `f` with copies of its arguments.
+    ///
+    /// Arguments which cannot be copied into a [`SharedValue`] raise an error instead, as does an
+    /// error returned by `f`.
+    pub fn set_function<F>(&mut self, name: &str, f: F)
+    where
+        F: Fn(Vec<Option<SharedValue>>) -> Result<Vec<Option<SharedValue>>, RuntimeError>
+            + Send
+            + 'static,
+    {
+        self.0.enter(|ctx| {
+            let callback = Callback::from_fn(&ctx, move |ctx, _, mut stack| {
+                let args = stack
+                    .drain(..)
+                    .map(SharedValue::from_value)
+                    .collect::<Result<Vec<_>, _>>()?;
+                let results = f(args)?;
+                stack.extend(results.iter().map(|v| v.as_ref().into_value(ctx)));
+                Ok(CallbackReturn::Return)
+            });
+            ctx.globals()
+                .set(ctx, ctx.intern(name.as_bytes()), callback)
+                .unwrap();
+        })
+    }
+
+    /// Load and run a chunk of Lua source, returning copies of its results.
+    pub fn run(
+        &mut self,
+        name: &str,
+        source: &[u8],
+    ) -> Result<Vec<Option<SharedValue>>, ExternError> {
+        let executor = self.0.try_enter(|ctx| {
+            let closure = Closure::load(ctx, Some(name), source)?;
+            Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+        })?;
+        self.finish(&executor)
+    }
+
+    /// Call the global function `name` with the given arguments, returning copies of its results.
+    pub fn call(
+        &mut self,
+        name: &str,
+        args: &[Option<SharedValue>],
+    ) -> Result<Vec<Option<SharedValue>>, ExternError> {
+        let executor = self.0.try_enter(|ctx| {
+            let function = Function::from_value(
+                ctx,
+                ctx.globals().get_value(ctx, ctx.intern(name.as_bytes())),
+            )?;
+            let args = args.iter().map(|v| v.as_ref().into_value(ctx));
+            Ok(ctx.stash(Executor::start(ctx, function, Variadic(args))))
+        })?;
+        self.finish(&executor)
+    }
+
+    /// Send the output of `print` to the given writer, see [`Lua::set_output`].
+    pub fn set_output(&mut self, writer: impl Write + Send + 'static) {
+        self.0.set_output(writer)
+    }
+
+    /// Send warnings to the given function, see [`Lua::set_warn_fn`].
+    pub fn set_warn_fn(&mut self, f: impl FnMut(&[u8]) + Send + 'static) {
+        self.0.set_warn_fn(f)
+    }
+
+    /// See [`Lua::set_memory_limit`].
+    pub fn set_memory_limit(&mut self, bytes: Option<usize>) {
+        self.0.set_memory_limit(bytes)
+    }
+
+    /// See [`Lua::total_memory`].
+    pub fn total_memory(&self) -> usize {
+        self.0.total_memory()
+    }
+
+    /// See [`Lua::gc_collect`].
+    pub fn gc_collect(&mut self) {
+        self.0.gc_collect()
+    }
+
+    fn finish(
+        &mut self,
+        executor: &StashedExecutor,
+    ) -> Result<Vec<Option<SharedValue>>, ExternError> {
+        self.0.finish(executor).map_err(RuntimeError::new)?;
+        self.0.try_enter(|ctx| {
+            let results = ctx
+                .fetch(executor)
+                .take_result::<Variadic<Vec<Value>>>(ctx)??;
+            Ok(results
+                .into_iter()
+                .map(SharedValue::from_value)
+                .collect::<Result<_, _>>()?)
+        })
+    }
+}
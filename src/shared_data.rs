@@ -0,0 +1,462 @@
+use std::{
+    collections::HashMap as StdHashMap,
+    fmt,
+    hash::{BuildHasherDefault, Hash, Hasher},
+    sync::Arc,
+};
+
+use ahash::AHasher;
+use gc_arena::Gc;
+use hashbrown::{Equivalent, HashMap};
+use thiserror::Error;
+
+use crate::{
+    userdata::{UserDataMethods, UserDataType},
+    value::f64_to_i64_exact,
+    Callback, CallbackReturn, Context, FromValue, IntoValue, MetaMethod, Table, TypeError,
+    UserData, Value,
+};
+
+#[derive(Debug, Clone, Error)]
+pub enum SharedDataError {
+    #[error("cannot share a value of type {0}")]
+    UnsupportedValue(&'static str),
+    #[error("cannot share a table key of type {0}")]
+    UnsupportedKey(&'static str),
+    #[error("cannot share a table which contains itself")]
+    Cycle,
+}
+
+/// A key in a [`SharedTable`].
+///
+/// Float keys are not supported, except for floats with an exact integer representation which are
+/// converted to integers, the same as Lua does.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SharedKey {
+    Boolean(bool),
+    Integer(i64),
+    String(Box<[u8]>),
+}
+
+impl Hash for SharedKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_key_ref().hash(state)
+    }
+}
+
+impl SharedKey {
+    fn as_key_ref(&self) -> KeyRef<'_> {
+        match self {
+            SharedKey::Boolean(b) => KeyRef::Boolean(*b),
+            SharedKey::Integer(i) => KeyRef::Integer(*i),
+            SharedKey::String(s) => KeyRef::String(s),
+        }
+    }
+}
+
+impl From<bool> for SharedKey {
+    fn from(b: bool) -> Self {
+        SharedKey::Boolean(b)
+    }
+}
+
+impl From<i64> for SharedKey {
+    fn from(i: i64) -> Self {
+        SharedKey::Integer(i)
+    }
+}
+
+impl From<&str> for SharedKey {
+    fn from(s: &str) -> Self {
+        SharedKey::String(s.as_bytes().into())
+    }
+}
+
+impl From<std::string::String> for SharedKey {
+    fn from(s: std::string::String) -> Self {
+        SharedKey::String(s.into_bytes().into_boxed_slice())
+    }
+}
+
+// A borrowed `SharedKey`, which allows looking up keys without allocating.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum KeyRef<'a> {
+    Boolean(bool),
+    Integer(i64),
+    String(&'a [u8]),
+}
+
+impl<'a> Hash for KeyRef<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            KeyRef::Boolean(b) => {
+                state.write_u8(0);
+                b.hash(state);
+            }
+            KeyRef::Integer(i) => {
+                state.write_u8(1);
+                i.hash(state);
+            }
+            KeyRef::String(s) => {
+                state.write_u8(2);
+                s.hash(state);
+            }
+        }
+    }
+}
+
+impl<'a> Equivalent<SharedKey> for KeyRef<'a> {
+    fn equivalent(&self, key: &SharedKey) -> bool {
+        *self == key.as_key_ref()
+    }
+}
+
+impl<'a> KeyRef<'a> {
+    fn from_value(value: Value<'a>) -> Option<Self> {
+        match value {
+            Value::Boolean(b) => Some(KeyRef::Boolean(b)),
+            Value::Integer(i) => Some(KeyRef::Integer(i)),
+            Value::Number(n) => f64_to_i64_exact(n).map(KeyRef::Integer),
+            Value::String(s) => Some(KeyRef::String(s.as_bytes())),
+            _ => None,
+        }
+    }
+}
+
+/// A value in a [`SharedTable`].
+#[derive(Debug, Clone)]
+pub enum SharedValue {
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(Box<[u8]>),
+    Table(SharedTable),
+}
+
+impl From<bool> for SharedValue {
+    fn from(b: bool) -> Self {
+        SharedValue::Boolean(b)
+    }
+}
+
+impl From<i64> for SharedValue {
+    fn from(i: i64) -> Self {
+        SharedValue::Integer(i)
+    }
+}
+
+impl From<f64> for SharedValue {
+    fn from(n: f64) -> Self {
+        SharedValue::Number(n)
+    }
+}
+
+impl From<&str> for SharedValue {
+    fn from(s: &str) -> Self {
+        SharedValue::String(s.as_bytes().into())
+    }
+}
+
+impl From<std::string::String> for SharedValue {
+    fn from(s: std::string::String) -> Self {
+        SharedValue::String(s.into_bytes().into_boxed_slice())
+    }
+}
+
+impl From<SharedTable> for SharedValue {
+    fn from(table: SharedTable) -> Self {
+        SharedValue::Table(table)
+    }
+}
+
+impl SharedValue {
+    /// Copy a Lua value into a `SharedValue`, or `None` if it is nil.
+    ///
+    /// Tables are copied with [`SharedTable::from_table`], and the same values are unsupported.
+    pub fn from_value(value: Value<'_>) -> Result<Option<Self>, SharedDataError> {
+        Ok(Some(match value {
+            Value::Nil => return Ok(None),
+            Value::Boolean(b) => SharedValue::Boolean(b),
+            Value::Integer(i) => SharedValue::Integer(i),
+            Value::Number(n) => SharedValue::Number(n),
+            Value::String(s) => SharedValue::String(s.as_bytes().into()),
+            Value::Table(t) => SharedValue::Table(SharedTable::from_table(t)?),
+            Value::UserData(ud) => match ud.downcast_static::<SharedTable>() {
+                Ok(shared) => SharedValue::Table(shared.clone()),
+                Err(_) => return Err(SharedDataError::UnsupportedValue("userdata")),
+            },
+            value => return Err(SharedDataError::UnsupportedValue(value.type_name())),
+        }))
+    }
+}
+
+impl<'gc> IntoValue<'gc> for &SharedValue {
+    fn into_value(self, ctx: Context<'gc>) -> Value<'gc> {
+        match self {
+            SharedValue::Boolean(b) => Value::Boolean(*b),
+            SharedValue::Integer(i) => Value::Integer(*i),
+            SharedValue::Number(n) => Value::Number(*n),
+            SharedValue::String(s) => Value::String(ctx.intern(s)),
+            SharedValue::Table(t) => t.clone().into_value(ctx),
+        }
+    }
+}
+
+/// An immutable table which can be shared between any number of [`Lua`](crate::Lua) instances.
+///
+/// The contents of a `SharedTable` live outside of any garbage collected arena, behind an [`Arc`],
+/// so large read-only data such as lookup tables or localized strings can be given to many Lua
+/// instances (even on different threads) while only being stored once.
+///
+/// Scripts see a `SharedTable` as a frozen table: it can be indexed, measured with `#`, and
+/// iterated with `pairs` and `ipairs`, but any attempt to modify it raises an error. Values are
+/// only converted to Lua values when they are accessed, strings are interned into the accessing
+/// instance and nested tables are exposed as `SharedTable`s themselves. Since they are userdata
+/// rather than tables, two accesses of the same nested table compare equal with `==` but are
+/// distinct when used as table keys.
+#[derive(Clone)]
+pub struct SharedTable(Arc<SharedTableInner>);
+
+struct SharedTableInner {
+    // Values for the keys `1..=array.len()`.
+    array: Vec<SharedValue>,
+    map: Vec<(SharedKey, SharedValue)>,
+    // The position of every key in `map`.
+    index: HashMap<SharedKey, usize, BuildHasherDefault<AHasher>>,
+}
+
+impl fmt::Debug for SharedTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl PartialEq for SharedTable {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for SharedTable {}
+
+impl FromIterator<(SharedKey, SharedValue)> for SharedTable {
+    fn from_iter<I: IntoIterator<Item = (SharedKey, SharedValue)>>(iter: I) -> Self {
+        let mut entries: StdHashMap<SharedKey, SharedValue> = iter.into_iter().collect();
+
+        let mut array = Vec::new();
+        while let Some(value) = entries.remove(&SharedKey::Integer(array.len() as i64 + 1)) {
+            array.push(value);
+        }
+
+        // Sorting makes the iteration order deterministic.
+        let mut map: Vec<_> = entries.into_iter().collect();
+        map.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let index = map
+            .iter()
+            .enumerate()
+            .map(|(i, (key, _))| (key.clone(), i))
+            .collect();
+
+        SharedTable(Arc::new(SharedTableInner { array, map, index }))
+    }
+}
+
+impl SharedTable {
+    /// Create a `SharedTable` with the given values at the keys `1..=n`.
+    pub fn from_sequence(values: impl IntoIterator<Item = impl Into<SharedValue>>) -> Self {
+        values
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (SharedKey::Integer(i as i64 + 1), v.into()))
+            .collect()
+    }
+
+    /// Copy the contents of a Lua table into a new `SharedTable`.
+    ///
+    /// Nested tables are copied as nested `SharedTable`s, a table which is referenced more than
+    /// once is only copied once. Metatables are ignored. Returns an error if the table contains
+    /// functions, threads, or userdata (other than `SharedTable`s), or if it contains itself.
+    pub fn from_table(table: Table<'_>) -> Result<Self, SharedDataError> {
+        fn convert(
+            table: Table<'_>,
+            converted: &mut StdHashMap<*const (), Option<SharedTable>>,
+        ) -> Result<SharedTable, SharedDataError> {
+            let ptr = Gc::as_ptr(table.into_inner()) as *const ();
+            match converted.get(&ptr) {
+                Some(Some(shared)) => return Ok(shared.clone()),
+                Some(None) => return Err(SharedDataError::Cycle),
+                None => {}
+            }
+            converted.insert(ptr, None);
+
+            let entries = table
+                .iter()
+                .map(|(key, value)| {
+                    let key = match key {
+                        Value::Boolean(b) => SharedKey::Boolean(b),
+                        Value::Integer(i) => SharedKey::Integer(i),
+                        Value::Number(n) => SharedKey::Integer(
+                            f64_to_i64_exact(n).ok_or(SharedDataError::UnsupportedKey("float"))?,
+                        ),
+                        Value::String(s) => SharedKey::String(s.as_bytes().into()),
+                        key => return Err(SharedDataError::UnsupportedKey(key.type_name())),
+                    };
+                    let value = match value {
+                        Value::Boolean(b) => SharedValue::Boolean(b),
+                        Value::Integer(i) => SharedValue::Integer(i),
+                        Value::Number(n) => SharedValue::Number(n),
+                        Value::String(s) => SharedValue::String(s.as_bytes().into()),
+                        Value::Table(t) => SharedValue::Table(convert(t, converted)?),
+                        Value::UserData(ud) => match ud.downcast_static::<SharedTable>() {
+                            Ok(shared) => SharedValue::Table(shared.clone()),
+                            Err(_) => return Err(SharedDataError::UnsupportedValue("userdata")),
+                        },
+                        value => return Err(SharedDataError::UnsupportedValue(value.type_name())),
+                    };
+                    Ok((key, value))
+                })
+                .collect::<Result<SharedTable, _>>()?;
+
+            converted.insert(ptr, Some(entries.clone()));
+            Ok(entries)
+        }
+
+        convert(table, &mut StdHashMap::new())
+    }
+
+    /// The length of the sequence part of the table, the same as `#t` in Lua.
+    pub fn len(&self) -> usize {
+        self.0.array.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.array.is_empty() && self.0.map.is_empty()
+    }
+
+    pub fn get(&self, key: impl Into<SharedKey>) -> Option<&SharedValue> {
+        self.get_ref(key.into().as_key_ref())
+    }
+
+    /// Iterate over every entry, first the sequence part in order followed by the remaining keys
+    /// in sorted order. This is the same order as `pairs` in Lua.
+    pub fn iter(&self) -> impl Iterator<Item = (SharedKey, &SharedValue)> + '_ {
+        self.0
+            .array
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (SharedKey::Integer(i as i64 + 1), v))
+            .chain(self.0.map.iter().map(|(k, v)| (k.clone(), v)))
+    }
+
+    /// Returns true if both are the same table, rather than tables with equal contents.
+    pub fn ptr_eq(&self, other: &SharedTable) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    fn get_ref(&self, key: KeyRef) -> Option<&SharedValue> {
+        if let KeyRef::Integer(i) = key {
+            if i >= 1 && i <= self.0.array.len() as i64 {
+                return Some(&self.0.array[i as usize - 1]);
+            }
+        }
+        Some(&self.0.map[*self.0.index.get(&key)?].1)
+    }
+
+    // The entry after the given key, in the same order as `SharedTable::iter`. Returns an error if
+    // the key is not in the table.
+    fn next_entry<'gc>(
+        &self,
+        ctx: Context<'gc>,
+        key: Value<'gc>,
+    ) -> Result<Option<(Value<'gc>, Value<'gc>)>, ()> {
+        let position = if key.is_nil() {
+            0
+        } else {
+            match KeyRef::from_value(key) {
+                Some(KeyRef::Integer(i)) if i >= 1 && i <= self.0.array.len() as i64 => i as usize,
+                Some(key) => self.0.array.len() + self.0.index.get(&key).ok_or(())? + 1,
+                None => return Err(()),
+            }
+        };
+
+        Ok(if let Some(value) = self.0.array.get(position) {
+            Some((Value::Integer(position as i64 + 1), value.into_value(ctx)))
+        } else if let Some((key, value)) = self.0.map.get(position - self.0.array.len()) {
+            let key = match key {
+                SharedKey::Boolean(b) => Value::Boolean(*b),
+                SharedKey::Integer(i) => Value::Integer(*i),
+                SharedKey::String(s) => Value::String(ctx.intern(s)),
+            };
+            Some((key, value.into_value(ctx)))
+        } else {
+            None
+        })
+    }
+}
+
+impl UserDataType for SharedTable {
+    const NAME: Option<&'static str> = Some("SharedTable");
+
+    fn add_methods<'gc>(methods: &mut UserDataMethods<'gc, Self>) {
+        methods.add_meta_method(MetaMethod::Index, |this, ctx, key: Value<'gc>| {
+            Ok(KeyRef::from_value(key)
+                .and_then(|key| this.get_ref(key))
+                .map(|value| value.into_value(ctx)))
+        });
+
+        methods.add_meta_method(MetaMethod::NewIndex, |_, ctx, ()| {
+            Err::<(), _>("attempt to modify a frozen table".into_value(ctx).into())
+        });
+
+        methods.add_meta_method(MetaMethod::Len, |this, _, ()| Ok(this.len() as i64));
+
+        methods.add_meta_function(
+            MetaMethod::Eq,
+            |_, (a, b): (UserData<'gc>, UserData<'gc>)| {
+                Ok(
+                    match (
+                        a.downcast_static::<SharedTable>(),
+                        b.downcast_static::<SharedTable>(),
+                    ) {
+                        (Ok(a), Ok(b)) => a.ptr_eq(b),
+                        _ => false,
+                    },
+                )
+            },
+        );
+
+        methods.add_meta_function(MetaMethod::Pairs, |ctx, this: UserData<'gc>| {
+            let next = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (this, key): (UserData, Value) = stack.consume(ctx)?;
+                let this = this.downcast_static::<SharedTable>()?;
+                match this.next_entry(ctx, key) {
+                    Ok(Some(entry)) => stack.replace(ctx, entry),
+                    Ok(None) => stack.replace(ctx, Value::Nil),
+                    Err(_) => {
+                        return Err("invalid key to 'next'".into_value(ctx).into());
+                    }
+                }
+                Ok(CallbackReturn::Return)
+            });
+            Ok((next, this, Value::Nil))
+        });
+    }
+}
+
+impl<'gc> IntoValue<'gc> for SharedTable {
+    fn into_value(self, ctx: Context<'gc>) -> Value<'gc> {
+        UserData::new_typed(ctx, self).into()
+    }
+}
+
+impl<'gc> FromValue<'gc> for SharedTable {
+    fn from_value(_: Context<'gc>, value: Value<'gc>) -> Result<Self, TypeError> {
+        match value {
+            Value::UserData(ud) => match ud.downcast_static::<SharedTable>() {
+                Ok(shared) => Ok(shared.clone()),
+                Err(_) => Err(TypeError::new("SharedTable", value)),
+            },
+            _ => Err(TypeError::new("SharedTable", value)),
+        }
+    }
+}
@@ -7,7 +7,9 @@ use std::{
 use allocator_api2::vec;
 use gc_arena::allocator_api::MetricsAlloc;
 
-use crate::{Context, FromMultiValue, FromValue, IntoMultiValue, IntoValue, TypeError, Value};
+use crate::{
+    Context, FromMultiValue, FromValue, IntoMultiValue, IntoValue, TypeError, Value, Variadic,
+};
 
 /// The mechanism through which all callbacks receive parameters and return values.
 ///
@@ -42,6 +44,52 @@ impl<'gc, 'a> Stack<'gc, 'a> {
         }
     }
 
+    /// Returns a sub-stack of every value starting at index `at`.
+    ///
+    /// Unlike [`Stack::sub_stack`], if the stack has fewer than `at` values, then it is first
+    /// padded with nils so that the returned view is always empty rather than invalid. Values
+    /// pushed to or popped from the returned stack only affect the values after `at`.
+    pub fn split_off(&mut self, at: usize) -> Stack<'gc, '_> {
+        if self.len() < at {
+            self.resize(at);
+        }
+        self.sub_stack(at)
+    }
+
+    /// Converts the first `count` values into `H`, and returns it along with a sub-stack of the
+    /// remaining values.
+    ///
+    /// The head values are not removed and remain below the returned sub-stack. If there are fewer
+    /// than `count` values, the missing values are converted as nil.
+    ///
+    /// This is useful for builtins which take some fixed arguments followed by a variable number of
+    /// arguments, which can then be passed on to some helper function as a separate `Stack`.
+    pub fn split_head<H: FromMultiValue<'gc>>(
+        &mut self,
+        ctx: Context<'gc>,
+        count: usize,
+    ) -> Result<(H, Stack<'gc, '_>), TypeError> {
+        let head = H::from_multi_value(ctx, (0..count).map(|i| self.get(i)))?;
+        Ok((head, self.split_off(count)))
+    }
+
+    /// Converts every value starting at index `start` into a `Variadic` without removing them.
+    ///
+    /// The position of a conversion error is the position of the bad value in the whole stack.
+    pub fn tail<T: FromValue<'gc>>(
+        &self,
+        ctx: Context<'gc>,
+        start: usize,
+    ) -> Result<Variadic<Vec<T>>, TypeError> {
+        let first = (self.bottom + start).min(self.values.len());
+        Variadic::from_multi_value(ctx, self.values[first..].iter().copied()).map_err(
+            |mut err: TypeError| {
+                err.position = err.position.map(|position| start + position);
+                err
+            },
+        )
+    }
+
     pub fn get(&self, i: usize) -> Value<'gc> {
         self.values
             .get(self.bottom + i)
@@ -1,12 +1,13 @@
-use std::pin::Pin;
+use std::{pin::Pin, string::String as StdString};
 
-use gc_arena::Collect;
+use gc_arena::{metrics::Pacing, Collect};
 
 use crate::{
-    meta_ops::{self, MetaResult},
+    closure::UpValueState,
+    meta_ops::{self, MetaPairs, MetaResult},
     table::NextValue,
-    BoxSequence, Callback, CallbackReturn, Context, Error, Execution, IntoValue, MetaMethod,
-    Sequence, SequencePoll, Stack, String, Table, TypeError, Value, Variadic,
+    BoxSequence, Callback, CallbackReturn, Closure, Context, Error, ErrorBoundary, Execution,
+    Function, IntoValue, Sequence, SequencePoll, Stack, String, Table, TypeError, Value, Variadic,
 };
 
 pub fn load_base<'gc>(ctx: Context<'gc>) {
@@ -31,13 +32,7 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
                 // Avoid implicitly converting value to a string
                 let s = match value {
                     Value::String(s) => s,
-                    _ => {
-                        return Err(TypeError {
-                            expected: "string",
-                            found: value.type_name(),
-                        }
-                        .into())
-                    }
+                    _ => return Err(TypeError::new("string", value).into()),
                 };
                 if !(2..=36).contains(&base) {
                     Err("base out of range".into_value(ctx))?;
@@ -110,6 +105,47 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
         }),
     );
 
+    ctx.set_global(
+        "warn",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            if stack.is_empty() {
+                return Err("bad argument #1 to 'warn' (string expected, got no value)"
+                    .into_value(ctx)
+                    .into());
+            }
+
+            let mut message = Vec::new();
+            for i in 0..stack.len() {
+                let value = stack.get(i);
+                let Some(s) = value.into_string(ctx) else {
+                    return Err(format!(
+                        "bad argument #{} to 'warn' (string expected, got {})",
+                        i + 1,
+                        value.type_name()
+                    )
+                    .into_value(ctx)
+                    .into());
+                };
+                message.extend_from_slice(s.as_bytes());
+            }
+
+            let output = ctx.output();
+            if stack.len() == 1 && message.starts_with(b"@") {
+                // A control message, unknown control messages are ignored.
+                match &message[..] {
+                    b"@on" => output.set_warnings_enabled(true),
+                    b"@off" => output.set_warnings_enabled(false),
+                    _ => {}
+                }
+            } else {
+                output.warn(&message)?;
+            }
+
+            stack.clear();
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
     ctx.set_global(
         "pcall",
         Callback::from_fn(&ctx, move |ctx, _, mut stack| {
@@ -122,6 +158,20 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
         }),
     );
 
+    ctx.set_global(
+        "xpcall",
+        Callback::from_fn(&ctx, move |ctx, _, mut stack| {
+            let function = meta_ops::call(ctx, stack.get(0))?;
+            let handler = meta_ops::call(ctx, stack.get(1))?;
+            stack.pop_front();
+            stack.pop_front();
+            Ok(CallbackReturn::Call {
+                function,
+                then: Some(BoxSequence::new(&ctx, XPCall { handler })),
+            })
+        }),
+    );
+
     ctx.set_global(
         "type",
         Callback::from_fn(&ctx, |ctx, _, mut stack| {
@@ -193,14 +243,23 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
     ctx.set_global(
         "getmetatable",
         Callback::from_fn(&ctx, |ctx, _, mut stack| {
-            if let Value::Table(t) = stack.get(0) {
-                stack.replace(ctx, t.metatable());
-                Ok(CallbackReturn::Return)
-            } else {
-                Err("'getmetatable' can only be used on table types"
-                    .into_value(ctx)
-                    .into())
+            let metatable = match stack.get(0) {
+                Value::Table(t) => t.metatable(),
+                Value::String(_) => ctx.string_metatable(),
+                _ => {
+                    return Err("'getmetatable' can only be used on table and string types"
+                        .into_value(ctx)
+                        .into())
+                }
+            };
+            match metatable {
+                Some(mt) => match mt.get_value(ctx, "__metatable") {
+                    Value::Nil => stack.replace(ctx, mt),
+                    protected => stack.replace(ctx, protected),
+                },
+                None => stack.replace(ctx, Value::Nil),
             }
+            Ok(CallbackReturn::Return)
         }),
     );
 
@@ -208,7 +267,12 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
         "setmetatable",
         Callback::from_fn(&ctx, |ctx, _, mut stack| {
             let (t, mt): (Table, Option<Table>) = stack.consume(ctx)?;
-            t.set_metatable(&ctx, mt);
+            if t.metatable()
+                .is_some_and(|mt| !mt.get_value(ctx, "__metatable").is_nil())
+            {
+                return Err("cannot change a protected metatable".into_value(ctx).into());
+            }
+            t.set_metatable(ctx, mt);
             stack.replace(ctx, t);
             Ok(CallbackReturn::Return)
         }),
@@ -237,25 +301,38 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
     ctx.set_global(
         "pairs",
         Callback::from_fn_with(&ctx, next, move |next, ctx, _, mut stack| {
-            let table = stack.get(0);
-            if let Some(mt) = match table {
-                Value::Table(t) => t.metatable(),
-                Value::UserData(u) => u.metatable(),
-                _ => None,
-            } {
-                let pairs = mt.get_value(ctx, MetaMethod::Pairs);
-                if !pairs.is_nil() {
-                    let function = meta_ops::call(ctx, pairs)?;
-                    stack.replace(ctx, (table, Value::Nil));
-                    return Ok(CallbackReturn::Call {
-                        function,
-                        then: None,
-                    });
+            let value = stack.get(0);
+            match meta_ops::pairs(ctx, value)? {
+                MetaPairs::Table(table) => {
+                    stack.replace(ctx, (*next, table));
+                    Ok(CallbackReturn::Return)
                 }
-            }
+                MetaPairs::Call(call) => {
+                    #[derive(Collect)]
+                    #[collect(require_static)]
+                    struct PairsResults;
+
+                    impl<'gc> Sequence<'gc> for PairsResults {
+                        fn poll(
+                            self: Pin<&mut Self>,
+                            _ctx: Context<'gc>,
+                            _exec: Execution<'gc, '_>,
+                            mut stack: Stack<'gc, '_>,
+                        ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+                            // `__pairs` always produces exactly 3 results.
+                            stack.resize(3);
+                            Ok(SequencePoll::Return)
+                        }
+                    }
 
-            stack.replace(ctx, (*next, table));
-            Ok(CallbackReturn::Return)
+                    stack.clear();
+                    stack.extend(call.args);
+                    Ok(CallbackReturn::Call {
+                        function: call.function,
+                        then: Some(BoxSequence::new(&ctx, PairsResults)),
+                    })
+                }
+            }
         }),
     );
 
@@ -308,22 +385,212 @@ pub fn load_base<'gc>(ctx: Context<'gc>) {
     ctx.set_global(
         "collectgarbage",
         Callback::from_fn(&ctx, move |ctx, _, mut stack| {
-            match stack.consume::<Option<String>>(ctx)? {
-                Some(arg) if arg == "count" => {
-                    stack.into_back(ctx, ctx.metrics().total_allocation() as f64 / 1024.0);
+            // Collection cannot happen while inside the arena, so "collect" and "step" only
+            // request a full collection, which happens before the calling Lua code continues.
+            let option: Option<String> = stack.from_front(ctx)?;
+            let gc = ctx.gc_control();
+            match option
+                .map(String::as_bytes)
+                .unwrap_or(b"collect".as_slice())
+            {
+                b"collect" => {
+                    gc.request_collection();
+                    stack.replace(ctx, 0);
+                }
+                b"step" => {
+                    gc.request_collection();
+                    stack.replace(ctx, true);
+                }
+                b"count" => {
+                    stack.replace(ctx, ctx.memory_usage() as f64 / 1024.0);
+                }
+                b"stop" => {
+                    gc.set_running(false);
+                    stack.replace(ctx, 0);
+                }
+                b"restart" => {
+                    gc.set_running(true);
+                    stack.replace(ctx, 0);
                 }
-                Some(_) => {
-                    return Err("bad argument to 'collectgarbage'".into_value(ctx).into());
+                b"isrunning" => {
+                    stack.replace(ctx, gc.is_running());
+                }
+                b"incremental" => {
+                    // Only the pause is meaningful to gc-arena. Like in PUC-Rio Lua, it is the
+                    // percentage of memory which must be in use after a collection before the next
+                    // one starts, a missing or zero pause keeps the current pacing.
+                    let pause: Option<i64> = stack.from_front(ctx)?;
+                    if let Some(pause) = pause.filter(|&p| p > 0) {
+                        let mut pacing = Pacing::default();
+                        pacing.sleep_factor = (pause.max(100) - 100) as f64 / 100.0;
+                        ctx.metrics().set_pacing(pacing);
+                    }
+                    stack.replace(ctx, "incremental");
+                }
+                b"generational" => {
+                    // There is no generational mode, the collector stays incremental.
+                    stack.replace(ctx, "incremental");
+                }
+                _ => {
+                    return Err(format!(
+                        "bad argument #1 to 'collectgarbage' (invalid option '{}')",
+                        option.unwrap().display_lossy()
+                    )
+                    .into_value(ctx)
+                    .into());
                 }
-                None => {}
             }
             Ok(CallbackReturn::Return)
         }),
     );
 
+    ctx.set_global(
+        "load",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let chunk: Value = stack.from_front(ctx)?;
+            let name: Option<String> = stack.from_front(ctx)?;
+            let mode: Option<String> = stack.from_front(ctx)?;
+            // An explicit `nil` environment is still used as the environment.
+            let env = (!stack.is_empty()).then(|| stack.get(0));
+            let args = LoadArgs { name, mode, env };
+
+            match chunk {
+                Value::String(source) => {
+                    args.load(ctx, source.as_bytes(), stack);
+                    Ok(CallbackReturn::Return)
+                }
+                Value::Function(reader) => {
+                    stack.clear();
+                    Ok(CallbackReturn::Call {
+                        function: reader,
+                        then: Some(BoxSequence::new(
+                            &ctx,
+                            LoadReader {
+                                args,
+                                reader,
+                                source: Vec::new(),
+                            },
+                        )),
+                    })
+                }
+                chunk => Err(format!(
+                    "bad argument #1 to 'load' (string expected, got {})",
+                    chunk.type_name()
+                )
+                .into_value(ctx)
+                .into()),
+            }
+        }),
+    );
+
     ctx.set_global("_VERSION", "piccolo");
 }
 
+#[derive(Collect)]
+#[collect(no_drop)]
+struct LoadArgs<'gc> {
+    name: Option<String<'gc>>,
+    mode: Option<String<'gc>>,
+    env: Option<Value<'gc>>,
+}
+
+impl<'gc> LoadArgs<'gc> {
+    // Compile the chunk and place the result of `load` on the stack, either the loaded function or
+    // `nil` and an error message.
+    fn load(&self, ctx: Context<'gc>, source: &[u8], mut stack: Stack<'gc, '_>) {
+        match self.compile(ctx, source) {
+            Ok(closure) => stack.replace(ctx, closure),
+            Err(msg) => stack.replace(ctx, (Value::Nil, msg)),
+        }
+    }
+
+    fn compile(&self, ctx: Context<'gc>, source: &[u8]) -> Result<Closure<'gc>, StdString> {
+        let mode = self.mode.map(|m| m.as_bytes()).unwrap_or(b"bt".as_slice());
+        // Binary chunks start with the escape character, like PUC-Rio Lua.
+        let is_binary = source.first() == Some(&0x1b);
+        let (kind, allowed) = if is_binary {
+            ("binary", mode.contains(&b'b'))
+        } else {
+            ("text", mode.contains(&b't'))
+        };
+        if !allowed {
+            return Err(format!(
+                "attempt to load a {kind} chunk (mode is '{}')",
+                StdString::from_utf8_lossy(mode)
+            ));
+        }
+        if is_binary {
+            return Err("binary chunks are not supported".to_owned());
+        }
+
+        let name = self
+            .name
+            .map(|n| StdString::from_utf8_lossy(n.as_bytes()).into_owned());
+        let closure = Closure::load(ctx, Some(name.as_deref().unwrap_or("(load)")), source)
+            .map_err(|err| err.to_string())?;
+
+        if let Some(env) = self.env {
+            // The first upvalue of a main chunk is always `_ENV`.
+            if let Some(upvalue) = closure.upvalues().first() {
+                upvalue.set(&ctx, UpValueState::Closed(env));
+            }
+        }
+        Ok(closure)
+    }
+}
+
+// Collects the pieces of a chunk returned by a `load` reader function.
+#[derive(Collect)]
+#[collect(no_drop)]
+struct LoadReader<'gc> {
+    args: LoadArgs<'gc>,
+    reader: Function<'gc>,
+    source: Vec<u8>,
+}
+
+impl<'gc> Sequence<'gc> for LoadReader<'gc> {
+    fn poll(
+        mut self: Pin<&mut Self>,
+        ctx: Context<'gc>,
+        _exec: Execution<'gc, '_>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        match stack.get(0) {
+            Value::String(piece) if !piece.as_bytes().is_empty() => {
+                self.source.extend_from_slice(piece.as_bytes());
+                stack.clear();
+                Ok(SequencePoll::Call {
+                    function: self.reader,
+                    bottom: 0,
+                })
+            }
+            Value::Nil | Value::String(_) => {
+                self.args.load(ctx, &self.source, stack);
+                Ok(SequencePoll::Return)
+            }
+            _ => {
+                stack.replace(ctx, (Value::Nil, "reader function must return a string"));
+                Ok(SequencePoll::Return)
+            }
+        }
+    }
+
+    fn error(
+        self: Pin<&mut Self>,
+        ctx: Context<'gc>,
+        _exec: Execution<'gc, '_>,
+        error: Error<'gc>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        stack.replace(ctx, (Value::Nil, error));
+        Ok(SequencePoll::Return)
+    }
+
+    fn error_boundary(&self) -> ErrorBoundary<'gc> {
+        ErrorBoundary::Catch
+    }
+}
+
 #[derive(Collect)]
 #[collect(require_static)]
 pub struct PCall;
@@ -349,4 +616,42 @@ impl<'gc> Sequence<'gc> for PCall {
         stack.replace(ctx, (false, error));
         Ok(SequencePoll::Return)
     }
+
+    fn error_boundary(&self) -> ErrorBoundary<'gc> {
+        ErrorBoundary::Catch
+    }
+}
+
+#[derive(Collect)]
+#[collect(no_drop)]
+pub struct XPCall<'gc> {
+    handler: Function<'gc>,
+}
+
+impl<'gc> Sequence<'gc> for XPCall<'gc> {
+    fn poll(
+        self: Pin<&mut Self>,
+        ctx: Context<'gc>,
+        _exec: Execution<'gc, '_>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        stack.into_front(ctx, true);
+        Ok(SequencePoll::Return)
+    }
+
+    fn error(
+        self: Pin<&mut Self>,
+        ctx: Context<'gc>,
+        _exec: Execution<'gc, '_>,
+        error: Error<'gc>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        // The message handler has already been called by the executor, `error` is its result.
+        stack.replace(ctx, (false, error));
+        Ok(SequencePoll::Return)
+    }
+
+    fn error_boundary(&self) -> ErrorBoundary<'gc> {
+        ErrorBoundary::Handler(self.handler)
+    }
 }
@@ -0,0 +1,275 @@
+use std::pin::Pin;
+
+use gc_arena::{Collect, Rootable};
+
+use crate::{
+    meta_ops::{self, MAX_META_CHAIN},
+    BoxSequence, Callback, CallbackReturn, Context, Error, Execution, Function, IntoValue,
+    Sequence, SequencePoll, Singleton, Stack, String, Table, Value,
+};
+
+/// Load the `class` library, a small object system built on metatables.
+///
+/// `class.new(name, parent)` (or just `class(name, parent)`) creates a class table, which is the
+/// metatable of all of its instances and has itself as `__index`, so methods defined on the class
+/// are available on every instance. Calling a class creates an instance and calls its `init`
+/// method with the given arguments. A class inherits methods from its parent class through the
+/// `__index` of its own metatable, and copies the metamethods defined on the parent when it is
+/// created.
+///
+/// The library also provides `class.isinstance(value, class)`, `class.of(value)`,
+/// `class.parent(class)`, `class.isclass(value)`, and `class.name(class)`.
+///
+/// Classes can also be created from Rust with [`new_class`]. Userdata whose metatable is a class
+/// table are instances of that class like tables are, so host types can take part in the same
+/// class hierarchy as script-defined ones.
+///
+/// This library is not part of [`Lua::core`](crate::Lua::core).
+pub fn load_class<'gc>(ctx: Context<'gc>) {
+    let class = Table::new(&ctx);
+
+    class.set_field(
+        ctx,
+        "new",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (name, parent): (Option<String>, Option<Table>) = stack.consume(ctx)?;
+            stack.replace(ctx, new_class(ctx, name, parent)?);
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    class.set_field(
+        ctx,
+        "isinstance",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (value, class): (Value, Table) = stack.consume(ctx)?;
+            if !is_class(ctx, class) {
+                return Err("bad argument #2 to 'isinstance' (class expected)"
+                    .into_value(ctx)
+                    .into());
+            }
+            stack.replace(ctx, is_instance(ctx, value, class));
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    class.set_field(
+        ctx,
+        "of",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let value: Value = stack.consume(ctx)?;
+            stack.replace(ctx, class_of(ctx, value));
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    class.set_field(
+        ctx,
+        "parent",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let class: Table = stack.consume(ctx)?;
+            stack.replace(ctx, parent_class(ctx, class));
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    class.set_field(
+        ctx,
+        "isclass",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let value: Value = stack.consume(ctx)?;
+            stack.replace(ctx, matches!(value, Value::Table(t) if is_class(ctx, t)));
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    class.set_field(
+        ctx,
+        "name",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let class: Table = stack.consume(ctx)?;
+            let name = if is_class(ctx, class) {
+                class.get_value(ctx, "__name")
+            } else {
+                Value::Nil
+            };
+            stack.replace(ctx, name);
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    let meta = Table::new(&ctx);
+    meta.set_field(
+        ctx,
+        "__call",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (_, name, parent): (Value, Option<String>, Option<Table>) = stack.consume(ctx)?;
+            stack.replace(ctx, new_class(ctx, name, parent)?);
+            Ok(CallbackReturn::Return)
+        }),
+    );
+    class.set_metatable(ctx, Some(meta));
+
+    ctx.set_global("class", class);
+}
+
+/// Create a new class, inheriting from `parent` if it is given, which must itself be a class.
+///
+/// The class table is the metatable of every instance of the class, and may also be set as the
+/// metatable of userdata.
+pub fn new_class<'gc>(
+    ctx: Context<'gc>,
+    name: Option<String<'gc>>,
+    parent: Option<Table<'gc>>,
+) -> Result<Table<'gc>, Error<'gc>> {
+    let class = Table::new(&ctx);
+
+    if let Some(parent) = parent {
+        if !is_class(ctx, parent) {
+            return Err("parent is not a class".into_value(ctx).into());
+        }
+
+        // Metamethods are only looked up in the metatable itself, so they are not inherited
+        // through `__index` like other methods.
+        for (key, value) in parent {
+            if let Value::String(key) = key {
+                if key.as_bytes().starts_with(b"__") && key != b"__index" && key != b"__name" {
+                    class.set_raw(&ctx, key.into(), value).unwrap();
+                }
+            }
+        }
+    }
+
+    class.set_field(ctx, "__index", class);
+    if let Some(name) = name {
+        class.set_field(ctx, "__name", name);
+    }
+
+    let meta = Table::new(&ctx);
+    meta.set_field(ctx, "__call", ClassState::get(ctx).construct);
+    if let Some(parent) = parent {
+        meta.set_field(ctx, "__index", parent);
+    }
+    class.set_metatable(ctx, Some(meta));
+
+    Ok(class)
+}
+
+/// Returns true if `table` is a class created with [`new_class`] or by the `class` library.
+pub fn is_class<'gc>(ctx: Context<'gc>, table: Table<'gc>) -> bool {
+    let construct = ClassState::get(ctx).construct;
+    table.metatable().is_some_and(|meta| {
+        matches!(
+            meta.get_value(ctx, "__call"),
+            Value::Function(Function::Callback(c)) if c == construct
+        )
+    })
+}
+
+/// The class of a table or userdata, which is its metatable if that is a class.
+pub fn class_of<'gc>(ctx: Context<'gc>, value: Value<'gc>) -> Option<Table<'gc>> {
+    let meta = match value {
+        Value::Table(t) => t.metatable(),
+        Value::UserData(u) => u.metatable(),
+        _ => None,
+    }?;
+    is_class(ctx, meta).then_some(meta)
+}
+
+/// The class that `class` inherits from, if it is a class and has a parent.
+pub fn parent_class<'gc>(ctx: Context<'gc>, class: Table<'gc>) -> Option<Table<'gc>> {
+    if !is_class(ctx, class) {
+        return None;
+    }
+    match class.metatable()?.get_value(ctx, "__index") {
+        Value::Table(parent) => Some(parent),
+        _ => None,
+    }
+}
+
+/// Returns true if `value` is an instance of `class` or of any class inheriting from it.
+pub fn is_instance<'gc>(ctx: Context<'gc>, value: Value<'gc>, class: Table<'gc>) -> bool {
+    let mut current = class_of(ctx, value);
+    for _ in 0..MAX_META_CHAIN {
+        match current {
+            Some(c) if c == class => return true,
+            Some(c) => current = parent_class(ctx, c),
+            None => return false,
+        }
+    }
+    false
+}
+
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+struct ClassState<'gc> {
+    // The `__call` metamethod shared by the metatable of every class, which is also how classes
+    // are told apart from other tables.
+    construct: Callback<'gc>,
+}
+
+impl<'gc> Singleton<'gc> for ClassState<'gc> {
+    fn create(ctx: Context<'gc>) -> Self {
+        let construct = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let class = match stack.get(0) {
+                Value::Table(class) if is_class(ctx, class) => class,
+                _ => {
+                    return Err("class constructor called without a class"
+                        .into_value(ctx)
+                        .into())
+                }
+            };
+
+            let instance = Table::new(&ctx);
+            instance.set_metatable(ctx, Some(class));
+            stack[0] = instance.into();
+
+            let mut init = Value::Nil;
+            let mut current = Some(class);
+            for _ in 0..MAX_META_CHAIN {
+                let Some(c) = current else {
+                    break;
+                };
+                init = c.get_value(ctx, "init");
+                if !init.is_nil() {
+                    break;
+                }
+                current = parent_class(ctx, c);
+            }
+
+            if init.is_nil() {
+                stack.replace(ctx, instance);
+                Ok(CallbackReturn::Return)
+            } else {
+                Ok(CallbackReturn::Call {
+                    function: meta_ops::call(ctx, init)?,
+                    then: Some(BoxSequence::new(&ctx, Construct(instance))),
+                })
+            }
+        });
+        Self { construct }
+    }
+}
+
+impl<'gc> ClassState<'gc> {
+    fn get(ctx: Context<'gc>) -> Self {
+        *ctx.singleton::<Rootable![ClassState<'_>]>()
+    }
+}
+
+// Returns the new instance once its `init` method has returned.
+#[derive(Collect)]
+#[collect(no_drop)]
+struct Construct<'gc>(Table<'gc>);
+
+impl<'gc> Sequence<'gc> for Construct<'gc> {
+    fn poll(
+        self: Pin<&mut Self>,
+        ctx: Context<'gc>,
+        _exec: Execution<'gc, '_>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        stack.replace(ctx, self.0);
+        Ok(SequencePoll::Return)
+    }
+}
@@ -1,4 +1,7 @@
-use crate::{meta_ops, BoxSequence, Callback, CallbackReturn, Context, Table, Thread, ThreadMode};
+use crate::{
+    meta_ops, BoxSequence, Callback, CallbackReturn, Context, IntoValue, String, Table, Thread,
+    ThreadMode,
+};
 
 use super::base::PCall;
 
@@ -8,8 +11,8 @@ pub fn load_coroutine<'gc>(ctx: Context<'gc>) {
     coroutine.set_field(
         ctx,
         "create",
-        Callback::from_fn(&ctx, |ctx, _, mut stack| {
-            let thread = Thread::new(ctx);
+        Callback::from_fn(&ctx, |ctx, exec, mut stack| {
+            let thread = Thread::with_parent(ctx, exec.current_thread().thread);
             thread
                 .start_suspended(&ctx, meta_ops::call(ctx, stack.get(0))?)
                 .unwrap();
@@ -39,6 +42,68 @@ pub fn load_coroutine<'gc>(ctx: Context<'gc>) {
         }),
     );
 
+    coroutine.set_field(
+        ctx,
+        "wrap",
+        Callback::from_fn(&ctx, |ctx, exec, mut stack| {
+            let thread = Thread::with_parent(ctx, exec.current_thread().thread);
+            thread
+                .start_suspended(&ctx, meta_ops::call(ctx, stack.get(0))?)
+                .unwrap();
+            stack.replace(
+                ctx,
+                Callback::from_fn_with(&ctx, thread, |&thread, ctx, _, _| {
+                    // Without a `then` sequence, any error raised by the coroutine propagates to
+                    // the caller unchanged.
+                    match thread.mode() {
+                        ThreadMode::Suspended => Ok(CallbackReturn::Resume { thread, then: None }),
+                        ThreadMode::Stopped => {
+                            Err("cannot resume dead coroutine".into_value(ctx).into())
+                        }
+                        _ => Err("cannot resume non-suspended coroutine"
+                            .into_value(ctx)
+                            .into()),
+                    }
+                }),
+            );
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    coroutine.set_field(
+        ctx,
+        "close",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let thread: Thread = stack.consume(ctx)?;
+            match thread.mode() {
+                ThreadMode::Running => {
+                    return Err("cannot close a running coroutine".into_value(ctx).into());
+                }
+                ThreadMode::Normal | ThreadMode::Waiting => {
+                    return Err("cannot close a normal coroutine".into_value(ctx).into());
+                }
+                _ => {}
+            }
+            match thread.close(&ctx)? {
+                Ok(()) => stack.replace(ctx, true),
+                Err(err) => stack.replace(ctx, (false, err.to_value(ctx))),
+            }
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    coroutine.set_field(
+        ctx,
+        "isyieldable",
+        Callback::from_fn(&ctx, |ctx, exec, mut stack| {
+            let thread: Option<Thread> = stack.consume(ctx)?;
+            let thread = thread.unwrap_or(exec.current_thread().thread);
+            // The main thread of an `Executor` has no resuming thread to yield to.
+            stack.replace(ctx, thread != exec.main_thread());
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
     coroutine.set_field(
         ctx,
         "status",
@@ -90,5 +155,35 @@ pub fn load_coroutine<'gc>(ctx: Context<'gc>) {
         }),
     );
 
+    coroutine.set_field(
+        ctx,
+        "getname",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let thread: Thread = stack.consume(ctx)?;
+            stack.replace(ctx, thread.name());
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    coroutine.set_field(
+        ctx,
+        "setname",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (thread, name): (Thread, Option<String>) = stack.consume(ctx)?;
+            thread.set_name(&ctx, name);
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    coroutine.set_field(
+        ctx,
+        "parent",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let thread: Thread = stack.consume(ctx)?;
+            stack.replace(ctx, thread.parent(&ctx));
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
     ctx.set_global("coroutine", coroutine);
 }
@@ -0,0 +1,581 @@
+use std::{
+    cell::RefCell,
+    io::{self, Read, Seek, SeekFrom, Write},
+    string::String as StdString,
+};
+
+use gc_arena::Gc;
+
+use crate::{
+    userdata::UserDataMethods, Callback, CallbackReturn, Constant, Context, Error, IntoValue,
+    MetaMethod, String, UserData, UserDataType, Value, Variadic,
+};
+
+use super::vfs::VfsFile;
+
+/// The return values of a fallible `io` function, either the successful results or `nil`, an error
+/// message, and an error code.
+pub type IoResult<'gc> = Variadic<Vec<Value<'gc>>>;
+
+/// The `FILE*` userdata type of the `io` library.
+pub struct LuaFile {
+    // `None` once the file has been closed.
+    state: RefCell<Option<FileState>>,
+    // Standard streams cannot be closed.
+    standard: bool,
+}
+
+impl UserDataType for LuaFile {
+    const NAME: Option<&'static str> = Some("FILE*");
+
+    fn add_methods<'gc>(methods: &mut UserDataMethods<'gc, Self>) {
+        methods.add_method("close", |this, ctx, ()| this.close(ctx));
+
+        methods.add_method("flush", |this, ctx, ()| {
+            this.with_state(ctx, |state| match state.file.flush() {
+                Ok(()) => Variadic(vec![Value::Boolean(true)]),
+                Err(err) => io_fail(ctx, None, &err),
+            })
+        });
+
+        methods.add_method("read", |this, ctx, formats: Variadic<Vec<Value<'gc>>>| {
+            let formats = ReadFormat::parse_all(ctx, "read", &formats)?;
+            this.read(ctx, &formats)
+        });
+
+        methods.add_function(
+            "write",
+            |ctx, (file, args): (UserData<'gc>, Variadic<Vec<Value<'gc>>>)| write(ctx, file, &args),
+        );
+
+        methods.add_function(
+            "lines",
+            |ctx, (file, formats): (UserData<'gc>, Variadic<Vec<Value<'gc>>>)| {
+                let formats = ReadFormat::parse_all(ctx, "lines", &formats)?;
+                Ok(lines(ctx, file, formats, false))
+            },
+        );
+
+        methods.add_method(
+            "seek",
+            |this, ctx, (whence, offset): (Option<String<'gc>>, Option<i64>)| {
+                let offset = offset.unwrap_or(0);
+                let pos = match whence.as_ref().map(|w| w.as_bytes()).unwrap_or(b"cur") {
+                    b"set" => match u64::try_from(offset) {
+                        Ok(offset) => SeekFrom::Start(offset),
+                        Err(_) => {
+                            return this.with_state(ctx, |_| {
+                                io_fail(ctx, None, &io::Error::from(io::ErrorKind::InvalidInput))
+                            })
+                        }
+                    },
+                    b"cur" => SeekFrom::Current(offset),
+                    b"end" => SeekFrom::End(offset),
+                    _ => {
+                        return Err("bad argument #1 to 'seek' (invalid option)"
+                            .into_value(ctx)
+                            .into())
+                    }
+                };
+                this.with_state(ctx, |state| match state.seek(pos) {
+                    Ok(pos) => Variadic(vec![(pos as i64).into()]),
+                    Err(err) => io_fail(ctx, None, &err),
+                })
+            },
+        );
+
+        // Files are always unbuffered on the Rust side, so there is nothing to configure.
+        methods.add_method("setvbuf", |this, ctx, _: Variadic<Vec<Value<'gc>>>| {
+            this.with_state(ctx, |_| true)
+        });
+
+        methods.add_meta_function(MetaMethod::ToString, |ctx, file: UserData<'gc>| {
+            let this = file.downcast_static::<LuaFile>()?;
+            Ok(if this.is_closed() {
+                "file (closed)".into_value(ctx)
+            } else {
+                format!("file ({:p})", Gc::as_ptr(file.into_inner())).into_value(ctx)
+            })
+        });
+    }
+}
+
+impl LuaFile {
+    pub fn new(file: Box<dyn VfsFile>) -> Self {
+        Self {
+            state: RefCell::new(Some(FileState::new(file))),
+            standard: false,
+        }
+    }
+
+    /// Create a file for one of the standard streams, which cannot be closed.
+    pub fn standard(stream: StdStream) -> Self {
+        Self {
+            state: RefCell::new(Some(FileState::new(Box::new(stream)))),
+            standard: true,
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.borrow().is_none()
+    }
+
+    pub fn close<'gc>(&self, ctx: Context<'gc>) -> Result<IoResult<'gc>, Error<'gc>> {
+        if self.standard {
+            return self.with_state(ctx, |_| {
+                Variadic(vec![
+                    Value::Nil,
+                    "cannot close standard file".into_value(ctx),
+                ])
+            });
+        }
+
+        let Some(mut state) = self.state.borrow_mut().take() else {
+            return Err(closed_file_error(ctx));
+        };
+        Ok(match state.file.flush() {
+            Ok(()) => Variadic(vec![Value::Boolean(true)]),
+            Err(err) => io_fail(ctx, None, &err),
+        })
+    }
+
+    pub fn read<'gc>(
+        &self,
+        ctx: Context<'gc>,
+        formats: &[ReadFormat],
+    ) -> Result<IoResult<'gc>, Error<'gc>> {
+        self.with_state(ctx, |state| match state.read(ctx, formats) {
+            Ok(values) => values,
+            Err(err) => io_fail(ctx, None, &err),
+        })
+    }
+
+    fn with_state<'gc, R>(
+        &self,
+        ctx: Context<'gc>,
+        f: impl FnOnce(&mut FileState) -> R,
+    ) -> Result<R, Error<'gc>> {
+        match self.state.borrow_mut().as_mut() {
+            Some(state) => Ok(f(state)),
+            None => Err(closed_file_error(ctx)),
+        }
+    }
+}
+
+/// Write every argument to the given file, returning the file on success.
+pub fn write<'gc>(
+    ctx: Context<'gc>,
+    file: UserData<'gc>,
+    args: &[Value<'gc>],
+) -> Result<IoResult<'gc>, Error<'gc>> {
+    let this = file.downcast_static::<LuaFile>()?;
+
+    let mut buf = ctx.scratch_buffer();
+    for (i, &arg) in args.iter().enumerate() {
+        match arg {
+            Value::String(s) => buf.extend_from_slice(s.as_bytes()),
+            Value::Integer(_) | Value::Number(_) => write!(&mut *buf, "{}", arg.display())?,
+            _ => {
+                return Err(format!(
+                    "bad argument #{} to 'write' (string expected, got {})",
+                    i + 1,
+                    arg.type_name()
+                )
+                .into_value(ctx)
+                .into())
+            }
+        }
+    }
+
+    this.with_state(ctx, |state| match state.write(&buf) {
+        Ok(()) => Variadic(vec![file.into()]),
+        Err(err) => io_fail(ctx, None, &err),
+    })
+}
+
+/// Create an iterator function which reads from `file` with the given formats every time it is
+/// called, as returned by `file:lines` and `io.lines`.
+///
+/// If `close_at_eof` is set, the file is closed once the iterator has returned `nil`.
+pub fn lines<'gc>(
+    ctx: Context<'gc>,
+    file: UserData<'gc>,
+    formats: Vec<ReadFormat>,
+    close_at_eof: bool,
+) -> Callback<'gc> {
+    Callback::from_fn_with(&ctx, file, move |file, ctx, _, mut stack| {
+        let this = file.downcast_static::<LuaFile>()?;
+        if this.is_closed() {
+            return Err("file is already closed".into_value(ctx).into());
+        }
+
+        let values = this.with_state(ctx, |state| state.read(ctx, &formats))?;
+        match values {
+            Ok(values) => {
+                if close_at_eof && values.first().map_or(true, |v| v.is_nil()) {
+                    this.close(ctx)?;
+                }
+                stack.replace(ctx, values);
+                Ok(CallbackReturn::Return)
+            }
+            Err(err) => Err(error_message(&err).into_value(ctx).into()),
+        }
+    })
+}
+
+/// Returns `nil`, the error message (prefixed with `prefix` if given), and the OS error code if
+/// there is one.
+pub fn io_fail<'gc>(ctx: Context<'gc>, prefix: Option<&str>, err: &io::Error) -> IoResult<'gc> {
+    let msg = error_message(err);
+    let msg = match prefix {
+        Some(prefix) => format!("{prefix}: {msg}"),
+        None => msg,
+    };
+    Variadic(vec![
+        Value::Nil,
+        msg.into_value(ctx),
+        err.raw_os_error().unwrap_or(0).into_value(ctx),
+    ])
+}
+
+pub fn error_message(err: &io::Error) -> StdString {
+    let msg = err.to_string();
+    // Match the C `strerror` message by removing the suffix `io::Error` adds to OS errors.
+    match err.raw_os_error() {
+        Some(code) => match msg.strip_suffix(&format!(" (os error {code})")) {
+            Some(msg) => msg.to_owned(),
+            None => msg,
+        },
+        None => msg,
+    }
+}
+
+fn closed_file_error<'gc>(ctx: Context<'gc>) -> Error<'gc> {
+    "attempt to use a closed file".into_value(ctx).into()
+}
+
+/// A format accepted by `file:read` and `file:lines`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ReadFormat {
+    /// `"n"`, reads a numeral.
+    Number,
+    /// `"l"`, reads a line and skips the end of line.
+    Line,
+    /// `"L"`, reads a line and keeps the end of line.
+    LineWithEnd,
+    /// `"a"`, reads the whole rest of the file.
+    All,
+    /// Reads up to this many bytes.
+    Count(usize),
+}
+
+impl ReadFormat {
+    /// Parse the format arguments to `read` or `lines`, which defaults to reading a line if there
+    /// are no formats.
+    pub fn parse_all<'gc>(
+        ctx: Context<'gc>,
+        function: &'static str,
+        formats: &[Value<'gc>],
+    ) -> Result<Vec<ReadFormat>, Error<'gc>> {
+        if formats.is_empty() {
+            return Ok(vec![ReadFormat::Line]);
+        }
+
+        formats
+            .iter()
+            .enumerate()
+            .map(|(i, &format)| {
+                let parsed = match format {
+                    Value::Integer(i) => Some(ReadFormat::Count(i.max(0) as usize)),
+                    Value::Number(n) => Some(ReadFormat::Count(n.max(0.0) as usize)),
+                    Value::String(s) => {
+                        // Lua 5.1 style formats are prefixed with a '*'.
+                        let s = s.as_bytes();
+                        match s.strip_prefix(b"*").unwrap_or(s).first() {
+                            Some(b'n') => Some(ReadFormat::Number),
+                            Some(b'l') => Some(ReadFormat::Line),
+                            Some(b'L') => Some(ReadFormat::LineWithEnd),
+                            Some(b'a') => Some(ReadFormat::All),
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                };
+                parsed.ok_or_else(|| {
+                    Error::from(
+                        format!("bad argument #{} to '{function}' (invalid format)", i + 1)
+                            .into_value(ctx),
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+/// One of the standard streams, as a [`VfsFile`] which returns errors for unsupported operations.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StdStream {
+    Stdin,
+    Stdout,
+    Stderr,
+}
+
+impl Read for StdStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            StdStream::Stdin => io::stdin().read(buf),
+            _ => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+}
+
+impl Write for StdStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            StdStream::Stdin => Err(io::ErrorKind::Unsupported.into()),
+            StdStream::Stdout => io::stdout().write(buf),
+            StdStream::Stderr => io::stderr().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            StdStream::Stdin => Ok(()),
+            StdStream::Stdout => io::stdout().flush(),
+            StdStream::Stderr => io::stderr().flush(),
+        }
+    }
+}
+
+impl Seek for StdStream {
+    fn seek(&mut self, _: SeekFrom) -> io::Result<u64> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+struct FileState {
+    file: Box<dyn VfsFile>,
+    // Bytes read ahead from `file` which have not been consumed yet start at `read_pos`.
+    read_buf: Vec<u8>,
+    read_pos: usize,
+}
+
+impl FileState {
+    const BUFFER_SIZE: usize = 8192;
+    // The maximum length of a numeral read by the "n" format, the same limit as PUC-Rio Lua.
+    const MAX_NUMERAL_LEN: usize = 200;
+
+    fn new(file: Box<dyn VfsFile>) -> Self {
+        Self {
+            file,
+            read_buf: Vec::new(),
+            read_pos: 0,
+        }
+    }
+
+    fn read<'gc>(
+        &mut self,
+        ctx: Context<'gc>,
+        formats: &[ReadFormat],
+    ) -> io::Result<IoResult<'gc>> {
+        let mut values = Vec::with_capacity(formats.len());
+        for &format in formats {
+            let value = match format {
+                ReadFormat::Number => self.read_numeral()?,
+                ReadFormat::Line => self.read_line(false)?,
+                ReadFormat::LineWithEnd => self.read_line(true)?,
+                ReadFormat::All => Some(self.read_all()?),
+                ReadFormat::Count(n) => self.read_count(n)?,
+            };
+            match value {
+                Some(ReadValue::Bytes(bytes)) => values.push(ctx.intern(&bytes).into()),
+                Some(ReadValue::Integer(i)) => values.push(Value::Integer(i)),
+                Some(ReadValue::Number(n)) => values.push(Value::Number(n)),
+                None => {
+                    // Reading stops at the first failed format, which returns `nil`.
+                    values.push(Value::Nil);
+                    break;
+                }
+            }
+        }
+        Ok(Variadic(values))
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.discard_read_buf()?;
+        self.file.write_all(data)
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.discard_read_buf()?;
+        self.file.seek(pos)
+    }
+
+    fn read_line(&mut self, keep_end: bool) -> io::Result<Option<ReadValue>> {
+        let mut line = Vec::new();
+        let mut read_any = false;
+        loop {
+            let buf = self.fill_buf()?;
+            if buf.is_empty() {
+                break;
+            }
+            read_any = true;
+
+            match buf.iter().position(|&b| b == b'\n') {
+                Some(i) => {
+                    line.extend_from_slice(&buf[..if keep_end { i + 1 } else { i }]);
+                    self.read_pos += i + 1;
+                    break;
+                }
+                None => {
+                    let len = buf.len();
+                    line.extend_from_slice(buf);
+                    self.read_pos += len;
+                }
+            }
+        }
+        Ok(read_any.then_some(ReadValue::Bytes(line)))
+    }
+
+    fn read_all(&mut self) -> io::Result<ReadValue> {
+        let mut all = self.read_buf.split_off(self.read_pos);
+        self.read_buf.clear();
+        self.read_pos = 0;
+        self.file.read_to_end(&mut all)?;
+        Ok(ReadValue::Bytes(all))
+    }
+
+    fn read_count(&mut self, count: usize) -> io::Result<Option<ReadValue>> {
+        if count == 0 {
+            // Reading zero bytes tests for the end of the file.
+            return Ok(if self.fill_buf()?.is_empty() {
+                None
+            } else {
+                Some(ReadValue::Bytes(Vec::new()))
+            });
+        }
+
+        let mut bytes = Vec::new();
+        while bytes.len() < count {
+            let buf = self.fill_buf()?;
+            if buf.is_empty() {
+                break;
+            }
+            let len = buf.len().min(count - bytes.len());
+            bytes.extend_from_slice(&buf[..len]);
+            self.read_pos += len;
+        }
+        Ok((!bytes.is_empty()).then_some(ReadValue::Bytes(bytes)))
+    }
+
+    // Reads the longest prefix of a numeral that could be valid, following the same rules as
+    // PUC-Rio Lua, and then converts it to a number. Invalid numerals return `None`, but are still
+    // consumed.
+    fn read_numeral(&mut self) -> io::Result<Option<ReadValue>> {
+        while let Some(b) = self.peek()? {
+            if b.is_ascii_whitespace() {
+                self.read_pos += 1;
+            } else {
+                break;
+            }
+        }
+
+        let mut numeral = Vec::new();
+        let mut count = 0;
+        let mut hex = false;
+        self.accept(&mut numeral, b"+-")?;
+        if self.accept(&mut numeral, b"0")? {
+            if self.accept(&mut numeral, b"xX")? {
+                hex = true;
+            } else {
+                count = 1;
+            }
+        }
+        count += self.accept_digits(&mut numeral, hex)?;
+        if self.accept(&mut numeral, b".")? {
+            count += self.accept_digits(&mut numeral, hex)?;
+        }
+        if count > 0 && self.accept(&mut numeral, if hex { b"pP" } else { b"eE" })? {
+            self.accept(&mut numeral, b"+-")?;
+            self.accept_digits(&mut numeral, false)?;
+        }
+
+        Ok(match Constant::String(&numeral[..]).to_numeric() {
+            Some(Constant::Integer(i)) => Some(ReadValue::Integer(i)),
+            Some(Constant::Number(n)) => Some(ReadValue::Number(n)),
+            _ => None,
+        })
+    }
+
+    fn accept(&mut self, numeral: &mut Vec<u8>, set: &[u8]) -> io::Result<bool> {
+        match self.peek()? {
+            Some(b) if numeral.len() < Self::MAX_NUMERAL_LEN && set.contains(&b) => {
+                numeral.push(b);
+                self.read_pos += 1;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn accept_digits(&mut self, numeral: &mut Vec<u8>, hex: bool) -> io::Result<usize> {
+        let mut count = 0;
+        while let Some(b) = self.peek()? {
+            let is_digit = if hex {
+                b.is_ascii_hexdigit()
+            } else {
+                b.is_ascii_digit()
+            };
+            if !is_digit || numeral.len() >= Self::MAX_NUMERAL_LEN {
+                break;
+            }
+            numeral.push(b);
+            self.read_pos += 1;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn peek(&mut self) -> io::Result<Option<u8>> {
+        Ok(self.fill_buf()?.first().copied())
+    }
+
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.read_pos >= self.read_buf.len() {
+            self.read_buf.resize(Self::BUFFER_SIZE, 0);
+            self.read_pos = 0;
+            let res = loop {
+                match self.file.read(&mut self.read_buf) {
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    res => break res,
+                }
+            };
+            match res {
+                Ok(len) => self.read_buf.truncate(len),
+                Err(err) => {
+                    self.read_buf.clear();
+                    return Err(err);
+                }
+            }
+        }
+        Ok(&self.read_buf[self.read_pos..])
+    }
+
+    // Any read ahead bytes must be given back before writing or seeking, so that the position of
+    // the underlying file matches what Lua has observed.
+    fn discard_read_buf(&mut self) -> io::Result<()> {
+        let unread = self.read_buf.len() - self.read_pos;
+        self.read_buf.clear();
+        self.read_pos = 0;
+        if unread > 0 {
+            self.file.seek(SeekFrom::Current(-(unread as i64)))?;
+        }
+        Ok(())
+    }
+}
+
+enum ReadValue {
+    Bytes(Vec<u8>),
+    Integer(i64),
+    Number(f64),
+}
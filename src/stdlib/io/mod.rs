@@ -0,0 +1,302 @@
+mod file;
+mod vfs;
+
+use std::{
+    io::{self, Write},
+    pin::Pin,
+    rc::Rc,
+};
+
+use gc_arena::{lock::RefLock, Collect, Gc};
+
+use crate::{
+    meta_ops::{self, MetaResult},
+    BoxSequence, Callback, CallbackReturn, Context, Error, Execution, IntoValue, Sequence,
+    SequencePoll, Stack, String, Table, UserData, Value, Variadic,
+};
+
+use self::file::{error_message, lines, write, LuaFile, ReadFormat, StdStream};
+
+pub(super) use self::file::io_fail;
+
+pub use self::vfs::{
+    HostFilesystem, NoFilesystem, OpenMode, PermissionVfs, VfsAccess, VfsFile, VfsProvider,
+};
+
+/// Load `print` and the `io` library, without access to any filesystem.
+///
+/// Only the standard streams are available, opening a file always fails. Use [`load_io_with_vfs`]
+/// with [`HostFilesystem`] to allow scripts to open files from the host filesystem.
+pub fn load_io<'gc>(ctx: Context<'gc>) {
+    load_io_with_vfs(ctx, NoFilesystem);
+}
+
+/// Load `print` and the `io` library, with files opened from the given [`VfsProvider`].
+///
+/// The standard streams `io.stdin`, `io.stdout`, and `io.stderr` are always the process streams.
+pub fn load_io_with_vfs<'gc>(ctx: Context<'gc>, vfs: impl VfsProvider) {
+    ctx.set_global(
+        "print",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            #[derive(Collect)]
+            #[collect(require_static)]
+            struct PrintSeq {
+                first: bool,
+                line: Vec<u8>,
+            }
+
+            impl<'gc> Sequence<'gc> for PrintSeq {
+                fn poll(
+                    mut self: Pin<&mut Self>,
+                    ctx: Context<'gc>,
+                    _exec: Execution<'gc, '_>,
+                    mut stack: Stack<'gc, '_>,
+                ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+                    while let Some(value) = stack.pop_back() {
+                        match meta_ops::tostring(ctx, value)? {
+                            MetaResult::Value(v) => {
+                                if self.first {
+                                    self.first = false;
+                                } else {
+                                    self.line.push(b'\t');
+                                }
+                                if let Value::String(s) = v {
+                                    self.line.extend_from_slice(s.as_bytes());
+                                } else {
+                                    write!(self.line, "{}", v.display())?;
+                                }
+                            }
+                            MetaResult::Call(call) => {
+                                let bottom = stack.len();
+                                stack.extend(call.args);
+                                return Ok(SequencePoll::Call {
+                                    function: call.function,
+                                    bottom,
+                                });
+                            }
+                        }
+                    }
+
+                    // The whole line is written at once, so that an output function installed
+                    // with `Lua::set_output_fn` always receives complete lines.
+                    self.line.push(b'\n');
+                    ctx.output().write(&self.line)?;
+                    Ok(SequencePoll::Return)
+                }
+            }
+
+            stack[..].reverse();
+
+            Ok(CallbackReturn::Sequence(BoxSequence::new(
+                &ctx,
+                PrintSeq {
+                    first: true,
+                    line: Vec::new(),
+                },
+            )))
+        }),
+    );
+
+    let vfs: Rc<dyn VfsProvider> = Rc::new(vfs);
+    let io = Table::new(&ctx);
+
+    let stdin = UserData::new_typed(ctx, LuaFile::standard(StdStream::Stdin));
+    let stdout = UserData::new_typed(ctx, LuaFile::standard(StdStream::Stdout));
+    let stderr = UserData::new_typed(ctx, LuaFile::standard(StdStream::Stderr));
+    io.set_field(ctx, "stdin", stdin);
+    io.set_field(ctx, "stdout", stdout);
+    io.set_field(ctx, "stderr", stderr);
+
+    let defaults = Gc::new(
+        &ctx,
+        RefLock::new(DefaultFiles {
+            input: stdin,
+            output: stdout,
+        }),
+    );
+
+    io.set_field(
+        ctx,
+        "open",
+        Callback::from_fn(&ctx, {
+            let vfs = vfs.clone();
+            move |ctx, _, mut stack| {
+                let (filename, mode): (String, Option<String>) = stack.consume(ctx)?;
+                let Some(mode) = OpenMode::parse(mode.map(|m| m.as_bytes()).unwrap_or(b"r")) else {
+                    return Err("bad argument #2 to 'open' (invalid mode)"
+                        .into_value(ctx)
+                        .into());
+                };
+                match open(ctx, &*vfs, filename, mode) {
+                    Ok(file) => stack.replace(ctx, file),
+                    Err(err) => {
+                        let prefix = filename.display_lossy().to_string();
+                        stack.replace(ctx, io_fail(ctx, Some(&prefix), &err));
+                    }
+                }
+                Ok(CallbackReturn::Return)
+            }
+        }),
+    );
+
+    io.set_field(
+        ctx,
+        "close",
+        Callback::from_fn_with(&ctx, defaults, |defaults, ctx, _, mut stack| {
+            let file = match stack.consume::<Option<UserData>>(ctx)? {
+                Some(file) => file,
+                None => defaults.borrow().output,
+            };
+            let res = file.downcast_static::<LuaFile>()?.close(ctx)?;
+            stack.replace(ctx, res);
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    io.set_field(
+        ctx,
+        "read",
+        Callback::from_fn_with(&ctx, defaults, |defaults, ctx, _, mut stack| {
+            let formats = ReadFormat::parse_all(ctx, "read", &stack[..])?;
+            let input = defaults.borrow().input;
+            let res = input.downcast_static::<LuaFile>()?.read(ctx, &formats)?;
+            stack.replace(ctx, res);
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    io.set_field(
+        ctx,
+        "write",
+        Callback::from_fn_with(&ctx, defaults, |defaults, ctx, _, mut stack| {
+            let output = defaults.borrow().output;
+            let res = write(ctx, output, &stack[..])?;
+            stack.replace(ctx, res);
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    io.set_field(
+        ctx,
+        "lines",
+        Callback::from_fn_with(&ctx, defaults, {
+            let vfs = vfs.clone();
+            move |defaults, ctx, _, mut stack| {
+                let (filename, formats): (Option<String>, Variadic<Vec<Value>>) =
+                    stack.consume(ctx)?;
+                let formats = ReadFormat::parse_all(ctx, "lines", &formats)?;
+                let iter = match filename {
+                    Some(filename) => {
+                        let file = open(ctx, &*vfs, filename, OpenMode::READ).map_err(|err| {
+                            format!("{}: {}", filename.display_lossy(), error_message(&err))
+                                .into_value(ctx)
+                        })?;
+                        lines(ctx, file, formats, true)
+                    }
+                    None => lines(ctx, defaults.borrow().input, formats, false),
+                };
+                stack.replace(ctx, iter);
+                Ok(CallbackReturn::Return)
+            }
+        }),
+    );
+
+    io.set_field(
+        ctx,
+        "input",
+        Callback::from_fn_with(&ctx, defaults, {
+            let vfs = vfs.clone();
+            move |defaults, ctx, _, mut stack| {
+                let arg: Value = stack.consume(ctx)?;
+                if let Some(file) = new_default(ctx, &*vfs, "input", arg, OpenMode::READ)? {
+                    defaults.borrow_mut(&ctx).input = file;
+                }
+                stack.replace(ctx, defaults.borrow().input);
+                Ok(CallbackReturn::Return)
+            }
+        }),
+    );
+
+    io.set_field(
+        ctx,
+        "output",
+        Callback::from_fn_with(&ctx, defaults, {
+            let vfs = vfs.clone();
+            move |defaults, ctx, _, mut stack| {
+                let arg: Value = stack.consume(ctx)?;
+                if let Some(file) = new_default(ctx, &*vfs, "output", arg, OpenMode::WRITE)? {
+                    defaults.borrow_mut(&ctx).output = file;
+                }
+                stack.replace(ctx, defaults.borrow().output);
+                Ok(CallbackReturn::Return)
+            }
+        }),
+    );
+
+    io.set_field(
+        ctx,
+        "type",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let file_type = match stack.consume::<Value>(ctx)? {
+                Value::UserData(ud) => match ud.downcast_static::<LuaFile>() {
+                    Ok(file) if file.is_closed() => Some("closed file"),
+                    Ok(_) => Some("file"),
+                    Err(_) => None,
+                },
+                _ => None,
+            };
+            stack.replace(ctx, file_type);
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    ctx.set_global("io", io);
+}
+
+#[derive(Collect)]
+#[collect(no_drop)]
+struct DefaultFiles<'gc> {
+    input: UserData<'gc>,
+    output: UserData<'gc>,
+}
+
+fn open<'gc>(
+    ctx: Context<'gc>,
+    vfs: &dyn VfsProvider,
+    filename: String<'gc>,
+    mode: OpenMode,
+) -> Result<UserData<'gc>, io::Error> {
+    let path = filename
+        .to_str()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let file = vfs.open(path, mode)?;
+    Ok(UserData::new_typed(ctx, LuaFile::new(file)))
+}
+
+// Returns the file that `io.input` or `io.output` should change the default file to, if any.
+fn new_default<'gc>(
+    ctx: Context<'gc>,
+    vfs: &dyn VfsProvider,
+    function: &'static str,
+    arg: Value<'gc>,
+    mode: OpenMode,
+) -> Result<Option<UserData<'gc>>, Error<'gc>> {
+    match arg {
+        Value::Nil => Ok(None),
+        Value::String(filename) => match open(ctx, vfs, filename, mode) {
+            Ok(file) => Ok(Some(file)),
+            Err(err) => Err(
+                format!("{}: {}", filename.display_lossy(), error_message(&err))
+                    .into_value(ctx)
+                    .into(),
+            ),
+        },
+        Value::UserData(ud) if ud.downcast_static::<LuaFile>().is_ok() => Ok(Some(ud)),
+        _ => Err(format!(
+            "bad argument #1 to '{function}' (FILE* expected, got {})",
+            arg.type_name()
+        )
+        .into_value(ctx)
+        .into()),
+    }
+}
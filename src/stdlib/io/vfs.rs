@@ -0,0 +1,281 @@
+use std::{
+    env, fs,
+    io::{self, Read, Seek, Write},
+    rc::Rc,
+};
+
+/// An open file returned by a [`VfsProvider`].
+///
+/// This is implemented for every type which is `Read + Write + Seek`. Files which do not support
+/// one of these operations (such as a read-only file in an asset pack) should return an error from
+/// it, which is reported to Lua as a failed result.
+pub trait VfsFile: Read + Write + Seek {}
+
+impl<T: Read + Write + Seek> VfsFile for T {}
+
+/// The mode a file is opened with, parsed from the mode string given to `io.open`.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct OpenMode {
+    pub read: bool,
+    pub write: bool,
+    /// All writes go to the end of the file.
+    pub append: bool,
+    /// The file is truncated to zero length when opened.
+    pub truncate: bool,
+    /// The file is created if it does not exist.
+    pub create: bool,
+}
+
+impl OpenMode {
+    /// The mode used by `io.open` when no mode is given, equivalent to `"r"`.
+    pub const READ: OpenMode = OpenMode {
+        read: true,
+        write: false,
+        append: false,
+        truncate: false,
+        create: false,
+    };
+
+    /// Equivalent to `"w"`.
+    pub const WRITE: OpenMode = OpenMode {
+        read: false,
+        write: true,
+        append: false,
+        truncate: true,
+        create: true,
+    };
+
+    /// Parse a C `fopen` style mode string, which must match the pattern `[rwa]%+?b*`.
+    ///
+    /// Returns `None` if the mode string is invalid.
+    pub fn parse(mode: &[u8]) -> Option<OpenMode> {
+        let (&kind, mut rest) = mode.split_first()?;
+        let update = if let Some(r) = rest.strip_prefix(b"+") {
+            rest = r;
+            true
+        } else {
+            false
+        };
+        if !rest.iter().all(|&b| b == b'b') {
+            return None;
+        }
+
+        match kind {
+            b'r' => Some(OpenMode {
+                write: update,
+                ..OpenMode::READ
+            }),
+            b'w' => Some(OpenMode {
+                read: update,
+                ..OpenMode::WRITE
+            }),
+            b'a' => Some(OpenMode {
+                read: update,
+                write: true,
+                append: true,
+                truncate: false,
+                create: true,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// A filesystem that the `io` library opens files from.
+///
+/// The `io` library loaded by [`load_io`](crate::stdlib::load_io) uses [`NoFilesystem`], so that
+/// scripts cannot open any files unless the embedder opts in by passing a provider to
+/// [`load_io_with_vfs`](crate::stdlib::load_io_with_vfs). This can be [`HostFilesystem`], or an
+/// implementation which serves files out of an asset pack or only allows access to a single
+/// directory.
+pub trait VfsProvider: 'static {
+    /// Open the file at `path` with the given mode.
+    ///
+    /// Errors are not raised as Lua errors, they are returned from `io.open` as `nil` followed by
+    /// the error message.
+    fn open(&self, path: &str, mode: OpenMode) -> io::Result<Box<dyn VfsFile>>;
+
+    /// Delete the file at `path`, used by `os.remove`.
+    ///
+    /// The default implementation always fails, so providers which only serve files for reading
+    /// need not implement this.
+    fn remove(&self, path: &str) -> io::Result<()> {
+        let _ = path;
+        Err(unsupported())
+    }
+
+    /// Rename the file at `from` to `to`, used by `os.rename`.
+    ///
+    /// The default implementation always fails.
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let _ = (from, to);
+        Err(unsupported())
+    }
+
+    /// Create a new empty file with a unique name and return its path, used by `os.tmpname`.
+    ///
+    /// The default implementation always fails.
+    fn tmpname(&self) -> io::Result<std::string::String> {
+        Err(unsupported())
+    }
+}
+
+impl<V: VfsProvider + ?Sized> VfsProvider for Rc<V> {
+    fn open(&self, path: &str, mode: OpenMode) -> io::Result<Box<dyn VfsFile>> {
+        (**self).open(path, mode)
+    }
+
+    fn remove(&self, path: &str) -> io::Result<()> {
+        (**self).remove(path)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        (**self).rename(from, to)
+    }
+
+    fn tmpname(&self) -> io::Result<std::string::String> {
+        (**self).tmpname()
+    }
+}
+
+/// A [`VfsProvider`] without any files, every operation fails.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct NoFilesystem;
+
+impl VfsProvider for NoFilesystem {
+    fn open(&self, path: &str, mode: OpenMode) -> io::Result<Box<dyn VfsFile>> {
+        let _ = (path, mode);
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "filesystem access is disabled",
+        ))
+    }
+}
+
+/// A [`VfsProvider`] which opens files from the host filesystem with [`std::fs`].
+#[derive(Debug, Copy, Clone, Default)]
+pub struct HostFilesystem;
+
+impl VfsProvider for HostFilesystem {
+    fn open(&self, path: &str, mode: OpenMode) -> io::Result<Box<dyn VfsFile>> {
+        let file = fs::OpenOptions::new()
+            .read(mode.read)
+            .write(mode.write)
+            .append(mode.append)
+            .truncate(mode.truncate)
+            .create(mode.create)
+            .open(path)?;
+        Ok(Box::new(file))
+    }
+
+    fn remove(&self, path: &str) -> io::Result<()> {
+        // Like C `remove`, this deletes either a file or an empty directory.
+        if fs::symlink_metadata(path)?.is_dir() {
+            fs::remove_dir(path)
+        } else {
+            fs::remove_file(path)
+        }
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn tmpname(&self) -> io::Result<std::string::String> {
+        let dir = env::temp_dir();
+        for _ in 0..16 {
+            let path = dir.join(format!("lua_{:016x}", rand::random::<u64>()));
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(_) => {
+                    return path.into_os_string().into_string().map_err(|_| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "temporary directory is not valid UTF-8",
+                        )
+                    })
+                }
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Err(io::ErrorKind::AlreadyExists.into())
+    }
+}
+
+/// An operation checked by the permission callback of a [`PermissionVfs`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum VfsAccess {
+    /// Opening a file with the given mode, by `io.open` and every other function which opens
+    /// files.
+    Open(OpenMode),
+    /// Deleting a file with `os.remove`.
+    Remove,
+    /// Renaming a file with `os.rename`, which is checked for both the old and the new path.
+    Rename,
+}
+
+/// A [`VfsProvider`] which checks every path against a permission callback before passing the
+/// operation on to another provider.
+///
+/// Denied operations fail with [`io::ErrorKind::PermissionDenied`], which scripts see as a normal
+/// failed result like `nil, "secret.txt: Permission denied"`. A file created by `os.tmpname` is
+/// checked as though it is opened for writing, and deleted again if that is denied.
+pub struct PermissionVfs<V> {
+    inner: V,
+    allow: Box<dyn Fn(&str, VfsAccess) -> bool>,
+}
+
+impl<V: VfsProvider> PermissionVfs<V> {
+    pub fn new(inner: V, allow: impl Fn(&str, VfsAccess) -> bool + 'static) -> Self {
+        Self {
+            inner,
+            allow: Box::new(allow),
+        }
+    }
+
+    fn check(&self, path: &str, access: VfsAccess) -> io::Result<()> {
+        if (self.allow)(path, access) {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Permission denied",
+            ))
+        }
+    }
+}
+
+impl<V: VfsProvider> VfsProvider for PermissionVfs<V> {
+    fn open(&self, path: &str, mode: OpenMode) -> io::Result<Box<dyn VfsFile>> {
+        self.check(path, VfsAccess::Open(mode))?;
+        self.inner.open(path, mode)
+    }
+
+    fn remove(&self, path: &str) -> io::Result<()> {
+        self.check(path, VfsAccess::Remove)?;
+        self.inner.remove(path)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        self.check(from, VfsAccess::Rename)?;
+        self.check(to, VfsAccess::Rename)?;
+        self.inner.rename(from, to)
+    }
+
+    fn tmpname(&self) -> io::Result<std::string::String> {
+        let path = self.inner.tmpname()?;
+        if let Err(err) = self.check(&path, VfsAccess::Open(OpenMode::WRITE)) {
+            let _ = self.inner.remove(&path);
+            return Err(err);
+        }
+        Ok(path)
+    }
+}
+
+fn unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "Operation not supported")
+}
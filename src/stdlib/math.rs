@@ -1,7 +1,6 @@
-use std::{cell::RefCell, f64, rc::Rc};
+use std::f64;
 
 use gc_arena::Mutation;
-use rand::{rngs::SmallRng, Rng, SeedableRng};
 
 use crate::{
     async_sequence, meta_ops, Callback, CallbackReturn, Context, FromMultiValue, IntoMultiValue,
@@ -34,7 +33,6 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
     }
 
     let math = Table::new(&ctx);
-    let seeded_rng: Rc<RefCell<SmallRng>> = Rc::new(RefCell::new(SmallRng::from_entropy()));
 
     math.set_field(
         ctx,
@@ -234,63 +232,69 @@ pub fn load_math<'gc>(ctx: Context<'gc>) {
         callback("rad", &ctx, |_, v: f64| Some(v.to_radians())),
     );
 
-    let random_rng = seeded_rng.clone();
     math.set_field(
         ctx,
         "random",
-        callback(
-            "random",
-            &ctx,
-            move |_, (a, b): (Option<i64>, Option<i64>)| -> Option<Value> {
-                let rng = &random_rng;
-                match (a, b) {
-                    (None, None) => Some(rng.borrow_mut().gen::<f64>().into()),
-                    (Some(0), None) => Some(rng.borrow_mut().gen::<i64>().into()),
-                    (Some(a), None) if a < 0 => None,
-                    (Some(a), None) => Some(rng.borrow_mut().gen_range(1..=a).into()),
-                    (Some(a), Some(b)) if a <= b => Some(rng.borrow_mut().gen_range(a..=b).into()),
-                    _ => None,
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let random = ctx.random();
+            let (low, high) = match stack.len() {
+                0 => {
+                    stack.replace(ctx, random.next_f64());
+                    return Ok(CallbackReturn::Return);
                 }
-            },
-        ),
+                1 => match stack.consume::<i64>(ctx)? {
+                    0 => {
+                        stack.replace(ctx, random.next_u64() as i64);
+                        return Ok(CallbackReturn::Return);
+                    }
+                    high => (1, high),
+                },
+                2 => stack.consume::<(i64, i64)>(ctx)?,
+                _ => return Err("wrong number of arguments".into_value(ctx).into()),
+            };
+
+            if low > high {
+                return Err("bad argument to 'random' (interval is empty)"
+                    .into_value(ctx)
+                    .into());
+            }
+            // Computed with wrapping arithmetic so that the interval can span the entire integer
+            // range.
+            let offset = random.next_below_or_eq(high.wrapping_sub(low) as u64);
+            stack.replace(ctx, low.wrapping_add(offset as i64));
+            Ok(CallbackReturn::Return)
+        }),
     );
 
-    let randomseed_rng = seeded_rng.clone();
     math.set_field(
         ctx,
         "randomseed",
-        callback(
-            "randomseed",
-            &ctx,
-            move |_, (u, l): (Option<u64>, Option<u64>)| {
-                let rng = &randomseed_rng;
-                match (u, l) {
-                    (None, None) => {
-                        *rng.borrow_mut() = SmallRng::from_entropy();
-                        Some(())
-                    }
-                    (Some(seed), None) | (Some(seed), Some(0)) => {
-                        *rng.borrow_mut() = SmallRng::seed_from_u64(seed);
-                        Some(())
-                    }
-                    (Some(high), Some(low)) => {
-                        let high_bytes = high.to_ne_bytes();
-                        let low_bytes = low.to_ne_bytes();
-                        let seed = std::array::from_fn(|idx| {
-                            let idx_mod_16 = idx % 16;
-                            if idx_mod_16 >= 8 {
-                                high_bytes[idx_mod_16 - 8]
-                            } else {
-                                low_bytes[idx_mod_16]
-                            }
-                        });
-                        *rng.borrow_mut() = SmallRng::from_seed(seed);
-                        Some(())
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let random = ctx.random();
+            let (n1, n2) = if stack.is_empty() {
+                random.randomize()
+            } else {
+                let (n1, n2): (Value, Option<i64>) = stack.consume(ctx)?;
+                let n1 = match (n1.to_integer(), n1) {
+                    (Some(i), _) => i,
+                    // Floats without an integer representation are used by their bit pattern.
+                    (None, Value::Number(n)) => n.to_bits() as i64,
+                    (None, v) => {
+                        return Err(format!(
+                            "bad argument #1 to 'randomseed' (number expected, got {})",
+                            v.type_name()
+                        )
+                        .into_value(ctx)
+                        .into())
                     }
-                    _ => None,
-                }
-            },
-        ),
+                };
+                let n2 = n2.unwrap_or(0);
+                random.seed(n1, n2);
+                (n1, n2)
+            };
+            stack.replace(ctx, (n1, n2));
+            Ok(CallbackReturn::Return)
+        }),
     );
 
     math.set_field(ctx, "sin", callback("sin", &ctx, |_, v: f64| Some(v.sin())));
@@ -1,11 +1,28 @@
 mod base;
+mod class;
 mod coroutine;
 mod io;
 mod math;
+mod os;
+mod package;
 mod string;
 mod table;
+mod unsupported;
 
 pub use self::{
-    base::load_base, coroutine::load_coroutine, io::load_io, math::load_math, string::load_string,
+    base::load_base,
+    class::{class_of, is_class, is_instance, load_class, new_class, parent_class},
+    coroutine::load_coroutine,
+    io::{
+        load_io, load_io_with_vfs, HostFilesystem, NoFilesystem, OpenMode, PermissionVfs,
+        VfsAccess, VfsFile, VfsProvider,
+    },
+    math::load_math,
+    os::{load_os, load_os_with_vfs},
+    package::{add_searcher, load_package, load_package_with_vfs, loaded, preload},
+    string::load_string,
     table::load_table,
+    unsupported::{
+        load_unsupported, set_unsupported_hook, UnsupportedFunction, STANDARD_FUNCTIONS,
+    },
 };
@@ -0,0 +1,427 @@
+use std::{
+    env,
+    io::{self, Write as _},
+    rc::Rc,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{Callback, CallbackReturn, Context, Error, IntoValue, String, Table};
+
+use super::io::{io_fail, VfsProvider};
+
+/// Load the `os` library.
+///
+/// Only the parts of the `os` library which do not modify the host system are provided: `clock`,
+/// `date`, `difftime`, `getenv`, and `time`. This library is not part of [`Lua::core`], so that
+/// sandboxed environments can choose to not expose the host environment or clock.
+///
+/// There is no time zone database available, so local time is always treated as UTC. Use
+/// [`load_os_with_vfs`] to also provide the functions which manage files.
+///
+/// [`Lua::core`]: crate::Lua::core
+pub fn load_os<'gc>(ctx: Context<'gc>) {
+    ctx.set_global("os", os_table(ctx));
+}
+
+/// Load the `os` library, including `remove`, `rename`, and `tmpname` which manage files through
+/// the given [`VfsProvider`].
+///
+/// Wrap the provider in a [`PermissionVfs`](super::PermissionVfs) to control which paths scripts
+/// may modify.
+pub fn load_os_with_vfs<'gc>(ctx: Context<'gc>, vfs: impl VfsProvider) {
+    let os = os_table(ctx);
+    let vfs: Rc<dyn VfsProvider> = Rc::new(vfs);
+
+    os.set_field(
+        ctx,
+        "remove",
+        Callback::from_fn(&ctx, {
+            let vfs = vfs.clone();
+            move |ctx, _, mut stack| {
+                let filename: String = stack.consume(ctx)?;
+                let res = path(filename).and_then(|path| vfs.remove(path));
+                match res {
+                    Ok(()) => stack.replace(ctx, true),
+                    Err(err) => {
+                        let prefix = filename.display_lossy().to_string();
+                        stack.replace(ctx, io_fail(ctx, Some(&prefix), &err));
+                    }
+                }
+                Ok(CallbackReturn::Return)
+            }
+        }),
+    );
+
+    os.set_field(
+        ctx,
+        "rename",
+        Callback::from_fn(&ctx, {
+            let vfs = vfs.clone();
+            move |ctx, _, mut stack| {
+                let (from, to): (String, String) = stack.consume(ctx)?;
+                let res = path(from).and_then(|from| vfs.rename(from, path(to)?));
+                match res {
+                    Ok(()) => stack.replace(ctx, true),
+                    Err(err) => stack.replace(ctx, io_fail(ctx, None, &err)),
+                }
+                Ok(CallbackReturn::Return)
+            }
+        }),
+    );
+
+    os.set_field(
+        ctx,
+        "tmpname",
+        Callback::from_fn(&ctx, move |ctx, _, mut stack| match vfs.tmpname() {
+            Ok(name) => {
+                stack.replace(ctx, ctx.intern(name.as_bytes()));
+                Ok(CallbackReturn::Return)
+            }
+            Err(_) => Err("unable to generate a unique filename"
+                .into_value(ctx)
+                .into()),
+        }),
+    );
+
+    ctx.set_global("os", os);
+}
+
+fn os_table<'gc>(ctx: Context<'gc>) -> Table<'gc> {
+    let os = Table::new(&ctx);
+
+    let start = Instant::now();
+    os.set_field(
+        ctx,
+        "clock",
+        Callback::from_fn(&ctx, move |ctx, _, mut stack| {
+            // There is no portable way to get process CPU time, so this measures wall clock time
+            // from when the library was loaded.
+            stack.replace(ctx, start.elapsed().as_secs_f64());
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    os.set_field(
+        ctx,
+        "time",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let time = match stack.consume::<Option<Table>>(ctx)? {
+                None => now(),
+                Some(table) => {
+                    // Like PUC-Rio Lua, every field must fit in a C `int` once the offset of the
+                    // matching `struct tm` field has been subtracted.
+                    let field = |name: &'static str,
+                                 default: Option<i64>,
+                                 delta: i64|
+                     -> Result<i64, Error<'gc>> {
+                        match table.get::<_, Option<i64>>(ctx, name)?.or(default) {
+                            Some(v)
+                                if v.checked_sub(delta)
+                                    .is_some_and(|v| i32::try_from(v).is_ok()) =>
+                            {
+                                Ok(v)
+                            }
+                            Some(_) => Err(format!("field '{name}' is out-of-bound")
+                                .into_value(ctx)
+                                .into()),
+                            None => Err(format!("field '{name}' missing in date table")
+                                .into_value(ctx)
+                                .into()),
+                        }
+                    };
+
+                    let year = field("year", None, 1900)?;
+                    let month = field("month", None, 1)?;
+                    let day = field("day", None, 0)?;
+                    let hour = field("hour", Some(12), 0)?;
+                    let min = field("min", Some(0), 0)?;
+                    let sec = field("sec", Some(0), 0)?;
+
+                    time_from_fields(year, month, day, hour, min, sec).ok_or_else(|| {
+                        "time result cannot be represented in this installation".into_value(ctx)
+                    })?
+                }
+            };
+            stack.replace(ctx, time);
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    os.set_field(
+        ctx,
+        "difftime",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (t2, t1): (f64, Option<f64>) = stack.consume(ctx)?;
+            stack.replace(ctx, t2 - t1.unwrap_or(0.0));
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    os.set_field(
+        ctx,
+        "date",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (format, time): (Option<String>, Option<i64>) = stack.consume(ctx)?;
+            let mut format = format.map(|f| f.as_bytes()).unwrap_or(b"%c");
+            let date = Date::from_timestamp(time.unwrap_or_else(now));
+
+            // Local time is UTC, so the `!` prefix is accepted and ignored.
+            if let Some(rest) = format.strip_prefix(b"!") {
+                format = rest;
+            }
+
+            if format.starts_with(b"*t") {
+                let table = Table::new(&ctx);
+                table.set(ctx, "year", date.year)?;
+                table.set(ctx, "month", date.month)?;
+                table.set(ctx, "day", date.day)?;
+                table.set(ctx, "hour", date.hour)?;
+                table.set(ctx, "min", date.min)?;
+                table.set(ctx, "sec", date.sec)?;
+                table.set(ctx, "wday", date.wday)?;
+                table.set(ctx, "yday", date.yday)?;
+                table.set(ctx, "isdst", false)?;
+                stack.replace(ctx, table);
+            } else {
+                match date.format(format) {
+                    Ok(s) => stack.replace(ctx, ctx.intern(&s)),
+                    Err(c) => {
+                        return Err(format!(
+                            "bad argument #1 to 'date' (invalid conversion specifier '%{}')",
+                            c.escape_ascii()
+                        )
+                        .into_value(ctx)
+                        .into())
+                    }
+                }
+            }
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    os.set_field(
+        ctx,
+        "getenv",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let name: String = stack.consume(ctx)?;
+            let value = name
+                .to_str()
+                .ok()
+                .and_then(env::var_os)
+                .map(|v| v.to_string_lossy().into_owned());
+            stack.replace(ctx, value);
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    os
+}
+
+fn path<'gc>(filename: String<'gc>) -> io::Result<&'gc str> {
+    filename
+        .to_str()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+fn now() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+struct Date {
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    min: i64,
+    sec: i64,
+    // Day of the week, with Sunday as 1.
+    wday: i64,
+    // Day of the year, with January 1st as 1.
+    yday: i64,
+}
+
+impl Date {
+    fn from_timestamp(time: i64) -> Date {
+        let days = time.div_euclid(86400);
+        let secs = time.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        Date {
+            year,
+            month,
+            day,
+            hour: secs / 3600,
+            min: secs % 3600 / 60,
+            sec: secs % 60,
+            // 1970-01-01 was a Thursday.
+            wday: (days + 4).rem_euclid(7) + 1,
+            yday: days
+                - days_from_civil(year, 1, 1).expect("the year of a timestamp is always in range")
+                + 1,
+        }
+    }
+
+    // Formats the date like C `strftime` in the "C" locale. Returns the offending byte if the
+    // format contains an unknown conversion specifier.
+    fn format(&self, format: &[u8]) -> Result<Vec<u8>, u8> {
+        const DAYS: [&str; 7] = [
+            "Sunday",
+            "Monday",
+            "Tuesday",
+            "Wednesday",
+            "Thursday",
+            "Friday",
+            "Saturday",
+        ];
+        const MONTHS: [&str; 12] = [
+            "January",
+            "February",
+            "March",
+            "April",
+            "May",
+            "June",
+            "July",
+            "August",
+            "September",
+            "October",
+            "November",
+            "December",
+        ];
+
+        let day_name = DAYS[(self.wday - 1) as usize];
+        let month_name = MONTHS[(self.month - 1) as usize];
+        let hour12 = match self.hour % 12 {
+            0 => 12,
+            h => h,
+        };
+
+        let mut out = Vec::new();
+        let mut iter = format.iter();
+        while let Some(&b) = iter.next() {
+            if b != b'%' {
+                out.push(b);
+                continue;
+            }
+
+            let Some(&c) = iter.next() else {
+                return Err(b'%');
+            };
+            match c {
+                b'a' => out.extend_from_slice(day_name[..3].as_bytes()),
+                b'A' => out.extend_from_slice(day_name.as_bytes()),
+                b'b' | b'h' => out.extend_from_slice(month_name[..3].as_bytes()),
+                b'B' => out.extend_from_slice(month_name.as_bytes()),
+                b'c' => write!(
+                    out,
+                    "{} {} {:2} {:02}:{:02}:{:02} {}",
+                    &day_name[..3],
+                    &month_name[..3],
+                    self.day,
+                    self.hour,
+                    self.min,
+                    self.sec,
+                    self.year
+                )
+                .unwrap(),
+                b'C' => write!(out, "{:02}", self.year.div_euclid(100)).unwrap(),
+                b'd' => write!(out, "{:02}", self.day).unwrap(),
+                b'D' | b'x' => write!(
+                    out,
+                    "{:02}/{:02}/{:02}",
+                    self.month,
+                    self.day,
+                    self.year.rem_euclid(100)
+                )
+                .unwrap(),
+                b'e' => write!(out, "{:2}", self.day).unwrap(),
+                b'F' => write!(out, "{}-{:02}-{:02}", self.year, self.month, self.day).unwrap(),
+                b'H' => write!(out, "{:02}", self.hour).unwrap(),
+                b'I' => write!(out, "{:02}", hour12).unwrap(),
+                b'j' => write!(out, "{:03}", self.yday).unwrap(),
+                b'm' => write!(out, "{:02}", self.month).unwrap(),
+                b'M' => write!(out, "{:02}", self.min).unwrap(),
+                b'n' => out.push(b'\n'),
+                b'p' => out.extend_from_slice(if self.hour < 12 { b"AM" } else { b"PM" }),
+                b'r' => write!(
+                    out,
+                    "{:02}:{:02}:{:02} {}",
+                    hour12,
+                    self.min,
+                    self.sec,
+                    if self.hour < 12 { "AM" } else { "PM" }
+                )
+                .unwrap(),
+                b'R' => write!(out, "{:02}:{:02}", self.hour, self.min).unwrap(),
+                b'S' => write!(out, "{:02}", self.sec).unwrap(),
+                b't' => out.push(b'\t'),
+                b'T' | b'X' => {
+                    write!(out, "{:02}:{:02}:{:02}", self.hour, self.min, self.sec).unwrap()
+                }
+                b'u' => write!(out, "{}", (self.wday + 5) % 7 + 1).unwrap(),
+                b'w' => write!(out, "{}", self.wday - 1).unwrap(),
+                b'y' => write!(out, "{:02}", self.year.rem_euclid(100)).unwrap(),
+                b'Y' => write!(out, "{}", self.year).unwrap(),
+                b'z' => out.extend_from_slice(b"+0000"),
+                b'Z' => out.extend_from_slice(b"UTC"),
+                b'%' => out.push(b'%'),
+                c => return Err(c),
+            }
+        }
+        Ok(out)
+    }
+}
+
+// Converts the fields of a date table into a timestamp, or `None` if it does not fit in an `i64`.
+//
+// Out of range fields are normalized, so `month = 13` is January of the next year and `day = 0` is
+// the last day of the previous month.
+fn time_from_fields(year: i64, month: i64, day: i64, hour: i64, min: i64, sec: i64) -> Option<i64> {
+    let month = month.checked_sub(1)?;
+    let year = year.checked_add(month.div_euclid(12))?;
+    let month = month.rem_euclid(12) + 1;
+    let days = days_from_civil(year, month, 1)?.checked_add(day.checked_sub(1)?)?;
+    days.checked_mul(86400)?
+        .checked_add(hour.checked_mul(3600)?)?
+        .checked_add(min.checked_mul(60)?)?
+        .checked_add(sec)
+}
+
+// Converts a count of days since 1970-01-01 into a (year, month, day) civil date in the proleptic
+// Gregorian calendar.
+//
+// This cannot overflow for any count of days within an `i64` timestamp, which is at most
+// `i64::MAX / 86400`.
+//
+// Algorithm from <http://howardhinnant.github.io/date_algorithms.html>.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let y = yoe + era * 400;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    (if m <= 2 { y + 1 } else { y }, m, d)
+}
+
+// The inverse of `civil_from_days`, `month` must be within 1..=12 but `day` may be any value.
+// Returns `None` on overflow.
+fn days_from_civil(year: i64, month: i64, day: i64) -> Option<i64> {
+    let y = if month <= 2 {
+        year.checked_sub(1)?
+    } else {
+        year
+    };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = ((153 * mp + 2) / 5).checked_add(day)? - 1;
+    let doe = (yoe * 365 + yoe / 4 - yoe / 100).checked_add(doy)?;
+    era.checked_mul(146097)?
+        .checked_add(doe)?
+        .checked_sub(719468)
+}
@@ -0,0 +1,308 @@
+use std::{io::Read, pin::Pin, rc::Rc, string::String as StdString};
+
+use gc_arena::{Collect, Rootable};
+
+use crate::{
+    meta_ops, BoxSequence, Callback, CallbackReturn, Closure, Context, Error, Execution, Function,
+    IntoValue, Sequence, SequencePoll, Singleton, Stack, String, Table, Value,
+};
+
+use super::io::{OpenMode, VfsProvider};
+
+/// The default value of `package.path`.
+pub const DEFAULT_PATH: &str = "./?.lua;./?/init.lua";
+
+/// Load `require` and the `package` library, without searching for modules in any filesystem.
+///
+/// Modules can be provided to scripts with [`preload`] or with custom searchers registered with
+/// [`add_searcher`]. The tables used by `require` exist once per [`Lua`](crate::Lua) instance, so
+/// modules are never shared between instances.
+pub fn load_package<'gc>(ctx: Context<'gc>) {
+    let package = PackageState::get(ctx);
+
+    if package.searchers.length() == 0 {
+        package
+            .searchers
+            .set(
+                ctx,
+                1,
+                Callback::from_fn_with(&ctx, package.preload, |preload, ctx, _, mut stack| {
+                    let name: String = stack.consume(ctx)?;
+                    match preload.get_value(ctx, name) {
+                        Value::Nil => stack.replace(
+                            ctx,
+                            format!("no field package.preload['{}']", name.display_lossy()),
+                        ),
+                        loader => stack.replace(ctx, (loader, ":preload:")),
+                    }
+                    Ok(CallbackReturn::Return)
+                }),
+            )
+            .unwrap();
+    }
+
+    // Libraries which have already been loaded are available from `require` as well.
+    for lib in ["coroutine", "io", "math", "os", "string", "table"] {
+        let value = ctx.get_global_value(lib);
+        if !value.is_nil() && package.loaded.get_value(ctx, lib).is_nil() {
+            package.loaded.set(ctx, lib, value).unwrap();
+        }
+    }
+
+    let package_table = Table::new(&ctx);
+    package_table.set_field(ctx, "loaded", package.loaded);
+    package_table.set_field(ctx, "preload", package.preload);
+    package_table.set_field(ctx, "searchers", package.searchers);
+    package_table.set_field(ctx, "path", DEFAULT_PATH);
+    package.loaded.set(ctx, "package", package_table).unwrap();
+    ctx.set_global("package", package_table);
+
+    ctx.set_global(
+        "require",
+        Callback::from_fn_with(&ctx, package, |&package, ctx, _, mut stack| {
+            let name: String = stack.consume(ctx)?;
+
+            let value = package.loaded.get_value(ctx, name);
+            if !value.is_nil() {
+                stack.replace(ctx, value);
+                return Ok(CallbackReturn::Return);
+            }
+
+            if !package.loading.get_value(ctx, name).is_nil() {
+                return Err(format!(
+                    "loop or previous error loading module '{}'",
+                    name.display_lossy()
+                )
+                .into_value(ctx)
+                .into());
+            }
+            package.loading.set(ctx, name, true)?;
+
+            Ok(CallbackReturn::Sequence(BoxSequence::new(
+                &ctx,
+                Require {
+                    package,
+                    name,
+                    searcher: 0,
+                    loader_data: None,
+                    not_found: Vec::new(),
+                },
+            )))
+        }),
+    );
+}
+
+/// Load `require` and the `package` library, also searching for Lua modules in the given
+/// [`VfsProvider`] using the templates in `package.path`.
+pub fn load_package_with_vfs<'gc>(ctx: Context<'gc>, vfs: impl VfsProvider) {
+    load_package(ctx);
+
+    let vfs: Rc<dyn VfsProvider> = Rc::new(vfs);
+    let package_table: Table = ctx.get_global("package").unwrap();
+    let searcher =
+        Callback::from_fn_with(&ctx, package_table, move |package, ctx, _, mut stack| {
+            let name: String = stack.consume(ctx)?;
+            let Value::String(path) = package.get_value(ctx, "path") else {
+                return Err("'package.path' must be a string".into_value(ctx).into());
+            };
+
+            let module_path: Vec<u8> = name
+                .as_bytes()
+                .iter()
+                .map(|&b| if b == b'.' { b'/' } else { b })
+                .collect();
+
+            let mut not_found = Vec::new();
+            for template in path.as_bytes().split(|&b| b == b';') {
+                if template.is_empty() {
+                    continue;
+                }
+
+                let mut filename = Vec::new();
+                for (i, part) in template.split(|&b| b == b'?').enumerate() {
+                    if i != 0 {
+                        filename.extend_from_slice(&module_path);
+                    }
+                    filename.extend_from_slice(part);
+                }
+                let filename = StdString::from_utf8_lossy(&filename).into_owned();
+
+                let Ok(mut file) = vfs.open(&filename, OpenMode::READ) else {
+                    not_found.push(format!("no file '{filename}'"));
+                    continue;
+                };
+
+                let mut source = Vec::new();
+                file.read_to_end(&mut source)?;
+                let closure = Closure::load(ctx, Some(&filename), &source[..]).map_err(|err| {
+                    format!(
+                        "error loading module '{}' from file '{filename}':\n\t{err}",
+                        name.display_lossy()
+                    )
+                    .into_value(ctx)
+                })?;
+                stack.replace(ctx, (closure, filename));
+                return Ok(CallbackReturn::Return);
+            }
+
+            stack.replace(ctx, not_found.join("\n\t"));
+            Ok(CallbackReturn::Return)
+        });
+
+    // Searchers are tried in order, and filesystem modules are searched for right after preloaded
+    // modules like in PUC-Rio Lua.
+    let searchers = PackageState::get(ctx).searchers;
+    let len = searchers.length();
+    for i in (2..=len).rev() {
+        searchers
+            .set(ctx, i + 1, searchers.get_value(ctx, i))
+            .unwrap();
+    }
+    searchers.set(ctx, 2, searcher).unwrap();
+}
+
+/// Register a loader function for the module `name`, which is called by `require` with the module
+/// name the first time the module is required. This is the same as setting
+/// `package.preload[name]`.
+pub fn preload<'gc>(ctx: Context<'gc>, name: &str, loader: impl Into<Function<'gc>>) {
+    PackageState::get(ctx)
+        .preload
+        .set(ctx, ctx.intern(name.as_bytes()), loader.into())
+        .unwrap();
+}
+
+/// Append a searcher to `package.searchers`.
+///
+/// A searcher is called by `require` with the module name, and must return a loader function and a
+/// value to pass to the loader if it can find the module. If it cannot, it should return a string
+/// describing why, which is included in the "module not found" error.
+pub fn add_searcher<'gc>(ctx: Context<'gc>, searcher: impl Into<Function<'gc>>) {
+    let searchers = PackageState::get(ctx).searchers;
+    searchers
+        .set(ctx, searchers.length() + 1, searcher.into())
+        .unwrap();
+}
+
+/// Returns the `package.loaded` table, where `require` caches loaded modules.
+pub fn loaded<'gc>(ctx: Context<'gc>) -> Table<'gc> {
+    PackageState::get(ctx).loaded
+}
+
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+struct PackageState<'gc> {
+    loaded: Table<'gc>,
+    preload: Table<'gc>,
+    searchers: Table<'gc>,
+    // Modules whose loaders are currently running, to detect circular requires.
+    loading: Table<'gc>,
+}
+
+impl<'gc> Singleton<'gc> for PackageState<'gc> {
+    fn create(ctx: Context<'gc>) -> Self {
+        Self {
+            loaded: Table::new(&ctx),
+            preload: Table::new(&ctx),
+            searchers: Table::new(&ctx),
+            loading: Table::new(&ctx),
+        }
+    }
+}
+
+impl<'gc> PackageState<'gc> {
+    fn get(ctx: Context<'gc>) -> Self {
+        *ctx.singleton::<Rootable![PackageState<'_>]>()
+    }
+}
+
+#[derive(Collect)]
+#[collect(no_drop)]
+struct Require<'gc> {
+    package: PackageState<'gc>,
+    name: String<'gc>,
+    // The index of the searcher that was last called.
+    searcher: i64,
+    // Set once a loader has been found and called, to the extra value returned by its searcher.
+    loader_data: Option<Value<'gc>>,
+    not_found: Vec<u8>,
+}
+
+impl<'gc> Sequence<'gc> for Require<'gc> {
+    fn poll(
+        mut self: Pin<&mut Self>,
+        ctx: Context<'gc>,
+        _exec: Execution<'gc, '_>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        let name = self.name;
+        let loaded = self.package.loaded;
+
+        if let Some(loader_data) = self.loader_data {
+            // The loader has returned, if it did not return a value and did not set
+            // `package.loaded[name]` itself, then the module is loaded as `true`.
+            let ret = stack.get(0);
+            if !ret.is_nil() {
+                loaded.set(ctx, name, ret)?;
+            }
+            if loaded.get_value(ctx, name).is_nil() {
+                loaded.set(ctx, name, true)?;
+            }
+            self.package.loading.set(ctx, name, Value::Nil)?;
+            stack.replace(ctx, (loaded.get_value(ctx, name), loader_data));
+            return Ok(SequencePoll::Return);
+        }
+
+        if self.searcher > 0 {
+            match stack.get(0) {
+                Value::Function(loader) => {
+                    let loader_data = stack.get(1);
+                    self.loader_data = Some(loader_data);
+                    stack.replace(ctx, (name, loader_data));
+                    return Ok(SequencePoll::Call {
+                        function: loader,
+                        bottom: 0,
+                    });
+                }
+                Value::String(msg) => {
+                    self.not_found.extend_from_slice(b"\n\t");
+                    self.not_found.extend_from_slice(msg.as_bytes());
+                }
+                _ => {}
+            }
+        }
+
+        self.searcher += 1;
+        match self.package.searchers.get_value(ctx, self.searcher) {
+            Value::Nil => {
+                self.package.loading.set(ctx, name, Value::Nil)?;
+                Err(format!(
+                    "module '{}' not found:{}",
+                    name.display_lossy(),
+                    StdString::from_utf8_lossy(&self.not_found)
+                )
+                .into_value(ctx)
+                .into())
+            }
+            searcher => {
+                let function = meta_ops::call(ctx, searcher)?;
+                stack.replace(ctx, name);
+                Ok(SequencePoll::Call {
+                    function,
+                    bottom: 0,
+                })
+            }
+        }
+    }
+
+    fn error(
+        self: Pin<&mut Self>,
+        ctx: Context<'gc>,
+        _exec: Execution<'gc, '_>,
+        error: Error<'gc>,
+        _stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        // A module which failed to load can be required again.
+        self.package.loading.set(ctx, self.name, Value::Nil)?;
+        Err(error)
+    }
+}
@@ -1,4 +1,9 @@
-use crate::{Callback, CallbackReturn, Context, String, Table};
+use std::cell::Cell;
+
+use crate::{
+    string::pattern::{self, Capture, Captures},
+    Callback, CallbackReturn, Context, IntoValue, MetaMethod, Stack, String, Table, Value,
+};
 
 pub fn load_string<'gc>(ctx: Context<'gc>) {
     let string = Table::new(&ctx);
@@ -99,5 +104,154 @@ pub fn load_string<'gc>(ctx: Context<'gc>) {
         }),
     );
 
+    string.set_field(
+        ctx,
+        "find",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (string, pat, init, plain) =
+                stack.consume::<(String, String, Option<i64>, Option<Value>)>(ctx)?;
+            let (haystack, pat) = (string.as_bytes(), pat.as_bytes());
+            let Some(init) = start_offset(init, haystack.len()) else {
+                stack.replace(ctx, Value::Nil);
+                return Ok(CallbackReturn::Return);
+            };
+
+            if plain.is_some_and(Value::to_bool) || !pattern::has_specials(pat) {
+                let found = if pat.is_empty() {
+                    Some(init)
+                } else {
+                    haystack[init..]
+                        .windows(pat.len())
+                        .position(|w| w == pat)
+                        .map(|i| init + i)
+                };
+                match found {
+                    Some(start) => {
+                        stack.replace(ctx, (start as i64 + 1, (start + pat.len()) as i64))
+                    }
+                    None => stack.replace(ctx, Value::Nil),
+                }
+            } else {
+                match pattern::match_captures_at(haystack, pat, init)? {
+                    Some(captures) => {
+                        stack.replace(
+                            ctx,
+                            (captures.whole.start as i64 + 1, captures.whole.end as i64),
+                        );
+                        for capture in &captures.captures {
+                            stack.push_back(capture_value(ctx, string, capture));
+                        }
+                    }
+                    None => stack.replace(ctx, Value::Nil),
+                }
+            }
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    string.set_field(
+        ctx,
+        "match",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (string, pat, init) = stack.consume::<(String, String, Option<i64>)>(ctx)?;
+            let captures = match start_offset(init, string.as_bytes().len()) {
+                Some(init) => pattern::match_captures_at(string.as_bytes(), pat.as_bytes(), init)?,
+                None => None,
+            };
+            match captures {
+                Some(captures) => push_captures(ctx, &mut stack, string, &captures),
+                None => stack.replace(ctx, Value::Nil),
+            }
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
+    string.set_field(
+        ctx,
+        "gmatch",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (string, pat, init) = stack.consume::<(String, String, Option<i64>)>(ctx)?;
+            let len = string.as_bytes().len();
+            // Start past the end if `init` is out of range, so that there are no matches.
+            let position = Cell::new(start_offset(init, len).unwrap_or(len + 1));
+            // The end of the last match, an empty match is not allowed to end at the same place.
+            let last_match = Cell::new(None);
+
+            let iter = Callback::from_fn_with(
+                &ctx,
+                (string, pat),
+                move |&(string, pat), ctx, _, mut stack| {
+                    let haystack = string.as_bytes();
+                    stack.clear();
+                    for start in position.get()..=haystack.len() {
+                        let Some(captures) =
+                            pattern::match_captures_here(haystack, pat.as_bytes(), start)?
+                        else {
+                            continue;
+                        };
+                        if last_match.get() == Some(captures.whole.end) {
+                            continue;
+                        }
+                        position.set(captures.whole.end);
+                        last_match.set(Some(captures.whole.end));
+                        push_captures(ctx, &mut stack, string, &captures);
+                        return Ok(CallbackReturn::Return);
+                    }
+                    position.set(haystack.len() + 1);
+                    Ok(CallbackReturn::Return)
+                },
+            );
+            stack.replace(ctx, iter);
+            Ok(CallbackReturn::Return)
+        }),
+    );
+
     ctx.set_global("string", string);
+
+    // Strings share a metatable which indexes the `string` table, so that `s:upper()` works. Like
+    // PUC-Rio Lua, `getmetatable` returns the real metatable, `SandboxBuilder` hides it behind a
+    // read-only proxy. Embedders can replace it through `Context::set_string_metatable`.
+    let metatable = Table::new(&ctx);
+    metatable.set(ctx, MetaMethod::Index, string).unwrap();
+    ctx.set_string_metatable(Some(metatable));
+}
+
+// Converts a 1-indexed, possibly negative, Lua start position into a byte offset, or `None` if it
+// is past the end of the string.
+fn start_offset(init: Option<i64>, len: usize) -> Option<usize> {
+    let init = match init.unwrap_or(1) {
+        i if i > 0 => usize::try_from(i - 1).unwrap_or(usize::MAX),
+        0 => 0,
+        i => len.saturating_sub(usize::try_from(i.unsigned_abs()).unwrap_or(usize::MAX)),
+    };
+    (init <= len).then_some(init)
+}
+
+fn capture_value<'gc>(ctx: Context<'gc>, string: String<'gc>, capture: &Capture) -> Value<'gc> {
+    match capture {
+        Capture::Span(span) => ctx.intern(&string.as_bytes()[span.clone()]).into(),
+        Capture::Position(position) => (*position as i64 + 1).into_value(ctx),
+    }
+}
+
+// Replaces the contents of the stack with every capture, or the whole match if the pattern has no
+// captures.
+fn push_captures<'gc>(
+    ctx: Context<'gc>,
+    stack: &mut Stack<'gc, '_>,
+    string: String<'gc>,
+    captures: &Captures,
+) {
+    stack.clear();
+    if captures.captures.is_empty() {
+        stack.push_back(capture_value(
+            ctx,
+            string,
+            &Capture::Span(captures.whole.clone()),
+        ));
+    } else {
+        for capture in &captures.captures {
+            stack.push_back(capture_value(ctx, string, capture));
+        }
+    }
 }
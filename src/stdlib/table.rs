@@ -8,7 +8,7 @@ use crate::{
     async_callback::{AsyncSequence, Locals},
     async_sequence,
     fuel::count_fuel,
-    meta_ops::{self, concat_separated, ConcatMetaResult, MetaResult},
+    meta_ops::{self, concat_separated, estimate_concatenated_len, ConcatMetaResult, MetaResult},
     table::RawTable,
     BoxSequence, Callback, CallbackReturn, Closure, Context, Error, Execution, Function, IntoValue,
     MetaMethod, Sequence, SequencePoll, SequenceReturn, Stack, StashedError, StashedFunction,
@@ -67,20 +67,58 @@ pub fn load_table<'gc>(ctx: Context<'gc>) {
         "concat",
         Callback::from_fn_with(&ctx, unpack, move |unpack, ctx, _exec, mut stack| {
             let sep = stack.remove(1).unwrap_or_default();
+            if !matches!(
+                sep,
+                Value::Nil | Value::String(_) | Value::Integer(_) | Value::Number(_)
+            ) {
+                return Err(format!(
+                    "bad argument #2 to 'concat' (string expected, got {})",
+                    sep.type_name()
+                )
+                .into_value(ctx)
+                .into());
+            }
+            let start = match stack.get(1) {
+                Value::Nil => 1,
+                v => v.to_integer().ok_or_else(|| {
+                    format!(
+                        "bad argument #3 to 'concat' (number expected, got {})",
+                        v.type_name()
+                    )
+                    .into_value(ctx)
+                })?,
+            };
 
-            let then_impl = Callback::from_fn_with(&ctx, sep, |sep, ctx, _, mut stack| {
-                let values = &stack[..];
-                match concat_separated(ctx, values, *sep)? {
-                    ConcatMetaResult::Value(v) => {
-                        stack.replace(ctx, v);
-                        Ok(CallbackReturn::Return)
+            let then_impl =
+                Callback::from_fn_with(&ctx, (sep, start), |&(sep, start), ctx, _, mut stack| {
+                    let values = &stack[..];
+                    // Unlike the `..` operator, `table.concat` only accepts strings and numbers and
+                    // never calls `__concat`.
+                    if let Some(i) = values.iter().position(|v| {
+                        !matches!(v, Value::String(_) | Value::Integer(_) | Value::Number(_))
+                    }) {
+                        return Err(format!(
+                            "invalid value (at index {}) in table for 'concat'",
+                            start.wrapping_add(i as i64)
+                        )
+                        .into_value(ctx)
+                        .into());
                     }
-                    ConcatMetaResult::Call(func) => Ok(CallbackReturn::Call {
-                        function: func,
-                        then: None,
-                    }),
-                }
-            });
+                    let sep_len = estimate_concatenated_len(&[sep])?.unwrap_or(0);
+                    if let Some(len) = estimate_concatenated_len(values)? {
+                        ctx.check_memory(len.saturating_add(sep_len.saturating_mul(values.len())))?;
+                    }
+                    match concat_separated(ctx, values, sep)? {
+                        ConcatMetaResult::Value(v) => {
+                            stack.replace(ctx, v);
+                            Ok(CallbackReturn::Return)
+                        }
+                        ConcatMetaResult::Call(func) => Ok(CallbackReturn::Call {
+                            function: func,
+                            then: None,
+                        }),
+                    }
+                });
 
             #[derive(Collect)]
             #[collect(no_drop)]
@@ -51,11 +51,36 @@ local function sort(arr, lo, hi, t, cmp)
 end
 
 local arr, cmp = ...
+
+local arr_type = type(arr)
+if arr_type ~= "table" and arr_type ~= "userdata" then
+    error("bad argument #1 to 'sort' (table expected, got " .. arr_type .. ")")
+end
+if cmp ~= nil and type(cmp) ~= "function" then
+    error("bad argument #2 to 'sort' (function expected, got " .. type(cmp) .. ")")
+end
+
 local len = #arr
+if math.type(len) ~= "integer" then
+    error("object length is not an integer")
+end
+if len >= 0x7fffffff then
+    error("bad argument #1 to 'sort' (array too big)")
+end
 if len < 0 then
     len = 0
 end
 
 local temp = {}
 sort(arr, 1, len, temp, cmp)
-return
+
+-- A merge sort never goes out of bounds with an inconsistent comparison function, so check the
+-- result instead. For a valid order no element may compare less than the one before it, which
+-- catches the common mistake of using `<=` as the comparison.
+if cmp ~= nil then
+    for i = 2, len do
+        if cmp(arr[i], arr[i - 1]) then
+            error("invalid order function for sorting")
+        end
+    end
+end
@@ -0,0 +1,208 @@
+use std::{cell::RefCell, string::String as StdString};
+
+use gc_arena::{Collect, Rootable};
+use thiserror::Error;
+
+use crate::{Callback, Context, Table, Value};
+
+/// Every function in the Lua 5.4 standard library, grouped by the global table which contains
+/// them. Base library functions are listed under the empty name.
+pub const STANDARD_FUNCTIONS: &[(&str, &[&str])] = &[
+    (
+        "",
+        &[
+            "assert",
+            "collectgarbage",
+            "dofile",
+            "error",
+            "getmetatable",
+            "ipairs",
+            "load",
+            "loadfile",
+            "next",
+            "pairs",
+            "pcall",
+            "print",
+            "rawequal",
+            "rawget",
+            "rawlen",
+            "rawset",
+            "require",
+            "select",
+            "setmetatable",
+            "tonumber",
+            "tostring",
+            "type",
+            "warn",
+            "xpcall",
+        ],
+    ),
+    (
+        "coroutine",
+        &[
+            "close",
+            "create",
+            "isyieldable",
+            "resume",
+            "running",
+            "status",
+            "wrap",
+            "yield",
+        ],
+    ),
+    (
+        "debug",
+        &[
+            "debug",
+            "gethook",
+            "getinfo",
+            "getlocal",
+            "getmetatable",
+            "getregistry",
+            "getupvalue",
+            "getuservalue",
+            "sethook",
+            "setlocal",
+            "setmetatable",
+            "setupvalue",
+            "setuservalue",
+            "traceback",
+            "upvalueid",
+            "upvaluejoin",
+        ],
+    ),
+    (
+        "io",
+        &[
+            "close", "flush", "input", "lines", "open", "output", "popen", "read", "tmpfile",
+            "type", "write",
+        ],
+    ),
+    (
+        "math",
+        &[
+            "abs",
+            "acos",
+            "asin",
+            "atan",
+            "ceil",
+            "cos",
+            "deg",
+            "exp",
+            "floor",
+            "fmod",
+            "log",
+            "max",
+            "min",
+            "modf",
+            "rad",
+            "random",
+            "randomseed",
+            "sin",
+            "sqrt",
+            "tan",
+            "tointeger",
+            "type",
+            "ult",
+        ],
+    ),
+    (
+        "os",
+        &[
+            "clock",
+            "date",
+            "difftime",
+            "execute",
+            "exit",
+            "getenv",
+            "remove",
+            "rename",
+            "setlocale",
+            "time",
+            "tmpname",
+        ],
+    ),
+    ("package", &["loadlib", "searchpath"]),
+    (
+        "string",
+        &[
+            "byte", "char", "dump", "find", "format", "gmatch", "gsub", "len", "lower", "match",
+            "pack", "packsize", "rep", "reverse", "sub", "unpack", "upper",
+        ],
+    ),
+    (
+        "table",
+        &[
+            "concat", "insert", "move", "pack", "remove", "sort", "unpack",
+        ],
+    ),
+    ("utf8", &["char", "codepoint", "codes", "len", "offset"]),
+];
+
+/// The error raised by a stub installed with [`load_unsupported`].
+#[derive(Debug, Clone, Error)]
+#[error("'{name}' is not supported in this sandbox")]
+pub struct UnsupportedFunction {
+    /// The full name of the function, like `string.pack` or `dofile`.
+    pub name: StdString,
+}
+
+#[derive(Default, Collect)]
+#[collect(require_static)]
+struct UnsupportedHook(RefCell<Option<Box<dyn FnMut(&str)>>>);
+
+/// Set a function to be called with the full name of a stub installed by [`load_unsupported`]
+/// every time a script calls one.
+pub fn set_unsupported_hook<'gc>(ctx: Context<'gc>, f: impl FnMut(&str) + 'static) {
+    *ctx.singleton::<Rootable![UnsupportedHook]>().0.borrow_mut() = Some(Box::new(f));
+}
+
+/// Fill in every function of the standard library which is not present with a stub that raises an
+/// [`UnsupportedFunction`] error.
+///
+/// This should be called after loading every other part of the stdlib. Scripts then fail with an
+/// error naming the missing function at the point they call it, rather than with an "attempt to
+/// call a nil value" error, and the host can find out which functions scripts need with
+/// [`set_unsupported_hook`]. Library tables which do not exist at all are created, globals which
+/// exist but are not tables are left alone.
+pub fn load_unsupported<'gc>(ctx: Context<'gc>) {
+    for &(library, functions) in STANDARD_FUNCTIONS {
+        let table = if library.is_empty() {
+            ctx.globals()
+        } else {
+            match ctx.get_global_value(library) {
+                Value::Table(table) => table,
+                Value::Nil => {
+                    let table = Table::new(&ctx);
+                    ctx.set_global(library, table);
+                    table
+                }
+                _ => continue,
+            }
+        };
+
+        for &function in functions {
+            if table.get_value(ctx, function).is_nil() {
+                table.set_field(ctx, function, unsupported_stub(ctx, library, function));
+            }
+        }
+    }
+}
+
+fn unsupported_stub<'gc>(
+    ctx: Context<'gc>,
+    library: &'static str,
+    function: &'static str,
+) -> Callback<'gc> {
+    Callback::from_fn(&ctx, move |ctx, _, _| {
+        let name = if library.is_empty() {
+            function.to_owned()
+        } else {
+            format!("{library}.{function}")
+        };
+        if let Some(hook) = &mut *ctx.singleton::<Rootable![UnsupportedHook]>().0.borrow_mut() {
+            hook(&name);
+        }
+        Err(UnsupportedFunction { name }.into())
+    })
+}
@@ -1,7 +1,7 @@
 use std::{
     alloc, fmt,
     hash::{BuildHasherDefault, Hash, Hasher},
-    ops, slice,
+    ops, ptr, slice,
     str::{self, Utf8Error},
 };
 
@@ -14,6 +14,8 @@ use hashbrown::{hash_map, raw::RawTable, HashMap};
 
 use crate::compiler::string_utils::{debug_utf8_lossy, display_utf8_lossy};
 
+pub mod pattern;
+
 /// The Lua string type.
 ///
 /// Unlike Rust strings, Lua strings may contain *arbitrary bytes*, and as such are not necessarily
@@ -28,6 +30,9 @@ pub struct String<'gc>(Gc<'gc, StringInner>);
 #[collect(require_static)]
 pub struct StringInner {
     hash: u64,
+    // Set for strings created by an `InternedStringSet`. No two live interned strings have the
+    // same contents, so interned strings can be compared by pointer alone.
+    interned: bool,
     buffer: Buffer,
 }
 
@@ -39,6 +44,18 @@ enum Buffer {
 
 impl<'gc> String<'gc> {
     pub fn from_buffer(mc: &Mutation<'gc>, s: Box<[u8]>) -> String<'gc> {
+        Self::new_buffer(mc, s, false)
+    }
+
+    pub fn from_slice(mc: &Mutation<'gc>, s: impl AsRef<[u8]>) -> String<'gc> {
+        Self::new_slice(mc, s.as_ref(), false)
+    }
+
+    pub fn from_static<S: ?Sized + AsRef<[u8]>>(mc: &Mutation<'gc>, s: &'static S) -> String<'gc> {
+        Self::new_static(mc, s.as_ref(), false)
+    }
+
+    fn new_buffer(mc: &Mutation<'gc>, s: Box<[u8]>, interned: bool) -> String<'gc> {
         #[derive(Collect)]
         #[collect(require_static)]
         #[repr(C)]
@@ -64,6 +81,7 @@ impl<'gc> String<'gc> {
         let owned = Owned {
             header: StringInner {
                 hash: str_hash(&s),
+                interned,
                 buffer: Buffer::Indirect(Box::into_raw(s)),
             },
             metrics,
@@ -72,13 +90,17 @@ impl<'gc> String<'gc> {
         String(unsafe { Gc::cast::<StringInner>(Gc::new(mc, owned)) })
     }
 
-    pub fn from_slice(mc: &Mutation<'gc>, s: impl AsRef<[u8]>) -> String<'gc> {
+    fn new_slice(mc: &Mutation<'gc>, s: &[u8], interned: bool) -> String<'gc> {
         // TODO: This is an extremely silly way to allocate a dynamically sized, inline string.
         // Since gc-arena does not support variable sized allocations, we try a set of static
         // sizes to inline small strings. All larger strings are instead allocated with an indirect
         // buffer. This can be improved when gc-arena learns to allocate variable sizes.
 
-        fn create<'gc, const N: usize>(mc: &Mutation<'gc>, s: &[u8]) -> String<'gc> {
+        fn create<'gc, const N: usize>(
+            mc: &Mutation<'gc>,
+            s: &[u8],
+            interned: bool,
+        ) -> String<'gc> {
             #[derive(Collect)]
             #[collect(require_static)]
             #[repr(C)]
@@ -91,6 +113,7 @@ impl<'gc> String<'gc> {
             let mut string = InlineString {
                 header: StringInner {
                     hash: str_hash(&s),
+                    interned,
                     buffer: Buffer::Inline(s.len()),
                 },
                 array: [0; N],
@@ -103,26 +126,25 @@ impl<'gc> String<'gc> {
             unsafe { String(Gc::cast::<StringInner>(string)) }
         }
 
-        let s = s.as_ref();
-
         macro_rules! try_sizes {
             ($($size:expr),*) => {
                 $(if s.len() <= $size {
-                    return create::<$size>(mc, s);
+                    return create::<$size>(mc, s, interned);
                 })*
             };
         }
         try_sizes!(0, 2, 4, 8, 12, 16, 24, 32, 48, 64, 96, 128, 192, 256);
 
-        Self::from_buffer(mc, s.into())
+        Self::new_buffer(mc, s.into(), interned)
     }
 
-    pub fn from_static<S: ?Sized + AsRef<[u8]>>(mc: &Mutation<'gc>, s: &'static S) -> String<'gc> {
+    fn new_static(mc: &Mutation<'gc>, s: &'static [u8], interned: bool) -> String<'gc> {
         String(Gc::new(
             mc,
             StringInner {
-                hash: str_hash(s.as_ref()),
-                buffer: Buffer::Indirect(s.as_ref()),
+                hash: str_hash(s),
+                interned,
+                buffer: Buffer::Indirect(s),
             },
         ))
     }
@@ -139,6 +161,28 @@ impl<'gc> String<'gc> {
         self.0.hash
     }
 
+    /// Returns true if this string was created by an [`InternedStringSet`], such as with
+    /// [`Context::intern`](crate::Context::intern).
+    ///
+    /// All strings created by the VM and the stdlib, as well as all string constants, are interned.
+    pub fn is_interned(self) -> bool {
+        self.0.interned
+    }
+
+    /// Compare two strings by contents, taking advantage of interning.
+    ///
+    /// Two interned strings are equal only if they are the same string, so this is a pointer
+    /// comparison for them, and otherwise compares the stored hashes before the contents.
+    pub fn fast_eq(self, other: String<'gc>) -> bool {
+        if Gc::ptr_eq(self.0, other.0) {
+            true
+        } else if self.0.interned && other.0.interned {
+            false
+        } else {
+            self.0.hash == other.0.hash && self.as_bytes() == other.as_bytes()
+        }
+    }
+
     pub fn as_bytes(self) -> &'gc [u8] {
         // SAFETY: `&'gc [u8]` has the correct lifetime because `Gc::as_ref` also returns `&'gc T`.
         unsafe {
@@ -209,7 +253,9 @@ where
     T: ?Sized + AsRef<[u8]>,
 {
     fn eq(&self, other: &T) -> bool {
-        self.as_bytes() == other.as_ref()
+        let (this, other) = (self.as_bytes(), other.as_ref());
+        // Comparing two handles to the same string is common, and needs no `memcmp`.
+        ptr::eq(this, other) || this == other
     }
 }
 
@@ -256,6 +302,17 @@ impl<'gc> InternedDynStrings<'gc> {
     }
 
     fn intern(self, mc: &Mutation<'gc>, s: &[u8]) -> String<'gc> {
+        self.intern_with(mc, s, || String::new_slice(mc, s, true))
+    }
+
+    // Find the live interned string with the given contents, or add the string returned by
+    // `create`, which must have the same contents and be marked as interned.
+    fn intern_with(
+        self,
+        mc: &Mutation<'gc>,
+        s: &[u8],
+        create: impl FnOnce() -> String<'gc>,
+    ) -> String<'gc> {
         // SAFETY: If a new string is added, we call the write barrier.
         let mut dyn_strings = unsafe { self.0 .0.unlock_unchecked() }.borrow_mut();
 
@@ -277,14 +334,15 @@ impl<'gc> InternedDynStrings<'gc> {
         // SAFETY: We are going to modify the dyn_strings table, so call the write barrier.
         Gc::write(mc, self.0);
 
-        let s = String::from_slice(mc, s);
+        let string = create();
+        debug_assert!(string.is_interned() && string == s);
         dyn_strings.insert(
-            s.stored_hash(),
-            (Gc::downgrade(s.into_inner()), s.stored_hash()),
+            string.stored_hash(),
+            (Gc::downgrade(string.into_inner()), string.stored_hash()),
             |(_, hash)| *hash,
         );
 
-        s
+        string
     }
 }
 
@@ -315,7 +373,14 @@ impl<'gc> InternedStaticStrings<'gc> {
         ))
     }
 
-    fn intern(self, mc: &Mutation<'gc>, s: &'static [u8]) -> String<'gc> {
+    // Static strings are interned in `dyn_strings` as well, so that a static string and a dynamic
+    // string with the same contents are the same string.
+    fn intern(
+        self,
+        mc: &Mutation<'gc>,
+        dyn_strings: InternedDynStrings<'gc>,
+        s: &'static [u8],
+    ) -> String<'gc> {
         let key = Static(s as *const _);
 
         // SAFETY: If a new string is added, we call the write barrier.
@@ -326,7 +391,7 @@ impl<'gc> InternedStaticStrings<'gc> {
             hash_map::Entry::Vacant(vacant) => {
                 // SAFETY: We are modifying the static_strings table, so we call the write barrier.
                 Gc::write(mc, self.0);
-                *vacant.insert(String::from_static(mc, s))
+                *vacant.insert(dyn_strings.intern_with(mc, s, || String::new_static(mc, s, true)))
             }
         }
     }
@@ -339,6 +404,10 @@ impl<'gc> InternedStaticStrings<'gc> {
 /// string.
 ///
 /// If there is no matching existing live interned string, then a new string is allocated.
+///
+/// Static and dynamic strings are interned together, so there is never more than one live
+/// interned string with the same contents, and interned strings can be compared by pointer (see
+/// [`String::fast_eq`]).
 #[derive(Copy, Clone, Collect)]
 #[collect(no_drop)]
 pub struct InternedStringSet<'gc> {
@@ -359,7 +428,7 @@ impl<'gc> InternedStringSet<'gc> {
     }
 
     pub fn intern_static(self, mc: &Mutation<'gc>, s: &'static [u8]) -> String<'gc> {
-        self.static_strings.intern(mc, s)
+        self.static_strings.intern(mc, self.dyn_strings, s)
     }
 }
 
@@ -389,4 +458,28 @@ mod tests {
             assert_eq!(test6.as_bytes(), b"test 666666");
         });
     }
+
+    #[test]
+    fn test_interning() {
+        rootless_mutate(|mc| {
+            let set = InternedStringSet::new(mc);
+
+            let a = set.intern(mc, b"key");
+            let b = set.intern_static(mc, b"key");
+            let c = set.intern(mc, b"key");
+            assert!(a.is_interned());
+            assert!(Gc::ptr_eq(a.into_inner(), b.into_inner()));
+            assert!(Gc::ptr_eq(a.into_inner(), c.into_inner()));
+
+            let static_first = set.intern_static(mc, b"other");
+            let dynamic = set.intern(mc, b"other");
+            assert!(Gc::ptr_eq(static_first.into_inner(), dynamic.into_inner()));
+
+            let uninterned = String::from_slice(mc, b"key");
+            assert!(!uninterned.is_interned());
+            assert!(a.fast_eq(uninterned) && uninterned.fast_eq(a));
+            assert!(!a.fast_eq(dynamic));
+            assert!(!String::from_slice(mc, b"kez").fast_eq(uninterned));
+        });
+    }
 }
@@ -0,0 +1,542 @@
+//! Lua string patterns, as used by `string.find`, `string.match` and `string.gmatch`.
+//!
+//! The functions here use the same matcher as the stdlib, so host code can match or validate
+//! patterns with exactly the semantics that scripts see.
+
+use std::ops::Range;
+
+use thiserror::Error;
+
+/// The maximum number of captures in a single pattern.
+pub const MAX_CAPTURES: usize = 32;
+
+// The maximum depth of recursive matching, the same as the `MAXCCALLS` of PUC-Rio Lua.
+const MAX_RECURSION: usize = 200;
+
+/// An error in the syntax of a pattern.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Error)]
+pub enum PatternError {
+    #[error("malformed pattern (ends with '%')")]
+    EndsWithPercent,
+    #[error("malformed pattern (missing ']')")]
+    MissingBracket,
+    #[error("missing '[' after '%f' in pattern")]
+    MissingFrontierBracket,
+    #[error("malformed pattern (missing arguments to '%b')")]
+    MissingBalanceArguments,
+    #[error("invalid capture index %{0} in pattern")]
+    InvalidCaptureIndex(u8),
+    #[error("invalid pattern capture")]
+    InvalidCapture,
+    #[error("unfinished capture")]
+    UnfinishedCapture,
+    #[error("too many captures")]
+    TooManyCaptures,
+    #[error("pattern too complex")]
+    TooComplex,
+}
+
+/// A single capture of a successful match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Capture {
+    /// A substring capture like `(%a+)`, as a range of byte offsets into the haystack.
+    Span(Range<usize>),
+    /// A position capture `()`, as a 0-indexed byte offset into the haystack.
+    ///
+    /// Lua represents this as the 1-indexed position, one greater than this offset.
+    Position(usize),
+}
+
+/// The result of a successful match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Captures {
+    /// The byte range of the whole match.
+    pub whole: Range<usize>,
+    /// Every capture in the pattern, in the order of their opening parentheses.
+    pub captures: Vec<Capture>,
+}
+
+/// Find the first match of `pattern` in `haystack`, see [`match_captures_at`].
+pub fn match_captures(haystack: &[u8], pattern: &[u8]) -> Result<Option<Captures>, PatternError> {
+    match_captures_at(haystack, pattern, 0)
+}
+
+/// Find the first match of `pattern` in `haystack` starting at or after the byte offset `init`,
+/// the same as `string.find` and `string.match`.
+///
+/// A pattern starting with `^` only matches at `init`. Returns `None` if there is no match or
+/// `init` is past the end of the haystack.
+///
+/// Like PUC-Rio Lua, syntax errors are only reported if they are reached while matching, use
+/// [`validate`] to check the whole pattern up front.
+pub fn match_captures_at(
+    haystack: &[u8],
+    pattern: &[u8],
+    init: usize,
+) -> Result<Option<Captures>, PatternError> {
+    if init > haystack.len() {
+        return Ok(None);
+    }
+
+    let (anchor, pattern_start) = match pattern.first() {
+        Some(b'^') => (true, 1),
+        _ => (false, 0),
+    };
+
+    let mut state = MatchState::new(haystack, pattern);
+    let mut start = init;
+    loop {
+        if let Some(end) = state.start_match(start, pattern_start)? {
+            return state.captures(start..end).map(Some);
+        }
+        start += 1;
+        if anchor || start > haystack.len() {
+            return Ok(None);
+        }
+    }
+}
+
+// Match `pattern` exactly at `start` with no special handling of a leading `^`, as used by
+// `string.gmatch`.
+pub(crate) fn match_captures_here(
+    haystack: &[u8],
+    pattern: &[u8],
+    start: usize,
+) -> Result<Option<Captures>, PatternError> {
+    let mut state = MatchState::new(haystack, pattern);
+    match state.start_match(start, 0)? {
+        Some(end) => state.captures(start..end).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Check the syntax of an entire pattern.
+///
+/// This is stricter than matching, which only reports the errors that it reaches: any pattern that
+/// passes validation will never produce a [`PatternError`] other than
+/// [`PatternError::TooComplex`].
+pub fn validate(pattern: &[u8]) -> Result<(), PatternError> {
+    // Whether each capture started so far has been closed.
+    let mut closed = Vec::new();
+    let mut open = Vec::new();
+
+    let mut p = if pattern.first() == Some(&b'^') { 1 } else { 0 };
+    while p < pattern.len() {
+        match pattern[p] {
+            b'(' => {
+                if closed.len() >= MAX_CAPTURES {
+                    return Err(PatternError::TooManyCaptures);
+                }
+                if pattern.get(p + 1) == Some(&b')') {
+                    closed.push(true);
+                    p += 2;
+                } else {
+                    open.push(closed.len());
+                    closed.push(false);
+                    p += 1;
+                }
+                continue;
+            }
+            b')' => {
+                let i = open.pop().ok_or(PatternError::InvalidCapture)?;
+                closed[i] = true;
+                p += 1;
+                continue;
+            }
+            b'$' if p + 1 == pattern.len() => break,
+            b'%' => match pattern.get(p + 1) {
+                Some(b'b') => {
+                    if pattern.len() < p + 4 {
+                        return Err(PatternError::MissingBalanceArguments);
+                    }
+                    p += 4;
+                    continue;
+                }
+                Some(b'f') => {
+                    p += 2;
+                    if pattern.get(p) != Some(&b'[') {
+                        return Err(PatternError::MissingFrontierBracket);
+                    }
+                    p = class_end(pattern, p)?;
+                    continue;
+                }
+                Some(&d) if d.is_ascii_digit() => {
+                    match (d - b'0').checked_sub(1) {
+                        Some(l) if closed.get(usize::from(l)) == Some(&true) => {}
+                        _ => return Err(PatternError::InvalidCaptureIndex(d - b'0')),
+                    }
+                    p += 2;
+                    continue;
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+
+        p = class_end(pattern, p)?;
+        if matches!(pattern.get(p), Some(b'*' | b'+' | b'-' | b'?')) {
+            p += 1;
+        }
+    }
+
+    if open.is_empty() {
+        Ok(())
+    } else {
+        Err(PatternError::UnfinishedCapture)
+    }
+}
+
+/// Returns true if `pattern` contains any characters with a special meaning.
+///
+/// Patterns without special characters can be searched for as plain substrings.
+pub fn has_specials(pattern: &[u8]) -> bool {
+    pattern.iter().any(|c| b"^$*+?.([%-".contains(c))
+}
+
+#[derive(Debug, Copy, Clone)]
+enum CaptureLen {
+    Unfinished,
+    Position,
+    Closed(usize),
+}
+
+// A port of the matcher in PUC-Rio Lua's `lstrlib.c`. Positions in the haystack are `s` and
+// positions in the pattern are `p`.
+struct MatchState<'a> {
+    src: &'a [u8],
+    pat: &'a [u8],
+    depth: usize,
+    level: usize,
+    capture: [(usize, CaptureLen); MAX_CAPTURES],
+}
+
+impl<'a> MatchState<'a> {
+    fn new(src: &'a [u8], pat: &'a [u8]) -> Self {
+        Self {
+            src,
+            pat,
+            depth: MAX_RECURSION,
+            level: 0,
+            capture: [(0, CaptureLen::Unfinished); MAX_CAPTURES],
+        }
+    }
+
+    fn start_match(&mut self, s: usize, p: usize) -> Result<Option<usize>, PatternError> {
+        self.depth = MAX_RECURSION;
+        self.level = 0;
+        self.do_match(s, p)
+    }
+
+    fn captures(&self, whole: Range<usize>) -> Result<Captures, PatternError> {
+        let captures = self.capture[..self.level]
+            .iter()
+            .map(|&(start, len)| match len {
+                CaptureLen::Unfinished => Err(PatternError::UnfinishedCapture),
+                CaptureLen::Position => Ok(Capture::Position(start)),
+                CaptureLen::Closed(len) => Ok(Capture::Span(start..start + len)),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Captures { whole, captures })
+    }
+
+    // Match the pattern from `p` against the haystack from `s`, returning the end of the match.
+    fn do_match(&mut self, mut s: usize, mut p: usize) -> Result<Option<usize>, PatternError> {
+        if self.depth == 0 {
+            return Err(PatternError::TooComplex);
+        }
+        self.depth -= 1;
+
+        let pat = self.pat;
+        let res = loop {
+            if p == pat.len() {
+                break Some(s);
+            }
+
+            match pat[p] {
+                b'(' => {
+                    break if pat.get(p + 1) == Some(&b')') {
+                        self.start_capture(s, p + 2, CaptureLen::Position)?
+                    } else {
+                        self.start_capture(s, p + 1, CaptureLen::Unfinished)?
+                    };
+                }
+                b')' => break self.end_capture(s, p + 1)?,
+                b'$' if p + 1 == pat.len() => break (s == self.src.len()).then_some(s),
+                b'%' => match pat.get(p + 1) {
+                    Some(b'b') => match self.match_balance(s, p + 2)? {
+                        Some(end) => {
+                            s = end;
+                            p += 4;
+                            continue;
+                        }
+                        None => break None,
+                    },
+                    Some(b'f') => {
+                        p += 2;
+                        if pat.get(p) != Some(&b'[') {
+                            return Err(PatternError::MissingFrontierBracket);
+                        }
+                        let ep = class_end(pat, p)?;
+                        let prev = if s == 0 { 0 } else { self.src[s - 1] };
+                        let cur = self.src.get(s).copied().unwrap_or(0);
+                        if !match_bracket_class(pat, prev, p, ep - 1)
+                            && match_bracket_class(pat, cur, p, ep - 1)
+                        {
+                            p = ep;
+                            continue;
+                        }
+                        break None;
+                    }
+                    Some(&d) if d.is_ascii_digit() => match self.match_back_reference(s, d)? {
+                        Some(end) => {
+                            s = end;
+                            p += 2;
+                            continue;
+                        }
+                        None => break None,
+                    },
+                    _ => {}
+                },
+                _ => {}
+            }
+
+            let ep = class_end(pat, p)?;
+            let suffix = pat.get(ep).copied();
+            if !self.single_match(s, p, ep) {
+                if matches!(suffix, Some(b'*' | b'?' | b'-')) {
+                    // The item accepts zero matches.
+                    p = ep + 1;
+                    continue;
+                }
+                break None;
+            }
+
+            match suffix {
+                Some(b'?') => {
+                    if let Some(end) = self.do_match(s + 1, ep + 1)? {
+                        break Some(end);
+                    }
+                    p = ep + 1;
+                }
+                Some(b'+') => break self.max_expand(s + 1, p, ep)?,
+                Some(b'*') => break self.max_expand(s, p, ep)?,
+                Some(b'-') => break self.min_expand(s, p, ep)?,
+                _ => {
+                    s += 1;
+                    p = ep;
+                }
+            }
+        };
+
+        self.depth += 1;
+        Ok(res)
+    }
+
+    fn single_match(&self, s: usize, p: usize, ep: usize) -> bool {
+        let Some(&c) = self.src.get(s) else {
+            return false;
+        };
+        match self.pat[p] {
+            b'.' => true,
+            b'%' => match_class(c, self.pat[p + 1]),
+            b'[' => match_bracket_class(self.pat, c, p, ep - 1),
+            pc => pc == c,
+        }
+    }
+
+    fn max_expand(&mut self, s: usize, p: usize, ep: usize) -> Result<Option<usize>, PatternError> {
+        let mut i = 0;
+        while self.single_match(s + i, p, ep) {
+            i += 1;
+        }
+        // Try with the maximum repetitions, then with fewer until the rest of the pattern matches.
+        loop {
+            if let Some(end) = self.do_match(s + i, ep + 1)? {
+                return Ok(Some(end));
+            }
+            if i == 0 {
+                return Ok(None);
+            }
+            i -= 1;
+        }
+    }
+
+    fn min_expand(
+        &mut self,
+        mut s: usize,
+        p: usize,
+        ep: usize,
+    ) -> Result<Option<usize>, PatternError> {
+        loop {
+            if let Some(end) = self.do_match(s, ep + 1)? {
+                return Ok(Some(end));
+            }
+            if self.single_match(s, p, ep) {
+                s += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn start_capture(
+        &mut self,
+        s: usize,
+        p: usize,
+        len: CaptureLen,
+    ) -> Result<Option<usize>, PatternError> {
+        if self.level >= MAX_CAPTURES {
+            return Err(PatternError::TooManyCaptures);
+        }
+        self.capture[self.level] = (s, len);
+        self.level += 1;
+        let res = self.do_match(s, p)?;
+        if res.is_none() {
+            self.level -= 1;
+        }
+        Ok(res)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>, PatternError> {
+        let l = self.capture[..self.level]
+            .iter()
+            .rposition(|(_, len)| matches!(len, CaptureLen::Unfinished))
+            .ok_or(PatternError::InvalidCapture)?;
+        self.capture[l].1 = CaptureLen::Closed(s - self.capture[l].0);
+        let res = self.do_match(s, p)?;
+        if res.is_none() {
+            self.capture[l].1 = CaptureLen::Unfinished;
+        }
+        Ok(res)
+    }
+
+    fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>, PatternError> {
+        let (Some(&open), Some(&close)) = (self.pat.get(p), self.pat.get(p + 1)) else {
+            return Err(PatternError::MissingBalanceArguments);
+        };
+        if self.src.get(s) != Some(&open) {
+            return Ok(None);
+        }
+
+        let mut depth = 1;
+        for (i, &c) in self.src.iter().enumerate().skip(s + 1) {
+            if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(Some(i + 1));
+                }
+            } else if c == open {
+                depth += 1;
+            }
+        }
+        Ok(None)
+    }
+
+    fn match_back_reference(&self, s: usize, digit: u8) -> Result<Option<usize>, PatternError> {
+        let index = digit - b'0';
+        let (start, len) = usize::from(index)
+            .checked_sub(1)
+            .filter(|&l| l < self.level)
+            .map(|l| self.capture[l])
+            .ok_or(PatternError::InvalidCaptureIndex(index))?;
+        match len {
+            CaptureLen::Unfinished => Err(PatternError::InvalidCaptureIndex(index)),
+            // A position capture never matches as a back reference.
+            CaptureLen::Position => Ok(None),
+            CaptureLen::Closed(len) => {
+                let captured = &self.src[start..start + len];
+                Ok(self.src[s..].starts_with(captured).then_some(s + len))
+            }
+        }
+    }
+}
+
+// Returns the end of the single character class starting at `p`.
+fn class_end(pat: &[u8], mut p: usize) -> Result<usize, PatternError> {
+    let c = pat[p];
+    p += 1;
+    match c {
+        b'%' => {
+            if p >= pat.len() {
+                return Err(PatternError::EndsWithPercent);
+            }
+            Ok(p + 1)
+        }
+        b'[' => {
+            if pat.get(p) == Some(&b'^') {
+                p += 1;
+            }
+            // The first character is part of the set even if it is a `]`.
+            loop {
+                if p >= pat.len() {
+                    return Err(PatternError::MissingBracket);
+                }
+                let c = pat[p];
+                p += 1;
+                if c == b'%' && p < pat.len() {
+                    p += 1;
+                }
+                if pat.get(p) == Some(&b']') {
+                    break;
+                }
+            }
+            Ok(p + 1)
+        }
+        _ => Ok(p),
+    }
+}
+
+// Match `c` against the `%` class `cl`, where an upper case class is the complement of its lower
+// case class. Any other character matches itself.
+fn match_class(c: u8, cl: u8) -> bool {
+    let res = match cl.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'g' => c.is_ascii_graphic(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        // Unlike `u8::is_ascii_whitespace`, this includes vertical tab.
+        b's' => matches!(c, b' ' | b'\t' | b'\n' | b'\x0b' | b'\x0c' | b'\r'),
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        _ => return cl == c,
+    };
+    if cl.is_ascii_uppercase() {
+        !res
+    } else {
+        res
+    }
+}
+
+// Match `c` against the set starting with the `[` at `p` and ending with the `]` at `ec`.
+fn match_bracket_class(pat: &[u8], c: u8, mut p: usize, ec: usize) -> bool {
+    let mut matches = true;
+    p += 1;
+    if pat[p] == b'^' {
+        matches = false;
+        p += 1;
+    }
+
+    while p < ec {
+        if pat[p] == b'%' {
+            p += 1;
+            if match_class(c, pat[p]) {
+                return matches;
+            }
+            p += 1;
+        } else if pat[p + 1] == b'-' && p + 2 < ec {
+            if pat[p] <= c && c <= pat[p + 2] {
+                return matches;
+            }
+            p += 3;
+        } else {
+            if pat[p] == c {
+                return matches;
+            }
+            p += 1;
+        }
+    }
+    !matches
+}
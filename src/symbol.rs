@@ -0,0 +1,151 @@
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+use gc_arena::{Collect, Gc};
+
+use crate::{Context, FromValue, IntoValue, String, TypeError, Value};
+
+/// An interned, UTF-8 Lua string used as an enum-like tag.
+///
+/// Symbols are always created through the interned string set of the [`Context`], so two symbols
+/// with the same contents are the same string and are compared by pointer rather than by contents.
+/// The string hash is computed once when the string is created, so hashing a symbol is also O(1).
+///
+/// In Lua, a symbol is just a string. Converting a string into a symbol (with [`FromValue`])
+/// interns it, after which it can be compared cheaply any number of times. On the Rust side,
+/// symbols can be matched against string literals with [`Symbol::as_str`] or with
+/// [`match_symbol!`](crate::match_symbol).
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub struct Symbol<'gc>(String<'gc>);
+
+impl<'gc> Symbol<'gc> {
+    pub fn new(ctx: Context<'gc>, s: &str) -> Symbol<'gc> {
+        Symbol(ctx.intern(s.as_bytes()))
+    }
+
+    /// Intern the contents of the given string as a symbol.
+    ///
+    /// Returns `None` if the string is not valid UTF-8.
+    pub fn from_string(ctx: Context<'gc>, s: String<'gc>) -> Option<Symbol<'gc>> {
+        s.to_str().ok()?;
+        Some(Symbol(ctx.intern(s.as_bytes())))
+    }
+
+    pub fn as_str(self) -> &'gc str {
+        // SAFETY: Symbols are only ever created from valid UTF-8.
+        unsafe { std::str::from_utf8_unchecked(self.0.as_bytes()) }
+    }
+
+    pub fn as_string(self) -> String<'gc> {
+        self.0
+    }
+
+    /// The precomputed hash of the symbol's contents.
+    ///
+    /// This is stable for the lifetime of the arena, but is *not* unique: symbols with the same id
+    /// may still be different symbols.
+    pub fn id(self) -> u64 {
+        self.0.stored_hash()
+    }
+}
+
+impl<'gc> fmt::Debug for Symbol<'gc> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_tuple("Symbol").field(&self.as_str()).finish()
+    }
+}
+
+impl<'gc> fmt::Display for Symbol<'gc> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(self.as_str())
+    }
+}
+
+impl<'gc> PartialEq for Symbol<'gc> {
+    fn eq(&self, other: &Symbol<'gc>) -> bool {
+        Gc::ptr_eq(self.0.into_inner(), other.0.into_inner())
+    }
+}
+
+impl<'gc> Eq for Symbol<'gc> {}
+
+impl<'gc> PartialEq<str> for Symbol<'gc> {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl<'gc> PartialEq<&str> for Symbol<'gc> {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl<'gc> Hash for Symbol<'gc> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.id())
+    }
+}
+
+impl<'gc> From<Symbol<'gc>> for String<'gc> {
+    fn from(symbol: Symbol<'gc>) -> String<'gc> {
+        symbol.0
+    }
+}
+
+impl<'gc> From<Symbol<'gc>> for Value<'gc> {
+    fn from(symbol: Symbol<'gc>) -> Value<'gc> {
+        Value::String(symbol.0)
+    }
+}
+
+impl<'gc> IntoValue<'gc> for Symbol<'gc> {
+    fn into_value(self, _: Context<'gc>) -> Value<'gc> {
+        self.into()
+    }
+}
+
+impl<'gc> FromValue<'gc> for Symbol<'gc> {
+    fn from_value(ctx: Context<'gc>, value: Value<'gc>) -> Result<Self, TypeError> {
+        // Unlike `String`, numbers are not coerced, a symbol must already be a string.
+        match value {
+            Value::String(s) => Symbol::from_string(ctx, s).ok_or(TypeError {
+                expected: "UTF-8 String",
+                found: "non-UTF-8 String",
+                found_name: None,
+                position: None,
+            }),
+            _ => Err(TypeError::new("symbol", value)),
+        }
+    }
+}
+
+/// Match a [`Symbol`] against string literal patterns.
+///
+/// This is equivalent to matching on [`Symbol::as_str`], and accepts the same patterns and guards.
+///
+/// ```
+/// # use piccolo::{match_symbol, Lua, Symbol};
+/// # let mut lua = Lua::core();
+/// # lua.enter(|ctx| {
+/// let symbol = Symbol::new(ctx, "green");
+/// let index = match_symbol!(symbol, {
+///     "red" => 0,
+///     "green" => 1,
+///     "blue" | "indigo" => 2,
+///     _ => 3,
+/// });
+/// assert_eq!(index, 1);
+/// # });
+/// ```
+#[macro_export]
+macro_rules! match_symbol {
+    ($symbol:expr, { $($pattern:pat $(if $guard:expr)? => $arm:expr),* $(,)? }) => {
+        match $crate::Symbol::as_str($symbol) {
+            $($pattern $(if $guard)? => $arm,)*
+        }
+    };
+}
@@ -3,5 +3,5 @@ mod table;
 
 pub use self::{
     raw::{InvalidTableKey, NextValue, RawTable},
-    table::{Table, TableInner, TableState},
+    table::{ArrayIter, Table, TableInner, TableMode, TableState},
 };
@@ -1,11 +1,20 @@
-use std::{fmt, hash::Hash, i64, mem};
+use std::{
+    cell::Cell,
+    fmt,
+    hash::{Hash, Hasher},
+    i64, mem,
+};
 
 use allocator_api2::vec;
-use gc_arena::{allocator_api::MetricsAlloc, Collect, Gc, Mutation};
+use gc_arena::{allocator_api::MetricsAlloc, Collect, Collection, Finalization, Gc, Mutation};
 use hashbrown::{hash_map, HashMap};
 use thiserror::Error;
 
-use crate::{Callback, Closure, Function, String, Table, Thread, UserData, Value};
+use crate::{
+    value::f64_to_i64_exact, Callback, Closure, Function, String, Table, Thread, UserData, Value,
+};
+
+use super::table::TableMode;
 
 #[derive(Debug, Copy, Clone, Error)]
 pub enum InvalidTableKey {
@@ -32,6 +41,21 @@ pub struct RawTable<'gc> {
     map: HashMap<Key<'gc>, Value<'gc>, (), MetricsAlloc<'gc>>,
     #[collect(require_static)]
     hash_builder: ahash::random_state::RandomState,
+    // What is known about the result of `RawTable::length`, kept up to date by every write.
+    #[collect(require_static)]
+    length: Cell<Length>,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Length {
+    // The positive integer keys of the table are exactly `1..=n`, so `n` is the only border. This
+    // survives pushing and popping elements, so `#t` is constant time for sequences.
+    Sequence(i64),
+    // The border found by the last search. The search only depends on which positive integer keys
+    // are present and on the size of the array part, so this stays valid until one of those
+    // changes, and the result is always the same as searching again.
+    Border(i64),
+    Unknown,
 }
 
 impl<'gc> fmt::Debug for RawTable<'gc> {
@@ -73,6 +97,7 @@ impl<'gc> RawTable<'gc> {
             array,
             map,
             hash_builder,
+            length: Cell::new(Length::Sequence(0)),
         }
     }
 
@@ -102,6 +127,28 @@ impl<'gc> RawTable<'gc> {
         &mut self,
         key: Value<'gc>,
         value: Value<'gc>,
+    ) -> Result<Value<'gc>, InvalidTableKey> {
+        let old = self.set_value(key, value)?;
+        if old.is_nil() != value.is_nil() {
+            if let Ok(CanonicalKey::Integer(i)) = CanonicalKey::new(key) {
+                if i > 0 {
+                    self.length.set(match self.length.get() {
+                        Length::Sequence(n) if value.is_nil() && i == n => Length::Sequence(n - 1),
+                        Length::Sequence(n) if !value.is_nil() && Some(i) == n.checked_add(1) => {
+                            Length::Sequence(i)
+                        }
+                        _ => Length::Unknown,
+                    });
+                }
+            }
+        }
+        Ok(old)
+    }
+
+    fn set_value(
+        &mut self,
+        key: Value<'gc>,
+        value: Value<'gc>,
     ) -> Result<Value<'gc>, InvalidTableKey> {
         // If the key is an array candidate and less than the current length of the array, it will
         // go there.
@@ -134,6 +181,24 @@ impl<'gc> RawTable<'gc> {
             );
         }
 
+        // Appending to a sequence is by far the most common way for the array part to grow, so if
+        // the key is just past the end of a full array part, double the array directly rather than
+        // going through the map part and waiting for a rehash to move the new elements over.
+        if let Some(index) = index_key {
+            if index == self.array.len()
+                && self.array.last().map_or(true, |v| !v.is_nil())
+                && self
+                    .map
+                    .raw_entry()
+                    .from_hash(hash, |k| k.eq(table_key))
+                    .is_none()
+            {
+                self.grow_array(self.array.len().max(1));
+                self.array[index] = value;
+                return Ok(Value::Nil);
+            }
+        }
+
         // If there is an existing entry in the map part, replace it, otherwise try to fit a new
         // entry.
         let raw_map = self.map.raw_table_mut();
@@ -152,88 +217,136 @@ impl<'gc> RawTable<'gc> {
         }
 
         // If a new element does not fit in either the array or map part of the table, we need to
-        // grow.
-
-        if let Some(index_key) = index_key {
-            // We have an array-candidate key, so we'd like to grow the array and place it there,
-            // if possible.
+        // grow. Rehash first, which may make room for an array-candidate key in the array part.
+        self.rehash(index_key);
+        if let Some(index) = index_key {
+            if index < self.array.len() {
+                // If the value is non-nil, it should have been replaced in the map part without
+                // needing to grow.
+                debug_assert!(self.array[index].is_nil());
+                self.array[index] = value;
+                return Ok(Value::Nil);
+            }
+        }
 
-            // First, we find the total count of array candidate elements across the array part, the
-            // map part, and the newly inserted key.
+        // Otherwise the key goes in the map part. We explicitly double the size of the map if it
+        // is still full.
+        if let Err(entry) = self
+            .map
+            .raw_table_mut()
+            .try_insert_no_grow(hash, (Key::Live(table_key), value))
+        {
+            self.grow_map(self.map.len().max(1));
+            self.map
+                .raw_table_mut()
+                .try_insert_no_grow(hash, entry)
+                .unwrap();
+        }
 
-            const USIZE_BITS: usize = mem::size_of::<usize>() * 8;
+        Ok(Value::Nil)
+    }
 
-            // Count of array-candidate elements based on the highest bit in the index
-            let mut array_counts = [0; USIZE_BITS];
-            // Total count of all array-candidate elements
-            let mut array_total = 0;
+    // Grow the array part to the largest power of two size that would be more than half full,
+    // counting both the existing array-candidate keys and `new_key`, like `rehash` in PUC-Rio Lua.
+    // This moves every such key out of the map part, and never shrinks the array part.
+    fn rehash(&mut self, new_key: Option<usize>) {
+        const USIZE_BITS: usize = mem::size_of::<usize>() * 8;
+
+        // Count of array-candidate elements based on the highest bit in the index
+        let mut array_counts = [0; USIZE_BITS];
+        // Total count of all array-candidate elements
+        let mut array_total = 0;
+
+        for (i, e) in self.array.iter().enumerate() {
+            if !e.is_nil() {
+                array_counts[highest_bit(i)] += 1;
+                array_total += 1;
+            }
+        }
 
-            for (i, e) in self.array.iter().enumerate() {
-                if !e.is_nil() {
+        for (&key, &value) in &self.map {
+            if !value.is_nil() {
+                if let Some(i) = to_array_index(
+                    key.live_key()
+                        .expect("dead keys must have Nil values")
+                        .to_value(),
+                ) {
                     array_counts[highest_bit(i)] += 1;
                     array_total += 1;
                 }
             }
+        }
 
-            for (&key, &value) in &self.map {
-                if !value.is_nil() {
-                    if let Some(i) = to_array_index(
-                        key.live_key()
-                            .expect("dead keys must have Nil values")
-                            .to_value(),
-                    ) {
-                        array_counts[highest_bit(i)] += 1;
-                        array_total += 1;
-                    }
-                }
-            }
-
-            array_counts[highest_bit(index_key)] += 1;
+        if let Some(i) = new_key {
+            array_counts[highest_bit(i)] += 1;
             array_total += 1;
+        }
 
-            // Then, we compute the new optimal size for the array by finding the largest array size
-            // such that at least half of the elements in the array would be in use.
-
-            let mut optimal_size = 0;
-            let mut total = 0;
-            for i in 0..USIZE_BITS {
-                if (1 << i) / 2 >= array_total {
-                    break;
-                }
+        // Then, we compute the new optimal size for the array by finding the largest array size
+        // such that at least half of the elements in the array would be in use.
 
-                if array_counts[i] > 0 {
-                    total += array_counts[i];
-                    if total > (1 << i) / 2 {
-                        optimal_size = 1 << i;
-                    }
-                }
+        let mut optimal_size = 0;
+        let mut total = 0;
+        for i in 0..USIZE_BITS {
+            if (1 << i) / 2 >= array_total {
+                break;
             }
 
-            // If we can fit our new key in an optimally sized array, resize the array and do that.
-            if optimal_size > index_key {
-                self.grow_array(optimal_size - self.array.len());
-                // If the value is non-nil, it should have been replaced in the map part without
-                // needing to grow growing.
-                debug_assert!(self.array[index_key].is_nil());
-                self.array[index_key] = value;
-                return Ok(Value::Nil);
+            if array_counts[i] > 0 {
+                total += array_counts[i];
+                if total > (1 << i) / 2 {
+                    optimal_size = 1 << i;
+                }
             }
         }
 
-        // If we can't grow the array, we need to grow the map and place the key there. We
-        // explicitly double the size of the map.
-        self.reserve_map(self.map.len().max(1));
+        if optimal_size > self.array.len() {
+            self.grow_array(optimal_size - self.array.len());
+        }
+    }
 
-        // Now we can insert the new key value pair
-        self.map
-            .raw_table_mut()
-            .try_insert_no_grow(hash, (Key::Live(table_key), value))
-            .unwrap();
+    /// An estimate of the number of bytes which setting `key` to a non-nil value would allocate to
+    /// grow the table, zero if the key already has room.
+    pub fn growth_size(&self, key: Value<'gc>) -> usize {
+        let index_key = to_array_index(key);
+        if index_key.is_some_and(|i| i < self.array.len()) {
+            return 0;
+        }
 
-        Ok(Value::Nil)
+        let Ok(table_key) = CanonicalKey::new(key) else {
+            return 0;
+        };
+        let hash = self.hash_builder.hash_one(table_key);
+        let in_map = self
+            .map
+            .raw_entry()
+            .from_hash(hash, |k| k.eq(table_key))
+            .is_some();
+
+        if index_key == Some(self.array.len()) && !in_map {
+            // Appending doubles the array part directly, see `RawTable::set`.
+            self.array.len().max(1) * mem::size_of::<Value<'gc>>()
+        } else if in_map || self.map.len() < self.map.capacity() {
+            0
+        } else {
+            // Otherwise the table is rehashed, which at most doubles both parts.
+            self.array.len() * mem::size_of::<Value<'gc>>()
+                + self.map.capacity().max(1) * mem::size_of::<(Key<'gc>, Value<'gc>)>()
+        }
     }
 
     pub fn length(&self) -> i64 {
+        match self.length.get() {
+            Length::Sequence(n) | Length::Border(n) => n,
+            Length::Unknown => {
+                let border = self.find_border();
+                self.length.set(Length::Border(border));
+                border
+            }
+        }
+    }
+
+    fn find_border(&self) -> i64 {
         // Binary search for a border. Entry at max must be Nil, min must be 0 or entry at min must
         // be != Nil.
         fn binary_search<F: Fn(i64) -> bool>(mut min: i64, mut max: i64, is_nil: F) -> i64 {
@@ -391,6 +504,13 @@ impl<'gc> RawTable<'gc> {
         &self.array
     }
 
+    /// The approximate number of bytes allocated for the array and map parts of the table.
+    pub fn allocated_size(&self) -> usize {
+        // Every map bucket also has a one byte control tag.
+        self.array.capacity() * mem::size_of::<Value<'gc>>()
+            + self.map.capacity() * (mem::size_of::<(Key<'gc>, Value<'gc>)>() + 1)
+    }
+
     /// Return the array part of the table as a mutable slice.
     ///
     /// All values that *can* be stored in the array part of the table will always be stored
@@ -398,6 +518,7 @@ impl<'gc> RawTable<'gc> {
     /// table is equivalent to setting the key `i + 1` in the table, and writing `Value::Nil` is
     /// equivalent to removing the key.
     pub fn array_mut(&mut self) -> &mut [Value<'gc>] {
+        self.length.set(Length::Unknown);
         &mut self.array
     }
 
@@ -432,25 +553,131 @@ impl<'gc> RawTable<'gc> {
 
             true
         });
+
+        self.reset_length();
+    }
+
+    // Check whether the table is a sequence from scratch. This looks at every entry, so it is only
+    // done when the whole table has just been gone through anyway.
+    fn reset_length(&self) {
+        let mut length = Length::Unknown;
+
+        let prefix = self
+            .array
+            .iter()
+            .position(|v| v.is_nil())
+            .unwrap_or(self.array.len());
+        if self.array[prefix..].iter().all(|v| v.is_nil()) {
+            let prefix: i64 = prefix.try_into().unwrap();
+
+            // Integer keys in the map part are all past the end of the array part, so they
+            // continue the sequence exactly when they are the keys right after it.
+            let mut count = 0;
+            let mut max = prefix;
+            for (key, value) in &self.map {
+                if let (Key::Live(CanonicalKey::Integer(i)), false) = (key, value.is_nil()) {
+                    if *i > 0 {
+                        count += 1;
+                        max = max.max(*i);
+                    }
+                }
+            }
+
+            if max - prefix == count {
+                length = Length::Sequence(max);
+            }
+        }
+
+        self.length.set(length);
+    }
+
+    // Trace this table, skipping any weakly held keys or values.
+    pub(crate) fn trace_with_mode(&self, cc: &Collection, mode: TableMode) {
+        if !mode.is_weak() {
+            return self.trace(cc);
+        }
+
+        for &value in &self.array {
+            if !(mode.weak_values && is_weak_object(value)) {
+                value.trace(cc);
+            }
+        }
+
+        for (key, value) in &self.map {
+            if let Key::Live(key) = key {
+                if !(mode.weak_keys && is_weak_object(key.to_value())) {
+                    key.trace(cc);
+                }
+            }
+            if !(mode.weak_values && is_weak_object(*value)) {
+                value.trace(cc);
+            }
+        }
+    }
+
+    // Remove every entry with a weakly held key or value which is dead.
+    //
+    // Removed entries from the map part become dead keys, so this does not disturb any ongoing
+    // iteration.
+    pub(crate) fn clear_dead(&mut self, fc: &Finalization<'gc>, mode: TableMode) {
+        if mode.weak_values {
+            for value in self.array.iter_mut() {
+                if is_dead_object(fc, *value) {
+                    *value = Value::Nil;
+                }
+            }
+        }
+
+        let raw_table = self.map.raw_table_mut();
+        unsafe {
+            for bucket_index in 0..raw_table.buckets() {
+                if raw_table.is_bucket_full(bucket_index) {
+                    let (key, value) = raw_table.bucket(bucket_index).as_mut();
+                    let dead_key = mode.weak_keys
+                        && key
+                            .live_key()
+                            .is_some_and(|k| is_dead_object(fc, k.to_value()));
+                    let dead_value = mode.weak_values && is_dead_object(fc, *value);
+                    if dead_key || dead_value {
+                        *value = Value::Nil;
+                        if let Some(dead) = key.kill() {
+                            *key = dead;
+                        }
+                    }
+                }
+            }
+        }
+
+        self.reset_length();
     }
 
     /// Reserve space in the map part of the table for at least `additional` more elements.
+    ///
+    /// If the map part needs to grow, the table is rehashed first, which may move integer keys
+    /// from the map part into a larger array part.
     pub fn reserve_map(&mut self, additional: usize) {
         if additional > self.map.capacity() - self.map.len() {
-            // We always filter out all dead keys when growing the map.
-            self.map.retain(|_, v| !v.is_nil());
-
-            self.map.raw_table_mut().reserve(additional, |(key, _)| {
-                self.hash_builder.hash_one(
-                    key.live_key()
-                        .expect("all keys must be live when table is grown"),
-                )
-            });
+            self.rehash(None);
+            if additional > self.map.capacity() - self.map.len() {
+                self.grow_map(additional);
+            }
         }
     }
+
+    fn grow_map(&mut self, additional: usize) {
+        // We always filter out all dead keys when growing the map.
+        self.map.retain(|_, v| !v.is_nil());
+
+        self.map.raw_table_mut().reserve(additional, |(key, _)| {
+            self.hash_builder.hash_one(
+                key.live_key()
+                    .expect("all keys must be live when table is grown"),
+            )
+        });
+    }
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Collect)]
+#[derive(Debug, Copy, Clone, Collect)]
 #[collect(no_drop)]
 enum CanonicalKey<'gc> {
     Boolean(bool),
@@ -464,6 +691,43 @@ enum CanonicalKey<'gc> {
     UserData(UserData<'gc>),
 }
 
+impl<'gc> PartialEq for CanonicalKey<'gc> {
+    fn eq(&self, other: &Self) -> bool {
+        match (*self, *other) {
+            (CanonicalKey::Boolean(a), CanonicalKey::Boolean(b)) => a == b,
+            (CanonicalKey::Integer(a), CanonicalKey::Integer(b)) => a == b,
+            (CanonicalKey::Number(a), CanonicalKey::Number(b)) => a == b,
+            // String keys are almost always interned, which makes this a pointer comparison.
+            (CanonicalKey::String(a), CanonicalKey::String(b)) => a.fast_eq(b),
+            (CanonicalKey::Table(a), CanonicalKey::Table(b)) => a == b,
+            (CanonicalKey::Closure(a), CanonicalKey::Closure(b)) => a == b,
+            (CanonicalKey::Callback(a), CanonicalKey::Callback(b)) => a == b,
+            (CanonicalKey::Thread(a), CanonicalKey::Thread(b)) => a == b,
+            (CanonicalKey::UserData(a), CanonicalKey::UserData(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl<'gc> Eq for CanonicalKey<'gc> {}
+
+impl<'gc> Hash for CanonicalKey<'gc> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match *self {
+            CanonicalKey::Boolean(b) => b.hash(state),
+            CanonicalKey::Integer(i) => i.hash(state),
+            CanonicalKey::Number(n) => n.hash(state),
+            CanonicalKey::String(s) => s.hash(state),
+            CanonicalKey::Table(t) => t.hash(state),
+            CanonicalKey::Closure(c) => c.hash(state),
+            CanonicalKey::Callback(c) => c.hash(state),
+            CanonicalKey::Thread(t) => t.hash(state),
+            CanonicalKey::UserData(u) => u.hash(state),
+        }
+    }
+}
+
 impl<'gc> CanonicalKey<'gc> {
     fn new(value: Value<'gc>) -> Result<Self, InvalidTableKey> {
         Ok(match value {
@@ -475,7 +739,7 @@ impl<'gc> CanonicalKey<'gc> {
                 // to themselves when cast back to f64 are considered integer keys.
                 if n.is_nan() {
                     return Err(InvalidTableKey::IsNaN);
-                } else if let Some(i) = f64_to_i64(n) {
+                } else if let Some(i) = f64_to_i64_exact(n) {
                     CanonicalKey::Integer(i)
                 } else {
                     CanonicalKey::Number(canonical_float_bytes(n))
@@ -573,14 +837,23 @@ impl<'gc> Key<'gc> {
     }
 }
 
-// Returns the closest i64 to a given f64 such that casting the i64 back to an f64 results in an
-// equal value, if such an integer exists.
-fn f64_to_i64(n: f64) -> Option<i64> {
-    let i = n as i64;
-    if i as f64 == n {
-        Some(i)
-    } else {
-        None
+// Returns true for values which may be removed from weak tables. Strings are considered values
+// rather than objects, so like in PUC-Rio Lua they are never removed.
+fn is_weak_object(value: Value<'_>) -> bool {
+    matches!(
+        value,
+        Value::Table(_) | Value::Function(_) | Value::Thread(_) | Value::UserData(_)
+    )
+}
+
+fn is_dead_object<'gc>(fc: &Finalization<'gc>, value: Value<'gc>) -> bool {
+    match value {
+        Value::Table(t) => Gc::is_dead(fc, t.into_inner()),
+        Value::Function(Function::Closure(c)) => Gc::is_dead(fc, c.into_inner()),
+        Value::Function(Function::Callback(c)) => Gc::is_dead(fc, c.into_inner()),
+        Value::Thread(t) => Gc::is_dead(fc, t.into_inner()),
+        Value::UserData(u) => Gc::is_dead(fc, u.into_inner()),
+        _ => false,
     }
 }
 
@@ -600,7 +873,7 @@ fn canonical_float_bytes(f: f64) -> u64 {
 fn to_array_index<'gc>(key: Value<'gc>) -> Option<usize> {
     let i = match key {
         Value::Integer(i) => i,
-        Value::Number(f) => f64_to_i64(f)?,
+        Value::Number(f) => f64_to_i64_exact(f)?,
         _ => return None,
     };
 
@@ -3,9 +3,12 @@ use std::{
     i64, mem,
 };
 
-use gc_arena::{lock::RefLock, Collect, Gc, Mutation};
+use gc_arena::{lock::RefLock, Collect, Collection, Finalization, Gc, Mutation};
 
-use crate::{Context, FromValue, IntoValue, TypeError, Value};
+use crate::{
+    meta_ops::{self, MetaCall, MetaOperatorError, MetaResult},
+    tags, Context, FromValue, IntoValue, String, TypeError, Value,
+};
 
 use super::raw::{InvalidTableKey, NextValue, RawTable};
 
@@ -26,6 +29,9 @@ pub type TableInner<'gc> = RefLock<TableState<'gc>>;
 /// act in Lua code. In Lua code, operations on a table can trigger special "metamethods" in the
 /// metatable (if they are present).
 ///
+/// If the metatable has a `__mode` field containing the characters 'k' and / or 'v' at the time it
+/// is set with [`Table::set_metatable`], then the table is "weak" (see [`TableMode`]).
+///
 /// On the Rust side, all methods on `Table` are "raw", which in Lua jargon means that they
 /// never trigger metamethods. This MUST be true, because `piccolo` does not (and cannot)
 /// silently trigger running Lua code. In order to trigger metamethods, you must use the
@@ -51,21 +57,27 @@ impl<'gc> Hash for Table<'gc> {
 
 impl<'gc> Table<'gc> {
     pub fn new(mc: &Mutation<'gc>) -> Table<'gc> {
-        Self::from_parts(mc, RawTable::new(mc), None)
+        Self(Gc::new(
+            mc,
+            RefLock::new(TableState::new(RawTable::new(mc), None)),
+        ))
     }
 
+    /// Create a table from a raw table and a metatable.
+    ///
+    /// This requires a `Context` rather than a `&Mutation`, because the metatable is set with
+    /// [`Table::set_metatable`] so that its `__mode` field is respected.
     pub fn from_parts(
-        mc: &Mutation<'gc>,
+        ctx: Context<'gc>,
         raw_table: RawTable<'gc>,
         metatable: Option<Table<'gc>>,
     ) -> Table<'gc> {
-        Self(Gc::new(
-            mc,
-            RefLock::new(TableState {
-                raw_table,
-                metatable,
-            }),
-        ))
+        let table = Self(Gc::new(
+            &ctx,
+            RefLock::new(TableState::new(raw_table, None)),
+        ));
+        table.set_metatable(ctx, metatable);
+        table
     }
 
     pub fn from_inner(inner: Gc<'gc, TableInner<'gc>>) -> Self {
@@ -97,6 +109,35 @@ impl<'gc> Table<'gc> {
         self.get_raw(key.into_value(ctx))
     }
 
+    /// Get a value from this table exactly like indexing it in Lua would, including any `__index`
+    /// metamethods.
+    ///
+    /// Chains of `__index` tables are followed directly, so the value of a field inherited from a
+    /// "class" table is returned without running any Lua code. If an `__index` function must be
+    /// called to produce the value, the call is returned instead, which can be run with an
+    /// [`Executor`](crate::Executor). See [`meta_ops::index_chain`].
+    pub fn get_with_meta<K: IntoValue<'gc>>(
+        self,
+        ctx: Context<'gc>,
+        key: K,
+    ) -> Result<MetaResult<'gc, 2>, MetaOperatorError> {
+        meta_ops::index_chain(ctx, self.into(), key.into_value(ctx))
+    }
+
+    /// Set a value in this table exactly like assigning to it in Lua would, including any
+    /// `__newindex` metamethods.
+    ///
+    /// Returns a call if a `__newindex` function must be run to set the value, see
+    /// [`Table::get_with_meta`] and [`meta_ops::new_index_chain`].
+    pub fn set_with_meta<K: IntoValue<'gc>, V: IntoValue<'gc>>(
+        self,
+        ctx: Context<'gc>,
+        key: K,
+        value: V,
+    ) -> Result<Option<MetaCall<'gc, 3>>, MetaOperatorError> {
+        meta_ops::new_index_chain(ctx, self.into(), key.into_value(ctx), value.into_value(ctx))
+    }
+
     /// A convenience method over [`Table::set`] for setting a string field of a table.
     ///
     /// It behaves exactly the same as [`Table::set`], except since this only accepts string keys,
@@ -136,6 +177,11 @@ impl<'gc> Table<'gc> {
         self.0.borrow().raw_table.length()
     }
 
+    /// See [`RawTable::growth_size`].
+    pub fn growth_size(self, key: Value<'gc>) -> usize {
+        self.0.borrow().raw_table.growth_size(key)
+    }
+
     /// Returns the next value after this key in the table order.
     ///
     /// The table order in the map portion of the table is defined by the incidental order of the
@@ -157,16 +203,136 @@ impl<'gc> Table<'gc> {
         Iter::new(self)
     }
 
+    /// Iterate over the sequence portion of the table, starting at index 1 and stopping before the
+    /// first `nil` value.
+    ///
+    /// This visits exactly the same pairs as `ipairs` would for a table without metamethods. Like
+    /// [`Table::get_raw`], any `__index` metamethod is ignored.
+    pub fn iter_array(self) -> ArrayIter<'gc> {
+        ArrayIter::new(self)
+    }
+
     pub fn metatable(self) -> Option<Table<'gc>> {
         self.0.borrow().metatable
     }
 
+    /// Returns the `__name` field of this table's metatable, if it is set to a string.
+    ///
+    /// This is the name used for the table by `tostring`.
+    pub fn name(self) -> Option<String<'gc>> {
+        self.metatable()?.name_field()
+    }
+
+    // Searching the table avoids needing a `Context` to intern the key. Metatables are generally
+    // small and this is only used for display purposes.
+    pub(crate) fn name_field(self) -> Option<String<'gc>> {
+        self.iter().find_map(|(key, value)| match (key, value) {
+            (Value::String(key), Value::String(name)) if key == b"__name" => Some(name),
+            _ => None,
+        })
+    }
+
+    /// Set the metatable for this table, returning the previous metatable.
+    ///
+    /// The `__mode` field of the metatable is read *once* here to determine the [`TableMode`] of
+    /// this table, changing `__mode` afterwards has no effect until the metatable is set again.
+    ///
+    /// This requires a `Context` rather than a `&Mutation`, since reading `__mode` needs an interned
+    /// key and a weak table must be registered so that its dead entries are cleared.
     pub fn set_metatable(
         self,
-        mc: &Mutation<'gc>,
+        ctx: Context<'gc>,
         metatable: Option<Table<'gc>>,
     ) -> Option<Table<'gc>> {
-        mem::replace(&mut self.0.borrow_mut(mc).metatable, metatable)
+        let mode = metatable
+            .map(|mt| TableMode::from_metatable(ctx, mt))
+            .unwrap_or_default();
+
+        let mut state = self.0.borrow_mut(&ctx);
+        if mode.is_weak() && !state.mode.is_weak() {
+            ctx.finalizers().register_weak_table(&ctx, self.0);
+        }
+        state.mode = mode;
+        mem::replace(&mut state.metatable, metatable)
+    }
+
+    /// Create a new table with a host-defined tag, see [`Table::set_tag`].
+    pub fn new_tagged(ctx: Context<'gc>, tag: u32) -> Table<'gc> {
+        let table = Table::new(&ctx);
+        table.set_tag(ctx, tag);
+        table
+    }
+
+    /// Returns the tag set with [`Table::set_tag`].
+    pub fn tag(self) -> Option<u32> {
+        self.0.borrow().tag
+    }
+
+    /// Attach a host-defined tag to this table, replacing any previous tag.
+    ///
+    /// Tagged objects are counted by [`memory_by_tag`](crate::tags::memory_by_tag), which allows a
+    /// host to attribute script memory to its own subsystems.
+    pub fn set_tag(self, ctx: Context<'gc>, tag: u32) {
+        if self.0.borrow_mut(&ctx).tag.replace(tag).is_none() {
+            tags::register_table(ctx, self.0);
+        }
+    }
+
+    /// The approximate size in bytes of this table, including the memory allocated for its array
+    /// and map parts.
+    pub fn allocated_size(self) -> usize {
+        mem::size_of::<TableInner<'gc>>() + self.0.borrow().raw_table.allocated_size()
+    }
+
+    /// Returns the weakness of this table, determined by the `__mode` field of its metatable.
+    pub fn mode(self) -> TableMode {
+        self.0.borrow().mode
+    }
+
+    // Clear every entry in a weak table which references a dead object. Returns false if the table
+    // is no longer weak.
+    //
+    // Must be called during finalization, *after* the arena has been fully marked.
+    pub(crate) fn clear_dead_weak_entries(self, fc: &Finalization<'gc>) -> bool {
+        let mut state = self.0.borrow_mut(fc);
+        let mode = state.mode;
+        if mode.is_weak() {
+            state.raw_table.clear_dead(fc, mode);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The weakness of a [`Table`], set by the `__mode` field of its metatable.
+///
+/// A weak reference does not keep its referent alive. Once a table, function, thread or userdata
+/// is only weakly reachable, any entry in a weak table which references it is removed when the
+/// object is collected. Strings are values rather than objects for this purpose, and are never
+/// removed from weak tables.
+///
+/// Weak keys are not ephemerons: a value in a table with weak keys is always strongly held, even
+/// if it references its own key.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct TableMode {
+    pub weak_keys: bool,
+    pub weak_values: bool,
+}
+
+impl TableMode {
+    pub fn from_metatable<'gc>(ctx: Context<'gc>, metatable: Table<'gc>) -> Self {
+        match metatable.get_value(ctx, "__mode") {
+            Value::String(mode) => TableMode {
+                weak_keys: mode.as_bytes().contains(&b'k'),
+                weak_values: mode.as_bytes().contains(&b'v'),
+            },
+            _ => TableMode::default(),
+        }
+    }
+
+    pub fn is_weak(self) -> bool {
+        self.weak_keys || self.weak_values
     }
 }
 
@@ -200,6 +366,36 @@ impl<'gc> Iterator for Iter<'gc> {
     }
 }
 
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub struct ArrayIter<'gc> {
+    table: Table<'gc>,
+    next: Option<i64>,
+}
+
+impl<'gc> ArrayIter<'gc> {
+    pub fn new(table: Table<'gc>) -> Self {
+        Self {
+            table,
+            next: Some(1),
+        }
+    }
+}
+
+impl<'gc> Iterator for ArrayIter<'gc> {
+    type Item = (i64, Value<'gc>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.next.take()?;
+        let value = self.table.get_raw(Value::Integer(index));
+        if value.is_nil() {
+            return None;
+        }
+        self.next = index.checked_add(1);
+        Some((index, value))
+    }
+}
+
 impl<'gc> IntoIterator for Table<'gc> {
     type Item = (Value<'gc>, Value<'gc>);
     type IntoIter = Iter<'gc>;
@@ -209,9 +405,34 @@ impl<'gc> IntoIterator for Table<'gc> {
     }
 }
 
-#[derive(Debug, Collect)]
-#[collect(no_drop)]
+#[derive(Debug)]
 pub struct TableState<'gc> {
     pub raw_table: RawTable<'gc>,
     pub metatable: Option<Table<'gc>>,
+    mode: TableMode,
+    tag: Option<u32>,
+}
+
+impl<'gc> TableState<'gc> {
+    /// Create the state of an untagged table.
+    ///
+    /// The `__mode` field of `metatable` is *not* read, so the table is never weak. Use
+    /// [`Table::from_parts`] or [`Table::set_metatable`] to create a weak table.
+    pub fn new(raw_table: RawTable<'gc>, metatable: Option<Table<'gc>>) -> Self {
+        TableState {
+            raw_table,
+            metatable,
+            mode: TableMode::default(),
+            tag: None,
+        }
+    }
+}
+
+unsafe impl<'gc> Collect for TableState<'gc> {
+    fn trace(&self, cc: &Collection) {
+        // SAFETY: A table only becomes weak when it is registered with `Finalizers`, which clears
+        // any entries referencing dead objects before they can be freed.
+        self.metatable.trace(cc);
+        self.raw_table.trace_with_mode(cc, self.mode);
+    }
 }
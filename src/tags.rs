@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+
+use gc_arena::{lock::RefLock, Collect, Gc, GcWeak, Mutation, Rootable};
+
+use crate::{table::TableInner, userdata::UserDataInner, Context, Singleton, Table, UserData};
+
+/// The memory used by every live object with a single tag, see [`memory_by_tag`].
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct TagMemory {
+    /// The number of live tagged objects.
+    pub objects: usize,
+    /// The approximate number of bytes used by the objects, including the memory allocated for
+    /// table contents.
+    ///
+    /// Memory used by the values within an object, such as strings or other tables, is not
+    /// included.
+    pub bytes: usize,
+}
+
+/// Returns the memory used by all live tagged tables and userdata, grouped by tag.
+///
+/// Tags are attached to objects with [`Table::set_tag`] and [`UserData::set_tag`], and allow a host
+/// to attribute script memory to its own subsystems. Untagged objects are not included.
+///
+/// Objects which are unreachable but have not been collected yet are still counted, so for an
+/// accurate report perform a full collection first with [`Lua::gc_collect`].
+///
+/// [`Lua::gc_collect`]: crate::Lua::gc_collect
+pub fn memory_by_tag<'gc>(ctx: Context<'gc>) -> BTreeMap<u32, TagMemory> {
+    let mut memory = BTreeMap::<u32, TagMemory>::new();
+    let mut state = TaggedObjects::get(ctx).0.borrow_mut(&ctx);
+    state.objects.retain(|object| {
+        let (tag, bytes) = match *object {
+            TaggedObject::Table(ptr) => {
+                let Some(ptr) = ptr.upgrade(&ctx) else {
+                    return false;
+                };
+                let table = Table::from_inner(ptr);
+                (table.tag(), table.allocated_size())
+            }
+            TaggedObject::UserData(ptr) => {
+                let Some(ptr) = ptr.upgrade(&ctx) else {
+                    return false;
+                };
+                let userdata = UserData::from_inner(ptr);
+                (userdata.tag(), userdata.allocation_size())
+            }
+        };
+
+        // Objects are only registered once they have a tag, and tags cannot be removed.
+        let tag = tag.unwrap();
+        let entry = memory.entry(tag).or_default();
+        entry.objects += 1;
+        entry.bytes += bytes;
+        true
+    });
+    memory
+}
+
+pub(crate) fn register_table<'gc>(ctx: Context<'gc>, ptr: Gc<'gc, TableInner<'gc>>) {
+    TaggedObjects::get(ctx).register(&ctx, TaggedObject::Table(Gc::downgrade(ptr)));
+}
+
+pub(crate) fn register_userdata<'gc>(ctx: Context<'gc>, ptr: Gc<'gc, UserDataInner<'gc>>) {
+    TaggedObjects::get(ctx).register(&ctx, TaggedObject::UserData(Gc::downgrade(ptr)));
+}
+
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+struct TaggedObjects<'gc>(Gc<'gc, RefLock<TaggedState<'gc>>>);
+
+#[derive(Collect)]
+#[collect(no_drop)]
+struct TaggedState<'gc> {
+    objects: Vec<TaggedObject<'gc>>,
+    // Dead objects are removed whenever the list grows past this length.
+    prune_len: usize,
+}
+
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+enum TaggedObject<'gc> {
+    Table(GcWeak<'gc, TableInner<'gc>>),
+    UserData(GcWeak<'gc, UserDataInner<'gc>>),
+}
+
+impl<'gc> Singleton<'gc> for TaggedObjects<'gc> {
+    fn create(ctx: Context<'gc>) -> Self {
+        TaggedObjects(Gc::new(
+            &ctx,
+            RefLock::new(TaggedState {
+                objects: Vec::new(),
+                prune_len: Self::MIN_PRUNE_LEN,
+            }),
+        ))
+    }
+}
+
+impl<'gc> TaggedObjects<'gc> {
+    const MIN_PRUNE_LEN: usize = 64;
+
+    fn get(ctx: Context<'gc>) -> Self {
+        *ctx.singleton::<Rootable![TaggedObjects<'_>]>()
+    }
+
+    fn register(self, mc: &Mutation<'gc>, object: TaggedObject<'gc>) {
+        let mut state = self.0.borrow_mut(mc);
+        if state.objects.len() >= state.prune_len {
+            state.objects.retain(|object| match *object {
+                TaggedObject::Table(ptr) => ptr.upgrade(mc).is_some(),
+                TaggedObject::UserData(ptr) => ptr.upgrade(mc).is_some(),
+            });
+            state.prune_len = (state.objects.len() * 2).max(Self::MIN_PRUNE_LEN);
+        }
+        state.objects.push(object);
+    }
+}
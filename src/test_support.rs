@@ -0,0 +1,29 @@
+use std::cell::Cell;
+
+use gc_arena::Collect;
+
+/// State for the `test-support` feature which must be reachable from within the arena.
+#[derive(Debug, Default, Collect)]
+#[collect(require_static)]
+pub(crate) struct TestSupport {
+    // The total allocation of the arena past which the next Lua instruction fails.
+    fail_allocations_at: Cell<Option<usize>>,
+}
+
+impl TestSupport {
+    pub(crate) fn set_allocation_limit(&self, limit: Option<usize>) {
+        self.fail_allocations_at.set(limit);
+    }
+
+    // Returns true if the allocation limit has been exceeded, in which case the limit is also
+    // cleared so that only a single failure is injected.
+    pub(crate) fn take_allocation_failure(&self, total_allocation: usize) -> bool {
+        match self.fail_allocations_at.get() {
+            Some(limit) if total_allocation > limit => {
+                self.fail_allocations_at.set(None);
+                true
+            }
+            _ => false,
+        }
+    }
+}
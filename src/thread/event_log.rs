@@ -0,0 +1,188 @@
+use std::collections::VecDeque;
+
+use gc_arena::Gc;
+
+use super::thread::{Frame, Thread};
+
+/// The kind of a frame on the call stack of a [`Thread`], as recorded in an [`ExecutorEvent`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FrameKind {
+    /// A running Lua function.
+    Lua,
+    /// A running [`Sequence`](crate::Sequence).
+    Sequence,
+    /// A function call in a thread which has not been resumed yet.
+    Start,
+    /// A callback which has been queued but not called yet.
+    Callback,
+    /// The thread has yielded.
+    Yielded,
+    /// The thread was suspended by [`Thread::suspend_external`].
+    Preempted,
+    /// The thread is waiting for a thread above it to finish.
+    WaitThread,
+    /// The thread has finished and its results have not been taken yet.
+    Result,
+    /// An error is unwinding.
+    Error,
+    /// An error message handler is running.
+    MessageHandler,
+}
+
+impl FrameKind {
+    pub(super) fn of(frame: &Frame<'_>) -> Self {
+        match frame {
+            Frame::Lua { .. } => FrameKind::Lua,
+            Frame::Sequence { .. } => FrameKind::Sequence,
+            Frame::Start(_) => FrameKind::Start,
+            Frame::Callback { .. } => FrameKind::Callback,
+            Frame::Yielded => FrameKind::Yielded,
+            Frame::Preempted => FrameKind::Preempted,
+            Frame::WaitThread => FrameKind::WaitThread,
+            Frame::Result { .. } => FrameKind::Result,
+            Frame::Error(_) => FrameKind::Error,
+            Frame::MessageHandler { .. } => FrameKind::MessageHandler,
+        }
+    }
+}
+
+/// A single state transition of an [`Executor`](crate::Executor), recorded while its event log
+/// is enabled with [`Executor::set_event_log`](crate::Executor::set_event_log).
+///
+/// Threads are identified by their depth in the executor's stack of running threads, where the
+/// main thread has depth 1.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExecutorEvent {
+    /// A frame was pushed onto the top thread.
+    PushFrame { thread: usize, frame: FrameKind },
+    /// A frame was popped from the top thread.
+    PopFrame { thread: usize, frame: FrameKind },
+    /// A frame was removed from the top thread by an unwinding error.
+    Unwind { thread: usize, frame: FrameKind },
+    /// A different thread is now at the top of the thread stack.
+    SwitchThread { from: usize, to: usize },
+    /// A call to [`Executor::step`](crate::Executor::step) returned, with whether the executor has
+    /// finished running.
+    Step { finished: bool },
+}
+
+/// A bounded buffer of the most recent [`ExecutorEvent`]s of an
+/// [`Executor`](crate::Executor).
+///
+/// Events are not recorded as they happen, instead the call stack of the top thread is compared
+/// after every step of the executor's internal loop. A frame which is popped and replaced by a
+/// frame of the same kind during a single step, such as a Lua function making a tail call, does
+/// not show up in the log.
+#[derive(Debug, Clone)]
+pub struct EventLog {
+    events: VecDeque<ExecutorEvent>,
+    capacity: usize,
+    dropped: u64,
+    last: Option<Snapshot>,
+}
+
+impl EventLog {
+    pub(super) fn new(capacity: usize) -> Self {
+        Self {
+            events: VecDeque::with_capacity(capacity.min(1024)),
+            capacity,
+            dropped: 0,
+            last: None,
+        }
+    }
+
+    /// The maximum number of events kept, older events are dropped first.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of events which were dropped to stay within the capacity.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// The recorded events, from oldest to newest.
+    pub fn events(&self) -> impl Iterator<Item = ExecutorEvent> + '_ {
+        self.events.iter().copied()
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+        self.dropped = 0;
+    }
+
+    // Record every change to the thread stack since the last call.
+    pub(super) fn record(&mut self, thread_stack: &[Thread<'_>]) {
+        let now = Snapshot::new(thread_stack);
+        if let Some(last) = self.last.take() {
+            if last.thread != now.thread {
+                self.push(ExecutorEvent::SwitchThread {
+                    from: last.depth,
+                    to: now.depth,
+                });
+            } else {
+                let common = last
+                    .frames
+                    .iter()
+                    .zip(&now.frames)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                let unwinding = last.frames.last() == Some(&FrameKind::Error);
+                for &frame in last.frames[common..].iter().rev() {
+                    self.push(if unwinding && frame != FrameKind::Error {
+                        ExecutorEvent::Unwind {
+                            thread: now.depth,
+                            frame,
+                        }
+                    } else {
+                        ExecutorEvent::PopFrame {
+                            thread: now.depth,
+                            frame,
+                        }
+                    });
+                }
+                for &frame in &now.frames[common..] {
+                    self.push(ExecutorEvent::PushFrame {
+                        thread: now.depth,
+                        frame,
+                    });
+                }
+            }
+        }
+        self.last = Some(now);
+    }
+
+    pub(super) fn push(&mut self, event: ExecutorEvent) {
+        if self.capacity == 0 {
+            self.dropped += 1;
+            return;
+        }
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+            self.dropped += 1;
+        }
+        self.events.push_back(event);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Snapshot {
+    // The address of the top thread, only used for comparison.
+    thread: usize,
+    depth: usize,
+    frames: Vec<FrameKind>,
+}
+
+impl Snapshot {
+    fn new(thread_stack: &[Thread<'_>]) -> Self {
+        let top = thread_stack.last();
+        Self {
+            thread: top.map_or(0, |thread| Gc::as_ptr(thread.into_inner()) as usize),
+            depth: thread_stack.len(),
+            frames: top
+                .and_then(|thread| thread.into_inner().state.try_borrow().ok())
+                .map(|state| state.frames.iter().map(FrameKind::of).collect())
+                .unwrap_or_default(),
+        }
+    }
+}
@@ -1,4 +1,11 @@
-use std::hash::{Hash, Hasher};
+use std::{
+    cell::RefMut,
+    fmt,
+    hash::{Hash, Hasher},
+    string::String as StdString,
+    task::Waker,
+    time::Instant,
+};
 
 use allocator_api2::vec;
 use gc_arena::{allocator_api::MetricsAlloc, lock::RefLock, Collect, Gc, Mutation};
@@ -6,14 +13,20 @@ use thiserror::Error;
 
 use crate::{
     compiler::{FunctionRef, LineNumber},
+    memory::LimitCheck,
+    opcode::Operation,
+    profile::{FunctionStats, Profile, ProfileCost},
     thread::BadThreadMode,
-    CallbackReturn, Context, Error, FromMultiValue, Fuel, Function, IntoMultiValue, SequencePoll,
-    Stack, String, Thread, ThreadMode, Variadic,
+    CallbackReturn, Closure, Context, Error, ErrorBoundary, FromMultiValue, Fuel, Function,
+    IntoMultiValue, IntoValue, SequencePoll, Stack, String, Thread, ThreadMode, Variadic,
 };
 
+#[cfg(feature = "event-log")]
+use super::event_log::{EventLog, ExecutorEvent};
 use super::{
     thread::{Frame, LuaFrame, ThreadState},
     vm::run_vm,
+    VMError,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -26,7 +39,8 @@ pub enum ExecutorMode {
     /// There is an active thread in the `ThreadMode::Normal` state and it is can be run with
     /// `Executor::step`.
     Normal,
-    /// The main thread has yielded and is waiting on being resumed.
+    /// The main thread has yielded and is waiting on being resumed, or the currently running thread
+    /// was suspended by `Thread::suspend_external`.
     Suspended,
     /// The `Executor` is currently inside its own `Executor::step` function.
     Running,
@@ -39,10 +53,129 @@ pub struct BadExecutorMode {
     pub expected: ExecutorMode,
 }
 
+/// Counters of the work done by an [`Executor`], see [`Executor::metrics`].
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ExecutionMetrics {
+    /// The number of VM instructions run.
+    pub instructions: u64,
+    /// The number of times a callback was called.
+    pub callbacks: u64,
+    /// The number of times a sequence was polled, including polls which handle an error.
+    pub sequence_polls: u64,
+    /// The number of times the running thread changed, by resuming a coroutine, or by a coroutine
+    /// yielding, returning or erroring back to the thread which resumed it.
+    pub thread_switches: u64,
+    /// The largest number of frames of any one running thread.
+    pub peak_call_depth: usize,
+    /// The largest number of values on the stack of any one running thread.
+    pub peak_stack_size: usize,
+}
+
 #[derive(Debug, Collect)]
 #[collect(no_drop)]
 pub struct ExecutorState<'gc> {
     thread_stack: vec::Vec<Thread<'gc>, MetricsAlloc<'gc>>,
+    error_handler: Option<ErrorHandler>,
+    // The Lua frames that the currently unwinding error has passed through, only recorded when
+    // there is an error handler.
+    traceback: vec::Vec<TracebackFrame<'gc>, MetricsAlloc<'gc>>,
+    // Set when profiling is enabled with `Executor::set_profiling`.
+    #[collect(require_static)]
+    profile: Option<Profile>,
+    #[collect(require_static)]
+    metrics: ExecutionMetrics,
+    // Passed to sequences waiting on external futures, set with `Executor::set_waker`.
+    #[collect(require_static)]
+    waker: Option<Waker>,
+    // Set when the running thread was suspended by a sequence returning `SequencePoll::Suspend`,
+    // and cleared when it is resumed.
+    awaiting_external: bool,
+    fuel_handler: Option<FuelHandler>,
+    // An error returned by the fuel handler, raised in the top thread at the next safe point.
+    raise_error: Option<Error<'gc>>,
+    // Set when the event log is enabled with `Executor::set_event_log`.
+    #[cfg(feature = "event-log")]
+    #[collect(require_static)]
+    event_log: Option<EventLog>,
+}
+
+#[cfg(feature = "event-log")]
+impl<'gc> ExecutorState<'gc> {
+    fn record_events(&mut self) {
+        if let Some(log) = &mut self.event_log {
+            log.record(&self.thread_stack);
+        }
+    }
+}
+
+/// What an [`Executor`] should do when it runs out of fuel, returned by the handler set with
+/// [`Executor::set_fuel_handler`].
+pub enum OutOfFuel<'gc> {
+    /// Return from [`Executor::step`] as if there were no handler.
+    Yield,
+    /// Keep running. The handler must have added fuel (or moved the deadline), otherwise this is the
+    /// same as `OutOfFuel::Yield`.
+    Continue,
+    /// Return from [`Executor::step`], and raise the given error in the running thread before any
+    /// more of it runs on the next step.
+    ///
+    /// The error unwinds like any other Lua error, so it can be caught by `pcall`. To stop a script
+    /// that keeps catching the error, return `OutOfFuel::Yield` instead and stop the `Executor`.
+    Error(Error<'gc>),
+}
+
+#[derive(Collect)]
+#[collect(require_static)]
+struct FuelHandler(Box<dyn for<'gc> FnMut(Context<'gc>, &mut Fuel) -> OutOfFuel<'gc>>);
+
+impl fmt::Debug for FuelHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("FuelHandler").finish_non_exhaustive()
+    }
+}
+
+/// An error which reached the bottom of an [`Executor`]'s main thread without being caught, passed
+/// to the handler set with [`Executor::set_error_handler`].
+pub struct UncaughtError<'gc, 'a> {
+    pub error: &'a Error<'gc>,
+    /// The Lua functions that the error unwound through, innermost first.
+    pub traceback: &'a [TracebackFrame<'gc>],
+    /// The thread that the error was raised in.
+    pub thread: Thread<'gc>,
+}
+
+/// A Lua function that an uncaught error unwound through.
+#[derive(Debug, Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub struct TracebackFrame<'gc> {
+    pub thread: Thread<'gc>,
+    pub chunk_name: String<'gc>,
+    pub function: FunctionRef<String<'gc>>,
+    pub line: LineNumber,
+}
+
+impl<'gc> fmt::Display for TracebackFrame<'gc> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: in {}",
+            self.chunk_name.display_lossy(),
+            self.line,
+            self.function
+                .as_string_ref()
+                .map_strings(|s| s.display_lossy())
+        )
+    }
+}
+
+#[derive(Collect)]
+#[collect(require_static)]
+struct ErrorHandler(Box<dyn for<'gc> Fn(Context<'gc>, UncaughtError<'gc, '_>)>);
+
+impl fmt::Debug for ErrorHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ErrorHandler").finish_non_exhaustive()
+    }
 }
 
 pub type ExecutorInner<'gc> = RefLock<ExecutorState<'gc>>;
@@ -53,17 +186,35 @@ pub type ExecutorInner<'gc> = RefLock<ExecutorState<'gc>>;
 /// control back and forth. All Lua code that is run is done so directly or indirectly by calling
 /// [`Executor::step`].
 ///
-/// # Panics
+/// # Errors and panics
+///
+/// `Executor` is not meant to be used from within any kind of Lua callback, and `Executor`s should
+/// not be nested. Instead, use the normal mechanisms for callbacks to call Lua code so that
+/// everything is run by the same `Executor` which called the callback.
+///
+/// Misuse of this kind is reported as an error wherever possible rather than panicking. The
+/// following return an error:
+///
+///   - Calling [`Executor::step`], [`Executor::reset`], [`Executor::try_stop`], or
+///     [`Executor::try_restart`] on an `Executor` from within a callback that it is running. This
+///     is reported as a [`BadThreadMode`] error with a `found` mode of [`ThreadMode::Running`].
+///   - Stepping an `Executor` whose threads have had their state changed externally, for example
+///     by running the same `Thread` in two `Executor`s at once, or by calling
+///     [`Thread::take_result`] or [`Thread::reset`] on a thread that an `Executor` is running.
+///     These are reported by `Executor::step` as a [`BadThreadMode`] error, and leave the
+///     `Executor` in a state where it can be safely stopped or reset.
+///   - [`Executor::take_result`], [`Executor::resume`], and [`Executor::resume_err`] when the
+///     `Executor` is not in the expected mode (including while it is running).
+///   - Changing the configuration of an `Executor` from within a callback that it is running, such
+///     as with [`Executor::set_error_handler`] or [`Executor::set_profiling`].
+///
+/// Lua code run by an inner `Executor` which accesses an upvalue in a `Thread` that an outer
+/// `Executor` is running raises a normal Lua error, since the upvalue cannot be reached.
 ///
-/// `Executor` is dangerous to use from within any kind of Lua callback. It it not meant to be used
-/// reentrantly, and calling `Executor` methods from within a callback which it itself is running
-/// (other than `Executor::mode`) will panic. Additionally, even if an independent `Executor` is
-/// used, cross-thread upvalues can still panic when an inner `Executor` tries to change an upvalue
-/// in a `Thread` that an outer `Executor` has mutably borrowed.
+/// The following misuse will still panic:
 ///
-/// `Executor`s are not meant to be used from callbacks at all, and `Executor`s should not be
-/// nested. Instead, use the normal mechanisms for callbacks to call Lua code so that everything is
-/// run by the same `Executor` which called the callback.
+///   - Calling [`Executor::stop`] or [`Executor::restart`] from within a callback that the
+///     `Executor` is running. Use [`Executor::try_stop`] and [`Executor::try_restart`] instead.
 #[derive(Debug, Copy, Clone, Collect)]
 #[collect(no_drop)]
 pub struct Executor<'gc>(Gc<'gc, ExecutorInner<'gc>>);
@@ -103,6 +254,16 @@ impl<'gc> Executor<'gc> {
             mc,
             RefLock::new(ExecutorState {
                 thread_stack: vec::Vec::new_in(MetricsAlloc::new(mc)),
+                error_handler: None,
+                traceback: vec::Vec::new_in(MetricsAlloc::new(mc)),
+                profile: None,
+                metrics: ExecutionMetrics::default(),
+                waker: None,
+                awaiting_external: false,
+                fuel_handler: None,
+                raise_error: None,
+                #[cfg(feature = "event-log")]
+                event_log: None,
             }),
         ));
         executor.reset(mc, thread)?;
@@ -131,7 +292,11 @@ impl<'gc> Executor<'gc> {
     pub fn mode(self) -> ExecutorMode {
         if let Ok(state) = self.0.try_borrow() {
             if state.thread_stack.len() > 1 {
-                ExecutorMode::Normal
+                if state.thread_stack.last().unwrap().is_suspended_externally() {
+                    ExecutorMode::Suspended
+                } else {
+                    ExecutorMode::Normal
+                }
             } else {
                 match state.thread_stack[0].mode() {
                     ThreadMode::Stopped => ExecutorMode::Stopped,
@@ -184,20 +349,96 @@ impl<'gc> Executor<'gc> {
     /// triggered solely by Lua and likely indicates a bug in some Rust code, so this error is
     /// delivered through a separate channel than normal results and cannot be caught by Lua.
     pub fn step(self, ctx: Context<'gc>, fuel: &mut Fuel) -> Result<bool, BadThreadMode> {
-        let mut state = self.0.borrow_mut(&ctx);
-        Ok(loop {
+        self.step_limited(ctx, fuel, None)
+    }
+
+    /// Runs at most `count` VM instructions and returns exactly how many were run.
+    ///
+    /// Unlike [`Executor::step`], this is not limited by fuel. Instead, the `Executor` keeps
+    /// running until exactly `count` instructions have run, running any callbacks and sequences
+    /// and crossing Lua frame and thread boundaries as needed. Stepping through the same script
+    /// one instruction at a time therefore visits the same instructions in the same order as
+    /// running it all at once, which allows comparing the state of two executions in lockstep.
+    ///
+    /// An instruction which raises an error or calls or returns from a function is counted once it
+    /// starts; whatever it calls is then run by the following steps. Time spent in callbacks and
+    /// sequences is not counted.
+    ///
+    /// If fewer than `count` instructions ran, then either the `Executor` can make no more
+    /// progress (see [`Executor::mode`]), or a sequence is waiting on an external future.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `BadThreadMode` error in the same cases as [`Executor::step`].
+    pub fn step_instructions(self, ctx: Context<'gc>, count: u32) -> Result<u32, BadThreadMode> {
+        if count == 0 {
+            return Ok(0);
+        }
+
+        let mut fuel = Fuel::with(i32::MAX);
+        let mut remaining = count;
+        self.step_limited(ctx, &mut fuel, Some(&mut remaining))?;
+        Ok(count - remaining)
+    }
+
+    // Runs the `Executor` until it runs out of fuel, or if `instructions` is set, until that many
+    // VM instructions have run (decrementing it by the number of instructions run).
+    fn step_limited(
+        self,
+        ctx: Context<'gc>,
+        fuel: &mut Fuel,
+        mut instructions: Option<&mut u32>,
+    ) -> Result<bool, BadThreadMode> {
+        let Ok(mut state) = self.0.try_borrow_mut(&ctx) else {
+            // We are being called from within a callback that we are already running.
+            return Err(BadThreadMode {
+                found: ThreadMode::Running,
+                expected: None,
+            });
+        };
+        let waker = state.waker.clone();
+        let mut out_of_fuel = false;
+        let mut pending = false;
+
+        let finished = loop {
+            #[cfg(feature = "event-log")]
+            state.record_events();
+
+            if out_of_fuel {
+                out_of_fuel = false;
+                // Only consult the fuel handler if there is still work to do.
+                let top_thread = state.thread_stack.last().copied().unwrap();
+                let finished = match top_thread.mode() {
+                    ThreadMode::Stopped | ThreadMode::Suspended | ThreadMode::Result => {
+                        state.thread_stack.len() == 1 || top_thread.is_suspended_externally()
+                    }
+                    _ => false,
+                };
+                if !finished {
+                    let handler = state.fuel_handler.as_mut().unwrap();
+                    match (handler.0)(ctx, fuel) {
+                        OutOfFuel::Continue if fuel.should_continue() => {}
+                        OutOfFuel::Error(err) => {
+                            state.raise_error = Some(err);
+                            break false;
+                        }
+                        _ => break false,
+                    }
+                }
+            }
+
             let mut top_thread = state.thread_stack.last().copied().unwrap();
             let mut res_thread = None;
             match top_thread.mode() {
                 ThreadMode::Normal => {}
-                ThreadMode::Running => {
-                    panic!("`Executor` thread already running")
-                }
                 ThreadMode::Stopped | ThreadMode::Suspended | ThreadMode::Result
                     if state.thread_stack.len() == 1 =>
                 {
                     break true;
                 }
+                ThreadMode::Suspended if top_thread.is_suspended_externally() => {
+                    break true;
+                }
                 ThreadMode::Result => {
                     state.thread_stack.pop();
                     res_thread = Some(top_thread);
@@ -211,24 +452,34 @@ impl<'gc> Executor<'gc> {
                 }
             }
 
-            let mut top_state = top_thread.into_inner().borrow_mut(&ctx);
+            let Ok(mut top_state) = top_thread.try_state_mut(&ctx) else {
+                // The thread is being run by some other `Executor`.
+                state.thread_stack.extend(res_thread);
+                return Err(BadThreadMode {
+                    found: ThreadMode::Running,
+                    expected: None,
+                });
+            };
             let top_state = &mut *top_state;
             if let Some(res_thread) = res_thread {
                 let mode = top_state.mode();
                 if mode != ThreadMode::Waiting {
                     // Shenanigans have happened and the top thread has had its state externally
-                    // changed.
+                    // changed. Put the finished thread back so that our state is unchanged.
+                    state.thread_stack.push(res_thread);
                     return Err(BadThreadMode {
                         found: mode,
                         expected: Some(ThreadMode::Waiting),
                     });
                 }
 
-                assert!(matches!(top_state.frames.pop(), Some(Frame::WaitThread)));
-                assert_eq!(res_thread.mode(), ThreadMode::Result);
+                state.metrics.thread_switches += 1;
+
+                // A `Waiting` thread always has `Frame::WaitThread` as its top frame.
+                top_state.frames.pop();
                 // Take the results from the res_thread and return them to our top
                 // thread.
-                let mut res_state = res_thread.into_inner().borrow_mut(&ctx);
+                let mut res_state = res_thread.state_mut(&ctx);
                 match res_state.take_result() {
                     Ok(vals) => {
                         let bottom = top_state.stack.len();
@@ -242,6 +493,28 @@ impl<'gc> Executor<'gc> {
                 drop(res_state);
             }
 
+            if let Some(err) = state.raise_error.take() {
+                // An error which is already unwinding must remain the top frame, so in that case
+                // there is no need to raise another.
+                if top_state.mode() == ThreadMode::Normal
+                    && !matches!(top_state.frames.last(), Some(Frame::Error(_)))
+                {
+                    top_state.frames.push(Frame::Error(err));
+                }
+            }
+
+            if top_state.suspend_requested
+                && top_state.mode() == ThreadMode::Normal
+                && !matches!(top_state.frames.last(), Some(Frame::Error(_)))
+            {
+                // We are between instructions, so this is a safe point to honor a request from
+                // `Thread::suspend_external`. Errors are left to unwind first, since the `Error`
+                // frame must remain the top frame.
+                top_state.suspend_requested = false;
+                top_state.frames.push(Frame::Preempted);
+                break true;
+            }
+
             if top_state.mode() == ThreadMode::Normal {
                 fn do_yield<'gc>(
                     ctx: Context<'gc>,
@@ -287,19 +560,86 @@ impl<'gc> Executor<'gc> {
                     }
                 }
 
+                #[cfg(feature = "test-support")]
+                if matches!(top_state.frames.last(), Some(Frame::Lua { .. }))
+                    && ctx
+                        .test_support()
+                        .take_allocation_failure(ctx.metrics().total_allocation())
+                {
+                    top_state
+                        .frames
+                        .push(Frame::Error("not enough memory".into_value(ctx).into()));
+                }
+
+                if matches!(top_state.frames.last(), Some(Frame::Lua { .. })) {
+                    if ctx.gc_control().should_stop() {
+                        // Stop so that a requested collection happens before Lua code continues.
+                        break false;
+                    }
+                    match ctx.memory_limit().check(ctx.memory_usage()) {
+                        LimitCheck::Ok => {}
+                        // Nothing has been run yet this iteration, so we can stop here and resume
+                        // the same frame once garbage has been collected.
+                        LimitCheck::Collect => break false,
+                        LimitCheck::Exceeded => {
+                            top_state
+                                .frames
+                                .push(Frame::Error("not enough memory".into_value(ctx).into()));
+                        }
+                    }
+                }
+
+                if matches!(top_state.frames.last(), Some(Frame::Lua { .. }))
+                    && top_thread
+                        .stack_limits()
+                        .is_exceeded(top_state.frames.len(), top_state.stack.len())
+                {
+                    top_state
+                        .frames
+                        .push(Frame::Error("stack overflow".into_value(ctx).into()));
+                }
+
+                let profile_start = state.profile.is_some().then(|| {
+                    // A callback frame is always a new call, and a Lua frame is a new call if it
+                    // has not run any instructions yet.
+                    let is_call = matches!(
+                        top_state.frames.last(),
+                        Some(Frame::Callback { .. } | Frame::Lua { pc: 0, .. })
+                    );
+                    (
+                        profile_stack(&state.thread_stack, &top_state.frames),
+                        is_call,
+                        fuel.remaining(),
+                        Instant::now(),
+                    )
+                });
+                // The instructions run by a Lua frame, recorded while profiling.
+                let mut profile_instructions = None;
+
                 match top_state.frames.pop() {
                     Some(Frame::Callback { bottom, callback }) => {
-                        fuel.consume(Self::FUEL_PER_CALLBACK);
-                        match callback.call(
+                        state.metrics.callbacks += 1;
+                        let fixed_cost = callback.fuel().fixed_cost();
+                        let fuel_before = fuel.remaining();
+                        if fixed_cost.is_none() {
+                            fuel.consume(Self::FUEL_PER_CALLBACK);
+                        }
+                        let ret = callback.call(
                             ctx,
                             Execution {
                                 executor: self,
                                 fuel,
                                 threads: &state.thread_stack,
                                 upper_frames: &top_state.frames,
+                                waker: waker.as_ref(),
                             },
                             Stack::new(&mut top_state.stack, bottom),
-                        ) {
+                        );
+                        if let Some(cost) = fixed_cost {
+                            // Whatever the callback consumed itself is not counted.
+                            fuel.set_remaining(fuel_before.saturating_sub(cost));
+                        }
+                        match ret {
                             Ok(CallbackReturn::Return) => {
                                 top_state.return_to(bottom);
                             }
@@ -358,15 +698,28 @@ impl<'gc> Executor<'gc> {
                         pending_error,
                     }) => {
                         fuel.consume(Self::FUEL_PER_SEQ_STEP);
+                        state.metrics.sequence_polls += 1;
 
                         let exec = Execution {
                             executor: self,
                             fuel,
                             threads: &state.thread_stack,
                             upper_frames: &top_state.frames,
+                            waker: waker.as_ref(),
                         };
                         let poll = if let Some(err) = pending_error {
-                            sequence.error(ctx, exec, err, Stack::new(&mut top_state.stack, bottom))
+                            let poll = sequence.error(
+                                ctx,
+                                exec,
+                                err,
+                                Stack::new(&mut top_state.stack, bottom),
+                            );
+                            if poll.is_ok() {
+                                // The error has been caught.
+                                state.traceback.clear();
+                                top_state.error_handled = false;
+                            }
+                            poll
                         } else {
                             sequence.poll(ctx, exec, Stack::new(&mut top_state.stack, bottom))
                         };
@@ -378,6 +731,16 @@ impl<'gc> Executor<'gc> {
                                     sequence,
                                     pending_error: None,
                                 });
+                                pending = true;
+                            }
+                            Ok(SequencePoll::Suspend) => {
+                                top_state.frames.push(Frame::Sequence {
+                                    bottom,
+                                    sequence,
+                                    pending_error: None,
+                                });
+                                top_state.frames.push(Frame::Preempted);
+                                state.awaiting_external = true;
                             }
                             Ok(SequencePoll::Return) => {
                                 top_state.return_to(bottom);
@@ -456,7 +819,35 @@ impl<'gc> Executor<'gc> {
                             thread: top_thread,
                             fuel,
                         };
-                        match run_vm(ctx, lua_frame, Self::VM_GRANULARITY) {
+                        let max_instructions = match &instructions {
+                            Some(remaining) => (**remaining).min(Self::VM_GRANULARITY),
+                            None => Self::VM_GRANULARITY,
+                        };
+                        let mut executed = 0;
+                        let prototype = lua_frame.closure().prototype();
+                        let mut pcs = profile_start.is_some().then(Vec::new);
+                        let res = run_vm(
+                            ctx,
+                            lua_frame,
+                            max_instructions,
+                            &mut executed,
+                            pcs.as_mut(),
+                        );
+                        if let Some(remaining) = &mut instructions {
+                            **remaining -= executed;
+                        }
+                        state.metrics.instructions += u64::from(executed);
+                        if let Some(pcs) = pcs {
+                            profile_instructions = Some((prototype, pcs));
+                        }
+                        match res {
+                            // Raised as a plain string, like the memory errors raised by the
+                            // executor itself.
+                            Err(VMError::NotEnoughMemory) => {
+                                top_state
+                                    .frames
+                                    .push(Frame::Error("not enough memory".into_value(ctx).into()));
+                            }
                             Err(err) => {
                                 top_state.frames.push(Frame::Error(err.into()));
                             }
@@ -465,42 +856,233 @@ impl<'gc> Executor<'gc> {
                             }
                         }
                     }
-                    Some(Frame::Error(err)) => {
-                        match top_state
+                    Some(Frame::MessageHandler { bottom }) => {
+                        // The message handler has returned, continue unwinding with its result in
+                        // place of the original error.
+                        let error = top_state.stack.get(bottom).copied().unwrap_or_default();
+                        top_state.stack.truncate(bottom);
+                        top_state.error_handled = true;
+                        top_state.frames.push(Frame::Error(error.into()));
+                    }
+                    Some(Frame::Error(err)) if !top_state.error_handled => {
+                        // A new error has been raised. Before unwinding anything, find the nearest
+                        // sequence which catches errors and see whether it has a message handler.
+                        //
+                        // An error raised by a running message handler is handled by that same
+                        // handler again, so count how many times it is already running.
+                        let mut handler_depth = 0;
+                        let handler = top_state
                             .frames
-                            .pop()
-                            .expect("normal thread must have frame above error")
-                        {
-                            Frame::Lua { bottom, .. } => {
-                                top_state.close_upvalues(&ctx, bottom);
-                                top_state.stack.truncate(bottom);
+                            .iter()
+                            .rev()
+                            .find_map(|frame| match frame {
+                                Frame::Sequence { sequence, .. } => {
+                                    match sequence.error_boundary() {
+                                        ErrorBoundary::Transparent => None,
+                                        ErrorBoundary::Catch => Some(None),
+                                        ErrorBoundary::Handler(handler) => Some(Some(handler)),
+                                    }
+                                }
+                                Frame::MessageHandler { .. } => {
+                                    handler_depth += 1;
+                                    None
+                                }
+                                _ => None,
+                            })
+                            .flatten();
+
+                        match handler {
+                            Some(_)
+                                if handler_depth >= ctx.recursion_limits().max_handler_depth =>
+                            {
+                                top_state.error_handled = true;
+                                top_state.frames.push(Frame::Error(
+                                    "error in error handling".into_value(ctx).into(),
+                                ));
+                            }
+                            Some(handler) => {
+                                // Call the handler on top of the frames which raised the error.
+                                let bottom = top_state.stack.len();
+                                top_state.stack.push(err.to_value(ctx));
+                                top_state.frames.push(Frame::MessageHandler { bottom });
+                                top_state.push_call(bottom, handler);
+                            }
+                            None => {
+                                top_state.error_handled = true;
                                 top_state.frames.push(Frame::Error(err));
                             }
-                            Frame::Sequence {
+                        }
+                    }
+                    Some(Frame::Error(err)) => match top_state.frames.pop() {
+                        Some(Frame::Lua {
+                            bottom,
+                            closure,
+                            pc,
+                            ..
+                        }) => {
+                            if state.error_handler.is_some() {
+                                let proto = closure.prototype();
+                                // The pc has already moved past the erroring instruction.
+                                let (chunk_name, line) =
+                                    proto.opcode_location(pc.saturating_sub(1));
+                                state.traceback.push(TracebackFrame {
+                                    thread: top_thread,
+                                    chunk_name,
+                                    function: proto.reference,
+                                    line,
+                                });
+                            }
+                            top_state.close_upvalues(&ctx, bottom);
+                            top_state.stack.truncate(bottom);
+                            top_state.frames.push(Frame::Error(err));
+                        }
+                        Some(Frame::Sequence {
+                            bottom,
+                            sequence,
+                            pending_error: None,
+                        }) => {
+                            top_state.frames.push(Frame::Sequence {
                                 bottom,
                                 sequence,
-                                pending_error,
-                            } => {
-                                assert!(pending_error.is_none());
-                                top_state.frames.push(Frame::Sequence {
-                                    bottom,
-                                    sequence,
-                                    pending_error: Some(err),
-                                });
+                                pending_error: Some(err),
+                            });
+                        }
+                        Some(Frame::MessageHandler { bottom }) => {
+                            // The message handler itself raised an error, which has already been
+                            // handled by calling the handler again or replaced with "error in
+                            // error handling", so it just continues unwinding.
+                            top_state.stack.truncate(bottom);
+                            top_state.frames.push(Frame::Error(err));
+                        }
+                        frame => {
+                            // Errors can only unwind through Lua and sequence frames, any other
+                            // frame here means that the thread has been externally changed. Put
+                            // everything back the way we found it.
+                            top_state.frames.extend(frame);
+                            top_state.frames.push(Frame::Error(err));
+                            return Err(BadThreadMode {
+                                found: top_state.mode(),
+                                expected: None,
+                            });
+                        }
+                    },
+                    frame => {
+                        // `ThreadMode::Normal` threads always have a Lua, callback, sequence, or
+                        // error frame on top, so this is also only reachable through external
+                        // changes to the thread.
+                        top_state.frames.extend(frame);
+                        return Err(BadThreadMode {
+                            found: top_state.mode(),
+                            expected: Some(ThreadMode::Normal),
+                        });
+                    }
+                }
+
+                if state.thread_stack.last() != Some(&top_thread) {
+                    state.metrics.thread_switches += 1;
+                }
+                let metrics = &mut state.metrics;
+                metrics.peak_call_depth = metrics.peak_call_depth.max(top_state.frames.len());
+                metrics.peak_stack_size = metrics.peak_stack_size.max(top_state.stack.len());
+
+                if let (Some(profile), Some((stack, is_call, start_fuel, start_time))) =
+                    (&mut state.profile, profile_start)
+                {
+                    let cost = ProfileCost {
+                        fuel: start_fuel
+                            .saturating_sub(fuel.remaining())
+                            .try_into()
+                            .unwrap_or(0),
+                        time: start_time.elapsed(),
+                    };
+                    if let Some(function) = stack.last() {
+                        let mut stats = FunctionStats {
+                            calls: is_call.into(),
+                            instructions: 0,
+                            cost,
+                        };
+                        if let Some((proto, pcs)) = profile_instructions {
+                            stats.instructions = pcs.len() as u64;
+                            // Consecutive instructions are usually on the same line, so record
+                            // them together.
+                            let mut current: Option<(LineNumber, u64)> = None;
+                            for pc in pcs {
+                                let line = proto.opcode_line_number(pc);
+                                match &mut current {
+                                    Some((current_line, count)) if *current_line == line => {
+                                        *count += 1;
+                                    }
+                                    _ => {
+                                        if let Some((line, count)) = current {
+                                            profile.record_line(function, line, count);
+                                        }
+                                        current = Some((line, 1));
+                                    }
+                                }
+                            }
+                            if let Some((line, count)) = current {
+                                profile.record_line(function, line, count);
                             }
-                            frame => panic!("tried to wind through improper frame {frame:?}"),
                         }
+                        profile.record_function(function, stats);
                     }
-                    _ => panic!("tried to step invalid frame type"),
+                    profile.record(stack, cost);
+                }
+            }
+
+            if state.thread_stack.len() == 1 && state.thread_stack[0] == top_thread {
+                if let [Frame::Error(error)] = top_state.frames.as_slice() {
+                    // The error has reached the bottom of the main thread, so the executor is about
+                    // to be in `ExecutorMode::Result`.
+                    let state = &mut *state;
+                    if let Some(handler) = &state.error_handler {
+                        let thread = state
+                            .traceback
+                            .first()
+                            .map(|frame| frame.thread)
+                            .unwrap_or(top_thread);
+                        (handler.0)(
+                            ctx,
+                            UncaughtError {
+                                error,
+                                traceback: &state.traceback,
+                                thread,
+                            },
+                        );
+                    }
+                    state.traceback.clear();
+                }
+            }
+
+            if let Some(remaining) = &instructions {
+                // A pending sequence would otherwise be polled forever without running any
+                // instructions.
+                if **remaining == 0 || pending {
+                    break false;
                 }
             }
 
             fuel.consume(Self::FUEL_PER_STEP);
 
             if !fuel.should_continue() {
-                break false;
+                if fuel.is_interrupted() || state.fuel_handler.is_none() {
+                    break false;
+                }
+                // Let the top of the loop check whether there is any work left before calling the
+                // handler.
+                out_of_fuel = true;
             }
-        })
+        };
+
+        #[cfg(feature = "event-log")]
+        {
+            state.record_events();
+            if let Some(log) = &mut state.event_log {
+                log.push(ExecutorEvent::Step { finished });
+            }
+        }
+
+        Ok(finished)
     }
 
     pub fn take_result<T: FromMultiValue<'gc>>(
@@ -526,8 +1108,14 @@ impl<'gc> Executor<'gc> {
     ) -> Result<(), BadExecutorMode> {
         let mode = self.mode();
         if mode == ExecutorMode::Suspended {
-            let state = self.0.borrow();
-            state.thread_stack[0].resume(ctx, args).unwrap();
+            let mut state = self.0.borrow_mut(&ctx);
+            state.awaiting_external = false;
+            state
+                .thread_stack
+                .last()
+                .unwrap()
+                .resume(ctx, args)
+                .unwrap();
             Ok(())
         } else {
             Err(BadExecutorMode {
@@ -540,8 +1128,14 @@ impl<'gc> Executor<'gc> {
     pub fn resume_err(self, mc: &Mutation<'gc>, error: Error<'gc>) -> Result<(), BadExecutorMode> {
         let mode = self.mode();
         if mode == ExecutorMode::Suspended {
-            let state = self.0.borrow();
-            state.thread_stack[0].resume_err(mc, error).unwrap();
+            let mut state = self.0.borrow_mut(mc);
+            state.awaiting_external = false;
+            state
+                .thread_stack
+                .last()
+                .unwrap()
+                .resume_err(mc, error)
+                .unwrap();
             Ok(())
         } else {
             Err(BadExecutorMode {
@@ -551,12 +1145,232 @@ impl<'gc> Executor<'gc> {
         }
     }
 
+    /// Set a handler which is called whenever an error reaches the bottom of the main thread without
+    /// being caught, right before the `Executor` enters [`ExecutorMode::Result`].
+    ///
+    /// This allows uncaught errors to be logged in one place rather than wherever the result of an
+    /// `Executor` is taken. The error is still returned by [`Executor::take_result`] as normal. The
+    /// handler is called from within [`Executor::step`], so the `Executor` and its threads are
+    /// still running and cannot be used by the handler. The handler is kept when the `Executor` is
+    /// stopped or reset.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`BadThreadMode`] error if the `Executor` is currently running, such as when
+    /// called from one of its own callbacks.
+    pub fn set_error_handler(
+        self,
+        mc: &Mutation<'gc>,
+        handler: impl for<'a> Fn(Context<'a>, UncaughtError<'a, '_>) + 'static,
+    ) -> Result<(), BadThreadMode> {
+        self.state_mut(mc)?.error_handler = Some(ErrorHandler(Box::new(handler)));
+        Ok(())
+    }
+
+    /// Set a handler which is called when the `Executor` runs out of fuel (or passes the deadline
+    /// of its [`Fuel`]) in the middle of [`Executor::step`] with work still left to do.
+    ///
+    /// The handler may refill the fuel and continue, return from `Executor::step` as usual, or
+    /// raise an error in the running thread to cancel a runaway script, see [`OutOfFuel`]. It is
+    /// not called when the fuel was explicitly interrupted with [`Fuel::interrupt`]. Like the
+    /// handler set with [`Executor::set_error_handler`], the handler cannot use the `Executor` and
+    /// is kept when the `Executor` is stopped or reset.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`BadThreadMode`] error if the `Executor` is currently running, such as when
+    /// called from one of its own callbacks.
+    pub fn set_fuel_handler(
+        self,
+        mc: &Mutation<'gc>,
+        handler: impl for<'a> FnMut(Context<'a>, &mut Fuel) -> OutOfFuel<'a> + 'static,
+    ) -> Result<(), BadThreadMode> {
+        self.state_mut(mc)?.fuel_handler = Some(FuelHandler(Box::new(handler)));
+        Ok(())
+    }
+
+    /// Remove the handler set with [`Executor::set_fuel_handler`].
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`BadThreadMode`] error if the `Executor` is currently running, such as when
+    /// called from one of its own callbacks.
+    pub fn clear_fuel_handler(self, mc: &Mutation<'gc>) -> Result<(), BadThreadMode> {
+        self.state_mut(mc)?.fuel_handler = None;
+        Ok(())
+    }
+
+    /// Remove the handler set with [`Executor::set_error_handler`].
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`BadThreadMode`] error if the `Executor` is currently running, such as when
+    /// called from one of its own callbacks.
+    pub fn clear_error_handler(self, mc: &Mutation<'gc>) -> Result<(), BadThreadMode> {
+        let mut state = self.state_mut(mc)?;
+        state.error_handler = None;
+        state.traceback.clear();
+        Ok(())
+    }
+
+    /// Enable or disable profiling.
+    ///
+    /// While profiling is enabled, the fuel used and time spent by every part of
+    /// [`Executor::step`] is recorded against the current call stack, and the calls and
+    /// instructions of every function are counted, see [`Profile`]. Disabling profiling discards
+    /// any data that has not been taken with [`Executor::take_profile`].
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`BadThreadMode`] error if the `Executor` is currently running, such as when
+    /// called from one of its own callbacks.
+    pub fn set_profiling(self, mc: &Mutation<'gc>, enabled: bool) -> Result<(), BadThreadMode> {
+        let mut state = self.state_mut(mc)?;
+        if enabled != state.profile.is_some() {
+            state.profile = enabled.then(Profile::new);
+        }
+        Ok(())
+    }
+
+    /// Returns true if profiling has been enabled with [`Executor::set_profiling`].
+    ///
+    /// Returns false while the `Executor` is running, since its state cannot be inspected.
+    pub fn is_profiling(self) -> bool {
+        self.0
+            .try_borrow()
+            .is_ok_and(|state| state.profile.is_some())
+    }
+
+    /// Take all of the profiling data recorded since profiling was enabled or since the last call
+    /// to `take_profile`.
+    ///
+    /// Returns `None` if profiling is not enabled.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`BadThreadMode`] error if the `Executor` is currently running, such as when
+    /// called from one of its own callbacks.
+    pub fn take_profile(self, mc: &Mutation<'gc>) -> Result<Option<Profile>, BadThreadMode> {
+        let mut state = self.state_mut(mc)?;
+        Ok(state.profile.as_mut().map(std::mem::take))
+    }
+
+    /// Returns the counters of the work this `Executor` has done since it was created or since the
+    /// last call to [`Executor::reset_metrics`].
+    ///
+    /// The counters are always kept, and are not reset when the `Executor` is reset or restarted.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`BadThreadMode`] error if the `Executor` is currently running, such as when
+    /// called from one of its own callbacks.
+    pub fn metrics(self) -> Result<ExecutionMetrics, BadThreadMode> {
+        let state = self.0.try_borrow().map_err(|_| BadThreadMode {
+            found: ThreadMode::Running,
+            expected: None,
+        })?;
+        Ok(state.metrics)
+    }
+
+    /// Reset every counter returned by [`Executor::metrics`] to zero.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`BadThreadMode`] error if the `Executor` is currently running, such as when
+    /// called from one of its own callbacks.
+    pub fn reset_metrics(self, mc: &Mutation<'gc>) -> Result<(), BadThreadMode> {
+        self.state_mut(mc)?.metrics = ExecutionMetrics::default();
+        Ok(())
+    }
+
+    /// Enable recording the state transitions of this `Executor` in an [`EventLog`] which keeps
+    /// the last `capacity` events, or disable it with `None`.
+    ///
+    /// This is meant for diagnosing problems with the executor itself or with code that drives
+    /// threads manually, the log can be retrieved with [`Executor::event_log`] after a failure.
+    /// Changing the capacity discards every recorded event.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`BadThreadMode`] error if the `Executor` is currently running, such as when
+    /// called from one of its own callbacks.
+    #[cfg(feature = "event-log")]
+    pub fn set_event_log(
+        self,
+        mc: &Mutation<'gc>,
+        capacity: Option<usize>,
+    ) -> Result<(), BadThreadMode> {
+        self.state_mut(mc)?.event_log = capacity.map(EventLog::new);
+        Ok(())
+    }
+
+    /// Returns a copy of the event log enabled with [`Executor::set_event_log`], if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`BadThreadMode`] error if the `Executor` is currently running, such as when
+    /// called from one of its own callbacks.
+    #[cfg(feature = "event-log")]
+    pub fn event_log(self) -> Result<Option<EventLog>, BadThreadMode> {
+        let state = self.0.try_borrow().map_err(|_| BadThreadMode {
+            found: ThreadMode::Running,
+            expected: None,
+        })?;
+        Ok(state.event_log.clone())
+    }
+
+    /// Set the [`Waker`] given to sequences which wait on something outside of the `Executor`,
+    /// such as futures awaited with [`AsyncSequence::await_external`].
+    ///
+    /// When such a sequence cannot make progress, the `Executor` stops in
+    /// [`ExecutorMode::Suspended`] with [`Executor::is_awaiting_external`] returning true. Once the
+    /// waker is woken, the embedder should call [`Executor::resume`] and continue stepping the
+    /// `Executor`. Without a waker, the embedder must instead resume the `Executor` periodically.
+    ///
+    /// [`Lua::execute_async`](crate::Lua::execute_async) sets this automatically.
+    ///
+    /// [`AsyncSequence::await_external`]: crate::async_callback::AsyncSequence::await_external
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`BadThreadMode`] error if the `Executor` is currently running, such as when
+    /// called from one of its own callbacks.
+    pub fn set_waker(self, mc: &Mutation<'gc>, waker: Option<Waker>) -> Result<(), BadThreadMode> {
+        self.state_mut(mc)?.waker = waker;
+        Ok(())
+    }
+
+    /// Returns true if the `Executor` is suspended because a sequence is waiting on something
+    /// outside of the `Executor`, see [`SequencePoll::Suspend`].
+    pub fn is_awaiting_external(self) -> bool {
+        match self.0.try_borrow() {
+            Ok(state) => {
+                state.awaiting_external
+                    && state.thread_stack.last().unwrap().is_suspended_externally()
+            }
+            Err(_) => false,
+        }
+    }
+
     /// Reset this `Executor` entirely, leaving it with a stopped main thread. Equivalent to
     /// creating a new executor with `Executor::new`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `Executor` or its main thread is currently running, see
+    /// [`Executor::try_stop`] for a fallible version.
     pub fn stop(self, mc: &Mutation<'gc>) {
-        let mut state = self.0.borrow_mut(mc);
+        self.try_stop(mc).unwrap()
+    }
+
+    /// A version of [`Executor::stop`] which returns an error rather than panicking if the
+    /// `Executor` or its main thread is currently running.
+    pub fn try_stop(self, mc: &Mutation<'gc>) -> Result<(), BadThreadMode> {
+        let mut state = self.state_mut(mc)?;
+        state.thread_stack[0].reset(mc)?;
         state.thread_stack.truncate(1);
-        state.thread_stack[0].reset(mc).unwrap();
+        state.traceback.clear();
+        Ok(())
     }
 
     /// Reset this `Executor` entirely and begins running the given thread.
@@ -570,33 +1384,122 @@ impl<'gc> Executor<'gc> {
                 expected: Some(ThreadMode::Normal),
             });
         }
-        let mut state = self.0.borrow_mut(mc);
+        let mut state = self.state_mut(mc)?;
         state.thread_stack.clear();
         state.thread_stack.push(thread);
+        state.traceback.clear();
+        state.awaiting_external = false;
+        state.raise_error = None;
         Ok(())
     }
 
     /// Reset this `Executor` entirely and begins running the given function, equivalent to
     /// creating a new executor with `Executor::start`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the `Executor` or its main thread is currently running, see
+    /// [`Executor::try_restart`] for a fallible version.
     pub fn restart(
         self,
         ctx: Context<'gc>,
         function: Function<'gc>,
         args: impl IntoMultiValue<'gc>,
     ) {
-        let mut state = self.0.borrow_mut(&ctx);
-        state.thread_stack.truncate(1);
-        state.thread_stack[0].reset(&ctx).unwrap();
-        state.thread_stack[0].start(ctx, function, args).unwrap();
+        self.try_restart(ctx, function, args).unwrap()
+    }
+
+    /// A version of [`Executor::restart`] which returns an error rather than panicking if the
+    /// `Executor` or its main thread is currently running.
+    pub fn try_restart(
+        self,
+        ctx: Context<'gc>,
+        function: Function<'gc>,
+        args: impl IntoMultiValue<'gc>,
+    ) -> Result<(), BadThreadMode> {
+        self.try_stop(&ctx)?;
+        let state = self.0.borrow();
+        // The main thread was just successfully reset, so it must be stopped.
+        state.thread_stack[0].start(ctx, function, args)
+    }
+
+    // Borrow the state of the `Executor` in order to change its configuration, which is not
+    // possible while it is running.
+    fn state_mut(
+        self,
+        mc: &Mutation<'gc>,
+    ) -> Result<RefMut<'gc, ExecutorState<'gc>>, BadThreadMode> {
+        self.0.try_borrow_mut(mc).map_err(|_| BadThreadMode {
+            found: ThreadMode::Running,
+            expected: None,
+        })
     }
 }
 
+// Returns the names of every frame in the running threads, outermost first, for profiling.
+fn profile_stack<'gc>(threads: &[Thread<'gc>], top_frames: &[Frame<'gc>]) -> Vec<StdString> {
+    let mut stack = Vec::new();
+    let mut push_frames = |frames: &[Frame<'gc>]| {
+        for (i, frame) in frames.iter().enumerate() {
+            match frame {
+                Frame::Lua { closure, .. } => {
+                    let proto = closure.prototype();
+                    let chunk_name = proto.chunk_name.display_lossy();
+                    stack.push(match proto.reference {
+                        FunctionRef::Named(name, line) => {
+                            format!("{} ({chunk_name}:{line})", name.display_lossy())
+                        }
+                        FunctionRef::Expression(line) => {
+                            format!("<function> ({chunk_name}:{line})")
+                        }
+                        FunctionRef::Chunk => format!("<chunk> ({chunk_name})"),
+                    });
+                }
+                Frame::Callback { .. } => {
+                    // Name callbacks called directly from Lua after the variable they were called
+                    // through.
+                    let name = match i.checked_sub(1).map(|i| &frames[i]) {
+                        Some(&Frame::Lua { closure, pc, .. }) if pc > 0 => {
+                            let proto = closure.prototype();
+                            match proto.opcodes[pc - 1].decode() {
+                                Operation::Call { func, .. } => proto
+                                    .describe_register(func, pc - 1)
+                                    .map(|name| name.name()),
+                                _ => None,
+                            }
+                        }
+                        _ => None,
+                    };
+                    stack.push(match name {
+                        Some(name) => format!("[callback] {}", name.display_lossy()),
+                        None => "[callback]".to_owned(),
+                    });
+                }
+                Frame::Sequence { .. } => stack.push("[sequence]".to_owned()),
+                _ => {}
+            }
+        }
+    };
+
+    // The top thread is already borrowed, and the threads below it are waiting on it.
+    if let Some((_, lower)) = threads.split_last() {
+        for thread in lower {
+            if let Ok(state) = thread.into_inner().state.try_borrow() {
+                push_frames(&state.frames);
+            }
+        }
+    }
+    push_frames(top_frames);
+    stack
+}
+
 /// Execution state passed to callbacks when they are run by an `Executor`.
 pub struct Execution<'gc, 'a> {
     executor: Executor<'gc>,
     fuel: &'a mut Fuel,
     threads: &'a [Thread<'gc>],
     upper_frames: &'a [Frame<'gc>],
+    waker: Option<&'a Waker>,
 }
 
 impl<'gc, 'a> Execution<'gc, 'a> {
@@ -606,9 +1509,18 @@ impl<'gc, 'a> Execution<'gc, 'a> {
             fuel: self.fuel,
             threads: self.threads,
             upper_frames: self.upper_frames,
+            waker: self.waker,
         }
     }
 
+    /// The waker set with [`Executor::set_waker`], if any.
+    ///
+    /// Sequences waiting on something outside of the `Executor` should arrange for this to be woken
+    /// once they can make progress.
+    pub fn waker(&self) -> Option<&Waker> {
+        self.waker
+    }
+
     /// The fuel parameter passed to `Executor::step`.
     pub fn fuel(&mut self) -> &mut Fuel {
         self.fuel
@@ -622,6 +1534,14 @@ impl<'gc, 'a> Execution<'gc, 'a> {
         }
     }
 
+    /// The thread that the current Executor was started with.
+    ///
+    /// This is the bottom of the stack of threads being run, and the only one which is not a
+    /// coroutine resumed by another thread.
+    pub fn main_thread(&self) -> Thread<'gc> {
+        self.threads[0]
+    }
+
     /// The curently running Executor.
     ///
     /// Do not call methods on this from callbacks! This is provided only for identification
@@ -642,16 +1562,13 @@ impl<'gc, 'a> Execution<'gc, 'a> {
         // The previously executed instruction for a callback should be the Call opcode.
         let call_opcode = *pc - 1;
 
+        let (chunk_name, current_line) = proto.opcode_location(call_opcode);
         Some(UpperLuaFrame {
-            chunk_name: proto.chunk_name,
+            chunk_name,
             current_function: proto.reference,
-            current_line: match proto
-                .opcode_line_numbers
-                .binary_search_by_key(&call_opcode, |(opi, _)| *opi)
-            {
-                Ok(i) => proto.opcode_line_numbers[i].1,
-                Err(i) => proto.opcode_line_numbers[i - 1].1,
-            },
+            current_line,
+            closure: *closure,
+            call_opcode,
         })
     }
 }
@@ -665,4 +1582,10 @@ pub struct UpperLuaFrame<'gc> {
     pub chunk_name: String<'gc>,
     pub current_function: FunctionRef<String<'gc>>,
     pub current_line: LineNumber,
+    /// The closure running in the frame.
+    pub closure: Closure<'gc>,
+    /// The index of the opcode which called the current callback, which can be used with
+    /// [`FunctionPrototype::active_locals`](crate::FunctionPrototype::active_locals) to find the
+    /// local variables in scope at the call.
+    pub call_opcode: usize,
 }
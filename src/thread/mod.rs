@@ -1,19 +1,32 @@
+#[cfg(feature = "event-log")]
+mod event_log;
 mod executor;
+mod snapshot;
 mod thread;
+mod typed;
 mod vm;
 
 use thiserror::Error;
 
-use crate::meta_ops::{MetaCallError, MetaOperatorError};
+use crate::{
+    compiler::LineNumber,
+    meta_ops::{MetaCallError, MetaOperatorError},
+};
 
+#[cfg(feature = "event-log")]
+pub use self::event_log::{EventLog, ExecutorEvent, FrameKind};
 pub use self::{
     executor::{
-        BadExecutorMode, CurrentThread, Execution, Executor, ExecutorInner, ExecutorMode,
-        UpperLuaFrame,
+        BadExecutorMode, CurrentThread, Execution, ExecutionMetrics, Executor, ExecutorInner,
+        ExecutorMode, OutOfFuel, TracebackFrame, UncaughtError, UpperLuaFrame,
     },
-    thread::{BadThreadMode, OpenUpValue, Thread, ThreadInner, ThreadMode},
+    snapshot::{SnapshotError, SnapshotHooks, SNAPSHOT_FORMAT_VERSION},
+    thread::{BadThreadMode, OpenUpValue, StackLimits, Thread, ThreadInner, ThreadMode},
+    typed::{ThreadResult, TypedThread},
 };
 
+pub(crate) use self::snapshot::{restore_fork, restore_globals, save_fork, save_globals};
+
 #[derive(Debug, Clone, Error)]
 pub enum VMError {
     #[error("{}", if *.0 {
@@ -30,8 +43,37 @@ pub enum VMError {
     OperatorError(#[from] MetaOperatorError),
     #[error("_ENV upvalue is only allowed on top-level closure")]
     BadEnvUpValue,
+    #[error("variable stack ends below the register an operation expects values from")]
+    BadVariableStack,
+    #[error("cannot access an upvalue of a thread which is currently running elsewhere")]
+    UnreachableUpValue,
     #[error("Invalid types in for loop; expected numbers, found {0}, {1}, and {2}")]
     BadForLoop(&'static str, &'static str, &'static str),
     #[error("Invalid types in for loop; expected numbers, found {0} and {1}")]
     BadForLoopPrep(&'static str, &'static str),
+    #[error("undeclared global '{name}' at {chunk_name}:{line_number}")]
+    UndeclaredGlobal {
+        name: std::string::String,
+        chunk_name: std::string::String,
+        line_number: LineNumber,
+    },
+    #[error("attempt to index a {type_name} value{}", describe_variable(.variable))]
+    BadIndex {
+        type_name: &'static str,
+        variable: Option<std::string::String>,
+    },
+    #[error("attempt to call a {type_name} value{}", describe_variable(.variable))]
+    BadCallValue {
+        type_name: &'static str,
+        variable: Option<std::string::String>,
+    },
+    #[error("not enough memory")]
+    NotEnoughMemory,
+}
+
+fn describe_variable(variable: &Option<std::string::String>) -> std::string::String {
+    match variable {
+        Some(variable) => format!(" ({variable})"),
+        None => std::string::String::new(),
+    }
 }
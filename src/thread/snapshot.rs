@@ -0,0 +1,1211 @@
+use std::collections::{hash_map, HashMap};
+
+use allocator_api2::{boxed, vec};
+use gc_arena::{allocator_api::MetricsAlloc, Gc};
+use thiserror::Error;
+
+use crate::{
+    closure::{UpValue, UpValueState},
+    compiler::{Annotation, FunctionRef, LineNumber, LocalVariable},
+    opcode::OpCode,
+    types::{RegisterIndex, UpValueDescriptor, UpValueIndex, VarCount},
+    Callback, Closure, Constant, Context, Function, FunctionPrototype, PrototypeError,
+    SourceFragment, StackLimits, String, Table, UserData, Value,
+};
+
+use super::thread::{Frame, LuaReturn, MetaReturn, OpenUpValue, Thread, ThreadMode, ThreadState};
+
+/// The version of the byte format produced by [`Thread::snapshot`].
+///
+/// Snapshots are only restored by a version of piccolo with the same format version. The format
+/// includes compiled bytecode, so this is bumped whenever
+/// [`OPCODE_FORMAT_VERSION`](crate::opcode::OPCODE_FORMAT_VERSION) is.
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 1;
+
+const MAGIC: &[u8; 8] = b"PICSNAP\0";
+
+/// Lets the embedder take part in snapshotting values that piccolo cannot serialize itself, such
+/// as userdata.
+///
+/// Every table, function, and userdata reachable from a snapshotted thread is first offered to
+/// [`SnapshotHooks::save_value`]. If it returns a key, the value is stored as only that key, and
+/// [`SnapshotHooks::restore_value`] must turn the key back into a value when restoring.
+pub trait SnapshotHooks<'gc> {
+    /// Return a key to store in place of `value`, or `None` to snapshot it normally.
+    fn save_value(&mut self, ctx: Context<'gc>, value: Value<'gc>) -> Option<Vec<u8>> {
+        let _ = (ctx, value);
+        None
+    }
+
+    /// Return the value for a key returned by [`SnapshotHooks::save_value`].
+    fn restore_value(&mut self, ctx: Context<'gc>, key: &[u8]) -> Option<Value<'gc>> {
+        let _ = (ctx, key);
+        None
+    }
+}
+
+/// Snapshot without any hooks, which fails on any userdata.
+impl<'gc> SnapshotHooks<'gc> for () {}
+
+#[derive(Debug, Clone, Error)]
+pub enum SnapshotError {
+    #[error("cannot snapshot a thread in mode {0:?}, it must be stopped or suspended")]
+    BadThreadMode(ThreadMode),
+    #[error("cannot snapshot a thread with a {0} frame")]
+    UnsupportedFrame(&'static str),
+    #[error("cannot snapshot a {0} which is not a global and is not saved by the snapshot hooks")]
+    Unsupported(&'static str),
+    #[error("snapshot hooks did not restore an external value")]
+    MissingExternal,
+    #[error("global {0:?} referenced by the snapshot does not exist")]
+    MissingGlobal(std::string::String),
+    #[error("unsupported snapshot format version {0}, expected {SNAPSHOT_FORMAT_VERSION}")]
+    Version(u32),
+    #[error("malformed snapshot: {0}")]
+    Malformed(&'static str),
+    #[error("invalid function prototype in snapshot")]
+    Prototype(#[from] PrototypeError),
+}
+
+// Object kinds, every object is written as a "shell" with one of these kinds, and tables,
+// upvalues, and threads are then filled in with a separate body.
+const OBJECT_TABLE: u8 = 0;
+const OBJECT_CLOSURE: u8 = 1;
+const OBJECT_UPVALUE: u8 = 2;
+const OBJECT_THREAD: u8 = 3;
+const OBJECT_EXTERNAL: u8 = 4;
+const OBJECT_GLOBALS: u8 = 5;
+const OBJECT_GLOBAL: u8 = 6;
+const OBJECT_EXTERNAL_TABLE: u8 = 7;
+
+const VALUE_NIL: u8 = 0;
+const VALUE_FALSE: u8 = 1;
+const VALUE_TRUE: u8 = 2;
+const VALUE_INTEGER: u8 = 3;
+const VALUE_NUMBER: u8 = 4;
+const VALUE_STRING: u8 = 5;
+const VALUE_OBJECT: u8 = 6;
+
+// What the root of a snapshot is, and how the tables of the instance it is taken from are treated.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Mode {
+    // A thread, which refers to global values by name.
+    Thread,
+    // The globals table, whose contents are set in the globals table of another instance.
+    Globals,
+    // The globals table, which the globals table of another instance with the same libraries
+    // loaded is made into a copy of. Tables which the hooks give a key for are copied into the
+    // table that the key is restored to rather than referred to, and these and the globals table
+    // are cleared before they are filled.
+    Fork,
+}
+
+const FRAME_LUA: u8 = 0;
+const FRAME_START: u8 = 1;
+const FRAME_YIELDED: u8 = 2;
+const FRAME_PREEMPTED: u8 = 3;
+
+// An object which is snapshotted by identity.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+enum Object<'gc> {
+    Table(Table<'gc>),
+    Closure(Closure<'gc>),
+    Callback(Callback<'gc>),
+    UserData(UserData<'gc>),
+    Thread(Thread<'gc>),
+    UpValue(UpValue<'gc>),
+}
+
+impl<'gc> Object<'gc> {
+    fn from_value(value: Value<'gc>) -> Option<Self> {
+        match value {
+            Value::Table(t) => Some(Object::Table(t)),
+            Value::Function(Function::Closure(c)) => Some(Object::Closure(c)),
+            Value::Function(Function::Callback(c)) => Some(Object::Callback(c)),
+            Value::UserData(u) => Some(Object::UserData(u)),
+            Value::Thread(t) => Some(Object::Thread(t)),
+            _ => None,
+        }
+    }
+
+    fn to_value(self) -> Option<Value<'gc>> {
+        match self {
+            Object::Table(t) => Some(t.into()),
+            Object::Closure(c) => Some(c.into()),
+            Object::Callback(c) => Some(c.into()),
+            Object::UserData(u) => Some(u.into()),
+            Object::Thread(_) | Object::UpValue(_) => None,
+        }
+    }
+}
+
+pub(super) fn save<'gc>(
+    ctx: Context<'gc>,
+    thread: Thread<'gc>,
+    hooks: &mut impl SnapshotHooks<'gc>,
+) -> Result<Vec<u8>, SnapshotError> {
+    save_graph(ctx, Object::Thread(thread), Mode::Thread, hooks)
+}
+
+pub(super) fn restore<'gc>(
+    ctx: Context<'gc>,
+    data: &[u8],
+    hooks: &mut impl SnapshotHooks<'gc>,
+) -> Result<Thread<'gc>, SnapshotError> {
+    match restore_graph(ctx, data, Mode::Thread, hooks)? {
+        Restored::Thread(thread) => Ok(thread),
+        _ => Err(SnapshotError::Malformed("root object is not a thread")),
+    }
+}
+
+/// Save the contents of the globals table and everything reachable from it, for restoring into the
+/// globals of another `Lua` instance with [`restore_globals`].
+///
+/// Unlike with a thread snapshot, global tables are copied rather than referred to by name, only
+/// callbacks and userdata are.
+pub(crate) fn save_globals<'gc>(
+    ctx: Context<'gc>,
+    hooks: &mut impl SnapshotHooks<'gc>,
+) -> Result<Vec<u8>, SnapshotError> {
+    save_graph(ctx, Object::Table(ctx.globals()), Mode::Globals, hooks)
+}
+
+pub(crate) fn restore_globals<'gc>(
+    ctx: Context<'gc>,
+    data: &[u8],
+    hooks: &mut impl SnapshotHooks<'gc>,
+) -> Result<(), SnapshotError> {
+    restore_globals_graph(ctx, data, Mode::Globals, hooks)
+}
+
+/// Save the globals table and everything reachable from it, for making the globals of another
+/// `Lua` instance with the same libraries loaded into a copy with [`restore_fork`].
+///
+/// The hooks give keys to the library tables, callbacks, and userdata of this instance which
+/// restore to their counterparts in the other instance. Library tables are then copied into their
+/// counterparts, so that anything else referring to them (such as the string metatable) sees the
+/// copied contents.
+pub(crate) fn save_fork<'gc>(
+    ctx: Context<'gc>,
+    hooks: &mut impl SnapshotHooks<'gc>,
+) -> Result<Vec<u8>, SnapshotError> {
+    save_graph(ctx, Object::Table(ctx.globals()), Mode::Fork, hooks)
+}
+
+pub(crate) fn restore_fork<'gc>(
+    ctx: Context<'gc>,
+    data: &[u8],
+    hooks: &mut impl SnapshotHooks<'gc>,
+) -> Result<(), SnapshotError> {
+    restore_globals_graph(ctx, data, Mode::Fork, hooks)
+}
+
+fn restore_globals_graph<'gc>(
+    ctx: Context<'gc>,
+    data: &[u8],
+    mode: Mode,
+    hooks: &mut impl SnapshotHooks<'gc>,
+) -> Result<(), SnapshotError> {
+    match restore_graph(ctx, data, mode, hooks)? {
+        Restored::Table(table) if table == ctx.globals() => Ok(()),
+        _ => Err(SnapshotError::Malformed(
+            "root object is not the globals table",
+        )),
+    }
+}
+
+fn save_graph<'gc>(
+    ctx: Context<'gc>,
+    root: Object<'gc>,
+    mode: Mode,
+    hooks: &mut impl SnapshotHooks<'gc>,
+) -> Result<Vec<u8>, SnapshotError> {
+    let mut writer = Writer {
+        ctx,
+        hooks,
+        global_paths: global_paths(ctx, mode == Mode::Thread),
+        mode,
+        ids: HashMap::new(),
+        shells: Vec::new(),
+        pending: Vec::new(),
+        bodies: Vec::new(),
+        body_count: 0,
+        prototypes: HashMap::new(),
+        prototype_data: Vec::new(),
+        prototype_count: 0,
+    };
+
+    let root = writer.object(root)?;
+    while let Some(object) = writer.pending.pop() {
+        writer.body(object)?;
+    }
+
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    write_uint(&mut out, SNAPSHOT_FORMAT_VERSION.into());
+    write_uint(&mut out, writer.prototype_count.into());
+    out.extend_from_slice(&writer.prototype_data);
+    write_len(&mut out, writer.shells.len());
+    for shell in &writer.shells {
+        out.extend_from_slice(shell);
+    }
+    write_uint(&mut out, writer.body_count.into());
+    out.extend_from_slice(&writer.bodies);
+    write_uint(&mut out, root.into());
+    Ok(out)
+}
+
+fn restore_graph<'gc>(
+    ctx: Context<'gc>,
+    data: &[u8],
+    mode: Mode,
+    hooks: &mut impl SnapshotHooks<'gc>,
+) -> Result<Restored<'gc>, SnapshotError> {
+    let mut reader = Reader { data };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(SnapshotError::Malformed("not a snapshot"));
+    }
+    let version = reader.read_u32()?;
+    if version != SNAPSHOT_FORMAT_VERSION {
+        return Err(SnapshotError::Version(version));
+    }
+
+    let mut prototypes = Vec::new();
+    for _ in 0..reader.read_len()? {
+        let prototype = reader.read_prototype(ctx, &prototypes)?;
+        prototype.validate()?;
+        prototypes.push(Gc::new(&ctx, prototype));
+    }
+
+    let mut restorer = Restorer {
+        ctx,
+        objects: Vec::new(),
+        metatables: Vec::new(),
+        filled: Vec::new(),
+        cleared: Vec::new(),
+        open_upvalues: Vec::new(),
+    };
+
+    // Closures refer to their upvalues directly, so every other object is created first.
+    let mut closures = Vec::new();
+    for id in 0..reader.read_len()? {
+        let kind = reader.read_u8()?;
+        let restored = match kind {
+            OBJECT_TABLE => Restored::Table(Table::new(&ctx)),
+            OBJECT_CLOSURE => {
+                let prototype = reader.read_index(prototypes.len())?;
+                let mut upvalues = Vec::new();
+                for _ in 0..reader.read_len()? {
+                    upvalues.push(reader.read_u32()?);
+                }
+                closures.push((id, prototypes[prototype], upvalues));
+                Restored::Value(Value::Nil)
+            }
+            OBJECT_UPVALUE => {
+                Restored::UpValue(UpValue::new(&ctx, UpValueState::Closed(Value::Nil)))
+            }
+            OBJECT_THREAD => Restored::Thread(Thread::new(ctx)),
+            OBJECT_EXTERNAL => {
+                let key = reader.read_bytes()?;
+                Restored::Value(
+                    hooks
+                        .restore_value(ctx, key)
+                        .ok_or(SnapshotError::MissingExternal)?,
+                )
+            }
+            OBJECT_EXTERNAL_TABLE if mode == Mode::Fork => {
+                let key = reader.read_bytes()?;
+                match hooks.restore_value(ctx, key) {
+                    Some(Value::Table(table)) => Restored::Table(table),
+                    Some(_) => return Err(SnapshotError::Malformed("external is not a table")),
+                    None => return Err(SnapshotError::MissingExternal),
+                }
+            }
+            OBJECT_GLOBALS => Restored::Table(ctx.globals()),
+            OBJECT_GLOBAL => {
+                let mut value = Value::Table(ctx.globals());
+                let mut path = Vec::new();
+                for _ in 0..reader.read_len()? {
+                    let name = reader.read_bytes()?;
+                    path.push(std::string::String::from_utf8_lossy(name).into_owned());
+                    value = match value {
+                        Value::Table(t) => t.get_raw(ctx.intern(name).into()),
+                        _ => Value::Nil,
+                    };
+                }
+                if value.is_nil() {
+                    return Err(SnapshotError::MissingGlobal(path.join(".")));
+                }
+                Restored::Value(value)
+            }
+            _ => return Err(SnapshotError::Malformed("unknown object kind")),
+        };
+        restorer.objects.push(restored);
+        restorer.filled.push(false);
+        restorer
+            .cleared
+            .push(mode == Mode::Fork && matches!(kind, OBJECT_GLOBALS | OBJECT_EXTERNAL_TABLE));
+        restorer.open_upvalues.push(false);
+    }
+
+    for (id, prototype, upvalue_ids) in closures {
+        if upvalue_ids.len() != prototype.upvalues.len() {
+            return Err(SnapshotError::Malformed("wrong number of closure upvalues"));
+        }
+        let mut upvalues = vec::Vec::new_in(MetricsAlloc::new(&ctx));
+        for upvalue in upvalue_ids {
+            upvalues.push(restorer.upvalue(upvalue)?);
+        }
+        restorer.objects[id] = Restored::Closure(Closure::from_parts(&ctx, prototype, upvalues));
+    }
+
+    for _ in 0..reader.read_len()? {
+        let id = reader.read_index(restorer.objects.len())?;
+        if restorer.filled[id] {
+            return Err(SnapshotError::Malformed("object restored twice"));
+        }
+        restorer.filled[id] = true;
+        match restorer.objects[id] {
+            Restored::Table(table) => {
+                if restorer.cleared[id] {
+                    let keys = table.iter().map(|(key, _)| key).collect::<Vec<_>>();
+                    for key in keys {
+                        table.set_raw(&ctx, key, Value::Nil).unwrap();
+                    }
+                    table.set_metatable(ctx, None);
+                }
+                restorer.table(&mut reader, table)?
+            }
+            Restored::UpValue(upvalue) => restorer.upvalue_body(&mut reader, id, upvalue)?,
+            Restored::Thread(thread) => restorer.thread(&mut reader, thread)?,
+            _ => return Err(SnapshotError::Malformed("object has no body")),
+        }
+    }
+
+    let root = restorer.objects[reader.read_index(restorer.objects.len())?];
+    if !reader.data.is_empty() {
+        return Err(SnapshotError::Malformed("trailing data"));
+    }
+    if restorer.open_upvalues.iter().any(|&open| open) {
+        return Err(SnapshotError::Malformed(
+            "open upvalue not owned by any thread",
+        ));
+    }
+
+    // Setting a metatable reads its `__mode` field, so this waits until every table is filled.
+    for (table, metatable) in restorer.metatables {
+        table.set_metatable(ctx, Some(metatable));
+    }
+
+    Ok(root)
+}
+
+// Find every value which is a global, or a field of a global table. These are stored by name rather
+// than by value, so that they refer to the same values in the `Lua` instance that the snapshot is
+// restored into. Tables are only included if `tables` is set, otherwise they are copied.
+fn global_paths<'gc>(ctx: Context<'gc>, tables: bool) -> HashMap<Object<'gc>, Vec<String<'gc>>> {
+    let globals = ctx.globals();
+    let mut paths = HashMap::new();
+    for (key, value) in globals {
+        let (Value::String(name), Some(object)) = (key, Object::from_value(value)) else {
+            continue;
+        };
+        if tables || !matches!(object, Object::Table(_)) {
+            paths.entry(object).or_insert_with(|| vec![name]);
+        }
+
+        if let Value::Table(table) = value {
+            if table == globals {
+                continue;
+            }
+            for (key, value) in table {
+                if let (Value::String(field), Some(object)) = (key, Object::from_value(value)) {
+                    if tables || !matches!(object, Object::Table(_)) {
+                        paths.entry(object).or_insert_with(|| vec![name, field]);
+                    }
+                }
+            }
+        }
+    }
+    paths
+}
+
+struct Writer<'gc, 'a, H> {
+    ctx: Context<'gc>,
+    hooks: &'a mut H,
+    global_paths: HashMap<Object<'gc>, Vec<String<'gc>>>,
+    mode: Mode,
+    ids: HashMap<Object<'gc>, u32>,
+    shells: Vec<Vec<u8>>,
+    // Objects whose body has yet to be written.
+    pending: Vec<Object<'gc>>,
+    bodies: Vec<u8>,
+    body_count: u32,
+    prototypes: HashMap<*const FunctionPrototype<'gc>, u32>,
+    prototype_data: Vec<u8>,
+    prototype_count: u32,
+}
+
+impl<'gc, 'a, H: SnapshotHooks<'gc>> Writer<'gc, 'a, H> {
+    // Return the id of an object, writing its shell if it has not been seen before.
+    fn object(&mut self, object: Object<'gc>) -> Result<u32, SnapshotError> {
+        let id = match self.ids.entry(object) {
+            hash_map::Entry::Occupied(entry) => return Ok(*entry.get()),
+            hash_map::Entry::Vacant(entry) => {
+                let id = self.shells.len() as u32;
+                entry.insert(id);
+                self.shells.push(Vec::new());
+                id
+            }
+        };
+
+        let mut shell = Vec::new();
+        if let Object::Thread(_) = object {
+            // Threads are always snapshotted by value, since they own the open upvalues of the
+            // closures which are part of the snapshot.
+            shell.push(OBJECT_THREAD);
+            self.pending.push(object);
+        } else if object == Object::Table(self.ctx.globals()) {
+            shell.push(OBJECT_GLOBALS);
+            if self.mode != Mode::Thread {
+                self.pending.push(object);
+            }
+        } else if let Some(key) = object
+            .to_value()
+            .and_then(|value| self.hooks.save_value(self.ctx, value))
+        {
+            if self.mode == Mode::Fork && matches!(object, Object::Table(_)) {
+                shell.push(OBJECT_EXTERNAL_TABLE);
+                self.pending.push(object);
+            } else {
+                shell.push(OBJECT_EXTERNAL);
+            }
+            write_bytes(&mut shell, &key);
+        } else if let Some(path) = self.global_paths.get(&object) {
+            shell.push(OBJECT_GLOBAL);
+            write_len(&mut shell, path.len());
+            for name in path {
+                write_bytes(&mut shell, name.as_bytes());
+            }
+        } else {
+            match object {
+                Object::Table(_) => {
+                    shell.push(OBJECT_TABLE);
+                    self.pending.push(object);
+                }
+                Object::Closure(closure) => {
+                    let prototype = self.prototype(closure.prototype());
+                    shell.push(OBJECT_CLOSURE);
+                    write_uint(&mut shell, prototype.into());
+                    write_len(&mut shell, closure.upvalues().len());
+                    for &upvalue in closure.upvalues() {
+                        write_uint(&mut shell, self.object(Object::UpValue(upvalue))?.into());
+                    }
+                }
+                Object::UpValue(_) => {
+                    shell.push(OBJECT_UPVALUE);
+                    self.pending.push(object);
+                }
+                Object::Thread(_) => unreachable!(),
+                Object::Callback(_) => return Err(SnapshotError::Unsupported("callback")),
+                Object::UserData(_) => return Err(SnapshotError::Unsupported("userdata")),
+            }
+        }
+
+        self.shells[id as usize] = shell;
+        Ok(id)
+    }
+
+    fn value(&mut self, buf: &mut Vec<u8>, value: Value<'gc>) -> Result<(), SnapshotError> {
+        match value {
+            Value::Nil => buf.push(VALUE_NIL),
+            Value::Boolean(false) => buf.push(VALUE_FALSE),
+            Value::Boolean(true) => buf.push(VALUE_TRUE),
+            Value::Integer(i) => {
+                buf.push(VALUE_INTEGER);
+                write_int(buf, i);
+            }
+            Value::Number(n) => {
+                buf.push(VALUE_NUMBER);
+                buf.extend_from_slice(&n.to_le_bytes());
+            }
+            Value::String(s) => {
+                buf.push(VALUE_STRING);
+                write_bytes(buf, s.as_bytes());
+            }
+            value => {
+                let object = Object::from_value(value).unwrap();
+                let id = self.object(object)?;
+                buf.push(VALUE_OBJECT);
+                write_uint(buf, id.into());
+            }
+        }
+        Ok(())
+    }
+
+    fn body(&mut self, object: Object<'gc>) -> Result<(), SnapshotError> {
+        let mut buf = Vec::new();
+        match object {
+            Object::Table(table) => {
+                let entries = table.iter().collect::<Vec<_>>();
+                write_len(&mut buf, entries.len());
+                for (key, value) in entries {
+                    self.value(&mut buf, key)?;
+                    self.value(&mut buf, value)?;
+                }
+                self.value(&mut buf, table.metatable().map_or(Value::Nil, Value::Table))?;
+            }
+            Object::UpValue(upvalue) => match upvalue.get() {
+                UpValueState::Open(open) => {
+                    // The stack index is written with the thread which owns the upvalue.
+                    buf.push(1);
+                    self.object(Object::Thread(open.thread(&self.ctx)))?;
+                }
+                UpValueState::Closed(value) => {
+                    buf.push(0);
+                    self.value(&mut buf, value)?;
+                }
+            },
+            Object::Thread(thread) => self.thread(&mut buf, thread)?,
+            _ => unreachable!(),
+        }
+
+        write_uint(&mut self.bodies, self.ids[&object].into());
+        self.bodies.extend_from_slice(&buf);
+        self.body_count += 1;
+        Ok(())
+    }
+
+    fn thread(&mut self, buf: &mut Vec<u8>, thread: Thread<'gc>) -> Result<(), SnapshotError> {
+        let inner = thread.into_inner();
+        let Ok(state) = inner.state.try_borrow() else {
+            return Err(SnapshotError::BadThreadMode(ThreadMode::Running));
+        };
+        match state.mode() {
+            ThreadMode::Stopped | ThreadMode::Suspended => {}
+            mode => return Err(SnapshotError::BadThreadMode(mode)),
+        }
+
+        match thread.name() {
+            Some(name) => {
+                buf.push(1);
+                write_bytes(buf, name.as_bytes());
+            }
+            None => buf.push(0),
+        }
+        let limits = thread.stack_limits();
+        write_len(buf, limits.max_frames);
+        write_len(buf, limits.max_stack);
+
+        write_len(buf, state.stack.len());
+        for &value in &state.stack {
+            self.value(buf, value)?;
+        }
+
+        write_len(buf, state.open_upvalues.len());
+        for &upvalue in &state.open_upvalues {
+            let UpValueState::Open(open) = upvalue.get() else {
+                unreachable!("closed upvalue in the open upvalue list");
+            };
+            write_uint(buf, self.object(Object::UpValue(upvalue))?.into());
+            write_len(buf, open.stack_index());
+        }
+
+        write_len(buf, state.frames.len());
+        for frame in &state.frames {
+            match *frame {
+                Frame::Lua {
+                    bottom,
+                    closure,
+                    base,
+                    is_variable,
+                    pc,
+                    stack_size,
+                    expected_return,
+                } => {
+                    buf.push(FRAME_LUA);
+                    write_uint(buf, self.object(Object::Closure(closure))?.into());
+                    write_len(buf, bottom);
+                    write_len(buf, base);
+                    buf.push(is_variable.into());
+                    write_len(buf, pc);
+                    write_len(buf, stack_size);
+                    write_expected_return(buf, expected_return);
+                }
+                Frame::Start(function) => {
+                    buf.push(FRAME_START);
+                    self.value(buf, function.into())?;
+                }
+                Frame::Yielded => buf.push(FRAME_YIELDED),
+                Frame::Preempted => buf.push(FRAME_PREEMPTED),
+                Frame::Sequence { .. } => return Err(SnapshotError::UnsupportedFrame("sequence")),
+                Frame::Callback { .. } => return Err(SnapshotError::UnsupportedFrame("callback")),
+                Frame::MessageHandler { .. } => {
+                    return Err(SnapshotError::UnsupportedFrame("message handler"))
+                }
+                Frame::WaitThread | Frame::Result { .. } | Frame::Error(_) => {
+                    unreachable!("thread is stopped or suspended")
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Prototypes are written children first, so that a prototype only refers to lower ids.
+    fn prototype(&mut self, prototype: Gc<'gc, FunctionPrototype<'gc>>) -> u32 {
+        if let Some(&id) = self.prototypes.get(&Gc::as_ptr(prototype)) {
+            return id;
+        }
+        let children = prototype
+            .prototypes
+            .iter()
+            .map(|&child| self.prototype(child))
+            .collect::<Vec<_>>();
+
+        let buf = &mut self.prototype_data;
+        write_bytes(buf, prototype.chunk_name.as_bytes());
+        match prototype.reference {
+            FunctionRef::Named(name, line) => {
+                buf.push(0);
+                write_bytes(buf, name.as_bytes());
+                write_uint(buf, line.0);
+            }
+            FunctionRef::Expression(line) => {
+                buf.push(1);
+                write_uint(buf, line.0);
+            }
+            FunctionRef::Chunk => buf.push(2),
+        }
+        buf.push(prototype.fixed_params);
+        buf.push(prototype.has_varargs.into());
+        write_uint(buf, prototype.stack_size.into());
+
+        write_len(buf, prototype.constants.len());
+        for constant in prototype.constants.iter() {
+            match *constant {
+                Constant::Nil => buf.push(0),
+                Constant::Boolean(b) => {
+                    buf.push(1);
+                    buf.push(b.into());
+                }
+                Constant::Integer(i) => {
+                    buf.push(2);
+                    write_int(buf, i);
+                }
+                Constant::Number(n) => {
+                    buf.push(3);
+                    buf.extend_from_slice(&n.to_le_bytes());
+                }
+                Constant::String(s) => {
+                    buf.push(4);
+                    write_bytes(buf, s.as_bytes());
+                }
+            }
+        }
+
+        write_len(buf, prototype.opcodes.len());
+        for opcode in prototype.opcodes.iter() {
+            write_uint(buf, opcode.to_u32().into());
+        }
+        write_len(buf, prototype.opcode_line_numbers.len());
+        for &(opcode, line) in prototype.opcode_line_numbers.iter() {
+            write_len(buf, opcode);
+            write_uint(buf, line.0);
+        }
+
+        write_len(buf, prototype.upvalues.len());
+        for upvalue in prototype.upvalues.iter() {
+            match *upvalue {
+                UpValueDescriptor::Environment => buf.push(0),
+                UpValueDescriptor::ParentLocal(register) => buf.extend_from_slice(&[1, register.0]),
+                UpValueDescriptor::Outer(index) => buf.extend_from_slice(&[2, index.0]),
+            }
+        }
+
+        write_len(buf, children.len());
+        for child in children {
+            write_uint(buf, child.into());
+        }
+
+        write_len(buf, prototype.annotations.len());
+        for annotation in prototype.annotations.iter() {
+            write_bytes(buf, annotation.tag.as_bytes());
+            write_bytes(buf, annotation.text.as_bytes());
+            write_uint(buf, annotation.line_number.0);
+        }
+        buf.push(prototype.strict_globals.into());
+        write_len(buf, prototype.fragments.len());
+        for fragment in prototype.fragments.iter() {
+            write_bytes(buf, fragment.name.as_bytes());
+            write_uint(buf, fragment.first_line.0);
+        }
+        write_len(buf, prototype.local_variables.len());
+        for local in prototype.local_variables.iter() {
+            write_bytes(buf, local.name.as_bytes());
+            buf.push(local.register.0);
+            write_len(buf, local.start_pc);
+            write_len(buf, local.end_pc);
+        }
+        write_len(buf, prototype.upvalue_names.len());
+        for name in prototype.upvalue_names.iter() {
+            write_bytes(buf, name.as_bytes());
+        }
+
+        let id = self.prototype_count;
+        self.prototype_count += 1;
+        self.prototypes.insert(Gc::as_ptr(prototype), id);
+        id
+    }
+}
+
+fn write_expected_return(buf: &mut Vec<u8>, expected_return: Option<LuaReturn>) {
+    match expected_return {
+        None => buf.push(0),
+        Some(LuaReturn::Normal(count)) => {
+            buf.push(1);
+            // 0 for a variable count, otherwise the constant count plus one.
+            write_uint(buf, count.to_constant().map_or(0, |c| u64::from(c) + 1));
+        }
+        Some(LuaReturn::Meta(MetaReturn::None)) => buf.push(2),
+        Some(LuaReturn::Meta(MetaReturn::Register(register))) => {
+            buf.extend_from_slice(&[3, register.0])
+        }
+        Some(LuaReturn::Meta(MetaReturn::SkipIf(skip_if))) => {
+            buf.extend_from_slice(&[4, skip_if.into()])
+        }
+    }
+}
+
+fn write_uint(buf: &mut Vec<u8>, mut n: u64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn write_len(buf: &mut Vec<u8>, n: usize) {
+    write_uint(buf, n as u64);
+}
+
+fn write_int(buf: &mut Vec<u8>, i: i64) {
+    write_uint(buf, ((i << 1) ^ (i >> 63)) as u64);
+}
+
+fn write_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    write_len(buf, bytes.len());
+    buf.extend_from_slice(bytes);
+}
+
+#[derive(Copy, Clone)]
+enum Restored<'gc> {
+    Table(Table<'gc>),
+    Closure(Closure<'gc>),
+    UpValue(UpValue<'gc>),
+    Thread(Thread<'gc>),
+    Value(Value<'gc>),
+}
+
+struct Restorer<'gc> {
+    ctx: Context<'gc>,
+    objects: Vec<Restored<'gc>>,
+    metatables: Vec<(Table<'gc>, Table<'gc>)>,
+    filled: Vec<bool>,
+    // Tables which already exist and are emptied before their contents are restored.
+    cleared: Vec<bool>,
+    // Upvalues which are open, and have not yet been claimed by the thread that owns them.
+    open_upvalues: Vec<bool>,
+}
+
+impl<'gc> Restorer<'gc> {
+    fn object(&self, id: u32) -> Result<Restored<'gc>, SnapshotError> {
+        self.objects
+            .get(id as usize)
+            .copied()
+            .ok_or(SnapshotError::Malformed("object id out of range"))
+    }
+
+    fn upvalue(&self, id: u32) -> Result<UpValue<'gc>, SnapshotError> {
+        match self.object(id)? {
+            Restored::UpValue(upvalue) => Ok(upvalue),
+            _ => Err(SnapshotError::Malformed("expected an upvalue")),
+        }
+    }
+
+    fn value(&self, reader: &mut Reader) -> Result<Value<'gc>, SnapshotError> {
+        Ok(match reader.read_u8()? {
+            VALUE_NIL => Value::Nil,
+            VALUE_FALSE => Value::Boolean(false),
+            VALUE_TRUE => Value::Boolean(true),
+            VALUE_INTEGER => Value::Integer(reader.read_int()?),
+            VALUE_NUMBER => Value::Number(reader.read_f64()?),
+            VALUE_STRING => Value::String(self.ctx.intern(reader.read_bytes()?)),
+            VALUE_OBJECT => match self.object(reader.read_u32()?)? {
+                Restored::Table(table) => table.into(),
+                Restored::Closure(closure) => closure.into(),
+                Restored::Thread(thread) => thread.into(),
+                Restored::Value(value) => value,
+                Restored::UpValue(_) => {
+                    return Err(SnapshotError::Malformed("upvalue used as a value"))
+                }
+            },
+            _ => return Err(SnapshotError::Malformed("unknown value kind")),
+        })
+    }
+
+    fn table(&mut self, reader: &mut Reader, table: Table<'gc>) -> Result<(), SnapshotError> {
+        for _ in 0..reader.read_len()? {
+            let key = self.value(reader)?;
+            let value = self.value(reader)?;
+            table
+                .set_raw(&self.ctx, key, value)
+                .map_err(|_| SnapshotError::Malformed("invalid table key"))?;
+        }
+        match self.value(reader)? {
+            Value::Nil => {}
+            Value::Table(metatable) => self.metatables.push((table, metatable)),
+            _ => return Err(SnapshotError::Malformed("metatable is not a table")),
+        }
+        Ok(())
+    }
+
+    fn upvalue_body(
+        &mut self,
+        reader: &mut Reader,
+        id: usize,
+        upvalue: UpValue<'gc>,
+    ) -> Result<(), SnapshotError> {
+        match reader.read_u8()? {
+            0 => upvalue.set(&self.ctx, UpValueState::Closed(self.value(reader)?)),
+            1 => self.open_upvalues[id] = true,
+            _ => return Err(SnapshotError::Malformed("unknown upvalue state")),
+        }
+        Ok(())
+    }
+
+    fn thread(&mut self, reader: &mut Reader, thread: Thread<'gc>) -> Result<(), SnapshotError> {
+        if reader.read_bool()? {
+            thread.set_name(&self.ctx, Some(self.ctx.intern(reader.read_bytes()?)));
+        }
+        thread.set_stack_limits(StackLimits {
+            max_frames: reader.read_usize()?,
+            max_stack: reader.read_usize()?,
+        });
+
+        let ctx = self.ctx;
+        let mut state = thread.state_mut(&ctx);
+        for _ in 0..reader.read_len()? {
+            state.stack.push(self.value(reader)?);
+        }
+
+        for _ in 0..reader.read_len()? {
+            let id = reader.read_u32()?;
+            let upvalue = self.upvalue(id)?;
+            let stack_index = reader.read_usize()?;
+            if !self.open_upvalues[id as usize] {
+                return Err(SnapshotError::Malformed("upvalue is not open"));
+            }
+            if stack_index >= state.stack.len() {
+                return Err(SnapshotError::Malformed("open upvalue out of stack range"));
+            }
+            if let Some(UpValueState::Open(last)) = state.open_upvalues.last().map(|u| u.get()) {
+                if last.stack_index() >= stack_index {
+                    return Err(SnapshotError::Malformed("open upvalues out of order"));
+                }
+            }
+            self.open_upvalues[id as usize] = false;
+            upvalue.set(
+                &ctx,
+                UpValueState::Open(OpenUpValue::new(thread, stack_index)),
+            );
+            state.open_upvalues.push(upvalue);
+        }
+
+        for _ in 0..reader.read_len()? {
+            let frame = match reader.read_u8()? {
+                FRAME_LUA => {
+                    let Restored::Closure(closure) = self.object(reader.read_u32()?)? else {
+                        return Err(SnapshotError::Malformed("lua frame without a closure"));
+                    };
+                    Frame::Lua {
+                        closure,
+                        bottom: reader.read_usize()?,
+                        base: reader.read_usize()?,
+                        is_variable: reader.read_bool()?,
+                        pc: reader.read_usize()?,
+                        stack_size: reader.read_usize()?,
+                        expected_return: reader.read_expected_return()?,
+                    }
+                }
+                FRAME_START => match self.value(reader)? {
+                    Value::Function(function) => Frame::Start(function),
+                    _ => return Err(SnapshotError::Malformed("start frame without a function")),
+                },
+                FRAME_YIELDED => Frame::Yielded,
+                FRAME_PREEMPTED => Frame::Preempted,
+                _ => return Err(SnapshotError::Malformed("unknown frame kind")),
+            };
+            state.frames.push(frame);
+        }
+
+        validate_frames(&state)
+    }
+}
+
+// Check enough of the restored state that running the thread cannot index outside of its stack.
+fn validate_frames(state: &ThreadState) -> Result<(), SnapshotError> {
+    match state.frames.last() {
+        None if state.stack.is_empty() => return Ok(()),
+        None => return Err(SnapshotError::Malformed("stopped thread with a stack")),
+        Some(Frame::Start(_)) => {
+            if state.frames.len() != 1 || !state.stack.is_empty() {
+                return Err(SnapshotError::Malformed("start frame on a running thread"));
+            }
+            return Ok(());
+        }
+        Some(Frame::Yielded | Frame::Preempted) => {}
+        Some(_) => return Err(SnapshotError::Malformed("thread is not suspended")),
+    }
+
+    let lua_frames = &state.frames[..state.frames.len() - 1];
+    for (i, frame) in lua_frames.iter().enumerate() {
+        let &Frame::Lua {
+            bottom,
+            closure,
+            base,
+            pc,
+            stack_size,
+            expected_return,
+            ..
+        } = frame
+        else {
+            return Err(SnapshotError::Malformed("suspended frame below the top"));
+        };
+        let prototype = closure.prototype();
+        if stack_size != usize::from(prototype.stack_size)
+            || pc > prototype.opcodes.len()
+            || bottom > base
+            || base > state.stack.len()
+        {
+            return Err(SnapshotError::Malformed("lua frame out of range"));
+        }
+        // Only the top frame may have a shrunken stack, and only while waiting on returns.
+        let is_top = i + 1 == lua_frames.len();
+        if (!is_top || expected_return.is_none()) && base + stack_size > state.stack.len() {
+            return Err(SnapshotError::Malformed("lua frame out of stack range"));
+        }
+        if !is_top && expected_return.is_none() {
+            return Err(SnapshotError::Malformed(
+                "calling lua frame expects no returns",
+            ));
+        }
+    }
+    Ok(())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if len > self.data.len() {
+            return Err(SnapshotError::Malformed("unexpected end of data"));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_bool(&mut self) -> Result<bool, SnapshotError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SnapshotError::Malformed("invalid boolean")),
+        }
+    }
+
+    fn read_uint(&mut self) -> Result<u64, SnapshotError> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_u8()?;
+            n |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(SnapshotError::Malformed("integer too long"))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, SnapshotError> {
+        u32::try_from(self.read_uint()?).map_err(|_| SnapshotError::Malformed("integer too large"))
+    }
+
+    fn read_usize(&mut self) -> Result<usize, SnapshotError> {
+        usize::try_from(self.read_uint()?)
+            .map_err(|_| SnapshotError::Malformed("integer too large"))
+    }
+
+    // A count of items which each take at least one byte, so that a corrupt count cannot cause
+    // huge allocations.
+    fn read_len(&mut self) -> Result<usize, SnapshotError> {
+        let len = self.read_usize()?;
+        if len > self.data.len() {
+            return Err(SnapshotError::Malformed("length out of range"));
+        }
+        Ok(len)
+    }
+
+    fn read_index(&mut self, count: usize) -> Result<usize, SnapshotError> {
+        let index = self.read_usize()?;
+        if index >= count {
+            return Err(SnapshotError::Malformed("index out of range"));
+        }
+        Ok(index)
+    }
+
+    fn read_int(&mut self) -> Result<i64, SnapshotError> {
+        let n = self.read_uint()?;
+        Ok((n >> 1) as i64 ^ -((n & 1) as i64))
+    }
+
+    fn read_f64(&mut self) -> Result<f64, SnapshotError> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn read_bytes(&mut self) -> Result<&'a [u8], SnapshotError> {
+        let len = self.read_len()?;
+        self.take(len)
+    }
+
+    fn read_line(&mut self) -> Result<LineNumber, SnapshotError> {
+        Ok(LineNumber(self.read_uint()?))
+    }
+
+    fn read_expected_return(&mut self) -> Result<Option<LuaReturn>, SnapshotError> {
+        Ok(match self.read_u8()? {
+            0 => None,
+            1 => Some(LuaReturn::Normal(match self.read_uint()? {
+                0 => VarCount::variable(),
+                c => u8::try_from(c - 1)
+                    .ok()
+                    .and_then(VarCount::try_constant)
+                    .ok_or(SnapshotError::Malformed("invalid return count"))?,
+            })),
+            2 => Some(LuaReturn::Meta(MetaReturn::None)),
+            3 => Some(LuaReturn::Meta(MetaReturn::Register(RegisterIndex(
+                self.read_u8()?,
+            )))),
+            4 => Some(LuaReturn::Meta(MetaReturn::SkipIf(self.read_bool()?))),
+            _ => return Err(SnapshotError::Malformed("unknown return kind")),
+        })
+    }
+
+    fn read_list<'gc, T>(
+        &mut self,
+        ctx: Context<'gc>,
+        mut read: impl FnMut(&mut Self) -> Result<T, SnapshotError>,
+    ) -> Result<boxed::Box<[T], MetricsAlloc<'gc>>, SnapshotError> {
+        let len = self.read_len()?;
+        let mut list = vec::Vec::with_capacity_in(len, MetricsAlloc::new(&ctx));
+        for _ in 0..len {
+            list.push(read(self)?);
+        }
+        Ok(list.into_boxed_slice())
+    }
+
+    fn read_prototype<'gc>(
+        &mut self,
+        ctx: Context<'gc>,
+        prototypes: &[Gc<'gc, FunctionPrototype<'gc>>],
+    ) -> Result<FunctionPrototype<'gc>, SnapshotError> {
+        let string = |r: &mut Self| -> Result<String<'gc>, SnapshotError> {
+            Ok(ctx.intern(r.read_bytes()?))
+        };
+
+        let chunk_name = string(self)?;
+        let reference = match self.read_u8()? {
+            0 => FunctionRef::Named(string(self)?, self.read_line()?),
+            1 => FunctionRef::Expression(self.read_line()?),
+            2 => FunctionRef::Chunk,
+            _ => return Err(SnapshotError::Malformed("unknown function reference")),
+        };
+        let fixed_params = self.read_u8()?;
+        let has_varargs = self.read_bool()?;
+        let stack_size = u16::try_from(self.read_uint()?)
+            .map_err(|_| SnapshotError::Malformed("stack size too large"))?;
+
+        let constants = self.read_list(ctx, |r| {
+            Ok(match r.read_u8()? {
+                0 => Constant::Nil,
+                1 => Constant::Boolean(r.read_bool()?),
+                2 => Constant::Integer(r.read_int()?),
+                3 => Constant::Number(r.read_f64()?),
+                4 => Constant::String(string(r)?),
+                _ => return Err(SnapshotError::Malformed("unknown constant kind")),
+            })
+        })?;
+        let opcodes = self.read_list(ctx, |r| {
+            OpCode::from_u32(r.read_u32()?).map_err(|_| SnapshotError::Malformed("invalid opcode"))
+        })?;
+        let opcode_line_numbers = self.read_list(ctx, |r| Ok((r.read_usize()?, r.read_line()?)))?;
+        let upvalues = self.read_list(ctx, |r| {
+            Ok(match r.read_u8()? {
+                0 => UpValueDescriptor::Environment,
+                1 => UpValueDescriptor::ParentLocal(RegisterIndex(r.read_u8()?)),
+                2 => UpValueDescriptor::Outer(UpValueIndex(r.read_u8()?)),
+                _ => return Err(SnapshotError::Malformed("unknown upvalue descriptor")),
+            })
+        })?;
+        let children = self.read_list(ctx, |r| Ok(prototypes[r.read_index(prototypes.len())?]))?;
+        let annotations = self.read_list(ctx, |r| {
+            Ok(Annotation {
+                tag: string(r)?,
+                text: string(r)?,
+                line_number: r.read_line()?,
+            })
+        })?;
+        let strict_globals = self.read_bool()?;
+        let fragments = self.read_list(ctx, |r| {
+            Ok(SourceFragment {
+                name: string(r)?,
+                first_line: r.read_line()?,
+            })
+        })?;
+        let local_variables = self.read_list(ctx, |r| {
+            Ok(LocalVariable {
+                name: string(r)?,
+                register: RegisterIndex(r.read_u8()?),
+                start_pc: r.read_usize()?,
+                end_pc: r.read_usize()?,
+            })
+        })?;
+        let upvalue_names = self.read_list(ctx, string)?;
+
+        Ok(FunctionPrototype {
+            chunk_name,
+            reference,
+            fixed_params,
+            has_varargs,
+            stack_size,
+            constants,
+            opcodes,
+            opcode_line_numbers,
+            upvalues,
+            prototypes: children,
+            annotations,
+            strict_globals,
+            fragments,
+            local_variables,
+            upvalue_names,
+        })
+    }
+}
@@ -1,11 +1,14 @@
 use std::{
-    cell::RefMut,
+    cell::{BorrowMutError, Cell, RefMut},
     hash::{Hash, Hasher},
 };
 
 use allocator_api2::vec;
 use gc_arena::{
-    allocator_api::MetricsAlloc, lock::RefLock, Collect, Finalization, Gc, GcWeak, Mutation,
+    allocator_api::MetricsAlloc,
+    barrier,
+    lock::{Lock, RefLock},
+    Collect, Finalization, Gc, GcWeak, Mutation,
 };
 use thiserror::Error;
 
@@ -18,7 +21,10 @@ use crate::{
     String, Table, UserData, Value,
 };
 
-use super::VMError;
+use super::{
+    snapshot::{self, SnapshotError, SnapshotHooks},
+    VMError,
+};
 
 /// The current state of a [`Thread`].
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -49,7 +55,54 @@ pub struct BadThreadMode {
     pub expected: Option<ThreadMode>,
 }
 
-pub type ThreadInner<'gc> = RefLock<ThreadState<'gc>>;
+/// Limits on the size of the call stack of a [`Thread`].
+///
+/// Piccolo does not use the Rust stack to call Lua functions, so without a limit unbounded
+/// recursion would grow a thread until memory is exhausted. Once a thread has more frames or
+/// stack values than its limits allow, the running function raises a "stack overflow" error which
+/// can be caught like any other error.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StackLimits {
+    /// The maximum number of frames, which is roughly the number of nested function calls.
+    pub max_frames: usize,
+    /// The maximum number of values on the stack, shared by the registers and arguments of every
+    /// running function.
+    pub max_stack: usize,
+}
+
+impl StackLimits {
+    /// The default limits, which are similar to those of PUC-Rio Lua.
+    pub const DEFAULT: StackLimits = StackLimits {
+        max_frames: 200_000,
+        max_stack: 1_000_000,
+    };
+
+    pub const UNLIMITED: StackLimits = StackLimits {
+        max_frames: usize::MAX,
+        max_stack: usize::MAX,
+    };
+
+    pub fn is_exceeded(&self, frames: usize, stack: usize) -> bool {
+        frames > self.max_frames || stack > self.max_stack
+    }
+}
+
+impl Default for StackLimits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+#[derive(Debug, Collect)]
+#[collect(no_drop)]
+pub struct ThreadInner<'gc> {
+    pub(super) state: RefLock<ThreadState<'gc>>,
+    // Metadata is kept outside of `state` so that it is available while the thread is running.
+    name: Lock<Option<String<'gc>>>,
+    parent: Option<GcWeak<'gc, ThreadInner<'gc>>>,
+    #[collect(require_static)]
+    stack_limits: Cell<StackLimits>,
+}
 
 /// A Lua coroutine.
 ///
@@ -57,7 +110,7 @@ pub type ThreadInner<'gc> = RefLock<ThreadState<'gc>>;
 /// `Thread`s, suspend them, resume them, and may yield to calling `Thread`s.
 #[derive(Debug, Clone, Copy, Collect)]
 #[collect(no_drop)]
-pub struct Thread<'gc>(Gc<'gc, RefLock<ThreadState<'gc>>>);
+pub struct Thread<'gc>(Gc<'gc, ThreadInner<'gc>>);
 
 impl<'gc> PartialEq for Thread<'gc> {
     fn eq(&self, other: &Thread<'gc>) -> bool {
@@ -75,13 +128,32 @@ impl<'gc> Hash for Thread<'gc> {
 
 impl<'gc> Thread<'gc> {
     pub fn new(ctx: Context<'gc>) -> Thread<'gc> {
+        Self::new_inner(ctx, None)
+    }
+
+    /// Create a new thread which records `parent` as the thread that created it.
+    ///
+    /// The parent is held weakly, and is only used for introspection. The new thread starts with
+    /// the same [`StackLimits`] as its parent.
+    pub fn with_parent(ctx: Context<'gc>, parent: Thread<'gc>) -> Thread<'gc> {
+        Self::new_inner(ctx, Some(parent))
+    }
+
+    fn new_inner(ctx: Context<'gc>, parent: Option<Thread<'gc>>) -> Thread<'gc> {
         let p = Gc::new(
             &ctx,
-            RefLock::new(ThreadState {
-                frames: vec::Vec::new_in(MetricsAlloc::new(&ctx)),
-                stack: vec::Vec::new_in(MetricsAlloc::new(&ctx)),
-                open_upvalues: vec::Vec::new_in(MetricsAlloc::new(&ctx)),
-            }),
+            ThreadInner {
+                state: RefLock::new(ThreadState {
+                    frames: vec::Vec::new_in(MetricsAlloc::new(&ctx)),
+                    stack: vec::Vec::new_in(MetricsAlloc::new(&ctx)),
+                    open_upvalues: vec::Vec::new_in(MetricsAlloc::new(&ctx)),
+                    suspend_requested: false,
+                    error_handled: false,
+                }),
+                name: Lock::new(None),
+                parent: parent.map(|p| Gc::downgrade(p.0)),
+                stack_limits: Cell::new(parent.map_or(StackLimits::DEFAULT, |p| p.stack_limits())),
+            },
         );
         ctx.finalizers().register_thread(&ctx, p);
         Thread(p)
@@ -95,8 +167,60 @@ impl<'gc> Thread<'gc> {
         self.0
     }
 
+    // The state is behind a `RefLock` within the thread, so mutably borrowing it requires a write
+    // barrier on the whole thread.
+    pub(super) fn try_state_mut(
+        self,
+        mc: &Mutation<'gc>,
+    ) -> Result<RefMut<'gc, ThreadState<'gc>>, BorrowMutError> {
+        barrier::field!(Gc::write(mc, self.0), ThreadInner, state)
+            .unlock()
+            .try_borrow_mut()
+    }
+
+    pub(super) fn state_mut(self, mc: &Mutation<'gc>) -> RefMut<'gc, ThreadState<'gc>> {
+        barrier::field!(Gc::write(mc, self.0), ThreadInner, state)
+            .unlock()
+            .borrow_mut()
+    }
+
+    /// The name given to this thread with [`Thread::set_name`], if any.
+    ///
+    /// Unlike most methods on `Thread`, this is available even while the thread is running.
+    pub fn name(self) -> Option<String<'gc>> {
+        self.0.name.get()
+    }
+
+    /// Attach a name to this thread, which is shown when the thread is displayed.
+    pub fn set_name(self, mc: &Mutation<'gc>, name: Option<String<'gc>>) {
+        barrier::field!(Gc::write(mc, self.0), ThreadInner, name)
+            .unlock()
+            .set(name);
+    }
+
+    /// The thread which created this thread, if it was created with [`Thread::with_parent`] and
+    /// the parent thread is still alive.
+    pub fn parent(self, mc: &Mutation<'gc>) -> Option<Thread<'gc>> {
+        self.0.parent.and_then(|p| p.upgrade(mc)).map(Thread)
+    }
+
+    /// The limits on the call stack of this thread, [`StackLimits::DEFAULT`] unless changed.
+    ///
+    /// Like [`Thread::name`], this is available even while the thread is running.
+    pub fn stack_limits(self) -> StackLimits {
+        self.0.stack_limits.get()
+    }
+
+    /// Change the limits on the call stack of this thread.
+    ///
+    /// The limits are checked every time the thread resumes a Lua function, so lowering them below
+    /// the current size of a running thread makes it raise an error as soon as it continues.
+    pub fn set_stack_limits(self, limits: StackLimits) {
+        self.0.stack_limits.set(limits);
+    }
+
     pub fn mode(self) -> ThreadMode {
-        match self.0.try_borrow() {
+        match self.0.state.try_borrow() {
             Ok(state) => state.mode(),
             Err(_) => ThreadMode::Running,
         }
@@ -139,7 +263,22 @@ impl<'gc> Thread<'gc> {
             .and_then(|vals| Ok(T::from_multi_value(ctx, vals)?)))
     }
 
+    /// Returns true if this thread is in `Result` mode because it yielded, rather than because it
+    /// returned or errored.
+    pub fn has_yielded_result(self) -> bool {
+        match self.0.state.try_borrow() {
+            Ok(state) => matches!(
+                state.frames.as_slice(),
+                [.., Frame::Yielded, Frame::Result { .. }]
+            ),
+            Err(_) => false,
+        }
+    }
+
     /// If the thread is in `Suspended` mode, resume it.
+    ///
+    /// If the thread was suspended by [`Thread::suspend_external`], then it continues where it left
+    /// off and the given arguments are discarded.
     pub fn resume(
         self,
         ctx: Context<'gc>,
@@ -158,6 +297,9 @@ impl<'gc> Thread<'gc> {
             Frame::Yielded => {
                 state.return_to(bottom);
             }
+            Frame::Preempted => {
+                state.stack.truncate(bottom);
+            }
             _ => panic!("top frame not a suspended thread"),
         }
         Ok(())
@@ -168,16 +310,44 @@ impl<'gc> Thread<'gc> {
         let mut state = self.check_mode(mc, ThreadMode::Suspended)?;
         assert!(matches!(
             state.frames.pop(),
-            Some(Frame::Start(_) | Frame::Yielded)
+            Some(Frame::Start(_) | Frame::Yielded | Frame::Preempted)
         ));
         state.frames.push(Frame::Error(error));
         Ok(())
     }
 
+    /// If the thread is in `Normal` mode, request that it suspend itself at the next safe point.
+    ///
+    /// The next time an [`Executor`](crate::Executor) steps this thread, before running another
+    /// instruction or callback, the thread will move to the `Suspended` mode without yielding any
+    /// values and without the running script observing anything. If the thread is the one an
+    /// `Executor` is currently running, then the `Executor` becomes
+    /// [`ExecutorMode::Suspended`](crate::ExecutorMode::Suspended) as well.
+    ///
+    /// Calling [`Thread::resume`] (or [`Executor::resume`](crate::Executor::resume)) on the
+    /// suspended thread continues execution exactly where it left off. Calling
+    /// [`Thread::resume_err`] instead raises the error at the point of suspension.
+    ///
+    /// If the thread finishes or is reset before reaching a safe point, the request is discarded.
+    pub fn suspend_external(self, mc: &Mutation<'gc>) -> Result<(), BadThreadMode> {
+        let mut state = self.check_mode(mc, ThreadMode::Normal)?;
+        state.suspend_requested = true;
+        Ok(())
+    }
+
+    /// Returns true if this thread is `Suspended` because of a call to
+    /// [`Thread::suspend_external`], rather than because it yielded or has not yet been started.
+    pub fn is_suspended_externally(self) -> bool {
+        match self.0.state.try_borrow() {
+            Ok(state) => matches!(state.frames.last(), Some(Frame::Preempted)),
+            Err(_) => false,
+        }
+    }
+
     /// If this thread is in any other mode than `Running`, reset the thread completely and restore
     /// it to the `Stopped` state.
     pub fn reset(self, mc: &Mutation<'gc>) -> Result<(), BadThreadMode> {
-        match self.0.try_borrow_mut(mc) {
+        match self.try_state_mut(mc) {
             Ok(mut state) => {
                 state.reset(mc);
                 Ok(())
@@ -189,6 +359,71 @@ impl<'gc> Thread<'gc> {
         }
     }
 
+    /// Close a thread that is not currently executing, moving it to the `Stopped` mode.
+    ///
+    /// Unlike [`Thread::reset`], this is only allowed for threads which are `Stopped`, `Suspended`,
+    /// or have a pending `Result`, and never for a thread which is in the middle of running. Any
+    /// open upvalues are closed so that closures created by the thread keep their current values.
+    ///
+    /// If the thread had finished with an error which has not been taken yet, the error is
+    /// returned. Any other pending results are discarded.
+    pub fn close(self, mc: &Mutation<'gc>) -> Result<Result<(), Error<'gc>>, BadThreadMode> {
+        let mut state = self.try_state_mut(mc).map_err(|_| BadThreadMode {
+            found: ThreadMode::Running,
+            expected: None,
+        })?;
+
+        let res = match state.mode() {
+            ThreadMode::Stopped | ThreadMode::Suspended => Ok(()),
+            ThreadMode::Result => match state.frames.last() {
+                Some(Frame::Error(err)) => Err(err.clone()),
+                _ => Ok(()),
+            },
+            found => {
+                return Err(BadThreadMode {
+                    found,
+                    expected: None,
+                })
+            }
+        };
+        state.reset(mc);
+        Ok(res)
+    }
+
+    /// Serialize a `Stopped` or `Suspended` thread, along with everything reachable from it, to a
+    /// portable byte format which can be restored with [`Thread::restore`].
+    ///
+    /// The snapshot includes the thread's frames and stack, every closure, upvalue and table it
+    /// references, and any other threads it references, which must also be stopped or suspended.
+    /// Values which are globals, or fields of global tables, are stored by name and refer to the
+    /// globals of the `Lua` instance the snapshot is restored into. The globals table itself is
+    /// never part of the snapshot.
+    ///
+    /// Every other callback or userdata must be saved by `hooks`, or snapshotting fails. A thread
+    /// which is inside a callback or sequence, such as a coroutine waiting in `pcall`, cannot be
+    /// snapshotted.
+    pub fn snapshot(
+        self,
+        ctx: Context<'gc>,
+        hooks: &mut impl SnapshotHooks<'gc>,
+    ) -> Result<Vec<u8>, SnapshotError> {
+        snapshot::save(ctx, self, hooks)
+    }
+
+    /// Restore a thread from a snapshot made by [`Thread::snapshot`], possibly in a different
+    /// `Lua` instance.
+    ///
+    /// The snapshot is checked enough that a corrupt snapshot results in an error rather than a
+    /// panic when restoring, but it is not a sandbox, a restored thread runs whatever bytecode the
+    /// snapshot contains.
+    pub fn restore(
+        ctx: Context<'gc>,
+        data: &[u8],
+        hooks: &mut impl SnapshotHooks<'gc>,
+    ) -> Result<Thread<'gc>, SnapshotError> {
+        snapshot::restore(ctx, data, hooks)
+    }
+
     /// For each open upvalue pointing to this thread, if the upvalue itself is live, then resurrect
     /// the actual value that it is pointing to.
     ///
@@ -201,7 +436,7 @@ impl<'gc> Thread<'gc> {
         // If this thread is not dead, then none of the held stack values can be dead, so we don't
         // need to resurrect them.
         if Gc::is_dead(fc, self.0) {
-            let state = self.0.try_borrow().map_err(|_| BadThreadMode {
+            let state = self.0.state.try_borrow().map_err(|_| BadThreadMode {
                 found: ThreadMode::Running,
                 expected: None,
             })?;
@@ -216,7 +451,7 @@ impl<'gc> Thread<'gc> {
         expected: ThreadMode,
     ) -> Result<RefMut<ThreadState<'gc>>, BadThreadMode> {
         assert!(expected != ThreadMode::Running);
-        if let Ok(state) = self.0.try_borrow_mut(mc) {
+        if let Ok(state) = self.try_state_mut(mc) {
             let found = state.mode();
             if found == expected {
                 Ok(state)
@@ -238,27 +473,69 @@ impl<'gc> Thread<'gc> {
 #[derive(Debug, Copy, Clone, Collect)]
 #[collect(no_drop)]
 pub struct OpenUpValue<'gc> {
-    thread: GcWeak<'gc, RefLock<ThreadState<'gc>>>,
+    thread: GcWeak<'gc, ThreadInner<'gc>>,
     stack_index: usize,
 }
 
 impl<'gc> OpenUpValue<'gc> {
     const UPGRADE_ERR: &'static str = "thread not finalized: upvalues not closed";
 
+    pub(super) fn new(thread: Thread<'gc>, stack_index: usize) -> Self {
+        Self {
+            thread: Gc::downgrade(thread.0),
+            stack_index,
+        }
+    }
+
+    /// The thread whose stack holds the value of this upvalue.
+    pub fn thread(self, mc: &Mutation<'gc>) -> Thread<'gc> {
+        Thread(self.thread.upgrade(mc).expect(Self::UPGRADE_ERR))
+    }
+
+    /// The index of the value of this upvalue on the stack of its thread.
+    pub fn stack_index(self) -> usize {
+        self.stack_index
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the thread that owns this upvalue is currently running, see
+    /// [`OpenUpValue::try_get`] for a fallible version.
     pub fn get(self, mc: &Mutation<'gc>) -> Value<'gc> {
-        self.thread
-            .upgrade(mc)
-            .expect(Self::UPGRADE_ERR)
-            .borrow()
-            .stack[self.stack_index]
+        self.try_get(mc).unwrap()
     }
 
+    /// # Panics
+    ///
+    /// Panics if the thread that owns this upvalue is currently running, see
+    /// [`OpenUpValue::try_set`] for a fallible version.
     pub fn set(self, mc: &Mutation<'gc>, v: Value<'gc>) {
-        self.thread
-            .upgrade(mc)
-            .expect(Self::UPGRADE_ERR)
-            .borrow_mut(mc)
-            .stack[self.stack_index] = v;
+        self.try_set(mc, v).unwrap()
+    }
+
+    /// A version of [`OpenUpValue::get`] which returns an error rather than panicking if the thread
+    /// that owns this upvalue is currently running.
+    pub fn try_get(self, mc: &Mutation<'gc>) -> Result<Value<'gc>, BadThreadMode> {
+        let thread = self.thread.upgrade(mc).expect(Self::UPGRADE_ERR);
+        let state = thread.state.try_borrow().map_err(|_| BadThreadMode {
+            found: ThreadMode::Running,
+            expected: None,
+        })?;
+        Ok(state.stack[self.stack_index])
+    }
+
+    /// A version of [`OpenUpValue::set`] which returns an error rather than panicking if the thread
+    /// that owns this upvalue is currently running.
+    pub fn try_set(self, mc: &Mutation<'gc>, v: Value<'gc>) -> Result<(), BadThreadMode> {
+        let thread = self.thread.upgrade(mc).expect(Self::UPGRADE_ERR);
+        let mut state = Thread(thread)
+            .try_state_mut(mc)
+            .map_err(|_| BadThreadMode {
+                found: ThreadMode::Running,
+                expected: None,
+            })?;
+        state.stack[self.stack_index] = v;
+        Ok(())
     }
 }
 
@@ -316,12 +593,18 @@ pub(super) enum Frame<'gc> {
     /// Thread has yielded and is waiting resume. Must be the top frame of the stack or immediately
     /// below a Result frame.
     Yielded,
+    /// Thread was suspended at a safe point by `Thread::suspend_external` and will continue from
+    /// the frame below when resumed. Must be the top frame of the stack.
+    Preempted,
     /// We are waiting on an upper thread to finish. Must be the top frame of the stack.
     WaitThread,
     /// Results are waiting to be taken. Must be the top frame of the stack.
     Result { bottom: usize },
     /// An error is currently unwinding. Must be the top frame of the stack.
     Error(Error<'gc>),
+    /// A message handler (see [`ErrorBoundary::Handler`](crate::ErrorBoundary::Handler)) has been called with an error, and the
+    /// error will continue unwinding from here with the handler's result.
+    MessageHandler { bottom: usize },
 }
 
 #[derive(Debug, Collect)]
@@ -330,6 +613,11 @@ pub struct ThreadState<'gc> {
     pub(super) frames: vec::Vec<Frame<'gc>, MetricsAlloc<'gc>>,
     pub(super) stack: vec::Vec<Value<'gc>, MetricsAlloc<'gc>>,
     pub(super) open_upvalues: vec::Vec<UpValue<'gc>, MetricsAlloc<'gc>>,
+    // Set by `Thread::suspend_external`, and checked by the `Executor` before running the top frame.
+    pub(super) suspend_requested: bool,
+    // Set once the error currently unwinding this thread has been passed to a message handler (or
+    // there was none), so that it is not handled again. Cleared when the error is caught.
+    pub(super) error_handled: bool,
 }
 
 impl<'gc> ThreadState<'gc> {
@@ -340,10 +628,11 @@ impl<'gc> ThreadState<'gc> {
                 ThreadMode::Stopped
             }
             Some(frame) => match frame {
-                Frame::Lua { .. } | Frame::Callback { .. } | Frame::Sequence { .. } => {
-                    ThreadMode::Normal
-                }
-                Frame::Start(_) | Frame::Yielded => ThreadMode::Suspended,
+                Frame::Lua { .. }
+                | Frame::Callback { .. }
+                | Frame::Sequence { .. }
+                | Frame::MessageHandler { .. } => ThreadMode::Normal,
+                Frame::Start(_) | Frame::Yielded | Frame::Preempted => ThreadMode::Suspended,
                 Frame::WaitThread => ThreadMode::Waiting,
                 Frame::Result { .. } => ThreadMode::Result,
                 Frame::Error(_) => {
@@ -404,7 +693,7 @@ impl<'gc> ThreadState<'gc> {
     /// from the top of the stack starting at `bottom`.
     pub(super) fn return_to(&mut self, bottom: usize) {
         match self.frames.last_mut() {
-            Some(Frame::Sequence { .. }) => {}
+            Some(Frame::Sequence { .. } | Frame::MessageHandler { .. }) => {}
             Some(Frame::Lua {
                 expected_return,
                 is_variable,
@@ -458,6 +747,9 @@ impl<'gc> ThreadState<'gc> {
     pub(super) fn take_result(
         &mut self,
     ) -> Result<impl Iterator<Item = Value<'gc>> + '_, Error<'gc>> {
+        // The thread has finished (or yielded) before any pending suspend request was honored.
+        self.suspend_requested = false;
+        self.error_handled = false;
         match self.frames.pop() {
             Some(Frame::Result { bottom }) => Ok(self.stack.drain(bottom..)),
             Some(Frame::Error(err)) => {
@@ -483,7 +775,9 @@ impl<'gc> ThreadState<'gc> {
         for &upval in &self.open_upvalues[start..] {
             match upval.get() {
                 UpValueState::Open(open_upvalue) => {
-                    debug_assert!(open_upvalue.thread.upgrade(mc).unwrap().as_ptr() == this_ptr);
+                    debug_assert!(
+                        open_upvalue.thread.upgrade(mc).unwrap().state.as_ptr() == this_ptr
+                    );
                     upval.set(
                         mc,
                         UpValueState::Closed(self.stack[open_upvalue.stack_index]),
@@ -501,6 +795,8 @@ impl<'gc> ThreadState<'gc> {
         assert!(self.open_upvalues.is_empty());
         self.stack.clear();
         self.frames.clear();
+        self.suspend_requested = false;
+        self.error_handled = false;
     }
 
     fn resurrect_live_upvalues(&self, fc: &Finalization<'gc>) {
@@ -647,6 +943,11 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
         let table_ind = base + table_base.0 as usize;
         let start_ind = table_ind + 1;
 
+        let set_count = match count.to_constant() {
+            Some(c) => c as usize,
+            None => variable_count(self.state.stack.len(), table_ind + 2)?,
+        };
+
         let table = self.state.stack[table_ind];
         let start = self.state.stack[start_ind];
 
@@ -654,11 +955,6 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
             return Err(VMError::BadSetList(table.type_name(), start.type_name()));
         };
 
-        let set_count = count
-            .to_constant()
-            .map(|c| c as usize)
-            .unwrap_or(self.state.stack.len() - table_ind - 2);
-
         self.fuel
             .consume(count_fuel(Self::FUEL_PER_ITEM, set_count));
         for i in 0..set_count {
@@ -708,10 +1004,10 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
         self.fuel.consume(Self::FUEL_PER_CALL);
 
         let function_index = *base + func.0 as usize;
-        let arg_count = args
-            .to_constant()
-            .map(|c| c as usize)
-            .unwrap_or(self.state.stack.len() - function_index - 1);
+        let arg_count = match args.to_constant() {
+            Some(c) => c as usize,
+            None => variable_count(self.state.stack.len(), function_index + 1)?,
+        };
 
         let call = meta_ops::call(ctx, self.state.stack[function_index])?;
         *expected_return = Some(LuaReturn::Normal(returns));
@@ -888,10 +1184,10 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
         self.fuel.consume(Self::FUEL_PER_CALL);
 
         let function_index = base + func.0 as usize;
-        let arg_count = args
-            .to_constant()
-            .map(|c| c as usize)
-            .unwrap_or(self.state.stack.len() - function_index - 1);
+        let arg_count = match args.to_constant() {
+            Some(c) => c as usize,
+            None => variable_count(self.state.stack.len(), function_index + 1)?,
+        };
 
         let call = meta_ops::call(ctx, self.state.stack[function_index])?;
 
@@ -937,10 +1233,10 @@ impl<'gc, 'a> LuaFrame<'gc, 'a> {
         self.state.close_upvalues(mc, bottom);
 
         let start = base + start.0 as usize;
-        let count = count
-            .to_constant()
-            .map(|c| c as usize)
-            .unwrap_or(self.state.stack.len() - start);
+        let count = match count.to_constant() {
+            Some(c) => c as usize,
+            None => variable_count(self.state.stack.len(), start)?,
+        };
 
         self.fuel.consume(count_fuel(Self::FUEL_PER_ITEM, count));
 
@@ -984,20 +1280,25 @@ impl<'gc, 'a> LuaRegisters<'gc, 'a> {
         }
     }
 
-    pub(super) fn get_upvalue(&self, mc: &Mutation<'gc>, upvalue: UpValue<'gc>) -> Value<'gc> {
+    pub(super) fn get_upvalue(
+        &self,
+        mc: &Mutation<'gc>,
+        upvalue: UpValue<'gc>,
+    ) -> Result<Value<'gc>, VMError> {
         match upvalue.get() {
             UpValueState::Open(open_upvalue) => {
                 if open_upvalue.thread.as_ptr() == Gc::as_ptr(self.thread.0) {
-                    assert!(
-                        open_upvalue.stack_index < self.bottom,
-                        "upvalues must be above the current Lua frame"
-                    );
-                    self.upper_stack[open_upvalue.stack_index]
+                    if open_upvalue.stack_index >= self.bottom {
+                        return Err(VMError::UnreachableUpValue);
+                    }
+                    Ok(self.upper_stack[open_upvalue.stack_index])
                 } else {
-                    open_upvalue.get(mc)
+                    open_upvalue
+                        .try_get(mc)
+                        .map_err(|_| VMError::UnreachableUpValue)
                 }
             }
-            UpValueState::Closed(v) => v,
+            UpValueState::Closed(v) => Ok(v),
         }
     }
 
@@ -1006,23 +1307,25 @@ impl<'gc, 'a> LuaRegisters<'gc, 'a> {
         mc: &Mutation<'gc>,
         upvalue: UpValue<'gc>,
         value: Value<'gc>,
-    ) {
+    ) -> Result<(), VMError> {
         match upvalue.get() {
             UpValueState::Open(open_upvalue) => {
                 if open_upvalue.thread.as_ptr() == Gc::as_ptr(self.thread.0) {
-                    assert!(
-                        open_upvalue.stack_index < self.bottom,
-                        "upvalues must be above the current Lua frame"
-                    );
+                    if open_upvalue.stack_index >= self.bottom {
+                        return Err(VMError::UnreachableUpValue);
+                    }
                     self.upper_stack[open_upvalue.stack_index] = value;
                 } else {
-                    open_upvalue.set(mc, value);
+                    open_upvalue
+                        .try_set(mc, value)
+                        .map_err(|_| VMError::UnreachableUpValue)?;
                 }
             }
             UpValueState::Closed(_) => {
                 upvalue.set(mc, UpValueState::Closed(value));
             }
         }
+        Ok(())
     }
 
     pub(super) fn close_upvalues(&mut self, mc: &Mutation<'gc>, bottom_register: RegisterIndex) {
@@ -1056,6 +1359,13 @@ impl<'gc, 'a> LuaRegisters<'gc, 'a> {
     }
 }
 
+// The number of values from `start` to the top of a variable stack. Bytecode from the compiler
+// never expects values from above the top, but this is checked so that malformed bytecode cannot
+// cause a panic.
+fn variable_count(top: usize, start: usize) -> Result<usize, VMError> {
+    top.checked_sub(start).ok_or(VMError::BadVariableStack)
+}
+
 fn open_upvalue_ind<'gc>(u: UpValue<'gc>) -> usize {
     match u.get() {
         UpValueState::Open(open_upvalue) => open_upvalue.stack_index,
@@ -0,0 +1,128 @@
+use std::{fmt, marker::PhantomData};
+
+use gc_arena::Mutation;
+
+use crate::{Context, Error, FromMultiValue, Function, IntoMultiValue};
+
+use super::{BadThreadMode, Thread, ThreadMode};
+
+/// The values taken from a [`TypedThread`] which has stopped running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ThreadResult<Y, R> {
+    /// The thread yielded, and may be resumed again.
+    Yielded(Y),
+    /// The thread returned, and is now stopped.
+    Returned(R),
+}
+
+/// A [`Thread`] with a fixed protocol for the values passed across each suspension point.
+///
+/// The thread is started and resumed with arguments of type `A`, yields values of type `Y`, and
+/// finally returns values of type `R`. Values are converted with [`IntoMultiValue`] and
+/// [`FromMultiValue`] every time they cross between the host and the thread, so a script which
+/// yields or returns something unexpected produces a type error rather than silently handing the
+/// host the wrong values.
+///
+/// This is only a view of the underlying thread, Lua code can still resume the thread with any
+/// values if it has access to it.
+pub struct TypedThread<'gc, A, Y, R> {
+    thread: Thread<'gc>,
+    _marker: PhantomData<fn(A) -> (Y, R)>,
+}
+
+impl<'gc, A, Y, R> Copy for TypedThread<'gc, A, Y, R> {}
+
+impl<'gc, A, Y, R> Clone for TypedThread<'gc, A, Y, R> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'gc, A, Y, R> fmt::Debug for TypedThread<'gc, A, Y, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TypedThread").field(&self.thread).finish()
+    }
+}
+
+impl<'gc, A, Y, R> PartialEq for TypedThread<'gc, A, Y, R> {
+    fn eq(&self, other: &Self) -> bool {
+        self.thread == other.thread
+    }
+}
+
+impl<'gc, A, Y, R> Eq for TypedThread<'gc, A, Y, R> {}
+
+impl<'gc, A, Y, R> TypedThread<'gc, A, Y, R>
+where
+    A: IntoMultiValue<'gc>,
+    Y: FromMultiValue<'gc>,
+    R: FromMultiValue<'gc>,
+{
+    /// Create a new stopped thread.
+    pub fn new(ctx: Context<'gc>) -> Self {
+        Self::from_thread(Thread::new(ctx))
+    }
+
+    /// View an existing thread as a `TypedThread`.
+    pub fn from_thread(thread: Thread<'gc>) -> Self {
+        Self {
+            thread,
+            _marker: PhantomData,
+        }
+    }
+
+    /// The underlying untyped thread, which can be run by an [`Executor`](crate::Executor).
+    pub fn thread(self) -> Thread<'gc> {
+        self.thread
+    }
+
+    pub fn mode(self) -> ThreadMode {
+        self.thread.mode()
+    }
+
+    /// If this thread is `Stopped`, start a new function with the given arguments.
+    pub fn start(
+        self,
+        ctx: Context<'gc>,
+        function: Function<'gc>,
+        args: A,
+    ) -> Result<(), BadThreadMode> {
+        self.thread.start(ctx, function, args)
+    }
+
+    /// If this thread is `Stopped`, start a new suspended function which will receive the
+    /// arguments of the first call to [`TypedThread::resume`].
+    pub fn start_suspended(
+        self,
+        mc: &Mutation<'gc>,
+        function: Function<'gc>,
+    ) -> Result<(), BadThreadMode> {
+        self.thread.start_suspended(mc, function)
+    }
+
+    /// If this thread is `Suspended`, resume it with the given arguments.
+    pub fn resume(self, ctx: Context<'gc>, args: A) -> Result<(), BadThreadMode> {
+        self.thread.resume(ctx, args)
+    }
+
+    /// If the thread is in `Result` mode, take the yielded or returned values, converting them to
+    /// `Y` or `R` respectively.
+    ///
+    /// The thread moves back to `Suspended` or `Stopped` mode even if the conversion fails.
+    pub fn take_result(
+        self,
+        ctx: Context<'gc>,
+    ) -> Result<Result<ThreadResult<Y, R>, Error<'gc>>, BadThreadMode> {
+        if self.thread.has_yielded_result() {
+            Ok(self
+                .thread
+                .take_result::<Y>(ctx)?
+                .map(ThreadResult::Yielded))
+        } else {
+            Ok(self
+                .thread
+                .take_result::<R>(ctx)?
+                .map(ThreadResult::Returned))
+        }
+    }
+}
@@ -1,25 +1,37 @@
+use std::{mem, ops};
+
 use allocator_api2::vec;
 use gc_arena::allocator_api::MetricsAlloc;
 
 use crate::{
-    meta_ops::{self, ConcatMetaResult, MetaResult},
-    opcode::{Operation, RCIndex},
+    memory::LimitCheck,
+    meta_ops::{self, ConcatMetaResult, MetaOperatorError, MetaResult},
+    opcode::{OpCode, Operation, RCIndex},
     table::RawTable,
     thread::thread::MetaReturn,
     types::{RegisterIndex, UpValueDescriptor, VarCount},
-    Closure, Constant, Context, Function, String, Table, Value,
+    Closure, Constant, Context, Function, FunctionPrototype, MetaMethod, String, Table, Value,
+    VariableName,
 };
 
-use super::{thread::LuaFrame, VMError};
+use super::{
+    thread::{LuaFrame, LuaRegisters},
+    VMError,
+};
 
 // Runs the VM for the given number of instructions or until the current LuaFrame may have been
 // changed.
 //
-// Returns the number of instructions that were run.
+// Returns the number of instructions that were run to completion, which is what fuel is charged
+// for. The final instruction is not included if it changed the LuaFrame or raised an error, but
+// `executed` is incremented for every instruction started, including that one. If `pcs` is given,
+// the index of every started instruction is pushed to it.
 pub(super) fn run_vm<'gc>(
     ctx: Context<'gc>,
     mut lua_frame: LuaFrame<'gc, '_>,
     max_instructions: u32,
+    executed: &mut u32,
+    mut pcs: Option<&mut Vec<usize>>,
 ) -> Result<u32, VMError> {
     if max_instructions == 0 {
         return Ok(0);
@@ -42,9 +54,41 @@ pub(super) fn run_vm<'gc>(
         }
     }
 
+    // In strict globals mode, reading a nil global is an error. Globals are whatever is indexed
+    // through `_ENV`, so this applies to any environment the chunk is run with.
+    fn undeclared_global<'gc>(
+        prototype: &FunctionPrototype<'gc>,
+        pc: usize,
+        key: Value<'gc>,
+    ) -> VMError {
+        let (chunk_name, line_number) = prototype.opcode_location(pc);
+        VMError::UndeclaredGlobal {
+            name: key.display().to_string(),
+            chunk_name: chunk_name.display_lossy().to_string(),
+            line_number,
+        }
+    }
+
+    // Stop before running the current instruction, so that it runs again once the executor has
+    // collected garbage, see `reserve_memory`.
+    macro_rules! retry_after_collection {
+        () => {{
+            *registers.pc -= 1;
+            *executed -= 1;
+            if let Some(pcs) = pcs.as_deref_mut() {
+                pcs.pop();
+            }
+            break;
+        }};
+    }
+
     loop {
         let op = current_prototype.opcodes[*registers.pc].decode();
+        if let Some(pcs) = pcs.as_deref_mut() {
+            pcs.push(*registers.pc);
+        }
         *registers.pc += 1;
+        *executed += 1;
 
         match op {
             Operation::Move { dest, source } => {
@@ -78,8 +122,14 @@ pub(super) fn run_vm<'gc>(
                 array_size,
                 map_size,
             } => {
+                if !reserve_memory(ctx, || {
+                    array_size as usize * mem::size_of::<Value>()
+                        + map_size as usize * mem::size_of::<(Value, Value)>()
+                })? {
+                    retry_after_collection!();
+                }
                 let table = Table::from_parts(
-                    &ctx,
+                    ctx,
                     RawTable::with_capacity(&ctx, array_size as usize, map_size as usize),
                     None,
                 );
@@ -87,10 +137,22 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::GetTable { dest, table, key } => {
+                let pc = *registers.pc - 1;
+                let table_reg = table;
                 let table = registers.stack_frame[table.0 as usize];
                 let key = get_rc(&registers.stack_frame, &current_prototype.constants, key);
-                match meta_ops::index(ctx, table, key)? {
+                match meta_ops::index(ctx, table, key).map_err(|err| {
+                    index_error(err, || current_prototype.describe_register(table_reg, pc))
+                })? {
                     MetaResult::Value(v) => {
+                        if v.is_nil()
+                            && current_prototype.strict_globals
+                            && current_prototype
+                                .local_name(table_reg, pc)
+                                .is_some_and(|n| n == "_ENV")
+                        {
+                            return Err(undeclared_global(&current_prototype, pc, key));
+                        }
                         registers.stack_frame[dest.0 as usize] = v;
                     }
                     MetaResult::Call(call) => {
@@ -106,10 +168,17 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::SetTable { table, key, value } => {
+                let pc = *registers.pc - 1;
+                let table_reg = table;
                 let table = registers.stack_frame[table.0 as usize];
                 let key = get_rc(&registers.stack_frame, &current_prototype.constants, key);
                 let value = get_rc(&registers.stack_frame, &current_prototype.constants, value);
-                if let Some(call) = meta_ops::new_index(ctx, table, key, value)? {
+                if !reserve_table_growth(ctx, table, key, value)? {
+                    retry_after_collection!();
+                }
+                if let Some(call) = meta_ops::new_index(ctx, table, key, value).map_err(|err| {
+                    index_error(err, || current_prototype.describe_register(table_reg, pc))
+                })? {
                     lua_frame.call_meta_function(
                         ctx,
                         call.function,
@@ -121,10 +190,29 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::GetUpTable { dest, table, key } => {
-                let table = registers.get_upvalue(&ctx, current_upvalues[table.0 as usize]);
+                let upvalue = table;
+                let table = registers.get_upvalue(&ctx, current_upvalues[table.0 as usize])?;
                 let key = get_rc(&registers.stack_frame, &current_prototype.constants, key);
-                match meta_ops::index(ctx, table, key)? {
+                match meta_ops::index(ctx, table, key).map_err(|err| {
+                    index_error(err, || {
+                        current_prototype
+                            .upvalue_name(upvalue)
+                            .map(VariableName::UpValue)
+                    })
+                })? {
                     MetaResult::Value(v) => {
+                        if v.is_nil()
+                            && current_prototype.strict_globals
+                            && current_prototype
+                                .upvalue_name(upvalue)
+                                .is_some_and(|n| n == "_ENV")
+                        {
+                            return Err(undeclared_global(
+                                &current_prototype,
+                                *registers.pc - 1,
+                                key,
+                            ));
+                        }
                         registers.stack_frame[dest.0 as usize] = v;
                     }
                     MetaResult::Call(call) => {
@@ -140,10 +228,20 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::SetUpTable { table, key, value } => {
-                let table = registers.get_upvalue(&ctx, current_upvalues[table.0 as usize]);
+                let upvalue = table;
+                let table = registers.get_upvalue(&ctx, current_upvalues[table.0 as usize])?;
                 let key = get_rc(&registers.stack_frame, &current_prototype.constants, key);
                 let value = get_rc(&registers.stack_frame, &current_prototype.constants, value);
-                if let Some(call) = meta_ops::new_index(ctx, table, key, value)? {
+                if !reserve_table_growth(ctx, table, key, value)? {
+                    retry_after_collection!();
+                }
+                if let Some(call) = meta_ops::new_index(ctx, table, key, value).map_err(|err| {
+                    index_error(err, || {
+                        current_prototype
+                            .upvalue_name(upvalue)
+                            .map(VariableName::UpValue)
+                    })
+                })? {
                     lua_frame.call_meta_function(
                         ctx,
                         call.function,
@@ -155,6 +253,18 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::SetList { base, count } => {
+                if !reserve_memory(ctx, || {
+                    let count = match count.to_constant() {
+                        Some(c) => c as usize,
+                        None => registers
+                            .stack_frame
+                            .len()
+                            .saturating_sub(base.0 as usize + 2),
+                    };
+                    count * mem::size_of::<Value>()
+                })? {
+                    retry_after_collection!();
+                }
                 lua_frame.set_table_list(&ctx, base, count)?;
                 registers = lua_frame.registers();
             }
@@ -164,12 +274,22 @@ pub(super) fn run_vm<'gc>(
                 args,
                 returns,
             } => {
-                lua_frame.call_function(ctx, func, args, returns)?;
+                let pc = *registers.pc - 1;
+                lua_frame
+                    .call_function(ctx, func, args, returns)
+                    .map_err(|err| {
+                        call_error(err, || current_prototype.describe_register(func, pc))
+                    })?;
                 break;
             }
 
             Operation::TailCall { func, args } => {
-                lua_frame.tail_call_function(ctx, func, args)?;
+                let pc = *registers.pc - 1;
+                lua_frame
+                    .tail_call_function(ctx, func, args)
+                    .map_err(|err| {
+                        call_error(err, || current_prototype.describe_register(func, pc))
+                    })?;
                 break;
             }
 
@@ -236,16 +356,21 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::NumericForPrep { base, jump } => {
-                registers.stack_frame[base.0 as usize] = raw_subtract(
-                    registers.stack_frame[base.0 as usize],
-                    registers.stack_frame[base.0 as usize + 2],
-                )
-                .ok_or_else(|| {
-                    VMError::BadForLoopPrep(
-                        registers.stack_frame[base.0 as usize].type_name(),
-                        registers.stack_frame[base.0 as usize + 2].type_name(),
-                    )
-                })?;
+                let index = registers.stack_frame[base.0 as usize];
+                let step = registers.stack_frame[base.0 as usize + 2];
+                registers.stack_frame[base.0 as usize] =
+                    arith_fast_path(index, step, i64::wrapping_sub, ops::Sub::sub)
+                        .or_else(|| raw_subtract(index, step))
+                        .ok_or_else(|| {
+                            VMError::BadForLoopPrep(index.type_name(), step.type_name())
+                        })?;
+                if let (Value::Integer(_), Value::Number(limit), Value::Integer(step)) =
+                    (index, registers.stack_frame[base.0 as usize + 1], step)
+                {
+                    if let Some(limit) = integer_for_limit(limit, step) {
+                        registers.stack_frame[base.0 as usize + 1] = Value::Integer(limit);
+                    }
+                }
                 *registers.pc = add_offset(*registers.pc, jump);
             }
 
@@ -334,10 +459,14 @@ pub(super) fn run_vm<'gc>(
             }
 
             Operation::Method { base, table, key } => {
+                let pc = *registers.pc - 1;
+                let table_reg = table;
                 let table = registers.stack_frame[table.0 as usize];
                 let key = get_rc(&registers.stack_frame, &current_prototype.constants, key);
                 registers.stack_frame[base.0 as usize + 1] = table;
-                match meta_ops::index(ctx, table, key)? {
+                match meta_ops::index(ctx, table, key).map_err(|err| {
+                    index_error(err, || current_prototype.describe_register(table_reg, pc))
+                })? {
                     MetaResult::Value(v) => {
                         registers.stack_frame[base.0 as usize] = v;
                     }
@@ -360,6 +489,11 @@ pub(super) fn run_vm<'gc>(
             } => {
                 let base = source.0 as usize;
                 let values = &registers.stack_frame[base..base + count as usize];
+                if let Some(len) = meta_ops::estimate_concatenated_len(values)? {
+                    if !reserve_memory(ctx, || len)? {
+                        retry_after_collection!();
+                    }
+                }
                 match meta_ops::concat_many(ctx, values)? {
                     ConcatMetaResult::Value(v) => registers.stack_frame[dest.0 as usize] = v,
                     ConcatMetaResult::Call(func) => {
@@ -377,7 +511,7 @@ pub(super) fn run_vm<'gc>(
 
             Operation::GetUpValue { source, dest } => {
                 registers.stack_frame[dest.0 as usize] =
-                    registers.get_upvalue(&ctx, current_upvalues[source.0 as usize]);
+                    registers.get_upvalue(&ctx, current_upvalues[source.0 as usize])?;
             }
 
             Operation::SetUpValue { source, dest } => {
@@ -385,7 +519,7 @@ pub(super) fn run_vm<'gc>(
                     &ctx,
                     current_upvalues[dest.0 as usize],
                     registers.stack_frame[source.0 as usize],
-                );
+                )?;
             }
 
             Operation::Length { dest, source } => {
@@ -412,10 +546,23 @@ pub(super) fn run_vm<'gc>(
             } => {
                 let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
                 let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
-                match meta_ops::equal(ctx, left, right)? {
+                let result = match compare_fast_path(left, right, i64::eq, f64::eq) {
+                    Some(v) => MetaResult::Value(v.into()),
+                    None => meta_ops::equal(ctx, left, right)?,
+                };
+                match result {
                     MetaResult::Value(v) => {
                         if v.to_bool() == skip_if {
                             *registers.pc += 1;
+                        } else if instructions_run + 1 < max_instructions {
+                            let fused = fused_jump(
+                                ctx,
+                                &mut registers,
+                                &current_prototype.opcodes,
+                                pcs.as_deref_mut(),
+                            );
+                            instructions_run += fused;
+                            *executed += fused;
                         }
                     }
                     MetaResult::Call(call) => {
@@ -437,10 +584,23 @@ pub(super) fn run_vm<'gc>(
             } => {
                 let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
                 let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
-                match meta_ops::less_than(ctx, left, right)? {
+                let result = match compare_fast_path(left, right, i64::lt, f64::lt) {
+                    Some(v) => MetaResult::Value(v.into()),
+                    None => meta_ops::less_than(ctx, left, right)?,
+                };
+                match result {
                     MetaResult::Value(v) => {
                         if v.to_bool() == skip_if {
                             *registers.pc += 1;
+                        } else if instructions_run + 1 < max_instructions {
+                            let fused = fused_jump(
+                                ctx,
+                                &mut registers,
+                                &current_prototype.opcodes,
+                                pcs.as_deref_mut(),
+                            );
+                            instructions_run += fused;
+                            *executed += fused;
                         }
                     }
                     MetaResult::Call(call) => {
@@ -462,10 +622,23 @@ pub(super) fn run_vm<'gc>(
             } => {
                 let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
                 let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
-                match meta_ops::less_equal(ctx, left, right)? {
+                let result = match compare_fast_path(left, right, i64::le, f64::le) {
+                    Some(v) => MetaResult::Value(v.into()),
+                    None => meta_ops::less_equal(ctx, left, right)?,
+                };
+                match result {
                     MetaResult::Value(v) => {
                         if v.to_bool() == skip_if {
                             *registers.pc += 1;
+                        } else if instructions_run + 1 < max_instructions {
+                            let fused = fused_jump(
+                                ctx,
+                                &mut registers,
+                                &current_prototype.opcodes,
+                                pcs.as_deref_mut(),
+                            );
+                            instructions_run += fused;
+                            *executed += fused;
                         }
                     }
                     MetaResult::Call(call) => {
@@ -520,7 +693,11 @@ pub(super) fn run_vm<'gc>(
             Operation::Add { dest, left, right } => {
                 let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
                 let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
-                match meta_ops::add(ctx, left, right)? {
+                let result = match arith_fast_path(left, right, i64::wrapping_add, ops::Add::add) {
+                    Some(v) => MetaResult::Value(v),
+                    None => meta_ops::add(ctx, left, right)?,
+                };
+                match result {
                     MetaResult::Value(v) => registers.stack_frame[dest.0 as usize] = v,
                     MetaResult::Call(call) => {
                         lua_frame.call_meta_function(
@@ -537,7 +714,11 @@ pub(super) fn run_vm<'gc>(
             Operation::Sub { dest, left, right } => {
                 let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
                 let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
-                match meta_ops::subtract(ctx, left, right)? {
+                let result = match arith_fast_path(left, right, i64::wrapping_sub, ops::Sub::sub) {
+                    Some(v) => MetaResult::Value(v),
+                    None => meta_ops::subtract(ctx, left, right)?,
+                };
+                match result {
                     MetaResult::Value(v) => registers.stack_frame[dest.0 as usize] = v,
                     MetaResult::Call(call) => {
                         lua_frame.call_meta_function(
@@ -554,7 +735,11 @@ pub(super) fn run_vm<'gc>(
             Operation::Mul { dest, left, right } => {
                 let left = get_rc(&registers.stack_frame, &current_prototype.constants, left);
                 let right = get_rc(&registers.stack_frame, &current_prototype.constants, right);
-                match meta_ops::multiply(ctx, left, right)? {
+                let result = match arith_fast_path(left, right, i64::wrapping_mul, ops::Mul::mul) {
+                    Some(v) => MetaResult::Value(v),
+                    None => meta_ops::multiply(ctx, left, right)?,
+                };
+                match result {
                     MetaResult::Value(v) => registers.stack_frame[dest.0 as usize] = v,
                     MetaResult::Call(call) => {
                         lua_frame.call_meta_function(
@@ -731,6 +916,35 @@ pub(super) fn run_vm<'gc>(
     Ok(instructions_run)
 }
 
+// Replace the error from indexing a value which cannot be indexed with one which describes the
+// variable that the value came from, like PUC-Rio Lua.
+fn index_error<'gc>(
+    err: MetaOperatorError,
+    variable: impl FnOnce() -> Option<VariableName<'gc>>,
+) -> VMError {
+    match err {
+        MetaOperatorError::Unary(MetaMethod::Index | MetaMethod::NewIndex, type_name) => {
+            VMError::BadIndex {
+                type_name,
+                variable: variable().map(|v| v.to_string()),
+            }
+        }
+        err => err.into(),
+    }
+}
+
+// Replace the error from calling a value which cannot be called with one which describes the
+// variable that the value came from, like PUC-Rio Lua.
+fn call_error<'gc>(err: VMError, variable: impl FnOnce() -> Option<VariableName<'gc>>) -> VMError {
+    match err {
+        VMError::BadCall(err) => VMError::BadCallValue {
+            type_name: err.type_name(),
+            variable: variable().map(|v| v.to_string()),
+        },
+        err => err,
+    }
+}
+
 fn add_offset(pc: usize, offset: i16) -> usize {
     if offset > 0 {
         pc.checked_add(offset as usize).unwrap()
@@ -744,3 +958,106 @@ fn add_offset(pc: usize, offset: i16) -> usize {
 fn raw_subtract<'gc>(lhs: Value<'gc>, rhs: Value<'gc>) -> Option<Value<'gc>> {
     Some(lhs.to_constant()?.subtract(&rhs.to_constant()?)?.into())
 }
+
+// Arithmetic on two integers or two floats can never call a metamethod, so the hottest cases skip
+// `meta_ops` entirely. Must produce the same results as the equivalent `Constant` operation.
+#[inline(always)]
+fn arith_fast_path<'gc>(
+    lhs: Value<'gc>,
+    rhs: Value<'gc>,
+    int_op: impl FnOnce(i64, i64) -> i64,
+    float_op: impl FnOnce(f64, f64) -> f64,
+) -> Option<Value<'gc>> {
+    match (lhs, rhs) {
+        (Value::Integer(a), Value::Integer(b)) => Some(Value::Integer(int_op(a, b))),
+        (Value::Number(a), Value::Number(b)) => Some(Value::Number(float_op(a, b))),
+        _ => None,
+    }
+}
+
+#[inline(always)]
+fn compare_fast_path<'gc>(
+    lhs: Value<'gc>,
+    rhs: Value<'gc>,
+    int_op: impl FnOnce(&i64, &i64) -> bool,
+    float_op: impl FnOnce(&f64, &f64) -> bool,
+) -> Option<bool> {
+    match (lhs, rhs) {
+        (Value::Integer(a), Value::Integer(b)) => Some(int_op(&a, &b)),
+        (Value::Number(a), Value::Number(b)) => Some(float_op(&a, &b)),
+        _ => None,
+    }
+}
+
+// If an integer loop has a float limit, replace it with the equivalent integer limit so that every
+// iteration of the loop takes the all-integer path of `NumericForLoop`. Limits outside of the
+// integer range (or NaN) are left alone.
+fn integer_for_limit(limit: f64, step: i64) -> Option<i64> {
+    let limit = if step < 0 {
+        limit.ceil()
+    } else {
+        limit.floor()
+    };
+    if limit >= -(i64::MIN as f64) || !(limit >= i64::MIN as f64) {
+        None
+    } else {
+        Some(limit as i64)
+    }
+}
+
+// Comparisons are always followed by the `Jump` that they guard. When the comparison does not skip
+// it, take the jump right away rather than going back through dispatch. The jump still counts as
+// its own instruction, returns the number of instructions run.
+#[inline(always)]
+fn fused_jump<'gc>(
+    ctx: Context<'gc>,
+    registers: &mut LuaRegisters<'gc, '_>,
+    opcodes: &[OpCode],
+    pcs: Option<&mut Vec<usize>>,
+) -> u32 {
+    let Operation::Jump {
+        offset,
+        close_upvalues,
+    } = opcodes[*registers.pc].decode()
+    else {
+        return 0;
+    };
+    if let Some(pcs) = pcs {
+        pcs.push(*registers.pc);
+    }
+    *registers.pc = add_offset(*registers.pc + 1, offset);
+    if let Some(r) = close_upvalues.to_u8() {
+        registers.close_upvalues(&ctx, RegisterIndex(r));
+    }
+    1
+}
+
+// Check the memory limit before an allocation whose size is controlled by the script, which could
+// otherwise grow far past the limit in between the checks made by the executor. Returns false if
+// the VM must stop before running the current instruction, so that garbage can be collected first.
+fn reserve_memory(ctx: Context<'_>, additional: impl FnOnce() -> usize) -> Result<bool, VMError> {
+    if ctx.memory_limit().limit().is_none() {
+        return Ok(true);
+    }
+
+    match ctx
+        .memory_limit()
+        .check_allocation(ctx.memory_usage(), additional())
+    {
+        LimitCheck::Ok => Ok(true),
+        LimitCheck::Collect => Ok(false),
+        LimitCheck::Exceeded => Err(VMError::NotEnoughMemory),
+    }
+}
+
+fn reserve_table_growth<'gc>(
+    ctx: Context<'gc>,
+    table: Value<'gc>,
+    key: Value<'gc>,
+    value: Value<'gc>,
+) -> Result<bool, VMError> {
+    match table {
+        Value::Table(table) if !value.is_nil() => reserve_memory(ctx, || table.growth_size(key)),
+        _ => Ok(true),
+    }
+}
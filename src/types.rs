@@ -1,4 +1,4 @@
-use std::fmt::{self, Debug};
+use std::fmt::{self, Debug, Display};
 
 use gc_arena::Collect;
 
@@ -123,3 +123,51 @@ impl VarCount {
         self.0.to_u8()
     }
 }
+
+impl Display for RegisterIndex {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "R{}", self.0)
+    }
+}
+
+impl Display for ConstantIndex8 {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "K{}", self.0)
+    }
+}
+
+impl Display for ConstantIndex16 {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "K{}", self.0)
+    }
+}
+
+impl Display for UpValueIndex {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "U{}", self.0)
+    }
+}
+
+impl Display for PrototypeIndex {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "P{}", self.0)
+    }
+}
+
+impl Display for Opt254 {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.to_u8() {
+            Some(v) => write!(fmt, "{}", v),
+            None => write!(fmt, "none"),
+        }
+    }
+}
+
+impl Display for VarCount {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.to_constant() {
+            Some(c) => write!(fmt, "{}", c),
+            None => write!(fmt, "var"),
+        }
+    }
+}
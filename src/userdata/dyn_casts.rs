@@ -0,0 +1,90 @@
+use std::any::{Any, TypeId};
+
+use gc_arena::Collect;
+
+use super::UserData;
+
+/// The `dyn Trait` objects that static userdata can be borrowed as with
+/// [`UserData::downcast_dyn`].
+///
+/// A `DynCasts` is usually built once for a type and shared by every value of it, either by
+/// passing it to [`UserData::new_static_dyn`] or by declaring the casts of a
+/// [`UserDataType`](super::UserDataType) in
+/// [`UserDataType::add_casts`](super::UserDataType::add_casts).
+///
+/// ```
+/// # use gc_arena::Gc;
+/// # use piccolo::{userdata::DynCasts, Lua, UserData};
+/// trait Shape {
+///     fn area(&self) -> f64;
+/// }
+///
+/// struct Square(f64);
+///
+/// impl Shape for Square {
+///     fn area(&self) -> f64 {
+///         self.0 * self.0
+///     }
+/// }
+///
+/// # let mut lua = Lua::empty();
+/// lua.enter(|ctx| {
+///     let mut casts = DynCasts::new();
+///     casts.add::<Square, dyn Shape>(|s| s);
+///     let ud = UserData::new_static_dyn(&ctx, Square(2.0), Gc::new(&ctx, casts));
+///     assert_eq!(ud.downcast_dyn::<dyn Shape>().unwrap().area(), 4.0);
+/// });
+/// ```
+#[derive(Default, Collect)]
+#[collect(require_static)]
+pub struct DynCasts {
+    // Each cast is a `Box<dyn DynCast<D>>`, keyed by the `TypeId` of `D`.
+    casts: Vec<(TypeId, Box<dyn Any>)>,
+}
+
+impl DynCasts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow a userdata holding a `T` created with [`UserData::new_static`] (or one of its
+    /// variants) to be borrowed as `D`, replacing any previous cast to `D`.
+    ///
+    /// The cast is almost always `|v| v`, which relies on unsized coercion from `&T` to `&D`.
+    pub fn add<T: 'static, D: ?Sized + 'static>(&mut self, cast: fn(&T) -> &D) {
+        let cast: Box<dyn DynCast<D>> = Box::new(StaticCast(cast));
+        let type_id = TypeId::of::<D>();
+        match self.casts.iter_mut().find(|(id, _)| *id == type_id) {
+            Some((_, existing)) => *existing = Box::new(cast),
+            None => self.casts.push((type_id, Box::new(cast))),
+        }
+    }
+
+    /// Returns true if a cast to `D` has been added.
+    pub fn contains<D: ?Sized + 'static>(&self) -> bool {
+        self.casts.iter().any(|(id, _)| *id == TypeId::of::<D>())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.casts.is_empty()
+    }
+
+    pub(super) fn cast<'gc, D: ?Sized + 'static>(&self, ud: UserData<'gc>) -> Option<&'gc D> {
+        let (_, cast) = self.casts.iter().find(|(id, _)| *id == TypeId::of::<D>())?;
+        cast.downcast_ref::<Box<dyn DynCast<D>>>()?.cast(ud)
+    }
+}
+
+// A closure cannot return a reference whose lifetime comes from its argument's type, so the cast is
+// a trait instead.
+trait DynCast<D: ?Sized> {
+    fn cast<'gc>(&self, ud: UserData<'gc>) -> Option<&'gc D>;
+}
+
+struct StaticCast<T, D: ?Sized>(fn(&T) -> &D);
+
+impl<T: 'static, D: ?Sized + 'static> DynCast<D> for StaticCast<T, D> {
+    fn cast<'gc>(&self, ud: UserData<'gc>) -> Option<&'gc D> {
+        ud.downcast_static::<T>().ok().map(self.0)
+    }
+}
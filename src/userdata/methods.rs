@@ -0,0 +1,312 @@
+use std::{any::TypeId, hash::BuildHasherDefault, marker::PhantomData};
+
+use ahash::AHasher;
+use gc_arena::{allocator_api::MetricsAlloc, lock::RefLock, Collect, Gc, Rootable};
+use hashbrown::HashMap;
+
+use crate::{
+    Callback, CallbackReturn, Context, Error, FromMultiValue, IntoMultiValue, IntoValue,
+    MetaMethod, Singleton, Table, Value,
+};
+
+use super::{DynCasts, UserData};
+
+/// A `'static` Rust type which declares the methods, fields, and metamethods it exposes to Lua.
+///
+/// Values of these types are created with [`UserData::new_typed`], which gives them a metatable
+/// generated from the declarations here. The metatable is only generated once per type for each
+/// [`Lua`](crate::Lua) instance, and is cached in the [`Registry`](crate::Registry).
+///
+/// Methods and fields only receive a shared reference to the held value, types which are mutated
+/// from Lua should use interior mutability (such as a `RefCell`).
+pub trait UserDataType: Sized + 'static {
+    /// If set, this is stored as the `__name` metafield, which is used by `tostring` and in type
+    /// errors.
+    const NAME: Option<&'static str> = None;
+
+    fn add_fields<'gc>(_fields: &mut UserDataFields<'gc, Self>) {}
+
+    fn add_methods<'gc>(_methods: &mut UserDataMethods<'gc, Self>) {}
+
+    /// Declare the `dyn Trait` objects that values of this type can be borrowed as with
+    /// [`UserData::downcast_dyn`].
+    fn add_casts(_casts: &mut DynCasts) {}
+}
+
+/// Builder for the methods and metamethods of a [`UserDataType`].
+pub struct UserDataMethods<'gc, T> {
+    ctx: Context<'gc>,
+    methods: Table<'gc>,
+    metatable: Table<'gc>,
+    _marker: PhantomData<T>,
+}
+
+impl<'gc, T: UserDataType> UserDataMethods<'gc, T> {
+    /// Add a method, called from Lua as `value:name(...)`.
+    pub fn add_method<A, R, F>(&mut self, name: &'static str, method: F)
+    where
+        A: FromMultiValue<'gc>,
+        R: IntoMultiValue<'gc>,
+        F: Fn(&T, Context<'gc>, A) -> Result<R, Error<'gc>> + 'static,
+    {
+        self.methods
+            .set(self.ctx, name, method_callback(self.ctx, method))
+            .unwrap();
+    }
+
+    /// Add a function which does not take the userdata value, called from Lua as
+    /// `value.name(...)`.
+    pub fn add_function<A, R, F>(&mut self, name: &'static str, function: F)
+    where
+        A: FromMultiValue<'gc>,
+        R: IntoMultiValue<'gc>,
+        F: Fn(Context<'gc>, A) -> Result<R, Error<'gc>> + 'static,
+    {
+        self.methods
+            .set(self.ctx, name, function_callback(self.ctx, function))
+            .unwrap();
+    }
+
+    /// Add a metamethod whose first argument is the userdata value.
+    ///
+    /// Adding [`MetaMethod::Index`] or [`MetaMethod::NewIndex`] replaces the generated method and
+    /// field lookup.
+    pub fn add_meta_method<A, R, F>(&mut self, meta: MetaMethod, method: F)
+    where
+        A: FromMultiValue<'gc>,
+        R: IntoMultiValue<'gc>,
+        F: Fn(&T, Context<'gc>, A) -> Result<R, Error<'gc>> + 'static,
+    {
+        self.metatable
+            .set(self.ctx, meta, method_callback(self.ctx, method))
+            .unwrap();
+    }
+
+    /// Add a metamethod which receives all of its arguments unconverted.
+    ///
+    /// This is useful for binary operators, where the userdata value may be either operand.
+    pub fn add_meta_function<A, R, F>(&mut self, meta: MetaMethod, function: F)
+    where
+        A: FromMultiValue<'gc>,
+        R: IntoMultiValue<'gc>,
+        F: Fn(Context<'gc>, A) -> Result<R, Error<'gc>> + 'static,
+    {
+        self.metatable
+            .set(self.ctx, meta, function_callback(self.ctx, function))
+            .unwrap();
+    }
+}
+
+/// Builder for the fields of a [`UserDataType`].
+pub struct UserDataFields<'gc, T> {
+    ctx: Context<'gc>,
+    getters: Table<'gc>,
+    setters: Table<'gc>,
+    _marker: PhantomData<T>,
+}
+
+impl<'gc, T: UserDataType> UserDataFields<'gc, T> {
+    /// Add a field which is read from Lua as `value.name`.
+    pub fn add_field_method_get<R, F>(&mut self, name: &'static str, getter: F)
+    where
+        R: IntoMultiValue<'gc>,
+        F: Fn(&T, Context<'gc>) -> Result<R, Error<'gc>> + 'static,
+    {
+        self.getters
+            .set(
+                self.ctx,
+                name,
+                method_callback::<T, (), R, _>(self.ctx, move |this, ctx, ()| getter(this, ctx)),
+            )
+            .unwrap();
+    }
+
+    /// Add a field which is written from Lua as `value.name = v`.
+    pub fn add_field_method_set<A, F>(&mut self, name: &'static str, setter: F)
+    where
+        A: FromMultiValue<'gc>,
+        F: Fn(&T, Context<'gc>, A) -> Result<(), Error<'gc>> + 'static,
+    {
+        self.setters
+            .set(self.ctx, name, method_callback(self.ctx, setter))
+            .unwrap();
+    }
+}
+
+impl<'gc> UserData<'gc> {
+    /// Create a new `UserData` holding a [`UserDataType`], with its metatable set to the one
+    /// generated for `T`.
+    ///
+    /// The value can be downcast with [`UserData::downcast_static`], and with
+    /// [`UserData::downcast_dyn`] to any of the casts declared in [`UserDataType::add_casts`].
+    pub fn new_typed<T: UserDataType>(ctx: Context<'gc>, value: T) -> Self {
+        let info = type_info::<T>(ctx);
+        let ud = match info.casts {
+            Some(casts) => UserData::new_static_dyn(&ctx, value, casts),
+            None => UserData::new_static(&ctx, value),
+        };
+        ud.set_metatable(&ctx, Some(info.metatable));
+        ud
+    }
+
+    /// Returns the metatable generated for the [`UserDataType`] `T`.
+    ///
+    /// The metatable is generated the first time it is requested and is shared by every value of
+    /// type `T` created with [`UserData::new_typed`].
+    pub fn type_metatable<T: UserDataType>(ctx: Context<'gc>) -> Table<'gc> {
+        type_info::<T>(ctx).metatable
+    }
+}
+
+#[derive(Copy, Clone, Collect)]
+#[collect(no_drop)]
+struct TypeInfo<'gc> {
+    metatable: Table<'gc>,
+    casts: Option<Gc<'gc, DynCasts>>,
+}
+
+fn type_info<'gc, T: UserDataType>(ctx: Context<'gc>) -> TypeInfo<'gc> {
+    #[derive(Copy, Clone, Collect)]
+    #[collect(no_drop)]
+    struct TypeInfos<'gc>(
+        Gc<
+            'gc,
+            RefLock<HashMap<TypeId, TypeInfo<'gc>, BuildHasherDefault<AHasher>, MetricsAlloc<'gc>>>,
+        >,
+    );
+
+    impl<'gc> Singleton<'gc> for TypeInfos<'gc> {
+        fn create(ctx: Context<'gc>) -> Self {
+            Self(Gc::new(
+                &ctx,
+                RefLock::new(HashMap::with_hasher_in(
+                    BuildHasherDefault::default(),
+                    MetricsAlloc::new(&ctx),
+                )),
+            ))
+        }
+    }
+
+    let infos = ctx.singleton::<Rootable![TypeInfos<'_>]>().0;
+    if let Some(&info) = infos.borrow().get(&TypeId::of::<T>()) {
+        return info;
+    }
+
+    // Generating the metatable runs user code, which may itself request other metatables, so
+    // the cache must not be borrowed while it is built.
+    let metatable = generate_metatable::<T>(ctx);
+    let mut casts = DynCasts::new();
+    T::add_casts(&mut casts);
+    let info = TypeInfo {
+        metatable,
+        casts: (!casts.is_empty()).then(|| Gc::new(&ctx, casts)),
+    };
+    infos.borrow_mut(&ctx).insert(TypeId::of::<T>(), info);
+    info
+}
+
+fn generate_metatable<'gc, T: UserDataType>(ctx: Context<'gc>) -> Table<'gc> {
+    let mut fields = UserDataFields::<T> {
+        ctx,
+        getters: Table::new(&ctx),
+        setters: Table::new(&ctx),
+        _marker: PhantomData,
+    };
+    T::add_fields(&mut fields);
+
+    let mut methods = UserDataMethods::<T> {
+        ctx,
+        methods: Table::new(&ctx),
+        metatable: Table::new(&ctx),
+        _marker: PhantomData,
+    };
+    T::add_methods(&mut methods);
+
+    let metatable = methods.metatable;
+    if let Some(name) = T::NAME {
+        metatable.set(ctx, "__name", name).unwrap();
+    }
+
+    if metatable.get_value(ctx, MetaMethod::Index).is_nil() {
+        let index = if fields.getters.iter().next().is_none() {
+            methods.methods.into_value(ctx)
+        } else {
+            Callback::from_fn_with(
+                &ctx,
+                (fields.getters, methods.methods),
+                |&(getters, methods), ctx, _, mut stack| {
+                    let (this, key): (Value, Value) = stack.consume(ctx)?;
+                    match getters.get_value(ctx, key) {
+                        Value::Function(getter) => {
+                            stack.replace(ctx, this);
+                            Ok(CallbackReturn::Call {
+                                function: getter,
+                                then: None,
+                            })
+                        }
+                        _ => {
+                            stack.replace(ctx, methods.get_value(ctx, key));
+                            Ok(CallbackReturn::Return)
+                        }
+                    }
+                },
+            )
+            .into_value(ctx)
+        };
+        metatable.set(ctx, MetaMethod::Index, index).unwrap();
+    }
+
+    if metatable.get_value(ctx, MetaMethod::NewIndex).is_nil()
+        && fields.setters.iter().next().is_some()
+    {
+        let new_index =
+            Callback::from_fn_with(&ctx, fields.setters, |setters, ctx, _, mut stack| {
+                let (this, key, value): (Value, Value, Value) = stack.consume(ctx)?;
+                match setters.get_value(ctx, key) {
+                    Value::Function(setter) => {
+                        stack.replace(ctx, (this, value));
+                        Ok(CallbackReturn::Call {
+                            function: setter,
+                            then: None,
+                        })
+                    }
+                    _ => Err(format!("cannot set field '{}' of userdata", key.display())
+                        .into_value(ctx)
+                        .into()),
+                }
+            });
+        metatable.set(ctx, MetaMethod::NewIndex, new_index).unwrap();
+    }
+
+    metatable
+}
+
+fn method_callback<'gc, T, A, R, F>(ctx: Context<'gc>, method: F) -> Callback<'gc>
+where
+    T: 'static,
+    A: FromMultiValue<'gc>,
+    R: IntoMultiValue<'gc>,
+    F: Fn(&T, Context<'gc>, A) -> Result<R, Error<'gc>> + 'static,
+{
+    Callback::from_fn(&ctx, move |ctx, _, mut stack| {
+        let this: UserData = stack.from_front(ctx)?;
+        let args: A = stack.consume(ctx)?;
+        let ret = method(this.downcast_static::<T>()?, ctx, args)?;
+        stack.replace(ctx, ret);
+        Ok(CallbackReturn::Return)
+    })
+}
+
+fn function_callback<'gc, A, R, F>(ctx: Context<'gc>, function: F) -> Callback<'gc>
+where
+    A: FromMultiValue<'gc>,
+    R: IntoMultiValue<'gc>,
+    F: Fn(Context<'gc>, A) -> Result<R, Error<'gc>> + 'static,
+{
+    Callback::from_fn(&ctx, move |ctx, _, mut stack| {
+        let args: A = stack.consume(ctx)?;
+        let ret = function(ctx, args)?;
+        stack.replace(ctx, ret);
+        Ok(CallbackReturn::Return)
+    })
+}
@@ -0,0 +1,9 @@
+mod dyn_casts;
+mod methods;
+mod userdata;
+
+pub use self::{
+    dyn_casts::DynCasts,
+    methods::{UserDataFields, UserDataMethods, UserDataType},
+    userdata::{BadUserDataType, UserData, UserDataInner, UserDataMeta, UserDataMetaState},
+};
@@ -1,24 +1,49 @@
 use std::{
+    fmt,
     hash::{Hash, Hasher},
     mem,
 };
 
 use gc_arena::{arena::Root, barrier, lock, Collect, Gc, Mutation, Rootable, Static};
-use thiserror::Error;
 
 use crate::{
     any::{Any, AnyInner},
-    Table,
+    tags, Context, String, Table,
 };
 
-#[derive(Debug, Copy, Clone, Error)]
-#[error("UserData type mismatch")]
-pub struct BadUserDataType;
+use super::DynCasts;
+
+#[derive(Debug, Clone, Default)]
+pub struct BadUserDataType {
+    found_name: Option<Box<str>>,
+}
+
+impl BadUserDataType {
+    /// The `__name` metafield of the mismatched userdata, if its metatable has one.
+    pub fn found_name(&self) -> Option<&str> {
+        self.found_name.as_deref()
+    }
+}
+
+impl fmt::Display for BadUserDataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "UserData type mismatch")?;
+        if let Some(name) = &self.found_name {
+            write!(f, ", found {name}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for BadUserDataType {}
 
 #[derive(Debug, Copy, Clone, Default, Collect)]
 #[collect(no_drop)]
 pub struct UserDataMeta<'gc> {
     pub metatable: Option<Table<'gc>>,
+    pub tag: Option<u32>,
+    /// The `dyn Trait` objects the value can be borrowed as, see [`UserData::downcast_dyn`].
+    pub casts: Option<Gc<'gc, DynCasts>>,
 }
 
 pub type UserDataMetaState<'gc> = lock::Lock<UserDataMeta<'gc>>;
@@ -94,6 +119,23 @@ impl<'gc> UserData<'gc> {
         Self::new::<Static<T>>(mc, Static(val))
     }
 
+    /// Create a new `UserData` from a non-GC value which can also be borrowed as any of the
+    /// `dyn Trait` objects in `casts`, see [`UserData::downcast_dyn`].
+    pub fn new_static_dyn<T: 'static>(
+        mc: &Mutation<'gc>,
+        val: T,
+        casts: Gc<'gc, DynCasts>,
+    ) -> Self {
+        UserData(Any::with_metadata::<Static<T>>(
+            mc,
+            lock::Lock::new(UserDataMeta {
+                casts: Some(casts),
+                ..Default::default()
+            }),
+            Static(val),
+        ))
+    }
+
     pub fn from_inner(inner: Gc<'gc, UserDataInner<'gc>>) -> Self {
         Self(Any::from_inner(inner))
     }
@@ -130,7 +172,7 @@ impl<'gc> UserData<'gc> {
         R: for<'b> Rootable<'b> + 'static,
         Root<'gc, R>: Sized,
     {
-        self.0.downcast::<R>().ok_or(BadUserDataType)
+        self.0.downcast::<R>().ok_or_else(|| self.bad_type())
     }
 
     /// Downcast the `UserData` and get a reference to it wrapped in [`barrier::Write`].
@@ -149,7 +191,9 @@ impl<'gc> UserData<'gc> {
         R: for<'b> Rootable<'b> + 'static,
         Root<'gc, R>: Sized,
     {
-        self.0.downcast_write::<R>(mc).ok_or(BadUserDataType)
+        self.0
+            .downcast_write::<R>(mc)
+            .ok_or_else(|| self.bad_type())
     }
 
     /// Downcast a `'static` `UserData` and get a reference to it.
@@ -163,10 +207,36 @@ impl<'gc> UserData<'gc> {
         self.downcast::<Static<T>>().map(|r| &r.0)
     }
 
+    /// Borrow the held value as the `dyn Trait` object `D`.
+    ///
+    /// This works for any `UserData` created with a [`DynCasts`] containing a cast to `D` that
+    /// matches the type of the held value, such as with [`UserData::new_static_dyn`] or
+    /// [`UserData::new_typed`]. Otherwise, returns `Err(BadUserDataType)`.
+    pub fn downcast_dyn<D: ?Sized + 'static>(self) -> Result<&'gc D, BadUserDataType> {
+        self.0
+            .metadata()
+            .get()
+            .casts
+            .and_then(|casts| Gc::as_ref(casts).cast::<D>(self))
+            .ok_or_else(|| self.bad_type())
+    }
+
+    /// Returns true if [`UserData::downcast_dyn`] would succeed for `D`.
+    pub fn is_dyn<D: ?Sized + 'static>(self) -> bool {
+        self.downcast_dyn::<D>().is_ok()
+    }
+
     pub fn metatable(self) -> Option<Table<'gc>> {
         self.0.metadata().get().metatable
     }
 
+    /// Returns the `__name` field of this userdata's metatable, if it is set to a string.
+    ///
+    /// This is the name used for the userdata by `tostring` and in type errors.
+    pub fn name(self) -> Option<String<'gc>> {
+        self.metatable()?.name_field()
+    }
+
     pub fn set_metatable(
         self,
         mc: &Mutation<'gc>,
@@ -178,4 +248,32 @@ impl<'gc> UserData<'gc> {
         md.set(v);
         old_metatable
     }
+
+    /// Returns the tag set with [`UserData::set_tag`].
+    pub fn tag(self) -> Option<u32> {
+        self.0.metadata().get().tag
+    }
+
+    /// Attach a host-defined tag to this userdata, replacing any previous tag.
+    ///
+    /// Tagged objects are counted by [`memory_by_tag`](crate::tags::memory_by_tag).
+    pub fn set_tag(self, ctx: Context<'gc>, tag: u32) {
+        let md = self.0.write_metadata(&ctx).unlock();
+        let mut v = md.get();
+        if v.tag.replace(tag).is_none() {
+            tags::register_userdata(ctx, self.into_inner());
+        }
+        md.set(v);
+    }
+
+    /// The size in bytes of the allocation holding this userdata's value.
+    pub fn allocation_size(self) -> usize {
+        self.0.allocation_size()
+    }
+
+    fn bad_type(self) -> BadUserDataType {
+        BadUserDataType {
+            found_name: self.name().map(|n| n.display_lossy().to_string().into()),
+        }
+    }
 }
@@ -27,6 +27,12 @@ impl<'gc> Default for Value<'gc> {
     }
 }
 
+impl<'gc> fmt::Display for Value<'gc> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.display(), f)
+    }
+}
+
 impl<'gc> Value<'gc> {
     pub fn type_name(self) -> &'static str {
         match self {
@@ -43,15 +49,22 @@ impl<'gc> Value<'gc> {
 
     /// Returns a proxy object which can display any `Value`.
     ///
-    /// [`Value::Nil`] is printed as "nil", booleans, integers, and numbers are always printed as
-    /// directly as they would be from Rust.
+    /// [`Value::Nil`] is printed as "nil", and booleans and integers are printed as they would be
+    /// from Rust. Floats are printed like PUC-Rio Lua does, using the C format `%.14g` and adding a
+    /// trailing `.0` when the result would otherwise look like an integer, so `1.0` is printed as
+    /// `"1.0"` and `1e100` as `"1e+100"`. NaN is printed as `"nan"` or `"-nan"` depending on its
+    /// sign bit.
     ///
     /// [`Value::String`] is printed using the [`String::display_lossy`] method, which displays
     /// strings in a lossy fashion if they are not UTF-8 internally.
     ///
     /// [`Value::Table`]s, [`Value::Function`]s, [`Value::Thread`]s, and [`Value::UserData`]
     /// are all printed as `"<typename {:p}>"`, where 'typename' is the value returned by
-    /// [`Value::type_name`].
+    /// [`Value::type_name`]. Tables and userdata with a `__name` metafield are printed as
+    /// `"name: {:p}"`, like PUC-Rio Lua.
+    ///
+    /// This is the same as the result of the `tostring` builtin for values without a `__tostring`
+    /// metamethod, and is also how the `Display` impl for `Value` formats values.
     pub fn display(self) -> impl fmt::Display + 'gc {
         struct ValueDisplay<'gc>(Value<'gc>);
 
@@ -61,19 +74,41 @@ impl<'gc> Value<'gc> {
                     Value::Nil => write!(fmt, "nil"),
                     Value::Boolean(b) => write!(fmt, "{}", b),
                     Value::Integer(i) => write!(fmt, "{}", i),
-                    Value::Number(f) => write!(fmt, "{}", f),
+                    Value::Number(f) => write!(fmt, "{}", FloatDisplay(f)),
                     Value::String(s) => write!(fmt, "{}", s.display_lossy()),
-                    Value::Table(t) => write!(fmt, "<table {:p}>", Gc::as_ptr(t.into_inner())),
+                    Value::Table(t) => match t.name() {
+                        Some(name) => write!(
+                            fmt,
+                            "{}: {:p}",
+                            name.display_lossy(),
+                            Gc::as_ptr(t.into_inner())
+                        ),
+                        None => write!(fmt, "<table {:p}>", Gc::as_ptr(t.into_inner())),
+                    },
                     Value::Function(Function::Closure(c)) => {
                         write!(fmt, "<function {:p}>", Gc::as_ptr(c.into_inner()))
                     }
                     Value::Function(Function::Callback(c)) => {
                         write!(fmt, "<function {:p}>", Gc::as_ptr(c.into_inner()))
                     }
-                    Value::Thread(t) => write!(fmt, "<thread {:p}>", Gc::as_ptr(t.into_inner())),
-                    Value::UserData(u) => {
-                        write!(fmt, "<userdata {:p}>", Gc::as_ptr(u.into_inner()))
-                    }
+                    Value::Thread(t) => match t.name() {
+                        Some(name) => write!(
+                            fmt,
+                            "<thread '{}' {:p}>",
+                            name.display_lossy(),
+                            Gc::as_ptr(t.into_inner())
+                        ),
+                        None => write!(fmt, "<thread {:p}>", Gc::as_ptr(t.into_inner())),
+                    },
+                    Value::UserData(u) => match u.name() {
+                        Some(name) => write!(
+                            fmt,
+                            "{}: {:p}",
+                            name.display_lossy(),
+                            Gc::as_ptr(u.into_inner())
+                        ),
+                        None => write!(fmt, "<userdata {:p}>", Gc::as_ptr(u.into_inner())),
+                    },
                 }
             }
         }
@@ -94,6 +129,29 @@ impl<'gc> Value<'gc> {
         }
     }
 
+    /// The number stored in this value, if it is an Integer or a Number.
+    ///
+    /// Unlike [`Value::to_numeric`], strings are never converted.
+    pub fn number_kind(self) -> Option<NumberKind> {
+        match self {
+            Value::Integer(i) => Some(NumberKind::Integer(i)),
+            Value::Number(n) => Some(NumberKind::Float(n)),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as an integer if it is an Integer, or a Number with an exact integer
+    /// representation. Strings are never converted, see [`NumberKind::as_integer_exact`].
+    pub fn as_integer_exact(self) -> Option<i64> {
+        self.number_kind()?.as_integer_exact()
+    }
+
+    /// Returns the value as a float if it is a Number, or an Integer converted to the nearest
+    /// float. Strings are never converted, see [`NumberKind::as_float_lossy`].
+    pub fn as_float_lossy(self) -> Option<f64> {
+        Some(self.number_kind()?.as_float_lossy())
+    }
+
     /// Converts value to either a Number or an Integer, if possible.
     pub fn to_numeric(self) -> Option<Self> {
         self.to_constant()
@@ -118,7 +176,7 @@ impl<'gc> Value<'gc> {
     pub fn into_string(self, ctx: crate::Context<'gc>) -> Option<String<'gc>> {
         match self {
             Value::Integer(i) => Some(ctx.intern(i.to_string().as_bytes())),
-            Value::Number(n) => Some(ctx.intern(n.to_string().as_bytes())),
+            Value::Number(n) => Some(ctx.intern(FloatDisplay(n).to_string().as_bytes())),
             Value::String(s) => Some(s),
             _ => None,
         }
@@ -147,6 +205,58 @@ impl<'gc> Value<'gc> {
     }
 }
 
+/// A Lua number, which since Lua 5.4 is either an integer or a float.
+///
+/// The two kinds are distinct: arithmetic on two integers produces an integer (wrapping on
+/// overflow), and a float only converts to an integer if it has an exact integer representation.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NumberKind {
+    Integer(i64),
+    Float(f64),
+}
+
+impl NumberKind {
+    /// Returns the number as an integer without any loss of precision, if possible.
+    ///
+    /// Floats convert only if they are integral and within the range of `i64`, so for example
+    /// `2^63` does not convert, even though `(2^63) as i64` saturates to `i64::MAX`.
+    pub fn as_integer_exact(self) -> Option<i64> {
+        match self {
+            NumberKind::Integer(i) => Some(i),
+            NumberKind::Float(n) => f64_to_i64_exact(n),
+        }
+    }
+
+    /// Returns the number as a float, rounding integers too large to be represented exactly.
+    pub fn as_float_lossy(self) -> f64 {
+        match self {
+            NumberKind::Integer(i) => i as f64,
+            NumberKind::Float(n) => n,
+        }
+    }
+}
+
+impl<'gc> From<NumberKind> for Value<'gc> {
+    fn from(n: NumberKind) -> Value<'gc> {
+        match n {
+            NumberKind::Integer(i) => Value::Integer(i),
+            NumberKind::Float(n) => Value::Number(n),
+        }
+    }
+}
+
+// Converts an integral float in the range of `i64` to an integer, like `lua_numbertointeger`.
+pub(crate) fn f64_to_i64_exact(n: f64) -> Option<i64> {
+    // `i64::MIN` is exactly representable as a float but `i64::MAX` is not, so the upper bound is
+    // exclusive. NaN fails both comparisons.
+    const MIN: f64 = i64::MIN as f64;
+    if n >= MIN && n < -MIN && n.fract() == 0.0 {
+        Some(n as i64)
+    } else {
+        None
+    }
+}
+
 impl<'gc> From<bool> for Value<'gc> {
     fn from(v: bool) -> Value<'gc> {
         Value::Boolean(v)
@@ -221,3 +331,57 @@ impl<'gc> From<UserData<'gc>> for Value<'gc> {
         Value::UserData(v)
     }
 }
+
+/// Displays a float the same way as PUC-Rio Lua converts floats to strings.
+///
+/// The float is formatted with the C format `%.14g`, and `.0` is appended if the result would
+/// otherwise look like an integer.
+pub(crate) struct FloatDisplay(pub f64);
+
+impl fmt::Display for FloatDisplay {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const PRECISION: i32 = 14;
+
+        let n = self.0;
+        if n.is_nan() {
+            // C prints the sign of NaN, and `0/0` produces a negative NaN on most platforms.
+            return fmt.write_str(if n.is_sign_negative() { "-nan" } else { "nan" });
+        } else if n.is_infinite() {
+            return fmt.write_str(if n < 0.0 { "-inf" } else { "inf" });
+        } else if n == 0.0 {
+            return fmt.write_str(if n.is_sign_negative() { "-0.0" } else { "0.0" });
+        }
+
+        fn trim_fraction(s: &str) -> &str {
+            if s.contains('.') {
+                s.trim_end_matches('0').trim_end_matches('.')
+            } else {
+                s
+            }
+        }
+
+        // `%g` picks between fixed and scientific notation using the exponent of the value *after*
+        // rounding to the requested number of significant digits.
+        let scientific = format!("{:.*e}", (PRECISION - 1) as usize, n);
+        let (mantissa, exponent) = scientific.split_once('e').unwrap();
+        let exponent: i32 = exponent.parse().unwrap();
+
+        if exponent < -4 || exponent >= PRECISION {
+            write!(
+                fmt,
+                "{}e{}{:02}",
+                trim_fraction(mantissa),
+                if exponent < 0 { '-' } else { '+' },
+                exponent.abs()
+            )
+        } else {
+            let fixed = format!("{:.*}", (PRECISION - 1 - exponent) as usize, n);
+            let fixed = trim_fraction(&fixed);
+            if fixed.contains('.') {
+                fmt.write_str(fixed)
+            } else {
+                write!(fmt, "{}.0", fixed)
+            }
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use piccolo::{FunctionPrototype, Lua};
+
+#[test]
+fn function_annotations() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let proto = FunctionPrototype::compile_with_annotations(
+            ctx,
+            "test",
+            &br#"
+                ---@param x number
+                ---@param y number
+                ---@return number
+                function add(x, y)
+                    return x + y
+                end
+
+                ---@deprecated
+                local x = 1
+
+                -- not an annotation
+                ---@return string the name
+                local function name()
+                    return "name"
+                end
+            "#[..],
+        )
+        .unwrap();
+
+        assert!(proto.annotations.is_empty());
+        assert_eq!(proto.prototypes.len(), 2);
+
+        let add = &proto.prototypes[0].annotations;
+        assert_eq!(add.len(), 3);
+        assert_eq!(add[0].tag, "param");
+        assert_eq!(add[0].text, "x number");
+        assert_eq!(add[1].text, "y number");
+        assert_eq!(add[2].tag, "return");
+        assert_eq!(add[2].text, "number");
+
+        let name = &proto.prototypes[1].annotations;
+        assert_eq!(name.len(), 1);
+        assert_eq!(name[0].tag, "return");
+        assert_eq!(name[0].text, "string the name");
+    });
+
+    lua.enter(|ctx| {
+        let proto =
+            FunctionPrototype::compile(ctx, "test", &b"---@return nil\nfunction f() end"[..])
+                .unwrap();
+        assert!(proto.prototypes[0].annotations.is_empty());
+    });
+}
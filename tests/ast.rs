@@ -0,0 +1,47 @@
+use piccolo::{
+    compiler::ast::{HeadExpression, SimpleExpression, Statement},
+    Closure, CompileOptions, Executor, ExternError, FunctionPrototype, Lua,
+};
+
+#[test]
+fn transform_ast() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let mut chunk = FunctionPrototype::parse(
+            ctx,
+            &b"local a = 'original'\nreturn a"[..],
+            CompileOptions::default(),
+        )?;
+
+        // Replace the string constant assigned to `a`.
+        let Statement::LocalStatement(local) = &mut chunk.block.statements[0].inner else {
+            panic!("expected a local statement");
+        };
+        let HeadExpression::Simple(SimpleExpression::String(s)) = &mut *local.values[0].head else {
+            panic!("expected a string");
+        };
+        *s = ctx.intern(b"replaced");
+
+        // Inject statements parsed from another chunk at the start of the block.
+        let prologue = FunctionPrototype::parse(
+            ctx,
+            &b"calls = (calls or 0) + 1"[..],
+            CompileOptions::default(),
+        )?;
+        chunk
+            .block
+            .statements
+            .splice(0..0, prologue.block.statements);
+
+        let proto =
+            FunctionPrototype::compile_ast(ctx, "transformed", &chunk, CompileOptions::default())?;
+        let closure = Closure::new(&ctx, proto, Some(ctx.globals()))?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    assert_eq!(lua.execute::<String>(&executor)?, "replaced");
+    lua.enter(|ctx| assert_eq!(ctx.get_global::<i64>("calls").unwrap(), 1));
+
+    Ok(())
+}
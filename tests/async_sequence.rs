@@ -1,6 +1,16 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Wake, Waker},
+};
+
 use piccolo::{
-    async_sequence, meta_ops, Callback, CallbackReturn, Closure, Executor, ExternError, Lua,
-    SequenceReturn, Table, Variadic,
+    async_sequence, meta_ops, Callback, CallbackReturn, Closure, Error, Executor, ExecutorMode,
+    ExternError, Fuel, Lua, SequenceReturn, StashedExecutor, Table, Variadic,
 };
 
 #[test]
@@ -56,3 +66,136 @@ fn async_sequence_works() -> Result<(), ExternError> {
 
     Ok(())
 }
+
+#[derive(Default)]
+struct Slot {
+    value: Option<i64>,
+    waker: Option<Waker>,
+}
+
+// A host future which completes once a value has been sent through the shared slot.
+struct Receive(Arc<Mutex<Slot>>);
+
+impl Future for Receive {
+    type Output = i64;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<i64> {
+        let mut slot = self.0.lock().unwrap();
+        match slot.value.take() {
+            Some(value) => Poll::Ready(value),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct CountingWaker(AtomicUsize);
+
+impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+fn start_receiving(lua: &mut Lua, slot: &Arc<Mutex<Slot>>) -> Result<StashedExecutor, ExternError> {
+    let slot = slot.clone();
+    lua.try_enter(|ctx| {
+        let callback = Callback::from_fn(&ctx, move |ctx, _, _| {
+            let receive = Receive(slot.clone());
+            let seq = async_sequence(&ctx, |_, mut seq| async move {
+                let value = seq.await_external(receive).await?;
+                seq.enter(|ctx, _, _, mut stack| stack.replace(ctx, value));
+                Ok(SequenceReturn::Return)
+            });
+            Ok(CallbackReturn::Sequence(seq))
+        });
+        ctx.set_global("receive", callback);
+
+        let closure = Closure::load(ctx, None, &b"return receive() + 1"[..])?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })
+}
+
+#[test]
+fn await_external_future() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    let slot = Arc::new(Mutex::new(Slot::default()));
+    let executor = start_receiving(&mut lua, &slot)?;
+    let waker = Arc::new(CountingWaker::default());
+
+    lua.enter(|ctx| {
+        let executor = ctx.fetch(&executor);
+        executor
+            .set_waker(&ctx, Some(waker.clone().into()))
+            .unwrap();
+        assert!(executor.step(ctx, &mut Fuel::with(1000)).unwrap());
+        assert_eq!(executor.mode(), ExecutorMode::Suspended);
+        assert!(executor.is_awaiting_external());
+
+        // Resuming before the future is woken just polls it again.
+        executor.resume(ctx, ()).unwrap();
+        assert!(!executor.is_awaiting_external());
+        assert!(executor.step(ctx, &mut Fuel::with(1000)).unwrap());
+        assert!(executor.is_awaiting_external());
+    });
+
+    let mut locked = slot.lock().unwrap();
+    locked.value = Some(41);
+    locked.waker.take().unwrap().wake();
+    drop(locked);
+    assert_eq!(waker.0.load(Ordering::SeqCst), 1);
+
+    lua.enter(|ctx| ctx.fetch(&executor).resume(ctx, ()).unwrap());
+    assert_eq!(lua.execute::<i64>(&executor)?, 42);
+
+    Ok(())
+}
+
+#[test]
+fn await_external_cancelled() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    let slot = Arc::new(Mutex::new(Slot::default()));
+    let executor = start_receiving(&mut lua, &slot)?;
+
+    lua.enter(|ctx| {
+        let executor = ctx.fetch(&executor);
+        assert!(executor.step(ctx, &mut Fuel::with(1000)).unwrap());
+        assert!(executor.is_awaiting_external());
+        executor
+            .resume_err(&ctx, Error::from_value(ctx.intern(b"cancelled").into()))
+            .unwrap();
+    });
+
+    let err = lua.execute::<i64>(&executor).unwrap_err();
+    assert!(err.to_string().contains("cancelled"));
+
+    Ok(())
+}
+
+#[test]
+fn await_external_execute_async() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    let slot = Arc::new(Mutex::new(Slot::default()));
+    let executor = start_receiving(&mut lua, &slot)?;
+
+    let waker = Arc::new(CountingWaker::default());
+    let task_waker = waker.clone().into();
+    let mut cx = Context::from_waker(&task_waker);
+    let mut future = std::pin::pin!(lua.execute_async::<i64>(&executor));
+
+    assert!(future.as_mut().poll(&mut cx).is_pending());
+    assert_eq!(waker.0.load(Ordering::SeqCst), 0);
+
+    let mut locked = slot.lock().unwrap();
+    locked.value = Some(9);
+    locked.waker.take().unwrap().wake();
+    drop(locked);
+    assert_eq!(waker.0.load(Ordering::SeqCst), 1);
+
+    assert!(matches!(future.as_mut().poll(&mut cx), Poll::Ready(Ok(10))));
+
+    Ok(())
+}
@@ -2,8 +2,9 @@ use std::pin::Pin;
 
 use gc_arena::Collect;
 use piccolo::{
-    BoxSequence, Callback, CallbackReturn, Closure, Context, Error, Execution, Executor,
-    ExternError, Function, IntoValue, Lua, Sequence, SequencePoll, Stack, String, Thread, Value,
+    meta_ops, BoxSequence, Callback, CallbackFuel, CallbackReturn, Closure, Context, Error,
+    Execution, Executor, ExternError, FromValue, Fuel, Function, IntoValue, Lua, Sequence,
+    SequencePoll, Stack, String, Table, Thread, Value, Variadic,
 };
 
 #[test]
@@ -304,3 +305,197 @@ fn resume_with_err() {
         },
     );
 }
+
+#[test]
+fn split_stack() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let scale = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (scale, mut rest) = stack.split_head::<i64>(ctx, 1)?;
+            let values: Variadic<Vec<i64>> = rest.consume(ctx)?;
+            rest.extend(values.iter().map(|v| Value::Integer(v * scale)));
+            stack.pop_front();
+            Ok(CallbackReturn::Return)
+        });
+        ctx.set_global("scale", scale);
+
+        let pad = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let tail = stack.tail::<Value>(ctx, 1)?;
+            let mut rest = stack.split_off(3);
+            rest.push_back(Value::Integer(tail.len() as i64));
+            Ok(CallbackReturn::Return)
+        });
+        ctx.set_global("pad", pad);
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br##"
+                local a, b, c = scale(3, 1, 2)
+                assert(a == 3 and b == 6 and c == nil)
+                assert(select("#", scale(2)) == 0)
+
+                assert(select("#", pad(1)) == 4)
+                local a, b, c, d = pad(1)
+                assert(a == 1 and b == nil and c == nil and d == 0)
+                local a, b, c, d, e = pad(1, 2, 3, 4)
+                assert(a == 1 and b == 2 and c == 3 and d == 4 and e == 3)
+            "##[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute::<()>(&executor)?;
+    Ok(())
+}
+
+#[test]
+fn callback_fuel() -> Result<(), ExternError> {
+    fn fuel_used(fuel_mode: CallbackFuel) -> Result<i32, ExternError> {
+        let mut lua = Lua::core();
+        let executor = lua.try_enter(|ctx| {
+            let heavy = Callback::from_fn_with_fuel(&ctx, fuel_mode, |_, mut exec, _| {
+                exec.fuel().consume(1000);
+                Ok(CallbackReturn::Return)
+            });
+            assert_eq!(heavy.fuel(), fuel_mode);
+            ctx.set_global("heavy", heavy);
+
+            let closure = Closure::load(ctx, None, &b"for i = 1, 100 do heavy() end"[..])?;
+            Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+        })?;
+
+        Ok(lua.enter(|ctx| {
+            let mut fuel = Fuel::with(i32::MAX);
+            assert!(ctx.fetch(&executor).step(ctx, &mut fuel).unwrap());
+            i32::MAX - fuel.remaining()
+        }))
+    }
+
+    let metered = fuel_used(CallbackFuel::Metered)?;
+    let fixed = fuel_used(CallbackFuel::Fixed(5))?;
+    let exempt = fuel_used(CallbackFuel::Exempt)?;
+
+    assert!(metered > 100 * 1000);
+    assert_eq!(fixed - exempt, 100 * 5);
+    assert!(exempt < 100 * 1000);
+
+    Ok(())
+}
+
+#[test]
+fn typed_callback() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let callback = Callback::from_fn_typed(
+            &ctx,
+            "scale",
+            |ctx, (factor, list, offset): (i64, Table, Option<i64>)| {
+                let offset = offset.unwrap_or(0);
+                let scaled = Table::new(&ctx);
+                for (i, v) in list.iter_array() {
+                    scaled.set(ctx, i, i64::from_value(ctx, v)? * factor + offset)?;
+                }
+                Ok((scaled, list.length()))
+            },
+        );
+        ctx.set_global("scale", callback);
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local t, n = scale(2, { 1, 2, 3 })
+                assert(n == 3 and t[1] == 2 and t[2] == 4 and t[3] == 6)
+                t = scale(2, { 1 }, 10)
+                assert(t[1] == 12)
+
+                local function err(...)
+                    local ok, e = pcall(scale, ...)
+                    assert(not ok)
+                    return e
+                end
+                assert(err("x", {}) == "bad argument #1 to 'scale' (i64 expected, got string)")
+                assert(err(1, 2) == "bad argument #2 to 'scale' (Table expected, got number)")
+                assert(err(1) == "bad argument #2 to 'scale' (Table expected, got no value)")
+                assert(err(1, {}, {}) == "bad argument #3 to 'scale' (i64 expected, got table)")
+            "#[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute::<()>(&executor)?;
+    Ok(())
+}
+
+#[test]
+fn meta_ops_callback_return() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        ctx.set_global(
+            "rust_index",
+            Callback::from_fn(&ctx, |ctx, _, stack| {
+                let (t, k) = (stack.get(0), stack.get(1));
+                Ok(meta_ops::index(ctx, t, k)?.into_callback_return(ctx, stack))
+            }),
+        );
+        ctx.set_global(
+            "rust_add",
+            Callback::from_fn(&ctx, |ctx, _, stack| {
+                let (a, b) = (stack.get(0), stack.get(1));
+                Ok(meta_ops::add(ctx, a, b)?.into_callback_return(ctx, stack))
+            }),
+        );
+        ctx.set_global(
+            "rust_less_than",
+            Callback::from_fn(&ctx, |ctx, _, stack| {
+                let (a, b) = (stack.get(0), stack.get(1));
+                Ok(meta_ops::less_than(ctx, a, b)?.into_callback_return_bool(ctx, stack))
+            }),
+        );
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local plain = { x = 1 }
+                assert(rust_index(plain, "x") == 1)
+
+                local proxy = setmetatable({}, {
+                    __index = function(t, k) return k .. "!", "extra" end,
+                })
+                local v, extra = rust_index(proxy, "y")
+                assert(v == "y!" and extra == nil)
+
+                assert(rust_add(1, 2.5) == 3.5)
+                local mt = { __add = function(a, b) return "added" end }
+                assert(rust_add(setmetatable({}, mt), 1) == "added")
+
+                assert(rust_less_than(1, 2) == true)
+                local cmp = { __lt = function(a, b) return "truthy" end }
+                assert(rust_less_than(setmetatable({}, cmp), setmetatable({}, cmp)) == true)
+
+                assert(not pcall(rust_add, {}, 1))
+            "#[..],
+        )?;
+
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute::<()>(&executor)?;
+    Ok(())
+}
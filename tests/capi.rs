@@ -0,0 +1,97 @@
+use std::{
+    ffi::{CStr, CString},
+    slice,
+};
+
+use piccolo::capi::*;
+
+unsafe fn json(vm: *mut PiccoloVm) -> String {
+    let mut len = 0;
+    let data = piccolo_result_json(vm, &mut len);
+    String::from_utf8(slice::from_raw_parts(data, len).to_vec()).unwrap()
+}
+
+unsafe fn error(vm: *mut PiccoloVm) -> String {
+    CStr::from_ptr(piccolo_error(vm))
+        .to_string_lossy()
+        .into_owned()
+}
+
+unsafe fn load(vm: *mut PiccoloVm, source: &str) -> u64 {
+    let name = CString::new("test").unwrap();
+    piccolo_load(vm, name.as_ptr(), source.as_ptr(), source.len())
+}
+
+#[test]
+fn call_functions() {
+    unsafe {
+        let vm = piccolo_new();
+
+        let chunk = load(
+            vm,
+            r#"
+                function greet(name, times)
+                    local s = ""
+                    for i = 1, times do
+                        s = s .. "hi " .. name .. "!"
+                    end
+                    return s, { n = times, list = { 1, 2.5, true } }
+                end
+            "#,
+        );
+        assert_ne!(chunk, 0);
+        assert_eq!(piccolo_call(vm, chunk), PICCOLO_OK);
+        assert_eq!(piccolo_result_count(vm), 0);
+
+        let greet = piccolo_get_global(vm, CString::new("greet").unwrap().as_ptr());
+        assert_ne!(greet, 0);
+        piccolo_push_string(vm, b"bob".as_ptr(), 3);
+        piccolo_push_integer(vm, 2);
+        assert_eq!(piccolo_call(vm, greet), PICCOLO_OK);
+        assert_eq!(piccolo_result_count(vm), 2);
+
+        let mut len = 0;
+        let data = piccolo_result_string(vm, 0, &mut len);
+        assert_eq!(slice::from_raw_parts(data, len), b"hi bob!hi bob!");
+        assert!(piccolo_result_string(vm, 1, &mut len).is_null());
+
+        let json = json(vm);
+        assert!(json.starts_with(r#"["hi bob!hi bob!",{"#));
+        assert!(json.contains(r#""n":2"#));
+        assert!(json.contains(r#""list":[1,2.5,true]"#));
+
+        piccolo_release(vm, greet);
+        assert_eq!(piccolo_call(vm, greet), PICCOLO_INVALID);
+        assert_eq!(
+            piccolo_get_global(vm, CString::new("missing").unwrap().as_ptr()),
+            0
+        );
+
+        piccolo_free(vm);
+    }
+}
+
+#[test]
+fn errors_and_limits() {
+    unsafe {
+        let vm = piccolo_new();
+
+        assert_eq!(load(vm, "local x = = 1"), 0);
+        assert!(!error(vm).is_empty());
+
+        let chunk = load(vm, "error('oops')");
+        assert_eq!(piccolo_call(vm, chunk), PICCOLO_ERROR);
+        assert!(error(vm).contains("oops"));
+
+        let chunk = load(vm, "while true do end");
+        piccolo_set_fuel_limit(vm, 10_000);
+        assert_eq!(piccolo_call(vm, chunk), PICCOLO_FUEL_EXHAUSTED);
+        piccolo_set_fuel_limit(vm, 0);
+
+        let chunk = load(vm, "local t = {} for i = 1, 1e9 do t[i] = {} end");
+        piccolo_set_memory_limit(vm, 4 << 20);
+        assert_eq!(piccolo_call(vm, chunk), PICCOLO_MEMORY_EXHAUSTED);
+
+        piccolo_free(vm);
+    }
+}
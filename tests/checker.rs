@@ -0,0 +1,84 @@
+use piccolo::compiler::{
+    check_chunk, interning::BasicInterner, parse_chunk_with_annotations, TypeWarning,
+    TypeWarningKind,
+};
+
+fn check(source: &str) -> Vec<TypeWarning> {
+    let chunk = parse_chunk_with_annotations(source.as_bytes(), BasicInterner::default()).unwrap();
+    check_chunk(&chunk)
+}
+
+#[test]
+fn annotated_arguments_and_returns() {
+    let warnings = check(
+        r#"
+            ---@param x number
+            ---@param name string?
+            ---@return string
+            local function describe(x, name)
+                if x > 0 then
+                    return 1
+                end
+                return (name or "none") .. x
+            end
+
+            describe(1, "a")
+            describe(1)
+            describe(2, nil)
+            describe({}, true)
+
+            local n = 4
+            describe(n, n)
+        "#,
+    );
+
+    let kinds = warnings.iter().map(|w| &w.kind).collect::<Vec<_>>();
+    assert_eq!(warnings.len(), 4, "{warnings:?}");
+    assert!(
+        matches!(kinds[0], TypeWarningKind::Return { index: 1, found, .. } if found == "number")
+    );
+    assert!(matches!(
+        kinds[1],
+        TypeWarningKind::Argument { parameter, found, .. } if parameter == "x" && found == "table"
+    ));
+    assert!(matches!(
+        kinds[2],
+        TypeWarningKind::Argument { parameter, found, .. }
+            if parameter == "name" && found == "boolean"
+    ));
+    assert!(matches!(
+        kinds[3],
+        TypeWarningKind::Argument { parameter, found, .. }
+            if parameter == "name" && found == "number"
+    ));
+    assert_eq!(warnings[0].line_number.0, 6);
+}
+
+#[test]
+fn local_inference() {
+    let warnings = check(
+        r#"
+            local t = {}
+            local b = true
+            local s = "s"
+            local f = function() end
+
+            local x = b + 1
+            local y = t .. s
+            b()
+            f.field = 1
+            s.len()
+            t.call()
+
+            local changed = nil
+            changed = 1
+            local z = changed + 1
+        "#,
+    );
+
+    let kinds = warnings.iter().map(|w| &w.kind).collect::<Vec<_>>();
+    assert_eq!(warnings.len(), 3, "{warnings:?}");
+    assert!(matches!(kinds[0], TypeWarningKind::Arithmetic { found } if found == "boolean"));
+    assert!(matches!(kinds[1], TypeWarningKind::Call { found } if found == "boolean"));
+    assert!(matches!(kinds[2], TypeWarningKind::Index { found } if found == "function"));
+}
@@ -0,0 +1,81 @@
+use piccolo::{ChunkCacheLimits, ChunkCacheMetrics, ExternError, Lua};
+
+mod common;
+
+use common::run;
+
+fn cache_metrics(lua: &mut Lua) -> ChunkCacheMetrics {
+    lua.enter(|ctx| ctx.chunk_cache().metrics())
+}
+
+#[test]
+fn reuses_prototypes() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    lua.set_chunk_cache(Some(ChunkCacheLimits::default()));
+
+    run(
+        &mut lua,
+        r#"
+            local results = {}
+            for i = 1, 10 do
+                local f = load("x = (x or 0) + 1; return x", "rule", "t", { x = i })
+                results[i] = f()
+            end
+            for i = 1, 10 do
+                assert(results[i] == i + 1)
+            end
+        "#,
+    )?;
+
+    let metrics = cache_metrics(&mut lua);
+    // The outer chunk and the first load of the rule are misses.
+    assert_eq!(metrics.misses, 2);
+    assert_eq!(metrics.hits, 9);
+    assert_eq!(metrics.entries, 2);
+    assert_eq!(metrics.evictions, 0);
+
+    // The chunk name is part of the key.
+    run(
+        &mut lua,
+        r#"load("return 1", "a")() load("return 1", "b")()"#,
+    )?;
+    assert_eq!(cache_metrics(&mut lua).misses, 5);
+
+    Ok(())
+}
+
+#[test]
+fn generations_are_limited() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    lua.set_chunk_cache(Some(ChunkCacheLimits {
+        max_entries: 4,
+        max_source_bytes: 1 << 20,
+    }));
+
+    run(
+        &mut lua,
+        r#"
+            for i = 1, 20 do
+                load("return " .. i)()
+                -- Keep one chunk in use so that it survives every generation.
+                load("return 0")()
+            end
+        "#,
+    )?;
+
+    let metrics = cache_metrics(&mut lua);
+    assert!(metrics.entries <= 4);
+    assert!(metrics.evictions > 0);
+    assert_eq!(metrics.hits, 19);
+
+    lua.enter(|ctx| ctx.chunk_cache().clear(&ctx));
+    assert_eq!(cache_metrics(&mut lua).entries, 0);
+
+    lua.set_chunk_cache(None);
+    run(&mut lua, "return 1")?;
+    let disabled = cache_metrics(&mut lua);
+    assert_eq!(disabled.entries, 0);
+    assert_eq!(disabled.misses, metrics.misses);
+
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use piccolo::{
+    stdlib::{class_of, is_instance, new_class},
+    Callback, CallbackReturn, Closure, Executor, ExternError, Lua, UserData,
+};
+
+#[test]
+fn userdata_instances() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    lua.load_class();
+
+    lua.try_enter(|ctx| {
+        let host = new_class(ctx, Some(ctx.intern(b"Host")), None)?;
+        host.set_field(
+            ctx,
+            "get",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let userdata: UserData = stack.consume(ctx)?;
+                stack.replace(ctx, i64::from(*userdata.downcast_static::<u8>()?));
+                Ok(CallbackReturn::Return)
+            }),
+        );
+
+        let userdata = UserData::new_static(&ctx, 5u8);
+        userdata.set_metatable(&ctx, Some(host));
+        assert!(class_of(ctx, userdata.into()) == Some(host));
+        assert!(is_instance(ctx, userdata.into(), host));
+
+        ctx.set_global("Host", host);
+        ctx.set_global("object", userdata);
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                assert(object:get() == 5)
+                assert(class.isinstance(object, Host))
+
+                -- Script classes may inherit from host classes
+                local Sub = class("Sub", Host)
+                function Sub:twice()
+                    return 2 * self.value
+                end
+                local sub = Sub()
+                sub.value = 21
+                assert(sub:twice() == 42)
+                assert(class.isinstance(sub, Host))
+                assert(not class.isinstance(object, Sub))
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)
+}
@@ -0,0 +1,55 @@
+use piccolo::{ExternError, Lua};
+
+mod common;
+
+use common::run;
+
+#[test]
+fn byte_order_by_default() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| assert!(ctx.collator().is_byte_order()));
+    run(
+        &mut lua,
+        r#"
+            assert("B" < "a")
+            assert("a" < "ab" and "" < "a")
+            assert("a\0b" < "a\1")
+            assert("\255" > "z")
+
+            local t = { "b", "a", "C", "B" }
+            table.sort(t)
+            assert(table.concat(t, ",") == "B,C,a,b")
+        "#,
+    )
+}
+
+#[test]
+fn custom_collation() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    lua.set_collation(|a: &[u8], b: &[u8]| {
+        a.to_ascii_lowercase()
+            .cmp(&b.to_ascii_lowercase())
+            .then_with(|| a.cmp(b))
+    });
+
+    run(
+        &mut lua,
+        r#"
+            assert("a" < "B")
+            assert("B" <= "b" and "B" < "b")
+            assert("b" > "A" and "b" >= "A")
+            -- Constant comparisons must not be folded using byte order
+            assert(not ("B" < "a"))
+
+            local t = { "b", "a", "C", "B" }
+            table.sort(t)
+            assert(table.concat(t, ",") == "a,B,b,C")
+
+            -- Equality is unaffected
+            assert("a" ~= "A")
+        "#,
+    )?;
+
+    lua.reset_collation();
+    run(&mut lua, r#"assert("B" < "a")"#)
+}
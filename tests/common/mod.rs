@@ -0,0 +1,21 @@
+//! Helpers shared by the integration tests, each test crate only uses some of them.
+#![allow(dead_code)]
+
+use piccolo::{Closure, Executor, ExternError, FromMultiValue, Lua};
+
+/// Load and run a chunk of Lua source to completion.
+pub fn run(lua: &mut Lua, source: &str) -> Result<(), ExternError> {
+    eval(lua, source)
+}
+
+/// Load and run a chunk of Lua source to completion, returning its results.
+pub fn eval<R: for<'gc> FromMultiValue<'gc>>(
+    lua: &mut Lua,
+    source: &str,
+) -> Result<R, ExternError> {
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, Some("test"), source.as_bytes())?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute(&executor)
+}
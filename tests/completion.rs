@@ -0,0 +1,53 @@
+use piccolo::{
+    completion::{complete, CompletionOptions},
+    Closure, Executor, Lua,
+};
+
+#[test]
+fn complete_fields() {
+    let mut lua = Lua::core();
+
+    let executor = lua
+        .try_enter(|ctx| {
+            let closure = Closure::load(
+                ctx,
+                None,
+                &br#"
+                    player = { inventory = {}, inventions = 1, health = 3 }
+                    function player:add_item() end
+                    proxy = setmetatable({}, { __index = player })
+                "#[..],
+            )?;
+            Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+        })
+        .unwrap();
+    lua.execute::<()>(&executor).unwrap();
+
+    lua.enter(|ctx| {
+        let env = ctx.globals().into();
+
+        let c = complete(ctx, env, b"x = player.inv", CompletionOptions::default());
+        assert_eq!(c.start, 11);
+        assert_eq!(c.candidates, ["inventions", "inventory"]);
+
+        let c = complete(ctx, env, b"player:", CompletionOptions::default());
+        assert_eq!(c.candidates, ["add_item"]);
+
+        let c = complete(ctx, env, b"proxy.he", CompletionOptions::default());
+        assert_eq!(c.candidates, ["health"]);
+
+        let c = complete(
+            ctx,
+            env,
+            b"proxy.he",
+            CompletionOptions {
+                follow_index_tables: false,
+                ..Default::default()
+            },
+        );
+        assert!(c.is_empty());
+
+        let c = complete(ctx, env, b"nothing.he", CompletionOptions::default());
+        assert!(c.is_empty());
+    });
+}
@@ -1,4 +1,9 @@
-use piccolo::{FromMultiValue, FromValue, IntoMultiValue, IntoValue, Lua, Table, Value};
+use std::collections::{BTreeMap, HashMap};
+
+use piccolo::{
+    Closure, Executor, FromMultiValue, FromValue, IntoMultiValue, IntoValue, Lua, NumberKind,
+    Table, Value, Variadic,
+};
 
 #[test]
 fn test_conversions() {
@@ -58,6 +63,61 @@ fn test_conversions() {
     });
 }
 
+#[test]
+fn test_multi_value_tails() {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        let (a, b) =
+            <(i64, Option<String>)>::from_multi_value(ctx, 1.into_multi_value(ctx)).unwrap();
+        assert_eq!((a, b), (1, None));
+
+        let (a, b, rest) = <(i64, i64, Variadic<Vec<Value>>)>::from_multi_value(
+            ctx,
+            (1, 2, 3, "four").into_multi_value(ctx),
+        )
+        .unwrap();
+        assert_eq!((a, b), (1, 2));
+        assert!(matches!(
+            rest.as_slice(),
+            [Value::Integer(3), Value::String(s)] if s == b"four"
+        ));
+
+        // Each item of a variadic takes as many values as it needs.
+        let pairs = Variadic(vec![("a".to_owned(), 1), ("b".to_owned(), 2)]);
+        let values = pairs.clone().into_multi_value(ctx).collect::<Vec<_>>();
+        assert_eq!(values.len(), 4);
+        let (first, rest) = <(bool, Variadic<Vec<(String, i64)>>)>::from_multi_value(
+            ctx,
+            (true, Variadic(values)).into_multi_value(ctx),
+        )
+        .unwrap();
+        assert!(first);
+        assert_eq!(rest, pairs);
+
+        // Conversion errors report the position of the bad value.
+        let err = <(i64, bool, i64)>::from_multi_value(
+            ctx,
+            (1, true, Table::new(&ctx)).into_multi_value(ctx),
+        )
+        .unwrap_err();
+        assert_eq!(err.position, Some(3));
+        assert_eq!(
+            err.to_string(),
+            "type error in value #3, expected i64, found table"
+        );
+
+        let err = <(i64, Table)>::from_multi_value(ctx, 1.into_multi_value(ctx)).unwrap_err();
+        assert_eq!(err.position, Some(2));
+
+        let err = <(bool, Variadic<Vec<(String, i64)>>)>::from_multi_value(
+            ctx,
+            (true, "a", 1, "b", Table::new(&ctx)).into_multi_value(ctx),
+        )
+        .unwrap_err();
+        assert_eq!(err.position, Some(5));
+    });
+}
+
 #[test]
 fn test_result_conversion() {
     let mut lua = Lua::core();
@@ -87,3 +147,110 @@ fn test_result_conversion() {
         ));
     });
 }
+
+#[test]
+fn test_number_kind() {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        assert_eq!(
+            Value::Integer(3).number_kind(),
+            Some(NumberKind::Integer(3))
+        );
+        assert_eq!(
+            Value::Number(3.5).number_kind(),
+            Some(NumberKind::Float(3.5))
+        );
+        assert_eq!("3".into_value(ctx).number_kind(), None);
+
+        assert_eq!(Value::Number(3.0).as_integer_exact(), Some(3));
+        assert_eq!(Value::Number(3.5).as_integer_exact(), None);
+        assert_eq!(Value::Number(f64::NAN).as_integer_exact(), None);
+        assert_eq!(
+            Value::Number(9223372036854775808.0).as_integer_exact(),
+            None
+        );
+        assert_eq!(
+            Value::Number(-9223372036854775808.0).as_integer_exact(),
+            Some(i64::MIN)
+        );
+        assert_eq!(
+            Value::Integer(i64::MAX).as_float_lossy(),
+            Some(9223372036854775808.0)
+        );
+
+        // Conversions to Rust integers agree with the VM and never saturate.
+        assert!(i64::from_value(ctx, Value::Number(9223372036854775808.0)).is_err());
+        assert_eq!(i64::from_value(ctx, Value::Number(-0.0)).unwrap(), 0);
+        assert_eq!(i64::from_value(ctx, "  12 ".into_value(ctx)).unwrap(), 12);
+    });
+}
+
+#[test]
+fn test_map_conversions() {
+    let mut lua = Lua::core();
+
+    let executor = lua.enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                return {
+                    name = "server",
+                    ports = { 80, 443 },
+                    limits = { cpu = 2, memory = 512 },
+                }
+            "#[..],
+        )
+        .unwrap();
+        ctx.stash(Executor::start(ctx, closure.into(), ()))
+    });
+    lua.finish(&executor).unwrap();
+
+    lua.enter(|ctx| {
+        let config: Table = ctx.fetch(&executor).take_result(ctx).unwrap().unwrap();
+
+        let limits =
+            HashMap::<std::string::String, i64>::from_value(ctx, config.get_value(ctx, "limits"))
+                .unwrap();
+        assert_eq!(limits.len(), 2);
+        assert_eq!(limits["cpu"], 2);
+        assert_eq!(limits["memory"], 512);
+
+        let ports = Vec::<u16>::from_value(ctx, config.get_value(ctx, "ports")).unwrap();
+        assert_eq!(ports, [80, 443]);
+
+        // Sequences are maps from integer keys.
+        let ports = BTreeMap::<i64, u16>::from_value(ctx, config.get_value(ctx, "ports")).unwrap();
+        assert_eq!(ports.into_iter().collect::<Vec<_>>(), [(1, 80), (2, 443)]);
+
+        // Every entry must convert.
+        assert!(HashMap::<std::string::String, i64>::from_value(ctx, config.into()).is_err());
+        assert!(BTreeMap::<i64, i64>::from_value(ctx, Value::Integer(1)).is_err());
+
+        let map = BTreeMap::from([("a", 1), ("b", 2)]);
+        let round_trip =
+            BTreeMap::<std::string::String, i64>::from_value(ctx, map.clone().into_value(ctx))
+                .unwrap();
+        assert_eq!(
+            round_trip,
+            BTreeMap::from([("a".to_owned(), 1), ("b".to_owned(), 2)])
+        );
+
+        let map: HashMap<i64, bool> = HashMap::from([(1, true), (5, false)]);
+        let table = Table::from_value(ctx, map.clone().into_value(ctx)).unwrap();
+        assert!(matches!(table.get_value(ctx, 5), Value::Boolean(false)));
+        assert_eq!(
+            HashMap::<i64, bool>::from_value(ctx, table.into()).unwrap(),
+            map
+        );
+
+        // Keys which cannot be table keys are skipped.
+        let table = Table::from_value(
+            ctx,
+            HashMap::from([(None, 1), (Some(2), 3)]).into_value(ctx),
+        )
+        .unwrap();
+        assert!(matches!(table.get_value(ctx, 2), Value::Integer(3)));
+        assert_eq!(table.iter().count(), 1);
+    });
+}
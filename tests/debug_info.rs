@@ -0,0 +1,94 @@
+use std::{cell::RefCell, rc::Rc};
+
+use piccolo::{
+    types::{RegisterIndex, UpValueIndex},
+    Callback, CallbackReturn, Closure, Executor, ExternError, FunctionPrototype, Lua,
+};
+
+#[test]
+fn local_variables() {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        let proto = FunctionPrototype::compile(
+            ctx,
+            "test",
+            &br#"
+                local a = 1
+                do
+                    local b = 2
+                end
+                local function f(x, y)
+                    return a + x + y
+                end
+            "#[..],
+        )
+        .unwrap();
+
+        let names = proto
+            .local_variables
+            .iter()
+            .map(|l| l.name.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b", "f"]);
+
+        let a = &proto.local_variables[0];
+        let b = &proto.local_variables[1];
+        assert!(a.start_pc > 0);
+        assert!(b.start_pc >= a.start_pc && b.end_pc <= a.end_pc);
+        assert!(a.end_pc > b.end_pc && a.end_pc <= proto.opcodes.len());
+        assert_eq!(proto.local_name(a.register, a.start_pc).unwrap(), "a");
+        assert!(proto.local_name(a.register, 0).is_none());
+        // The register of `b` is reused for `f` once `b` goes out of scope.
+        assert_eq!(proto.local_name(b.register, b.end_pc).unwrap(), "f");
+        assert_eq!(proto.active_locals(b.start_pc).count(), 2);
+
+        let f = &proto.prototypes[0];
+        let params = f
+            .active_locals(0)
+            .map(|l| l.name.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(params, ["x", "y"]);
+        assert_eq!(f.local_name(RegisterIndex(1), 0).unwrap(), "y");
+        assert_eq!(f.upvalue_name(UpValueIndex(0)).unwrap(), "a");
+        assert!(f.upvalue_name(UpValueIndex(1)).is_none());
+    });
+}
+
+#[test]
+fn upper_frame_locals() -> Result<(), ExternError> {
+    let seen = Rc::new(RefCell::new(Vec::new()));
+
+    let mut lua = Lua::core();
+    let executor = lua.try_enter(|ctx| {
+        let seen = seen.clone();
+        ctx.set_global(
+            "inspect",
+            Callback::from_fn(&ctx, move |_, exec, _| {
+                let frame = exec.upper_lua_frame().unwrap();
+                let proto = frame.closure.prototype();
+                seen.borrow_mut().extend(
+                    proto
+                        .active_locals(frame.call_opcode)
+                        .map(|l| l.name.to_str().unwrap().to_owned()),
+                );
+                Ok(CallbackReturn::Return)
+            }),
+        );
+
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local first = 1
+                local second = 2
+                inspect()
+                local third = 3
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)?;
+
+    assert_eq!(*seen.borrow(), ["first", "second"]);
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use piccolo::{
+    compiler::{
+        ast::Statement, interning::BasicInterner, parse_chunk, parse_chunk_recovering, LineNumber,
+        ParseErrorKind, ParseOptions, SourceLocation,
+    },
+    Closure, CompilerError, Lua,
+};
+
+fn compile_error(source: &str) -> CompilerError {
+    let mut lua = Lua::empty();
+    lua.enter(|ctx| Closure::load(ctx, None, source.as_bytes()).unwrap_err())
+}
+
+fn location(offset: u64, line: u64, column: u64) -> SourceLocation {
+    SourceLocation {
+        offset,
+        line_number: LineNumber(line),
+        column,
+    }
+}
+
+#[test]
+fn error_location() {
+    let err = compile_error("local x = = 1");
+    assert_eq!(err.location(), Some(location(10, 0, 10)));
+    assert_eq!(err.related_location(), None);
+
+    let err = compile_error("local x = 1\n\tlocal y = )");
+    assert_eq!(err.location(), Some(location(23, 1, 11)));
+    assert_eq!(err.location().unwrap().to_string(), "2:12");
+}
+
+#[test]
+fn related_location() {
+    // The error is at the unexpected `end`, and the related site is the unclosed `(`.
+    let err = compile_error("if x then\n  print(1\nend");
+    assert_eq!(err.location(), Some(location(20, 2, 0)));
+    assert_eq!(err.related_location(), Some(location(17, 1, 7)));
+
+    // The error is at the end of the input, and the related site is the unclosed `if`.
+    let err = compile_error("if x then\n  y = 1\n");
+    assert_eq!(err.location(), Some(location(18, 2, 0)));
+    assert_eq!(err.related_location(), Some(location(0, 0, 0)));
+
+    let err = compile_error("local t = {\n  [1] = 2,\n  x = function() return 1\n}");
+    assert_eq!(err.location(), Some(location(49, 3, 0)));
+    assert_eq!(err.related_location(), Some(location(29, 2, 6)));
+}
+
+#[test]
+fn fragment_location() {
+    let mut lua = Lua::empty();
+    let err = lua.enter(|ctx| {
+        Closure::load_fragments(
+            ctx,
+            [
+                ("prologue", &b"local a = 1\n"[..]),
+                ("user", &b"local b = 2\nlocal c = = 3\n"[..]),
+            ],
+        )
+        .unwrap_err()
+    });
+
+    assert!(matches!(&err, CompilerError::Fragment { name, .. } if name == "user"));
+    assert_eq!(err.location(), Some(location(22, 1, 10)));
+}
+
+#[test]
+fn recover_multiple_errors() {
+    let source = "local x = = 1\nlocal y = )\nprint(1)\nend\nprint(2)";
+    let recovered = parse_chunk_recovering(
+        source.as_bytes(),
+        BasicInterner::default(),
+        ParseOptions::default(),
+    );
+
+    let locations = recovered
+        .errors
+        .iter()
+        .map(|err| err.location)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        locations,
+        [location(10, 0, 10), location(24, 1, 10), location(35, 3, 0)]
+    );
+    assert!(matches!(
+        recovered.errors[2].kind,
+        ParseErrorKind::EndOfStream { expected: None }
+    ));
+
+    // Only the statements without errors are kept.
+    let block = recovered.chunk.unwrap().block;
+    assert_eq!(block.statements.len(), 2);
+    assert!(block
+        .statements
+        .iter()
+        .all(|s| matches!(s.inner, Statement::FunctionCall(_))));
+    assert_eq!(block.statements[0].line_number, LineNumber(2));
+    assert_eq!(block.statements[1].line_number, LineNumber(4));
+}
+
+#[test]
+fn recover_nested_block() {
+    let source = "if x then\n  y = = 1\n  z = 2\nend\nreturn z";
+    let recovered = parse_chunk_recovering(
+        source.as_bytes(),
+        BasicInterner::default(),
+        ParseOptions::default(),
+    );
+    assert_eq!(recovered.errors.len(), 1);
+    assert_eq!(recovered.errors[0].location, location(16, 1, 6));
+
+    let block = recovered.chunk.unwrap().block;
+    assert_eq!(block.statements.len(), 1);
+    let Statement::If(if_statement) = &block.statements[0].inner else {
+        panic!("expected if statement");
+    };
+    assert_eq!(if_statement.if_part.1.statements.len(), 1);
+    assert!(block.return_statement.is_some());
+}
+
+#[test]
+fn recover_without_errors() {
+    let source = "local a = 1\nfor i = 1, 3 do a = a + i end\nreturn a";
+    let recovered = parse_chunk_recovering(
+        source.as_bytes(),
+        BasicInterner::default(),
+        ParseOptions::default(),
+    );
+    assert!(recovered.errors.is_empty());
+
+    let expected = parse_chunk(source.as_bytes(), BasicInterner::default()).unwrap();
+    assert_eq!(
+        format!("{:?}", recovered.chunk.unwrap()),
+        format!("{:?}", expected)
+    );
+}
+
+#[test]
+fn recover_stops_on_lex_error() {
+    let source = "local x = = 1\nlocal s = \"unfinished\nprint(1)";
+    let recovered = parse_chunk_recovering(
+        source.as_bytes(),
+        BasicInterner::default(),
+        ParseOptions::default(),
+    );
+    assert!(recovered.chunk.is_none());
+    assert_eq!(recovered.errors.len(), 2);
+    assert!(matches!(
+        recovered.errors[1].kind,
+        ParseErrorKind::LexError(_)
+    ));
+}
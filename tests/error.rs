@@ -1,6 +1,9 @@
 mod sizes;
 
-use piccolo::{error::LuaError, Callback, Closure, Error, Executor, ExternError, Lua, Value};
+use piccolo::{
+    error::{ExternLuaError, LuaError},
+    Callback, Closure, Error, Executor, ExternError, Lua, UserData, Value,
+};
 use thiserror::Error;
 
 #[test]
@@ -59,3 +62,92 @@ fn error_tostring() -> Result<(), ExternError> {
 
     lua.execute(&executor)
 }
+
+#[test]
+fn extern_error_tostring() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    let run = |lua: &mut Lua, source: &'static str| {
+        let executor = lua.try_enter(|ctx| {
+            let closure = Closure::load(ctx, None, source.as_bytes())?;
+            Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+        })?;
+        lua.execute::<()>(&executor)
+    };
+
+    let err = run(
+        &mut lua,
+        r#"
+            local MyError = { __tostring = function(e) return "my error: " .. e.code end }
+            error(setmetatable({ code = 42 }, MyError))
+        "#,
+    )
+    .unwrap_err();
+    match &err {
+        ExternError::Lua(ExternLuaError::Described {
+            type_name, message, ..
+        }) => {
+            assert_eq!(*type_name, "table");
+            assert_eq!(message, "my error: 42");
+        }
+        err => panic!("wrong error returned: {err}"),
+    }
+    assert_eq!(err.to_string(), "lua error: my error: 42");
+
+    // Without `__tostring`, the `__name` metafield is used like `tostring` does.
+    let err = run(
+        &mut lua,
+        r#"error(setmetatable({}, { __name = "MyError" }))"#,
+    )
+    .unwrap_err();
+    match &err {
+        ExternError::Lua(ExternLuaError::Described { message, .. }) => {
+            assert!(message.starts_with("MyError: 0x"));
+        }
+        err => panic!("wrong error returned: {err}"),
+    }
+
+    // A `__tostring` metamethod which errors or never finishes is ignored.
+    for source in [
+        r#"error(setmetatable({}, { __tostring = function(e) error(e) end }))"#,
+        r#"error(setmetatable({}, { __tostring = function() while true do end end }))"#,
+    ] {
+        assert!(matches!(
+            run(&mut lua, source).unwrap_err(),
+            ExternError::Lua(ExternLuaError::Table(_))
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn stashed_error_downcast() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    #[derive(Debug)]
+    struct ScriptError {
+        code: i64,
+    }
+
+    let executor = lua.try_enter(|ctx| {
+        let callback = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let code: i64 = stack.consume(ctx)?;
+            Err(Value::from(UserData::new_static(&ctx, ScriptError { code })).into())
+        });
+        ctx.set_global("raise", callback);
+
+        let closure = Closure::load(ctx, None, &b"raise(7)"[..])?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    let err = lua.execute_stashed::<()>(&executor).unwrap_err();
+    let code = lua.enter(|ctx| {
+        ctx.fetch(&err)
+            .downcast_static::<ScriptError>()
+            .map(|err| err.code)
+    });
+    assert_eq!(code, Some(7));
+
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use piccolo::{EvalConfigError, Lua, SandboxBuilder};
+
+const FUEL: i32 = 1 << 20;
+const MAX_DEPTH: usize = 200;
+
+#[test]
+fn eval_config() {
+    let mut lua = Lua::core();
+    let sandbox = SandboxBuilder::new().allow("math");
+
+    assert_eq!(
+        lua.eval_config::<i64>("1 + 2 * 3", &sandbox, FUEL, MAX_DEPTH)
+            .unwrap(),
+        7
+    );
+    assert_eq!(
+        lua.eval_config::<String>("'a' .. '-' .. 1", &sandbox, FUEL, MAX_DEPTH)
+            .unwrap(),
+        "a-1"
+    );
+    assert_eq!(
+        lua.eval_config::<i64>("math.max(1, 5, 3)", &sandbox, FUEL, MAX_DEPTH)
+            .unwrap(),
+        5
+    );
+    assert_eq!(
+        lua.eval_config::<Vec<i64>>(
+            "(function() local t = {} for i = 1, 3 do t[i] = i * i end return t end)()",
+            &sandbox,
+            FUEL,
+            MAX_DEPTH,
+        )
+        .unwrap(),
+        vec![1, 4, 9]
+    );
+}
+
+#[test]
+fn eval_config_errors() {
+    let mut lua = Lua::core();
+    let sandbox = SandboxBuilder::new();
+
+    assert!(matches!(
+        lua.eval_config::<i64>("x = 1", &sandbox, FUEL, MAX_DEPTH),
+        Err(EvalConfigError::Compile(_))
+    ));
+    assert!(matches!(
+        lua.eval_config::<i64>(
+            "(function() while true do end end)()",
+            &sandbox,
+            FUEL,
+            MAX_DEPTH
+        ),
+        Err(EvalConfigError::Compile(_))
+    ));
+    assert!(matches!(
+        lua.eval_config::<i64>(
+            "(function(f) return f(f) end)(function(f) return f(f) end)",
+            &sandbox,
+            FUEL,
+            MAX_DEPTH,
+        ),
+        Err(EvalConfigError::OutOfFuel)
+    ));
+    assert!(matches!(
+        lua.eval_config::<i64>("error('bad')", &sandbox, FUEL, MAX_DEPTH),
+        Err(EvalConfigError::Runtime(_))
+    ));
+    assert!(matches!(
+        lua.eval_config::<i64>("'not a number'", &sandbox, FUEL, MAX_DEPTH),
+        Err(EvalConfigError::Runtime(_))
+    ));
+    assert!(matches!(
+        lua.eval_config::<i64>(
+            "1",
+            &SandboxBuilder::new().allow("missing"),
+            FUEL,
+            MAX_DEPTH
+        ),
+        Err(EvalConfigError::Sandbox(_))
+    ));
+}
+
+#[test]
+fn eval_config_environment() {
+    let mut lua = Lua::full();
+
+    // Only the globals allowed by the sandbox are reachable, so the expression cannot use `load`
+    // to get around the loop restrictions, or reach the filesystem.
+    for source in [
+        "load('while true do end')()",
+        "io.open('Cargo.toml')",
+        "os.getenv('HOME')",
+        "require('anything')",
+    ] {
+        assert!(matches!(
+            lua.eval_config::<()>(source, &SandboxBuilder::new(), FUEL, MAX_DEPTH),
+            Err(EvalConfigError::Runtime(_))
+        ));
+    }
+    assert!(lua
+        .eval_config::<Option<i64>>("print", &SandboxBuilder::new(), FUEL, MAX_DEPTH)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn eval_config_depth() {
+    let mut lua = Lua::core();
+    let sandbox = SandboxBuilder::new();
+    let source = "(function(f, n) return f(f, n) end)(function(f, n) \
+                  if n == 0 then return 0 else return 1 + f(f, n - 1) end end, 100)";
+
+    assert_eq!(
+        lua.eval_config::<i64>(source, &sandbox, FUEL, 200).unwrap(),
+        100
+    );
+    assert!(matches!(
+        lua.eval_config::<i64>(source, &sandbox, FUEL, 50),
+        Err(EvalConfigError::Runtime(_))
+    ));
+}
@@ -0,0 +1,76 @@
+use piccolo::{
+    thread::{ExecutorEvent, FrameKind},
+    Closure, Executor, ExternError, Lua,
+};
+
+fn events(source: &str, capacity: usize) -> Result<(Vec<ExecutorEvent>, u64), ExternError> {
+    let mut lua = Lua::core();
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, Some("test"), source.as_bytes())?;
+        let executor = Executor::start(ctx, closure.into(), ());
+        executor.set_event_log(&ctx, Some(capacity))?;
+        Ok(ctx.stash(executor))
+    })?;
+    let _ = lua.execute::<()>(&executor);
+    let log = lua.enter(|ctx| ctx.fetch(&executor).event_log().unwrap().unwrap());
+    assert_eq!(log.capacity(), capacity);
+    Ok((log.events().collect(), log.dropped()))
+}
+
+#[test]
+fn records_transitions() -> Result<(), ExternError> {
+    let (events, dropped) = events(
+        r#"
+            local function f()
+                return 1
+            end
+            f()
+            local co = coroutine.create(function() coroutine.yield() end)
+            coroutine.resume(co)
+            pcall(error, "oops")
+        "#,
+        1024,
+    )?;
+    assert_eq!(dropped, 0);
+
+    let push = |frame| ExecutorEvent::PushFrame { thread: 1, frame };
+    let pop = |frame| ExecutorEvent::PopFrame { thread: 1, frame };
+    // Calling and returning from a Lua function.
+    assert!(events.contains(&push(FrameKind::Lua)));
+    assert!(events.contains(&pop(FrameKind::Lua)));
+    // Running and leaving the coroutine.
+    assert!(events.contains(&ExecutorEvent::SwitchThread { from: 1, to: 2 }));
+    assert!(events.contains(&ExecutorEvent::SwitchThread { from: 2, to: 1 }));
+    // The error from `pcall(error, "oops")` is raised and caught.
+    assert!(events.contains(&push(FrameKind::Error)));
+    assert_eq!(events.last(), Some(&ExecutorEvent::Step { finished: true }));
+
+    Ok(())
+}
+
+#[test]
+fn records_unwinding() -> Result<(), ExternError> {
+    let (events, _) = events(
+        r#"
+            local function f()
+                error("oops")
+            end
+            f()
+        "#,
+        1024,
+    )?;
+
+    assert!(events.contains(&ExecutorEvent::Unwind {
+        thread: 1,
+        frame: FrameKind::Lua,
+    }));
+    Ok(())
+}
+
+#[test]
+fn bounded() -> Result<(), ExternError> {
+    let (events, dropped) = events("for i = 1, 100 do tostring(i) end", 8)?;
+    assert_eq!(events.len(), 8);
+    assert!(dropped > 0);
+    Ok(())
+}
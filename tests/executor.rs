@@ -0,0 +1,530 @@
+use std::{cell::RefCell, rc::Rc};
+
+use piccolo::{
+    Callback, CallbackReturn, Closure, ExecutionMetrics, Executor, ExecutorMode, ExternError, Fuel,
+    Lua, RecursionLimits, StackLimits, Thread, ThreadMode,
+};
+
+#[test]
+fn reentrant_executor_errors() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let callback = Callback::from_fn(&ctx, |ctx, mut exec, mut stack| {
+            let executor = exec.executor();
+            assert_eq!(executor.mode(), ExecutorMode::Running);
+            assert!(executor.step(ctx, exec.fuel()).is_err());
+            assert!(executor.try_stop(&ctx).is_err());
+            assert!(executor
+                .try_restart(
+                    ctx,
+                    Callback::from_fn(&ctx, |_, _, _| Ok(CallbackReturn::Return)).into(),
+                    ()
+                )
+                .is_err());
+            assert!(executor.reset(&ctx, Thread::new(ctx)).is_err());
+            assert!(executor.set_error_handler(&ctx, |_, _| {}).is_err());
+            assert!(executor.clear_fuel_handler(&ctx).is_err());
+            assert!(executor.set_profiling(&ctx, true).is_err());
+            assert!(executor.take_profile(&ctx).is_err());
+            assert!(executor.metrics().is_err());
+            stack.replace(ctx, 1);
+            Ok(CallbackReturn::Return)
+        });
+        ctx.set_global("callback", callback);
+    });
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, &b"return callback()"[..])?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    assert_eq!(lua.execute::<i64>(&executor)?, 1);
+    Ok(())
+}
+
+#[test]
+fn externally_changed_thread_errors() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local co = coroutine.create(function()
+                    for i = 1, 100000 do end
+                    return 1
+                end)
+                thread = co
+                return coroutine.resume(co)
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    // Step until the inner coroutine is running.
+    lua.enter(|ctx| {
+        let executor = ctx.fetch(&executor);
+        loop {
+            let mut fuel = Fuel::with(16);
+            assert!(!executor.step(ctx, &mut fuel).unwrap());
+            let thread: Thread = match ctx.get_global("thread") {
+                Ok(thread) => thread,
+                Err(_) => continue,
+            };
+            if thread.mode() == ThreadMode::Normal {
+                break;
+            }
+        }
+
+        // Resetting a thread out from under the executor causes an error rather than a panic.
+        let thread: Thread = ctx.get_global("thread").unwrap();
+        thread.reset(&ctx).unwrap();
+        let mut fuel = Fuel::with(1024);
+        assert!(executor.step(ctx, &mut fuel).is_err());
+
+        // The executor can still be stopped and reused afterwards.
+        executor.try_stop(&ctx).unwrap();
+        assert_eq!(executor.mode(), ExecutorMode::Stopped);
+    });
+
+    Ok(())
+}
+
+#[test]
+fn suspend_external() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    let (thread, executor) = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local sum = 0
+                for i = 1, 1000 do
+                    sum = sum + i
+                end
+                return sum
+            "#[..],
+        )?;
+        let thread = Thread::new(ctx);
+        thread.start(ctx, closure.into(), ())?;
+        Ok((ctx.stash(thread), ctx.stash(Executor::run(&ctx, thread)?)))
+    })?;
+
+    lua.enter(|ctx| {
+        let thread = ctx.fetch(&thread);
+        let executor = ctx.fetch(&executor);
+
+        assert!(!executor.step(ctx, &mut Fuel::with(16)).unwrap());
+        thread.suspend_external(&ctx).unwrap();
+        assert_eq!(thread.mode(), ThreadMode::Normal);
+
+        // The thread suspends at the next safe point without running any further.
+        assert!(executor.step(ctx, &mut Fuel::with(1024)).unwrap());
+        assert_eq!(thread.mode(), ThreadMode::Suspended);
+        assert!(thread.is_suspended_externally());
+        assert_eq!(executor.mode(), ExecutorMode::Suspended);
+        assert!(thread.suspend_external(&ctx).is_err());
+
+        executor.resume(ctx, ()).unwrap();
+        assert!(!thread.is_suspended_externally());
+        assert_eq!(executor.mode(), ExecutorMode::Normal);
+    });
+
+    assert_eq!(lua.execute::<i64>(&executor)?, 500500);
+    Ok(())
+}
+
+#[test]
+fn suspend_external_coroutine() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local co = coroutine.create(function()
+                    local sum = 0
+                    for i = 1, 1000 do
+                        sum = sum + i
+                    end
+                    return sum
+                end)
+                thread = co
+                local _, sum = coroutine.resume(co)
+                return sum
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.enter(|ctx| {
+        let executor = ctx.fetch(&executor);
+        let thread = loop {
+            assert!(!executor.step(ctx, &mut Fuel::with(16)).unwrap());
+            if let Ok(thread) = ctx.get_global::<Thread>("thread") {
+                if thread.mode() == ThreadMode::Normal {
+                    break thread;
+                }
+            }
+        };
+
+        thread.suspend_external(&ctx).unwrap();
+        assert!(executor.step(ctx, &mut Fuel::with(1024)).unwrap());
+        assert!(thread.is_suspended_externally());
+        assert_eq!(executor.mode(), ExecutorMode::Suspended);
+
+        // Resuming the executor continues the suspended coroutine, not the main thread.
+        executor.resume(ctx, ()).unwrap();
+        assert_eq!(thread.mode(), ThreadMode::Normal);
+    });
+
+    assert_eq!(lua.execute::<i64>(&executor)?, 500500);
+    Ok(())
+}
+
+#[test]
+fn uncaught_error_handler() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    let reported = Rc::new(RefCell::new(Vec::new()));
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            Some("test"),
+            &br#"
+                local function inner()
+                    error("boom")
+                end
+                local function outer()
+                    inner()
+                end
+                assert(not pcall(outer))
+                outer()
+            "#[..],
+        )?;
+        let executor = Executor::start(ctx, closure.into(), ());
+        let reported = reported.clone();
+        executor.set_error_handler(&ctx, move |_, uncaught| {
+            let traceback = uncaught
+                .traceback
+                .iter()
+                .map(|frame| frame.to_string())
+                .collect::<Vec<_>>();
+            reported
+                .borrow_mut()
+                .push((uncaught.error.to_string(), traceback));
+        })?;
+        Ok(ctx.stash(executor))
+    })?;
+
+    assert!(lua.execute::<()>(&executor).is_err());
+
+    // Only the error which was not caught by `pcall` is reported, and only once.
+    let reported = reported.borrow();
+    assert_eq!(reported.len(), 1);
+    let (error, traceback) = &reported[0];
+    assert_eq!(error, "lua error: boom");
+    assert_eq!(
+        traceback,
+        &[
+            "test:3: in <function 'inner' at line 2>",
+            "test:6: in <function 'outer' at line 5>",
+            "test:9: in <chunk>",
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn execute_async() -> Result<(), ExternError> {
+    use std::{
+        future::Future,
+        pin::pin,
+        sync::Arc,
+        task::{Context, Poll, Wake},
+    };
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    let mut lua = Lua::core();
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local sum = 0
+                for i = 1, 10000 do
+                    sum = sum + i
+                end
+                return sum
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    let waker = Arc::new(NoopWaker).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(lua.execute_async::<i64>(&executor).fuel_per_poll(1000));
+    let mut polls = 0;
+    let result = loop {
+        polls += 1;
+        if let Poll::Ready(result) = future.as_mut().poll(&mut cx) {
+            break result?;
+        }
+    };
+
+    assert_eq!(result, 50005000);
+    assert!(polls > 10);
+    Ok(())
+}
+
+#[test]
+fn stack_limits() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local function depth(n)
+                    if n == 0 then
+                        return 0
+                    end
+                    return 1 + depth(n - 1)
+                end
+
+                assert(depth(50) == 50)
+                local ok, err = pcall(depth, 200)
+                assert(not ok and err == "stack overflow")
+
+                -- Coroutines inherit the limits of the thread which created them.
+                local ok, err = coroutine.resume(coroutine.create(depth), 200)
+                assert(not ok and err == "stack overflow")
+            "#[..],
+        )?;
+        let thread = Thread::new(ctx);
+        let limits = StackLimits {
+            max_frames: 100,
+            ..StackLimits::DEFAULT
+        };
+        thread.set_stack_limits(limits);
+        assert_eq!(thread.stack_limits(), limits);
+        thread.start(ctx, closure.into(), ())?;
+        Ok(ctx.stash(Executor::run(&ctx, thread)?))
+    })?;
+
+    lua.execute::<()>(&executor)
+}
+
+#[test]
+fn message_handler_depth() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    lua.set_recursion_limits(RecursionLimits {
+        max_handler_depth: 3,
+        ..RecursionLimits::DEFAULT
+    });
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                -- Errors raised by the handler are handled by the handler again.
+                local ok, e = xpcall(error, function(e)
+                    if e == "first" then
+                        error("second", 0)
+                    end
+                    return "handled " .. e
+                end, "first")
+                assert(ok == false and e == "handled second")
+
+                -- Up to a limit.
+                local calls = 0
+                local ok, e = xpcall(error, function(e)
+                    calls = calls + 1
+                    error("again")
+                end, "first")
+                assert(ok == false and e == "error in error handling" and calls == 3)
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute::<()>(&executor)
+}
+
+#[test]
+fn cross_executor_upvalue_errors() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        // Runs a function to completion on a new inner `Executor`.
+        let run_inner = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let function = stack.from_front(ctx)?;
+            let executor = Executor::start(ctx, function, ());
+            while !executor.step(ctx, &mut Fuel::with(i32::MAX))? {}
+            executor.take_result::<()>(ctx)??;
+            stack.clear();
+            Ok(CallbackReturn::Return)
+        });
+        ctx.set_global("run_inner", run_inner);
+    });
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local a = 1
+                local ok, err = pcall(run_inner, function()
+                    a = 2
+                end)
+                assert(not ok and a == 1)
+                assert(string.find(tostring(err), "upvalue"))
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.execute::<()>(&executor)
+}
+
+#[test]
+fn step_instructions() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    let start = |lua: &mut Lua| {
+        lua.try_enter(|ctx| {
+            let closure = Closure::load(
+                ctx,
+                None,
+                &br#"
+                    local function add(a, b) return a + b end
+                    local co = coroutine.wrap(function(x)
+                        local y = coroutine.yield(x + 1)
+                        return y * 2
+                    end)
+                    local sum = 0
+                    for i = 1, 10 do
+                        sum = add(sum, i)
+                    end
+                    return sum + co(1) + co(3) + math.max(1, 2)
+                "#[..],
+            )?;
+            Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+        })
+    };
+
+    // Step a single instruction at a time, across Lua calls, callbacks and coroutines.
+    let executor = start(&mut lua)?;
+    let total = lua.enter(|ctx| {
+        let executor = ctx.fetch(&executor);
+        assert_eq!(executor.step_instructions(ctx, 0).unwrap(), 0);
+        let mut total = 0;
+        loop {
+            match executor.step_instructions(ctx, 1).unwrap() {
+                0 => break,
+                n => {
+                    assert_eq!(n, 1);
+                    total += 1;
+                }
+            }
+        }
+        assert_eq!(executor.mode(), ExecutorMode::Result);
+        total
+    });
+    assert!(total > 50);
+    assert_eq!(lua.execute::<i64>(&executor)?, 65);
+
+    // Larger steps run exactly the same instructions.
+    let executor = start(&mut lua)?;
+    lua.enter(|ctx| {
+        let executor = ctx.fetch(&executor);
+        let mut remaining = total;
+        while remaining > 0 {
+            let n = executor.step_instructions(ctx, 7).unwrap();
+            assert_eq!(n, remaining.min(7));
+            remaining -= n;
+        }
+        assert_eq!(executor.step_instructions(ctx, 7).unwrap(), 0);
+        assert_eq!(executor.mode(), ExecutorMode::Result);
+    });
+    assert_eq!(lua.execute::<i64>(&executor)?, 65);
+
+    // Stepping by instructions and by fuel can be freely mixed.
+    let executor = start(&mut lua)?;
+    lua.enter(|ctx| {
+        let executor = ctx.fetch(&executor);
+        assert_eq!(executor.step_instructions(ctx, 10).unwrap(), 10);
+        executor.step(ctx, &mut Fuel::with(8)).unwrap();
+        while executor.step_instructions(ctx, 1000).unwrap() != 0 {}
+    });
+    assert_eq!(lua.execute::<i64>(&executor)?, 65);
+
+    Ok(())
+}
+
+#[test]
+fn execution_metrics() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local function depth(n)
+                    if n == 0 then
+                        return 0
+                    end
+                    return 1 + depth(n - 1)
+                end
+
+                local co = coroutine.create(function(a)
+                    local b = coroutine.yield(a + 1)
+                    return depth(b)
+                end)
+                local _, x = coroutine.resume(co, 1)
+                local _, y = coroutine.resume(co, 40)
+                return x + y
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.enter(|ctx| {
+        let executor = ctx.fetch(&executor);
+        assert_eq!(executor.metrics().unwrap(), ExecutionMetrics::default());
+
+        // Between steps, the instruction count is exactly the number of instructions run.
+        let ran = executor.step_instructions(ctx, 5).unwrap();
+        assert_eq!(executor.metrics().unwrap().instructions, u64::from(ran));
+    });
+    assert_eq!(lua.execute::<i64>(&executor)?, 42);
+
+    lua.enter(|ctx| {
+        let executor = ctx.fetch(&executor);
+        let metrics = executor.metrics().unwrap();
+        assert!(metrics.instructions > 40 * 4);
+        assert!(metrics.callbacks >= 3);
+        // Two resumes, one yield back and one return back.
+        assert_eq!(metrics.thread_switches, 4);
+        assert!(metrics.peak_call_depth > 40);
+        assert!(metrics.peak_stack_size > 40);
+
+        executor.reset_metrics(&ctx).unwrap();
+        assert_eq!(executor.metrics().unwrap(), ExecutionMetrics::default());
+    });
+
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use piccolo::{facade, ExternError, FacadeError, Lua};
+
+mod common;
+
+use common::run;
+
+facade! {
+    trait Hooks {
+        fn add(&self, a: i64, b: i64) -> i64;
+        fn split(&self, s: String) -> (String, Option<String>);
+        fn fail(&self);
+        fn missing(&self);
+    }
+}
+
+#[test]
+fn call_module_functions() -> Result<(), ExternError> {
+    let mut lua = Lua::full();
+    run(
+        &mut lua,
+        r#"
+            hooks = {}
+            function hooks.add(a, b) return a + b end
+            function hooks.split(s)
+                local a, b = string.match(s, "^(%w+) ?(%w*)$")
+                return a, b ~= "" and b or nil
+            end
+            function hooks.fail() error("hook failed") end
+        "#,
+    )?;
+
+    let hooks = lua.enter(|ctx| Hooks::new(ctx, ctx.get_global("hooks").unwrap()));
+
+    assert_eq!(hooks.add(&mut lua, 1, 2).unwrap(), 3);
+    assert_eq!(
+        hooks.split(&mut lua, "hello world".to_owned()).unwrap(),
+        ("hello".to_owned(), Some("world".to_owned()))
+    );
+    assert_eq!(
+        hooks.split(&mut lua, "hello".to_owned()).unwrap(),
+        ("hello".to_owned(), None)
+    );
+
+    match hooks.fail(&mut lua) {
+        Err(FacadeError::Runtime { name, error }) => {
+            assert_eq!(name, "fail");
+            assert!(error.to_string().contains("hook failed"));
+        }
+        _ => panic!("expected a runtime error"),
+    }
+
+    // The executor is reused after an error.
+    assert_eq!(hooks.add(&mut lua, 2, 2).unwrap(), 4);
+
+    assert!(!hooks.facade().has_function(&mut lua, "missing"));
+    assert!(matches!(
+        hooks.missing(&mut lua),
+        Err(FacadeError::MissingFunction("missing"))
+    ));
+
+    // Functions are looked up on every call.
+    run(&mut lua, "function hooks.add(a, b) return a * b end")?;
+    assert_eq!(hooks.add(&mut lua, 3, 4).unwrap(), 12);
+
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use piccolo::{thread::SnapshotError, Callback, CallbackReturn, ExternError, Lua};
+
+mod common;
+
+use common::{eval, run};
+
+#[test]
+fn fork_copies_globals() -> Result<(), ExternError> {
+    let mut template = Lua::core();
+    run(
+        &mut template,
+        r#"
+            count = 0
+            function bump()
+                count = count + 1
+                return count
+            end
+
+            local hidden = 10
+            function next_hidden()
+                hidden = hidden + 1
+                return hidden
+            end
+
+            config = { name = "template", shout = string.upper }
+            config.self = config
+            setmetatable(config, { __index = function(_, key) return key .. "?" end })
+
+            gen = coroutine.create(function()
+                for i = 1, 3 do
+                    coroutine.yield(i)
+                end
+            end)
+            coroutine.resume(gen)
+        "#,
+    )?;
+
+    let mut a = template.fork().unwrap();
+    let mut b = template.fork().unwrap();
+
+    assert_eq!(eval::<i64>(&mut a, "bump() return bump()")?, 2);
+    assert_eq!(eval::<i64>(&mut b, "return bump()")?, 1);
+    assert_eq!(eval::<i64>(&mut template, "return count")?, 0);
+
+    assert_eq!(eval::<i64>(&mut a, "return next_hidden()")?, 11);
+    assert_eq!(eval::<i64>(&mut a, "return next_hidden()")?, 12);
+    assert_eq!(eval::<i64>(&mut b, "return next_hidden()")?, 11);
+
+    // Cycles and metatables are preserved, and library callbacks refer to the fork's own library
+    assert!(eval::<bool>(
+        &mut a,
+        r#"
+            return config.self == config
+                and config.shout("x") == "X"
+                and config.shout == string.upper
+                and config.missing == "missing?"
+        "#
+    )?);
+
+    // Mutating one fork's tables does not affect the other fork or the template
+    run(&mut a, "config.name = 'a'")?;
+    assert!(eval::<bool>(&mut b, "return config.name == 'template'")?);
+    assert!(eval::<bool>(
+        &mut template,
+        "return config.name == 'template'"
+    )?);
+
+    assert_eq!(
+        eval::<(bool, i64)>(&mut a, "return coroutine.resume(gen)")?,
+        (true, 2)
+    );
+    assert_eq!(
+        eval::<(bool, i64)>(&mut b, "return coroutine.resume(gen)")?,
+        (true, 2)
+    );
+    assert_eq!(
+        eval::<(bool, i64)>(&mut a, "return coroutine.resume(gen)")?,
+        (true, 3)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn fork_errors_on_callbacks() {
+    let mut template = Lua::core();
+    template.enter(|ctx| {
+        let callback = Callback::from_fn(&ctx, |_, _, _| Ok(CallbackReturn::Return));
+        let holder = piccolo::Table::new(&ctx);
+        holder.set(ctx, 1, callback).unwrap();
+        ctx.set_global("holder", holder);
+    });
+
+    assert!(matches!(
+        template.fork(),
+        Err(SnapshotError::Unsupported("callback"))
+    ));
+}
+
+#[test]
+fn fork_copies_stdlib_changes() -> Result<(), ExternError> {
+    let mut template = Lua::full();
+    run(
+        &mut template,
+        r#"
+            function string.shout(s)
+                return s:upper() .. "!"
+            end
+            string.rep = nil
+            package.loaded.greeting = { hello = "hi" }
+            searchers = package.searchers
+        "#,
+    )?;
+
+    let mut a = template.fork().unwrap();
+    assert!(eval::<bool>(
+        &mut a,
+        r#"
+            return ("x"):shout() == "X!"
+                and string.rep == nil
+                and require("greeting").hello == "hi"
+                and searchers == package.searchers
+                and io.type(io.stdout) == "file"
+        "#
+    )?);
+
+    // Forks of forks load the same stdlib, and their library tables are their own.
+    run(&mut a, "string.extra = true")?;
+    let mut b = a.fork().unwrap();
+    assert!(eval::<bool>(
+        &mut b,
+        r#"return ("x"):shout() == "X!" and string.extra"#
+    )?);
+    assert!(eval::<bool>(&mut template, "return string.extra == nil")?);
+
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use std::{cell::RefCell, rc::Rc};
+
+use piccolo::{compiler::LineNumber, Closure, CompilerError, Executor, ExternError, Lua};
+
+const PROLOGUE: &[u8] = b"local function run()\n";
+const EPILOGUE: &[u8] = b"end\nrun()\n";
+
+#[test]
+fn fragment_locations() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    let traceback = Rc::new(RefCell::new(Vec::new()));
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load_fragments(
+            ctx,
+            [
+                ("prologue", PROLOGUE),
+                ("user", &b"local x = 1\nerror('boom')"[..]),
+                ("epilogue", EPILOGUE),
+            ],
+        )?;
+        let executor = Executor::start(ctx, closure.into(), ());
+        let traceback = traceback.clone();
+        executor.set_error_handler(&ctx, move |_, uncaught| {
+            traceback
+                .borrow_mut()
+                .extend(uncaught.traceback.iter().map(|frame| frame.to_string()));
+        })?;
+        Ok(ctx.stash(executor))
+    })?;
+
+    assert!(lua.execute::<()>(&executor).is_err());
+    assert_eq!(
+        &*traceback.borrow(),
+        &[
+            "user:2: in <function 'run' at line 1>",
+            "epilogue:2: in <chunk>",
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn fragment_compile_error() {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        let err = Closure::load_fragments(
+            ctx,
+            [
+                ("prologue", PROLOGUE),
+                ("user", &b"local a = 1\r\nlocal b = = 2\n"[..]),
+                ("epilogue", EPILOGUE),
+            ],
+        )
+        .unwrap_err();
+
+        match err {
+            CompilerError::Fragment { name, error } => {
+                assert_eq!(name, "user");
+                match *error {
+                    CompilerError::Parsing(err) => assert_eq!(err.line_number, LineNumber(1)),
+                    err => panic!("unexpected error {err:?}"),
+                }
+            }
+            err => panic!("unexpected error {err:?}"),
+        }
+    });
+}
@@ -1,4 +1,15 @@
-use piccolo::{Callback, CallbackReturn, Closure, Executor, ExecutorMode, ExternError, Fuel, Lua};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use piccolo::{
+    Callback, CallbackReturn, Closure, Error, Executor, ExecutorMode, ExternError, Fuel, IntoValue,
+    Lua, OutOfFuel, StashedExecutor,
+};
 
 #[test]
 fn test_interrupt() -> Result<(), ExternError> {
@@ -28,3 +39,117 @@ fn test_interrupt() -> Result<(), ExternError> {
 
     Ok(())
 }
+
+fn start_infinite_loop(lua: &mut Lua) -> Result<StashedExecutor, ExternError> {
+    lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, &b"local i = 0 while true do i = i + 1 end"[..])?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })
+}
+
+#[test]
+fn test_deadline() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    let executor = start_infinite_loop(&mut lua)?;
+
+    lua.enter(|ctx| {
+        let executor = ctx.fetch(&executor);
+        let start = Instant::now();
+        let mut fuel = Fuel::until(start + Duration::from_millis(20));
+        assert!(!executor.step(ctx, &mut fuel).unwrap());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(fuel.is_expired());
+        assert!(fuel.remaining() > 0);
+        assert!(executor.mode() == ExecutorMode::Normal);
+
+        // A new deadline lets execution continue.
+        fuel.set_deadline(Some(Instant::now() + Duration::from_millis(5)));
+        assert!(fuel.should_continue());
+        assert!(!executor.step(ctx, &mut fuel).unwrap());
+    });
+
+    Ok(())
+}
+
+#[test]
+fn test_yield_check() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    let executor = start_infinite_loop(&mut lua)?;
+
+    let checks = Arc::new(AtomicUsize::new(0));
+    let mut fuel = Fuel::with(i32::MAX);
+    fuel.set_yield_check({
+        let checks = checks.clone();
+        move || checks.fetch_add(1, Ordering::SeqCst) >= 100
+    });
+
+    lua.enter(|ctx| {
+        let executor = ctx.fetch(&executor);
+        assert!(!executor.step(ctx, &mut fuel).unwrap());
+        assert!(executor.mode() == ExecutorMode::Normal);
+    });
+    assert!(checks.load(Ordering::SeqCst) > 100);
+    assert!(fuel.is_expired());
+
+    fuel.clear_yield_check();
+    assert!(!fuel.is_expired());
+
+    Ok(())
+}
+
+#[test]
+fn test_fuel_handler_refill() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    let executor = start_infinite_loop(&mut lua)?;
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    lua.enter(|ctx| {
+        let executor = ctx.fetch(&executor);
+        executor
+            .set_fuel_handler(&ctx, {
+                let calls = calls.clone();
+                move |_, fuel| {
+                    if calls.fetch_add(1, Ordering::SeqCst) < 3 {
+                        fuel.refill(1000, 1000);
+                        OutOfFuel::Continue
+                    } else {
+                        OutOfFuel::Yield
+                    }
+                }
+            })
+            .unwrap();
+
+        let mut fuel = Fuel::with(1000);
+        assert!(!executor.step(ctx, &mut fuel).unwrap());
+        assert!(fuel.remaining() <= 0);
+        assert!(executor.mode() == ExecutorMode::Normal);
+    });
+    assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+    Ok(())
+}
+
+#[test]
+fn test_fuel_handler_error() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    let executor = start_infinite_loop(&mut lua)?;
+
+    lua.enter(|ctx| {
+        let executor = ctx.fetch(&executor);
+        executor
+            .set_fuel_handler(&ctx, |ctx, _| {
+                OutOfFuel::Error(Error::from_value("killed".into_value(ctx)))
+            })
+            .unwrap();
+        assert!(!executor.step(ctx, &mut Fuel::with(1000)).unwrap());
+        assert!(executor.step(ctx, &mut Fuel::with(1000)).unwrap());
+        assert!(executor.mode() == ExecutorMode::Result);
+    });
+
+    let err = lua
+        .try_enter(|ctx| ctx.fetch(&executor).take_result::<()>(ctx)?)
+        .unwrap_err();
+    assert!(err.to_string().contains("killed"));
+
+    Ok(())
+}
@@ -0,0 +1,9 @@
+local sum = 0
+for i = 1, 1000 do
+    sum = sum + i * 2 - i // 3 + i % 7
+end
+local f = 1.5
+for i = 1, 200 do
+    f = f * 1.01 + i / 4
+end
+return sum, f
@@ -0,0 +1,19 @@
+local function fib(n)
+    if n < 2 then
+        return n
+    end
+    return fib(n - 1) + fib(n - 2)
+end
+
+local function count(n, acc)
+    if n == 0 then
+        return acc
+    end
+    return count(n - 1, acc + 1)
+end
+
+local function varargs(...)
+    return select("#", ...), ...
+end
+
+return fib(15), count(500, 0), varargs(1, 2, 3, 4, 5)
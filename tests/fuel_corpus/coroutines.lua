@@ -0,0 +1,24 @@
+local function gen(n)
+    return coroutine.wrap(function()
+        for i = 1, n do
+            coroutine.yield(i)
+        end
+    end)
+end
+
+local sum = 0
+for v in gen(300) do
+    sum = sum + v
+end
+
+local co = coroutine.create(function(a)
+    while true do
+        a = coroutine.yield(a * 2)
+    end
+end)
+local last
+for i = 1, 100 do
+    _, last = coroutine.resume(co, i)
+end
+
+return sum, last
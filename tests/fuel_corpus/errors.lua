@@ -0,0 +1,17 @@
+local caught = 0
+for i = 1, 100 do
+    local ok = pcall(error, { code = i })
+    if not ok then
+        caught = caught + 1
+    end
+end
+
+local handled = 0
+for i = 1, 50 do
+    xpcall(function() error("oops") end, function(e)
+        handled = handled + 1
+        return e
+    end)
+end
+
+return caught, handled
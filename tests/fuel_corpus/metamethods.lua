@@ -0,0 +1,26 @@
+local V = {}
+V.__index = V
+V.__add = function(a, b)
+    return setmetatable({ x = a.x + b.x }, V)
+end
+V.__call = function(self, n)
+    return self.x * n
+end
+
+local acc = setmetatable({ x = 0 }, V)
+local one = setmetatable({ x = 1 }, V)
+for i = 1, 200 do
+    acc = acc + one
+end
+
+local proxy = setmetatable({}, {
+    __index = function(_, k) return k * 2 end,
+    __newindex = function(t, k, v) rawset(t, k, v + 1) end,
+})
+local total = 0
+for i = 1, 100 do
+    total = total + proxy[i]
+end
+proxy[1] = 1
+
+return acc(3), total, rawget(proxy, 1)
@@ -0,0 +1,11 @@
+local parts = {}
+for i = 1, 200 do
+    parts[#parts + 1] = i .. ":" .. string.sub("abababab", 1, i % 8)
+end
+local s = table.concat(parts, ";")
+local count = 0
+for _ in string.gmatch(s, "%d+") do
+    count = count + 1
+end
+local upper = string.upper(string.sub(s, 1, 20))
+return #s, count, upper, string.reverse(upper), string.find(s, "100:")
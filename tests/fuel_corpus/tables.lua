@@ -0,0 +1,15 @@
+local t = {}
+for i = 1, 500 do
+    t[i] = i * i
+end
+table.insert(t, 1, 0)
+table.remove(t, 1)
+table.sort(t, function(a, b) return a > b end)
+
+local r = { x = 1, y = 2 }
+for i = 1, 200 do
+    r.x = r.x + r.y
+end
+
+local packed = table.pack(table.unpack(t, 1, 100))
+return #t, r.x, packed.n, table.concat(t, ",", 1, 10)
@@ -0,0 +1,155 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    fmt::Write as _,
+    fs::{read_dir, read_to_string, write},
+};
+
+use piccolo::{Closure, Executor, ExternError, Fuel, FuelSchedule, Lua, Value, Variadic};
+
+const CORPUS_DIR: &str = "./tests/fuel_corpus";
+const SCHEDULE_FILE: &str = "./tests/fuel_corpus/schedule.txt";
+
+// Set to re-record every entry of the schedule file, after a deliberate change to fuel costs.
+const BLESS_VAR: &str = "PICCOLO_BLESS_FUEL";
+
+fn fuel_consumed(name: &str, source: &[u8]) -> Result<i64, ExternError> {
+    let mut lua = Lua::core();
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, Some(name), source)?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    let mut consumed = 0;
+    loop {
+        let mut fuel = Fuel::with(i32::MAX);
+        let finished = lua.enter(|ctx| ctx.fetch(&executor).step(ctx, &mut fuel).unwrap());
+        consumed += i64::from(i32::MAX) - i64::from(fuel.remaining());
+        if finished {
+            break;
+        }
+    }
+
+    lua.try_enter(|ctx| {
+        ctx.fetch(&executor)
+            .take_result::<Variadic<Vec<Value>>>(ctx)??;
+        Ok(())
+    })?;
+    Ok(consumed)
+}
+
+struct Schedule {
+    header: String,
+    version: u32,
+    fuel: BTreeMap<String, i64>,
+}
+
+impl Schedule {
+    fn read() -> Schedule {
+        let mut schedule = Schedule {
+            header: String::new(),
+            version: 0,
+            fuel: BTreeMap::new(),
+        };
+        for line in read_to_string(SCHEDULE_FILE)
+            .expect("could not read fuel schedule")
+            .lines()
+        {
+            if line.starts_with('#') {
+                writeln!(schedule.header, "{line}").unwrap();
+            } else if let Some(version) = line.strip_prefix("version ") {
+                schedule.version = version.parse().expect("bad fuel schedule version");
+            } else if let Some((name, fuel)) = line.split_once(' ') {
+                schedule.fuel.insert(
+                    name.to_owned(),
+                    fuel.parse().expect("bad fuel schedule entry"),
+                );
+            }
+        }
+        schedule
+    }
+
+    fn write(&self) {
+        let mut out = self.header.clone();
+        writeln!(out, "version {}", self.version).unwrap();
+        for (name, fuel) in &self.fuel {
+            writeln!(out, "{name} {fuel}").unwrap();
+        }
+        write(SCHEDULE_FILE, out).expect("could not write fuel schedule");
+    }
+}
+
+#[test]
+fn fuel_schedule() -> Result<(), ExternError> {
+    let bless = env::var_os(BLESS_VAR).is_some();
+
+    let mut schedule = Schedule::read();
+    if !bless {
+        assert_eq!(
+            schedule.version,
+            FuelSchedule::CURRENT.version(),
+            "the recorded fuel schedule does not match `FuelSchedule::CURRENT`, rerun with \
+             `{BLESS_VAR}=1` to record it"
+        );
+    }
+
+    let mut scripts = read_dir(CORPUS_DIR)
+        .expect("could not list dir contents")
+        .map(|entry| entry.expect("could not read dir entry").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lua"))
+        .collect::<Vec<_>>();
+    scripts.sort();
+
+    let mut recorded = BTreeMap::new();
+    let mut changed = Vec::new();
+    for path in scripts {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let source = read_to_string(&path).unwrap();
+
+        let consumed = fuel_consumed(&name, source.as_bytes())?;
+        assert_eq!(
+            fuel_consumed(&name, source.as_bytes())?,
+            consumed,
+            "fuel consumed by {name:?} differs between runs"
+        );
+
+        match schedule.fuel.get(&name) {
+            Some(&expected) if expected != consumed && !bless => {
+                changed.push(format!("{name}: expected {expected}, consumed {consumed}"));
+            }
+            _ => {}
+        }
+        recorded.insert(name, consumed);
+    }
+
+    assert!(
+        changed.is_empty(),
+        "fuel consumption changed under fuel schedule {}:\n{}\nif this is intended, bump \
+         `FuelSchedule::CURRENT` and rerun with `{BLESS_VAR}=1`",
+        FuelSchedule::CURRENT,
+        changed.join("\n"),
+    );
+
+    // New scripts are recorded as they are added, removed scripts are only dropped when blessing.
+    if bless {
+        schedule.fuel = recorded;
+    } else {
+        for (name, consumed) in recorded {
+            schedule.fuel.entry(name).or_insert(consumed);
+        }
+    }
+    schedule.version = FuelSchedule::CURRENT.version();
+    schedule.write();
+
+    Ok(())
+}
+
+#[test]
+fn require_schedule() {
+    let current = FuelSchedule::CURRENT;
+    assert_eq!(FuelSchedule::require(current.version()).unwrap(), current);
+
+    let err = FuelSchedule::require(current.version() + 1).unwrap_err();
+    assert_eq!(err.required, current.version() + 1);
+    assert_eq!(err.current, current.version());
+}
@@ -1,4 +1,6 @@
-use piccolo::{Callback, CallbackReturn, Executor, ExternError, Function, Lua, Variadic};
+use piccolo::{
+    Callback, CallbackReturn, Closure, Executor, ExternError, Function, Lua, Table, Variadic,
+};
 
 #[test]
 fn function_compose_bind() -> Result<(), ExternError> {
@@ -42,3 +44,54 @@ fn function_compose_bind() -> Result<(), ExternError> {
     assert_eq!(lua.execute::<i64>(&executor)?, 33);
     Ok(())
 }
+
+#[test]
+fn function_bind_front_method() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local counter = { count = 0 }
+                function counter:add(n, m)
+                    self.count = self.count + n + (m or 0)
+                    return self.count
+                end
+                return counter, counter.add
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.finish(&executor).unwrap();
+    let (counter, add) = lua.try_enter(|ctx| {
+        let (counter, add): (Table, Function) = ctx.fetch(&executor).take_result(ctx)??;
+        Ok((ctx.stash(counter), ctx.stash(add)))
+    })?;
+
+    let bound = lua.enter(|ctx| {
+        let add = ctx.fetch(&add).bind_front(ctx, ctx.fetch(&counter));
+        ctx.stash(add)
+    });
+
+    let executor = lua.enter(|ctx| ctx.stash(Executor::start(ctx, ctx.fetch(&bound), 2)));
+    assert_eq!(lua.execute::<i64>(&executor)?, 2);
+    lua.enter(|ctx| ctx.fetch(&executor).restart(ctx, ctx.fetch(&bound), (3, 4)));
+    assert_eq!(lua.execute::<i64>(&executor)?, 9);
+
+    // Callbacks are called directly with the bound arguments in front.
+    let executor = lua.enter(|ctx| {
+        let sub = Function::from(Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (a, b): (i64, i64) = stack.consume(ctx)?;
+            stack.replace(ctx, a - b);
+            Ok(CallbackReturn::Return)
+        }))
+        .bind_front(ctx, 10);
+        ctx.stash(Executor::start(ctx, sub, 3))
+    });
+    assert_eq!(lua.execute::<i64>(&executor)?, 7);
+
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use piccolo::{ExternError, Lua};
+
+mod common;
+
+use common::run;
+
+#[test]
+fn host_controlled_collection() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    run(&mut lua, "collectgarbage()")?;
+    let baseline = lua.total_memory();
+
+    lua.set_gc_running(false);
+    assert!(!lua.is_gc_running());
+    run(&mut lua, "for i = 1, 10000 do local t = { i } end")?;
+    // Nothing is collected while automatic collection is stopped.
+    let garbage = lua.total_memory();
+    assert!(garbage > baseline);
+
+    // The embedder can do the collection work itself instead.
+    for _ in 0..100 {
+        if lua.total_memory() < garbage {
+            break;
+        }
+        lua.gc_step();
+    }
+    assert!(lua.total_memory() < garbage);
+
+    // Scripts can restart collection.
+    run(
+        &mut lua,
+        "assert(not collectgarbage('isrunning')) collectgarbage('restart')",
+    )?;
+    assert!(lua.is_gc_running());
+
+    Ok(())
+}
+
+#[test]
+fn deferred_collection() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    run(&mut lua, "collectgarbage()")?;
+    let baseline = lua.total_memory();
+
+    lua.set_gc_deferred(Some(usize::MAX));
+    assert!(lua.is_gc_deferred());
+    run(&mut lua, "for i = 1, 10000 do local t = { i } end")?;
+    // No collection work happens while running, the debt accumulates instead.
+    let garbage = lua.total_memory();
+    assert!(garbage > baseline);
+    assert!(lua.gc_metrics().allocation_debt() > 0.0);
+
+    // Once the host is idle, the debt is paid off.
+    let mut paid = 0.0;
+    for _ in 0..100 {
+        if lua.total_memory() < garbage {
+            break;
+        }
+        paid += lua.gc_idle();
+    }
+    assert!(paid > 0.0);
+    assert!(lua.total_memory() < garbage);
+
+    // Idle time does no work while collection is stopped.
+    lua.set_gc_running(false);
+    run(&mut lua, "for i = 1, 10000 do local t = { i } end")?;
+    assert_eq!(lua.gc_idle(), 0.0);
+    lua.set_gc_running(true);
+
+    // A small limit still bounds the garbage built up without idle time.
+    lua.gc_collect();
+    let baseline = lua.total_memory();
+    lua.set_gc_deferred(Some(64 * 1024));
+    run(&mut lua, "for i = 1, 100000 do local t = { i } end")?;
+    assert!(lua.total_memory().saturating_sub(baseline) < 4 * 1024 * 1024);
+
+    lua.set_gc_deferred(None);
+    assert!(!lua.is_gc_deferred());
+    Ok(())
+}
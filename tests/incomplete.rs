@@ -0,0 +1,50 @@
+use piccolo::{Closure, CompilerError, Lua};
+
+fn compile_error(source: &str) -> Option<bool> {
+    let mut lua = Lua::empty();
+    lua.enter(|ctx| {
+        Closure::load(ctx, None, source.as_bytes())
+            .err()
+            .map(|err: CompilerError| err.is_incomplete())
+    })
+}
+
+#[test]
+fn incomplete_input() {
+    for source in [
+        "function f()",
+        "if x then",
+        "local t = {",
+        "print(1,",
+        "x = [[long",
+        "while true do",
+        "return 1 +",
+        "--[[ unfinished comment",
+    ] {
+        assert_eq!(compile_error(source), Some(true), "{source:?}");
+    }
+
+    for source in [
+        "end",
+        "x = = 1",
+        "1 + 1",
+        "x = \"unfinished\nstring\"",
+        "goto nowhere",
+        "x = 0x",
+    ] {
+        assert_eq!(compile_error(source), Some(false), "{source:?}");
+    }
+
+    assert_eq!(compile_error("function f() end"), None);
+}
+
+#[test]
+fn incomplete_fragment() {
+    let mut lua = Lua::empty();
+    lua.enter(|ctx| {
+        let err =
+            Closure::load_fragments(ctx, [("a", &b"local x = 1"[..]), ("b", &b"if x then"[..])])
+                .unwrap_err();
+        assert!(err.is_incomplete());
+    });
+}
@@ -0,0 +1,168 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{self, Read, Seek, SeekFrom, Write},
+    rc::Rc,
+};
+
+use piccolo::{
+    stdlib::{OpenMode, VfsFile, VfsProvider},
+    ExternError, Lua,
+};
+
+mod common;
+
+use common::run;
+
+#[derive(Default, Clone)]
+struct MemoryVfs {
+    files: Rc<RefCell<HashMap<String, Rc<RefCell<Vec<u8>>>>>>,
+}
+
+struct MemoryFile {
+    data: Rc<RefCell<Vec<u8>>>,
+    pos: usize,
+    mode: OpenMode,
+}
+
+impl VfsProvider for MemoryVfs {
+    fn open(&self, path: &str, mode: OpenMode) -> io::Result<Box<dyn VfsFile>> {
+        let mut files = self.files.borrow_mut();
+        let data = match files.get(path) {
+            Some(data) => data.clone(),
+            None if mode.create => files.entry(path.to_owned()).or_default().clone(),
+            None => return Err(io::ErrorKind::NotFound.into()),
+        };
+        if mode.truncate {
+            data.borrow_mut().clear();
+        }
+        Ok(Box::new(MemoryFile { data, pos: 0, mode }))
+    }
+}
+
+impl Read for MemoryFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.mode.read {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        let data = self.data.borrow();
+        let len = buf.len().min(data.len().saturating_sub(self.pos));
+        buf[..len].copy_from_slice(&data[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+impl Write for MemoryFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.mode.write {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        let mut data = self.data.borrow_mut();
+        if self.mode.append {
+            self.pos = data.len();
+        }
+        let end = self.pos + buf.len();
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[self.pos..end].copy_from_slice(buf);
+        self.pos = end;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemoryFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(p) => p as i64,
+            SeekFrom::Current(p) => self.pos as i64 + p,
+            SeekFrom::End(p) => self.data.borrow().len() as i64 + p,
+        };
+        if pos < 0 {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        self.pos = pos as usize;
+        Ok(self.pos as u64)
+    }
+}
+
+#[test]
+fn read_write_files() -> Result<(), ExternError> {
+    let vfs = MemoryVfs::default();
+    let mut lua = Lua::core();
+    lua.load_io_with_vfs(vfs.clone());
+
+    run(
+        &mut lua,
+        r#"
+            local f = assert(io.open("test.txt", "w"))
+            assert(io.type(f) == "file")
+            assert(f:write("hello\n", 42, " ", 1.5, "\nworld") == f)
+            assert(f:close())
+            assert(io.type(f) == "closed file")
+            assert(tostring(f) == "file (closed)")
+            assert(not pcall(f.read, f))
+
+            f = assert(io.open("test.txt"))
+            assert(f:read() == "hello")
+            assert(f:read("n") == 42)
+            assert(f:read("*n") == 1.5)
+            assert(f:read("L") == "\n")
+            assert(f:read(3) == "wor")
+            assert(f:seek() == 16)
+            assert(f:read("a") == "ld")
+            assert(f:read("a") == "")
+            assert(f:read() == nil)
+            assert(f:read(0) == nil)
+
+            assert(f:seek("set") == 0)
+            local a, b, c = f:read("l", "n", "n")
+            assert(a == "hello" and b == 42 and c == 1.5)
+            assert(f:seek("set", 0) == 0)
+            local lines = {}
+            for l in f:lines() do
+                lines[#lines + 1] = l
+            end
+            assert(#lines == 3 and lines[2] == "42 1.5" and lines[3] == "world")
+            f:close()
+
+            local count = 0
+            for a, b in io.lines("test.txt", 1, 1) do
+                count = count + 1
+            end
+            assert(count == 9)
+
+            local f, err = io.open("missing.txt")
+            assert(f == nil and string.sub(err, 1, 13) == "missing.txt: ")
+            assert(not pcall(io.open, "test.txt", "rw"))
+            assert(not pcall(io.lines, "missing.txt"))
+
+            f = assert(io.open("test.txt", "a+"))
+            f:write("!")
+            f:seek("set", 0)
+            assert(f:read("a") == "hello\n42 1.5\nworld!")
+            f:close()
+
+            io.output("out.txt")
+            io.write("a", "b")
+            io.output():close()
+            io.input("out.txt")
+            assert(io.read("a") == "ab")
+
+            assert(io.type(io.stdout) == "file")
+            assert(io.type(42) == nil)
+            assert(io.stdout:close() == nil)
+        "#,
+    )?;
+
+    assert_eq!(
+        vfs.files.borrow()["test.txt"].borrow().as_slice(),
+        b"hello\n42 1.5\nworld!"
+    );
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use piccolo::{ExternError, Lua};
+
+mod common;
+
+use common::run;
+
+#[test]
+fn memory_errors_are_catchable() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    assert_eq!(lua.memory_limit(), None);
+
+    let limit = lua.total_memory() + (4 << 20);
+    lua.set_memory_limit(Some(limit));
+    assert_eq!(lua.memory_limit(), Some(limit));
+
+    run(
+        &mut lua,
+        r#"
+            local ok, err = pcall(function()
+                local t = {}
+                for i = 1, 1e9 do
+                    t[i] = { i }
+                end
+            end)
+            assert(not ok and err == "not enough memory")
+
+            local ok, err = pcall(function()
+                local s = "xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx"
+                local parts = {}
+                for i = 1, 1e9 do
+                    parts[i] = s .. i
+                end
+            end)
+            assert(not ok and err == "not enough memory")
+
+            -- Once the memory has been released, the script can keep going.
+            local t = {}
+            for i = 1, 100 do
+                t[i] = tostring(i)
+            end
+        "#,
+    )?;
+
+    assert!(lua.enter(|ctx| !ctx.memory_limit().is_exceeded(ctx.memory_usage())));
+    Ok(())
+}
+
+#[test]
+fn large_allocations_are_checked_first() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    let limit = lua.total_memory() + (4 << 20);
+    lua.set_memory_limit(Some(limit));
+
+    // Each of these would use far more memory than the limit within a few dozen instructions.
+    run(
+        &mut lua,
+        r#"
+            local ok, err = pcall(function()
+                local s = "x"
+                for i = 1, 64 do
+                    s = s .. s
+                end
+            end)
+            assert(not ok and err == "not enough memory")
+
+            local ok, err = pcall(function()
+                local t = { "xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx" }
+                for i = 1, 64 do
+                    t[1] = table.concat({ t[1], t[1] })
+                end
+            end)
+            assert(not ok and err == "not enough memory")
+        "#,
+    )?;
+
+    assert!(lua.total_memory() < limit + (4 << 20));
+    Ok(())
+}
+
+#[test]
+fn garbage_does_not_count() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    lua.set_memory_limit(Some(lua.total_memory() + (1 << 20)));
+
+    // Far more than the limit is allocated in total, but almost all of it is garbage.
+    run(
+        &mut lua,
+        r#"
+            for i = 1, 200000 do
+                local t = { i, i + 1, i + 2, tostring(i) }
+            end
+        "#,
+    )?;
+
+    lua.set_memory_limit(None);
+    run(&mut lua, "local t = {} for i = 1, 100000 do t[i] = {} end")?;
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use piccolo::{Lua, ModuleError, SandboxBuilder, Table};
+
+#[test]
+fn load_module() {
+    let mut lua = Lua::full();
+    let sandbox = SandboxBuilder::new().allow_safe_core();
+
+    let module = lua
+        .load_module(
+            "greeting",
+            br#"
+                local M = {}
+                function M.greet(name)
+                    return string.format("hello %s", name)
+                end
+                leaked = true
+                return M
+            "#,
+            &sandbox,
+            10_000,
+        )
+        .unwrap();
+
+    lua.enter(|ctx| {
+        let module: Table = ctx.fetch(&module);
+        assert!(!module.get_value(ctx, "greet").is_nil());
+        // Globals set by the module stay in its environment.
+        assert!(ctx.get_global_value("leaked").is_nil());
+    });
+
+    // A module which returns nothing produces its environment.
+    let env = lua
+        .load_module("globals", b"value = 42", &sandbox, 10_000)
+        .unwrap();
+    lua.enter(|ctx| {
+        let env: Table = ctx.fetch(&env);
+        assert_eq!(env.get::<_, i64>(ctx, "value").unwrap(), 42);
+        assert!(!env.get_value(ctx, "string").is_nil());
+    });
+}
+
+#[test]
+fn load_module_errors() {
+    let mut lua = Lua::full();
+    let sandbox = SandboxBuilder::new().allow_safe_core();
+
+    assert!(matches!(
+        lua.load_module("loop", b"while true do end", &sandbox, 10_000),
+        Err(ModuleError::OutOfFuel)
+    ));
+    assert!(matches!(
+        lua.load_module("bad", b"return return", &sandbox, 10_000),
+        Err(ModuleError::Compile(_))
+    ));
+    assert!(matches!(
+        lua.load_module("error", b"error('oops')", &sandbox, 10_000),
+        Err(ModuleError::Runtime(_))
+    ));
+    assert!(matches!(
+        lua.load_module("number", b"return 1", &sandbox, 10_000),
+        Err(ModuleError::NotATable("number"))
+    ));
+    assert!(matches!(
+        lua.load_module(
+            "io",
+            b"return {}",
+            &SandboxBuilder::new().allow("missing"),
+            10_000
+        ),
+        Err(ModuleError::Sandbox(_))
+    ));
+}
@@ -0,0 +1,77 @@
+use gc_arena::Gc;
+use piccolo::{
+    closure::FunctionPrototype,
+    opcode::{InvalidOpCode, OpCode, Operation, RCIndex},
+    types::{ConstantIndex8, RegisterIndex},
+    Closure, Lua,
+};
+
+fn check_prototype(proto: Gc<'_, FunctionPrototype<'_>>, count: &mut usize) {
+    for &opcode in proto.opcodes.iter() {
+        let encoded = opcode.to_u32();
+        assert_eq!(OpCode::from_u32(encoded), Ok(opcode));
+        assert_eq!(opcode.to_string(), opcode.decode().to_string());
+        *count += 1;
+    }
+    for &proto in proto.prototypes.iter() {
+        check_prototype(proto, count);
+    }
+}
+
+#[test]
+fn opcode_round_trip() {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br##"
+                local t = { 1, 2, 3, x = "y" }
+                local a, b = 1, 2.5
+                t[a] = t.x .. b
+                for i = 1, 10, 2 do
+                    a = a + i * 2 - b / 3 // 1 % 4 ^ 2
+                end
+                for k, v in pairs(t) do
+                    b = (a & 1) | (a ~ 2) << 1 >> 2
+                end
+                local function f(...)
+                    local s = select("#", ...)
+                    if s == 1 or s < 2 and s <= 3 then
+                        return not s, -s, ~s, #t
+                    end
+                    return f(...)
+                end
+                g = t:insert(f(1, 2))
+            "##[..],
+        )
+        .unwrap();
+
+        let mut count = 0;
+        check_prototype(closure.prototype(), &mut count);
+        assert!(count > 0);
+    });
+}
+
+#[test]
+fn opcode_stable_encoding() {
+    let op = OpCode::encode(Operation::Move {
+        dest: RegisterIndex(1),
+        source: RegisterIndex(2),
+    });
+    assert_eq!(op.to_u32(), 0x00_02_01_00);
+    assert_eq!(op.to_string(), "Move dest=R1 source=R2");
+
+    let op = OpCode::encode(Operation::Add {
+        dest: RegisterIndex(3),
+        left: RCIndex::Register(RegisterIndex(4)),
+        right: RCIndex::Constant(ConstantIndex8(5)),
+    });
+    assert_eq!(OpCode::from_u32(op.to_u32()), Ok(op));
+    assert_eq!(op.to_string(), "Add dest=R3 left=R4 right=K5");
+
+    // Unknown operations, invalid booleans, and non-zero padding are all rejected.
+    assert_eq!(OpCode::from_u32(0xff), Err(InvalidOpCode(0xff)));
+    assert!(OpCode::from_u32(0x00_00_02_02).is_err());
+    assert!(OpCode::from_u32(0x01_02_01_00).is_err());
+}
@@ -0,0 +1,100 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{self, Cursor},
+    rc::Rc,
+};
+
+use piccolo::{
+    stdlib::{HostFilesystem, OpenMode, PermissionVfs, VfsAccess, VfsFile, VfsProvider},
+    ExternError, Lua,
+};
+
+mod common;
+
+use common::run;
+
+#[derive(Default, Clone)]
+struct MemoryVfs {
+    files: Rc<RefCell<HashMap<String, Vec<u8>>>>,
+}
+
+impl VfsProvider for MemoryVfs {
+    fn open(&self, path: &str, _mode: OpenMode) -> io::Result<Box<dyn VfsFile>> {
+        match self.files.borrow().get(path) {
+            Some(data) => Ok(Box::new(Cursor::new(data.clone()))),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn remove(&self, path: &str) -> io::Result<()> {
+        match self.files.borrow_mut().remove(path) {
+            Some(_) => Ok(()),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No such file or directory",
+            )),
+        }
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let mut files = self.files.borrow_mut();
+        let data = files.remove(from).ok_or(io::ErrorKind::NotFound)?;
+        files.insert(to.to_owned(), data);
+        Ok(())
+    }
+}
+
+#[test]
+fn os_file_management() -> Result<(), ExternError> {
+    let vfs = MemoryVfs::default();
+    vfs.files
+        .borrow_mut()
+        .insert("a.txt".to_owned(), b"a".to_vec());
+    vfs.files
+        .borrow_mut()
+        .insert("protected.txt".to_owned(), b"p".to_vec());
+
+    let mut lua = Lua::core();
+    lua.load_os_with_vfs(PermissionVfs::new(vfs.clone(), |path, access| {
+        path != "protected.txt" || access == VfsAccess::Open(OpenMode::READ)
+    }));
+
+    run(
+        &mut lua,
+        r#"
+            assert(os.rename("a.txt", "b.txt") == true)
+
+            local ok, err = os.remove("missing.txt")
+            assert(ok == nil and err == "missing.txt: No such file or directory")
+
+            ok, err = os.remove("protected.txt")
+            assert(ok == nil and err == "protected.txt: Permission denied")
+            ok, err = os.rename("b.txt", "protected.txt")
+            assert(ok == nil and err == "Permission denied")
+
+            -- The default implementation of `tmpname` is an error.
+            assert(not pcall(os.tmpname))
+        "#,
+    )?;
+
+    let files = vfs.files.borrow();
+    assert!(files.contains_key("b.txt") && !files.contains_key("a.txt"));
+    assert!(files.contains_key("protected.txt"));
+    Ok(())
+}
+
+#[test]
+fn host_tmpname() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    lua.load_os_with_vfs(HostFilesystem);
+    run(
+        &mut lua,
+        r#"
+            local name = os.tmpname()
+            assert(type(name) == "string")
+            assert(os.remove(name) == true)
+            assert(os.remove(name) == nil)
+        "#,
+    )
+}
@@ -0,0 +1,121 @@
+use std::{cell::RefCell, rc::Rc};
+
+use piccolo::{ExternError, Lua};
+
+mod common;
+
+use common::run;
+
+fn capture(lua: &mut Lua) -> Rc<RefCell<Vec<Vec<u8>>>> {
+    let lines = Rc::new(RefCell::new(Vec::new()));
+    lua.set_output_fn({
+        let lines = lines.clone();
+        move |line| lines.borrow_mut().push(line.to_vec())
+    });
+    lines
+}
+
+#[test]
+fn print_output_fn() -> Result<(), ExternError> {
+    let mut lua_a = Lua::full();
+    let mut lua_b = Lua::full();
+    let lines_a = capture(&mut lua_a);
+    let lines_b = capture(&mut lua_b);
+
+    run(
+        &mut lua_a,
+        r#"
+            print("hello", 1, 2.5, nil, true)
+            print()
+            print("", "x")
+            print(setmetatable({}, { __tostring = function() return "meta" end }))
+        "#,
+    )?;
+    run(&mut lua_b, "print('other')")?;
+
+    assert_eq!(
+        *lines_a.borrow(),
+        vec![
+            b"hello\t1\t2.5\tnil\ttrue\n".to_vec(),
+            b"\n".to_vec(),
+            b"\tx\n".to_vec(),
+            b"meta\n".to_vec(),
+        ]
+    );
+    assert_eq!(*lines_b.borrow(), vec![b"other\n".to_vec()]);
+    Ok(())
+}
+
+#[test]
+fn print_output_writer() -> Result<(), ExternError> {
+    #[derive(Clone, Default)]
+    struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let buf = SharedBuf::default();
+    let mut lua = Lua::full();
+    lua.set_output(buf.clone());
+    run(&mut lua, "print('a') print('b', 'c')")?;
+    assert_eq!(buf.0.borrow().as_slice(), b"a\nb\tc\n");
+    Ok(())
+}
+
+#[test]
+fn warn_control_messages() -> Result<(), ExternError> {
+    let mut lua = Lua::full();
+    let lines = capture(&mut lua);
+    let warnings = Rc::new(RefCell::new(Vec::new()));
+    lua.set_warn_fn({
+        let warnings = warnings.clone();
+        move |message| warnings.borrow_mut().push(message.to_vec())
+    });
+
+    run(
+        &mut lua,
+        r#"
+            warn("disabled by default")
+            warn("@on")
+            warn("hello ", "world ", 1)
+            warn("@unknown")
+            warn("@off")
+            warn("disabled again")
+            assert(not pcall(warn))
+            assert(not pcall(warn, "a", {}))
+        "#,
+    )?;
+
+    assert!(lines.borrow().is_empty());
+    assert_eq!(*warnings.borrow(), vec![b"hello world 1".to_vec()]);
+    Ok(())
+}
+
+#[test]
+fn warn_fn() -> Result<(), ExternError> {
+    let mut lua = Lua::full();
+    let lines = capture(&mut lua);
+    let warnings = Rc::new(RefCell::new(Vec::new()));
+    lua.set_warn_fn({
+        let warnings = warnings.clone();
+        move |message| warnings.borrow_mut().push(message.to_vec())
+    });
+    lua.set_warnings_enabled(true);
+
+    run(&mut lua, "warn('first') warn('@', 'not control')")?;
+
+    assert!(lines.borrow().is_empty());
+    assert_eq!(
+        *warnings.borrow(),
+        vec![b"first".to_vec(), b"@not control".to_vec()]
+    );
+    Ok(())
+}
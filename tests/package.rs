@@ -0,0 +1,174 @@
+use std::{
+    collections::HashMap,
+    io::{self, Cursor},
+};
+
+use piccolo::{
+    stdlib::{self, OpenMode, VfsFile, VfsProvider},
+    Callback, CallbackReturn, ExternError, Lua, Table, Value,
+};
+
+mod common;
+
+use common::run;
+
+struct StaticVfs(HashMap<&'static str, &'static str>);
+
+impl VfsProvider for StaticVfs {
+    fn open(&self, path: &str, _mode: OpenMode) -> io::Result<Box<dyn VfsFile>> {
+        match self.0.get(path) {
+            Some(source) => Ok(Box::new(Cursor::new(source.as_bytes().to_vec()))),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+}
+
+#[test]
+fn require_preload() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        stdlib::load_package(ctx);
+        stdlib::preload(
+            ctx,
+            "counter",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let module = Table::new(&ctx);
+                module.set(ctx, "name", stack.get(0))?;
+                module.set(ctx, "extra", stack.get(1))?;
+                stack.replace(ctx, module);
+                Ok(CallbackReturn::Return)
+            }),
+        );
+    });
+
+    run(
+        &mut lua,
+        r#"
+            local m, extra = require("counter")
+            assert(m.name == "counter" and m.extra == ":preload:" and extra == ":preload:")
+            assert(require("counter") == m)
+            assert(package.loaded.counter == m)
+            assert(require("string") == string)
+
+            package.preload.flag = function() end
+            assert(require("flag") == true)
+
+            local ok, err = pcall(require, "missing")
+            assert(not ok)
+            assert(err == "module 'missing' not found:\n\tno field package.preload['missing']")
+        "#,
+    )
+}
+
+#[test]
+fn require_custom_searcher() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        stdlib::load_package(ctx);
+        stdlib::add_searcher(
+            ctx,
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let name: piccolo::String = stack.consume(ctx)?;
+                if name.as_bytes().starts_with(b"gen.") {
+                    let loader = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                        let (name, data): (Value, Value) = stack.consume(ctx)?;
+                        let module = Table::new(&ctx);
+                        module.set(ctx, "name", name)?;
+                        module.set(ctx, "data", data)?;
+                        stack.replace(ctx, module);
+                        Ok(CallbackReturn::Return)
+                    });
+                    stack.replace(ctx, (loader, 42));
+                } else {
+                    stack.replace(ctx, "not a generated module");
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        );
+    });
+
+    run(
+        &mut lua,
+        r#"
+            local m, data = require("gen.a")
+            assert(m.name == "gen.a" and m.data == 42 and data == 42)
+            assert(require("gen.a") == m)
+            assert(require("gen.b") ~= m)
+
+            local ok, err = pcall(require, "other")
+            assert(not ok)
+            assert(err == "module 'other' not found:\n\tno field package.preload['other']\n\tnot a generated module")
+        "#,
+    )
+}
+
+#[test]
+fn require_files() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    lua.load_package_with_vfs(StaticVfs(HashMap::from([
+        ("./a.lua", "return { b = require('b.c'), name = ... }"),
+        ("./b/c/init.lua", "local name, path = ... return path"),
+        ("./loop1.lua", "return require('loop2')"),
+        ("./loop2.lua", "return require('loop1')"),
+        ("./broken.lua", "return +"),
+        (
+            "./failing.lua",
+            "counter = (counter or 0) + 1 error('failed')",
+        ),
+    ])));
+
+    run(
+        &mut lua,
+        r#"
+            local a = require("a")
+            assert(a.name == "a" and a.b == "./b/c/init.lua")
+
+            local ok, err = pcall(require, "loop1")
+            assert(not ok and err == "loop or previous error loading module 'loop1'")
+            assert(package.loaded.loop1 == nil and package.loaded.loop2 == nil)
+
+            assert(not pcall(require, "broken"))
+
+            assert(not pcall(require, "failing"))
+            assert(not pcall(require, "failing"))
+            assert(counter == 2)
+
+            ok, err = pcall(require, "missing")
+            assert(not ok)
+            assert(string.sub(err, 1, 55) == "module 'missing' not found:\n\tno field package.preload['")
+            assert(string.sub(err, -53) == "no file './missing.lua'\n\tno file './missing/init.lua'")
+
+            package.path = "./?/c/init.lua"
+            assert(require("b") == "./b/c/init.lua")
+        "#,
+    )
+}
+
+#[test]
+fn loaded_is_per_lua() -> Result<(), ExternError> {
+    let mut lua1 = Lua::core();
+    let mut lua2 = Lua::core();
+    for lua in [&mut lua1, &mut lua2] {
+        lua.enter(|ctx| {
+            stdlib::load_package(ctx);
+            stdlib::preload(
+                ctx,
+                "shared",
+                Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                    stack.replace(ctx, Table::new(&ctx));
+                    Ok(CallbackReturn::Return)
+                }),
+            );
+        });
+    }
+
+    run(&mut lua1, "require('shared').value = 1")?;
+    run(&mut lua2, "assert(require('shared').value == nil)")?;
+
+    lua1.enter(|ctx| {
+        let shared: Table = stdlib::loaded(ctx).get(ctx, "shared").unwrap();
+        assert_eq!(shared.get::<_, i64>(ctx, "value").unwrap(), 1);
+    });
+
+    Ok(())
+}
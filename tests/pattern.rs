@@ -0,0 +1,69 @@
+use piccolo::string::pattern::{self, Capture, Captures, PatternError};
+
+#[test]
+fn match_captures() {
+    assert_eq!(
+        pattern::match_captures(b"key = value", b"(%w+)%s*=%s*()(%w+)").unwrap(),
+        Some(Captures {
+            whole: 0..11,
+            captures: vec![
+                Capture::Span(0..3),
+                Capture::Position(6),
+                Capture::Span(6..11)
+            ],
+        })
+    );
+    assert_eq!(pattern::match_captures(b"hello", b"^l").unwrap(), None);
+    assert_eq!(
+        pattern::match_captures_at(b"hello", b"^l", 2).unwrap(),
+        Some(Captures {
+            whole: 2..3,
+            captures: vec![],
+        })
+    );
+    assert_eq!(pattern::match_captures_at(b"hello", b"", 6).unwrap(), None);
+}
+
+#[test]
+fn validate_patterns() {
+    for pat in [
+        &b"%a+"[..],
+        b"^(%w+)=(%w*)$",
+        b"%b()",
+        b"%f[%w]",
+        b"[]%]]",
+        b"(a)%1",
+    ] {
+        assert_eq!(pattern::validate(pat), Ok(()), "{:?}", pat);
+    }
+
+    assert_eq!(pattern::validate(b"%"), Err(PatternError::EndsWithPercent));
+    assert_eq!(
+        pattern::validate(b"[abc"),
+        Err(PatternError::MissingBracket)
+    );
+    assert_eq!(
+        pattern::validate(b"%fx"),
+        Err(PatternError::MissingFrontierBracket)
+    );
+    assert_eq!(
+        pattern::validate(b"%b("),
+        Err(PatternError::MissingBalanceArguments)
+    );
+    assert_eq!(
+        pattern::validate(b"(a%1)"),
+        Err(PatternError::InvalidCaptureIndex(1))
+    );
+    assert_eq!(pattern::validate(b"a)"), Err(PatternError::InvalidCapture));
+    assert_eq!(
+        pattern::validate(b"(a"),
+        Err(PatternError::UnfinishedCapture)
+    );
+
+    // Matching only reports the errors it reaches.
+    assert_eq!(pattern::match_captures(b"abc", b"x%").unwrap(), None);
+    assert_eq!(
+        pattern::match_captures(b"abc", b"a%"),
+        Err(PatternError::EndsWithPercent)
+    );
+}
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+
+use piccolo::{Closure, Executor, ExternError, Fuel, Lua, Profile, ProfileMetric};
+
+fn profile(source: &str) -> Result<Profile, ExternError> {
+    let mut lua = Lua::full();
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, Some("test"), source.as_bytes())?;
+        let executor = Executor::start(ctx, closure.into(), ());
+        executor.set_profiling(&ctx, true)?;
+        Ok(ctx.stash(executor))
+    })?;
+    lua.execute::<()>(&executor)?;
+    Ok(lua.enter(|ctx| ctx.fetch(&executor).take_profile(&ctx).unwrap().unwrap()))
+}
+
+#[test]
+fn collapsed_stacks() -> Result<(), ExternError> {
+    let profile = profile(
+        r#"
+            local function fib(n)
+                if n < 2 then
+                    return n
+                end
+                return fib(n - 1) + fib(n - 2)
+            end
+
+            local function run()
+                for _ = 1, 10 do
+                    tostring(fib(10))
+                end
+            end
+
+            run()
+        "#,
+    )?;
+
+    let collapsed = profile.to_collapsed(ProfileMetric::Fuel);
+    for line in collapsed.lines() {
+        let (stack, count) = line.rsplit_once(' ').unwrap();
+        assert!(stack.starts_with("<chunk> (test)"));
+        assert!(count.parse::<u64>().unwrap() > 0);
+    }
+    assert!(collapsed.contains("<chunk> (test);run (test:9);fib (test:2);fib (test:2)"));
+    assert!(collapsed.contains("run (test:9);[callback]"));
+
+    let inclusive = profile.inclusive();
+    let total = profile.total();
+    assert_eq!(inclusive["<chunk> (test)"], total);
+    let fib = inclusive["fib (test:2)"];
+    assert!(fib.fuel > 0 && fib.fuel < inclusive["run (test:9)"].fuel);
+
+    Ok(())
+}
+
+#[test]
+fn incremental_profiles() -> Result<(), ExternError> {
+    let mut lua = Lua::full();
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, Some("test"), &b"for i = 1, 1000 do end"[..])?;
+        let executor = Executor::start(ctx, closure.into(), ());
+        assert!(!executor.is_profiling());
+        assert!(executor.take_profile(&ctx)?.is_none());
+        executor.set_profiling(&ctx, true)?;
+        Ok(ctx.stash(executor))
+    })?;
+
+    let mut combined = Profile::new();
+    loop {
+        let (finished, part) = lua.enter(|ctx| {
+            let executor = ctx.fetch(&executor);
+            let finished = executor.step(ctx, &mut Fuel::with(500)).unwrap();
+            (finished, executor.take_profile(&ctx).unwrap().unwrap())
+        });
+        combined.merge(&part);
+        if finished {
+            break;
+        }
+    }
+
+    assert!(combined.total().fuel >= 1000);
+    Ok(())
+}
+
+#[test]
+fn function_stats() -> Result<(), ExternError> {
+    let profile = profile(
+        r#"
+            local function square(n)
+                return n * n
+            end
+
+            local total = 0
+            for i = 1, 100 do
+                total = total + square(i)
+            end
+            tostring(total)
+        "#,
+    )?;
+
+    let functions: BTreeMap<_, _> = profile.functions().collect();
+    let square = functions["square (test:2)"];
+    assert_eq!(square.calls, 100);
+    assert!(square.instructions >= 200);
+    assert!(square.cost.fuel > 0);
+    assert_eq!(functions["<chunk> (test)"].calls, 1);
+    assert_eq!(functions["[callback] tostring"].calls, 1);
+    assert_eq!(functions["[callback] tostring"].instructions, 0);
+
+    // Exclusive costs add up to the total.
+    let total: u64 = functions.values().map(|stats| stats.cost.fuel).sum();
+    assert_eq!(total, profile.total().fuel);
+
+    // Every instruction of `square` is on its single line of code.
+    let lines: Vec<_> = profile
+        .lines()
+        .filter(|(function, _, _)| *function == "square (test:2)")
+        .map(|(_, line, instructions)| (line.to_string(), instructions))
+        .collect();
+    assert_eq!(lines, [("3".to_owned(), square.instructions)]);
+
+    let report = profile.to_report(ProfileMetric::Fuel, 5);
+    assert!(report.lines().any(|l| l.ends_with("square (test:2)")));
+    assert!(report.contains("square (test:2) line 3"));
+
+    Ok(())
+}
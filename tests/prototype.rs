@@ -0,0 +1,150 @@
+use allocator_api2::vec;
+use gc_arena::allocator_api::MetricsAlloc;
+use piccolo::{
+    opcode::{OpCode, Operation},
+    types::{Opt254, RegisterIndex},
+    Closure, Constant, Context, Executor, ExternError, FunctionPrototype, Lua, PrototypeError,
+};
+
+#[test]
+fn rewrite_constants() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let proto = FunctionPrototype::compile(
+            ctx,
+            "chunk",
+            &br#"
+                local function path(name)
+                    return "assets/" .. name
+                end
+                return path("a.png"), "password123", 42
+            "#[..],
+        )?;
+        proto.validate()?;
+
+        let strings: Vec<_> = proto
+            .constants
+            .iter()
+            .filter_map(|c| match c {
+                Constant::String(s) => Some(s.to_str().unwrap().to_owned()),
+                _ => None,
+            })
+            .collect();
+        assert!(strings.iter().any(|s| s == "password123"));
+
+        let proto = proto.map_constants(&ctx, |c| match c {
+            Constant::String(s) if s == "password123" => {
+                Constant::String(ctx.intern(b"<redacted>"))
+            }
+            Constant::String(s) if s == "assets/" => Constant::String(ctx.intern(b"cdn/")),
+            Constant::Integer(i) => Constant::Integer(i + 1),
+            c => c,
+        })?;
+
+        let closure = Closure::new(&ctx, proto, Some(ctx.globals()))?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    let (path, secret, number) = lua.execute::<(String, String, i64)>(&executor)?;
+    assert_eq!(path, "cdn/a.png");
+    assert_eq!(secret, "<redacted>");
+    assert_eq!(number, 43);
+
+    Ok(())
+}
+
+fn replace_opcodes<'gc>(
+    ctx: Context<'gc>,
+    proto: FunctionPrototype<'gc>,
+    f: impl FnOnce(&mut Vec<OpCode>),
+) -> FunctionPrototype<'gc> {
+    let mut opcodes = proto.opcodes.to_vec();
+    f(&mut opcodes);
+    let mut replaced = vec::Vec::new_in(MetricsAlloc::new(&ctx));
+    replaced.extend(opcodes);
+    FunctionPrototype {
+        opcodes: replaced.into_boxed_slice(),
+        ..proto
+    }
+}
+
+#[test]
+fn verify_bytecode() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    lua.try_enter(|ctx| {
+        let compile = || {
+            FunctionPrototype::compile(
+                ctx,
+                "chunk",
+                &br#"
+                    local t = { 1, 2, 3, x = "y", ... }
+                    for i = 1, #t do
+                        t[i] = t[i] .. i
+                    end
+                    for k, v in pairs(t) do
+                        if k == 1 or v ~= "y" and not k then
+                            break
+                        end
+                    end
+                    local function f(...)
+                        return t, ...
+                    end
+                    return f(t:concat())
+                "#[..],
+            )
+        };
+
+        // Everything the compiler produces is valid.
+        compile()?.validate()?;
+
+        let proto = replace_opcodes(ctx, compile()?, |ops| {
+            ops.insert(
+                0,
+                OpCode::encode(Operation::Move {
+                    dest: RegisterIndex(200),
+                    source: RegisterIndex(0),
+                }),
+            );
+        });
+        assert!(matches!(
+            proto.validate(),
+            Err(PrototypeError::RegisterOutOfRange {
+                opcode: 0,
+                register: 200,
+                ..
+            })
+        ));
+
+        let proto = replace_opcodes(ctx, compile()?, |ops| {
+            ops.insert(
+                0,
+                OpCode::encode(Operation::Jump {
+                    offset: -2,
+                    close_upvalues: Opt254::none(),
+                }),
+            );
+        });
+        assert!(matches!(
+            proto.validate(),
+            Err(PrototypeError::JumpOutOfRange {
+                opcode: 0,
+                target: -1,
+                ..
+            })
+        ));
+
+        let proto = replace_opcodes(ctx, compile()?, |ops| {
+            ops.pop();
+        });
+        assert!(matches!(
+            proto.validate(),
+            Err(PrototypeError::MissingReturn)
+        ));
+
+        Ok(())
+    })?;
+
+    Ok(())
+}
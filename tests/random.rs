@@ -0,0 +1,77 @@
+use piccolo::{random::RandomSource, Closure, Executor, ExternError, Lua, Variadic};
+
+fn random_values(lua: &mut Lua) -> Result<Vec<i64>, ExternError> {
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local values = {}
+                for i = 1, 8 do
+                    values[i] = math.random(0)
+                end
+                values[9] = math.random(1, 6)
+                values[10] = math.random(math.mininteger, math.maxinteger)
+                return table.unpack(values)
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    Ok(lua.execute::<Variadic<Vec<i64>>>(&executor)?.0)
+}
+
+#[test]
+fn deterministic_seed() -> Result<(), ExternError> {
+    let mut lua_a = Lua::core();
+    let mut lua_b = Lua::core();
+    lua_a.set_random_seed(1234, 5678);
+    lua_b.set_random_seed(1234, 5678);
+
+    let values = random_values(&mut lua_a)?;
+    assert_eq!(values, random_values(&mut lua_b)?);
+
+    lua_b.set_random_seed(1234, 5679);
+    assert_ne!(values, random_values(&mut lua_b)?);
+
+    // Seeding from Lua is the same as seeding from Rust.
+    let mut lua_c = Lua::core();
+    let executor = lua_c.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, &b"math.randomseed(1234, 5678)"[..])?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua_c.execute::<()>(&executor)?;
+    assert_eq!(values, random_values(&mut lua_c)?);
+
+    Ok(())
+}
+
+#[test]
+fn custom_source() -> Result<(), ExternError> {
+    struct Counter(u64);
+
+    impl RandomSource for Counter {
+        fn next_u64(&mut self) -> u64 {
+            self.0 += 1;
+            self.0
+        }
+
+        fn seed(&mut self, n1: i64, _n2: i64) {
+            self.0 = n1 as u64;
+        }
+
+        fn randomize(&mut self) -> (i64, i64) {
+            self.seed(100, 0);
+            (100, 0)
+        }
+    }
+
+    let mut lua = Lua::core();
+    lua.set_random_source(Counter(0));
+    let values = random_values(&mut lua)?;
+    assert_eq!(&values[..8], &[1, 2, 3, 4, 5, 6, 7, 8]);
+    // 9 masked to the range [0, 7] is 1, which is within [0, 5].
+    assert_eq!(values[8], 2);
+    assert_eq!(values[9], i64::MIN + 10);
+
+    Ok(())
+}
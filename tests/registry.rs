@@ -0,0 +1,52 @@
+use piccolo::{Lua, Table, Value};
+
+#[test]
+fn named_slots() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let registry = ctx.registry();
+        let metatable = Table::new(&ctx);
+        assert!(registry
+            .set_named(&ctx, "mymod", "metatable", metatable)
+            .is_nil());
+        registry.set_named(&ctx, "mymod", "version", 3i64);
+        registry.set_named(&ctx, "other", "metatable", true);
+    });
+
+    lua.enter(|ctx| {
+        let registry = ctx.registry();
+        assert!(matches!(
+            registry.get_named("mymod", "metatable"),
+            Value::Table(_)
+        ));
+        assert!(matches!(
+            registry.get_named("other", "metatable"),
+            Value::Boolean(true)
+        ));
+        assert!(registry.get_named("mymod", "missing").is_nil());
+        assert!(registry.get_named("missing", "metatable").is_nil());
+
+        let mut names = registry
+            .named_slots("mymod")
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["metatable", "version"]);
+
+        assert!(matches!(
+            registry.set_named(&ctx, "mymod", "version", Value::Nil),
+            Value::Integer(3)
+        ));
+        assert_eq!(registry.named_slots("mymod").len(), 1);
+
+        registry.clear_namespace(&ctx, "mymod");
+        assert!(registry.named_slots("mymod").is_empty());
+        assert!(registry.get_named("mymod", "metatable").is_nil());
+        assert!(matches!(
+            registry.get_named("other", "metatable"),
+            Value::Boolean(true)
+        ));
+    });
+}
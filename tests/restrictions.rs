@@ -0,0 +1,89 @@
+use piccolo::{
+    compiler::{ParseErrorKind, Restrictions},
+    Closure, CompileOptions, CompilerError, Executor, Lua, Table,
+};
+
+fn load(lua: &mut Lua, source: &str, restrictions: Restrictions) -> Result<(), CompilerError> {
+    lua.enter(|ctx| {
+        Closure::load_with_options(
+            ctx,
+            None,
+            source.as_bytes(),
+            ctx.globals(),
+            CompileOptions {
+                restrictions,
+                ..Default::default()
+            },
+        )
+        .map(|_| ())
+    })
+}
+
+fn is_restricted(result: Result<(), CompilerError>) -> bool {
+    matches!(
+        result,
+        Err(CompilerError::Parsing(err)) if matches!(err.kind, ParseErrorKind::Restricted(_))
+    )
+}
+
+#[test]
+fn reject_loops() {
+    let mut lua = Lua::core();
+    let restrictions = Restrictions::NO_UNBOUNDED_LOOPS;
+
+    assert!(load(&mut lua, "for i = 1, 10 do end", restrictions).is_ok());
+    assert!(load(&mut lua, "for k, v in pairs({}) do end", restrictions).is_ok());
+    assert!(is_restricted(load(
+        &mut lua,
+        "while true do end",
+        restrictions
+    )));
+    assert!(is_restricted(load(
+        &mut lua,
+        "repeat until false",
+        restrictions
+    )));
+    assert!(is_restricted(load(&mut lua, "::a:: goto a", restrictions)));
+    assert!(is_restricted(load(
+        &mut lua,
+        "local function f() while true do end end",
+        restrictions,
+    )));
+
+    assert!(load(&mut lua, "while false do end", Restrictions::default()).is_ok());
+}
+
+#[test]
+fn expression_only() -> Result<(), anyhow::Error> {
+    let mut lua = Lua::core();
+    let restrictions = Restrictions {
+        expression_only: true,
+        ..Default::default()
+    };
+
+    assert!(load(&mut lua, "x = 1", restrictions).is_err());
+    assert!(load(&mut lua, "1 + 2 return 3", restrictions).is_err());
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load_with_options(
+            ctx,
+            None,
+            &b"{ name = 'config', size = 2 + 3 }"[..],
+            ctx.globals(),
+            CompileOptions {
+                restrictions,
+                ..Default::default()
+            },
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.finish(&executor)?;
+    lua.try_enter(|ctx| {
+        let table = ctx.fetch(&executor).take_result::<Table>(ctx)??;
+        assert_eq!(table.get::<_, i64>(ctx, "size")?, 5);
+        Ok(())
+    })?;
+
+    Ok(())
+}
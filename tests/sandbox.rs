@@ -0,0 +1,205 @@
+use gc_arena::Gc;
+use piccolo::{
+    closure::ClosureError, Closure, Executor, ExternError, Function, Lua, SandboxBuilder,
+    SandboxError, Table,
+};
+
+fn run_sandboxed(lua: &mut Lua, sandbox: &SandboxBuilder, source: &str) -> Result<(), ExternError> {
+    let executor = lua.try_enter(|ctx| {
+        let env = sandbox.build(ctx)?;
+        let closure = Closure::load_with_env(ctx, Some("sandbox"), source.as_bytes(), env)?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)
+}
+
+#[test]
+fn sandbox_whitelist() -> Result<(), ExternError> {
+    let mut lua = Lua::full();
+    let sandbox = SandboxBuilder::new()
+        .allow_all(["assert", "pcall", "type"])
+        .allow("string.sub")
+        .allow("math");
+
+    run_sandboxed(
+        &mut lua,
+        &sandbox,
+        r#"
+            assert(print == nil and io == nil and os == nil and require == nil)
+            assert(string.sub("hello", 2, 3) == "el")
+            assert(string.len == nil)
+            assert(math.floor(1.5) == 1)
+
+            -- Globals set by the script stay in the sandbox.
+            x = 1
+            string.extra = true
+            assert(not pcall(function() math.floor = nil end))
+            assert(math.floor ~= nil)
+        "#,
+    )?;
+
+    lua.enter(|ctx| {
+        assert!(ctx.get_global_value("x").is_nil());
+        let string: Table = ctx.get_global("string").unwrap();
+        assert!(string.get_value(ctx, "extra").is_nil());
+        assert!(!string.get_value(ctx, "len").is_nil());
+    });
+
+    Ok(())
+}
+
+#[test]
+fn sandbox_read_only() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    lua.try_enter(|ctx| {
+        let shared = Table::new(&ctx);
+        shared.set(ctx, 1, "a")?;
+        shared.set(ctx, 2, "b")?;
+        let nested = Table::new(&ctx);
+        nested.set(ctx, "value", 1)?;
+        shared.set(ctx, "nested", nested)?;
+        shared.set(ctx, "again", nested)?;
+        ctx.set_global("shared", shared);
+        Ok(())
+    })?;
+
+    let sandbox = SandboxBuilder::new().allow_safe_core().allow("shared");
+    run_sandboxed(
+        &mut lua,
+        &sandbox,
+        r#"
+            assert(#shared == 2 and shared[2] == "b")
+            assert(shared.nested.value == 1)
+            assert(shared.nested == shared.again)
+            assert(getmetatable(shared) == false)
+            assert(not pcall(setmetatable, shared, nil))
+            assert(not pcall(function() shared.nested.value = 2 end))
+            assert(not pcall(function() shared[3] = "c" end))
+            assert(not pcall(function() math.pi = 3 end))
+
+            local count = 0
+            for k, v in pairs(shared) do
+                count = count + 1
+                if k == "nested" then
+                    assert(not pcall(function() v.value = 2 end))
+                end
+            end
+            assert(count == 4)
+
+            local items = {}
+            for i, v in ipairs(shared) do
+                items[i] = v
+            end
+            assert(#items == 2 and items[1] == "a")
+        "#,
+    )?;
+
+    let writable = SandboxBuilder::new().read_only(false).allow("shared");
+    run_sandboxed(&mut lua, &writable, "shared.nested.value = 2")?;
+    lua.enter(|ctx| {
+        let shared: Table = ctx.get_global("shared").unwrap();
+        let nested: Table = shared.get(ctx, "nested").unwrap();
+        assert_eq!(nested.get::<_, i64>(ctx, "value").unwrap(), 2);
+    });
+
+    Ok(())
+}
+
+#[test]
+fn sandbox_string_metatable() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    let sandbox = SandboxBuilder::new().allow_safe_core();
+    run_sandboxed(
+        &mut lua,
+        &sandbox,
+        r#"
+            local mt = getmetatable("")
+            assert(mt.__index == string)
+            assert(mt == getmetatable("other"))
+            assert(getmetatable({}) == nil)
+            assert(not pcall(function() getmetatable("").__index = {} end))
+            assert(not pcall(function() getmetatable("").__index.format = print end))
+            assert(not pcall(function() mt.__index.upper = nil end))
+            assert(("abc"):upper() == "ABC")
+        "#,
+    )?;
+
+    lua.enter(|ctx| {
+        let metatable = ctx.string_metatable().unwrap();
+        let string: Table = ctx.get_global("string").unwrap();
+        assert!(matches!(
+            metatable.get_value(ctx, "__index"),
+            piccolo::Value::Table(t) if t == string
+        ));
+        assert!(!string.get_value(ctx, "format").is_nil());
+        assert!(!string.get_value(ctx, "upper").is_nil());
+    });
+
+    Ok(())
+}
+
+#[test]
+fn sandbox_missing_global() {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        assert!(matches!(
+            SandboxBuilder::new().allow("nonexistent").build(ctx),
+            Err(SandboxError::NotFound(path)) if path == "nonexistent"
+        ));
+        assert!(matches!(
+            SandboxBuilder::new().allow("string.nonexistent").build(ctx),
+            Err(SandboxError::NotFound(path)) if path == "string.nonexistent"
+        ));
+        assert!(matches!(
+            SandboxBuilder::new().allow("print.x").build(ctx),
+            Err(SandboxError::NotATable(path)) if path == "print"
+        ));
+    });
+}
+
+#[test]
+fn sandbox_shared_prototype() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    let sandbox = SandboxBuilder::new().allow("math.max");
+
+    let (a, b) = lua.try_enter(|ctx| {
+        let template = Closure::load(
+            ctx,
+            Some("template"),
+            &b"count = (count or 0) + 1 return math.max(count, 0), function() return count end"[..],
+        )?;
+
+        let (env_a, env_b) = (sandbox.build(ctx)?, sandbox.build(ctx)?);
+        let a = template.with_env(&ctx, env_a)?;
+        let b = template.with_env(&ctx, env_b)?;
+        assert!(Gc::ptr_eq(a.prototype(), template.prototype()));
+        assert!(Gc::ptr_eq(b.prototype(), template.prototype()));
+
+        Ok((
+            ctx.stash(Executor::start(ctx, a.into(), ())),
+            ctx.stash(Executor::start(ctx, b.into(), ())),
+        ))
+    })?;
+
+    lua.finish(&a).unwrap();
+    let inner = lua.try_enter(|ctx| {
+        let (count, inner): (i64, Closure) = ctx.fetch(&a).take_result(ctx)??;
+        assert_eq!(count, 1);
+        // Nested closures have upvalues other than `_ENV`, and cannot be given a new one.
+        assert!(matches!(
+            inner.with_env(&ctx, Table::new(&ctx)),
+            Err(ClosureError::HasUpValues)
+        ));
+        Ok(ctx.stash(Function::from(inner)))
+    })?;
+
+    lua.try_enter(|ctx| {
+        ctx.fetch(&a).restart(ctx, ctx.fetch(&inner), ());
+        Ok(())
+    })?;
+    assert_eq!(lua.execute::<i64>(&a)?, 1);
+    assert_eq!(lua.execute::<i64>(&b)?, 1);
+
+    lua.enter(|ctx| assert!(ctx.get_global_value("count").is_nil()));
+    Ok(())
+}
@@ -0,0 +1,169 @@
+use std::{cell::RefCell, rc::Rc};
+
+use piccolo::{
+    scheduler::Completion, Closure, Executor, ExternError, Lua, Scheduler, StashedExecutor,
+};
+
+fn start(lua: &mut Lua, source: &str) -> Result<StashedExecutor, ExternError> {
+    lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, source.as_bytes())?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })
+}
+
+const INFINITE_LOOP: &str = "local i = 0 while true do i = i + 1 end";
+
+#[test]
+fn fair_distribution() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    let mut scheduler = Scheduler::with_slice(1000);
+
+    let mut tasks = Vec::new();
+    for _ in 0..3 {
+        tasks.push(scheduler.spawn(start(&mut lua, INFINITE_LOOP)?));
+    }
+    let high = scheduler.spawn(start(&mut lua, INFINITE_LOOP)?);
+    scheduler.set_priority(high, 3);
+
+    let consumed = scheduler.run(&mut lua, 600_000).unwrap();
+    assert!(consumed >= 600_000);
+
+    let fuel = tasks
+        .iter()
+        .map(|&task| scheduler.fuel_consumed(task).unwrap() as f64)
+        .collect::<Vec<_>>();
+    let min = fuel.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = fuel.iter().copied().fold(0.0, f64::max);
+    assert!(max / min < 1.1);
+
+    let ratio = scheduler.fuel_consumed(high).unwrap() as f64 / min;
+    assert!((2.5..3.5).contains(&ratio));
+
+    Ok(())
+}
+
+#[test]
+fn no_starvation() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    let mut scheduler = Scheduler::with_slice(1000);
+
+    let mut tasks = Vec::new();
+    for _ in 0..4 {
+        tasks.push(scheduler.spawn(start(&mut lua, INFINITE_LOOP)?));
+    }
+
+    // Every budget is only enough for one turn, but the next call continues with the next task.
+    for _ in 0..4 {
+        scheduler.run(&mut lua, 1000).unwrap();
+    }
+    for &task in &tasks {
+        assert!(scheduler.fuel_consumed(task).unwrap() > 0);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn quotas_and_completion() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    let mut scheduler = Scheduler::with_slice(1000);
+    let log = Rc::new(RefCell::new(Vec::new()));
+
+    let looping = scheduler.spawn(start(&mut lua, INFINITE_LOOP)?);
+    scheduler.set_quota(looping, Some(5000));
+    scheduler.set_on_complete(looping, {
+        let log = log.clone();
+        move |_, _, completion| {
+            assert_eq!(completion, Completion::QuotaExhausted);
+            log.borrow_mut().push("loop".to_owned());
+        }
+    });
+
+    let sum = scheduler.spawn(start(
+        &mut lua,
+        "local s = 0 for i = 1, 10000 do s = s + i end return s",
+    )?);
+    scheduler.set_on_complete(sum, {
+        let log = log.clone();
+        move |lua, executor, completion| {
+            let s = lua
+                .try_enter(|ctx| ctx.fetch(executor).take_result::<i64>(ctx)?)
+                .unwrap();
+            assert_eq!(completion, Completion::Result);
+            log.borrow_mut().push(format!("sum {s}"));
+        }
+    });
+
+    // A task whose main thread yields stays scheduled as long as its callback resumes it.
+    let generator = scheduler.spawn(start(
+        &mut lua,
+        "for i = 1, 3 do coroutine.yield(i) end return 0",
+    )?);
+    scheduler.set_on_complete(generator, {
+        let log = log.clone();
+        move |lua, executor, _| {
+            lua.enter(|ctx| {
+                let executor = ctx.fetch(executor);
+                let i = executor.take_result::<i64>(ctx).unwrap().unwrap();
+                log.borrow_mut().push(format!("generator {i}"));
+                if i != 0 {
+                    executor.resume(ctx, ()).unwrap();
+                }
+            })
+        }
+    });
+
+    // Without a callback, the result is left for whoever holds the executor.
+    let plain = start(&mut lua, "return 'plain'")?;
+    scheduler.spawn(plain.clone());
+
+    assert_eq!(scheduler.len(), 4);
+    scheduler.finish(&mut lua, 10_000).unwrap();
+    assert!(scheduler.is_empty());
+
+    let log = log.borrow();
+    assert!(log.contains(&"loop".to_owned()));
+    assert!(log.contains(&"sum 50005000".to_owned()));
+    let generated = log
+        .iter()
+        .filter(|entry| entry.starts_with("generator"))
+        .cloned()
+        .collect::<Vec<_>>();
+    assert_eq!(
+        generated,
+        ["generator 1", "generator 2", "generator 3", "generator 0"]
+    );
+    assert_eq!(
+        lua.try_enter(|ctx| ctx.fetch(&plain).take_result::<String>(ctx)?)?,
+        "plain"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn suspended_tasks_are_skipped() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    let mut scheduler = Scheduler::new();
+
+    let yielding = start(&mut lua, "coroutine.yield() return 1")?;
+    let task = scheduler.spawn(yielding.clone());
+    scheduler.set_on_complete(task, |lua, executor, _| {
+        // Take the results without resuming, after a yield this leaves the task suspended.
+        lua.try_enter(|ctx| ctx.fetch(executor).take_result::<()>(ctx)?)
+            .unwrap();
+    });
+    let other = scheduler.spawn(start(&mut lua, "return")?);
+
+    scheduler.finish(&mut lua, 10_000).unwrap();
+    assert!(!scheduler.contains(other));
+    assert!(scheduler.contains(task));
+    assert_eq!(scheduler.run(&mut lua, 10_000).unwrap(), 0);
+
+    // Once resumed by the host, the task runs to completion.
+    lua.enter(|ctx| ctx.fetch(&yielding).resume(ctx, ()).unwrap());
+    scheduler.finish(&mut lua, 10_000).unwrap();
+    assert!(scheduler.is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,38 @@
+use std::io::Write;
+
+use piccolo::Lua;
+
+#[test]
+fn scratch_buffers_are_reused() {
+    let mut lua = Lua::empty();
+
+    lua.enter(|ctx| {
+        let ptr = {
+            let mut buffer = ctx.scratch_buffer();
+            write!(&mut *buffer, "hello {}", 42).unwrap();
+            assert_eq!(ctx.intern(&buffer), "hello 42");
+            buffer.as_ptr()
+        };
+
+        let mut a = ctx.scratch_buffer();
+        assert!(a.is_empty());
+        assert_eq!(a.as_ptr(), ptr);
+
+        // Borrowing more than one buffer at once is fine.
+        let mut b = ctx.scratch_buffer();
+        a.extend(b"a");
+        b.extend(b"b");
+        assert_eq!(&a[..], b"a");
+        assert_eq!(&b[..], b"b");
+    });
+
+    lua.enter(|ctx| {
+        let mut buffer = ctx.scratch_buffer();
+        buffer.resize(1024 * 1024, 0);
+    });
+
+    lua.enter(|ctx| {
+        // Very large buffers are not retained across calls to `Lua::enter`.
+        assert!(ctx.scratch_buffer().capacity() < 1024 * 1024);
+    });
+}
@@ -26,3 +26,27 @@ do
     local i, j = -16, 3
     assert(i // j == math.floor(i / j))
 end
+
+do
+    -- Integer and float operands of the same type take a fast path in the VM, which must agree with
+    -- the general case.
+    local max, min = math.maxinteger, math.mininteger
+    assert(max + 1 == min)
+    assert(min - 1 == max)
+    assert(max * 2 == -2)
+    assert(math.type(1 + 2) == "integer")
+    assert(math.type(1.0 + 2.0) == "float")
+    assert(math.type(1 + 2.0) == "float")
+
+    local nan = 0 / 0
+    assert(not (nan < nan) and not (nan <= nan))
+    assert(not (nan < 1.0) and not (1.0 <= nan))
+    assert(1 < 2 and 1 <= 1 and not (2 < 1))
+    assert(1.5 < 2.5 and 2.5 <= 2.5)
+
+    local sum = 0
+    for i = 1, 100 do
+        sum = sum + i * 2 - 1
+    end
+    assert(sum == 10000)
+end
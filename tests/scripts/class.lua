@@ -0,0 +1,73 @@
+do
+    local Animal = class("Animal")
+
+    function Animal:init(name)
+        self.name = name
+    end
+
+    function Animal:speak()
+        return "..."
+    end
+
+    function Animal:describe()
+        return self.name .. " says " .. self:speak()
+    end
+
+    Animal.__eq = function(a, b)
+        return a.name == b.name
+    end
+
+    local Dog = class.new("Dog", Animal)
+
+    function Dog:speak()
+        return "woof"
+    end
+
+    local generic = Animal("generic")
+    local rex = Dog("rex")
+
+    assert(generic:describe() == "generic says ...")
+    assert(rex:describe() == "rex says woof")
+
+    assert(class.isinstance(rex, Dog))
+    assert(class.isinstance(rex, Animal))
+    assert(not class.isinstance(generic, Dog))
+    assert(not class.isinstance({}, Animal))
+    assert(not class.isinstance(1, Animal))
+    assert(not pcall(class.isinstance, rex, {}))
+
+    assert(class.of(rex) == Dog)
+    assert(class.of({}) == nil)
+    assert(class.parent(Dog) == Animal)
+    assert(class.parent(Animal) == nil)
+    assert(class.name(Dog) == "Dog")
+    assert(class.isclass(Dog))
+    assert(not class.isclass(rex))
+
+    -- Metamethods of the parent are copied when the subclass is created
+    assert(Dog("a") == Dog("a"))
+    assert(Dog("a") ~= Dog("b"))
+end
+
+do
+    -- Classes without `init` just create an empty instance
+    local Point = class()
+    local p = Point(1, 2)
+    assert(class.of(p) == Point)
+    assert(next(p) == nil)
+    assert(class.name(Point) == nil)
+
+    -- Constructors return the instance regardless of what `init` returns
+    local Counter = class()
+    function Counter:init(n)
+        self.n = n
+        return 42
+    end
+    assert(Counter(3).n == 3)
+
+    -- `init` is inherited
+    local Sub = class(nil, Counter)
+    assert(Sub(7).n == 7)
+
+    assert(not pcall(class.new, "Bad", {}))
+end
@@ -0,0 +1,35 @@
+local function allocate()
+    local t = {}
+    for i = 1, 10000 do
+        t[i] = { i }
+    end
+    return #t
+end
+
+do
+    local before = collectgarbage("count")
+    assert(allocate() == 10000)
+    local during = collectgarbage("count")
+    assert(during > before)
+
+    -- The collection happens before the script continues.
+    assert(collectgarbage() == 0)
+    assert(collectgarbage("count") < during)
+end
+
+do
+    assert(collectgarbage("isrunning") == true)
+    assert(collectgarbage("stop") == 0)
+    assert(collectgarbage("isrunning") == false)
+    allocate()
+    assert(collectgarbage("step") == true)
+    assert(collectgarbage("restart") == 0)
+    assert(collectgarbage("isrunning") == true)
+end
+
+do
+    assert(collectgarbage("incremental", 150) == "incremental")
+    assert(collectgarbage("generational") == "incremental")
+    assert(collectgarbage("incremental", 200) == "incremental")
+    assert(not pcall(collectgarbage, "nonsense"))
+end
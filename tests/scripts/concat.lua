@@ -52,3 +52,27 @@ do
     assert(table.concat(t, "", 1, #t) == "abcdefghijklmnopqrstuvwxyz")
     assert(table.concat(t, "!", 1, #t) == "a!b!c!d!e!f!g!h!i!j!k!l!m!n!o!p!q!r!s!t!u!v!w!x!y!z")
 end
+
+do
+    -- table.concat never uses `__concat` or `__tostring`
+    local mt = { __concat = function() return "x" end, __tostring = function() return "x" end }
+    local ok, err = pcall(table.concat, { "a", setmetatable({}, mt) })
+    assert(not ok and err == "invalid value (at index 2) in table for 'concat'")
+
+    ok, err = pcall(table.concat, { 1, 2, 3, false }, ",", 2)
+    assert(not ok and err == "invalid value (at index 4) in table for 'concat'")
+
+    assert(not pcall(table.concat, { "a", "b" }, {}))
+    assert(table.concat({ "a", "b" }, 0) == "a0b")
+end
+
+do
+    -- values are read through `__index`
+    local t = setmetatable({}, {
+        __index = function(_, i) return "v" .. i end,
+        __len = function() return 3 end,
+    })
+    assert(table.concat(t, " ") == "v1 v2 v3")
+    assert(table.concat(t, "", 5, 6) == "v5v6")
+    assert(table.concat(t, "", 3, 2) == "")
+end
@@ -103,3 +103,97 @@ do
         coroutine.yieldto(co)
     end) == false)
 end
+
+do
+    -- Thread names and parents
+    local main = coroutine.running()
+    local co = coroutine.create(function()
+        assert(coroutine.getname(coroutine.running()) == "worker")
+        return coroutine.parent(coroutine.running())
+    end)
+
+    assert(coroutine.getname(co) == nil)
+    coroutine.setname(co, "worker")
+    assert(coroutine.getname(co) == "worker")
+    assert(string.sub(tostring(co), 1, 17) == "<thread 'worker' ")
+    assert(coroutine.parent(co) == main)
+
+    local ok, parent = coroutine.resume(co)
+    assert(ok and parent == main)
+    assert(coroutine.getname(co) == "worker")
+
+    coroutine.setname(co, nil)
+    assert(coroutine.getname(co) == nil)
+end
+
+do
+    -- coroutine.wrap
+    local gen = coroutine.wrap(function(a)
+        local b = coroutine.yield(a + 1)
+        local c = coroutine.yield(b * 2)
+        return c, "done"
+    end)
+    assert(gen(1) == 2)
+    assert(gen(3) == 6)
+    local c, done = gen(4)
+    assert(c == 4 and done == "done")
+
+    local ok, err = pcall(gen)
+    assert(not ok and err == "cannot resume dead coroutine")
+
+    -- Errors are raised in the caller with the original error object.
+    local obj = {}
+    local failing = coroutine.wrap(function() error(obj) end)
+    ok, err = pcall(failing)
+    assert(not ok and err == obj)
+    ok, err = pcall(failing)
+    assert(not ok and err == "cannot resume dead coroutine")
+
+    local recursive
+    recursive = coroutine.wrap(function() return recursive() end)
+    ok, err = pcall(recursive)
+    assert(not ok and err == "cannot resume non-suspended coroutine")
+end
+
+do
+    -- coroutine.close
+    local captured
+    local co = coroutine.create(function()
+        local x = 1
+        captured = function() return x end
+        coroutine.yield()
+        x = 2
+    end)
+    coroutine.resume(co)
+    assert(coroutine.status(co) == "suspended")
+    assert(coroutine.close(co) == true)
+    assert(coroutine.status(co) == "dead")
+    assert(captured() == 1)
+    assert(not coroutine.resume(co))
+
+    assert(coroutine.close(coroutine.create(function() end)) == true)
+    assert(coroutine.close(co) == true)
+
+    local ok, err = pcall(coroutine.close, coroutine.running())
+    assert(not ok and err == "cannot close a running coroutine")
+
+    local outer
+    outer = coroutine.create(function()
+        local inner = coroutine.create(function()
+            return pcall(coroutine.close, outer)
+        end)
+        return coroutine.resume(inner)
+    end)
+    local _, _, ok2, err2 = coroutine.resume(outer)
+    assert(not ok2 and err2 == "cannot close a normal coroutine")
+end
+
+do
+    -- coroutine.isyieldable
+    assert(coroutine.isyieldable() == false)
+    assert(coroutine.isyieldable(coroutine.running()) == false)
+    local co = coroutine.create(function() return coroutine.isyieldable() end)
+    assert(coroutine.isyieldable(co) == true)
+    local _, yieldable = coroutine.resume(co)
+    assert(yieldable == true)
+end
@@ -0,0 +1,65 @@
+local function check_error(expected, f, ...)
+    local ok, err = pcall(f, ...)
+    assert(not ok)
+    err = tostring(err)
+    assert(string.find(err, expected, 1, true), err)
+end
+
+do
+    check_error("attempt to call a nil value (global 'undefined_function')", function()
+        undefined_function()
+    end)
+
+    check_error("attempt to index a nil value (global 'undefined_table')", function()
+        return undefined_table.x
+    end)
+
+    check_error("attempt to index a nil value (global 'undefined_table')", function()
+        undefined_table.x = 1
+    end)
+end
+
+do
+    local t = {}
+
+    check_error("attempt to index a nil value (field 'missing')", function()
+        return t.missing.x
+    end)
+
+    check_error("attempt to call a nil value (field 'missing')", function()
+        t.missing()
+    end)
+
+    check_error("attempt to call a nil value (method 'missing')", function()
+        t:missing()
+    end)
+
+    check_error("attempt to call a nil value (method 'missing')", function()
+        return t:missing()
+    end)
+
+    check_error("attempt to index a nil value (upvalue 'u')", function()
+        local u = nil
+        return (function() return u.x end)()
+    end)
+end
+
+do
+    check_error("attempt to index a nil value (local 'l')", function(...)
+        local l = ...
+        return l.x
+    end)
+
+    check_error("attempt to call a number value (local 'n')", function()
+        local n = 1
+        n()
+    end)
+
+    check_error("attempt to index a boolean value (local 'b')", function(b)
+        b.x = 1
+    end, true)
+
+    check_error("attempt to call a table value", function()
+        ({})()
+    end)
+end
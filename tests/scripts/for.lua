@@ -120,6 +120,67 @@ function test_mixed_floats()
     return true
 end
 
+function test_float_limits()
+    -- integer loops with a float limit stay integer loops
+    local iters = 0
+    for i = 1, 10.5 do
+        iters = iters + 1
+        assert(math.type(i) == "integer")
+    end
+    assert(iters == 10)
+
+    iters = 0
+    for i = 10, -0.5, -1 do
+        iters = iters + 1
+    end
+    assert(iters == 11)
+
+    iters = 0
+    for i = 1, 0 / 0 do
+        iters = iters + 1
+    end
+    assert(iters == 0)
+
+    iters = 0
+    for i = math.mininteger, -math.huge do
+        iters = iters + 1
+    end
+    assert(iters == 0)
+
+    iters = 0
+    for i = 1, -9.2e18 do
+        iters = iters + 1
+    end
+    assert(iters == 0)
+
+    return true
+end
+
+function test_compare_jump()
+    -- comparisons jump past their block, closing any upvalues on the way
+    local closures = {}
+    local i = 0
+    while i < 5 do
+        local j = i
+        closures[#closures + 1] = function() return j end
+        if j == 3 then
+            break
+        end
+        i = i + 1
+    end
+    assert(#closures == 4 and closures[1]() == 0 and closures[4]() == 3)
+
+    local count = 0
+    for k = 1, 20 do
+        if k <= 10 then count = count + 1 end
+        if 2.5 < k then count = count + 1 end
+        if k ~= 7 then count = count + 1 end
+    end
+    assert(count == 10 + 18 + 19)
+
+    return true
+end
+
 assert(
     test_generic() and
     test_numeric() and
@@ -127,5 +188,7 @@ assert(
     test_generic_closure() and
     test_break_scope() and
     test_mixed_floats() and
+    test_float_limits() and
+    test_compare_jump() and
     test_overflow()
 )
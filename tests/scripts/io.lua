@@ -0,0 +1,9 @@
+do
+    -- The stdlib loaded by `Lua::full` cannot access the host filesystem.
+    local f, err = io.open("Cargo.toml")
+    assert(f == nil and string.find(err, "filesystem access is disabled", 1, true))
+    assert(not pcall(io.lines, "Cargo.toml"))
+    assert(not pcall(io.input, "Cargo.toml"))
+    assert(not pcall(require, "tests.scripts.os"))
+    assert(os.remove == nil and os.rename == nil and os.tmpname == nil)
+end
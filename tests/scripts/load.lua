@@ -0,0 +1,63 @@
+do
+    local f = load("return 1 + 2")
+    assert(f() == 3)
+
+    local f, e = load("return return")
+    assert(f == nil and type(e) == "string")
+
+    local f = load("local a, b = ... return a * b")
+    assert(f(6, 7) == 42)
+end
+
+do
+    -- Chunks use the given environment, even if it is nil.
+    local env = { x = 5 }
+    local f = load("y = x * 2 return y", "chunk", "t", env)
+    assert(f() == 10 and env.y == 10 and y == nil)
+
+    local f = load("return x", "chunk", "t", nil)
+    assert(not pcall(f))
+
+    x = "global"
+    local f = load("return x", "chunk", "t")
+    assert(f() == "global")
+    x = nil
+end
+
+do
+    local f, e = load("return 1", "chunk", "b")
+    assert(f == nil and string.find(e, "attempt to load a text chunk"))
+
+    local f, e = load("\27Lua", "chunk", "t")
+    assert(f == nil and string.find(e, "attempt to load a binary chunk"))
+
+    local f, e = load("\27Lua", "chunk", "bt")
+    assert(f == nil and type(e) == "string")
+end
+
+do
+    -- Chunks can be read in pieces from a function.
+    local pieces = { "return ", "'a' ", ".. ", "'b'" }
+    local i = 0
+    local f = load(function()
+        i = i + 1
+        return pieces[i]
+    end)
+    assert(f() == "ab")
+
+    local done = false
+    local f = load(function()
+        if done then
+            return ""
+        end
+        done = true
+        return "return 7"
+    end)
+    assert(f() == 7)
+
+    local f, e = load(function() return {} end)
+    assert(f == nil and e == "reader function must return a string")
+
+    local f, e = load(function() error("reader failed") end)
+    assert(f == nil and string.find(e, "reader failed"))
+end
@@ -255,6 +255,28 @@ do
     assert(is_err(function()
         return math.random(5, 3)
     end))
+
+    assert(is_err(function()
+        return math.random(1, 2, 3)
+    end))
+
+    -- The interval may span the entire integer range.
+    for i = 1, 1000, 1 do
+        assert(is_integer(math.random(math.mininteger, math.maxinteger)))
+        local negative = math.random(math.mininteger, -1)
+        assert(is_integer(negative) and negative < 0)
+    end
+    assert(math.random(3, 3) == 3)
+
+    -- `math.randomseed` returns the seed that was used.
+    local a, b = math.randomseed(42)
+    assert(a == 42 and b == 0)
+    a, b = math.randomseed()
+    assert(is_integer(a) and is_integer(b))
+    math.randomseed(a, b)
+    local first = math.random(0)
+    math.randomseed(a, b)
+    assert(math.random(0) == first)
 end
 
 do
@@ -339,3 +361,17 @@ do
     assert(is_err(function() return "" + 2 end))
     assert(" 0x0 " + 2 == 2)
 end
+
+do
+    -- Floats only convert to integers if they are exactly representable
+    local big = 2^63
+    assert(math.tointeger(big) == nil)
+    assert(math.tointeger(-big) == math.mininteger)
+    assert(math.type(math.floor(big)) == "float")
+    assert(not pcall(function() return big | 0 end))
+
+    local t = {}
+    t[math.maxinteger] = "max"
+    t[big] = "big"
+    assert(t[math.maxinteger] == "max" and t[big] == "big")
+end
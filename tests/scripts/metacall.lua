@@ -29,3 +29,16 @@ assert(
   test1() == 5 and
   test2() == 7
 )
+
+do
+  -- Chains of callable tables are followed.
+  local inner = setmetatable({}, { __call = function(self, outer, a) return a * 2 end })
+  local outer = setmetatable({}, { __call = inner })
+  assert(outer(21) == 42)
+
+  -- Infinite chains raise an error instead of looping forever.
+  local t = {}
+  setmetatable(t, { __call = t })
+  local ok, err = pcall(t)
+  assert(not ok and string.find(err, "chain too long"))
+end
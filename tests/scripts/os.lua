@@ -0,0 +1,36 @@
+do
+    assert(os.time{year = 1970, month = 1, day = 1, hour = 0} == 0)
+    assert(os.time{year = 2000, month = 1, day = 1, hour = 0} == 946684800)
+    -- Fields are normalized and `hour` defaults to noon
+    assert(os.time{year = 1999, month = 13, day = 1} == 946684800 + 12 * 3600)
+    assert(math.type(os.time()) == "integer")
+
+    -- Fields which do not fit in a C `int` raise an error instead of overflowing
+    local ok, err = pcall(os.time, {year = math.maxinteger, month = 1, day = 1})
+    assert(not ok and string.find(err, "field 'year' is out-of-bound", 1, true))
+    ok, err = pcall(os.time, {year = 2000, month = math.mininteger, day = 1})
+    assert(not ok and string.find(err, "field 'month' is out-of-bound", 1, true))
+    ok, err = pcall(os.time, {year = 2000, month = 1, day = 1, sec = math.maxinteger})
+    assert(not ok and string.find(err, "field 'sec' is out-of-bound", 1, true))
+    assert(os.time{year = 1970, month = 1, day = 1, hour = 0, sec = 2147483647} == 2147483647)
+end
+
+do
+    assert(os.date("!%Y-%m-%d %H:%M:%S", 0) == "1970-01-01 00:00:00")
+    assert(os.date("%c", 0) == "Thu Jan  1 00:00:00 1970")
+    assert(os.date("%A %B %j %%", 946684800 + 86400 * 40) == "Thursday February 041 %")
+
+    local t = os.date("*t", 86400 + 3661)
+    assert(t.year == 1970 and t.month == 1 and t.day == 2)
+    assert(t.hour == 1 and t.min == 1 and t.sec == 1)
+    assert(t.wday == 6 and t.yday == 2 and t.isdst == false)
+    assert(os.time(t) == 86400 + 3661)
+
+    assert(not pcall(os.date, "%Q", 0))
+end
+
+do
+    assert(os.difftime(5, 3) == 2)
+    assert(type(os.clock()) == "number")
+    assert(os.getenv("PICCOLO_TEST_UNSET_ENVIRONMENT_VARIABLE") == nil)
+end
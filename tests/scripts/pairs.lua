@@ -68,3 +68,23 @@ do
   local a, b = inext(t, math.maxinteger)
   assert(a == -9223372036854775808 and b == 4)
 end
+
+do
+  local backing = {a = 1, b = 2}
+  local proxy = setmetatable({}, {
+    __pairs = function(t)
+      assert(next(t) == nil)
+      return next, backing, nil, "extra"
+    end
+  })
+
+  local count = 0
+  for k, v in pairs(proxy) do
+    assert(backing[k] == v)
+    count = count + 1
+  end
+  assert(count == 2)
+
+  assert(select("#", pairs(proxy)) == 3)
+  assert(not pcall(pairs, 1))
+end
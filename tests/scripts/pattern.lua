@@ -0,0 +1,60 @@
+do
+    assert(string.find("hello world", "wor") == 7)
+    assert(select(2, string.find("hello world", "wor")) == 9)
+    assert(string.find("hello", "l", 1, true) == 3)
+    assert(string.find("a.b", ".", 1, true) == 1)
+    assert(string.find("a.b", "%.") == 2)
+    assert(string.find("hello", "xyz") == nil)
+    assert(string.find("hello", "") == 1)
+    assert(string.find("hello", "", 10) == nil)
+    assert(string.find("hello", "l", -2) == 4)
+
+    local s, e, k, v = string.find("key = value", "(%w+)%s*=%s*(%w+)")
+    assert(s == 1 and e == 11 and k == "key" and v == "value")
+end
+
+do
+    assert(string.match("hello 123", "%d+") == "123")
+    assert(string.match("hello", "^ell") == nil)
+    assert(string.match("hello", "^hel") == "hel")
+    assert(string.match("hello", "lo$") == "lo")
+    assert(string.match("  trim  ", "^%s*(.-)%s*$") == "trim")
+    assert(string.match("[[nested]]", "%b[]") == "[[nested]]")
+    assert(string.match("THE (quick) fox", "%f[%a]%a+") == "THE")
+    assert(string.match("abcabc", "(abc)%1") == "abc")
+    assert(string.match("x = 10", "()=()") == 3)
+    assert(select(2, string.match("x = 10", "()=()")) == 4)
+    assert(string.match("a+b", "[%+]") == "+")
+    assert(string.match("a-z", "[a%-z]+") == "a-z")
+    assert(string.match("123abc", "[^%d]+") == "abc")
+    assert(string.match("aaab", "a-b") == "aaab")
+    assert(string.match("aaa", "a?a?a?a") == "aaa")
+end
+
+do
+    local words = {}
+    for word in string.gmatch("one two  three", "%a+") do
+        words[#words + 1] = word
+    end
+    assert(#words == 3 and words[1] == "one" and words[3] == "three")
+
+    local pairs = {}
+    for k, v in string.gmatch("a=1, b=2", "(%w+)=(%w+)") do
+        pairs[k] = tonumber(v)
+    end
+    assert(pairs.a == 1 and pairs.b == 2)
+
+    local count = 0
+    for _ in string.gmatch("abc", "") do
+        count = count + 1
+    end
+    assert(count == 4)
+end
+
+do
+    assert(not pcall(string.find, "abc", "%"))
+    assert(not pcall(string.match, "abc", "[a"))
+    assert(not pcall(string.match, "abc", "(a"))
+    assert(not pcall(string.match, "abc", "a)"))
+    assert(not pcall(string.match, "abc", "%1"))
+end
@@ -27,3 +27,57 @@ do
     table.sort(list)
     assert(#list == 100 and is_sorted(list))
 end
+
+do
+    local t = { 5, 3, 8, 1, 9, 2 }
+    table.sort(t, function(a, b) return a > b end)
+    assert(table.concat(t, ",") == "9,8,5,3,2,1")
+
+    t = { "banana", "apple", "cherry" }
+    table.sort(t)
+    assert(table.concat(t, ",") == "apple,banana,cherry")
+
+    t = { { n = 3 }, { n = 1 }, { n = 2 } }
+    table.sort(t, function(a, b) return a.n < b.n end)
+    assert(t[1].n == 1 and t[2].n == 2 and t[3].n == 3)
+end
+
+do
+    local ok, err = pcall(table.sort, { 3, 1, 2, 2 }, function(a, b) return a <= b end)
+    assert(not ok and err == "invalid order function for sorting")
+
+    ok, err = pcall(table.sort, { 3, 1, 2 }, function(a, b) return true end)
+    assert(not ok and err == "invalid order function for sorting")
+
+    ok, err = pcall(table.sort, { 1, 2 }, 3)
+    assert(not ok and err == "bad argument #2 to 'sort' (function expected, got number)")
+
+    ok, err = pcall(table.sort, nil)
+    assert(not ok and err == "bad argument #1 to 'sort' (table expected, got nil)")
+
+    assert(not pcall(table.sort, { 1, "a", 2 }))
+
+    local big = setmetatable({}, { __len = function() return math.maxinteger end })
+    ok, err = pcall(table.sort, big)
+    assert(not ok and err == "bad argument #1 to 'sort' (array too big)")
+end
+
+do
+    -- sorting goes through `__index` and `__newindex`
+    local store = { 3, 1, 2 }
+    local proxy = setmetatable({}, {
+        __index = store,
+        __newindex = store,
+        __len = function() return #store end,
+    })
+    table.sort(proxy)
+    assert(rawget(proxy, 1) == nil)
+    assert(store[1] == 1 and store[2] == 2 and store[3] == 3)
+end
+
+do
+    -- sorting does not leak any globals
+    table.sort({ 2, 1 })
+    table.move({ 1 }, 1, 1, 1, {})
+    assert(src == nil and dst == nil and arr == nil and cmp == nil)
+end
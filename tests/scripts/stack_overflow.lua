@@ -0,0 +1,24 @@
+local function depth(n)
+    if n == 0 then
+        return 0
+    end
+    return 1 + depth(n - 1)
+end
+
+-- Deep recursion within the limits is fine.
+assert(depth(10000) == 10000)
+
+local function forever(n)
+    return 1 + forever(n + 1)
+end
+
+local ok, err = pcall(forever, 1)
+assert(not ok and string.find(err, "stack overflow"))
+
+-- The thread is usable again once the error has unwound.
+assert(depth(100) == 100)
+
+-- Coroutines have their own limits.
+local co = coroutine.create(forever)
+local ok, err = coroutine.resume(co, 1)
+assert(not ok and string.find(err, "stack overflow"))
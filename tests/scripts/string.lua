@@ -96,3 +96,26 @@ do
     assert(string.upper(80) == "80")
     assert(string.upper(3.14) == "3.14")
 end
+
+do
+    local s = "hello"
+    assert(s:upper() == "HELLO")
+    assert(("HeLLo"):lower() == "hello")
+    assert(s:sub(1, 3) == "hel")
+    assert(s:len() == 5)
+    assert(s:find("l") == 3)
+    assert(("key=value"):match("(%w+)=(%w+)") == "key")
+    assert(s.upper == string.upper)
+    assert(s.missing == nil)
+
+    -- Methods added to the string table are visible through every string.
+    function string.shout(s) return s:upper() .. "!" end
+    assert(s:shout() == "HELLO!")
+    string.shout = nil
+
+    -- The real metatable is returned, like PUC-Rio Lua.
+    assert(getmetatable(s).__index == string)
+    assert(getmetatable(s) == getmetatable("other"))
+    assert(is_err(function() return getmetatable(1) end))
+    assert(is_err(function() return s.x.y end))
+end
@@ -0,0 +1,45 @@
+local function is_border(t, n)
+    return (n == 0 or t[n] ~= nil) and t[n + 1] == nil
+end
+
+do
+    local t = {}
+    for i = 1, 1000 do
+        t[#t + 1] = i
+        assert(#t == i)
+    end
+    for i = 1000, 1, -1 do
+        assert(#t == i)
+        t[#t] = nil
+    end
+    assert(#t == 0)
+end
+
+do
+    local t = { 1, 2, 3, 4, 5, 6, 7, 8, nil, nil }
+    assert(#t == 8)
+    t[4] = nil
+    assert(is_border(t, #t))
+    t[8] = nil
+    assert(is_border(t, #t))
+    t[1] = nil
+    assert(is_border(t, #t))
+    t[4] = 4
+    t[8] = 8
+    assert(is_border(t, #t))
+end
+
+do
+    local t = {}
+    local seed = 1
+    for i = 1, 2000 do
+        seed = (seed * 1103515245 + 12345) % 2147483648
+        local k = seed % 200 + 1
+        if seed % 3 == 0 then
+            t[k] = nil
+        else
+            t[k] = i
+        end
+        assert(is_border(t, #t))
+    end
+end
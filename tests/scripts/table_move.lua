@@ -62,3 +62,21 @@ do
     table.move(a, 1, 3, -1, b)
     assert(arrays_eq(b, { [-1] = 1, [0] = 2, 3 }))
 end
+
+do
+    local a = setmetatable({}, { __index = function(_, i) return i * 10 end })
+    local log = {}
+    local b = setmetatable({}, { __newindex = function(_, k, v) log[#log + 1] = k .. "=" .. v end })
+
+    assert(table.move(a, 1, 3, 1, b) == b)
+    assert(table.concat(log, ",") == "1=10,2=20,3=30")
+
+    local t = { 1, 2, 3 }
+    assert(table.move(t, 3, 1, 1) == t)
+    assert(arrays_eq(t, { 1, 2, 3 }))
+
+    assert(not pcall(table.move, t, 1, math.maxinteger, 2))
+    assert(not pcall(table.move, t, 1, 2, math.maxinteger))
+    assert(not pcall(table.move, t, 1, 2, 1, 5))
+    assert(not pcall(table.move, t, "x", 2, 1))
+end
@@ -0,0 +1,39 @@
+do
+    assert(tostring(1) == "1")
+    assert(tostring(-7) == "-7")
+    assert(tostring(1.0) == "1.0")
+    assert(tostring(-0.0) == "-0.0")
+    assert(tostring(0.0) == "0.0")
+    assert(tostring(2.5) == "2.5")
+    assert(tostring(0.1) == "0.1")
+    assert(tostring(1 / 3) == "0.33333333333333")
+    assert(tostring(1e100) == "1e+100")
+    assert(tostring(1e15) == "1e+15")
+    assert(tostring(1e13) == "10000000000000.0")
+    assert(tostring(123456789012345.0) == "1.2345678901234e+14")
+    assert(tostring(1e-5) == "1e-05")
+    assert(tostring(0.0001) == "0.0001")
+    assert(tostring(2^63) == "9.2233720368548e+18")
+    assert(tostring(1 / 0) == "inf")
+    assert(tostring(-1 / 0) == "-inf")
+    local nan = tostring(0 / 0)
+    assert(nan == "nan" or nan == "-nan")
+end
+
+do
+    assert(1.0 .. "" == "1.0")
+    assert(1.5 .. "|" .. 2 == "1.5|2")
+    assert(table.concat({ 1, 2.0, 1e100 }, " ") == "1 2.0 1e+100")
+end
+
+do
+    local t = setmetatable({}, { __name = "Point" })
+    assert(string.sub(tostring(t), 1, 7) == "Point: ")
+
+    local u = setmetatable({}, { __name = "Point", __tostring = function() return "point" end })
+    assert(tostring(u) == "point")
+
+    -- A non-string `__name` is ignored
+    local v = setmetatable({}, { __name = 1 })
+    assert(string.sub(tostring(v), 1, 7) == "<table ")
+end
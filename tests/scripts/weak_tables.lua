@@ -0,0 +1,65 @@
+do
+    local kept = {}
+    local weak_values, weak_keys, weak_both, strong
+
+    -- Fill the tables in a function, so that no register still refers to the garbage.
+    local function fill()
+        weak_values = setmetatable({}, { __mode = "v" })
+        weak_values[1] = {}
+        weak_values[2] = kept
+        weak_values.str = "strings are not collected"
+        weak_values.f = function() end
+
+        weak_keys = setmetatable({}, { __mode = "k" })
+        weak_keys[{}] = 1
+        weak_keys[kept] = 2
+
+        weak_both = setmetatable({}, { __mode = "kv" })
+        weak_both[{}] = kept
+        weak_both[kept] = {}
+        weak_both[kept] = nil
+        weak_both.kept = kept
+
+        strong = setmetatable({}, {})
+        strong[1] = {}
+    end
+    fill()
+
+    collectgarbage()
+
+    assert(weak_values[1] == nil)
+    assert(weak_values[2] == kept)
+    assert(weak_values.str == "strings are not collected")
+    assert(weak_values.f == nil)
+
+    local count = 0
+    for k, v in pairs(weak_keys) do
+        assert(k == kept and v == 2)
+        count = count + 1
+    end
+    assert(count == 1)
+
+    count = 0
+    for k, v in pairs(weak_both) do
+        assert(k == "kept" and v == kept)
+        count = count + 1
+    end
+    assert(count == 1)
+
+    assert(strong[1] ~= nil)
+end
+
+do
+    -- Removing the mode makes the table strong again.
+    local t
+    local function fill()
+        t = setmetatable({}, { __mode = "v" })
+        t[1] = {}
+        setmetatable(t, nil)
+    end
+    fill()
+
+    collectgarbage()
+
+    assert(t[1] ~= nil)
+end
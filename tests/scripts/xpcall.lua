@@ -0,0 +1,80 @@
+do
+    local ok, a, b = xpcall(function(x, y) return x + y, "ok" end, error, 1, 2)
+    assert(ok == true and a == 3 and b == "ok")
+end
+
+do
+    local seen
+    local ok, e = xpcall(function() error("boom") end, function(e)
+        seen = e
+        return "handled: " .. e
+    end)
+    assert(ok == false and e == "handled: " .. seen)
+    assert(string.find(seen, "boom"))
+end
+
+do
+    -- The handler is called with the original error value.
+    local t = {}
+    local ok, e = xpcall(function() error(t) end, function(e) return e == t end)
+    assert(ok == false and e == true)
+end
+
+do
+    -- Errors caught by an inner pcall do not call the handler.
+    local calls = 0
+    local ok, r1, r2 = xpcall(function()
+        return pcall(error, "inner")
+    end, function(e)
+        calls = calls + 1
+        return e
+    end)
+    assert(ok == true and r1 == false and r2 == "inner" and calls == 0)
+end
+
+do
+    -- Only the nearest handler is called.
+    local outer, inner = 0, 0
+    local ok, e = xpcall(function()
+        local ok, e = xpcall(error, function(e)
+            inner = inner + 1
+            return "inner " .. e
+        end, "x")
+        assert(ok == false and e == "inner x")
+        error("y")
+    end, function(e)
+        outer = outer + 1
+        return "outer " .. e
+    end)
+    assert(ok == false and e == "outer y" and inner == 1 and outer == 1)
+end
+
+do
+    -- An error within the handler is not handled again.
+    local calls = 0
+    local ok, e = xpcall(error, function(e)
+        calls = calls + 1
+        error("again")
+    end, "first")
+    assert(ok == false and e == "error in error handling" and calls == 1)
+end
+
+do
+    -- Errors within the handler can still be caught.
+    local ok, e = xpcall(error, function(e)
+        local ok, inner = pcall(error, "inner")
+        return e .. " " .. inner
+    end, "outer")
+    assert(ok == false and e == "outer inner")
+end
+
+do
+    -- Only the first return value of the handler is used.
+    local ok, e = xpcall(error, function(e) return end, "x")
+    assert(ok == false and e == nil)
+end
+
+do
+    local ok = pcall(xpcall, error)
+    assert(ok == false)
+end
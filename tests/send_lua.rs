@@ -0,0 +1,114 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use piccolo::{ExternError, SendLua, SharedDataError, SharedTable, SharedValue};
+
+#[test]
+fn send_lua_is_send() {
+    fn assert_send<T: Send>() {}
+    assert_send::<SendLua>();
+}
+
+#[test]
+fn run_on_other_thread() -> Result<(), ExternError> {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let output = Arc::new(Mutex::new(Vec::new()));
+
+    let mut lua = SendLua::full();
+    lua.set_function("count", {
+        let calls = calls.clone();
+        move |args| {
+            calls.fetch_add(1, Ordering::Relaxed);
+            Ok(vec![Some(SharedValue::Integer(args.len() as i64))])
+        }
+    });
+    lua.set_output(SharedOutput(output.clone()));
+    lua.set_global("limit", Some(SharedValue::Integer(3)));
+
+    let mut lua = thread::spawn(move || {
+        let results = lua.run(
+            "test",
+            br#"
+                local n = 0
+                for i = 1, limit do
+                    n = n + count(i, nil, "x")
+                end
+                print(n)
+                total = n
+                return n, "done", nil
+            "#,
+        )?;
+        assert!(matches!(results[..], [Some(SharedValue::Integer(9)), Some(SharedValue::String(ref s)), None] if &s[..] == b"done"));
+        Ok::<_, ExternError>(lua)
+    })
+    .join()
+    .unwrap()?;
+
+    assert_eq!(calls.load(Ordering::Relaxed), 3);
+    assert_eq!(&output.lock().unwrap()[..], b"9\n");
+    assert!(matches!(
+        lua.get_global("total"),
+        Ok(Some(SharedValue::Integer(9)))
+    ));
+    Ok(())
+}
+
+#[test]
+fn shared_tables() -> Result<(), ExternError> {
+    let mut lua = SendLua::core();
+    lua.run(
+        "test",
+        br#"
+            function sum(t)
+                local n = 0
+                for _, v in ipairs(t) do
+                    n = n + v
+                end
+                return n, { sum = n }
+            end
+        "#,
+    )?;
+
+    let input = SharedTable::from_sequence([1i64, 2, 3]);
+    let results = lua.call("sum", &[Some(SharedValue::Table(input))])?;
+    assert!(matches!(results[0], Some(SharedValue::Integer(6))));
+    match &results[1] {
+        Some(SharedValue::Table(t)) => {
+            assert!(matches!(t.get("sum"), Some(SharedValue::Integer(6))))
+        }
+        _ => panic!("expected a table"),
+    }
+    Ok(())
+}
+
+#[test]
+fn unsupported_values() -> Result<(), ExternError> {
+    let mut lua = SendLua::core();
+    lua.set_function("id", |args| Ok(args));
+    lua.run("test", b"f = function() end")?;
+    assert!(matches!(
+        lua.get_global("f"),
+        Err(SharedDataError::UnsupportedValue("function"))
+    ));
+    assert!(lua.run("test", b"return id(print)").is_err());
+    assert!(lua.run("test", b"return function() end").is_err());
+    Ok(())
+}
+
+struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
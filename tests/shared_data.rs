@@ -0,0 +1,106 @@
+use piccolo::{ExternError, Lua, SharedDataError, SharedKey, SharedTable, SharedValue};
+
+mod common;
+
+use common::run;
+
+fn names() -> SharedTable {
+    let nested = SharedTable::from_sequence([10i64, 20, 30]);
+    [
+        (SharedKey::from("greeting"), SharedValue::from("hello")),
+        (SharedKey::from("pi"), SharedValue::from(3.5)),
+        (SharedKey::from(true), SharedValue::from(false)),
+        (SharedKey::from(1i64), SharedValue::from("one")),
+        (SharedKey::from(2i64), SharedValue::from("two")),
+        (SharedKey::from("nested"), SharedValue::from(nested)),
+    ]
+    .into_iter()
+    .collect()
+}
+
+#[test]
+fn shared_between_instances() -> Result<(), ExternError> {
+    let shared = names();
+    assert_eq!(shared.len(), 2);
+
+    let mut lua_a = Lua::full();
+    let mut lua_b = Lua::full();
+    for lua in [&mut lua_a, &mut lua_b] {
+        lua.enter(|ctx| {
+            ctx.set_global("data", shared.clone());
+        });
+        run(
+            lua,
+            r#"
+                assert(data.greeting == "hello")
+                assert(data.pi == 3.5)
+                assert(data[true] == false)
+                assert(data[1.0] == "one")
+                assert(data.missing == nil)
+                assert(#data == 2)
+                assert(#data.nested == 3 and data.nested[3] == 30)
+                assert(data.nested == data.nested)
+                assert(data.nested ~= data)
+
+                local seq = {}
+                for i, v in ipairs(data) do
+                    seq[i] = v
+                end
+                assert(#seq == 2 and seq[1] == "one" and seq[2] == "two")
+
+                local keys = {}
+                for k in pairs(data) do
+                    keys[#keys + 1] = tostring(k)
+                end
+                assert(table.concat(keys, ",") == "1,2,true,greeting,nested,pi")
+
+                local ok, err = pcall(function() data.greeting = "bye" end)
+                assert(not ok and err:find("frozen"))
+                assert(data.greeting == "hello")
+            "#,
+        )?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn from_lua_table() -> Result<(), ExternError> {
+    let mut lua = Lua::full();
+    run(
+        &mut lua,
+        r#"
+            local inner = { x = 1 }
+            plain = { inner, inner, name = "t", [3.0] = 1.5 }
+            cyclic = { 1, 2 }
+            cyclic.self = cyclic
+            with_function = { f = print }
+        "#,
+    )?;
+
+    let (shared, cyclic, with_function) = lua.enter(|ctx| {
+        let table = |name| SharedTable::from_table(ctx.get_global(name).unwrap());
+        (table("plain"), table("cyclic"), table("with_function"))
+    });
+
+    let shared = shared.unwrap();
+    assert_eq!(shared.len(), 3);
+    assert!(matches!(shared.get(3i64), Some(SharedValue::Number(n)) if *n == 1.5));
+    assert!(matches!(shared.get("name"), Some(SharedValue::String(s)) if &**s == b"t"));
+    match (shared.get(1i64), shared.get(2i64)) {
+        (Some(SharedValue::Table(a)), Some(SharedValue::Table(b))) => {
+            // A table referenced twice is only copied once.
+            assert!(a.ptr_eq(b));
+            assert!(matches!(a.get("x"), Some(SharedValue::Integer(1))));
+        }
+        _ => panic!("unexpected contents"),
+    }
+
+    assert!(matches!(cyclic, Err(SharedDataError::Cycle)));
+    assert!(matches!(
+        with_function,
+        Err(SharedDataError::UnsupportedValue("function"))
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,174 @@
+use piccolo::{
+    thread::{SnapshotError, SnapshotHooks},
+    Callback, CallbackReturn, Closure, Context, Executor, ExternError, Function, Lua, Thread,
+    ThreadMode, Value,
+};
+
+const SCRIPT: &str = r#"
+    local counter = 0
+    local log = {}
+    log.self = log
+
+    local function bump(n)
+        counter = counter + n
+        return counter
+    end
+
+    local gen = coroutine.create(function(a)
+        local x = a
+        while true do
+            x = x + 1
+            coroutine.yield(x)
+        end
+    end)
+
+    for i = 1, 3 do
+        local _, v = coroutine.resume(gen, 10)
+        log[#log + 1] = bump(v)
+        coroutine.yield(i)
+    end
+
+    return counter, #log, log.self == log
+"#;
+
+#[test]
+fn snapshot_and_restore() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    let (thread, executor) = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, SCRIPT.as_bytes())?;
+        let thread = Thread::new(ctx);
+        thread.start(ctx, closure.into(), ()).unwrap();
+        let executor = Executor::run(&ctx, thread).unwrap();
+        Ok((ctx.stash(thread), ctx.stash(executor)))
+    })?;
+    assert_eq!(lua.execute::<i64>(&executor)?, 1);
+
+    let snapshot = lua.enter(|ctx| {
+        let thread = ctx.fetch(&thread);
+        assert_eq!(thread.mode(), ThreadMode::Suspended);
+        thread.snapshot(ctx, &mut ()).unwrap()
+    });
+
+    // The original keeps running independently of the snapshot.
+    lua.enter(|ctx| ctx.fetch(&executor).resume(ctx, ()).unwrap());
+    assert_eq!(lua.execute::<i64>(&executor)?, 2);
+
+    let mut restored = Lua::core();
+    let executor = restored.enter(|ctx| {
+        let thread = Thread::restore(ctx, &snapshot, &mut ()).unwrap();
+        assert_eq!(thread.mode(), ThreadMode::Suspended);
+        let executor = Executor::run(&ctx, thread).unwrap();
+        executor.resume(ctx, ()).unwrap();
+        ctx.stash(executor)
+    });
+    assert_eq!(restored.execute::<i64>(&executor)?, 2);
+    restored.enter(|ctx| ctx.fetch(&executor).resume(ctx, ()).unwrap());
+    assert_eq!(restored.execute::<i64>(&executor)?, 3);
+    restored.enter(|ctx| ctx.fetch(&executor).resume(ctx, ()).unwrap());
+    assert_eq!(
+        restored.execute::<(i64, i64, bool)>(&executor)?,
+        (36, 3, true)
+    );
+
+    Ok(())
+}
+
+struct CallbackHooks;
+
+impl<'gc> SnapshotHooks<'gc> for CallbackHooks {
+    fn save_value(&mut self, _ctx: Context<'gc>, value: Value<'gc>) -> Option<Vec<u8>> {
+        matches!(value, Value::Function(Function::Callback(_))).then(|| b"answer".to_vec())
+    }
+
+    fn restore_value(&mut self, ctx: Context<'gc>, key: &[u8]) -> Option<Value<'gc>> {
+        (key == b"answer").then(|| {
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                stack.replace(ctx, 42);
+                Ok(CallbackReturn::Return)
+            })
+            .into()
+        })
+    }
+}
+
+#[test]
+fn snapshot_hooks() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    let snapshot = lua.enter(|ctx| {
+        let callback = Callback::from_fn(&ctx, |_, _, _| Ok(CallbackReturn::Return));
+        let thread = Thread::new(ctx);
+        thread.start_suspended(&ctx, callback.into()).unwrap();
+
+        assert!(matches!(
+            thread.snapshot(ctx, &mut ()),
+            Err(SnapshotError::Unsupported("callback"))
+        ));
+        thread.snapshot(ctx, &mut CallbackHooks).unwrap()
+    });
+
+    let mut restored = Lua::core();
+    restored.enter(|ctx| {
+        assert!(matches!(
+            Thread::restore(ctx, &snapshot, &mut ()),
+            Err(SnapshotError::MissingExternal)
+        ));
+    });
+    let executor = restored.enter(|ctx| {
+        let thread = Thread::restore(ctx, &snapshot, &mut CallbackHooks).unwrap();
+        let executor = Executor::run(&ctx, thread).unwrap();
+        executor.resume(ctx, ()).unwrap();
+        ctx.stash(executor)
+    });
+    assert_eq!(restored.execute::<i64>(&executor)?, 42);
+
+    Ok(())
+}
+
+#[test]
+fn snapshot_errors() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    let (thread, executor) = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, &b"return 1"[..])?;
+        let thread = Thread::new(ctx);
+        thread.start(ctx, closure.into(), ()).unwrap();
+        let executor = Executor::run(&ctx, thread).unwrap();
+        Ok((ctx.stash(thread), ctx.stash(executor)))
+    })?;
+    lua.finish(&executor).unwrap();
+
+    lua.enter(|ctx| {
+        let thread = ctx.fetch(&thread);
+        assert!(matches!(
+            thread.snapshot(ctx, &mut ()),
+            Err(SnapshotError::BadThreadMode(ThreadMode::Result))
+        ));
+    });
+
+    let snapshot = lua.enter(|ctx| {
+        let thread = Thread::new(ctx);
+        let closure = Closure::load(ctx, None, &b"return 1"[..]).unwrap();
+        thread.start_suspended(&ctx, closure.into()).unwrap();
+        thread.snapshot(ctx, &mut ()).unwrap()
+    });
+
+    lua.enter(|ctx| {
+        assert!(Thread::restore(ctx, &snapshot, &mut ()).is_ok());
+
+        for len in 0..snapshot.len() {
+            assert!(Thread::restore(ctx, &snapshot[..len], &mut ()).is_err());
+        }
+        assert!(matches!(
+            Thread::restore(ctx, b"not a snapshot", &mut ()),
+            Err(SnapshotError::Malformed(_))
+        ));
+
+        let mut versioned = snapshot.clone();
+        versioned[8] += 1;
+        assert!(matches!(
+            Thread::restore(ctx, &versioned, &mut ()),
+            Err(SnapshotError::Version(2))
+        ));
+    });
+
+    Ok(())
+}
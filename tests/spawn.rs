@@ -0,0 +1,100 @@
+use std::{cell::RefCell, rc::Rc};
+
+use piccolo::{
+    Callback, CallbackReturn, Executor, ExternError, Function, Lua, StashedThread, Variadic,
+};
+
+mod common;
+
+use common::run;
+
+fn load_spawn(lua: &mut Lua) {
+    lua.enter(|ctx| {
+        let spawn = Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let function: Function = stack.from_front(ctx)?;
+            let args = Variadic(stack.drain(..).collect::<Vec<_>>());
+            ctx.spawn_callback_thread(function, args);
+            Ok(CallbackReturn::Return)
+        });
+        ctx.set_global("spawn", spawn);
+    });
+}
+
+fn run_thread(lua: &mut Lua, thread: &StashedThread) -> Result<(), ExternError> {
+    let executor = lua.enter(|ctx| ctx.stash(Executor::run(&ctx, ctx.fetch(thread)).unwrap()));
+    lua.execute::<()>(&executor)
+}
+
+#[test]
+fn spawn_handler() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    load_spawn(&mut lua);
+
+    let pending = Rc::new(RefCell::new(Vec::new()));
+    lua.set_spawn_handler({
+        let pending = pending.clone();
+        move |_, thread| pending.borrow_mut().push(thread)
+    });
+
+    run(
+        &mut lua,
+        r#"
+            log = {}
+            local function task(name, n)
+                table.insert(log, name .. n)
+                if n > 1 then
+                    spawn(task, name, n - 1)
+                end
+            end
+            spawn(task, "a", 2)
+            spawn(task, "b", 1)
+            -- Spawned threads do not run until the host starts them.
+            assert(#log == 0)
+        "#,
+    )?;
+
+    assert_eq!(pending.borrow().len(), 2);
+    while !pending.borrow().is_empty() {
+        let thread = pending.borrow_mut().remove(0);
+        run_thread(&mut lua, &thread)?;
+    }
+
+    run(&mut lua, r#"assert(table.concat(log, ",") == "a2,b1,a1")"#)?;
+
+    Ok(())
+}
+
+#[test]
+fn handler_runs_threads() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    load_spawn(&mut lua);
+
+    // The handler runs every thread to completion as soon as it is spawned, including threads
+    // spawned by the threads it runs.
+    lua.set_spawn_handler(|lua, thread| run_thread(lua, &thread).unwrap());
+
+    run(
+        &mut lua,
+        r#"
+            count = 0
+            local function task(n)
+                count = count + 1
+                if n > 0 then
+                    spawn(task, n - 1)
+                    spawn(task, n - 1)
+                end
+            end
+            spawn(task, 3)
+        "#,
+    )?;
+    run(&mut lua, "assert(count == 15)")?;
+
+    // Without a handler, spawned threads are kept until they are taken.
+    lua.clear_spawn_handler();
+    run(&mut lua, "spawn(function() end) spawn(print, 1)")?;
+    let threads = lua.take_spawned_threads();
+    assert_eq!(threads.len(), 2);
+    assert!(lua.take_spawned_threads().is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use piccolo::{Closure, CompileOptions, Executor, ExternError, Lua, Table};
+
+fn run(lua: &mut Lua, source: &str, strict_globals: bool) -> Result<(), ExternError> {
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load_with_options(
+            ctx,
+            Some("strict"),
+            source.as_bytes(),
+            ctx.globals(),
+            CompileOptions {
+                strict_globals,
+                ..Default::default()
+            },
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)
+}
+
+#[test]
+fn strict_globals() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    run(
+        &mut lua,
+        r#"
+            declared = 1
+            assert(declared == 1)
+
+            local t = {}
+            assert(t.missing == nil)
+
+            local function f()
+                return declared
+            end
+            assert(f() == 1)
+        "#,
+        true,
+    )?;
+
+    let err = run(&mut lua, "local a = 1\nreturn undeclared", true).unwrap_err();
+    let message = err.to_string();
+    assert!(message.contains("undeclared"), "{message}");
+    assert!(message.contains("strict:2"), "{message}");
+
+    let err = run(
+        &mut lua,
+        "local function f() return also_undeclared end\nf()",
+        true,
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("also_undeclared"));
+
+    run(&mut lua, "assert(undeclared == nil)", false)?;
+
+    Ok(())
+}
+
+#[test]
+fn strict_globals_use_env() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    // Fields of the globals table read through another name are not globals.
+    run(
+        &mut lua,
+        r#"
+            local g = _G
+            assert(g.undeclared == nil)
+
+            local function f(_ENV)
+                return declared
+            end
+            assert(f({ declared = 1 }) == 1)
+            assert(not pcall(f, {}))
+
+            local _ENV = { assert = assert, pcall = pcall }
+            assert(not pcall(function() return undeclared end))
+        "#,
+        true,
+    )?;
+
+    // A chunk run with a custom environment is strict about that environment.
+    let executor = lua.try_enter(|ctx| {
+        let env = Table::new(&ctx);
+        env.set(ctx, "declared", 1)?;
+        let closure = Closure::load_with_options(
+            ctx,
+            Some("strict"),
+            &b"local x = declared\nreturn undeclared"[..],
+            ctx.globals(),
+            CompileOptions {
+                strict_globals: true,
+                ..Default::default()
+            },
+        )?
+        .with_env(&ctx, env)?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    let err = lua.execute::<()>(&executor).unwrap_err();
+    let message = err.to_string();
+    assert!(
+        message.contains("undeclared global 'undeclared'"),
+        "{message}"
+    );
+    assert!(message.contains("strict:2"), "{message}");
+
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use piccolo::{
+    Callback, CallbackReturn, Closure, Executor, ExternError, Lua, MetaMethod, String, Table, Value,
+};
+
+#[test]
+fn replace_string_metatable() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let previous = ctx.string_metatable().unwrap();
+        assert!(previous.get_value(ctx, "__metatable").is_nil());
+
+        // An unprotected metatable which indexes strings by their length.
+        let metatable = Table::new(&ctx);
+        metatable
+            .set(
+                ctx,
+                MetaMethod::Index,
+                Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                    let (s, _): (String, Value) = stack.consume(ctx)?;
+                    stack.replace(ctx, s.len());
+                    Ok(CallbackReturn::Return)
+                }),
+            )
+            .unwrap();
+        assert_eq!(ctx.set_string_metatable(Some(metatable)), Some(previous));
+    });
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                assert(("abc").anything == 3)
+                assert(getmetatable("") ~= string)
+                getmetatable("").__index = string
+                assert(("abc"):upper() == "ABC")
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)?;
+
+    lua.enter(|ctx| {
+        ctx.set_string_metatable(None);
+    });
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, &b"return ('abc'):upper()"[..])?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    assert!(lua.execute::<()>(&executor).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use piccolo::{match_symbol, Closure, Executor, ExternError, FromValue, Lua, Symbol, Value};
+
+#[test]
+fn symbols_are_interned() {
+    let mut lua = Lua::core();
+    lua.enter(|ctx| {
+        let a = Symbol::new(ctx, "north");
+        let b = Symbol::from_string(ctx, piccolo::String::from_slice(&ctx, "north")).unwrap();
+        assert_eq!(a, b);
+        assert_eq!(a.id(), b.id());
+        assert_eq!(a, "north");
+        assert_ne!(a, Symbol::new(ctx, "south"));
+
+        assert!(Symbol::from_value(ctx, Value::Integer(1)).is_err());
+        assert!(Symbol::from_string(ctx, piccolo::String::from_slice(&ctx, b"\xff")).is_none());
+    });
+}
+
+#[test]
+fn match_symbols() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, &b"return 'west', 'up'"[..])?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.finish(&executor).unwrap();
+    lua.try_enter(|ctx| {
+        let (dir, other): (Symbol, Symbol) = ctx.fetch(&executor).take_result(ctx)??;
+        let dx = match_symbol!(dir, {
+            "east" => 1,
+            "west" => -1,
+            _ => 0,
+        });
+        assert_eq!(dx, -1);
+        assert_eq!(
+            match_symbol!(other, {
+                "north" | "south" => None,
+                s if s.len() == 2 => Some(s),
+                _ => None,
+            }),
+            Some("up")
+        );
+        assert_eq!(dir, Symbol::new(ctx, "west"));
+        Ok(())
+    })
+}
@@ -1,6 +1,9 @@
 use std::cmp::Ordering;
 
-use piccolo::{Lua, Table, Value};
+use piccolo::{
+    meta_ops::MetaResult, table::RawTable, Closure, Context, Executor, ExternError, Lua, Table,
+    Value,
+};
 
 #[test]
 fn test_table_iter() {
@@ -45,3 +48,251 @@ fn test_table_iter() {
         assert!(table.get_value(ctx, "3").is_nil());
     });
 }
+
+#[test]
+fn test_table_iter_array() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let table = Table::new(&ctx);
+        table.set(ctx, 1, "a").unwrap();
+        table.set(ctx, 2.0, "b").unwrap();
+        table.set(ctx, 3, "c").unwrap();
+        // Values after the first border are not visited, like `ipairs`.
+        table.set(ctx, 5, "e").unwrap();
+        table.set(ctx, 0, "zero").unwrap();
+        table.set(ctx, "key", "value").unwrap();
+
+        let items = table
+            .iter_array()
+            .map(|(i, v)| (i, v.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            items,
+            [
+                (1, "a".to_owned()),
+                (2, "b".to_owned()),
+                (3, "c".to_owned())
+            ]
+        );
+
+        table.set(ctx, 1, Value::Nil).unwrap();
+        assert_eq!(table.iter_array().count(), 0);
+    });
+}
+
+#[test]
+fn test_table_append_array() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let mut table = RawTable::new(&ctx);
+        for i in 1..=1000 {
+            table.set(Value::Integer(i), Value::Integer(i)).unwrap();
+            // Appended elements go straight into the array part, which grows by doubling.
+            assert!(table.array().len() >= i as usize);
+            assert!(table.array().len() <= (2 * i as usize).max(4));
+        }
+        assert_eq!(table.length(), 1000);
+        assert!(matches!(
+            table.get(Value::Integer(1000)),
+            Value::Integer(1000)
+        ));
+
+        // Sparse keys past the end of the array still go to the map part.
+        table
+            .set(Value::Integer(1 << 20), Value::Boolean(true))
+            .unwrap();
+        assert!(table.array().len() < 1 << 20);
+        assert!(matches!(
+            table.get(Value::Integer(1 << 20)),
+            Value::Boolean(true)
+        ));
+        assert_eq!(table.length(), 1000);
+    });
+}
+
+// The border search `RawTable::length` did before it cached anything.
+#[test]
+fn test_table_rehash_counts_keys() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        // Keys inserted in reverse order start out in the map part, and are moved to the array part
+        // once a rehash counts enough of them.
+        let mut table = RawTable::new(&ctx);
+        for i in (1..=1000).rev() {
+            table.set(Value::Integer(i), Value::Integer(i)).unwrap();
+        }
+        assert!(table.array().len() >= 1000);
+        assert!(table.array().len() <= 2048);
+        assert_eq!(table.length(), 1000);
+        for i in 1..=1000 {
+            assert!(matches!(table.get(Value::Integer(i)), Value::Integer(j) if i == j));
+        }
+
+        // Growing the map part rehashes the table as well.
+        let mut table = RawTable::new(&ctx);
+        for i in (1..=200).rev() {
+            table.set(Value::Integer(i), Value::Integer(i)).unwrap();
+            table.set(Value::Integer(-i), Value::Integer(i)).unwrap();
+        }
+        table.reserve_map(1000);
+        assert!(table.array().len() >= 200);
+        assert_eq!(table.length(), 200);
+        for i in 1..=200 {
+            assert!(matches!(table.get(Value::Integer(i)), Value::Integer(j) if i == j));
+            assert!(matches!(table.get(Value::Integer(-i)), Value::Integer(j) if i == j));
+        }
+    });
+}
+
+fn search_border(table: &RawTable<'_>) -> i64 {
+    fn binary_search(mut min: i64, mut max: i64, is_nil: impl Fn(i64) -> bool) -> i64 {
+        while max - min > 1 {
+            let mid = min + (max - min) / 2;
+            if is_nil(mid) {
+                max = mid;
+            } else {
+                min = mid;
+            }
+        }
+        min
+    }
+
+    let is_nil = |i: i64| table.get(Value::Integer(i)).is_nil();
+    let array_len = table.array().len() as i64;
+    if table.array().last().is_some_and(|v| v.is_nil()) {
+        binary_search(0, array_len, is_nil)
+    } else {
+        let mut max = array_len + 1;
+        while !is_nil(max) {
+            max *= 2;
+        }
+        binary_search(array_len, max, is_nil)
+    }
+}
+
+#[test]
+fn test_table_length_matches_border_search() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let mut table = RawTable::new(&ctx);
+        let mut seed: i64 = 1;
+        for i in 0..20_000 {
+            seed = (seed * 1103515245 + 12345) % 2147483648;
+            let key = match seed % 7 {
+                // Mostly push and pop, with the occasional hole and far away key.
+                0 | 1 | 2 => Value::Integer(table.length() + 1),
+                3 => Value::Integer(table.length()),
+                4 => Value::Integer(seed % 300 + 1),
+                5 => Value::Integer(seed % 3000 + 1),
+                _ => Value::String(ctx.intern(format!("key{}", seed % 50).as_bytes())),
+            };
+            let value = if seed % 5 == 0 || seed % 7 == 3 {
+                Value::Nil
+            } else {
+                Value::Integer(i)
+            };
+            table.set(key, value).unwrap();
+            assert_eq!(table.length(), search_border(&table));
+        }
+    });
+}
+
+fn meta_value<'gc>(ctx: Context<'gc>, table: Table<'gc>, key: &'static str) -> Value<'gc> {
+    match table.get_with_meta(ctx, key) {
+        Ok(MetaResult::Value(v)) => v,
+        _ => panic!("expected a value"),
+    }
+}
+
+#[test]
+fn test_table_with_meta() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local Base = {}
+                Base.__index = Base
+                Base.kind = "base"
+                local Derived = setmetatable({}, Base)
+                Derived.__index = Derived
+                Derived.name = "derived"
+
+                obj = setmetatable({ own = 1 }, Derived)
+                computed = setmetatable({}, { __index = function(t, k) return k .. "!" end })
+                loop = {}
+                setmetatable(loop, { __index = loop, __newindex = loop })
+
+                log = {}
+                proxy = setmetatable({}, {
+                    __newindex = setmetatable({}, {
+                        __newindex = function(t, k, v) log[k] = v end,
+                    }),
+                })
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.execute::<()>(&executor)?;
+
+    let executor = lua.try_enter(|ctx| {
+        let obj: Table = ctx.get_global("obj")?;
+        assert!(matches!(meta_value(ctx, obj, "own"), Value::Integer(1)));
+        assert!(matches!(meta_value(ctx, obj, "name"), Value::String(s) if s == "derived"));
+        assert!(matches!(meta_value(ctx, obj, "kind"), Value::String(s) if s == "base"));
+        assert!(meta_value(ctx, obj, "missing").is_nil());
+        assert!(obj.get_value(ctx, "kind").is_nil());
+
+        // Chains of tables are followed without returning a call.
+        assert!(obj.set_with_meta(ctx, "own", 2)?.is_none());
+        assert!(matches!(obj.get_value(ctx, "own"), Value::Integer(2)));
+
+        let loop_table: Table = ctx.get_global("loop")?;
+        assert!(loop_table.get_with_meta(ctx, "x").is_err());
+        assert!(loop_table.set_with_meta(ctx, "x", 1).is_err());
+
+        // A function must be called to finish these.
+        let proxy: Table = ctx.get_global("proxy")?;
+        let call = proxy.set_with_meta(ctx, "a", 3)?.unwrap();
+        assert!(proxy.get_value(ctx, "a").is_nil());
+        let computed: Table = ctx.get_global("computed")?;
+        let MetaResult::Call(index_call) = computed.get_with_meta(ctx, "key")? else {
+            panic!("expected a call");
+        };
+
+        let run = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local set, index = ...
+                set(table.unpack(set_args))
+                assert(log.a == 3)
+                return index(table.unpack(index_args))
+            "#[..],
+        )?;
+        let set_args = Table::new(&ctx);
+        for (i, arg) in call.args.into_iter().enumerate() {
+            set_args.set(ctx, i as i64 + 1, arg)?;
+        }
+        let index_args = Table::new(&ctx);
+        for (i, arg) in index_call.args.into_iter().enumerate() {
+            index_args.set(ctx, i as i64 + 1, arg)?;
+        }
+        ctx.set_global("set_args", set_args);
+        ctx.set_global("index_args", index_args);
+        Ok(ctx.stash(Executor::start(
+            ctx,
+            run.into(),
+            (call.function, index_call.function),
+        )))
+    })?;
+    assert_eq!(lua.execute::<String>(&executor)?, "key!");
+
+    Ok(())
+}
@@ -0,0 +1,49 @@
+use piccolo::{tags::TagMemory, Lua, Table, UserData};
+
+const UI: u32 = 1;
+const AI: u32 = 2;
+
+#[test]
+fn memory_by_tag() {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let ui = Table::new_tagged(ctx, UI);
+        for i in 1..=100 {
+            ui.set(ctx, i, i).unwrap();
+        }
+        assert_eq!(ui.tag(), Some(UI));
+
+        let ai = Table::new(&ctx);
+        assert_eq!(ai.tag(), None);
+        ai.set_tag(ctx, AI);
+        ai.set_tag(ctx, AI);
+
+        let userdata = UserData::new_static(&ctx, [0u8; 256]);
+        userdata.set_tag(ctx, AI);
+        assert_eq!(userdata.tag(), Some(AI));
+
+        // Untagged objects are not counted.
+        Table::new(&ctx);
+
+        ctx.set_global("ui", ui);
+        ctx.set_global("ai", ai);
+        ctx.set_global("userdata", userdata);
+    });
+
+    let memory = lua.memory_by_tag();
+    assert_eq!(memory.len(), 2);
+    assert_eq!(memory[&UI].objects, 1);
+    assert!(memory[&UI].bytes >= 100 * std::mem::size_of::<piccolo::Value>());
+    assert_eq!(memory[&AI].objects, 2);
+    assert!(memory[&AI].bytes >= 256);
+
+    // Collected objects are no longer counted.
+    lua.enter(|ctx| {
+        ctx.set_global("ui", piccolo::Value::Nil);
+    });
+    lua.gc_collect();
+    let memory = lua.memory_by_tag();
+    assert_eq!(memory.get(&UI), None::<&TagMemory>);
+    assert_eq!(memory[&AI].objects, 2);
+}
@@ -0,0 +1,61 @@
+use piccolo::{Closure, Executor, ExternError, Lua, String};
+
+#[test]
+fn collect_all_every_step() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    lua.collect_all_every_step(true);
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local weak = setmetatable({}, { __mode = "v" })
+                weak[1] = {}
+                -- Use enough fuel that the executor must be stepped more than once.
+                for i = 1, 10000 do end
+                return weak[1] == nil
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    assert!(lua.execute::<bool>(&executor)?);
+    Ok(())
+}
+
+#[test]
+fn fail_allocations() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local ok, err = pcall(function()
+                    local t = {}
+                    for i = 1, 10000 do
+                        t[i] = { i }
+                    end
+                end)
+                -- Only a single failure is injected.
+                local t = {}
+                for i = 1, 10000 do
+                    t[i] = { i }
+                end
+                return ok, err
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    lua.fail_allocations_after(Some(1024));
+    lua.finish(&executor).unwrap();
+    lua.try_enter(|ctx| {
+        let (ok, err): (bool, String) = ctx.fetch(&executor).take_result(ctx)??;
+        assert!(!ok);
+        assert_eq!(err, "not enough memory");
+        Ok(())
+    })
+}
@@ -0,0 +1,53 @@
+use piccolo::{Closure, Executor, ExternError, Lua, Table, Value};
+
+const FUEL: i32 = 10_000;
+
+#[test]
+fn value_display() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local t = {}
+                local named = setmetatable({}, { __tostring = function() return "named" end })
+                local number = setmetatable({}, { __tostring = function() return 42 end })
+                local bad = setmetatable({}, { __tostring = function() return {} end })
+                local endless = setmetatable({}, { __tostring = function() while true do end end })
+                return t, tostring(t), named, number, bad, endless
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+    lua.finish(&executor).unwrap();
+
+    lua.try_enter(|ctx| {
+        let (t, t_str, named, number, bad, endless): (Value, Value, Value, Value, Value, Value) =
+            ctx.fetch(&executor).take_result(ctx)??;
+
+        // Without `__tostring`, `Display` and `ctx.tostring` match the `tostring` builtin.
+        assert_eq!(t.to_string(), t_str.to_string());
+        assert_eq!(ctx.tostring(t, FUEL)?, t_str.to_string().as_str());
+
+        assert_eq!(Value::Nil.to_string(), "nil");
+        assert_eq!(Value::Boolean(true).to_string(), "true");
+        assert_eq!(Value::Integer(-3).to_string(), "-3");
+        assert_eq!(ctx.tostring(Value::Integer(7), FUEL)?, "7");
+
+        assert_eq!(ctx.tostring(named, FUEL)?, "named");
+        assert_eq!(ctx.tostring(number, FUEL)?, "42");
+        assert!(ctx.tostring(bad, FUEL).is_err());
+        assert!(ctx.tostring(endless, FUEL).is_err());
+
+        let plain: Table = Table::new(&ctx);
+        assert!(ctx
+            .tostring(plain.into(), FUEL)?
+            .as_bytes()
+            .starts_with(b"<table "));
+        Ok(())
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use piccolo::{Closure, Executor, Lua, StashedThread, ThreadMode, ThreadResult, TypedThread};
+
+type Dialogue<'gc> = TypedThread<'gc, i64, (i64, bool), std::string::String>;
+
+#[test]
+fn typed_thread() -> Result<(), anyhow::Error> {
+    let mut lua = Lua::core();
+
+    let (thread, executor) = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local a = ...
+                local b = coroutine.yield(a + 1, true)
+                local c = coroutine.yield(b * 2, false)
+                return "done " .. c
+            "#[..],
+        )?;
+        let thread = Dialogue::new(ctx);
+        thread.start(ctx, closure.into(), 1)?;
+        Ok((
+            ctx.stash(thread.thread()),
+            ctx.stash(Executor::run(&ctx, thread.thread())?),
+        ))
+    })?;
+
+    let mut results = Vec::new();
+    for arg in [10, 20] {
+        lua.finish(&executor)?;
+        let res = lua.try_enter(|ctx| {
+            let thread = Dialogue::from_thread(ctx.fetch(&thread));
+            let res = thread.take_result(ctx)??;
+            assert_eq!(thread.mode(), ThreadMode::Suspended);
+            thread.resume(ctx, arg)?;
+            Ok(res)
+        })?;
+        results.push(res);
+    }
+
+    lua.finish(&executor)?;
+    let res = lua.try_enter(|ctx| {
+        let thread = Dialogue::from_thread(ctx.fetch(&thread));
+        let res = thread.take_result(ctx)??;
+        assert_eq!(thread.mode(), ThreadMode::Stopped);
+        Ok(res)
+    })?;
+    results.push(res);
+
+    assert_eq!(
+        results,
+        vec![
+            ThreadResult::Yielded((2, true)),
+            ThreadResult::Yielded((20, false)),
+            ThreadResult::Returned("done 20".to_owned()),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn typed_thread_bad_yield() -> Result<(), anyhow::Error> {
+    let mut lua = Lua::core();
+
+    let (thread, executor): (StashedThread, _) = lua.try_enter(|ctx| {
+        let closure = Closure::load(ctx, None, &b"coroutine.yield({}, true)"[..])?;
+        let thread = Dialogue::new(ctx);
+        thread.start(ctx, closure.into(), 0)?;
+        Ok((
+            ctx.stash(thread.thread()),
+            ctx.stash(Executor::run(&ctx, thread.thread())?),
+        ))
+    })?;
+
+    lua.finish(&executor)?;
+    lua.enter(|ctx| {
+        let thread = Dialogue::from_thread(ctx.fetch(&thread));
+        // Yielding a table where an integer is expected is reported as an error, and the thread is
+        // still left suspended.
+        assert!(thread.take_result(ctx).unwrap().is_err());
+        assert_eq!(thread.mode(), ThreadMode::Suspended);
+    });
+    Ok(())
+}
@@ -0,0 +1,40 @@
+use std::{cell::RefCell, rc::Rc};
+
+use piccolo::{ExternError, Lua};
+
+mod common;
+
+use common::run;
+
+#[test]
+fn unsupported_stubs() -> Result<(), ExternError> {
+    let mut lua = Lua::core();
+    lua.load_unsupported();
+
+    let called = Rc::new(RefCell::new(Vec::new()));
+    lua.set_unsupported_fn({
+        let called = called.clone();
+        move |name| called.borrow_mut().push(name.to_owned())
+    });
+
+    run(
+        &mut lua,
+        r##"
+            assert(type(debug.traceback) == "function")
+            local ok, err = pcall(debug.traceback)
+            assert(not ok)
+            assert(string.find(tostring(err), "'debug.traceback' is not supported", 1, true))
+
+            local ok, err = pcall(dofile, "x.lua")
+            assert(not ok and string.find(tostring(err), "'dofile' is not supported", 1, true))
+
+            -- Functions which already exist are left alone.
+            assert(math.floor(1.5) == 1)
+            assert(select("#", 1, 2) == 2)
+        "##,
+    )?;
+
+    assert_eq!(*called.borrow(), ["debug.traceback", "dofile"]);
+
+    Ok(())
+}
@@ -1,5 +1,11 @@
+use std::cell::Cell;
+
 use gc_arena::{lock::Lock, Collect, Gc, Rootable};
-use piccolo::{Callback, CallbackReturn, Closure, Executor, Lua, UserData, Value};
+use piccolo::{
+    userdata::{DynCasts, UserDataFields, UserDataMethods},
+    Callback, CallbackReturn, Closure, Executor, FromValue, Lua, MetaMethod, Table, UserData,
+    UserDataType, Value,
+};
 
 #[derive(Collect)]
 #[collect(no_drop)]
@@ -62,3 +68,195 @@ fn userdata() -> Result<(), anyhow::Error> {
 
     Ok(())
 }
+
+#[test]
+fn userdata_name() -> Result<(), anyhow::Error> {
+    let mut lua = Lua::full();
+
+    lua.try_enter(|ctx| {
+        let userdata = UserData::new_static(&ctx, 5u8);
+        let metatable = Table::new(&ctx);
+        metatable.set(ctx, "__name", "FooHandle")?;
+        userdata.set_metatable(&ctx, Some(metatable));
+        ctx.set_global("handle", userdata);
+
+        assert_eq!(userdata.name().unwrap(), "FooHandle");
+
+        let err = userdata.downcast_static::<i32>().unwrap_err();
+        assert_eq!(err.to_string(), "UserData type mismatch, found FooHandle");
+
+        let err = i64::from_value(ctx, userdata.into()).unwrap_err();
+        assert_eq!(err.to_string(), "type error, expected i64, found FooHandle");
+        Ok(())
+    })?;
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                local s = tostring(handle)
+                return string.sub(s, 1, 11) == "FooHandle: "
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    assert!(lua.execute::<bool>(&executor)?);
+
+    Ok(())
+}
+
+struct Counter {
+    count: Cell<i64>,
+}
+
+impl UserDataType for Counter {
+    const NAME: Option<&'static str> = Some("Counter");
+
+    fn add_fields<'gc>(fields: &mut UserDataFields<'gc, Self>) {
+        fields.add_field_method_get("count", |this, _| Ok(this.count.get()));
+        fields.add_field_method_set("count", |this, _, count: i64| {
+            this.count.set(count);
+            Ok(())
+        });
+    }
+
+    fn add_methods<'gc>(methods: &mut UserDataMethods<'gc, Self>) {
+        methods.add_method("increment", |this, _, by: Option<i64>| {
+            this.count.set(this.count.get() + by.unwrap_or(1));
+            Ok(this.count.get())
+        });
+        methods.add_function("description", |_, ()| Ok("counts things"));
+        methods.add_meta_method(MetaMethod::Len, |this, _, ()| Ok(this.count.get()));
+    }
+}
+
+#[test]
+fn userdata_methods() -> Result<(), anyhow::Error> {
+    let mut lua = Lua::full();
+
+    lua.enter(|ctx| {
+        let a = UserData::new_typed(
+            ctx,
+            Counter {
+                count: Cell::new(0),
+            },
+        );
+        let b = UserData::new_typed(
+            ctx,
+            Counter {
+                count: Cell::new(10),
+            },
+        );
+        assert_eq!(a.metatable(), b.metatable());
+        ctx.set_global("a", a);
+        ctx.set_global("b", b);
+    });
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                assert(a:increment() == 1)
+                assert(a:increment(5) == 6)
+                assert(a.count == 6)
+                a.count = 2
+                assert(#a == 2)
+                assert(b.count == 10)
+                assert(a.description() == "counts things")
+                assert(a.missing == nil)
+                assert(not pcall(function() a.missing = 1 end))
+                assert(not pcall(a.increment, 1))
+                return string.sub(tostring(a), 1, 9) == "Counter: "
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    assert!(lua.execute::<bool>(&executor)?);
+
+    lua.enter(|ctx| {
+        let a: UserData = ctx.get_global("a").unwrap();
+        assert_eq!(a.downcast_static::<Counter>().unwrap().count.get(), 2);
+    });
+
+    Ok(())
+}
+
+trait Shape {
+    fn area(&self) -> f64;
+}
+
+struct Square(f64);
+
+impl Shape for Square {
+    fn area(&self) -> f64 {
+        self.0 * self.0
+    }
+}
+
+struct Rect(f64, f64);
+
+impl Shape for Rect {
+    fn area(&self) -> f64 {
+        self.0 * self.1
+    }
+}
+
+impl UserDataType for Rect {
+    fn add_casts(casts: &mut DynCasts) {
+        casts.add::<Self, dyn Shape>(|r| r);
+    }
+}
+
+#[test]
+fn userdata_dyn_casts() -> Result<(), anyhow::Error> {
+    let mut lua = Lua::core();
+
+    lua.enter(|ctx| {
+        let mut casts = DynCasts::new();
+        casts.add::<Square, dyn Shape>(|s| s);
+        ctx.set_global(
+            "square",
+            UserData::new_static_dyn(&ctx, Square(3.0), Gc::new(&ctx, casts)),
+        );
+        ctx.set_global("rect", UserData::new_typed(ctx, Rect(2.0, 5.0)));
+        ctx.set_global("plain", UserData::new_static(&ctx, Square(1.0)));
+        ctx.set_global(
+            "area",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let ud: UserData = stack.consume(ctx)?;
+                let area = ud.downcast_dyn::<dyn Shape>()?.area();
+                stack.replace(ctx, area);
+                Ok(CallbackReturn::Return)
+            }),
+        );
+    });
+
+    let executor = lua.try_enter(|ctx| {
+        let closure = Closure::load(
+            ctx,
+            None,
+            &br#"
+                assert(area(square) == 9)
+                assert(area(rect) == 10)
+                return pcall(area, plain)
+            "#[..],
+        )?;
+        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+    })?;
+
+    assert!(!lua.execute::<bool>(&executor)?);
+
+    lua.enter(|ctx| {
+        let rect: UserData = ctx.get_global("rect").unwrap();
+        assert!(rect.is_dyn::<dyn Shape>());
+        assert_eq!(rect.downcast_static::<Rect>().unwrap().area(), 10.0);
+        let plain: UserData = ctx.get_global("plain").unwrap();
+        assert!(!plain.is_dyn::<dyn Shape>());
+    });
+
+    Ok(())
+}